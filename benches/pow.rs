@@ -1,8 +1,11 @@
 use criterion::{criterion_group, criterion_main, BatchSize, BenchmarkId, Criterion};
 
-use post::pow::{
-    randomx::{PoW, RandomXFlag},
-    PowVerifier, Prover,
+use post::{
+    cancel::CancellationToken,
+    pow::{
+        randomx::{PoW, RandomXFlag},
+        PowVerifier, Prover,
+    },
 };
 #[cfg(not(windows))]
 use pprof::criterion::{Output, PProfProfiler};
@@ -17,6 +20,7 @@ fn bench_pow(c: &mut Criterion) {
 
     let flags = RandomXFlag::get_recommended_flags();
     let prover = PoW::new(flags).unwrap();
+    let cancel = CancellationToken::new();
 
     let mut group = c.benchmark_group("pow");
 
@@ -33,7 +37,7 @@ fn bench_pow(c: &mut Criterion) {
                     |nonce| {
                         pool.install(|| {
                             prover
-                                .prove(nonce, b"challeng", difficulty, &[7; 32])
+                                .prove(nonce, b"challeng", difficulty, &[7; 32], &cancel)
                                 .unwrap()
                         })
                     },
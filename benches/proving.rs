@@ -48,7 +48,7 @@ fn prover_bench(c: &mut Criterion) {
                 pow_prover
                     .expect_prove()
                     .times(nonces as usize / 16)
-                    .returning(|_, _, _, _| Ok(0));
+                    .returning(|_, _, _, _, _| Ok(0));
                 let prover =
                     Prover8_56::new(CHALLENGE, 0..nonces, params, &pow_prover, &[7; 32]).unwrap();
                 b.iter(|| {
@@ -1,7 +1,6 @@
-use std::sync::atomic::AtomicBool;
-
 use criterion::{criterion_group, criterion_main, Criterion};
 use post::{
+    cancel::CancellationToken,
     config::{InitConfig, ProofConfig, ScryptParams},
     initialize::{CpuInitializer, Initialize},
     metadata::ProofMetadata,
@@ -44,8 +43,8 @@ fn verifying(c: &mut Criterion) {
 
     let pow_flags = RandomXFlag::get_recommended_flags();
     // Generate a proof
-    let stop = AtomicBool::new(false);
-    let proof = generate_proof(datadir.path(), challenge, cfg, 32, 1, pow_flags, stop).unwrap();
+    let cancel = CancellationToken::new();
+    let proof = generate_proof(datadir.path(), challenge, cfg, 32, 1, pow_flags, cancel).unwrap();
     let metadata = ProofMetadata::new(metadata, *challenge);
 
     // Bench verifying the proof
@@ -1,8 +1,9 @@
-use std::{future::IntoFuture, net::SocketAddr, str::FromStr, sync::atomic::AtomicBool};
+use std::{future::IntoFuture, net::SocketAddr, str::FromStr};
 
 use certifier::{certifier::CertifyRequest, configuration::RandomXMode};
 use ed25519_dalek::SigningKey;
 use post::{
+    cancel::CancellationToken,
     config::{InitConfig, ProofConfig, ScryptParams},
     initialize::{CpuInitializer, Initialize},
     metadata::ProofMetadata,
@@ -45,8 +46,8 @@ async fn test_certificate_post_proof() {
 
     // Generate a proof
     let pow_flags = RandomXFlag::get_recommended_flags();
-    let stop = AtomicBool::new(false);
-    let proof = generate_proof(datadir.path(), challenge, cfg, 32, 1, pow_flags, stop).unwrap();
+    let cancel = CancellationToken::new();
+    let proof = generate_proof(datadir.path(), challenge, cfg, 32, 1, pow_flags, cancel).unwrap();
     let metadata = ProofMetadata::new(metadata, *challenge);
 
     // Spawn the certifier service
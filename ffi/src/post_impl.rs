@@ -5,10 +5,10 @@ use std::{
     ffi::{c_char, c_uchar, CStr},
     mem::ManuallyDrop,
     path::Path,
-    sync::atomic::AtomicBool,
 };
 
 use post::{
+    cancel::CancellationToken,
     config::{InitConfig, ProofConfig},
     metadata::ProofMetadata,
     pow::randomx::{PoW, RandomXFlag},
@@ -24,6 +24,10 @@ pub struct Proof {
     nonce: u32,
     indices: ArrayU8,
     pow: u64,
+    /// Version of the proof format (see [prove::ProofVersion]): 1 or 2.
+    /// '0' (i.e. left unset by callers not knowing the versions) means
+    /// the version implied by the nonce (see [prove::ProofVersion::of_nonce_group]).
+    version: u32,
 }
 
 impl From<prove::Proof<'_>> for Proof {
@@ -35,19 +39,27 @@ impl From<prove::Proof<'_>> for Proof {
             nonce: proof.nonce,
             indices: ArrayU8 { ptr, len, cap },
             pow: proof.pow,
+            version: proof.version.into(),
         }
     }
 }
 
-impl From<Proof> for prove::Proof<'_> {
-    fn from(val: Proof) -> Self {
+impl TryFrom<Proof> for prove::Proof<'_> {
+    type Error = String;
+
+    fn try_from(val: Proof) -> Result<Self, Self::Error> {
         let indices = unsafe { slice::from_raw_parts(val.indices.ptr, val.indices.len) };
 
-        post::prove::Proof {
+        let version = match val.version {
+            0 => prove::ProofVersion::of_nonce_group(val.nonce / 16),
+            version => version.try_into()?,
+        };
+        Ok(post::prove::Proof {
+            version,
             nonce: val.nonce,
             indices: Cow::from(indices),
             pow: val.pow,
-        }
+        })
     }
 }
 
@@ -105,8 +117,8 @@ fn _generate_proof(
     let challenge = unsafe { std::slice::from_raw_parts(challenge, 32) };
     let challenge = challenge.try_into()?;
 
-    let stop = AtomicBool::new(false);
-    let proof = prove::generate_proof(datadir, challenge, cfg, nonces, threads, pow_flags, stop)?;
+    let cancel = CancellationToken::new();
+    let proof = prove::generate_proof(datadir, challenge, cfg, nonces, threads, pow_flags, cancel)?;
     Ok(Box::new(Proof::from(proof)))
 }
 
@@ -205,7 +217,7 @@ pub unsafe extern "C" fn verify_proof(
 mod tests {
     use post::{
         config::ScryptParams, initialize::Initialize, metadata::ProofMetadata,
-        pow::randomx::RandomXFlag,
+        pow::randomx::RandomXFlag, prove::ProofVersion,
     };
 
     #[test]
@@ -228,6 +240,27 @@ mod tests {
         assert!(result.unwrap_err().to_string().contains("Utf8Error"));
     }
 
+    #[test]
+    fn unset_proof_version_is_implied_by_nonce() {
+        let mut indices = vec![0u8; 8];
+        let indices = crate::ArrayU8 {
+            ptr: indices.as_mut_ptr(),
+            len: indices.len(),
+            cap: indices.capacity(),
+        };
+        let proof = |nonce, version| super::Proof {
+            nonce,
+            indices,
+            pow: 0,
+            version,
+        };
+        let version = |proof: super::Proof| post::prove::Proof::try_from(proof).map(|p| p.version);
+        assert_eq!(Ok(ProofVersion::V1), version(proof(7, 0)));
+        assert_eq!(Ok(ProofVersion::V2), version(proof(256 * 16, 0)));
+        assert_eq!(Ok(ProofVersion::V1), version(proof(7, 1)));
+        assert!(version(proof(7, 3)).is_err());
+    }
+
     #[test]
     fn create_and_free_verifier() {
         let mut verifier = std::ptr::null_mut();
@@ -246,6 +279,7 @@ mod tests {
                     nonce: 0,
                     indices: crate::ArrayU8::default(),
                     pow: 0,
+                    version: 1,
                 },
                 std::ptr::null(),
                 super::ProofConfig {
@@ -341,6 +375,43 @@ mod tests {
         };
         assert_eq!(result, super::VerifyResult::Invalid);
 
+        // The version implied by the nonce if not set
+        assert_eq!(1, unsafe { (*cproof).version });
+        let unversioned_proof = unsafe {
+            crate::post_impl::Proof {
+                version: 0,
+                ..*cproof
+            }
+        };
+        let result = unsafe {
+            crate::post_impl::verify_proof(
+                verifier,
+                unversioned_proof,
+                &proof_metadata,
+                cfg,
+                init_cfg,
+            )
+        };
+        assert_eq!(result, super::VerifyResult::Ok);
+
+        // Proofs of unknown versions can't be verified
+        let unknown_version_proof = unsafe {
+            crate::post_impl::Proof {
+                version: 3,
+                ..*cproof
+            }
+        };
+        let result = unsafe {
+            crate::post_impl::verify_proof(
+                verifier,
+                unknown_version_proof,
+                &proof_metadata,
+                cfg,
+                init_cfg,
+            )
+        };
+        assert_eq!(result, super::VerifyResult::InvalidArgument);
+
         unsafe { super::free_proof(cproof) };
         super::free_verifier(verifier);
     }
@@ -9,13 +9,17 @@ use base64::{engine::general_purpose, Engine};
 use clap::{Args, Parser, Subcommand, ValueEnum};
 use eyre::Context;
 use post::{
-    config::ScryptParams,
-    initialize::{CpuInitializer, Initialize, LABEL_SIZE},
+    config::{FileLayout, ScryptParams},
+    encryption::{DataKey, EncryptingInitializer},
+    initialize::{CpuInitializer, HybridInitializer, Initialize, Progress},
 };
 use rand::seq::IteratorRandom;
 use rayon::prelude::{ParallelBridge, ParallelIterator};
 use scrypt_ocl::{ocl::DeviceType, OpenClInitializer, ProviderId};
 
+#[cfg(feature = "s3")]
+mod object_storage;
+
 /// Initialize labels on GPU
 #[derive(Parser)]
 #[command(author, version, about, long_about = None, args_conflicts_with_subcommands = true)]
@@ -33,6 +37,12 @@ enum Commands {
     Initialize(InitializeArgs),
     ListProviders,
     VerifyData(VerifyData),
+    /// Verify POS data directory and regenerate damaged files
+    Repair(RepairArgs),
+    /// Estimate time and disk space needed for initialization without writing anything
+    Estimate(EstimateArgs),
+    /// Move (or copy) POS data to another directory
+    Relocate(RelocateArgs),
 }
 
 #[derive(Args)]
@@ -49,6 +59,11 @@ struct InitializeArgs {
     #[arg(short, long, default_value_t = 4 * 1024 * 1024 * 1024)]
     max_file_size: usize,
 
+    /// Minimal number of digits of the file index in file names (zero-padded).
+    /// '0' gives the default `postdata_N.bin` naming.
+    #[arg(long, default_value_t = 0)]
+    file_index_width: u8,
+
     /// Number of units to initialize
     #[arg(short, long, default_value_t = 1)]
     units: usize,
@@ -71,14 +86,36 @@ struct InitializeArgs {
     #[arg(long)]
     provider: Option<u32>,
 
+    /// Number of threads to use for CPU initialization.
+    /// '0' means use all available threads
+    #[arg(long, default_value_t = 0)]
+    threads: usize,
+
+    /// Maximum rate of writing POS data in MiB/s for CPU initialization.
+    /// Unlimited if not specified.
+    #[arg(long)]
+    max_write_rate: Option<u64>,
+
+    /// File with the passphrase to encrypt the POS data at rest with.
+    /// The same passphrase must be given to the post-service for proving.
+    #[arg(long)]
+    encryption_passphrase_file: Option<PathBuf>,
+
     #[clap(value_enum, default_value_t = InitializationMethod::Gpu)]
     method: InitializationMethod,
+
+    #[cfg(feature = "s3")]
+    #[command(flatten, next_help_heading = "Object storage")]
+    s3: object_storage::S3Args,
 }
 
 #[derive(Clone, ValueEnum)]
 enum InitializationMethod {
     Cpu,
     Gpu,
+    /// CPU and GPUs working together.
+    /// Uses all GPU providers unless `--provider` is given.
+    Hybrid,
 }
 
 #[derive(Args)]
@@ -103,6 +140,49 @@ struct VerifyData {
     commitment_atx_id: String,
 }
 
+#[derive(Args)]
+struct EstimateArgs {
+    #[command(flatten)]
+    initialize: InitializeArgs,
+    /// Number of labels to initialize to measure the speed
+    #[arg(long, default_value_t = 64 * 1024)]
+    sample_labels: u64,
+}
+
+#[derive(Args)]
+struct RepairArgs {
+    /// Scrypt N parameter
+    #[arg(short, long, default_value_t = 8192)]
+    n: usize,
+    /// Path to POS data directory
+    #[arg(long, default_value = "./post-data")]
+    datadir: PathBuf,
+    /// Fraction of labels (in %) to verify in every file
+    #[arg(short, long, default_value_t = 0.1)]
+    fraction: f64,
+    /// Provider ID to use for GPU initialization.
+    #[arg(long)]
+    provider: Option<u32>,
+    #[clap(value_enum, default_value_t = InitializationMethod::Gpu)]
+    method: InitializationMethod,
+}
+
+#[derive(Args)]
+struct RelocateArgs {
+    /// Path to POS data directory
+    #[arg(long, default_value = "./post-data")]
+    datadir: PathBuf,
+    /// Path to the new POS data directory
+    #[arg(long)]
+    new_dir: PathBuf,
+    /// Copy the data, keeping the original directory intact
+    #[arg(long)]
+    keep_source: bool,
+    /// Don't validate copied files against checksums
+    #[arg(long)]
+    no_verify: bool,
+}
+
 fn calc_commitment(node_id: &str, commitment_atx_id: &str) -> eyre::Result<[u8; 32]> {
     let node_id = general_purpose::STANDARD.decode(node_id)?;
     let commitment_atx_id = general_purpose::STANDARD.decode(commitment_atx_id)?;
@@ -167,31 +247,132 @@ fn verify_data(args: VerifyData) -> eyre::Result<()> {
     Ok(())
 }
 
-fn initialize(args: InitializeArgs) -> eyre::Result<()> {
-    eyre::ensure!(args.n.is_power_of_two(), "scrypt N must be a power of two");
+/// CPU and GPU initializers working together.
+fn hybrid_initializer(
+    cpu: CpuInitializer,
+    provider: Option<u32>,
+    n: usize,
+) -> eyre::Result<HybridInitializer> {
+    let mut initializer = HybridInitializer::new().with_worker("cpu", Box::new(cpu));
+    // The same provider IDs as listed by `initializer list-providers`.
+    let device_types = Some(DeviceType::GPU | DeviceType::CPU);
+    let providers = match provider {
+        Some(id) => vec![id],
+        None => scrypt_ocl::get_providers(device_types)?
+            .iter()
+            .enumerate()
+            .filter(|(_, p)| p.class == DeviceType::GPU)
+            .map(|(id, _)| id as u32)
+            .collect(),
+    };
+    for id in providers {
+        let gpu = OpenClInitializer::new(Some(ProviderId(id)), n, device_types)?;
+        initializer = initializer.with_worker(format!("gpu-{id}"), Box::new(gpu));
+    }
+    Ok(initializer)
+}
 
+fn build_initializer(args: &InitializeArgs) -> eyre::Result<Box<dyn Initialize>> {
+    let cpu_initializer = || -> eyre::Result<CpuInitializer> {
+        let mut initializer =
+            CpuInitializer::with_threads(ScryptParams::new(args.n, 1, 1), args.threads)?;
+        if let Some(rate) = args.max_write_rate {
+            initializer = initializer.with_write_rate_limit(rate * 1024 * 1024);
+        }
+        Ok(initializer)
+    };
     let mut initializer: Box<dyn Initialize> = match args.method {
-        InitializationMethod::Cpu => Box::new(CpuInitializer::new(ScryptParams::new(args.n, 1, 1))),
+        InitializationMethod::Cpu => Box::new(cpu_initializer()?),
         InitializationMethod::Gpu => Box::new(OpenClInitializer::new(
             args.provider.map(ProviderId),
             args.n,
             Some(DeviceType::GPU | DeviceType::CPU),
         )?),
+        InitializationMethod::Hybrid => Box::new(hybrid_initializer(
+            cpu_initializer()?,
+            args.provider,
+            args.n,
+        )?),
     };
 
+    if let Some(path) = &args.encryption_passphrase_file {
+        let passphrase = std::fs::read_to_string(path)
+            .wrap_err_with(|| format!("reading passphrase from {}", path.display()))?;
+        let commitment = calc_commitment(&args.node_id, &args.commitment_atx_id)?;
+        let key = DataKey::derive(passphrase.trim_end().as_bytes(), &commitment);
+        initializer = Box::new(EncryptingInitializer::new(initializer, key));
+    }
+
+    Ok(initializer)
+}
+
+fn initialize(args: InitializeArgs) -> eyre::Result<()> {
+    eyre::ensure!(args.n.is_power_of_two(), "scrypt N must be a power of two");
+
+    let mut initializer = build_initializer(&args)?;
+
     let node_id = general_purpose::STANDARD.decode(args.node_id)?;
     let commitment_atx_id = general_purpose::STANDARD.decode(args.commitment_atx_id)?;
+    let layout = FileLayout {
+        max_file_size: args.max_file_size as u64,
+        file_index_width: args.file_index_width,
+    };
 
     let now = time::Instant::now();
+
+    #[cfg(feature = "s3")]
+    if args.s3.bucket.is_some() {
+        let metadata = object_storage::initialize(
+            initializer.as_mut(),
+            &args.s3,
+            node_id.as_slice().try_into()?,
+            commitment_atx_id.as_slice().try_into()?,
+            args.labels_per_unit as u64,
+            args.units as u32,
+            layout,
+            Some([0xFFu8; 32]),
+        )?;
+        println!(
+            "Initialization to object storage took {:.2} seconds, vrf_nonce: {:?}",
+            now.elapsed().as_secs_f64(),
+            metadata.nonce
+        );
+        return Ok(());
+    }
+
+    let mut last_report = now;
+    // Labels written at the first report. Labels initialized before resuming
+    // must not be counted in the speed.
+    let mut first_report = None;
+    let mut report_progress = |progress: Progress| {
+        let (start, start_labels) =
+            *first_report.get_or_insert((time::Instant::now(), progress.labels_written));
+        if last_report.elapsed() < time::Duration::from_secs(10) {
+            return;
+        }
+        last_report = time::Instant::now();
+        let labels = progress.labels_written - start_labels;
+        let speed = labels as f64 / start.elapsed().as_secs_f64();
+        let remaining = progress.total_labels - progress.labels_written;
+        println!(
+            "file {}: {}/{} labels ({:.2}%), ETA: {:.0} seconds",
+            progress.file_id,
+            progress.labels_written,
+            progress.total_labels,
+            progress.labels_written as f64 * 100.0 / progress.total_labels as f64,
+            remaining as f64 / speed,
+        );
+    };
     let metadata = initializer
-        .initialize(
+        .initialize_with_progress(
             &args.output,
             node_id.as_slice().try_into()?,
             commitment_atx_id.as_slice().try_into()?,
             args.labels_per_unit as u64,
             args.units as u32,
-            (args.max_file_size / LABEL_SIZE) as u64,
+            layout,
             Some([0xFFu8; 32]),
+            &mut report_progress,
         )
         .map_err(|e| eyre::eyre!("initializing: {}", e))?;
 
@@ -207,6 +388,88 @@ fn initialize(args: InitializeArgs) -> eyre::Result<()> {
     Ok(())
 }
 
+fn repair(args: RepairArgs) -> eyre::Result<()> {
+    let scrypt_params = ScryptParams::new(args.n, 1, 1);
+    let report = post::initialize::verify_data(&args.datadir, args.fraction, scrypt_params)?;
+    for file in report.damaged_files() {
+        println!(
+            "file {}: missing: {}, corrupted labels: {}/{}",
+            file.file_id,
+            file.missing,
+            file.corrupted_labels.len(),
+            file.labels_checked
+        );
+    }
+    if report.is_ok() {
+        println!("Data verified successfully");
+        return Ok(());
+    }
+
+    let mut initializer: Box<dyn Initialize> = match args.method {
+        InitializationMethod::Cpu => Box::new(CpuInitializer::new(scrypt_params)),
+        InitializationMethod::Gpu => Box::new(OpenClInitializer::new(
+            args.provider.map(ProviderId),
+            args.n,
+            Some(DeviceType::GPU | DeviceType::CPU),
+        )?),
+        InitializationMethod::Hybrid => Box::new(hybrid_initializer(
+            CpuInitializer::new(scrypt_params),
+            args.provider,
+            args.n,
+        )?),
+    };
+    let repaired = post::initialize::repair_data(initializer.as_mut(), &args.datadir, &report)?;
+    println!("Repaired files: {repaired:?}");
+    Ok(())
+}
+
+fn estimate(args: EstimateArgs) -> eyre::Result<()> {
+    let init = &args.initialize;
+    eyre::ensure!(init.n.is_power_of_two(), "scrypt N must be a power of two");
+    let mut initializer = build_initializer(init)?;
+    let layout = FileLayout {
+        max_file_size: init.max_file_size as u64,
+        file_index_width: init.file_index_width,
+    };
+    let estimate = post::initialize::estimate(
+        initializer.as_mut(),
+        init.labels_per_unit as u64,
+        init.units as u32,
+        layout,
+        args.sample_labels,
+    )
+    .map_err(|e| eyre::eyre!("estimating: {e}"))?;
+
+    println!(
+        "Labels: {} in {} files",
+        estimate.total_labels, estimate.num_files
+    );
+    println!(
+        "Disk space: {:.2} GiB",
+        estimate.disk_space as f64 / 1024.0 / 1024.0 / 1024.0
+    );
+    println!("Speed: {:.0} labels/sec", estimate.labels_per_sec);
+    println!(
+        "Write rate: {:.2} MiB/sec",
+        estimate.write_rate / 1024.0 / 1024.0
+    );
+    println!(
+        "Expected time: {:.2} hours",
+        estimate.duration.as_secs_f64() / 3600.0
+    );
+    Ok(())
+}
+
+fn relocate(args: RelocateArgs) -> eyre::Result<()> {
+    let options = post::initialize::RelocateOptions {
+        keep_source: args.keep_source,
+        verify: !args.no_verify,
+    };
+    post::initialize::relocate(&args.datadir, &args.new_dir, options)?;
+    println!("POS data relocated to {}", args.new_dir.display());
+    Ok(())
+}
+
 fn list_providers() -> eyre::Result<()> {
     let providers = scrypt_ocl::get_providers(Some(DeviceType::GPU | DeviceType::CPU))?;
     for (id, provider) in providers.iter().enumerate() {
@@ -227,6 +490,9 @@ fn main() -> eyre::Result<()> {
         Commands::Initialize(args) => initialize(args)?,
         Commands::ListProviders => list_providers()?,
         Commands::VerifyData(v) => verify_data(v)?,
+        Commands::Repair(args) => repair(args)?,
+        Commands::Estimate(args) => estimate(args)?,
+        Commands::Relocate(args) => relocate(args)?,
     }
 
     Ok(())
@@ -0,0 +1,248 @@
+//! Initialization directly to S3-compatible object storage.
+//!
+//! Each POS data file is streamed as a multipart upload, so that
+//! no local disk space is required for the POS data.
+
+use std::io::Write;
+
+use clap::Args;
+use eyre::Context;
+use post::{
+    config::FileLayout,
+    initialize::{calc_commitment, Initialize},
+    metadata::PostMetadata,
+};
+use s3::{creds::Credentials, serde_types::Part, Bucket, Region};
+
+/// Minimal size of a part in S3 multipart upload (except the last one).
+const MIN_PART_SIZE: usize = 5 * 1024 * 1024;
+const CONTENT_TYPE: &str = "application/octet-stream";
+
+/// S3-compatible object storage to initialize to.
+///
+/// Credentials are read from the standard AWS environment variables
+/// or the credentials file.
+#[derive(Args, Debug)]
+#[group(required = false)]
+pub(crate) struct S3Args {
+    /// Name of the bucket to upload POS data to.
+    /// Enables uploading to object storage instead of the output directory.
+    #[arg(long = "s3-bucket")]
+    pub(crate) bucket: Option<String>,
+
+    /// Region of the bucket
+    #[arg(long = "s3-region", default_value = "us-east-1")]
+    region: String,
+
+    /// Custom endpoint of S3-compatible storage (i.e. MinIO).
+    #[arg(long = "s3-endpoint")]
+    endpoint: Option<String>,
+
+    /// Prefix (directory) of the objects in the bucket
+    #[arg(long = "s3-prefix", default_value = "")]
+    prefix: String,
+
+    /// Size of a single part of multipart upload in MiB
+    #[arg(long = "s3-part-size", default_value_t = 64)]
+    part_size: usize,
+}
+
+impl S3Args {
+    fn bucket(&self) -> eyre::Result<Bucket> {
+        let name = self
+            .bucket
+            .as_deref()
+            .ok_or_else(|| eyre::eyre!("no bucket"))?;
+        let region = match &self.endpoint {
+            Some(endpoint) => Region::Custom {
+                region: self.region.clone(),
+                endpoint: endpoint.clone(),
+            },
+            None => self.region.parse()?,
+        };
+        let credentials = Credentials::default().wrap_err("loading S3 credentials")?;
+        let bucket = Bucket::new(name, region, credentials)?;
+        Ok(match self.endpoint {
+            Some(_) => bucket.with_path_style(),
+            None => bucket,
+        })
+    }
+
+    fn key(&self, name: &str) -> String {
+        if self.prefix.is_empty() {
+            name.to_string()
+        } else {
+            format!("{}/{name}", self.prefix.trim_end_matches('/'))
+        }
+    }
+}
+
+/// Writer uploading the data as parts of S3 multipart upload.
+struct MultipartUpload<'a> {
+    bucket: &'a Bucket,
+    key: String,
+    upload_id: String,
+    parts: Vec<Part>,
+    buffer: Vec<u8>,
+    part_size: usize,
+}
+
+impl<'a> MultipartUpload<'a> {
+    fn new(bucket: &'a Bucket, key: String, part_size: usize) -> eyre::Result<Self> {
+        let response = bucket
+            .initiate_multipart_upload(&key, CONTENT_TYPE)
+            .wrap_err_with(|| format!("initiating upload of {key}"))?;
+        let part_size = part_size.max(MIN_PART_SIZE);
+        Ok(Self {
+            bucket,
+            key,
+            upload_id: response.upload_id,
+            parts: Vec::new(),
+            buffer: Vec::with_capacity(part_size),
+            part_size,
+        })
+    }
+
+    fn upload_part(&mut self) -> std::io::Result<()> {
+        let part_number = self.parts.len() as u32 + 1;
+        let chunk = std::mem::replace(&mut self.buffer, Vec::with_capacity(self.part_size));
+        log::debug!(
+            "uploading part {part_number} of {} ({} bytes)",
+            self.key,
+            chunk.len()
+        );
+        let part = self
+            .bucket
+            .put_multipart_chunk(chunk, &self.key, part_number, &self.upload_id, CONTENT_TYPE)
+            .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?;
+        self.parts.push(part);
+        Ok(())
+    }
+
+    /// Upload the rest of the data and complete the upload,
+    /// aborting it on failure not to leave the uploaded parts behind.
+    fn complete(mut self) -> eyre::Result<()> {
+        let result = self.upload_rest();
+        if result.is_err() {
+            self.abort();
+        }
+        result
+    }
+
+    fn upload_rest(&mut self) -> eyre::Result<()> {
+        if !self.buffer.is_empty() || self.parts.is_empty() {
+            self.upload_part()
+                .wrap_err_with(|| format!("uploading last part of {}", self.key))?;
+        }
+        self.bucket
+            .complete_multipart_upload(&self.key, &self.upload_id, std::mem::take(&mut self.parts))
+            .wrap_err_with(|| format!("completing upload of {}", self.key))?;
+        Ok(())
+    }
+
+    fn abort(self) {
+        if let Err(e) = self.bucket.abort_upload(&self.key, &self.upload_id) {
+            log::warn!("failed to abort upload of {}: {e}", self.key);
+        }
+    }
+}
+
+impl Write for MultipartUpload<'_> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let len = buf.len().min(self.part_size - self.buffer.len());
+        self.buffer.extend_from_slice(&buf[..len]);
+        if self.buffer.len() == self.part_size {
+            self.upload_part()?;
+        }
+        Ok(len)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Initialize POS data uploading it to the object storage.
+#[allow(clippy::too_many_arguments)]
+pub(crate) fn initialize(
+    initializer: &mut dyn Initialize,
+    args: &S3Args,
+    node_id: &[u8; 32],
+    commitment_atx_id: &[u8; 32],
+    labels_per_unit: u64,
+    num_units: u32,
+    layout: FileLayout,
+    mut vrf_difficulty: Option<[u8; 32]>,
+) -> eyre::Result<PostMetadata> {
+    layout.validate()?;
+    let bucket = args.bucket()?;
+    let commitment = calc_commitment(node_id, commitment_atx_id);
+    let total_labels = labels_per_unit * num_units as u64;
+    let labels_per_file = layout.labels_per_file();
+    let files_number = (total_labels + labels_per_file - 1) / labels_per_file;
+
+    let mut nonce = None;
+    for file_id in 0..files_number {
+        let key = args.key(&layout.file_name(file_id));
+        log::info!("initializing s3://{}/{key}", bucket.name());
+        let mut upload = MultipartUpload::new(&bucket, key, args.part_size * 1024 * 1024)?;
+
+        let index = file_id * labels_per_file;
+        let labels = index..total_labels.min(index + labels_per_file);
+        match initializer.initialize_to(&mut upload, &commitment, labels, vrf_difficulty) {
+            Ok(new_nonce) => {
+                if let Some(n) = new_nonce {
+                    vrf_difficulty = Some(n.label);
+                    nonce = Some(n);
+                }
+                upload.complete()?;
+            }
+            Err(e) => {
+                upload.abort();
+                eyre::bail!("initializing file {file_id}: {e}");
+            }
+        }
+    }
+
+    let metadata = PostMetadata {
+        node_id: *node_id,
+        commitment_atx_id: *commitment_atx_id,
+        labels_per_unit,
+        num_units,
+        max_file_size: layout.max_file_size,
+        file_index_width: layout.file_index_width,
+        nonce: nonce.map(|n| n.index),
+        last_position: None,
+        encryption_key_check: None,
+    };
+    bucket
+        .put_object(
+            args.key("postdata_metadata.json"),
+            &serde_json::to_vec_pretty(&metadata)?,
+        )
+        .wrap_err("uploading metadata")?;
+
+    Ok(metadata)
+}
+
+#[cfg(test)]
+mod tests {
+    use clap::Parser;
+
+    use super::S3Args;
+
+    #[derive(Parser)]
+    struct Cli {
+        #[command(flatten)]
+        s3: S3Args,
+    }
+
+    #[test]
+    fn object_keys() {
+        let cli = Cli::parse_from(["test", "--s3-bucket", "b"]);
+        assert_eq!("postdata_0.bin", cli.s3.key("postdata_0.bin"));
+
+        let cli = Cli::parse_from(["test", "--s3-bucket", "b", "--s3-prefix", "smesher/"]);
+        assert_eq!("smesher/postdata_0.bin", cli.s3.key("postdata_0.bin"));
+    }
+}
@@ -12,6 +12,7 @@ use std::{
 use clap::{Args, Parser, Subcommand, ValueEnum};
 use eyre::Context;
 use post::{
+    cancel::CancellationToken,
     pow::{self, randomx, Prover as PowProver},
     prove::{Prover, Prover8_56, ProvingParams},
 };
@@ -219,7 +220,7 @@ fn proving(args: ProvingArgs) -> eyre::Result<()> {
         .build()?;
 
     let mut pow_prover = pow::MockProver::new();
-    pow_prover.expect_prove().returning(|_, _, _, _| Ok(0));
+    pow_prover.expect_prove().returning(|_, _, _, _, _| Ok(0));
     let prover = Prover8_56::new(challenge, 0..args.nonces, params, &pow_prover, &[7; 32])?;
 
     let mut total_time = time::Duration::from_secs(0);
@@ -283,10 +284,11 @@ fn pow(args: PowArgs) -> eyre::Result<()> {
         .num_threads(args.threads)
         .build()?;
 
+    let cancel = CancellationToken::new();
     pool.install(|| -> eyre::Result<()> {
         for i in 0..args.iterations {
             let start = time::Instant::now();
-            prover.prove(7, &i.to_le_bytes(), &args.difficulty, &[7; 32])?;
+            prover.prove(7, &i.to_le_bytes(), &args.difficulty, &[7; 32], &cancel)?;
             let duration = start.elapsed();
             eprintln!(
                 "[{i}]: {duration:.2?} (scaled: {:.2?})",
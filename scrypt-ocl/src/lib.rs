@@ -1,24 +1,31 @@
 use ocl::{
     builders::ProgramBuilder,
     enums::{DeviceInfo, DeviceInfoResult, KernelWorkGroupInfo, KernelWorkGroupInfoResult},
-    Buffer, Device, DeviceType, Kernel, MemFlags, Platform, ProQue, SpatialDims,
+    Buffer, Device, DeviceType, Kernel, MemFlags, Platform, ProQue, Queue, SpatialDims,
 };
 use post::initialize::{Initialize, VrfNonce, ENTIRE_LABEL_SIZE, LABEL_SIZE};
-use std::{cmp::min, fmt::Display, io::Write, ops::Range};
+use std::{cmp::min, fmt::Display, io::Write, ops::Range, time::Instant};
 use thiserror::Error;
 
 pub use ocl;
+pub use scanning::OpenClLabelScanner;
+pub use stats::ScryptStats;
 
 mod filtering;
+mod scanning;
+mod stats;
 
 #[derive(Debug)]
 struct Scrypter {
     kernel: Kernel,
+    queue: Queue,
     input: Buffer<u32>,
     output: Buffer<u8>,
     global_work_size: usize,
     preferred_wg_size_mult: usize,
     labels_buffer: Vec<u8>,
+    last_batch_stats: ScryptStats,
+    stats: ScryptStats,
 }
 
 #[derive(Error, Debug)]
@@ -110,6 +117,30 @@ pub fn get_providers(device_types: Option<DeviceType>) -> Result<Vec<Provider>,
     Ok(providers)
 }
 
+/// Select the provider with the given ID or the first one available.
+pub(crate) fn select_provider(
+    provider_id: Option<ProviderId>,
+    device_types: Option<DeviceType>,
+) -> Result<Provider, ScryptError> {
+    let providers = get_providers(device_types)?;
+    if let Some(id) = provider_id {
+        log::info!(
+            "selecting {} provider from {} available",
+            id.0,
+            providers.len()
+        );
+        providers
+            .into_iter()
+            .nth(id.0 as usize)
+            .ok_or(ScryptError::InvalidProviderId(id))
+    } else {
+        providers
+            .into_iter()
+            .next()
+            .ok_or(ScryptError::NoProvidersAvailable)
+    }
+}
+
 fn scan_for_vrf_nonce(labels: &[u8], mut difficulty: [u8; 32]) -> Option<VrfNonce> {
     let mut nonce = None;
     for (id, label) in labels.chunks(ENTIRE_LABEL_SIZE).enumerate() {
@@ -231,14 +262,108 @@ impl Scrypter {
 
         Ok(Self {
             kernel,
+            queue: pro_que.queue().clone(),
             input,
             output,
             global_work_size,
             preferred_wg_size_mult,
             labels_buffer: vec![0u8; global_work_size * ENTIRE_LABEL_SIZE],
+            last_batch_stats: ScryptStats::default(),
+            stats: ScryptStats::default(),
         })
     }
 
+    fn set_commitment(&mut self, commitment: &[u8; 32]) -> Result<(), ScryptError> {
+        let commitment: Vec<u32> = commitment
+            .chunks(4)
+            .map(|chunk| u32::from_le_bytes(chunk.try_into().unwrap()))
+            .collect();
+        self.input.write(&commitment).enq()?;
+        Ok(())
+    }
+
+    /// Run the kernel for a single batch of labels starting at `index`.
+    ///
+    /// The labels are left in `labels_buffer` as a continuous buffer of 16B labels.
+    /// Returns the number of labels initialized and the VRF nonce found in the batch (if any).
+    fn scrypt_batch(
+        &mut self,
+        index: u64,
+        labels_end: u64,
+        vrf_difficulty: Option<[u8; 32]>,
+    ) -> Result<(usize, Option<VrfNonce>), ScryptError> {
+        self.kernel.set_arg(1, index)?;
+
+        let index_end = min(index + self.global_work_size as u64, labels_end);
+        let labels_to_init = (index_end - index) as usize;
+
+        let gws = if labels_to_init < self.global_work_size {
+            // Round up labels_to_init to be a multiple of preferred_wg_size_mult
+            (labels_to_init + self.preferred_wg_size_mult - 1) / self.preferred_wg_size_mult
+                * self.preferred_wg_size_mult
+        } else {
+            self.global_work_size
+        };
+        self.kernel
+            .set_default_global_work_size(SpatialDims::One(gws));
+
+        let kernel_time = Instant::now();
+        unsafe {
+            self.kernel.enq()?;
+        }
+        self.queue.finish()?;
+        let kernel_time = kernel_time.elapsed();
+
+        let transfer_time = Instant::now();
+        let labels_buffer =
+            &mut self.labels_buffer.as_mut_slice()[..labels_to_init * ENTIRE_LABEL_SIZE];
+        self.output.read(labels_buffer.as_mut()).enq()?;
+        let transfer_time = transfer_time.elapsed();
+
+        let post_processing_time = Instant::now();
+
+        // Look for VRF nonce if enabled
+        // TODO: run in background / in parallel to GPU
+        let nonce = vrf_difficulty
+            .and_then(|difficulty| scan_for_vrf_nonce(labels_buffer, difficulty))
+            .map(|nonce| VrfNonce {
+                index: nonce.index + index,
+                label: nonce.label,
+            });
+
+        // Move labels in labels_buffer, taking only 16B of each label in-place, creating a continuous buffer of 16B labels.
+        // TODO: run in background / in parallel to GPU
+        let mut dst = 0;
+        for label_id in 0..labels_to_init {
+            let src = label_id * ENTIRE_LABEL_SIZE;
+            labels_buffer.copy_within(src..src + LABEL_SIZE, dst);
+            dst += LABEL_SIZE;
+        }
+
+        self.record_stats(ScryptStats {
+            labels: labels_to_init as u64,
+            kernel_time,
+            transfer_time,
+            post_processing_time: post_processing_time.elapsed(),
+            write_time: Default::default(),
+        });
+        Ok((labels_to_init, nonce))
+    }
+
+    fn record_stats(&mut self, batch: ScryptStats) {
+        self.last_batch_stats = batch;
+        self.stats += batch;
+        log::trace!(
+            "batch stats: {batch:?} ({:.0} labels/s)",
+            batch.labels_per_sec()
+        );
+    }
+
+    fn record_write_time(&mut self, write_time: std::time::Duration) {
+        self.last_batch_stats.write_time += write_time;
+        self.stats.write_time += write_time;
+    }
+
     pub fn scrypt<W: std::io::Write + ?Sized>(
         &mut self,
         writer: &mut W,
@@ -246,66 +371,89 @@ impl Scrypter {
         commitment: &[u8; 32],
         mut vrf_difficulty: Option<[u8; 32]>,
     ) -> Result<Option<VrfNonce>, ScryptError> {
-        let commitment: Vec<u32> = commitment
-            .chunks(4)
-            .map(|chunk| u32::from_le_bytes(chunk.try_into().unwrap()))
-            .collect();
-        self.input.write(&commitment).enq()?;
+        self.set_commitment(commitment)?;
 
         let mut best_nonce = None;
         let labels_end = labels.end;
 
         for index in labels.step_by(self.global_work_size) {
-            self.kernel.set_arg(1, index)?;
-
-            let index_end = min(index + self.global_work_size as u64, labels_end);
-            let labels_to_init = (index_end - index) as usize;
-
-            let gws = if labels_to_init < self.global_work_size {
-                // Round up labels_to_init to be a multiple of preferred_wg_size_mult
-                (labels_to_init + self.preferred_wg_size_mult - 1) / self.preferred_wg_size_mult
-                    * self.preferred_wg_size_mult
-            } else {
-                self.global_work_size
-            };
-            self.kernel
-                .set_default_global_work_size(SpatialDims::One(gws));
-
-            unsafe {
-                self.kernel.enq()?;
+            let (count, nonce) = self.scrypt_batch(index, labels_end, vrf_difficulty)?;
+            if let Some(nonce) = nonce {
+                best_nonce = Some(nonce);
+                vrf_difficulty = Some(nonce.label);
+                log::trace!("Found new smallest nonce: {best_nonce:?}");
             }
+            let write_time = Instant::now();
+            writer.write_all(&self.labels_buffer[..count * LABEL_SIZE])?;
+            self.record_write_time(write_time.elapsed());
+        }
+        Ok(best_nonce)
+    }
+}
 
-            let labels_buffer =
-                &mut self.labels_buffer.as_mut_slice()[..labels_to_init * ENTIRE_LABEL_SIZE];
-            self.output.read(labels_buffer.as_mut()).enq()?;
-
-            // Look for VRF nonce if enabled
-            // TODO: run in background / in parallel to GPU
-            if let Some(difficulty) = vrf_difficulty {
-                if let Some(nonce) = scan_for_vrf_nonce(labels_buffer, difficulty) {
-                    best_nonce = Some(VrfNonce {
-                        index: nonce.index + index,
-                        label: nonce.label,
-                    });
-                    vrf_difficulty = Some(nonce.label);
-                    log::trace!("Found new smallest nonce: {best_nonce:?}");
-                }
-            }
+/// A chunk of labels produced by a single kernel run.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LabelChunk {
+    /// Index of the first label in the chunk.
+    pub index: u64,
+    /// Continuous buffer of 16B labels.
+    pub labels: Vec<u8>,
+    /// VRF nonce found in this chunk, better than any found in previous chunks.
+    pub nonce: Option<VrfNonce>,
+}
 
-            // Move labels in labels_buffer, taking only 16B of each label in-place, creating a continuous buffer of 16B labels.
-            // TODO: run in background / in parallel to GPU
-            let mut dst = 0;
-            for label_id in 0..labels_to_init {
-                let src = label_id * ENTIRE_LABEL_SIZE;
-                labels_buffer.copy_within(src..src + LABEL_SIZE, dst);
-                dst += LABEL_SIZE;
-            }
-            writer.write_all(&labels_buffer[..dst])?;
+impl LabelChunk {
+    /// Range of label indices covered by the chunk.
+    pub fn range(&self) -> Range<u64> {
+        self.index..self.index + (self.labels.len() / LABEL_SIZE) as u64
+    }
+}
+
+/// Iterator over [LabelChunk]s yielded as the device computes them.
+///
+/// Created with [OpenClInitializer::label_chunks].
+pub struct LabelChunks<'a> {
+    scrypter: &'a mut Scrypter,
+    labels: Range<u64>,
+    vrf_difficulty: Option<[u8; 32]>,
+}
+
+impl Iterator for LabelChunks<'_> {
+    type Item = Result<LabelChunk, ScryptError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.labels.is_empty() {
+            return None;
         }
-        Ok(best_nonce)
+        let index = self.labels.start;
+        let result = self
+            .scrypter
+            .scrypt_batch(index, self.labels.end, self.vrf_difficulty)
+            .map(|(count, nonce)| {
+                if let Some(nonce) = nonce {
+                    self.vrf_difficulty = Some(nonce.label);
+                    log::trace!("Found new smallest nonce: {nonce:?}");
+                }
+                LabelChunk {
+                    index,
+                    labels: self.scrypter.labels_buffer[..count * LABEL_SIZE].to_vec(),
+                    nonce,
+                }
+            });
+        match &result {
+            Ok(chunk) => self.labels.start = chunk.range().end,
+            // Don't continue after an error
+            Err(_) => self.labels.start = self.labels.end,
+        }
+        Some(result)
     }
 }
 
+/// OpenCL-backed initializer.
+///
+/// Implements [Initialize] so it can be used interchangeably with
+/// [CpuInitializer](post::initialize::CpuInitializer) to initialize
+/// a whole POS data directory.
 pub struct OpenClInitializer {
     scrypter: Scrypter,
 }
@@ -316,27 +464,42 @@ impl OpenClInitializer {
         n: usize,
         device_types: Option<DeviceType>,
     ) -> Result<Self, ScryptError> {
-        let providers = get_providers(device_types)?;
-        let provider = if let Some(id) = provider_id {
-            log::info!(
-                "selecting {} provider from {} available",
-                id.0,
-                providers.len()
-            );
-            providers
-                .get(id.0 as usize)
-                .ok_or(ScryptError::InvalidProviderId(id))?
-        } else {
-            providers.first().ok_or(ScryptError::NoProvidersAvailable)?
-        };
-        let platform = provider.platform;
-        let device = provider.device;
+        let provider = select_provider(provider_id, device_types)?;
         log::info!("Using provider: {provider}");
 
-        let scrypter = Scrypter::new(platform, device, n)?;
+        let scrypter = Scrypter::new(provider.platform, provider.device, n)?;
 
         Ok(Self { scrypter })
     }
+
+    /// Statistics of the most recently initialized batch of labels.
+    pub fn last_batch_stats(&self) -> ScryptStats {
+        self.scrypter.last_batch_stats
+    }
+
+    /// Cumulative statistics of all labels initialized by this initializer.
+    pub fn stats(&self) -> ScryptStats {
+        self.scrypter.stats
+    }
+
+    /// Initialize labels in the given range, yielding chunks of labels as soon
+    /// as they are computed.
+    ///
+    /// It allows consumers (hashing, uploading, writing) to process each chunk
+    /// without waiting for the whole range to be initialized.
+    pub fn label_chunks(
+        &mut self,
+        commitment: &[u8; 32],
+        labels: Range<u64>,
+        vrf_difficulty: Option<[u8; 32]>,
+    ) -> Result<LabelChunks<'_>, ScryptError> {
+        self.scrypter.set_commitment(commitment)?;
+        Ok(LabelChunks {
+            scrypter: &mut self.scrypter,
+            labels,
+            vrf_difficulty,
+        })
+    }
 }
 
 impl Initialize for OpenClInitializer {
@@ -531,5 +694,84 @@ mod tests {
 
         assert_eq!(expected.len(), labels.len());
         assert_eq!(expected, labels);
+
+        let stats = initializer.stats();
+        assert_eq!(2 * gws, stats.labels);
+        assert!(stats.labels_per_sec() > 0.0);
+        assert_eq!(gws - smaller_batch, initializer.last_batch_stats().labels);
+    }
+
+    #[test]
+    fn streaming_label_chunks() {
+        const N: usize = 512;
+        let commitment = b"this is some commitment for init";
+
+        let mut initializer = OpenClInitializer::new(None, N, None).unwrap();
+        let gws = initializer.scrypter.global_work_size as u64;
+        let indices = 7..2 * gws + 3;
+
+        let mut labels = Vec::<u8>::new();
+        let mut next_index = indices.start;
+        let mut nonce = None;
+        for chunk in initializer
+            .label_chunks(commitment, indices.clone(), Some([0xFFu8; 32]))
+            .unwrap()
+        {
+            let chunk = chunk.unwrap();
+            assert_eq!(next_index, chunk.index);
+            next_index = chunk.range().end;
+            labels.extend_from_slice(&chunk.labels);
+            nonce = chunk.nonce.or(nonce);
+        }
+        assert_eq!(indices.end, next_index);
+
+        let mut expected = Vec::<u8>::new();
+        let expected_nonce = CpuInitializer::new(ScryptParams::new(N, 1, 1))
+            .initialize_to(&mut expected, commitment, indices, Some([0xFFu8; 32]))
+            .unwrap();
+
+        assert_eq!(expected, labels);
+        assert_eq!(expected_nonce, nonce);
+    }
+
+    #[test]
+    fn initialize_datadir_same_as_cpu() {
+        const N: usize = 512;
+        let datadir = tempfile::tempdir().unwrap();
+
+        let mut initializers: [(Box<dyn Initialize>, &str); 2] = [
+            (
+                Box::new(OpenClInitializer::new(None, N, None).unwrap()),
+                "gpu",
+            ),
+            (
+                Box::new(CpuInitializer::new(ScryptParams::new(N, 1, 1))),
+                "cpu",
+            ),
+        ];
+        let metadata = initializers
+            .iter_mut()
+            .map(|(initializer, dir)| {
+                initializer
+                    .initialize(
+                        &datadir.path().join(dir),
+                        &[1u8; 32],
+                        &[2u8; 32],
+                        1000,
+                        3,
+                        700,
+                        Some([0xFFu8; 32]),
+                    )
+                    .unwrap()
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(metadata[0].nonce, metadata[1].nonce);
+
+        for id in 0..metadata[0].num_files() {
+            let file = format!("postdata_{id}.bin");
+            let gpu = std::fs::read(datadir.path().join("gpu").join(&file)).unwrap();
+            let cpu = std::fs::read(datadir.path().join("cpu").join(&file)).unwrap();
+            assert_eq!(cpu, gpu, "{file} differs");
+        }
     }
 }
@@ -0,0 +1,222 @@
+//! Scanning POS labels for candidates passing the proving difficulty on a GPU.
+
+use ocl::{builders::ProgramBuilder, Buffer, DeviceType, Kernel, MemFlags, OclPrm, ProQue};
+use post::prove::{Candidate, LabelScanner};
+
+use crate::{select_provider, ProviderId, ScryptError};
+
+const LABEL_SIZE: usize = 16;
+const ROUND_KEYS_SIZE: usize = 176;
+/// Initial capacity of the hits buffer (in candidates). It grows if needed.
+const INITIAL_MAX_HITS: usize = 4096;
+
+/// [LabelScanner] evaluating the AES of labels with OpenCL.
+pub struct OpenClLabelScanner {
+    pro_que: ProQue,
+    kernel: Kernel,
+    labels: Buffer<u8>,
+    round_keys: Buffer<u8>,
+    hits_count: Buffer<u32>,
+    /// (label, cipher, offset) of every candidate.
+    hits: Buffer<u32>,
+    max_hits: usize,
+}
+
+fn buffer<T: OclPrm>(pro_que: &ProQue, len: usize, flags: MemFlags) -> ocl::Result<Buffer<T>> {
+    Buffer::<T>::builder()
+        .queue(pro_que.queue().clone())
+        .len(len.max(1))
+        .flags(flags)
+        .build()
+}
+
+impl OpenClLabelScanner {
+    pub fn new(
+        provider_id: Option<ProviderId>,
+        device_types: Option<DeviceType>,
+    ) -> Result<Self, ScryptError> {
+        let provider = select_provider(provider_id, device_types)?;
+        log::info!("Using provider for scanning labels: {provider}");
+
+        let program_builder = ProgramBuilder::new().source(include_str!("aes.cl")).clone();
+        let pro_que = ProQue::builder()
+            .platform(provider.platform)
+            .device(provider.device)
+            .prog_bldr(program_builder)
+            .dims(1)
+            .build()?;
+
+        let labels = buffer(&pro_que, 0, MemFlags::new().read_only())?;
+        let round_keys = buffer(&pro_que, 0, MemFlags::new().read_write())?;
+        let hits_count = buffer(&pro_que, 1, MemFlags::new().read_write())?;
+        let hits = buffer(&pro_que, INITIAL_MAX_HITS * 3, MemFlags::new().write_only())?;
+
+        let kernel = pro_que
+            .kernel_builder("scan_labels")
+            .arg(&labels)
+            .arg(0u32)
+            .arg(&round_keys)
+            .arg(0u32)
+            .arg(0u8)
+            .arg(&hits_count)
+            .arg(&hits)
+            .arg(INITIAL_MAX_HITS as u32)
+            .build()?;
+
+        Ok(Self {
+            pro_que,
+            kernel,
+            labels,
+            round_keys,
+            hits_count,
+            hits,
+            max_hits: INITIAL_MAX_HITS,
+        })
+    }
+
+    fn expand_keys(&mut self, keys: &[[u8; 16]], difficulty_msb: u8) -> Result<(), ScryptError> {
+        let keys_flat = keys.concat();
+        let keys_buffer = Buffer::<u8>::builder()
+            .queue(self.pro_que.queue().clone())
+            .len(keys_flat.len())
+            .flags(MemFlags::new().read_only())
+            .copy_host_slice(&keys_flat)
+            .build()?;
+        self.round_keys = buffer(
+            &self.pro_que,
+            keys.len() * ROUND_KEYS_SIZE,
+            MemFlags::new().read_write(),
+        )?;
+
+        let expand = self
+            .pro_que
+            .kernel_builder("expand_keys")
+            .arg(&keys_buffer)
+            .arg(&self.round_keys)
+            .global_work_size(keys.len())
+            .build()?;
+        unsafe {
+            expand.enq()?;
+        }
+
+        self.kernel.set_arg(2, &self.round_keys)?;
+        self.kernel.set_arg(3, keys.len() as u32)?;
+        self.kernel.set_arg(4, difficulty_msb)?;
+        Ok(())
+    }
+
+    fn scan_labels(&mut self, labels: &[u8]) -> Result<Vec<Candidate>, ScryptError> {
+        let num_labels = labels.len() / LABEL_SIZE;
+        if num_labels == 0 {
+            return Ok(Vec::new());
+        }
+        let labels = &labels[..num_labels * LABEL_SIZE];
+        if self.labels.len() < labels.len() {
+            self.labels = buffer(&self.pro_que, labels.len(), MemFlags::new().read_only())?;
+            self.kernel.set_arg(0, &self.labels)?;
+        }
+        self.labels.write(labels).enq()?;
+        self.kernel.set_arg(1, num_labels as u32)?;
+
+        loop {
+            self.hits_count.write(&[0u32][..]).enq()?;
+            unsafe {
+                self.kernel.cmd().global_work_size(num_labels).enq()?;
+            }
+            let mut count = [0u32];
+            self.hits_count.read(&mut count[..]).enq()?;
+            let count = count[0] as usize;
+
+            if count > self.max_hits {
+                log::debug!("growing hits buffer to {count} candidates and scanning again");
+                self.hits = buffer(&self.pro_que, count * 3, MemFlags::new().write_only())?;
+                self.max_hits = count;
+                self.kernel.set_arg(6, &self.hits)?;
+                self.kernel.set_arg(7, count as u32)?;
+                continue;
+            }
+
+            let mut hits = vec![0u32; count * 3];
+            if count > 0 {
+                self.hits.read(&mut hits[..]).enq()?;
+            }
+            return Ok(hits
+                .chunks_exact(3)
+                .map(|hit| Candidate {
+                    label: hit[0],
+                    cipher: hit[1],
+                    offset: hit[2],
+                })
+                .collect());
+        }
+    }
+}
+
+impl LabelScanner for OpenClLabelScanner {
+    fn set_ciphers(
+        &mut self,
+        keys: &[[u8; 16]],
+        difficulty_msb: u8,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        self.expand_keys(keys, difficulty_msb).map_err(Into::into)
+    }
+
+    fn scan(&mut self, labels: &[u8]) -> Result<Vec<Candidate>, Box<dyn std::error::Error>> {
+        self.scan_labels(labels).map_err(Into::into)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use post::{
+        config::ProofConfig,
+        metadata::PostMetadata,
+        prove::{Prover, Prover8_56, ProvingParams},
+    };
+    use rand::{thread_rng, RngCore};
+    use rstest::rstest;
+
+    use super::*;
+
+    #[rstest]
+    #[case::few_candidates(200)]
+    #[case::overflowing_hits_buffer(4000)]
+    fn scanning_gives_same_results_as_cpu(#[case] k1: u32) {
+        let num_labels = 4096 + 5;
+        let mut data = vec![0u8; num_labels * LABEL_SIZE];
+        thread_rng().fill_bytes(&mut data);
+        let metadata = PostMetadata {
+            num_units: 1,
+            labels_per_unit: num_labels as u64,
+            ..Default::default()
+        };
+        let cfg = ProofConfig {
+            k1,
+            k2: 1,
+            k3: 1,
+            pow_difficulty: [0xFF; 32],
+        };
+        let params = ProvingParams::new(&metadata, &cfg).unwrap();
+        let prover = Prover8_56::with_pows(&[7; 32], 0..32, params, &[1, 2]).unwrap();
+
+        let mut expected = Vec::new();
+        prover.prove(&data, 0, |nonce, index| {
+            expected.push((nonce, index));
+            None
+        });
+
+        let mut scanner = OpenClLabelScanner::new(None, None).unwrap();
+        scanner
+            .set_ciphers(&prover.nonce_group_keys(), prover.difficulty_msb())
+            .unwrap();
+        let candidates = scanner.scan(&data).unwrap();
+        let mut found = Vec::new();
+        prover.prove_candidates(&data, 0, candidates, |nonce, index| {
+            found.push((nonce, index));
+            None
+        });
+
+        assert!(!expected.is_empty());
+        assert_eq!(expected, found);
+    }
+}
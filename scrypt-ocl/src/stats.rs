@@ -0,0 +1,102 @@
+use std::{
+    ops::{Add, AddAssign},
+    time::Duration,
+};
+
+/// Throughput statistics of initialization.
+///
+/// Tracked per batch (a single kernel run) and cumulatively. Comparing the
+/// time spent in each phase shows whether initialization is bound by
+/// compute (kernel), PCIe (transfer), host post-processing or the disk (write).
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct ScryptStats {
+    /// Number of labels initialized.
+    pub labels: u64,
+    /// Time spent executing the scrypt kernel on the device.
+    pub kernel_time: Duration,
+    /// Time spent transferring labels from the device to the host.
+    pub transfer_time: Duration,
+    /// Time spent on the host looking for VRF nonce and compacting labels.
+    pub post_processing_time: Duration,
+    /// Time spent writing labels to the output.
+    pub write_time: Duration,
+}
+
+impl ScryptStats {
+    pub fn total_time(&self) -> Duration {
+        self.kernel_time + self.transfer_time + self.post_processing_time + self.write_time
+    }
+
+    pub fn labels_per_sec(&self) -> f64 {
+        let secs = self.total_time().as_secs_f64();
+        if secs == 0.0 {
+            return 0.0;
+        }
+        self.labels as f64 / secs
+    }
+}
+
+impl Add for ScryptStats {
+    type Output = Self;
+
+    fn add(self, rhs: Self) -> Self::Output {
+        Self {
+            labels: self.labels + rhs.labels,
+            kernel_time: self.kernel_time + rhs.kernel_time,
+            transfer_time: self.transfer_time + rhs.transfer_time,
+            post_processing_time: self.post_processing_time + rhs.post_processing_time,
+            write_time: self.write_time + rhs.write_time,
+        }
+    }
+}
+
+impl AddAssign for ScryptStats {
+    fn add_assign(&mut self, rhs: Self) {
+        *self = *self + rhs;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::ScryptStats;
+
+    #[test]
+    fn labels_per_sec() {
+        let stats = ScryptStats {
+            labels: 1000,
+            kernel_time: Duration::from_millis(500),
+            transfer_time: Duration::from_millis(250),
+            post_processing_time: Duration::from_millis(150),
+            write_time: Duration::from_millis(100),
+        };
+        assert_eq!(Duration::from_secs(1), stats.total_time());
+        assert_eq!(1000.0, stats.labels_per_sec());
+        assert_eq!(0.0, ScryptStats::default().labels_per_sec());
+    }
+
+    #[test]
+    fn accumulating_stats() {
+        let batch = ScryptStats {
+            labels: 10,
+            kernel_time: Duration::from_millis(5),
+            transfer_time: Duration::from_millis(4),
+            post_processing_time: Duration::from_millis(3),
+            write_time: Duration::from_millis(2),
+        };
+        let mut total = ScryptStats::default();
+        total += batch;
+        total += batch;
+        assert_eq!(
+            ScryptStats {
+                labels: 20,
+                kernel_time: Duration::from_millis(10),
+                transfer_time: Duration::from_millis(8),
+                post_processing_time: Duration::from_millis(6),
+                write_time: Duration::from_millis(4),
+            },
+            total
+        );
+    }
+}
@@ -1,4 +1,10 @@
 fn main() -> Result<(), Box<dyn std::error::Error>> {
     tonic_build::configure().compile(&["api/spacemesh/v1/post.proto"], &["api"])?;
+    tonic_build::configure().compile(&["proto/post/v1/initialization.proto"], &["proto"])?;
+    tonic_build::configure().compile(&["proto/k2pow/v1/k2pow.proto"], &["proto"])?;
+    tonic_build::configure().compile(&["proto/verification/v1/verification.proto"], &["proto"])?;
+    tonic_build::configure()
+        .extern_path(".spacemesh.v1", "crate::client::spacemesh_v1")
+        .compile(&["proto/post_service/v1/post_service.proto"], &["proto", "api"])?;
     Ok(())
 }
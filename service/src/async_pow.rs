@@ -0,0 +1,156 @@
+//! Proofs of work from async code
+//!
+//! Finding a proof of work takes minutes and even verifying one takes milliseconds
+//! of CPU time, blocking the thread. [AsyncPow] runs them on the blocking thread pool
+//! of tokio, so that they can be awaited without stalling the other tasks
+//! of the runtime. Dropping the future cancels finding the proof of work.
+
+use std::sync::Arc;
+
+use post::{cancel::CancellationToken, pow, prove::ProofVersion};
+
+/// Cancels the token once dropped, i.e. when the future waiting for the proof of work
+/// is dropped.
+struct CancelOnDrop(CancellationToken);
+
+impl Drop for CancelOnDrop {
+    fn drop(&mut self) {
+        self.0.cancel("proof of work is not awaited anymore");
+    }
+}
+
+/// Async wrapper of a [pow::Prover] and/or [pow::PowVerifier].
+pub struct AsyncPow<P: ?Sized> {
+    pow: Arc<P>,
+}
+
+impl<P: ?Sized> Clone for AsyncPow<P> {
+    fn clone(&self) -> Self {
+        Self {
+            pow: self.pow.clone(),
+        }
+    }
+}
+
+impl<P: ?Sized> AsyncPow<P> {
+    pub fn new(pow: Arc<P>) -> Self {
+        Self { pow }
+    }
+}
+
+impl<P: pow::Prover + Send + Sync + ?Sized + 'static> AsyncPow<P> {
+    /// Find a proof of work, until found or `cancel` is cancelled
+    /// (or the returned future is dropped).
+    ///
+    /// The `nonce_group` is encoded as in the proofs of its version
+    /// (see [ProofVersion::of_nonce_group]).
+    pub async fn prove(
+        &self,
+        nonce_group: u32,
+        challenge: [u8; 8],
+        difficulty: [u8; 32],
+        miner_id: [u8; 32],
+        cancel: &CancellationToken,
+    ) -> Result<u64, pow::Error> {
+        let cancel = cancel.child();
+        let _guard = CancelOnDrop(cancel.clone());
+        let pow = self.pow.clone();
+        tokio::task::spawn_blocking(move || {
+            let version = ProofVersion::of_nonce_group(nonce_group);
+            pow::prove(
+                &*pow,
+                version,
+                nonce_group,
+                &challenge,
+                &difficulty,
+                &miner_id,
+                &cancel,
+            )
+        })
+        .await
+        .map_err(|e| pow::Error::Internal(Box::new(e)))?
+    }
+}
+
+impl<P: pow::PowVerifier + Send + Sync + ?Sized + 'static> AsyncPow<P> {
+    /// Verify the proof of work.
+    pub async fn verify(
+        &self,
+        pow: u64,
+        nonce_group: u32,
+        challenge: [u8; 8],
+        difficulty: [u8; 32],
+        miner_id: [u8; 32],
+    ) -> Result<(), pow::Error> {
+        let verifier = self.pow.clone();
+        tokio::task::spawn_blocking(move || {
+            let version = ProofVersion::of_nonce_group(nonce_group);
+            pow::verify(
+                &*verifier,
+                version,
+                pow,
+                nonce_group,
+                &challenge,
+                &difficulty,
+                &miner_id,
+            )
+        })
+        .await
+        .map_err(|e| pow::Error::Internal(Box::new(e)))?
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use post::pow::randomx::{PoW, RandomXFlag};
+
+    use super::*;
+
+    #[tokio::test]
+    async fn proving_and_verifying() {
+        let pow = AsyncPow::new(Arc::new(
+            PoW::new(RandomXFlag::get_recommended_flags()).unwrap(),
+        ));
+        let mut difficulty = [0xFF; 32];
+        difficulty[0] = 0x0F;
+        let cancel = CancellationToken::new();
+        let proof = pow
+            .prove(7, [1; 8], difficulty, [2; 32], &cancel)
+            .await
+            .unwrap();
+        pow.verify(proof, 7, [1; 8], difficulty, [2; 32])
+            .await
+            .unwrap();
+        assert!(matches!(
+            pow.verify(proof, 7, [1; 8], [0; 32], [2; 32]).await,
+            Err(pow::Error::InvalidPoW)
+        ));
+    }
+
+    #[tokio::test]
+    async fn dropping_future_cancels_pow() {
+        let mut prover = pow::MockProver::new();
+        let (tx, rx) = std::sync::mpsc::channel();
+        prover.expect_prove().returning(move |_, _, _, _, cancel| {
+            while !cancel.is_cancelled() {
+                std::thread::sleep(Duration::from_millis(10));
+            }
+            tx.send(()).unwrap();
+            Err(pow::Error::Cancelled)
+        });
+        let pow = AsyncPow::new(Arc::new(prover));
+        let cancel = CancellationToken::new();
+        let result = tokio::time::timeout(
+            Duration::from_millis(100),
+            pow.prove(0, [0; 8], [0; 32], [0; 32], &cancel),
+        )
+        .await;
+        assert!(result.is_err());
+        tokio::task::spawn_blocking(move || rx.recv_timeout(Duration::from_secs(5)))
+            .await
+            .unwrap()
+            .expect("proof of work should be cancelled");
+    }
+}
@@ -0,0 +1,78 @@
+//! Remote initialization server
+//!
+//! Serves POS data labels to [post_service::initialization::RemoteInitializer]s.
+
+use std::net::SocketAddr;
+
+use clap::{Parser, ValueEnum};
+use eyre::Context;
+use post::{
+    config::ScryptParams,
+    initialize::{CpuInitializer, Initialize},
+};
+use post_service::initialization::{InitializationServer, DEFAULT_CHUNK_SIZE};
+use scrypt_ocl::{ocl::DeviceType, OpenClInitializer, ProviderId};
+use tonic::transport::Server;
+
+/// Remote initialization server
+#[derive(Parser, Debug)]
+#[command(version, about)]
+struct Cli {
+    /// address to listen on
+    #[arg(short, long, default_value = "[::]:50051")]
+    address: SocketAddr,
+    /// scrypt N parameter
+    #[arg(short, default_value_t = 8192)]
+    n: usize,
+    /// initialization method
+    #[arg(long, value_enum, default_value_t = Method::Gpu)]
+    method: Method,
+    /// provider ID to use for GPU initialization
+    /// (see `initializer list-providers`).
+    /// The default is to pick the first one.
+    #[arg(long)]
+    provider: Option<u32>,
+    /// number of threads to use for CPU initialization.
+    /// '0' means use all available threads
+    #[arg(long, default_value_t = 0)]
+    threads: usize,
+    /// size (in bytes) of the streamed chunks of labels
+    #[arg(long, default_value_t = DEFAULT_CHUNK_SIZE)]
+    chunk_size: usize,
+}
+
+#[derive(Debug, Clone, Copy, ValueEnum)]
+enum Method {
+    Cpu,
+    Gpu,
+}
+
+#[tokio::main]
+async fn main() -> eyre::Result<()> {
+    let args = Cli::parse();
+
+    let env = env_logger::Env::default().filter_or("RUST_LOG", "info");
+    env_logger::init_from_env(env);
+
+    eyre::ensure!(args.n.is_power_of_two(), "scrypt N must be a power of two");
+    let scrypt = ScryptParams::new(args.n, 1, 1);
+    let initializer: Box<dyn Initialize + Send> = match args.method {
+        Method::Cpu => Box::new(CpuInitializer::with_threads(scrypt, args.threads)?),
+        Method::Gpu => Box::new(
+            OpenClInitializer::new(
+                args.provider.map(ProviderId),
+                args.n,
+                Some(DeviceType::GPU | DeviceType::CPU),
+            )
+            .wrap_err("creating GPU initializer")?,
+        ),
+    };
+
+    let server = InitializationServer::new(initializer, scrypt).with_chunk_size(args.chunk_size);
+    log::info!("serving initialization on {}", args.address);
+    Server::builder()
+        .add_service(server.into_service())
+        .serve(args.address)
+        .await?;
+    Ok(())
+}
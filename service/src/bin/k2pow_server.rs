@@ -0,0 +1,88 @@
+//! Remote k2pow server
+//!
+//! Calculates k2 proofs of work for [post_service::k2pow::RemotePowProver]s.
+
+use std::{fs::read_to_string, net::SocketAddr, path::PathBuf};
+
+use clap::{Args, Parser};
+use eyre::Context;
+use post::pow::randomx::{PoW, RandomXFlag};
+use post_service::k2pow::K2PowServer;
+use tonic::transport::{Certificate, Identity, Server, ServerTlsConfig};
+
+/// Remote k2pow server
+#[derive(Parser, Debug)]
+#[command(version, about)]
+struct Cli {
+    /// address to listen on
+    #[arg(short, long, default_value = "[::]:50052")]
+    address: SocketAddr,
+    /// use the RandomX light mode (256 MiB of memory instead of 2080 MiB, but much slower)
+    #[arg(long)]
+    light: bool,
+    /// use large pages (if huge pages are reserved in the OS), falling back to regular pages
+    #[arg(long)]
+    large_pages: bool,
+    #[command(flatten, next_help_heading = "TLS configuration")]
+    tls: Option<Tls>,
+}
+
+/// TLS configuration
+///
+/// The certificate and the key must be specified together.
+#[derive(Args, Debug, Clone)]
+#[group(required = false)]
+struct Tls {
+    /// certificate of the server
+    #[arg(long, required = false)]
+    cert: PathBuf,
+    #[arg(long, required = false)]
+    key: PathBuf,
+    /// CA certificate to verify the certificates of the clients against.
+    /// If not set, the clients are not authenticated
+    #[arg(long)]
+    client_ca_cert: Option<PathBuf>,
+}
+
+#[tokio::main]
+async fn main() -> eyre::Result<()> {
+    let args = Cli::parse();
+
+    let env = env_logger::Env::default().filter_or("RUST_LOG", "info");
+    env_logger::init_from_env(env);
+
+    let mut flags = match args.light {
+        true => RandomXFlag::get_recommended_flags(),
+        false => RandomXFlag::get_recommended_flags() | RandomXFlag::FLAG_FULL_MEM,
+    };
+    if args.large_pages {
+        flags |= RandomXFlag::FLAG_LARGE_PAGES;
+    }
+    log::info!("initializing RandomX with flags: {flags:?}");
+    let pow = PoW::new(flags).wrap_err("initializing RandomX")?;
+
+    let mut server = Server::builder();
+    if let Some(tls) = args.tls {
+        let cert = read_to_string(&tls.cert)
+            .wrap_err_with(|| format!("reading {}", tls.cert.display()))?;
+        let key =
+            read_to_string(&tls.key).wrap_err_with(|| format!("reading {}", tls.key.display()))?;
+        let mut config = ServerTlsConfig::new().identity(Identity::from_pem(cert, key));
+        if let Some(path) = tls.client_ca_cert {
+            let ca_cert =
+                read_to_string(&path).wrap_err_with(|| format!("reading {}", path.display()))?;
+            config = config.client_ca_root(Certificate::from_pem(ca_cert));
+        }
+        server = server.tls_config(config)?;
+    } else {
+        log::info!("not configuring TLS");
+    }
+
+    let k2pow = K2PowServer::new(Box::new(pow));
+    log::info!("serving k2pow on {}", args.address);
+    server
+        .add_service(k2pow.into_service())
+        .serve(args.address)
+        .await?;
+    Ok(())
+}
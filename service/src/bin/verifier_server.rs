@@ -0,0 +1,162 @@
+//! Remote verification server
+//!
+//! Verifies proofs for nodes, see [post_service::verification::VerificationServer].
+
+use std::{fs::read_to_string, net::SocketAddr, path::PathBuf};
+
+use clap::{Args, Parser};
+use eyre::Context;
+use post::{
+    config::{InitConfig, ProofConfig, ScryptParams},
+    pow::randomx::{PoW, RandomXFlag},
+    verification::{Verifier, VerifierPool},
+};
+use post_service::verification::VerificationServer;
+use tonic::transport::{Certificate, Identity, Server, ServerTlsConfig};
+
+/// Remote verification server
+#[derive(Parser, Debug)]
+#[command(version, about)]
+struct Cli {
+    /// address to listen on
+    #[arg(short, long, default_value = "[::]:50053")]
+    address: SocketAddr,
+    /// use the RandomX light mode (256 MiB of memory instead of 2080 MiB, but much slower)
+    #[arg(long)]
+    light: bool,
+    /// number of proofs verified at the same time.
+    /// '0' means the number of available threads
+    #[arg(long, default_value_t = 0)]
+    workers: usize,
+    /// number of proofs waiting for a worker, the requests above are rejected
+    #[arg(long, default_value_t = 64)]
+    queue_size: usize,
+    #[command(flatten, next_help_heading = "POST configuration")]
+    post_config: PostConfig,
+    #[command(flatten, next_help_heading = "TLS configuration")]
+    tls: Option<Tls>,
+}
+
+/// POST configuration of the network
+#[derive(Args, Debug)]
+struct PostConfig {
+    /// The minimal number of units that must be initialized.
+    #[arg(long, default_value_t = 4)]
+    min_num_units: u32,
+    /// The maximal number of units that can be initialized.
+    #[arg(long, default_value_t = u32::MAX)]
+    max_num_units: u32,
+    ///  The number of labels per unit.
+    #[arg(long, default_value_t = 4294967296)]
+    labels_per_unit: u64,
+    /// K1 specifies the difficulty for a label to be a candidate for a proof
+    #[arg(long, default_value_t = 26)]
+    k1: u32,
+    /// K2 is the number of labels below the required difficulty required for a proof
+    #[arg(long, default_value_t = 37)]
+    k2: u32,
+    /// K3 is the size of the subset of proof indices that is validated
+    #[arg(long, default_value_t = 37)]
+    k3: u32,
+    /// difficulty for the nonce proof of work (aka "k2pow")
+    #[arg(
+        long,
+        default_value = "000dfb23b0979b4b000000000000000000000000000000000000000000000000",
+        value_parser(parse_difficulty)
+    )]
+    pow_difficulty: [u8; 32],
+    /// scrypt N parameter
+    #[arg(short, default_value_t = 8192)]
+    n: usize,
+    /// scrypt R parameter
+    #[arg(short, default_value_t = 1)]
+    r: usize,
+    /// scrypt P parameter
+    #[arg(short, default_value_t = 1)]
+    p: usize,
+}
+
+/// TLS configuration
+///
+/// The certificate and the key must be specified together.
+#[derive(Args, Debug, Clone)]
+#[group(required = false)]
+struct Tls {
+    /// certificate of the server
+    #[arg(long, required = false)]
+    cert: PathBuf,
+    #[arg(long, required = false)]
+    key: PathBuf,
+    /// CA certificate to verify the certificates of the clients against.
+    /// If not set, the clients are not authenticated
+    #[arg(long)]
+    client_ca_cert: Option<PathBuf>,
+}
+
+fn parse_difficulty(arg: &str) -> eyre::Result<[u8; 32]> {
+    hex::decode(arg)?
+        .as_slice()
+        .try_into()
+        .wrap_err("invalid difficulty length")
+}
+
+#[tokio::main]
+async fn main() -> eyre::Result<()> {
+    let args = Cli::parse();
+
+    let env = env_logger::Env::default().filter_or("RUST_LOG", "info");
+    env_logger::init_from_env(env);
+
+    let config = args.post_config;
+    let cfg = ProofConfig {
+        k1: config.k1,
+        k2: config.k2,
+        k3: config.k3,
+        pow_difficulty: config.pow_difficulty,
+    };
+    let init_cfg = InitConfig {
+        min_num_units: config.min_num_units,
+        max_num_units: config.max_num_units,
+        labels_per_unit: config.labels_per_unit,
+        scrypt: ScryptParams::new(config.n, config.r, config.p),
+    };
+
+    let flags = match args.light {
+        true => RandomXFlag::get_recommended_flags(),
+        false => RandomXFlag::get_recommended_flags() | RandomXFlag::FLAG_FULL_MEM,
+    };
+    log::info!("initializing RandomX with flags: {flags:?}");
+    let verifier = Verifier::new(Box::new(PoW::new(flags).wrap_err("initializing RandomX")?));
+
+    let workers = match args.workers {
+        0 => std::thread::available_parallelism()?.get(),
+        n => n,
+    };
+    log::info!("verifying up to {workers} proofs at the same time");
+    let pool = VerifierPool::new(verifier, workers, args.queue_size)?;
+
+    let mut server = Server::builder();
+    if let Some(tls) = args.tls {
+        let cert = read_to_string(&tls.cert)
+            .wrap_err_with(|| format!("reading {}", tls.cert.display()))?;
+        let key =
+            read_to_string(&tls.key).wrap_err_with(|| format!("reading {}", tls.key.display()))?;
+        let mut config = ServerTlsConfig::new().identity(Identity::from_pem(cert, key));
+        if let Some(path) = tls.client_ca_cert {
+            let ca_cert =
+                read_to_string(&path).wrap_err_with(|| format!("reading {}", path.display()))?;
+            config = config.client_ca_root(Certificate::from_pem(ca_cert));
+        }
+        server = server.tls_config(config)?;
+    } else {
+        log::info!("not configuring TLS");
+    }
+
+    let verification = VerificationServer::new(pool, cfg, init_cfg);
+    log::info!("serving verification on {}", args.address);
+    server
+        .add_service(verification.into_service())
+        .serve(args.address)
+        .await?;
+    Ok(())
+}
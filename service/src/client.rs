@@ -3,14 +3,21 @@
 //! This module implements a GRPC client for the Post Service.
 //! It connects to the node and registers itself as a Post Service.
 //! It then waits for requests from the node and forwards them to the Post Service.
+//!
+//! The node is connected over TCP (`http://` or `https://` addresses) or,
+//! on the same host, over a Unix domain socket (`unix:/path/to/socket`).
+//! Other nodes can be given to fail over to (see [ServiceClient::with_failover])
+//! when the connected one keeps failing.
 
-use std::time::Duration;
+use std::{path::PathBuf, sync::Arc, time::Duration};
 
 use post::metadata::PostMetadata;
+use post::prove::DeadlineExceeded;
 pub(crate) use spacemesh_v1::post_service_client::PostServiceClient;
 use spacemesh_v1::{node_request, service_response};
 use spacemesh_v1::{
-    GenProofRequest, GenProofResponse, GenProofStatus, Proof, ProofMetadata, ServiceResponse,
+    GenProofRequest, GenProofResponse, GenProofStatus, NodeRequest, Proof, ProofMetadata,
+    ServiceResponse,
 };
 use tokio::sync::mpsc;
 use tokio::time::sleep;
@@ -22,15 +29,81 @@ use tonic::transport::Identity;
 use tonic::Request;
 
 use crate::client::spacemesh_v1::MetadataResponse;
+use crate::operator::Readiness;
 use crate::service::ProofGenState;
 
+/// Prefix of the addresses of Unix domain sockets.
+const UNIX_SCHEME: &str = "unix:";
+
 pub mod spacemesh_v1 {
     tonic::include_proto!("spacemesh.v1");
 }
 
+/// How many times in a row connecting to a node can fail before failing over
+/// to the next one, by default.
+const FAILOVER_ATTEMPTS: usize = 3;
+
 pub struct ServiceClient<S: PostService> {
+    /// The nodes to connect to, the first one preferred.
+    nodes: Vec<Node>,
+    /// Index of the node connected (or being connected) to.
+    current: usize,
+    failover_attempts: usize,
+    service: Arc<S>,
+    readiness: Option<Arc<Readiness>>,
+    max_reconnect_interval: Option<Duration>,
+}
+
+/// A node to connect to and its health.
+struct Node {
+    address: String,
     endpoint: Endpoint,
-    service: S,
+    /// Unix domain socket to connect to instead of the address of the endpoint.
+    socket: Option<PathBuf>,
+    /// Failed attempts to connect to it since the last successful one.
+    failures: usize,
+}
+
+impl Node {
+    fn new(
+        address: String,
+        tls: Option<(Option<String>, Certificate, Identity)>,
+    ) -> eyre::Result<Self> {
+        let (endpoint, socket) = match address.strip_prefix(UNIX_SCHEME) {
+            Some(path) => {
+                eyre::ensure!(
+                    cfg!(unix),
+                    "Unix domain sockets are not supported on this platform"
+                );
+                eyre::ensure!(tls.is_none(), "TLS is not used with Unix domain sockets");
+                // The address is not used, only the socket.
+                let endpoint = Endpoint::from_static("http://localhost");
+                (endpoint, Some(PathBuf::from(path)))
+            }
+            None => (endpoint(address.clone(), tls)?, None),
+        };
+        Ok(Self {
+            address,
+            endpoint,
+            socket,
+            failures: 0,
+        })
+    }
+
+    async fn connect(&self) -> Result<PostServiceClient<Channel>, tonic::transport::Error> {
+        let channel = match &self.socket {
+            #[cfg(unix)]
+            Some(path) => {
+                let path = path.clone();
+                let connector = tower::service_fn(move |_: tonic::transport::Uri| {
+                    tokio::net::UnixStream::connect(path.clone())
+                });
+                self.endpoint.connect_with_connector(connector).await?
+            }
+            _ => self.endpoint.connect().await?,
+        };
+        Ok(PostServiceClient::new(channel))
+    }
 }
 
 #[mockall::automock]
@@ -65,36 +138,103 @@ impl<T: PostService + ?Sized> PostService for std::sync::Arc<T> {
     }
 }
 
-impl<S: PostService> ServiceClient<S> {
+/// Endpoint of the server at `address`, with the optional TLS configuration:
+/// the domain name to verify the certificate of the server against
+/// (the host of the address by default), the CA certificate and the client identity.
+pub fn endpoint(
+    address: String,
+    tls: Option<(Option<String>, Certificate, Identity)>,
+) -> eyre::Result<Endpoint> {
+    let endpoint = Channel::builder(address.parse()?);
+    Ok(match tls {
+        Some((domain, cert, identity)) => {
+            let domain = match domain {
+                Some(domain) => domain,
+                None => endpoint
+                    .uri()
+                    .authority()
+                    .ok_or_else(|| eyre::eyre!("no domain name in the endpoint"))?
+                    .host()
+                    .to_string(),
+            };
+
+            endpoint.tls_config(
+                ClientTlsConfig::new()
+                    .domain_name(domain)
+                    .ca_certificate(cert)
+                    .identity(identity),
+            )?
+        }
+        None => endpoint,
+    })
+}
+
+impl<S: PostService + Send + Sync + 'static> ServiceClient<S> {
     pub fn new(
         address: String,
         tls: Option<(Option<String>, Certificate, Identity)>,
         service: S,
     ) -> eyre::Result<Self> {
-        let endpoint = Channel::builder(address.parse()?);
-        let endpoint = match tls {
-            Some((domain, cert, identity)) => {
-                let domain = match domain {
-                    Some(domain) => domain,
-                    None => endpoint
-                        .uri()
-                        .authority()
-                        .ok_or_else(|| eyre::eyre!("no domain name in the endpoint"))?
-                        .host()
-                        .to_string(),
-                };
+        Ok(Self {
+            nodes: vec![Node::new(address, tls)?],
+            current: 0,
+            failover_attempts: FAILOVER_ATTEMPTS,
+            service: Arc::new(service),
+            readiness: None,
+            max_reconnect_interval: None,
+        })
+    }
 
-                endpoint.tls_config(
-                    ClientTlsConfig::new()
-                        .domain_name(domain)
-                        .ca_certificate(cert)
-                        .identity(identity),
-                )?
-            }
-            None => endpoint,
-        };
+    /// Report whether the node is connected to the `readiness`.
+    pub fn with_readiness(mut self, readiness: Arc<Readiness>) -> Self {
+        self.readiness = Some(readiness);
+        self
+    }
+
+    /// Fail over to the nodes at the other `addresses` (with the same `tls` configuration)
+    /// after `attempts` to connect to the current one failed in a row, so that an outage
+    /// of a node doesn't leave the service idle. The node with the fewest recent failures
+    /// is picked next, the next one in the order given on a tie.
+    pub fn with_failover(
+        mut self,
+        addresses: impl IntoIterator<Item = String>,
+        tls: Option<(Option<String>, Certificate, Identity)>,
+        attempts: usize,
+    ) -> eyre::Result<Self> {
+        for address in addresses {
+            self.nodes.push(Node::new(address, tls.clone())?);
+        }
+        self.failover_attempts = attempts.max(1);
+        Ok(self)
+    }
 
-        Ok(Self { endpoint, service })
+    /// Index of the node to fail over to from the current one.
+    fn next_node(&self) -> usize {
+        (1..=self.nodes.len())
+            .map(|offset| (self.current + offset) % self.nodes.len())
+            .min_by_key(|&index| self.nodes[index].failures)
+            .unwrap_or(self.current)
+    }
+
+    /// Double the interval between the attempts to reconnect to the node after every
+    /// failed one, up to `max_interval`, and randomize it ("jitter"), so that many services
+    /// don't reconnect to a recovering node all at once.
+    pub fn with_reconnect_backoff(mut self, max_interval: Duration) -> Self {
+        self.max_reconnect_interval = Some(max_interval);
+        self
+    }
+
+    /// Interval before the reconnect `attempt` (counted from 1).
+    fn reconnect_interval(&self, interval: Duration, attempt: usize) -> Duration {
+        let Some(max_interval) = self.max_reconnect_interval else {
+            return interval;
+        };
+        let exponent = (attempt - 1).min(31) as u32;
+        let interval = interval
+            .saturating_mul(2u32.pow(exponent))
+            .min(max_interval.max(interval));
+        // Half of it is random.
+        interval / 2 + interval.mul_f64(rand::random::<f64>() / 2.0)
     }
 
     pub async fn run(
@@ -105,26 +245,48 @@ impl<S: PostService> ServiceClient<S> {
         loop {
             let mut attempt = 1;
             let client = loop {
+                let node = &mut self.nodes[self.current];
                 log::debug!(
-                    "connecting to the node on {} (attempt {})",
-                    self.endpoint.uri(),
-                    attempt
+                    "connecting to the node on {} (attempt {attempt})",
+                    node.address
                 );
-                match PostServiceClient::connect(self.endpoint.clone()).await {
-                    Ok(client) => break client,
+                match node.connect().await {
+                    Ok(client) => {
+                        node.failures = 0;
+                        break client;
+                    }
                     Err(e) => {
-                        log::info!("could not connect to the node: {e}");
+                        log::info!("could not connect to the node on {}: {e}", node.address);
+                        node.failures += 1;
                         if let Some(max) = max_retries {
                             eyre::ensure!(attempt <= max, "max retries ({max}) reached");
                         }
-                        sleep(reconnect_interval).await;
+                        if node.failures % self.failover_attempts == 0 {
+                            let next = self.next_node();
+                            if next != self.current {
+                                log::warn!(
+                                    "failing over from the node on {} ({} failed attempts) to {}",
+                                    self.nodes[self.current].address,
+                                    self.nodes[self.current].failures,
+                                    self.nodes[next].address
+                                );
+                                self.current = next;
+                            }
+                        }
+                        sleep(self.reconnect_interval(reconnect_interval, attempt)).await;
                     }
                 }
                 attempt += 1;
             };
             let res = self.register_and_serve(client).await;
-            log::info!("disconnected: {res:?}");
-            sleep(reconnect_interval).await;
+            if let Some(readiness) = &self.readiness {
+                readiness.set_node_connected(false);
+            }
+            log::info!(
+                "disconnected from the node on {}: {res:?}",
+                self.nodes[self.current].address
+            );
+            sleep(self.reconnect_interval(reconnect_interval, 1)).await;
         }
     }
 
@@ -141,61 +303,53 @@ impl<S: PostService> ServiceClient<S> {
 
         let response = client.register(Request::new(outbound)).await?;
         let mut inbound = response.into_inner();
+        if let Some(readiness) = &self.readiness {
+            readiness.set_node_connected(true);
+        }
 
         while let Some(request) = inbound.message().await? {
-            log::debug!("Got request from node: {request:?}");
-            match request.kind {
-                Some(node_request::Kind::Metadata(_)) => {
-                    let resp = self.get_metadata();
-                    tx.send(resp).await?;
-                }
-                Some(node_request::Kind::GenProof(req)) => {
-                    let resp = self.generate_and_verify_proof(req);
-                    tx.send(resp).await?;
-                }
-                None => {
-                    log::warn!("Got a request with no kind");
-                    tx.send(ServiceResponse {
-                        kind: Some(service_response::Kind::GenProof(GenProofResponse {
-                            status: GenProofStatus::Error as i32,
-                            ..Default::default()
-                        })),
-                    })
-                    .await?
-                }
-            }
+            // Generating and verifying proofs blocks, off the async runtime.
+            let service = self.service.clone();
+            let response =
+                tokio::task::spawn_blocking(move || handle_request(&*service, request)).await?;
+            tx.send(response).await?;
         }
 
         Ok(())
     }
+}
 
-    fn generate_and_verify_proof(&self, request: GenProofRequest) -> ServiceResponse {
-        let result = self.service.gen_proof(request.challenge.clone());
-
-        match result {
-            Ok(ProofGenState::Finished { proof }) => {
-                log::info!("proof generation finished");
-                let post_metadata = match self.service.get_metadata() {
-                    Ok(m) => m,
-                    Err(err) => {
-                        log::error!("failed to get metadata: {err:?}");
-                        return ServiceResponse {
-                            kind: Some(service_response::Kind::GenProof(GenProofResponse {
-                                status: GenProofStatus::Error as i32,
-                                ..Default::default()
-                            })),
-                        };
-                    }
-                };
+/// Handle the `request` of the node with the `service`.
+pub(crate) fn handle_request<S: PostService>(service: &S, request: NodeRequest) -> ServiceResponse {
+    log::debug!("Got request from node: {request:?}");
+    match request.kind {
+        Some(node_request::Kind::Metadata(_)) => get_metadata(service),
+        Some(node_request::Kind::GenProof(req)) => generate_and_verify_proof(service, req),
+        None => {
+            log::warn!("Got a request with no kind");
+            ServiceResponse {
+                kind: Some(service_response::Kind::GenProof(GenProofResponse {
+                    status: GenProofStatus::Error as i32,
+                    ..Default::default()
+                })),
+            }
+        }
+    }
+}
 
-                if let Err(err) = self.service.verify_proof(
-                    &proof,
-                    &post::metadata::ProofMetadata::new(
-                        post_metadata,
-                        request.challenge.as_slice().try_into().unwrap(),
-                    ),
-                ) {
-                    log::error!("generated proof is not valid: {err:?}");
+fn generate_and_verify_proof<S: PostService>(
+    service: &S,
+    request: GenProofRequest,
+) -> ServiceResponse {
+    let result = service.gen_proof(request.challenge.clone());
+
+    match result {
+        Ok(ProofGenState::Finished { proof }) => {
+            log::info!("proof generation finished");
+            let post_metadata = match service.get_metadata() {
+                Ok(m) => m,
+                Err(err) => {
+                    log::error!("failed to get metadata: {err:?}");
                     return ServiceResponse {
                         kind: Some(service_response::Kind::GenProof(GenProofResponse {
                             status: GenProofStatus::Error as i32,
@@ -203,66 +357,86 @@ impl<S: PostService> ServiceClient<S> {
                         })),
                     };
                 }
+            };
 
-                ServiceResponse {
-                    kind: Some(service_response::Kind::GenProof(GenProofResponse {
-                        proof: Some(Proof {
-                            nonce: proof.nonce,
-                            indices: proof.indices.into_owned(),
-                            pow: proof.pow,
-                        }),
-                        metadata: Some(ProofMetadata {
-                            challenge: request.challenge,
-                            meta: Some(convert_metadata(post_metadata)),
-                        }),
-                        status: GenProofStatus::Ok as i32,
-                    })),
-                }
-            }
-            Ok(ProofGenState::InProgress) => {
-                log::info!("proof generation in progress");
-                ServiceResponse {
+            if let Err(err) = service.verify_proof(
+                &proof,
+                &post::metadata::ProofMetadata::new(
+                    post_metadata,
+                    request.challenge.as_slice().try_into().unwrap(),
+                ),
+            ) {
+                log::error!("generated proof is not valid: {err:?}");
+                return ServiceResponse {
                     kind: Some(service_response::Kind::GenProof(GenProofResponse {
-                        status: GenProofStatus::Ok as i32,
+                        status: GenProofStatus::Error as i32,
                         ..Default::default()
                     })),
-                }
+                };
             }
-            Err(e) => {
+
+            ServiceResponse {
+                kind: Some(service_response::Kind::GenProof(GenProofResponse {
+                    proof: Some(Proof {
+                        nonce: proof.nonce,
+                        indices: proof.indices.into_owned(),
+                        pow: proof.pow,
+                    }),
+                    metadata: Some(ProofMetadata {
+                        challenge: request.challenge,
+                        meta: Some(convert_metadata(post_metadata)),
+                    }),
+                    status: GenProofStatus::Ok as i32,
+                })),
+            }
+        }
+        Ok(ProofGenState::InProgress) => {
+            log::info!("proof generation in progress");
+            ServiceResponse {
+                kind: Some(service_response::Kind::GenProof(GenProofResponse {
+                    status: GenProofStatus::Ok as i32,
+                    ..Default::default()
+                })),
+            }
+        }
+        Err(e) => {
+            if e.downcast_ref::<DeadlineExceeded>().is_some() {
+                log::error!("proof generation gave up, it can't finish before the deadline");
+            } else {
                 log::error!("failed to generate proof: {e:?}");
-                ServiceResponse {
-                    kind: Some(service_response::Kind::GenProof(GenProofResponse {
-                        status: GenProofStatus::Error as i32,
-                        ..Default::default()
-                    })),
-                }
+            }
+            ServiceResponse {
+                kind: Some(service_response::Kind::GenProof(GenProofResponse {
+                    status: GenProofStatus::Error as i32,
+                    ..Default::default()
+                })),
             }
         }
     }
+}
 
-    fn get_metadata(&self) -> ServiceResponse {
-        match self.service.get_metadata() {
-            Ok(meta) => {
-                log::info!("obtained metadata: {meta:?}");
-                ServiceResponse {
-                    kind: Some(service_response::Kind::Metadata(MetadataResponse {
-                        meta: Some(convert_metadata(meta)),
-                    })),
-                }
+fn get_metadata<S: PostService>(service: &S) -> ServiceResponse {
+    match service.get_metadata() {
+        Ok(meta) => {
+            log::info!("obtained metadata: {meta:?}");
+            ServiceResponse {
+                kind: Some(service_response::Kind::Metadata(MetadataResponse {
+                    meta: Some(convert_metadata(meta)),
+                })),
             }
-            Err(e) => {
-                log::error!("failed to get metadata: {e:?}");
-                ServiceResponse {
-                    kind: Some(service_response::Kind::Metadata(MetadataResponse {
-                        meta: None,
-                    })),
-                }
+        }
+        Err(e) => {
+            log::error!("failed to get metadata: {e:?}");
+            ServiceResponse {
+                kind: Some(service_response::Kind::Metadata(MetadataResponse {
+                    meta: None,
+                })),
             }
         }
     }
 }
 
-fn convert_metadata(meta: PostMetadata) -> spacemesh_v1::Metadata {
+pub(crate) fn convert_metadata(meta: PostMetadata) -> spacemesh_v1::Metadata {
     spacemesh_v1::Metadata {
         node_id: meta.node_id.to_vec(),
         commitment_atx_id: meta.commitment_atx_id.to_vec(),
@@ -309,4 +483,56 @@ mod tests {
         let res = client.run(Some(2), Duration::from_millis(1)).await;
         assert_eq!(res.unwrap_err().to_string(), "max retries (2) reached");
     }
+
+    #[test]
+    fn backing_off_reconnects() {
+        let client = super::ServiceClient::new(
+            "http://localhost:1234".to_string(),
+            None,
+            super::MockPostService::new(),
+        )
+        .unwrap();
+        let interval = Duration::from_secs(2);
+        assert_eq!(interval, client.reconnect_interval(interval, 5));
+
+        let client = client.with_reconnect_backoff(Duration::from_secs(60));
+        for (attempt, expected) in [(1, 2), (2, 4), (3, 8), (6, 60), (100, 60)] {
+            let expected = Duration::from_secs(expected);
+            let interval = client.reconnect_interval(interval, attempt);
+            assert!(
+                interval >= expected / 2 && interval <= expected,
+                "attempt {attempt}: {interval:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn picking_node_to_fail_over_to() {
+        let mut client = super::ServiceClient::new(
+            "http://localhost:1234".to_string(),
+            None,
+            super::MockPostService::new(),
+        )
+        .unwrap();
+        // A single node is never failed over from.
+        assert_eq!(0, client.next_node());
+
+        client = client
+            .with_failover(
+                ["http://localhost:1235", "http://localhost:1236"].map(String::from),
+                None,
+                3,
+            )
+            .unwrap();
+        assert_eq!(1, client.next_node());
+
+        // The healthiest node is picked, the next one on a tie.
+        client.nodes[1].failures = 3;
+        assert_eq!(2, client.next_node());
+        client.current = 2;
+        client.nodes[2].failures = 3;
+        assert_eq!(0, client.next_node());
+        client.nodes[0].failures = 6;
+        assert_eq!(1, client.next_node());
+    }
 }
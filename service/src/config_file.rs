@@ -0,0 +1,249 @@
+//! Configuration file and environment variables of the service
+//!
+//! All the arguments of the CLI can be set in a TOML file (`--config post-service.toml`),
+//! keyed by their names in snake case (i.e. `pow_threads`), at the top level or in tables,
+//! which only group them (i.e. `[post_settings]`). Lists are given as arrays and flags
+//! as booleans.
+//!
+//! ```toml
+//! dir = "/data/post"
+//! address = "http://localhost:9094"
+//!
+//! [post_settings]
+//! threads = 4
+//! nonces = 128
+//! randomx_mode = "fast"
+//! cores = [0, 1, 2, 3]
+//! ```
+//!
+//! They can also be set in environment variables named after them with a prefix
+//! (see [with_env_vars]), i.e. `POST_SERVICE_POW_THREADS=4`. The arguments given on
+//! the command line override the environment variables, which override the file.
+
+use std::{ffi::OsString, path::PathBuf};
+
+use clap::{parser::ValueSource, Command};
+use eyre::Context;
+
+/// ID of the argument with the path of the configuration file.
+pub const CONFIG_ARG: &str = "config";
+
+/// Read the arguments of the `command` from the environment variables named
+/// `<prefix>_<ID in upper case>` (i.e. `POST_SERVICE_POW_THREADS`) when not on the command line.
+pub fn with_env_vars(command: Command, prefix: &str) -> Command {
+    command.mut_args(|arg| match arg.get_id().as_str() {
+        "help" | "version" => arg,
+        id => {
+            let name = format!("{prefix}_{}", id.to_uppercase());
+            // The command is built once, leaking the names is fine.
+            arg.env(&*Box::leak(name.into_boxed_str()))
+        }
+    })
+}
+
+/// The command line `args` (starting with the binary name) with the arguments
+/// of the `command` from the configuration file (if given with `--config`) inserted
+/// before them, except the arguments given on the command line or in environment variables.
+pub fn with_config_file(command: &Command, args: Vec<OsString>) -> eyre::Result<Vec<OsString>> {
+    let matches = command
+        .clone()
+        .ignore_errors(true)
+        .get_matches_from(args.clone());
+    let Some(path) = matches.get_one::<PathBuf>(CONFIG_ARG) else {
+        return Ok(args);
+    };
+    let content =
+        std::fs::read_to_string(path).wrap_err_with(|| format!("reading {}", path.display()))?;
+    let table: toml::value::Table =
+        toml::from_str(&content).wrap_err_with(|| format!("parsing {}", path.display()))?;
+
+    let mut settings = Vec::new();
+    flatten(table, &mut settings);
+    let mut config_args = Vec::new();
+    for (key, value) in settings {
+        let arg = command
+            .get_arguments()
+            .find(|arg| arg.get_id() == key.as_str() && key != CONFIG_ARG)
+            .ok_or_else(|| eyre::eyre!("unknown setting `{key}` in {}", path.display()))?;
+        if matches!(
+            matches.value_source(&key),
+            Some(ValueSource::CommandLine | ValueSource::EnvVariable)
+        ) {
+            continue;
+        }
+        let flag = match (arg.get_long(), arg.get_short()) {
+            (Some(long), _) => format!("--{long}"),
+            (None, Some(short)) => format!("-{short}"),
+            (None, None) => eyre::bail!("setting `{key}` can't be set in the configuration file"),
+        };
+        push_value(&mut config_args, &flag, &key, value)?;
+    }
+
+    let mut args = args.into_iter();
+    Ok(args
+        .next()
+        .into_iter()
+        .chain(config_args)
+        .chain(args)
+        .collect())
+}
+
+/// Collect the settings of the `table` and of the tables in it.
+fn flatten(table: toml::value::Table, settings: &mut Vec<(String, toml::Value)>) {
+    for (key, value) in table {
+        match value {
+            toml::Value::Table(table) => flatten(table, settings),
+            value => settings.push((key, value)),
+        }
+    }
+}
+
+fn push_value(
+    args: &mut Vec<OsString>,
+    flag: &str,
+    key: &str,
+    value: toml::Value,
+) -> eyre::Result<()> {
+    let value = match value {
+        toml::Value::Boolean(true) => {
+            args.push(flag.into());
+            return Ok(());
+        }
+        toml::Value::Boolean(false) => return Ok(()),
+        toml::Value::Array(values) => {
+            for value in values {
+                push_value(args, flag, key, value)?;
+            }
+            return Ok(());
+        }
+        toml::Value::String(value) => value,
+        toml::Value::Integer(value) => value.to_string(),
+        toml::Value::Float(value) => value.to_string(),
+        value => eyre::bail!("unsupported value of `{key}`: {value}"),
+    };
+    args.push(flag.into());
+    args.push(value.into());
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use clap::{CommandFactory, FromArgMatches, Parser};
+
+    use super::*;
+
+    #[derive(Parser, Debug)]
+    struct Cli {
+        #[arg(short, long)]
+        dir: PathBuf,
+        #[arg(long, default_value_t = 1)]
+        threads: usize,
+        #[arg(long)]
+        cores: Vec<usize>,
+        #[arg(long)]
+        best_effort: bool,
+        #[arg(short, default_value_t = 8192)]
+        n: usize,
+        #[arg(long)]
+        config: Option<PathBuf>,
+    }
+
+    fn parse(args: &[&str]) -> eyre::Result<Cli> {
+        parse_with_env(args, "UNSET_PREFIX")
+    }
+
+    fn parse_with_env(args: &[&str], prefix: &str) -> eyre::Result<Cli> {
+        let command = with_env_vars(Cli::command(), prefix);
+        let args = args.iter().map(OsString::from).collect();
+        let matches = command
+            .clone()
+            .try_get_matches_from(with_config_file(&command, args)?)?;
+        Ok(Cli::from_arg_matches(&matches)?)
+    }
+
+    #[test]
+    fn overriding_config_file() {
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        writeln!(
+            file,
+            r#"
+            dir = "/data"
+            best_effort = true
+            [settings]
+            threads = 4
+            cores = [1, 2]
+            n = 16
+            "#
+        )
+        .unwrap();
+        let config = file.path().to_str().unwrap();
+
+        let cli = parse(&["service", "--config", config]).unwrap();
+        assert_eq!(PathBuf::from("/data"), cli.dir);
+        assert_eq!(4, cli.threads);
+        assert_eq!(vec![1, 2], cli.cores);
+        assert!(cli.best_effort);
+        assert_eq!(16, cli.n);
+
+        let cli = parse(&[
+            "service",
+            "--config",
+            config,
+            "--threads",
+            "8",
+            "--cores",
+            "3",
+            "-d",
+            "/other",
+        ])
+        .unwrap();
+        assert_eq!(PathBuf::from("/other"), cli.dir);
+        assert_eq!(8, cli.threads);
+        assert_eq!(vec![3], cli.cores);
+        assert_eq!(16, cli.n);
+
+        // Without a config file, the arguments are unchanged.
+        let cli = parse(&["service", "-d", "/data"]).unwrap();
+        assert_eq!(1, cli.threads);
+    }
+
+    #[test]
+    fn rejecting_unknown_settings() {
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        writeln!(file, "unknown = 1").unwrap();
+        let config = file.path().to_str().unwrap();
+        assert!(parse(&["service", "--config", config]).is_err());
+    }
+
+    #[test]
+    fn reading_env_vars() {
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        writeln!(file, "threads = 4\nn = 16").unwrap();
+        let config = file.path().to_str().unwrap();
+        std::env::set_var("CONFIG_FILE_TEST_THREADS", "6");
+        std::env::set_var("CONFIG_FILE_TEST_CORES", "1");
+        std::env::set_var("CONFIG_FILE_TEST_BEST_EFFORT", "true");
+
+        let cli = parse_with_env(&["service", "-d", "/data"], "CONFIG_FILE_TEST").unwrap();
+        assert_eq!(6, cli.threads);
+        assert_eq!(vec![1], cli.cores);
+        assert!(cli.best_effort);
+
+        // The environment overrides the file, the command line overrides both.
+        let cli = parse_with_env(
+            &["service", "-d", "/data", "--config", config],
+            "CONFIG_FILE_TEST",
+        )
+        .unwrap();
+        assert_eq!(6, cli.threads);
+        assert_eq!(16, cli.n);
+        let cli = parse_with_env(
+            &["service", "-d", "/data", "--threads", "8"],
+            "CONFIG_FILE_TEST",
+        )
+        .unwrap();
+        assert_eq!(8, cli.threads);
+    }
+}
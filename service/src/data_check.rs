@@ -0,0 +1,158 @@
+//! Check of the POS data at startup
+//!
+//! A sample of the labels (a fraction or a number of them) is recomputed from the commitment
+//! and compared to the POS data, to find the corruption (i.e. by a failing disk) before
+//! the node asks for a proof. The mismatches are logged and the result is reported
+//! by `/readyz` of the operator API (see [crate::operator::Readiness]).
+//!
+//! The service is ready once no files are missing and the sampled labels corrupted
+//! are within a threshold (none by default). With the threshold set explicitly,
+//! the service waits for the check and refuses to register with the node if it fails.
+
+use std::path::PathBuf;
+
+use eyre::Context;
+use post::{config::ScryptParams, initialize::IntegrityReport, metadata::PostMetadata};
+use serde::Serialize;
+
+/// Size of the sample of labels to check.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Sample {
+    /// A fraction (in %) of the labels of every POS data file.
+    Percent(f64),
+    /// A number of the labels of the POS data, spread over its files.
+    Labels(u64),
+}
+
+impl Sample {
+    /// The fraction (in %) of the labels of the POS data described by `metadata` to check.
+    fn percent(&self, metadata: &PostMetadata) -> f64 {
+        match *self {
+            Sample::Percent(percent) => percent,
+            Sample::Labels(labels) => {
+                let total = metadata.num_units as u64 * metadata.labels_per_unit;
+                (labels as f64 / total.max(1) as f64 * 100.0).min(100.0)
+            }
+        }
+    }
+}
+
+/// Result of the check of the POS data.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct DataCheck {
+    pub labels_checked: u64,
+    pub corrupted_labels: u64,
+    pub missing_files: usize,
+}
+
+impl DataCheck {
+    /// Percentage of the sampled labels that are corrupted.
+    pub fn corruption(&self) -> f64 {
+        match self.labels_checked {
+            0 => 0.0,
+            checked => self.corrupted_labels as f64 / checked as f64 * 100.0,
+        }
+    }
+
+    /// No POS data files are missing and at most `max_corruption` (in %)
+    /// of the sampled labels are corrupted.
+    pub fn passed(&self, max_corruption: f64) -> bool {
+        self.missing_files == 0 && self.corruption() <= max_corruption
+    }
+
+    fn add(&mut self, report: &IntegrityReport) {
+        for file in &report.files {
+            self.labels_checked += file.labels_checked;
+            self.corrupted_labels += file.corrupted_labels.len() as u64;
+            self.missing_files += file.missing as usize;
+        }
+    }
+}
+
+/// Check a `sample` of the labels of the POS data in the `dirs`.
+pub fn check(dirs: &[PathBuf], sample: Sample, scrypt: ScryptParams) -> eyre::Result<DataCheck> {
+    let mut result = DataCheck::default();
+    for dir in dirs {
+        let metadata = post::metadata::load(dir)
+            .wrap_err_with(|| format!("loading metadata of {}", dir.display()))?;
+        let percent = sample.percent(&metadata);
+        log::info!("checking {percent}% of the POS data in {}", dir.display());
+        let report = post::initialize::verify_data(dir, percent, scrypt)
+            .wrap_err_with(|| format!("checking POS data in {}", dir.display()))?;
+        for file in report.damaged_files() {
+            log::error!(
+                "POS data file {} in {} is damaged: {}",
+                metadata.file_name(file.file_id),
+                dir.display(),
+                if file.missing {
+                    "missing".to_string()
+                } else {
+                    format!(
+                        "{} of {} sampled labels are corrupted (i.e. {:?})",
+                        file.corrupted_labels.len(),
+                        file.labels_checked,
+                        &file.corrupted_labels[..file.corrupted_labels.len().min(8)],
+                    )
+                }
+            );
+        }
+        result.add(&report);
+    }
+    log::info!(
+        "checked {} labels of the POS data: {} corrupted ({:.3}%), {} files missing",
+        result.labels_checked,
+        result.corrupted_labels,
+        result.corruption(),
+        result.missing_files
+    );
+    Ok(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Seek, SeekFrom, Write};
+
+    use post::initialize::{CpuInitializer, Initialize};
+
+    use super::*;
+
+    #[test]
+    fn checking_corrupted_data() {
+        let datadir = tempfile::tempdir().unwrap();
+        let scrypt = ScryptParams::new(2, 1, 1);
+        let metadata = CpuInitializer::new(scrypt)
+            .initialize(datadir.path(), &[77; 32], &[0; 32], 256, 2, 256 * 16, None)
+            .unwrap();
+        let dirs = [datadir.path().to_path_buf()];
+
+        let result = check(&dirs, Sample::Labels(512), scrypt).unwrap();
+        assert_eq!(
+            DataCheck {
+                labels_checked: 512,
+                corrupted_labels: 0,
+                missing_files: 0,
+            },
+            result
+        );
+        assert!(result.passed(0.0));
+
+        // Corrupt a quarter of the labels of the first file
+        let mut file = std::fs::OpenOptions::new()
+            .write(true)
+            .open(datadir.path().join(metadata.file_name(0)))
+            .unwrap();
+        file.seek(SeekFrom::Start(0)).unwrap();
+        file.write_all(&[0xFF; 64 * 16]).unwrap();
+
+        let result = check(&dirs, Sample::Percent(100.0), scrypt).unwrap();
+        assert_eq!(64, result.corrupted_labels);
+        assert_eq!(12.5, result.corruption());
+        assert!(!result.passed(0.0));
+        assert!(result.passed(20.0));
+
+        std::fs::remove_file(datadir.path().join(metadata.file_name(1))).unwrap();
+        let result = check(&dirs, Sample::Percent(100.0), scrypt).unwrap();
+        assert_eq!(1, result.missing_files);
+        assert!(!result.passed(100.0));
+    }
+}
@@ -0,0 +1,363 @@
+//! Initialization of POS data triggered by the operator
+//!
+//! The operator API starts initializing the POS data of the service, follows its progress
+//! and cancels it (see [crate::operator]), so that the whole lifecycle of the POS data,
+//! initializing and proving, runs under the one supervised service.
+//!
+//! The labels are written by the CPU or a GPU (OpenCL) in a background thread.
+//! Initializing on a GPU requires the service to be built with the `gpu` feature.
+//! Like with the `initializer`, a cancelled or failed initialization is resumed from
+//! the last completed file when started again with the same parameters.
+
+use std::{
+    error::Error,
+    io::Write,
+    ops::Range,
+    path::PathBuf,
+    str::FromStr,
+    sync::{Arc, Mutex},
+};
+
+use post::{
+    cancel::CancellationToken,
+    config::{FileLayout, InitConfig},
+    initialize::{CpuInitializer, Initialize, Progress, VrfNonce},
+};
+#[cfg(feature = "gpu")]
+use scrypt_ocl::{ocl::DeviceType, OpenClInitializer, ProviderId};
+use serde::Serialize;
+
+/// What initializes the labels.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Method {
+    Cpu,
+    /// The GPU of the provider (see `initializer list-providers`), the first one by default.
+    Gpu {
+        provider: Option<u32>,
+    },
+}
+
+impl FromStr for Method {
+    type Err = eyre::Report;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "cpu" => Ok(Method::Cpu),
+            "gpu" => Ok(Method::Gpu { provider: None }),
+            _ => eyre::bail!("unknown initialization method `{s}` (expected `cpu` or `gpu`)"),
+        }
+    }
+}
+
+/// Identity and size of the POS data to initialize.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct InitParams {
+    pub node_id: [u8; 32],
+    pub commitment_atx_id: [u8; 32],
+    pub num_units: u32,
+    pub method: Method,
+}
+
+/// State of the initialization of the POS data.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+#[serde(tag = "state", rename_all = "snake_case")]
+pub enum InitStatus {
+    #[default]
+    Idle,
+    Initializing {
+        method: Method,
+        labels_written: u64,
+        total_labels: u64,
+    },
+    Finished {
+        vrf_nonce: Option<u64>,
+    },
+    Cancelled {
+        labels_written: u64,
+        total_labels: u64,
+    },
+    Failed {
+        error: String,
+    },
+}
+
+/// Initializes the POS data in a directory, one initialization at a time.
+pub struct DataInit {
+    datadir: PathBuf,
+    cfg: InitConfig,
+    status: Arc<Mutex<InitStatus>>,
+    /// Cancels the running initialization.
+    cancel: Mutex<Option<CancellationToken>>,
+    /// Cancels all initializations (i.e. when the service stops).
+    stop: CancellationToken,
+}
+
+impl DataInit {
+    pub fn new(datadir: PathBuf, cfg: InitConfig, stop: CancellationToken) -> Self {
+        Self {
+            datadir,
+            cfg,
+            status: Arc::default(),
+            cancel: Mutex::new(None),
+            stop,
+        }
+    }
+
+    pub fn status(&self) -> InitStatus {
+        self.status.lock().unwrap().clone()
+    }
+
+    pub fn is_running(&self) -> bool {
+        matches!(
+            *self.status.lock().unwrap(),
+            InitStatus::Initializing { .. }
+        )
+    }
+
+    /// Start initializing the POS data in the background.
+    pub fn start(&self, params: InitParams) -> eyre::Result<()> {
+        let mut status = self.status.lock().unwrap();
+        eyre::ensure!(
+            !matches!(*status, InitStatus::Initializing { .. }),
+            "the POS data is being initialized already"
+        );
+        eyre::ensure!(
+            (self.cfg.min_num_units..=self.cfg.max_num_units).contains(&params.num_units),
+            "the number of units must be between {} and {}",
+            self.cfg.min_num_units,
+            self.cfg.max_num_units
+        );
+        let scrypt = self.cfg.scrypt;
+        let initializer: Box<dyn Initialize + Send> = match params.method {
+            Method::Cpu => Box::new(CpuInitializer::new(scrypt)),
+            #[cfg(feature = "gpu")]
+            Method::Gpu { provider } => Box::new(OpenClInitializer::new(
+                provider.map(ProviderId),
+                scrypt.n,
+                Some(DeviceType::GPU | DeviceType::CPU),
+            )?),
+            #[cfg(not(feature = "gpu"))]
+            Method::Gpu { .. } => {
+                eyre::bail!(
+                    "initializing on a GPU requires the service built with the `gpu` feature"
+                )
+            }
+        };
+        let total_labels = self.cfg.labels_per_unit * params.num_units as u64;
+        *status = InitStatus::Initializing {
+            method: params.method,
+            labels_written: 0,
+            total_labels,
+        };
+        let cancel = self.stop.child();
+        *self.cancel.lock().unwrap() = Some(cancel.clone());
+
+        log::info!(
+            "initializing {} units of POS data in {} ({:?})",
+            params.num_units,
+            self.datadir.display(),
+            params.method
+        );
+        let datadir = self.datadir.clone();
+        let cfg = self.cfg;
+        let shared_status = self.status.clone();
+        std::thread::spawn(move || {
+            let mut initializer = Cancellable {
+                inner: initializer,
+                cancel: cancel.clone(),
+            };
+            let mut labels_written = 0;
+            let result = initializer.initialize_with_progress(
+                &datadir,
+                &params.node_id,
+                &params.commitment_atx_id,
+                cfg.labels_per_unit,
+                params.num_units,
+                FileLayout::default(),
+                Some([0xFF; 32]),
+                &mut |progress: Progress| {
+                    labels_written = progress.labels_written;
+                    *shared_status.lock().unwrap() = InitStatus::Initializing {
+                        method: params.method,
+                        labels_written,
+                        total_labels,
+                    };
+                },
+            );
+            let status = match result {
+                Ok(metadata) => {
+                    log::info!(
+                        "initialized POS data in {} (VRF nonce: {:?})",
+                        datadir.display(),
+                        metadata.nonce
+                    );
+                    InitStatus::Finished {
+                        vrf_nonce: metadata.nonce,
+                    }
+                }
+                Err(_) if cancel.is_cancelled() => {
+                    log::info!(
+                        "initialization of POS data in {} cancelled",
+                        datadir.display()
+                    );
+                    InitStatus::Cancelled {
+                        labels_written,
+                        total_labels,
+                    }
+                }
+                Err(e) => {
+                    log::error!("initializing POS data in {}: {e}", datadir.display());
+                    InitStatus::Failed {
+                        error: e.to_string(),
+                    }
+                }
+            };
+            *shared_status.lock().unwrap() = status;
+        });
+        Ok(())
+    }
+
+    /// Cancel the running initialization, returns whether there was one.
+    pub fn cancel(&self) -> bool {
+        if !self.is_running() {
+            return false;
+        }
+        if let Some(cancel) = &*self.cancel.lock().unwrap() {
+            cancel.cancel("cancelled by the operator");
+        }
+        true
+    }
+}
+
+impl Drop for DataInit {
+    fn drop(&mut self) {
+        self.cancel();
+    }
+}
+
+/// Stops initializing when cancelled, by failing to write the labels.
+struct Cancellable {
+    inner: Box<dyn Initialize + Send>,
+    cancel: CancellationToken,
+}
+
+struct CancellableWriter<'a> {
+    inner: &'a mut dyn Write,
+    cancel: &'a CancellationToken,
+}
+
+impl Write for CancellableWriter<'_> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.cancel.check().map_err(std::io::Error::other)?;
+        self.inner.write(buf)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+impl Initialize for Cancellable {
+    fn initialize_to(
+        &mut self,
+        writer: &mut dyn Write,
+        commitment: &[u8; 32],
+        labels: Range<u64>,
+        vrf_difficulty: Option<[u8; 32]>,
+    ) -> Result<Option<VrfNonce>, Box<dyn Error>> {
+        let mut writer = CancellableWriter {
+            inner: writer,
+            cancel: &self.cancel,
+        };
+        self.inner
+            .initialize_to(&mut writer, commitment, labels, vrf_difficulty)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use post::config::ScryptParams;
+
+    use super::*;
+
+    fn wait_for(data_init: &DataInit, done: impl Fn(&InitStatus) -> bool) -> InitStatus {
+        let start = Instant::now();
+        loop {
+            let status = data_init.status();
+            if done(&status) {
+                return status;
+            }
+            assert!(start.elapsed() < Duration::from_secs(60), "{status:?}");
+            std::thread::sleep(Duration::from_millis(10));
+        }
+    }
+
+    #[test]
+    fn initializing_data() {
+        let datadir = tempfile::tempdir().unwrap();
+        let cfg = InitConfig {
+            min_num_units: 1,
+            max_num_units: 4,
+            labels_per_unit: 256,
+            scrypt: ScryptParams::new(2, 1, 1),
+        };
+        let data_init = DataInit::new(datadir.path().into(), cfg, CancellationToken::new());
+        assert_eq!(InitStatus::Idle, data_init.status());
+        assert!(!data_init.cancel());
+
+        let params = InitParams {
+            node_id: [7; 32],
+            commitment_atx_id: [8; 32],
+            num_units: 2,
+            method: Method::Cpu,
+        };
+        assert!(data_init
+            .start(InitParams {
+                num_units: 5,
+                ..params
+            })
+            .is_err());
+        data_init.start(params).unwrap();
+        let status = wait_for(&data_init, |s| {
+            !matches!(s, InitStatus::Initializing { .. })
+        });
+        assert!(matches!(
+            status,
+            InitStatus::Finished { vrf_nonce: Some(_) }
+        ));
+
+        let metadata = post::metadata::load(datadir.path()).unwrap();
+        assert_eq!([7; 32], metadata.node_id);
+        assert_eq!(2, metadata.num_units);
+    }
+
+    #[test]
+    fn cancelling_initialization() {
+        let datadir = tempfile::tempdir().unwrap();
+        let cfg = InitConfig {
+            min_num_units: 1,
+            max_num_units: 1,
+            labels_per_unit: 256,
+            scrypt: ScryptParams::new(2, 1, 1),
+        };
+        // Stopping the service cancels the initialization
+        let stop = CancellationToken::new();
+        stop.cancel("stopping");
+        let data_init = DataInit::new(datadir.path().into(), cfg, stop);
+        data_init
+            .start(InitParams {
+                node_id: [7; 32],
+                commitment_atx_id: [8; 32],
+                num_units: 1,
+                method: Method::Cpu,
+            })
+            .unwrap();
+        let status = wait_for(&data_init, |s| {
+            !matches!(s, InitStatus::Initializing { .. })
+        });
+        assert!(matches!(status, InitStatus::Cancelled { .. }), "{status:?}");
+    }
+}
@@ -0,0 +1,315 @@
+//! Remote initialization of POS data
+//!
+//! The [InitializationServer] runs on a machine with fast initializers (i.e. GPUs)
+//! and streams generated labels to clients.
+//! The [RemoteInitializer] is an [Initialize] implementation that requests labels
+//! from such a server, so that machines storing the POS data can initialize it
+//! without a GPU, using all the regular machinery (resuming, checksums, etc.).
+
+use std::{
+    error::Error,
+    io::Write,
+    ops::Range,
+    pin::Pin,
+    sync::{Arc, Mutex},
+};
+
+use post::{
+    config::ScryptParams,
+    initialize::{Initialize, VrfNonce},
+};
+use tokio::sync::mpsc;
+use tokio_stream::{wrappers::ReceiverStream, Stream};
+use tonic::{transport::Endpoint, Request, Response, Status};
+
+use post_v1::{
+    initialization_service_client::InitializationServiceClient, initialization_service_server,
+    initialize_response, InitializeRequest, InitializeResponse, LabelsChunk,
+};
+
+pub mod post_v1 {
+    tonic::include_proto!("post.v1");
+}
+
+pub use post_v1::initialization_service_server::InitializationServiceServer;
+
+const LABEL_SIZE: usize = 16;
+/// Default size of the streamed chunks of labels.
+pub const DEFAULT_CHUNK_SIZE: usize = 1024 * 1024;
+/// Number of chunks buffered in the server before the initialization blocks.
+const BUFFERED_CHUNKS: usize = 4;
+
+type ResponseSender = mpsc::Sender<Result<InitializeResponse, Status>>;
+
+/// Serves initialization requests with the wrapped initializer.
+///
+/// Requests are served one at a time, as the initializer (i.e. a GPU) can't be shared.
+pub struct InitializationServer {
+    initializer: Arc<Mutex<Box<dyn Initialize + Send>>>,
+    scrypt: ScryptParams,
+    chunk_size: usize,
+}
+
+impl InitializationServer {
+    pub fn new(initializer: Box<dyn Initialize + Send>, scrypt: ScryptParams) -> Self {
+        Self {
+            initializer: Arc::new(Mutex::new(initializer)),
+            scrypt,
+            chunk_size: DEFAULT_CHUNK_SIZE,
+        }
+    }
+
+    /// Set the size (in bytes) of streamed chunks of labels.
+    pub fn with_chunk_size(self, chunk_size: usize) -> Self {
+        Self {
+            chunk_size: (chunk_size / LABEL_SIZE).max(1) * LABEL_SIZE,
+            ..self
+        }
+    }
+
+    pub fn into_service(self) -> InitializationServiceServer<Self> {
+        InitializationServiceServer::new(self)
+    }
+}
+
+/// Sends written labels to the client in chunks.
+struct ChunkSender {
+    tx: ResponseSender,
+    first_label: u64,
+    chunk: Vec<u8>,
+    chunk_size: usize,
+}
+
+impl ChunkSender {
+    fn send(&mut self, kind: initialize_response::Kind) -> std::io::Result<()> {
+        self.tx
+            .blocking_send(Ok(InitializeResponse { kind: Some(kind) }))
+            .map_err(|_| std::io::Error::new(std::io::ErrorKind::BrokenPipe, "client disconnected"))
+    }
+
+    fn send_chunk(&mut self) -> std::io::Result<()> {
+        let labels = std::mem::replace(&mut self.chunk, Vec::with_capacity(self.chunk_size));
+        let first_label = self.first_label;
+        self.first_label += (labels.len() / LABEL_SIZE) as u64;
+        self.send(initialize_response::Kind::Labels(LabelsChunk {
+            first_label,
+            labels,
+        }))
+    }
+}
+
+impl Write for ChunkSender {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let n = buf.len().min(self.chunk_size - self.chunk.len());
+        self.chunk.extend_from_slice(&buf[..n]);
+        if self.chunk.len() == self.chunk_size {
+            self.send_chunk()?;
+        }
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        if !self.chunk.is_empty() {
+            self.send_chunk()?;
+        }
+        Ok(())
+    }
+}
+
+fn parse_request(
+    request: &InitializeRequest,
+    scrypt: ScryptParams,
+) -> Result<([u8; 32], Range<u64>, Option<[u8; 32]>), Status> {
+    let commitment = request
+        .commitment
+        .as_slice()
+        .try_into()
+        .map_err(|_| Status::invalid_argument("commitment must be 32 bytes"))?;
+    let vrf_difficulty = request
+        .vrf_difficulty
+        .as_ref()
+        .map(|d| d.as_slice().try_into())
+        .transpose()
+        .map_err(|_| Status::invalid_argument("VRF difficulty must be 32 bytes"))?;
+    let end = request
+        .first_label
+        .checked_add(request.labels)
+        .ok_or_else(|| Status::invalid_argument("label range overflows"))?;
+
+    match &request.scrypt {
+        Some(p) if (p.n, p.r, p.p) == (scrypt.n as u64, scrypt.r as u64, scrypt.p as u64) => {}
+        Some(p) => {
+            return Err(Status::failed_precondition(format!(
+                "scrypt params mismatch: requested N={} R={} P={}, server uses {scrypt:?}",
+                p.n, p.r, p.p
+            )))
+        }
+        None => return Err(Status::invalid_argument("missing scrypt params")),
+    }
+
+    Ok((commitment, request.first_label..end, vrf_difficulty))
+}
+
+#[tonic::async_trait]
+impl initialization_service_server::InitializationService for InitializationServer {
+    type InitializeStream =
+        Pin<Box<dyn Stream<Item = Result<InitializeResponse, Status>> + Send + 'static>>;
+
+    async fn initialize(
+        &self,
+        request: Request<InitializeRequest>,
+    ) -> Result<Response<Self::InitializeStream>, Status> {
+        let request = request.into_inner();
+        let (commitment, labels, vrf_difficulty) = parse_request(&request, self.scrypt)?;
+        log::info!(
+            "initializing labels {labels:?} for commitment {}",
+            hex::encode(commitment)
+        );
+
+        let (tx, rx) = mpsc::channel(BUFFERED_CHUNKS);
+        let initializer = self.initializer.clone();
+        let chunk_size = self.chunk_size;
+        tokio::task::spawn_blocking(move || {
+            let mut writer = ChunkSender {
+                tx: tx.clone(),
+                first_label: labels.start,
+                chunk: Vec::with_capacity(chunk_size),
+                chunk_size,
+            };
+            let mut initializer = match initializer.lock() {
+                Ok(guard) => guard,
+                Err(_) => {
+                    let _ = tx.blocking_send(Err(Status::internal("initializer poisoned")));
+                    return;
+                }
+            };
+            let result = initializer
+                .initialize_to(&mut writer, &commitment, labels.clone(), vrf_difficulty)
+                .and_then(|nonce| {
+                    writer.flush()?;
+                    if let Some(nonce) = nonce {
+                        writer.send(initialize_response::Kind::Nonce(post_v1::VrfNonce {
+                            index: nonce.index,
+                            label: nonce.label.to_vec(),
+                        }))?;
+                    }
+                    Ok(())
+                });
+            match result {
+                Ok(()) => log::info!("initialized labels {labels:?}"),
+                Err(e) => {
+                    log::warn!("failed to initialize labels {labels:?}: {e}");
+                    let _ = tx.blocking_send(Err(Status::internal(e.to_string())));
+                }
+            }
+        });
+
+        Ok(Response::new(
+            Box::pin(ReceiverStream::new(rx)) as Self::InitializeStream
+        ))
+    }
+}
+
+/// Initializes labels by requesting them from a remote [InitializationServer].
+///
+/// The requests run on the tokio runtime of the `handle`, blocking the calling thread.
+/// It must not be used in an async context (i.e. run it in `spawn_blocking`).
+pub struct RemoteInitializer {
+    endpoint: Endpoint,
+    scrypt: ScryptParams,
+    handle: tokio::runtime::Handle,
+}
+
+impl RemoteInitializer {
+    /// Create an initializer requesting labels from the server at `address`
+    /// on the runtime of the `handle`.
+    ///
+    /// The server must use the same scrypt params.
+    pub fn new(
+        address: String,
+        scrypt: ScryptParams,
+        handle: tokio::runtime::Handle,
+    ) -> eyre::Result<Self> {
+        Ok(Self {
+            endpoint: Endpoint::from_shared(address)?,
+            scrypt,
+            handle,
+        })
+    }
+
+    async fn request_labels(
+        &self,
+        writer: &mut dyn Write,
+        commitment: &[u8; 32],
+        labels: Range<u64>,
+        vrf_difficulty: Option<[u8; 32]>,
+    ) -> Result<Option<VrfNonce>, Box<dyn Error>> {
+        let mut client = InitializationServiceClient::connect(self.endpoint.clone()).await?;
+        let request = InitializeRequest {
+            commitment: commitment.to_vec(),
+            first_label: labels.start,
+            labels: labels.end - labels.start,
+            vrf_difficulty: vrf_difficulty.map(|d| d.to_vec()),
+            scrypt: Some(post_v1::ScryptParams {
+                n: self.scrypt.n as u64,
+                r: self.scrypt.r as u64,
+                p: self.scrypt.p as u64,
+            }),
+        };
+        let mut stream = client.initialize(request).await?.into_inner();
+
+        let mut next_label = labels.start;
+        let mut nonce = None;
+        while let Some(response) = stream.message().await? {
+            match response.kind {
+                Some(initialize_response::Kind::Labels(chunk)) => {
+                    if chunk.first_label != next_label || chunk.labels.len() % LABEL_SIZE != 0 {
+                        return Err(format!(
+                            "unexpected chunk of {}B at label {} (expected label {next_label})",
+                            chunk.labels.len(),
+                            chunk.first_label
+                        )
+                        .into());
+                    }
+                    next_label += (chunk.labels.len() / LABEL_SIZE) as u64;
+                    if next_label > labels.end {
+                        return Err("received more labels than requested".into());
+                    }
+                    writer.write_all(&chunk.labels)?;
+                }
+                Some(initialize_response::Kind::Nonce(n)) => {
+                    nonce = Some(VrfNonce {
+                        index: n.index,
+                        label: n
+                            .label
+                            .as_slice()
+                            .try_into()
+                            .map_err(|_| "invalid VRF nonce label")?,
+                    });
+                }
+                None => return Err("empty response".into()),
+            }
+        }
+        if next_label != labels.end {
+            return Err(format!(
+                "stream ended at label {next_label}, expected {}",
+                labels.end
+            )
+            .into());
+        }
+        Ok(nonce)
+    }
+}
+
+impl Initialize for RemoteInitializer {
+    fn initialize_to(
+        &mut self,
+        writer: &mut dyn Write,
+        commitment: &[u8; 32],
+        labels: Range<u64>,
+        vrf_difficulty: Option<[u8; 32]>,
+    ) -> Result<Option<VrfNonce>, Box<dyn Error>> {
+        self.handle
+            .block_on(self.request_labels(writer, commitment, labels, vrf_difficulty))
+    }
+}
@@ -0,0 +1,392 @@
+//! Remote k2 proofs of work
+//!
+//! The k2pow (RandomX) is the main CPU cost of proving for machines with slow CPUs.
+//! The [K2PowServer] runs on a machine with fast CPUs and calculates the proofs of work
+//! for clients. The [RemotePowProver] is a [pow::Prover] implementation that requests
+//! them from such a server, so that a farm of provers can share a single beefy machine.
+
+use std::{ops::Range, sync::Mutex, time::Duration};
+
+use post::{cancel::CancellationToken, pow};
+use tonic::{
+    transport::{Certificate, Endpoint, Identity},
+    Code, Request, Response, Status,
+};
+
+use crate::async_pow::AsyncPow;
+
+use k2pow_v1::{k2pow_service_client::K2PowServiceClient, ProveRequest, ProveResponse};
+
+pub mod k2pow_v1 {
+    tonic::include_proto!("k2pow.v1");
+}
+
+pub use k2pow_v1::k2pow_service_server::K2PowServiceServer;
+
+/// Default number of nonce groups requested at once.
+pub const DEFAULT_BATCH_SIZE: u32 = 4;
+/// Maximal number of nonce groups calculated in a single request.
+const MAX_BATCH_SIZE: u32 = 1024;
+/// How often the client checks if the proof of work was cancelled while waiting for it.
+const CANCEL_CHECK_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Serves k2pow requests with the wrapped [pow::Prover].
+///
+/// The nonce groups of a request are calculated one after another,
+/// every one of them on all the cores (see [pow::randomx::PoW]).
+/// The calculation is cancelled if the client goes away.
+pub struct K2PowServer {
+    prover: AsyncPow<dyn pow::Prover + Send + Sync>,
+}
+
+impl K2PowServer {
+    pub fn new(prover: Box<dyn pow::Prover + Send + Sync>) -> Self {
+        Self {
+            prover: AsyncPow::new(prover.into()),
+        }
+    }
+
+    pub fn into_service(self) -> K2PowServiceServer<Self> {
+        K2PowServiceServer::new(self)
+    }
+}
+
+struct ParsedRequest {
+    nonce_groups: Range<u32>,
+    challenge: [u8; 8],
+    difficulty: [u8; 32],
+    miner_id: [u8; 32],
+}
+
+fn parse_request(request: &ProveRequest) -> Result<ParsedRequest, Status> {
+    let end = request
+        .first_nonce_group
+        .checked_add(request.nonce_groups)
+        .ok_or_else(|| Status::invalid_argument("nonce group range overflows"))?;
+    if request.nonce_groups == 0 || request.nonce_groups > MAX_BATCH_SIZE {
+        return Err(Status::invalid_argument(format!(
+            "number of nonce groups must be between 1 and {MAX_BATCH_SIZE}"
+        )));
+    }
+    Ok(ParsedRequest {
+        nonce_groups: request.first_nonce_group..end,
+        challenge: request
+            .challenge
+            .as_slice()
+            .try_into()
+            .map_err(|_| Status::invalid_argument("challenge must be 8 bytes"))?,
+        difficulty: request
+            .difficulty
+            .as_slice()
+            .try_into()
+            .map_err(|_| Status::invalid_argument("difficulty must be 32 bytes"))?,
+        miner_id: request
+            .miner_id
+            .as_slice()
+            .try_into()
+            .map_err(|_| Status::invalid_argument("miner ID must be 32 bytes"))?,
+    })
+}
+
+#[tonic::async_trait]
+impl k2pow_v1::k2pow_service_server::K2PowService for K2PowServer {
+    async fn prove(
+        &self,
+        request: Request<ProveRequest>,
+    ) -> Result<Response<ProveResponse>, Status> {
+        let request = parse_request(request.get_ref())?;
+        let groups = request.nonce_groups.clone();
+        log::info!(
+            "calculating proofs of work for nonce groups {groups:?} of miner {}",
+            hex::encode(request.miner_id)
+        );
+
+        // Dropping the request (i.e. when the client disconnects) cancels the calculation.
+        let cancel = CancellationToken::new();
+        let pows = async {
+            let mut pows = Vec::with_capacity(groups.len());
+            for nonce_group in request.nonce_groups {
+                let pow = self.prover.prove(
+                    nonce_group,
+                    request.challenge,
+                    request.difficulty,
+                    request.miner_id,
+                    &cancel,
+                );
+                pows.push(pow.await?);
+            }
+            Ok::<_, pow::Error>(pows)
+        };
+
+        match pows.await {
+            Ok(pows) => {
+                log::info!("calculated proofs of work for nonce groups {groups:?}");
+                Ok(Response::new(ProveResponse { pows }))
+            }
+            Err(pow::Error::PoWNotFound) => Err(Status::not_found("proof of work not found")),
+            Err(pow::Error::Cancelled) => Err(Status::cancelled("proof of work was cancelled")),
+            Err(e) => {
+                log::warn!("failed to calculate proofs of work for nonce groups {groups:?}: {e}");
+                Err(Status::internal(e.to_string()))
+            }
+        }
+    }
+}
+
+/// Proofs of work of consecutive nonce groups, received in a single response.
+struct Batch {
+    challenge: [u8; 8],
+    difficulty: [u8; 32],
+    miner_id: [u8; 32],
+    nonce_groups: Range<u32>,
+    pows: Vec<u64>,
+}
+
+impl Batch {
+    fn get(
+        &self,
+        nonce_group: u32,
+        challenge: &[u8; 8],
+        difficulty: &[u8; 32],
+        miner_id: &[u8; 32],
+    ) -> Option<u64> {
+        if (&self.challenge, &self.difficulty, &self.miner_id) != (challenge, difficulty, miner_id)
+            || !self.nonce_groups.contains(&nonce_group)
+        {
+            return None;
+        }
+        Some(self.pows[(nonce_group - self.nonce_groups.start) as usize])
+    }
+}
+
+/// Calculates the proofs of work by requesting them from a remote [K2PowServer].
+///
+/// The nonce groups are proved one after another (see [post::prove::Prover8_56]),
+/// so a request asks for the next `batch_size` nonce groups at once,
+/// and the following calls are answered from the response.
+/// The server then calculates them without waiting for a round trip each.
+pub struct RemotePowProver {
+    endpoint: Endpoint,
+    batch_size: u32,
+    last_batch: Mutex<Option<Batch>>,
+    /// Always set, taken only when dropped.
+    runtime: Option<tokio::runtime::Runtime>,
+}
+
+impl RemotePowProver {
+    /// Create a prover requesting the proofs of work from the server at `address`,
+    /// with the optional TLS configuration (see [crate::client::endpoint]).
+    pub fn new(
+        address: String,
+        tls: Option<(Option<String>, Certificate, Identity)>,
+    ) -> eyre::Result<Self> {
+        Ok(Self {
+            endpoint: crate::client::endpoint(address, tls)?,
+            batch_size: DEFAULT_BATCH_SIZE,
+            last_batch: Mutex::new(None),
+            runtime: Some(
+                tokio::runtime::Builder::new_multi_thread()
+                    .worker_threads(1)
+                    .enable_all()
+                    .build()?,
+            ),
+        })
+    }
+
+    /// Set the number of nonce groups requested at once.
+    ///
+    /// Bigger batches need fewer round trips, but might calculate the proofs of work
+    /// of nonce groups that are never used.
+    pub fn with_batch_size(mut self, batch_size: u32) -> Self {
+        self.batch_size = batch_size.clamp(1, MAX_BATCH_SIZE);
+        self
+    }
+
+    /// The nonce groups to request along with the `nonce_group`.
+    /// A batch of narrow nonce groups doesn't extend to the wide ones,
+    /// which the prover might not be allowed to use.
+    fn batch(&self, nonce_group: u32) -> Range<u32> {
+        let mut end = nonce_group.saturating_add(self.batch_size);
+        if nonce_group < pow::NARROW_NONCE_GROUPS {
+            end = end.min(pow::NARROW_NONCE_GROUPS);
+        }
+        nonce_group..end
+    }
+
+    async fn request_pows(&self, request: ProveRequest) -> Result<Vec<u64>, Status> {
+        let mut client = K2PowServiceClient::connect(self.endpoint.clone())
+            .await
+            .map_err(|e| Status::unavailable(format!("connecting to k2pow server: {e}")))?;
+        let expected = request.nonce_groups as usize;
+        let pows = client.prove(request).await?.into_inner().pows;
+        if pows.len() != expected {
+            return Err(Status::internal(format!(
+                "expected {expected} proofs of work, got {}",
+                pows.len()
+            )));
+        }
+        Ok(pows)
+    }
+
+    /// Request the proofs of work, until the response arrives or `cancel` is cancelled.
+    async fn request_pows_until_cancelled(
+        &self,
+        request: ProveRequest,
+        cancel: &CancellationToken,
+    ) -> Result<Vec<u64>, pow::Error> {
+        let cancelled = async {
+            while !cancel.is_cancelled() {
+                tokio::time::sleep(CANCEL_CHECK_INTERVAL).await;
+            }
+        };
+        tokio::select! {
+            result = self.request_pows(request) => result.map_err(|status| match status.code() {
+                Code::NotFound => pow::Error::PoWNotFound,
+                _ => pow::Error::Internal(Box::new(status)),
+            }),
+            // Dropping the request makes the server stop calculating.
+            _ = cancelled => Err(pow::Error::Cancelled),
+        }
+    }
+}
+
+impl Drop for RemotePowProver {
+    fn drop(&mut self) {
+        // Dropping a runtime blocks, which panics if it's dropped in an async context
+        // (i.e. along with the POST service).
+        if let Some(runtime) = self.runtime.take() {
+            runtime.shutdown_background();
+        }
+    }
+}
+
+impl RemotePowProver {
+    /// The proof of work of the `nonce_group`, from the last batch or requested
+    /// with the following ones. The server encodes the nonce groups as in the proofs
+    /// of their versions (see [post::prove::ProofVersion::of_nonce_group]).
+    fn prove_nonce_group(
+        &self,
+        nonce_group: u32,
+        challenge: &[u8; 8],
+        difficulty: &[u8; 32],
+        miner_id: &[u8; 32],
+        cancel: &CancellationToken,
+    ) -> Result<u64, pow::Error> {
+        let cached = self
+            .last_batch
+            .lock()
+            .unwrap()
+            .as_ref()
+            .and_then(|batch| batch.get(nonce_group, challenge, difficulty, miner_id));
+        if let Some(pow) = cached {
+            return Ok(pow);
+        }
+
+        let nonce_groups = self.batch(nonce_group);
+        log::debug!("requesting proofs of work for nonce groups {nonce_groups:?}");
+        let request = ProveRequest {
+            first_nonce_group: nonce_groups.start,
+            nonce_groups: nonce_groups.len() as u32,
+            challenge: challenge.to_vec(),
+            difficulty: difficulty.to_vec(),
+            miner_id: miner_id.to_vec(),
+        };
+        let pows = self
+            .runtime
+            .as_ref()
+            .unwrap()
+            .block_on(self.request_pows_until_cancelled(request, cancel))?;
+        let pow = pows[0];
+        *self.last_batch.lock().unwrap() = Some(Batch {
+            challenge: *challenge,
+            difficulty: *difficulty,
+            miner_id: *miner_id,
+            nonce_groups,
+            pows,
+        });
+        Ok(pow)
+    }
+}
+
+impl pow::Prover for RemotePowProver {
+    fn prove(
+        &self,
+        nonce_group: u8,
+        challenge: &[u8; 8],
+        difficulty: &[u8; 32],
+        miner_id: &[u8; 32],
+        cancel: &CancellationToken,
+    ) -> Result<u64, pow::Error> {
+        self.prove_nonce_group(nonce_group as u32, challenge, difficulty, miner_id, cancel)
+    }
+
+    fn prove_wide(
+        &self,
+        nonce_group: u32,
+        challenge: &[u8; 8],
+        difficulty: &[u8; 32],
+        miner_id: &[u8; 32],
+        cancel: &CancellationToken,
+    ) -> Result<u64, pow::Error> {
+        if nonce_group < pow::NARROW_NONCE_GROUPS {
+            let msg = format!("nonce group {nonce_group} is not wide");
+            return Err(pow::Error::Internal(msg.into()));
+        }
+        self.prove_nonce_group(nonce_group, challenge, difficulty, miner_id, cancel)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn batching_nonce_groups() {
+        let prover = RemotePowProver::new("http://localhost:1".into(), None).unwrap();
+        assert_eq!(0..4, prover.batch(0));
+        assert_eq!(254..256, prover.batch(254));
+        assert_eq!(256..260, prover.batch(256));
+        assert_eq!(u32::MAX - 1..u32::MAX, prover.batch(u32::MAX - 1));
+
+        let prover = prover.with_batch_size(0);
+        assert_eq!(7..8, prover.batch(7));
+    }
+
+    #[test]
+    fn parsing_request() {
+        let request = ProveRequest {
+            first_nonce_group: 3,
+            nonce_groups: 2,
+            challenge: vec![1; 8],
+            difficulty: vec![2; 32],
+            miner_id: vec![3; 32],
+        };
+        let parsed = parse_request(&request).unwrap();
+        assert_eq!(3..5, parsed.nonce_groups);
+        assert_eq!([1; 8], parsed.challenge);
+
+        for invalid in [
+            ProveRequest {
+                nonce_groups: 0,
+                ..request.clone()
+            },
+            ProveRequest {
+                first_nonce_group: u32::MAX,
+                ..request.clone()
+            },
+            ProveRequest {
+                challenge: vec![1; 32],
+                ..request.clone()
+            },
+            ProveRequest {
+                miner_id: vec![],
+                ..request.clone()
+            },
+        ] {
+            assert_eq!(
+                Code::InvalidArgument,
+                parse_request(&invalid).err().unwrap().code()
+            );
+        }
+    }
+}
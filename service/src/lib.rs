@@ -1,2 +1,14 @@
+pub mod async_pow;
 pub mod client;
+pub mod config_file;
+pub mod data_check;
+pub mod data_init;
+pub mod initialization;
+pub mod k2pow;
+pub mod logging;
+pub mod operator;
+pub mod pool;
+pub mod schedule;
+pub mod server;
 pub mod service;
+pub mod verification;
@@ -0,0 +1,161 @@
+//! Logging of the service
+//!
+//! The logs are written as text or as JSON records (`--log-format json`), one per line,
+//! so that they can be queried once ingested (i.e. in Loki or Elasticsearch):
+//!
+//! ```json
+//! {"timestamp":"2024-01-01T12:00:00.000Z","level":"INFO","module":"post::prove",
+//!  "message":"...","proving":[{"challenges":["ca.."],"progress":{"passes":1,...}}]}
+//! ```
+//!
+//! The records logged while proofs are generated carry their challenges and progress
+//! (see [ProvingContext]).
+
+use std::{
+    io::Write,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+};
+
+use post::prove::ProvingStats;
+use serde_json::json;
+
+use crate::service::ProvingProgress;
+
+/// Format of the logs.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum LogFormat {
+    #[default]
+    Text,
+    Json,
+}
+
+/// Initialize the logger writing in the `format`,
+/// at the level set in `RUST_LOG` (`info` by default).
+pub fn init(format: LogFormat) {
+    let env = env_logger::Env::default().filter_or("RUST_LOG", "info");
+    let mut builder = env_logger::Builder::from_env(env);
+    if format == LogFormat::Json {
+        builder.format(|buf, record| {
+            let timestamp = buf.timestamp_millis().to_string();
+            writeln!(buf, "{}", json_record(timestamp, record))
+        });
+    }
+    builder.init();
+}
+
+fn json_record(timestamp: String, record: &log::Record) -> serde_json::Value {
+    let mut value = json!({
+        "timestamp": timestamp,
+        "level": record.level().as_str(),
+        "module": record.module_path().unwrap_or_else(|| record.target()),
+        "message": record.args().to_string(),
+    });
+    let proving = ProvingContext::current();
+    if !proving.is_empty() {
+        value["proving"] = proving.into();
+    }
+    value
+}
+
+struct RunningProof {
+    id: u64,
+    challenges: Vec<String>,
+    stats: Arc<Mutex<ProvingStats>>,
+}
+
+static RUNNING_PROOFS: Mutex<Vec<RunningProof>> = Mutex::new(Vec::new());
+static NEXT_ID: AtomicU64 = AtomicU64::new(0);
+
+/// A running proof generation, added to the JSON log records until dropped.
+pub struct ProvingContext {
+    id: u64,
+}
+
+impl ProvingContext {
+    pub fn enter(challenges: &[[u8; 32]], stats: Arc<Mutex<ProvingStats>>) -> Self {
+        let id = NEXT_ID.fetch_add(1, Ordering::Relaxed);
+        RUNNING_PROOFS.lock().unwrap().push(RunningProof {
+            id,
+            challenges: challenges.iter().map(hex::encode).collect(),
+            stats,
+        });
+        Self { id }
+    }
+
+    /// The challenges and progress of the running proof generations.
+    fn current() -> Vec<serde_json::Value> {
+        let running = RUNNING_PROOFS.lock().unwrap();
+        running
+            .iter()
+            .map(|proof| {
+                // The stats might be locked by the thread logging, skip them then.
+                let progress = proof
+                    .stats
+                    .try_lock()
+                    .ok()
+                    .map(|stats| ProvingProgress::from(&*stats));
+                json!({"challenges": proof.challenges, "progress": progress})
+            })
+            .collect()
+    }
+}
+
+impl Drop for ProvingContext {
+    fn drop(&mut self) {
+        RUNNING_PROOFS
+            .lock()
+            .unwrap()
+            .retain(|proof| proof.id != self.id);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn formatting_json_records() {
+        let record = |message: &str| {
+            json_record(
+                "2024-01-01T12:00:00.000Z".into(),
+                &log::Record::builder()
+                    .args(format_args!("{message}"))
+                    .level(log::Level::Info)
+                    .module_path(Some("post::prove"))
+                    .build(),
+            )
+        };
+        assert_eq!(
+            json!({
+                "timestamp": "2024-01-01T12:00:00.000Z",
+                "level": "INFO",
+                "module": "post::prove",
+                "message": "idle",
+            }),
+            record("idle")
+        );
+
+        let mut stats = ProvingStats::default();
+        stats.passes = 2;
+        let context = ProvingContext::enter(&[[0xCA; 32]], Arc::new(Mutex::new(stats)));
+        let value = record("proving");
+        let proving = value["proving"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .find(|p| p["challenges"] == json!([hex::encode([0xCA; 32])]))
+            .expect("running proof should be logged");
+        assert_eq!(2, proving["progress"]["passes"]);
+
+        drop(context);
+        let value = record("done");
+        assert!(value.get("proving").map_or(true, |proving| !proving
+            .as_array()
+            .unwrap()
+            .iter()
+            .any(|p| p["challenges"] == json!([hex::encode([0xCA; 32])]))));
+    }
+}
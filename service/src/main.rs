@@ -1,27 +1,79 @@
-use std::{fs::read_to_string, path::PathBuf, time::Duration};
+use std::{
+    fs::read_to_string,
+    path::PathBuf,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
 
-use clap::{Args, Parser, ValueEnum};
+use clap::{Args, CommandFactory, FromArgMatches, Parser, Subcommand, ValueEnum};
 use eyre::Context;
 use sysinfo::{Pid, ProcessExt, ProcessStatus, System, SystemExt};
 use tokio::sync::oneshot::{self, error::TryRecvError, Receiver};
-use tonic::transport::{Certificate, Identity};
+use tokio::task::JoinSet;
+use tonic::transport::{Certificate, Identity, Server, ServerTlsConfig};
 
-use post::pow::randomx::RandomXFlag;
-use post_service::client;
+use post::pow::randomx::{BackgroundPoW, RandomXFlag};
+use post::prove::{NumaPlacement, ProofCache, UnitRange};
+use post::reader::ReaderBackend;
+use post_service::{
+    client, config_file, data_check,
+    logging::{self, LogFormat},
+    operator, pool,
+    schedule::{ProvingSchedule, TimeWindow},
+    server,
+    service::PostService,
+};
+#[cfg(feature = "gpu")]
+use scrypt_ocl::ProviderId;
+
+/// Time between the PoET rounds of the mainnet, the proofs must be generated within.
+const CYCLE_GAP: Duration = Duration::from_secs(12 * 60 * 60);
 
 /// Post Service
 #[derive(Parser, Debug)]
-#[command(version, about)]
+#[command(version, about, subcommand_negates_reqs = true)]
 struct Cli {
-    /// directory of POST data
-    #[arg(short, long)]
-    dir: PathBuf,
-    /// address to connect to
-    #[arg(short, long)]
-    address: String,
+    #[command(subcommand)]
+    command: Option<Command>,
+
+    /// directory of POST data (can be repeated to prove for several identities)
+    #[arg(short, long, global = true)]
+    dir: Vec<PathBuf>,
+    /// directory tree to prove for all identities with POS data in (can be repeated)
+    ///
+    /// Every directory of POS data found in it is proved for, as if given with `--dir`.
+    #[arg(long, global = true)]
+    pool_dir: Vec<PathBuf>,
+    /// connections of nodes allowed per identity at a time when listening for the node
+    #[arg(long, default_value_t = 1)]
+    max_connections_per_identity: usize,
+    /// address to connect to, or `unix:/path/to/socket` for a Unix domain socket
+    ///
+    /// Can be repeated to fail over to the next nodes when connecting to one keeps failing
+    /// (see `--failover-attempts`).
+    #[arg(short, long, required_unless_present = "listen")]
+    address: Vec<String>,
+    /// failed attempts in a row to connect to a node before failing over to the next one
+    #[arg(long, default_value_t = 3)]
+    failover_attempts: usize,
+    /// address to listen on for the node to connect, instead of connecting to it
+    ///
+    /// With TLS, the node is authenticated against the CA certificate
+    /// and the service presents its certificate.
+    /// Proving for several identities, the node names the identity of its stream
+    /// in the `x-node-id` gRPC metadata (hex encoded node ID).
+    #[arg(long, conflicts_with = "address")]
+    listen: Option<std::net::SocketAddr>,
     /// time to wait before reconnecting to the node
     #[arg(long, default_value = "5", value_parser = |secs: &str| secs.parse().map(Duration::from_secs))]
     reconnect_interval_s: Duration,
+    /// maximum time to wait before reconnecting to the node
+    ///
+    /// If set, the time to wait doubles after every failed attempt (from `--reconnect-interval-s`)
+    /// up to it and is randomized, so that many services don't reconnect to a recovering node
+    /// all at once.
+    #[arg(long, value_parser = |secs: &str| secs.parse().map(Duration::from_secs))]
+    max_reconnect_interval_s: Option<Duration>,
     /// Maximum number of retries to connect to the node
     /// The default is infinite.
     #[arg(long)]
@@ -36,9 +88,37 @@ struct Cli {
     #[command(flatten, next_help_heading = "TLS configuration")]
     tls: Option<Tls>,
 
+    #[command(flatten, next_help_heading = "Remote k2pow")]
+    k2pow: K2Pow,
+
     /// watch PID and exit if it dies
     #[arg(long)]
     watch_pid: Option<sysinfo::Pid>,
+
+    #[command(flatten, next_help_heading = "Operator API")]
+    operator: Operator,
+
+    /// format of the logs: `text` or `json` (one record per line, with the progress
+    /// of the proof generation)
+    #[arg(long, value_enum, default_value_t = LogFormat::Text)]
+    log_format: LogFormat,
+
+    /// TOML file with the settings (keyed by the names of the arguments, i.e. `pow_threads`).
+    /// The arguments given on the command line or in `POST_SERVICE_*` environment
+    /// variables (i.e. `POST_SERVICE_POW_THREADS`) override it.
+    #[arg(long)]
+    config: Option<PathBuf>,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Generate a proof with the settings until a part of the POS data is read
+    /// and project how long a full pass takes, i.e. to check them before the deadline
+    Benchmark {
+        /// fraction (in %) of the POS data to read
+        #[arg(long, default_value_t = 1.0)]
+        percent: f64,
+    },
 }
 
 #[derive(Args, Debug)]
@@ -92,20 +172,210 @@ struct ScryptParams {
 /// POST proof generation settings
 struct PostSettings {
     /// number of threads to use
-    /// '0' means use all available threads
+    /// '0' means use all available threads (the CPU quota of the cgroup, if limited)
     #[arg(long, default_value_t = 1)]
     threads: usize,
+    /// number of threads calculating the k2pow, by default the same as `--threads`
+    /// '0' means use all available threads
+    #[arg(long)]
+    pow_threads: Option<usize>,
+    /// number of the proving threads checking the labels, out of `--threads`
+    /// '0' means all of them
+    ///
+    /// It can be changed while proving with `PUT /threads?active=N` of the operator API
+    /// (served with TLS or on the Unix domain socket),
+    /// i.e. to leave the CPU to other work on the machine for a while.
+    #[arg(long, default_value_t = 0)]
+    active_threads: usize,
+    /// pick the nonces and threads at startup instead of `--nonces` and `--threads`
+    ///
+    /// The speeds of reading the POS data, checking the labels and calculating the k2pow
+    /// (unless remote) are measured to pick the ones minimizing the expected proving time,
+    /// preferring fewer threads if more wouldn't prove noticeably faster.
+    #[arg(long)]
+    auto_tune: bool,
+    /// run the k2pow threads at the lowest OS priority (Linux and Windows only)
+    ///
+    /// They then use only the CPU time left by other threads and processes,
+    /// i.e. reading the POS data or a node on the same machine.
+    #[arg(long)]
+    low_priority_pow: bool,
     /// number of nonces to attempt in single pass over POS data
     ///
     /// Each group of 16 nonces requires a separate PoW. Must be a multiple of 16.
+    /// At most 4096 (256 groups), unless `--wide-nonce-groups` is set.
     ///
     /// Higher value gives a better chance to find a proof within less passes over the POS data,
     /// but also slows down the process.
+    ///
+    /// '0' or 'auto' picks the number minimizing the expected proving time,
+    /// measured from the read speed of the POS data and the CPU speed.
     #[arg(long, default_value_t = 128, value_parser(parse_nonces))]
     nonces: usize,
     /// modes of operation for RandomX
     #[arg(long, default_value_t = RandomXMode::Fast)]
     randomx_mode: RandomXMode,
+    /// use large pages for RandomX (if huge pages are reserved in the OS)
+    ///
+    /// Makes the k2pow faster. Falls back to regular pages if the huge pages
+    /// can't be allocated.
+    #[arg(long)]
+    randomx_large_pages: bool,
+    /// file with the passphrase to decrypt the POS data encrypted at rest
+    #[arg(long)]
+    data_passphrase_file: Option<PathBuf>,
+    /// interval of saving the proving progress (in seconds) to resume
+    /// an interrupted proof generation from. '0' disables checkpointing
+    #[arg(long, default_value = "60", value_parser = |secs: &str| secs.parse().map(Duration::from_secs))]
+    checkpoint_interval_s: Duration,
+    /// time (in seconds) the proof generation must finish within
+    ///
+    /// The proof generation fails early if it's estimated to not finish in time,
+    /// so that the node learns about it before it's too late.
+    #[arg(long, value_parser = |secs: &str| secs.parse().map(Duration::from_secs))]
+    proving_timeout_s: Option<Duration>,
+    /// time (in seconds) without progress over the POS data to give up the proof generation
+    /// after, i.e. on a dead disk or hung IO
+    ///
+    /// It must be longer than the k2pow of a pass, as the passes might wait for it.
+    /// Disabled by default.
+    #[arg(long, value_parser = |secs: &str| secs.parse().map(Duration::from_secs))]
+    stall_timeout_s: Option<Duration>,
+    /// times to retry a stalled proof generation before reporting the failure to the node
+    #[arg(long, default_value_t = 0, requires = "stall_timeout_s")]
+    stall_retries: usize,
+    /// initialize RandomX in the background at startup and reuse it for all proofs
+    ///
+    /// The dataset of the fast mode takes minutes to initialize, it's then done once
+    /// instead of at the start of every proof generation. Not used with a remote k2pow.
+    #[arg(long)]
+    background_randomx_init: bool,
+    /// benchmark the k2pow at startup for this long (in seconds)
+    ///
+    /// Warns if the proofs of work of a pass are expected to take longer than
+    /// the proving timeout (or the cycle gap if not set).
+    #[arg(long, value_parser = |secs: &str| secs.parse().map(Duration::from_secs))]
+    k2pow_benchmark_s: Option<Duration>,
+    /// how to read POS data
+    #[arg(long, value_enum, default_value_t = ReaderMode::Buffered)]
+    reader: ReaderMode,
+    /// size (in bytes) of chunks of POS data read at once
+    ///
+    /// Must be a multiple of 128. Larger chunks suit HDDs, smaller ones SSDs.
+    #[arg(long, default_value_t = post::reader::DEFAULT_READ_CHUNK_SIZE)]
+    read_chunk_size: usize,
+    /// number of chunks read ahead of processing them
+    ///
+    /// It bounds the queue between the reader and the workers checking the labels.
+    /// With the `io-uring` reader it's also the number of reads in flight at once.
+    /// NVMe drives benefit from deep queues. With '0' the reader hands every chunk
+    /// over to a worker before reading the next one.
+    #[arg(long, default_value_t = post::reader::DEFAULT_READ_AHEAD)]
+    read_ahead: usize,
+    /// read POS data files residing on different disks in parallel
+    ///
+    /// The disks are told apart by the device IDs of the files, unless given with `disk`.
+    #[arg(long)]
+    parallel_reads: bool,
+    /// directory a disk with POS data files is mounted at (can be repeated)
+    #[arg(long, requires = "parallel_reads")]
+    disk: Vec<PathBuf>,
+    /// scan labels for the proof on a GPU instead of the CPU
+    #[cfg(feature = "gpu")]
+    #[arg(long)]
+    gpu_scanning: bool,
+    /// provider ID of the GPU to scan labels on (see `initializer list-providers`)
+    ///
+    /// If not specified, the first available provider is used.
+    #[cfg(feature = "gpu")]
+    #[arg(long, requires = "gpu_scanning")]
+    gpu_scanning_provider: Option<u32>,
+    /// skip missing or unreadable POS data files instead of failing the proof generation
+    ///
+    /// The proof is looked for in the rest of the POS data, which might not succeed.
+    #[arg(long)]
+    best_effort: bool,
+    /// CPU cores to run the proving threads on (comma separated)
+    ///
+    /// Linux only. By default, the threads run on any core.
+    #[arg(long, value_delimiter = ',', conflicts_with = "exclude_cores")]
+    cores: Vec<usize>,
+    /// CPU cores to keep the proving threads off (comma separated)
+    ///
+    /// Linux only. Useful to reserve cores for other services, i.e. the node.
+    #[arg(long, value_delimiter = ',')]
+    exclude_cores: Vec<usize>,
+    /// NUMA node to run the proving threads on: `off`, `storage` or a node number
+    ///
+    /// Linux only. With `storage`, the node the POS data storage is attached to.
+    /// Combined with `--cores` or `--exclude-cores`, only the selected cores of the node are used.
+    #[arg(long, default_value = "off", value_parser(parse_numa))]
+    numa: NumaPlacement,
+    /// memory budget (in MiB) for reading the POS data and collecting the indices of labels
+    ///
+    /// Chunks of POS data are read smaller and fewer ahead to fit half of it.
+    /// Indices not fitting the rest are spilled to a temporary file.
+    /// If the memory of the cgroup of the process is limited (i.e. in a container),
+    /// it defaults to what's left under the limit after RandomX.
+    #[arg(long)]
+    memory_budget: Option<usize>,
+    /// directory to cache the generated proofs in
+    ///
+    /// A proof generated before the service restarts is then not generated again.
+    #[arg(long)]
+    proof_cache_dir: Option<PathBuf>,
+    /// allow more than 256 groups of nonces (4096 nonces)
+    ///
+    /// The PoW of the nonce groups above 255 has a wider input, so their proofs are V2,
+    /// the network must accept such proofs.
+    #[arg(long)]
+    wide_nonce_groups: bool,
+    /// prove with only a range of units of the POS data, i.e. `2..4` for units 2 and 3
+    ///
+    /// Useful when only some of the POS data is available. The proof is less likely
+    /// to be found in a pass over less data.
+    #[arg(long, value_parser(parse_units))]
+    units: Option<UnitRange>,
+    /// verify the POS data files against the checksums in the metadata while proving
+    ///
+    /// The files are hashed as they are read, so corrupted POS data is found
+    /// during proving rather than when the proof fails verification.
+    #[arg(long)]
+    verify_checksums: bool,
+    /// cache the k2 proofs of work found in the POS data directory
+    ///
+    /// A retried proof generation for the same challenge then reuses them
+    /// instead of calculating them again.
+    #[arg(long)]
+    cache_pows: bool,
+    /// check a fraction (in %) of the labels of the POS data at startup
+    ///
+    /// The service is not ready (see `/readyz` of the operator API) until the check passes.
+    #[arg(long, group = "data_check")]
+    startup_data_check: Option<f64>,
+    /// check a number of random labels of the POS data at startup
+    /// (instead of a fraction with `--startup-data-check`)
+    #[arg(long, group = "data_check")]
+    startup_data_check_labels: Option<u64>,
+    /// maximum fraction (in %) of the labels checked at startup that may be corrupted
+    ///
+    /// The service then waits for the check and refuses to register with the node
+    /// if more labels are corrupted or POS data files are missing.
+    /// By default, the check runs in the background and no corruption is tolerated.
+    #[arg(long, requires = "data_check")]
+    max_data_corruption: Option<f64>,
+    /// time window of the day (UTC) to generate proofs in, i.e. `22:00-06:00` (can be repeated)
+    ///
+    /// Outside of the windows, the proof generation is deferred, or paused if running
+    /// (and resumed from its checkpoint in the next window). By default, proofs are
+    /// generated at any time.
+    #[arg(long)]
+    proving_window: Vec<TimeWindow>,
+    /// with several directories of POST data, generate their proofs in parallel
+    ///
+    /// By default, they are generated one at a time, not to compete for the disks and the CPU.
+    #[arg(long)]
+    parallel_proving: bool,
 }
 
 /// RandomX modes of operation
@@ -118,6 +388,249 @@ enum RandomXMode {
     Fast,
     /// Light mode for verification. Requires only 256 MiB of memory, but runs significantly slower
     Light,
+    /// Fast mode if 2.5 GiB of memory is available (within the cgroup limits),
+    /// light mode otherwise.
+    Auto,
+}
+
+/// Memory (in bytes) safely fitting the fast mode: the dataset, the cache and the VMs.
+const FAST_MODE_MEMORY: u64 = 2560 * 1024 * 1024;
+/// Memory (in bytes) of the light mode: the cache.
+const LIGHT_MODE_MEMORY: u64 = 256 * 1024 * 1024;
+
+impl RandomXMode {
+    /// Pick the fast or the light mode for [RandomXMode::Auto],
+    /// by the memory (in bytes) that is `available` if known.
+    fn resolve(self, available: impl FnOnce() -> Option<u64>) -> Self {
+        if self != RandomXMode::Auto {
+            return self;
+        }
+        match available() {
+            Some(memory) if memory >= FAST_MODE_MEMORY => {
+                log::info!(
+                    "using the fast RandomX mode, {} MiB of memory is available",
+                    memory >> 20
+                );
+                RandomXMode::Fast
+            }
+            Some(memory) => {
+                log::warn!(
+                    "using the (slower) light RandomX mode, only {} MiB of memory is available",
+                    memory >> 20
+                );
+                RandomXMode::Light
+            }
+            None => {
+                log::warn!("using the (slower) light RandomX mode, available memory is unknown");
+                RandomXMode::Light
+            }
+        }
+    }
+}
+
+/// Memory (in bytes) available to the process: the available memory of the system,
+/// or less if the cgroup of the process has a lower limit.
+fn available_memory() -> Option<u64> {
+    let mut system = System::new();
+    system.refresh_memory();
+    let available = Some(system.available_memory()).filter(|&memory| memory > 0);
+    available.into_iter().chain(cgroup_available_memory()).min()
+}
+
+/// Memory (in bytes) left under the memory limit of the cgroup (v2 or v1), if limited.
+fn cgroup_available_memory() -> Option<u64> {
+    let read = |path: &str| -> Option<u64> { read_to_string(path).ok()?.trim().parse().ok() };
+    let (limit, usage) = match read("/sys/fs/cgroup/memory.max") {
+        Some(limit) => (limit, read("/sys/fs/cgroup/memory.current")?),
+        None => (
+            read("/sys/fs/cgroup/memory/memory.limit_in_bytes")?,
+            read("/sys/fs/cgroup/memory/memory.usage_in_bytes")?,
+        ),
+    };
+    Some(limit.saturating_sub(usage))
+}
+
+/// Memory budget (in MiB) of each of the proof generations running `at_once`, sharing
+/// the memory `available` (in bytes) once the `reserved` memory (i.e. of RandomX) is taken.
+/// A quarter of it is left to the rest of the process. None if nothing is left.
+fn memory_budget(available: u64, reserved: u64, at_once: usize) -> Option<usize> {
+    let budget = available.checked_sub(reserved)? / 4 * 3 / at_once.max(1) as u64;
+    Some((budget >> 20) as usize).filter(|&mib| mib > 0)
+}
+
+/// CPU quota of the cgroup (v2 or v1) in CPUs (rounded up), if limited.
+fn cgroup_cpu_quota() -> Option<usize> {
+    match read_to_string("/sys/fs/cgroup/cpu.max") {
+        Ok(max) => parse_cpu_max(&max),
+        Err(_) => {
+            let read =
+                |path: &str| -> Option<i64> { read_to_string(path).ok()?.trim().parse().ok() };
+            cpu_quota(
+                read("/sys/fs/cgroup/cpu/cpu.cfs_quota_us")?,
+                read("/sys/fs/cgroup/cpu/cpu.cfs_period_us")?,
+            )
+        }
+    }
+}
+
+/// CPUs of the `$QUOTA $PERIOD` of `cpu.max` (cgroup v2), the quota being `max` if unlimited.
+fn parse_cpu_max(max: &str) -> Option<usize> {
+    let (quota, period) = max.trim().split_once(' ')?;
+    cpu_quota(quota.parse().ok()?, period.parse().ok()?)
+}
+
+/// CPUs of the CPU time `quota` per `period`, rounded up. A negative quota is unlimited.
+fn cpu_quota(quota: i64, period: i64) -> Option<usize> {
+    if quota <= 0 || period <= 0 {
+        return None;
+    }
+    Some((quota as u64).div_ceil(period as u64) as usize)
+}
+
+/// Fit the proving threads and memory into the limits of the cgroup of the process
+/// (i.e. `docker run --cpus 2 --memory 3g`), as the cores and memory of the whole host
+/// are visible in a container, but using more gets the process throttled or killed.
+///
+/// All threads ('0') are as many as the CPU quota, more are warned about. Unless given,
+/// the memory budget is what's left under the memory limit after RandomX.
+fn fit_cgroup_limits(args: &mut Cli, pow_flags: RandomXFlag) {
+    let settings = &mut args.post_settings;
+    if let Some(quota) = cgroup_cpu_quota() {
+        for (name, threads) in [
+            ("--threads", Some(&mut settings.threads)),
+            ("--pow-threads", settings.pow_threads.as_mut()),
+        ] {
+            match threads {
+                Some(threads) if *threads == 0 => {
+                    log::info!("{name}: using {quota} threads, the CPU quota of the cgroup");
+                    *threads = quota;
+                }
+                Some(threads) if *threads > quota => log::warn!(
+                    "{name}: {threads} threads exceed the CPU quota of the cgroup ({quota} CPUs), \
+                    they will be throttled"
+                ),
+                _ => {}
+            }
+        }
+    }
+
+    let Some(available) = cgroup_available_memory() else {
+        return;
+    };
+    // Every service verifies its proofs with RandomX in the light mode.
+    let mut reserved = LIGHT_MODE_MEMORY * args.dir.len() as u64;
+    let at_once = match settings.parallel_proving {
+        true => args.dir.len(),
+        false => 1,
+    };
+    if args.k2pow.k2pow_address.is_none() {
+        let randomx = match pow_flags.contains(RandomXFlag::FLAG_FULL_MEM) {
+            true => FAST_MODE_MEMORY,
+            false => LIGHT_MODE_MEMORY,
+        };
+        // Initialized in the background, a single RandomX prover is shared by the services.
+        let provers = match settings.background_randomx_init {
+            true => 1,
+            false => at_once,
+        };
+        reserved += randomx * provers as u64;
+    }
+    match memory_budget(available, reserved, at_once) {
+        Some(_) if settings.memory_budget.is_some() => {}
+        Some(budget) => {
+            log::info!(
+                "using a memory budget of {budget} MiB for proving, fitting the memory limit \
+                of the cgroup ({} MiB available)",
+                available >> 20
+            );
+            settings.memory_budget = Some(budget);
+        }
+        None => log::warn!(
+            "only {} MiB of memory is available under the memory limit of the cgroup, \
+            less than the {} MiB RandomX needs; the service might be killed running out of memory \
+            (consider `--randomx-mode light` or a remote k2pow server)",
+            available >> 20,
+            reserved >> 20
+        ),
+    }
+}
+
+/// Modes of reading POS data
+#[derive(Debug, Copy, Clone, Eq, PartialEq, ValueEnum)]
+enum ReaderMode {
+    /// Buffered sequential reads.
+    Buffered,
+    /// Direct IO, bypassing the OS page cache.
+    Direct,
+    /// Memory mapped files. Might be faster if the POS data (partially) fits in RAM.
+    Mmap,
+    /// Reads submitted via io_uring.
+    #[cfg(all(feature = "io-uring", target_os = "linux"))]
+    IoUring,
+}
+
+impl From<ReaderMode> for ReaderBackend {
+    fn from(val: ReaderMode) -> Self {
+        match val {
+            ReaderMode::Buffered => ReaderBackend::Buffered,
+            ReaderMode::Direct => ReaderBackend::Direct,
+            ReaderMode::Mmap => ReaderBackend::Mmap,
+            #[cfg(all(feature = "io-uring", target_os = "linux"))]
+            ReaderMode::IoUring => ReaderBackend::IoUring,
+        }
+    }
+}
+
+/// Remote k2pow configuration
+#[derive(Args, Debug, Clone)]
+struct K2Pow {
+    /// address of a k2pow server (see `post-k2pow-server`) to calculate
+    /// the k2 proofs of work on instead of locally, i.e. `http://k2pow.lan:50052`
+    #[arg(long)]
+    k2pow_address: Option<String>,
+    /// number of nonce groups requested from the k2pow server at once
+    #[arg(long, default_value_t = post_service::k2pow::DEFAULT_BATCH_SIZE)]
+    k2pow_batch_size: u32,
+    /// CA certificate to verify the k2pow server against (enables TLS)
+    #[arg(long, requires_all = ["k2pow_cert", "k2pow_key"])]
+    k2pow_ca_cert: Option<PathBuf>,
+    /// certificate of this client for the k2pow server
+    #[arg(long)]
+    k2pow_cert: Option<PathBuf>,
+    #[arg(long)]
+    k2pow_key: Option<PathBuf>,
+    /// domain name to verify the certificate of the k2pow server against
+    /// defaults to the server hostname
+    #[arg(long)]
+    k2pow_domain: Option<String>,
+}
+
+/// Operator API configuration
+#[derive(Args, Debug, Clone)]
+struct Operator {
+    /// address to serve the HTTP operator API on (`/status`, `/proving`, `/metadata`,
+    /// `/healthz` and `/readyz`).
+    /// Disabled by default
+    ///
+    /// It's read-only unless served with TLS (`operator_cert`), as the clients
+    /// are not authenticated otherwise.
+    #[arg(long)]
+    operator_address: Option<std::net::SocketAddr>,
+    /// Unix domain socket to serve the HTTP operator API on, for tools on the same host
+    #[cfg(unix)]
+    #[arg(long)]
+    operator_socket: Option<PathBuf>,
+    /// certificate of the operator API (enables TLS)
+    ///
+    /// The clients must then authenticate with certificates signed by `operator_client_ca_cert`.
+    #[arg(long, requires_all = ["operator_address", "operator_key", "operator_client_ca_cert"])]
+    operator_cert: Option<PathBuf>,
+    #[arg(long, requires = "operator_cert")]
+    operator_key: Option<PathBuf>,
+    /// CA certificate to verify the certificates of the operator API clients against,
+    /// separate from the CA of the node connection
+    #[arg(long, requires = "operator_cert")]
+    operator_client_ca_cert: Option<PathBuf>,
 }
 
 /// TLS configuration
@@ -150,17 +663,44 @@ impl From<RandomXMode> for RandomXFlag {
         match val {
             RandomXMode::Fast => RandomXFlag::get_recommended_flags() | RandomXFlag::FLAG_FULL_MEM,
             RandomXMode::Light => RandomXFlag::get_recommended_flags(),
+            RandomXMode::Auto => val.resolve(available_memory).into(),
         }
     }
 }
 
 fn parse_nonces(arg: &str) -> eyre::Result<usize> {
+    if arg == "auto" {
+        return Ok(0);
+    }
     let nonces = arg.parse()?;
     eyre::ensure!(nonces % 16 == 0, "nonces must be multiple of 16");
-    eyre::ensure!(nonces / 16 <= 256, format!("max nonces is {}", 256 * 16));
     Ok(nonces)
 }
 
+fn parse_numa(arg: &str) -> eyre::Result<NumaPlacement> {
+    match arg {
+        "off" => Ok(NumaPlacement::Off),
+        "storage" => Ok(NumaPlacement::Storage),
+        node => Ok(NumaPlacement::Node(
+            node.parse()
+                .wrap_err("expected `off`, `storage` or a node number")?,
+        )),
+    }
+}
+
+fn parse_units(arg: &str) -> eyre::Result<UnitRange> {
+    let (start, end) = arg
+        .split_once("..")
+        .ok_or_else(|| eyre::eyre!("expected a range of units like `2..4`"))?;
+    let start: u32 = start.parse().wrap_err("invalid first unit")?;
+    let end: u32 = end.parse().wrap_err("invalid end unit")?;
+    eyre::ensure!(start < end, "the range of units is empty");
+    Ok(UnitRange {
+        first: start,
+        count: end - start,
+    })
+}
+
 fn parse_difficulty(arg: &str) -> eyre::Result<[u8; 32]> {
     hex::decode(arg)?
         .as_slice()
@@ -170,38 +710,202 @@ fn parse_difficulty(arg: &str) -> eyre::Result<[u8; 32]> {
 
 #[tokio::main]
 async fn main() -> eyre::Result<()> {
-    let args = Cli::parse();
+    let command = config_file::with_env_vars(Cli::command(), "POST_SERVICE");
+    let args = config_file::with_config_file(&command, std::env::args_os().collect())?;
+    let mut args = Cli::from_arg_matches(&command.get_matches_from(args))?;
 
-    let env = env_logger::Env::default().filter_or("RUST_LOG", "info");
-    env_logger::init_from_env(env);
+    logging::init(args.log_format);
 
     log::info!("POST network parameters: {:?}", args.post_config);
     log::info!("POST proving settings: {:?}", args.post_settings);
+    eyre::ensure!(
+        args.post_settings.wide_nonce_groups || args.post_settings.nonces / 16 <= 256,
+        "max nonces is {} without --wide-nonce-groups",
+        256 * 16
+    );
 
     let scrypt = post::config::ScryptParams::new(
         args.post_config.scrypt.n,
         args.post_config.scrypt.r,
         args.post_config.scrypt.p,
     );
-    let service = post_service::service::PostService::new(
-        args.dir,
-        post::config::ProofConfig {
-            k1: args.post_config.k1,
-            k2: args.post_config.k2,
-            k3: args.post_config.k3,
-            pow_difficulty: args.post_config.pow_difficulty,
-        },
-        post::config::InitConfig {
-            min_num_units: args.post_config.min_num_units,
-            max_num_units: args.post_config.max_num_units,
-            labels_per_unit: args.post_config.labels_per_unit,
-            scrypt,
-        },
-        args.post_settings.nonces,
-        args.post_settings.threads,
-        args.post_settings.randomx_mode.into(),
-    )
-    .wrap_err("creating Post Service")?;
+    let mut pow_flags = RandomXFlag::from(args.post_settings.randomx_mode);
+    if args.post_settings.randomx_large_pages {
+        pow_flags |= RandomXFlag::FLAG_LARGE_PAGES;
+    }
+    for root in &args.pool_dir {
+        let dirs = pool::discover(root)
+            .wrap_err_with(|| format!("discovering POS data in {}", root.display()))?;
+        log::info!(
+            "found POS data of {} identities in {}",
+            dirs.len(),
+            root.display()
+        );
+        args.dir.extend(dirs);
+    }
+    eyre::ensure!(
+        !args.dir.is_empty(),
+        "a directory of POST data (--dir or --pool-dir) is required"
+    );
+    fit_cgroup_limits(&mut args, pow_flags);
+    #[cfg(unix)]
+    let operator_enabled =
+        args.operator.operator_address.is_some() || args.operator.operator_socket.is_some();
+    #[cfg(not(unix))]
+    let operator_enabled = args.operator.operator_address.is_some();
+    eyre::ensure!(
+        args.dir.len() == 1 || !operator_enabled,
+        "the operator API supports a single directory of POST data"
+    );
+
+    // The k2pow prover is shared by the services of all the directories.
+    let pow_prover: Option<Arc<dyn post::pow::Prover + Send + Sync>> =
+        if let Some(address) = &args.k2pow.k2pow_address {
+            let tls = match (
+                &args.k2pow.k2pow_ca_cert,
+                &args.k2pow.k2pow_cert,
+                &args.k2pow.k2pow_key,
+            ) {
+                (Some(ca_cert), Some(cert), Some(key)) => Some((
+                    args.k2pow.k2pow_domain.clone(),
+                    Certificate::from_pem(read_to_string(ca_cert)?),
+                    Identity::from_pem(read_to_string(cert)?, read_to_string(key)?),
+                )),
+                _ => None,
+            };
+            log::info!("calculating k2pow on {address} (TLS: {})", tls.is_some());
+            let prover = post_service::k2pow::RemotePowProver::new(address.clone(), tls)
+                .wrap_err("creating remote k2pow prover")?
+                .with_batch_size(args.k2pow.k2pow_batch_size);
+            Some(Arc::new(prover))
+        } else if args.post_settings.background_randomx_init {
+            let pow = BackgroundPoW::spawn(move || post::prove::randomx_prover(pow_flags))
+                .wrap_err("spawning RandomX initialization")?;
+            Some(Arc::new(pow))
+        } else {
+            None
+        };
+
+    let proving_lock = Arc::new(Mutex::new(()));
+    let mut services = Vec::new();
+    for dir in &args.dir {
+        let mut service = new_service(&args, dir.clone(), scrypt, pow_flags)?;
+        if let Some(prover) = &pow_prover {
+            service = service.with_pow_prover(prover.clone());
+        }
+        if args.dir.len() > 1 && !args.post_settings.parallel_proving {
+            service = service.with_sequential_proving(proving_lock.clone());
+        }
+        if let Some(path) = &args.post_settings.data_passphrase_file {
+            let passphrase = read_to_string(path)
+                .wrap_err_with(|| format!("reading passphrase from {}", path.display()))?;
+            let metadata = post::metadata::load(dir).wrap_err("loading POST metadata")?;
+            let commitment =
+                post::initialize::calc_commitment(&metadata.node_id, &metadata.commitment_atx_id);
+            let key =
+                post::encryption::DataKey::derive(passphrase.trim_end().as_bytes(), &commitment);
+            key.validate(&metadata)
+                .wrap_err_with(|| format!("validating POS data key of {}", dir.display()))?;
+            service = service.with_data_key(key);
+        }
+        if args.post_settings.auto_tune {
+            service = service.with_auto_tuning(args.k2pow.k2pow_address.is_none())?;
+        }
+        services.push(service);
+    }
+
+    if let Some(Command::Benchmark { percent }) = args.command {
+        let limit = args.post_settings.proving_timeout_s.unwrap_or(CYCLE_GAP);
+        for (service, dir) in services.into_iter().zip(&args.dir) {
+            log::info!("benchmarking proving over {percent}% of {}", dir.display());
+            let result = tokio::task::spawn_blocking(move || service.benchmark(percent))
+                .await?
+                .wrap_err_with(|| format!("benchmarking proving in {}", dir.display()))?;
+            let projected = result.projected_pass();
+            log::info!(
+                "read {} of {} labels in {:.2?} after {:.2?} of k2pow",
+                result.labels_scanned,
+                result.total_labels,
+                result.reading_time,
+                result.k2pow_time
+            );
+            if projected > limit {
+                log::warn!(
+                    "a pass over {} is projected to take {projected:.0?}, longer than {limit:.0?}; \
+                    consider fewer nonces, more threads or faster storage",
+                    dir.display()
+                );
+            } else {
+                log::info!(
+                    "a pass over {} is projected to take {projected:.0?}",
+                    dir.display()
+                );
+            }
+        }
+        return Ok(());
+    }
+    let services: Vec<_> = services.into_iter().map(Arc::new).collect();
+    let mut identities = pool::Registry::new(args.max_connections_per_identity);
+    for (service, dir) in services.iter().zip(&args.dir) {
+        // The POS data might not be initialized yet.
+        let node_id = post::metadata::load(dir).ok().map(|m| m.node_id);
+        identities.register(node_id, dir.clone(), service.clone())?;
+    }
+
+    let sample = match (
+        args.post_settings.startup_data_check,
+        args.post_settings.startup_data_check_labels,
+    ) {
+        (Some(percent), _) => Some(data_check::Sample::Percent(percent)),
+        (None, Some(labels)) => Some(data_check::Sample::Labels(labels)),
+        (None, None) => None,
+    };
+    let readiness = Arc::new(operator::Readiness::new(sample.is_some()));
+    let data_check_task = sample.map(|sample| {
+        let dirs = args.dir.clone();
+        let max_corruption = args.post_settings.max_data_corruption.unwrap_or(0.0);
+        let readiness = readiness.clone();
+        tokio::task::spawn_blocking(move || {
+            let result = data_check::check(&dirs, sample, scrypt);
+            match &result {
+                Ok(result) => {
+                    readiness.report_data_check(result.clone());
+                    if result.passed(max_corruption) {
+                        log::info!("POS data passed the check");
+                        readiness.set_data_checked();
+                    } else {
+                        log::error!("POS data failed the check");
+                    }
+                }
+                Err(e) => log::error!("checking POS data: {e:?}"),
+            }
+            result
+        })
+    });
+
+    if let Some(duration) = args.post_settings.k2pow_benchmark_s {
+        if args.k2pow.k2pow_address.is_some() {
+            log::info!("not benchmarking k2pow, it's calculated remotely");
+        } else {
+            // The k2pow of the largest POS data takes the longest.
+            let limit = args.post_settings.proving_timeout_s.unwrap_or(CYCLE_GAP);
+            let mut estimate = Duration::ZERO;
+            for service in &services {
+                let service_estimate = service
+                    .estimate_k2pow_duration(duration)
+                    .wrap_err("benchmarking k2pow")?;
+                estimate = estimate.max(service_estimate);
+            }
+            if estimate > limit {
+                log::warn!(
+                    "k2pow of a pass is expected to take {estimate:.0?}, longer than {limit:.0?}; \
+                    consider fewer nonces, the fast RandomX mode or a remote k2pow server"
+                );
+            } else {
+                log::info!("k2pow of a pass is expected to take {estimate:.0?}");
+            }
+        }
+    }
 
     let tls = if let Some(tls) = args.tls {
         log::info!(
@@ -224,8 +928,91 @@ async fn main() -> eyre::Result<()> {
         None
     };
 
-    let client = client::ServiceClient::new(args.address, tls, service)?;
-    let client_handle = tokio::spawn(client.run(args.max_retries, args.reconnect_interval_s));
+    if let Some(address) = args.operator.operator_address {
+        let tls = match (
+            &args.operator.operator_cert,
+            &args.operator.operator_key,
+            &args.operator.operator_client_ca_cert,
+        ) {
+            (Some(cert), Some(key), Some(client_ca_cert)) => {
+                let read = |path: &PathBuf| {
+                    read_to_string(path).wrap_err_with(|| format!("reading {}", path.display()))
+                };
+                let acceptor =
+                    operator::tls_acceptor(&read(cert)?, &read(key)?, &read(client_ca_cert)?)
+                        .wrap_err("configuring TLS of the operator API")?;
+                Some(acceptor)
+            }
+            _ => None,
+        };
+        let listener = std::net::TcpListener::bind(address)
+            .wrap_err_with(|| format!("binding operator API to {address}"))?;
+        tokio::spawn(operator::serve(
+            listener,
+            services[0].clone(),
+            readiness.clone(),
+            tls,
+        ));
+    }
+    #[cfg(unix)]
+    if let Some(path) = args.operator.operator_socket.clone() {
+        let (state, readiness) = (services[0].clone(), readiness.clone());
+        tokio::spawn(async move {
+            if let Err(e) = operator::serve_unix(&path, state, readiness).await {
+                log::error!("serving operator API on {}: {e:?}", path.display());
+            }
+        });
+    }
+
+    if let (Some(max_corruption), Some(task)) =
+        (args.post_settings.max_data_corruption, data_check_task)
+    {
+        let result = task.await??;
+        eyre::ensure!(
+            result.passed(max_corruption),
+            "not registering with the node: {:.3}% of the checked labels are corrupted \
+            (allowed: {max_corruption}%) and {} POS data files are missing",
+            result.corruption(),
+            result.missing_files
+        );
+    }
+
+    let mut clients = JoinSet::new();
+    if let Some(listen) = args.listen {
+        let mut grpc = Server::builder();
+        if let Some((_, ca_cert, identity)) = tls {
+            let config = ServerTlsConfig::new()
+                .identity(identity)
+                .client_ca_root(ca_cert);
+            grpc = grpc.tls_config(config)?;
+        }
+        let node_server = server::NodeServer::new(identities).with_readiness(readiness.clone());
+        log::info!(
+            "listening for the node on {listen} for {} identities",
+            services.len()
+        );
+        clients.spawn(async move {
+            grpc.add_service(node_server.into_service())
+                .serve(listen)
+                .await
+                .wrap_err("serving the node")
+        });
+    } else {
+        // Every identity registers on its own stream to the node.
+        let (address, failover) = args
+            .address
+            .split_first()
+            .expect("address is required without a subcommand or listening");
+        for service in services {
+            let mut client = client::ServiceClient::new(address.clone(), tls.clone(), service)?
+                .with_failover(failover.to_vec(), tls.clone(), args.failover_attempts)?
+                .with_readiness(readiness.clone());
+            if let Some(interval) = args.max_reconnect_interval_s {
+                client = client.with_reconnect_backoff(interval);
+            }
+            clients.spawn(client.run(args.max_retries, args.reconnect_interval_s));
+        }
+    }
 
     // A channel to communicate when the blocking task should quit.
     let (term_tx, term_rx) = oneshot::channel();
@@ -235,13 +1022,112 @@ async fn main() -> eyre::Result<()> {
             log::info!("PID watcher exited: {err:?}");
             return Ok(())
         }
-        err = client_handle => {
+        Some(err) = clients.join_next() => {
             drop(term_tx);
             return err.unwrap();
         }
     }
 }
 
+/// Create the service proving with the POS data in `dir` with the settings of the `args`.
+fn new_service(
+    args: &Cli,
+    dir: PathBuf,
+    scrypt: post::config::ScryptParams,
+    pow_flags: RandomXFlag,
+) -> eyre::Result<PostService> {
+    let settings = &args.post_settings;
+    let mut service = PostService::new(
+        dir,
+        post::config::ProofConfig {
+            k1: args.post_config.k1,
+            k2: args.post_config.k2,
+            k3: args.post_config.k3,
+            pow_difficulty: args.post_config.pow_difficulty,
+        },
+        post::config::InitConfig {
+            min_num_units: args.post_config.min_num_units,
+            max_num_units: args.post_config.max_num_units,
+            labels_per_unit: args.post_config.labels_per_unit,
+            scrypt,
+        },
+        settings.nonces,
+        settings.threads,
+        pow_flags,
+    )
+    .wrap_err("creating Post Service")?;
+
+    if !settings.checkpoint_interval_s.is_zero() {
+        service = service.with_checkpoint_interval(settings.checkpoint_interval_s);
+    }
+
+    if let Some(timeout) = settings.proving_timeout_s {
+        service = service.with_proving_timeout(timeout);
+    }
+
+    if let Some(timeout) = settings.stall_timeout_s {
+        service = service.with_stall_watchdog(timeout, settings.stall_retries);
+    }
+
+    service = service
+        .with_reader(settings.reader.into())
+        .with_read_chunks(settings.read_chunk_size, settings.read_ahead);
+    if settings.parallel_reads {
+        service = service.with_parallel_reads(settings.disk.clone());
+    }
+    if settings.best_effort {
+        service = service.with_best_effort_proving();
+    }
+    if !settings.cores.is_empty() {
+        service = service.with_cores(settings.cores.clone());
+    } else if !settings.exclude_cores.is_empty() {
+        service = service.with_excluded_cores(settings.exclude_cores.clone());
+    }
+    service = service.with_numa_placement(settings.numa);
+    if let Some(budget) = settings.memory_budget {
+        service = service.with_memory_budget(budget * 1024 * 1024);
+    }
+    if settings.wide_nonce_groups {
+        service = service.with_wide_nonce_groups();
+    }
+    if let Some(units) = settings.units {
+        service = service.with_units(units);
+    }
+    if settings.verify_checksums {
+        service = service.with_checksum_verification();
+    }
+    if let Some(threads) = settings.pow_threads {
+        service = service.with_pow_threads(threads);
+    }
+    if settings.active_threads != 0 {
+        service = service.with_active_threads(settings.active_threads);
+    }
+    if settings.low_priority_pow {
+        service = service.with_low_priority_pow();
+    }
+    if settings.cache_pows {
+        service = service.with_pow_cache()?;
+    }
+    if !settings.proving_window.is_empty() {
+        let schedule = ProvingSchedule::new(settings.proving_window.clone());
+        service = service.with_proving_schedule(schedule);
+    }
+    if let Some(dir) = &settings.proof_cache_dir {
+        let cache = ProofCache::new(dir).wrap_err("creating proof cache")?;
+        service = service.with_proof_cache(cache);
+    }
+    #[cfg(feature = "gpu")]
+    if settings.gpu_scanning {
+        let scanner = scrypt_ocl::OpenClLabelScanner::new(
+            settings.gpu_scanning_provider.map(ProviderId),
+            None,
+        )
+        .wrap_err("creating GPU label scanner")?;
+        service = service.with_label_scanner(scanner);
+    }
+    Ok(service)
+}
+
 async fn watch_pid_if_needed(
     watch: Option<(Pid, Receiver<()>)>,
 ) -> Option<std::result::Result<(), tokio::task::JoinError>> {
@@ -285,6 +1171,59 @@ mod tests {
     use sysinfo::{Pid, PidExt};
     use tokio::sync::oneshot;
 
+    use super::{RandomXMode, FAST_MODE_MEMORY, LIGHT_MODE_MEMORY};
+
+    #[test]
+    fn resolving_auto_randomx_mode() {
+        let auto = RandomXMode::Auto;
+        assert_eq!(RandomXMode::Fast, auto.resolve(|| Some(FAST_MODE_MEMORY)));
+        assert_eq!(
+            RandomXMode::Light,
+            auto.resolve(|| Some(FAST_MODE_MEMORY - 1))
+        );
+        assert_eq!(RandomXMode::Light, auto.resolve(|| None));
+        assert_eq!(
+            RandomXMode::Light,
+            RandomXMode::Light.resolve(|| Some(u64::MAX))
+        );
+        assert_eq!(RandomXMode::Fast, RandomXMode::Fast.resolve(|| Some(0)));
+    }
+
+    #[test]
+    fn parsing_cgroup_cpu_quota() {
+        assert_eq!(None, super::parse_cpu_max("max 100000\n"));
+        assert_eq!(Some(2), super::parse_cpu_max("200000 100000\n"));
+        assert_eq!(Some(2), super::parse_cpu_max("150000 100000"));
+        assert_eq!(Some(1), super::parse_cpu_max("50000 100000"));
+        assert_eq!(None, super::parse_cpu_max(""));
+        // cgroup v1
+        assert_eq!(None, super::cpu_quota(-1, 100000));
+        assert_eq!(Some(4), super::cpu_quota(400000, 100000));
+    }
+
+    #[test]
+    fn fitting_memory_budget() {
+        const MIB: u64 = 1024 * 1024;
+        let available = 3072 * MIB;
+        assert_eq!(
+            Some(384),
+            super::memory_budget(available, FAST_MODE_MEMORY, 1)
+        );
+        assert_eq!(
+            Some(192),
+            super::memory_budget(available, FAST_MODE_MEMORY, 2)
+        );
+        assert_eq!(
+            Some(1920),
+            super::memory_budget(available, 2 * LIGHT_MODE_MEMORY, 1)
+        );
+        assert_eq!(None, super::memory_budget(2048 * MIB, FAST_MODE_MEMORY, 1));
+        assert_eq!(
+            None,
+            super::memory_budget(FAST_MODE_MEMORY, FAST_MODE_MEMORY, 1)
+        );
+    }
+
     #[tokio::test]
     async fn watch_pid_if_needed() {
         // Don't watch
@@ -0,0 +1,732 @@
+//! HTTP operator API
+//!
+//! A small JSON API for dashboards and monitoring, so that they don't have to scrape
+//! the logs of the service:
+//! - `GET /status`: whether a proof is being generated, the last error and the configuration,
+//! - `GET /proving`: the state and progress of the proof generation (see [ProvingStatus]),
+//! - `GET /metadata`: the metadata of the POS data (`postdata_metadata.json`), i.e. the node ID,
+//!   the commitment ATX ID, the number of units, the labels per unit and the VRF nonce,
+//! - `GET /threads`: the number of the proving threads checking the labels ('0' means all),
+//! - `PUT /threads?active=N`: change it, also for the running proof generation,
+//!   i.e. to leave the CPU to other work on the machine for a while,
+//! - `GET /initialization`: the state and progress of initializing the POS data,
+//! - `POST /initialization?node_id=..&commitment_atx_id=..&num_units=N[&method=gpu&provider=ID]`:
+//!   start initializing the POS data (the IDs hex encoded) on the CPU (by default) or a GPU,
+//!   see [crate::data_init],
+//! - `DELETE /initialization`: cancel it, it's resumed when started again
+//!   (only the authenticated clients can start or cancel it, like changing the threads),
+//! - `GET /healthz`: whether the service is running (for liveness probes),
+//! - `GET /readyz`: whether the service is ready to generate proofs (for readiness probes),
+//!   that is the POS metadata loads, the node is connected and the POS data passed
+//!   the startup check (if enabled, its result is reported too), see [Readiness].
+//!
+//! It can be served with TLS (see [tls_acceptor]), requiring the clients to authenticate
+//! with certificates, so that it can be exposed for remote management,
+//! or on a Unix domain socket for tools on the same host (see [serve_unix]).
+//! Without TLS, the clients connecting over TCP are not authenticated, so they can only
+//! read the state (`GET`), the requests changing it are forbidden.
+
+use std::{
+    convert::Infallible,
+    net::TcpListener,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+};
+
+use eyre::Context;
+use hyper::{
+    header,
+    server::conn::Http,
+    service::{make_service_fn, service_fn},
+    Body, Method, Request, Response, Server, StatusCode,
+};
+use post::metadata::PostMetadata;
+use serde_json::json;
+use tokio_rustls::{
+    rustls::{self, server::AllowAnyAuthenticatedClient},
+    TlsAcceptor,
+};
+
+use crate::{
+    data_check::DataCheck,
+    data_init::{InitParams, InitStatus, Method as InitMethod},
+    service::{PostService, ProvingStatus},
+};
+
+/// State of the service reported by the operator API.
+pub trait OperatorState: Send + Sync + 'static {
+    fn proving_status(&self) -> ProvingStatus;
+    fn config(&self) -> serde_json::Value;
+    fn metadata(&self) -> eyre::Result<PostMetadata>;
+    fn active_threads(&self) -> usize;
+    fn set_active_threads(&self, threads: usize);
+    fn start_initialization(&self, params: InitParams) -> eyre::Result<()>;
+    fn initialization_status(&self) -> InitStatus;
+    fn cancel_initialization(&self) -> bool;
+}
+
+impl OperatorState for PostService {
+    fn proving_status(&self) -> ProvingStatus {
+        PostService::proving_status(self)
+    }
+
+    fn config(&self) -> serde_json::Value {
+        PostService::config(self)
+    }
+
+    fn metadata(&self) -> eyre::Result<PostMetadata> {
+        crate::client::PostService::get_metadata(self)
+    }
+
+    fn active_threads(&self) -> usize {
+        PostService::active_threads(self)
+    }
+
+    fn set_active_threads(&self, threads: usize) {
+        PostService::set_active_threads(self, threads)
+    }
+
+    fn start_initialization(&self, params: InitParams) -> eyre::Result<()> {
+        PostService::start_initialization(self, params)
+    }
+
+    fn initialization_status(&self) -> InitStatus {
+        PostService::initialization_status(self)
+    }
+
+    fn cancel_initialization(&self) -> bool {
+        PostService::cancel_initialization(self)
+    }
+}
+
+/// Conditions of the readiness of the service, besides loading its POS metadata,
+/// updated as they change.
+#[derive(Debug)]
+pub struct Readiness {
+    node_connected: AtomicBool,
+    data_checked: AtomicBool,
+    data_check: Mutex<Option<DataCheck>>,
+}
+
+impl Readiness {
+    /// The POS data must be checked before the service is ready if `check_data` is set.
+    pub fn new(check_data: bool) -> Self {
+        Self {
+            node_connected: AtomicBool::new(false),
+            data_checked: AtomicBool::new(!check_data),
+            data_check: Mutex::new(None),
+        }
+    }
+
+    pub fn set_node_connected(&self, connected: bool) {
+        self.node_connected.store(connected, Ordering::Relaxed);
+    }
+
+    /// The POS data passed the startup check.
+    pub fn set_data_checked(&self) {
+        self.data_checked.store(true, Ordering::Relaxed);
+    }
+
+    /// Report the `result` of the startup check of the POS data.
+    pub fn report_data_check(&self, result: DataCheck) {
+        *self.data_check.lock().unwrap() = Some(result);
+    }
+
+    /// Reasons why the service is not ready (yet).
+    fn unready_reasons(&self, state: &dyn OperatorState) -> Vec<String> {
+        let mut reasons = Vec::new();
+        if let Err(e) = state.metadata() {
+            reasons.push(format!("POS metadata: {e:#}"));
+        }
+        if !self.node_connected.load(Ordering::Relaxed) {
+            reasons.push("not connected to the node".into());
+        }
+        if !self.data_checked.load(Ordering::Relaxed) {
+            reasons.push("POS data not checked".into());
+        }
+        reasons
+    }
+}
+
+/// What the clients of the operator API may do, depending on how they connect.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Access {
+    /// Only read the state (`GET`): the clients connecting over TCP without TLS,
+    /// which anyone reaching the port can do.
+    ReadOnly,
+    /// Also change it (i.e. `PUT /threads`, starting or cancelling the initialization):
+    /// the clients authenticated with certificates or connecting on the Unix domain socket,
+    /// on the same host.
+    Full,
+}
+
+/// Serve the operator API on the `listener` until the returned future is dropped,
+/// with TLS if the `tls` acceptor is given. Without TLS, it's read-only (see [Access]).
+pub async fn serve(
+    listener: TcpListener,
+    state: Arc<dyn OperatorState>,
+    readiness: Arc<Readiness>,
+    tls: Option<TlsAcceptor>,
+) -> eyre::Result<()> {
+    if let Some(acceptor) = tls {
+        return serve_tls(listener, state, readiness, acceptor).await;
+    }
+    log::info!("serving operator API on {}", listener.local_addr()?);
+    let make_service = make_service_fn(move |_| {
+        let state = state.clone();
+        let readiness = readiness.clone();
+        async move {
+            Ok::<_, Infallible>(service_fn(move |request: Request<Body>| {
+                let state = state.clone();
+                let readiness = readiness.clone();
+                async move {
+                    let response = handle(&*state, &readiness, Access::ReadOnly, &request);
+                    Ok::<_, Infallible>(response)
+                }
+            }))
+        }
+    });
+    Server::from_tcp(listener)?.serve(make_service).await?;
+    Ok(())
+}
+
+async fn serve_tls(
+    listener: TcpListener,
+    state: Arc<dyn OperatorState>,
+    readiness: Arc<Readiness>,
+    acceptor: TlsAcceptor,
+) -> eyre::Result<()> {
+    listener.set_nonblocking(true)?;
+    let listener = tokio::net::TcpListener::from_std(listener)?;
+    log::info!(
+        "serving operator API with TLS on {}",
+        listener.local_addr()?
+    );
+    loop {
+        let (stream, peer) = listener.accept().await?;
+        let acceptor = acceptor.clone();
+        let state = state.clone();
+        let readiness = readiness.clone();
+        tokio::spawn(async move {
+            let stream = match acceptor.accept(stream).await {
+                Ok(stream) => stream,
+                Err(e) => {
+                    log::warn!("rejected operator API connection from {peer}: {e}");
+                    return;
+                }
+            };
+            serve_connection(stream, state, readiness, Access::Full, &peer.to_string()).await;
+        });
+    }
+}
+
+/// Serve the operator API on the Unix domain socket at `path` until the returned future
+/// is dropped, i.e. for tools on the same host, without ports or TLS to manage.
+/// A stale socket left at `path` is replaced.
+#[cfg(unix)]
+pub async fn serve_unix(
+    path: &std::path::Path,
+    state: Arc<dyn OperatorState>,
+    readiness: Arc<Readiness>,
+) -> eyre::Result<()> {
+    use std::os::unix::fs::FileTypeExt;
+
+    if std::fs::metadata(path).is_ok_and(|m| m.file_type().is_socket()) {
+        std::fs::remove_file(path).wrap_err_with(|| format!("removing {}", path.display()))?;
+    }
+    let listener = tokio::net::UnixListener::bind(path)
+        .wrap_err_with(|| format!("binding operator API to {}", path.display()))?;
+    log::info!("serving operator API on {}", path.display());
+    loop {
+        let (stream, _) = listener.accept().await?;
+        let state = state.clone();
+        let readiness = readiness.clone();
+        tokio::spawn(async move {
+            let peer = "Unix domain socket";
+            serve_connection(stream, state, readiness, Access::Full, peer).await;
+        });
+    }
+}
+
+/// Serve the requests on the connection `stream` from the `peer` with the `access`.
+async fn serve_connection<S>(
+    stream: S,
+    state: Arc<dyn OperatorState>,
+    readiness: Arc<Readiness>,
+    access: Access,
+    peer: &str,
+) where
+    S: tokio::io::AsyncRead + tokio::io::AsyncWrite + Unpin + Send + 'static,
+{
+    let service = service_fn(move |request: Request<Body>| {
+        let state = state.clone();
+        let readiness = readiness.clone();
+        async move { Ok::<_, Infallible>(handle(&*state, &readiness, access, &request)) }
+    });
+    if let Err(e) = Http::new()
+        .http1_only(true)
+        .serve_connection(stream, service)
+        .await
+    {
+        log::debug!("operator API connection from {peer} failed: {e}");
+    }
+}
+
+/// Configure TLS of the operator API with the server's `cert` and `key`,
+/// requiring the clients to present certificates signed by the `client_ca_cert`
+/// (all PEM encoded).
+pub fn tls_acceptor(cert: &str, key: &str, client_ca_cert: &str) -> eyre::Result<TlsAcceptor> {
+    let certs = rustls_pemfile::certs(&mut cert.as_bytes())
+        .wrap_err("parsing certificate")?
+        .into_iter()
+        .map(rustls::Certificate)
+        .collect();
+    let key = rustls_pemfile::read_all(&mut key.as_bytes())
+        .wrap_err("parsing key")?
+        .into_iter()
+        .find_map(|item| match item {
+            rustls_pemfile::Item::PKCS8Key(key)
+            | rustls_pemfile::Item::RSAKey(key)
+            | rustls_pemfile::Item::ECKey(key) => Some(rustls::PrivateKey(key)),
+            _ => None,
+        })
+        .ok_or_else(|| eyre::eyre!("no private key found"))?;
+
+    let mut client_roots = rustls::RootCertStore::empty();
+    for ca_cert in rustls_pemfile::certs(&mut client_ca_cert.as_bytes())
+        .wrap_err("parsing client CA certificate")?
+    {
+        client_roots
+            .add(&rustls::Certificate(ca_cert))
+            .wrap_err("adding client CA certificate")?;
+    }
+    eyre::ensure!(!client_roots.is_empty(), "no client CA certificate found");
+
+    let config = rustls::ServerConfig::builder()
+        .with_safe_defaults()
+        .with_client_cert_verifier(AllowAnyAuthenticatedClient::new(client_roots).boxed())
+        .with_single_cert(certs, key)
+        .wrap_err("invalid certificate or key")?;
+    Ok(TlsAcceptor::from(Arc::new(config)))
+}
+
+fn handle(
+    state: &dyn OperatorState,
+    readiness: &Readiness,
+    access: Access,
+    request: &Request<Body>,
+) -> Response<Body> {
+    let path = request.uri().path_and_query().map_or("/", |p| p.as_str());
+    let (status, body) = match access == Access::ReadOnly && *request.method() != Method::GET {
+        true => {
+            let error = "read-only, changes require TLS or the Unix domain socket";
+            (StatusCode::FORBIDDEN, json!({ "error": error }))
+        }
+        false => route(state, readiness, request.method(), path),
+    };
+    Response::builder()
+        .status(status)
+        .header(header::CONTENT_TYPE, "application/json")
+        .body(Body::from(body.to_string()))
+        .expect("response should be valid")
+}
+
+fn route(
+    state: &dyn OperatorState,
+    readiness: &Readiness,
+    method: &Method,
+    path_and_query: &str,
+) -> (StatusCode, serde_json::Value) {
+    let (path, query) = path_and_query
+        .split_once('?')
+        .unwrap_or((path_and_query, ""));
+    match (method, path) {
+        (&Method::PUT, "/threads") => return set_active_threads(state, query),
+        (&Method::POST, "/initialization") => return start_initialization(state, query),
+        (&Method::DELETE, "/initialization") => {
+            let cancelled = state.cancel_initialization();
+            return (StatusCode::OK, json!({"cancelled": cancelled}));
+        }
+        (&Method::GET, _) => {}
+        _ => {
+            return (
+                StatusCode::METHOD_NOT_ALLOWED,
+                json!({"error": "only GET is allowed"}),
+            )
+        }
+    }
+    match path {
+        "/status" => {
+            let proving = state.proving_status();
+            let status = json!({
+                "state": if proving.proving.is_empty() { "idle" } else { "proving" },
+                "last_error": proving.last_error,
+                "config": state.config(),
+            });
+            (StatusCode::OK, status)
+        }
+        "/proving" => (StatusCode::OK, json!(state.proving_status())),
+        "/metadata" => match state.metadata() {
+            Ok(metadata) => (StatusCode::OK, json!(metadata)),
+            Err(e) => (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                json!({"error": format!("{e:?}")}),
+            ),
+        },
+        "/threads" => (StatusCode::OK, json!({"active": state.active_threads()})),
+        "/initialization" => (StatusCode::OK, json!(state.initialization_status())),
+        "/healthz" => (StatusCode::OK, json!({"status": "ok"})),
+        "/readyz" => {
+            let reasons = readiness.unready_reasons(state);
+            let (status, mut body) = if reasons.is_empty() {
+                (StatusCode::OK, json!({"status": "ready"}))
+            } else {
+                let body = json!({"status": "not ready", "reasons": reasons});
+                (StatusCode::SERVICE_UNAVAILABLE, body)
+            };
+            if let Some(result) = &*readiness.data_check.lock().unwrap() {
+                body["data_check"] = json!(result);
+            }
+            (status, body)
+        }
+        _ => (StatusCode::NOT_FOUND, json!({"error": "not found"})),
+    }
+}
+
+/// Value of the parameter `name` in the `query`.
+fn query_param<'a>(query: &'a str, name: &str) -> Option<&'a str> {
+    query.split('&').find_map(|param| {
+        param
+            .strip_prefix(name)
+            .and_then(|param| param.strip_prefix('='))
+    })
+}
+
+/// Change the number of active proving threads to the `active` parameter of the `query`.
+fn set_active_threads(state: &dyn OperatorState, query: &str) -> (StatusCode, serde_json::Value) {
+    let active = query_param(query, "active").map(str::parse::<usize>);
+    match active {
+        Some(Ok(threads)) => {
+            state.set_active_threads(threads);
+            (StatusCode::OK, json!({"active": state.active_threads()}))
+        }
+        _ => (
+            StatusCode::BAD_REQUEST,
+            json!({"error": "expected the number of threads in `active` (0 means all)"}),
+        ),
+    }
+}
+
+/// Start initializing the POS data with the parameters of the `query`.
+fn start_initialization(state: &dyn OperatorState, query: &str) -> (StatusCode, serde_json::Value) {
+    let params = match init_params(query) {
+        Ok(params) => params,
+        Err(e) => return (StatusCode::BAD_REQUEST, json!({"error": format!("{e}")})),
+    };
+    match state.start_initialization(params) {
+        Ok(()) => (StatusCode::ACCEPTED, json!(state.initialization_status())),
+        Err(e) => (StatusCode::CONFLICT, json!({"error": format!("{e}")})),
+    }
+}
+
+fn init_params(query: &str) -> eyre::Result<InitParams> {
+    let param =
+        |name| query_param(query, name).ok_or_else(|| eyre::eyre!("missing parameter `{name}`"));
+    let id = |name| -> eyre::Result<[u8; 32]> {
+        hex::decode(param(name)?)
+            .ok()
+            .and_then(|id| id.try_into().ok())
+            .ok_or_else(|| eyre::eyre!("`{name}` must be 32 hex encoded bytes"))
+    };
+    let mut method = match query_param(query, "method") {
+        Some(method) => method.parse()?,
+        None => InitMethod::Cpu,
+    };
+    if let InitMethod::Gpu { provider } = &mut method {
+        if let Some(id) = query_param(query, "provider") {
+            *provider = Some(id.parse().wrap_err("invalid `provider`")?);
+        }
+    }
+    Ok(InitParams {
+        node_id: id("node_id")?,
+        commitment_atx_id: id("commitment_atx_id")?,
+        num_units: param("num_units")?
+            .parse()
+            .wrap_err("invalid `num_units`")?,
+        method,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::AtomicUsize;
+
+    use super::*;
+
+    #[derive(Default)]
+    struct FakeState {
+        proving: ProvingStatus,
+        metadata: Option<PostMetadata>,
+        active_threads: AtomicUsize,
+        initialization: Mutex<InitStatus>,
+    }
+
+    impl OperatorState for FakeState {
+        fn proving_status(&self) -> ProvingStatus {
+            self.proving.clone()
+        }
+
+        fn config(&self) -> serde_json::Value {
+            json!({"threads": 4})
+        }
+
+        fn metadata(&self) -> eyre::Result<PostMetadata> {
+            self.metadata.ok_or_else(|| eyre::eyre!("no POS data"))
+        }
+
+        fn active_threads(&self) -> usize {
+            self.active_threads.load(Ordering::Relaxed)
+        }
+
+        fn set_active_threads(&self, threads: usize) {
+            self.active_threads.store(threads, Ordering::Relaxed);
+        }
+
+        fn start_initialization(&self, params: InitParams) -> eyre::Result<()> {
+            let mut status = self.initialization.lock().unwrap();
+            eyre::ensure!(*status == InitStatus::Idle, "already initialized");
+            *status = InitStatus::Initializing {
+                method: params.method,
+                labels_written: 0,
+                total_labels: params.num_units as u64 * 1024,
+            };
+            Ok(())
+        }
+
+        fn initialization_status(&self) -> InitStatus {
+            self.initialization.lock().unwrap().clone()
+        }
+
+        fn cancel_initialization(&self) -> bool {
+            let mut status = self.initialization.lock().unwrap();
+            let cancelled = matches!(*status, InitStatus::Initializing { .. });
+            if cancelled {
+                *status = InitStatus::Cancelled {
+                    labels_written: 0,
+                    total_labels: 0,
+                };
+            }
+            cancelled
+        }
+    }
+
+    #[test]
+    fn routing() {
+        let state = FakeState {
+            proving: ProvingStatus {
+                proving: vec!["aa".into()],
+                last_error: Some("deadline exceeded".into()),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+
+        let readiness = Readiness::new(false);
+        readiness.set_node_connected(true);
+
+        let (status, body) = route(&state, &readiness, &Method::GET, "/status");
+        assert_eq!(StatusCode::OK, status);
+        assert_eq!(
+            json!({
+                "state": "proving",
+                "last_error": "deadline exceeded",
+                "config": {"threads": 4},
+            }),
+            body
+        );
+
+        let (status, body) = route(&state, &readiness, &Method::GET, "/proving");
+        assert_eq!(StatusCode::OK, status);
+        assert_eq!(json!(["aa"]), body["proving"]);
+
+        let (status, _) = route(&state, &readiness, &Method::GET, "/metadata");
+        assert_eq!(StatusCode::INTERNAL_SERVER_ERROR, status);
+        let (status, _) = route(&state, &readiness, &Method::GET, "/unknown");
+        assert_eq!(StatusCode::NOT_FOUND, status);
+        let (status, _) = route(&state, &readiness, &Method::POST, "/status");
+        assert_eq!(StatusCode::METHOD_NOT_ALLOWED, status);
+    }
+
+    #[test]
+    fn probing_readiness() {
+        let mut state = FakeState::default();
+        let readiness = Readiness::new(true);
+
+        let (status, _) = route(&state, &readiness, &Method::GET, "/healthz");
+        assert_eq!(StatusCode::OK, status);
+        let (status, body) = route(&state, &readiness, &Method::GET, "/readyz");
+        assert_eq!(StatusCode::SERVICE_UNAVAILABLE, status);
+        assert_eq!(3, body["reasons"].as_array().unwrap().len());
+
+        state.metadata = Some(PostMetadata::default());
+        readiness.set_node_connected(true);
+        let (status, body) = route(&state, &readiness, &Method::GET, "/readyz");
+        assert_eq!(StatusCode::SERVICE_UNAVAILABLE, status);
+        assert_eq!(json!(["POS data not checked"]), body["reasons"]);
+
+        readiness.report_data_check(DataCheck {
+            labels_checked: 100,
+            ..Default::default()
+        });
+        readiness.set_data_checked();
+        let (status, body) = route(&state, &readiness, &Method::GET, "/readyz");
+        assert_eq!(StatusCode::OK, status);
+        assert_eq!(100, body["data_check"]["labels_checked"]);
+
+        readiness.set_node_connected(false);
+        let (status, _) = route(&state, &readiness, &Method::GET, "/readyz");
+        assert_eq!(StatusCode::SERVICE_UNAVAILABLE, status);
+    }
+
+    #[test]
+    fn throttling_proving_threads() {
+        let state = FakeState::default();
+        let readiness = Readiness::new(false);
+
+        let (status, body) = route(&state, &readiness, &Method::GET, "/threads");
+        assert_eq!(StatusCode::OK, status);
+        assert_eq!(json!({"active": 0}), body);
+
+        let (status, body) = route(&state, &readiness, &Method::PUT, "/threads?active=2");
+        assert_eq!(StatusCode::OK, status);
+        assert_eq!(json!({"active": 2}), body);
+        assert_eq!(2, state.active_threads());
+
+        let (status, _) = route(&state, &readiness, &Method::PUT, "/threads?active=all");
+        assert_eq!(StatusCode::BAD_REQUEST, status);
+        let (status, _) = route(&state, &readiness, &Method::PUT, "/threads");
+        assert_eq!(StatusCode::BAD_REQUEST, status);
+        let (status, _) = route(&state, &readiness, &Method::PUT, "/status");
+        assert_eq!(StatusCode::METHOD_NOT_ALLOWED, status);
+        assert_eq!(2, state.active_threads());
+
+        // Unauthenticated clients can't change it.
+        let request = |method| {
+            Request::builder()
+                .method(method)
+                .uri("/threads?active=3")
+                .body(Body::empty())
+                .unwrap()
+        };
+        let response = handle(&state, &readiness, Access::ReadOnly, &request(Method::PUT));
+        assert_eq!(StatusCode::FORBIDDEN, response.status());
+        assert_eq!(2, state.active_threads());
+        let response = handle(&state, &readiness, Access::ReadOnly, &request(Method::GET));
+        assert_eq!(StatusCode::OK, response.status());
+        let response = handle(&state, &readiness, Access::Full, &request(Method::PUT));
+        assert_eq!(StatusCode::OK, response.status());
+        assert_eq!(3, state.active_threads());
+    }
+
+    #[test]
+    fn initializing_data() {
+        let state = FakeState::default();
+        let readiness = Readiness::new(false);
+        let node_id = hex::encode([1; 32]);
+        let atx_id = hex::encode([2; 32]);
+
+        let (status, body) = route(&state, &readiness, &Method::GET, "/initialization");
+        assert_eq!(StatusCode::OK, status);
+        assert_eq!(json!({"state": "idle"}), body);
+
+        for query in [
+            format!("node_id={node_id}&commitment_atx_id={atx_id}"),
+            format!("node_id=aa&commitment_atx_id={atx_id}&num_units=4"),
+            format!("node_id={node_id}&commitment_atx_id={atx_id}&num_units=4&method=tpu"),
+        ] {
+            let path = format!("/initialization?{query}");
+            let (status, _) = route(&state, &readiness, &Method::POST, &path);
+            assert_eq!(StatusCode::BAD_REQUEST, status, "{query}");
+        }
+
+        let path = format!(
+            "/initialization?node_id={node_id}&commitment_atx_id={atx_id}&num_units=4&method=gpu&provider=1"
+        );
+        let (status, body) = route(&state, &readiness, &Method::POST, &path);
+        assert_eq!(StatusCode::ACCEPTED, status);
+        assert_eq!(
+            json!({
+                "state": "initializing",
+                "method": {"gpu": {"provider": 1}},
+                "labels_written": 0,
+                "total_labels": 4096,
+            }),
+            body
+        );
+        let (status, _) = route(&state, &readiness, &Method::POST, &path);
+        assert_eq!(StatusCode::CONFLICT, status);
+
+        let (status, body) = route(&state, &readiness, &Method::DELETE, "/initialization");
+        assert_eq!(StatusCode::OK, status);
+        assert_eq!(json!({"cancelled": true}), body);
+        let (_, body) = route(&state, &readiness, &Method::GET, "/initialization");
+        assert_eq!("cancelled", body["state"]);
+        let (_, body) = route(&state, &readiness, &Method::DELETE, "/initialization");
+        assert_eq!(json!({"cancelled": false}), body);
+    }
+
+    #[test]
+    fn initializing_data_requires_authentication() {
+        let state = FakeState::default();
+        let readiness = Readiness::new(false);
+        let request = |method, uri: &str| {
+            Request::builder()
+                .method(method)
+                .uri(uri)
+                .body(Body::empty())
+                .unwrap()
+        };
+        let start = format!(
+            "/initialization?node_id={}&commitment_atx_id={}&num_units=4",
+            hex::encode([1; 32]),
+            hex::encode([2; 32])
+        );
+
+        let response = handle(
+            &state,
+            &readiness,
+            Access::ReadOnly,
+            &request(Method::POST, &start),
+        );
+        assert_eq!(StatusCode::FORBIDDEN, response.status());
+        assert_eq!(InitStatus::Idle, state.initialization_status());
+        let response = handle(
+            &state,
+            &readiness,
+            Access::ReadOnly,
+            &request(Method::GET, "/initialization"),
+        );
+        assert_eq!(StatusCode::OK, response.status());
+
+        let response = handle(
+            &state,
+            &readiness,
+            Access::Full,
+            &request(Method::POST, &start),
+        );
+        assert_eq!(StatusCode::ACCEPTED, response.status());
+        let response = handle(
+            &state,
+            &readiness,
+            Access::ReadOnly,
+            &request(Method::DELETE, "/initialization"),
+        );
+        assert_eq!(StatusCode::FORBIDDEN, response.status());
+        assert!(matches!(
+            state.initialization_status(),
+            InitStatus::Initializing { .. }
+        ));
+    }
+}
@@ -0,0 +1,201 @@
+//! Proving for several identities (pool mode)
+//!
+//! The POS data of many identities often share a directory tree, one directory per identity
+//! (i.e. `/data/post/<node>`). They are found with [discover] (`--pool-dir`) and
+//! proved for by one service each, which are kept in a [Registry] by node ID.
+//!
+//! Connecting to the node, every identity registers on its own stream, which tells
+//! the identity of the challenges. Listening for the node (see [crate::server]),
+//! the node names the identity of the stream in the `x-node-id` metadata (hex encoded),
+//! unless the service proves for a single identity. The connections per identity
+//! are limited (to one by default), so that two nodes can't prove for the same identity.
+
+use std::{
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use eyre::Context;
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+use tonic::Status;
+
+/// gRPC metadata key naming the identity (node ID) of the stream of the node.
+pub const NODE_ID_HEADER: &str = "x-node-id";
+
+/// The directories of POS data in the tree under `root` (including itself), sorted.
+/// The directories of POS data are not searched further.
+pub fn discover(root: &Path) -> eyre::Result<Vec<PathBuf>> {
+    let mut dirs = Vec::new();
+    let mut pending = vec![root.to_path_buf()];
+    while let Some(dir) = pending.pop() {
+        if dir.join(post::metadata::METADATA_FILE_NAME).exists() {
+            dirs.push(dir);
+            continue;
+        }
+        for entry in
+            std::fs::read_dir(&dir).wrap_err_with(|| format!("reading {}", dir.display()))?
+        {
+            let entry = entry?;
+            if entry.file_type()?.is_dir() {
+                pending.push(entry.path());
+            }
+        }
+    }
+    dirs.sort();
+    Ok(dirs)
+}
+
+/// The services of the identities proved for.
+pub struct Registry<S> {
+    identities: Vec<Identity<S>>,
+    max_connections: usize,
+}
+
+struct Identity<S> {
+    /// Unknown before the POS data is initialized.
+    node_id: Option<[u8; 32]>,
+    dir: PathBuf,
+    service: Arc<S>,
+    connections: Arc<Semaphore>,
+}
+
+impl<S> Registry<S> {
+    /// The identities accept up to `max_connections` connections of nodes at a time.
+    pub fn new(max_connections: usize) -> Self {
+        Self {
+            identities: Vec::new(),
+            max_connections: max_connections.max(1),
+        }
+    }
+
+    /// Register the `service` proving for the identity `node_id` with the POS data in `dir`.
+    pub fn register(
+        &mut self,
+        node_id: Option<[u8; 32]>,
+        dir: PathBuf,
+        service: Arc<S>,
+    ) -> eyre::Result<()> {
+        if let Some(identity) = node_id.and_then(|id| self.find(&id)) {
+            eyre::bail!(
+                "identity {} has POS data in both {} and {}",
+                hex::encode(node_id.unwrap()),
+                identity.dir.display(),
+                dir.display()
+            );
+        }
+        self.identities.push(Identity {
+            node_id,
+            dir,
+            service,
+            connections: Arc::new(Semaphore::new(self.max_connections)),
+        });
+        Ok(())
+    }
+
+    fn find(&self, node_id: &[u8; 32]) -> Option<&Identity<S>> {
+        self.identities
+            .iter()
+            .find(|identity| identity.node_id.as_ref() == Some(node_id))
+    }
+
+    /// The service of the identity named by the node (by the only identity if not named)
+    /// and a permit for the connection, held while it lasts.
+    pub fn connect(&self, node_id: Option<&str>) -> Result<(Arc<S>, OwnedSemaphorePermit), Status> {
+        let identity = self.identity(node_id)?;
+        let permit = identity
+            .connections
+            .clone()
+            .try_acquire_owned()
+            .map_err(|_| {
+                Status::resource_exhausted(format!(
+                    "identity in {} is already connected to {} node(s)",
+                    identity.dir.display(),
+                    self.max_connections
+                ))
+            })?;
+        Ok((identity.service.clone(), permit))
+    }
+
+    /// The service of the identity named by the node, without connecting to it.
+    pub fn service(&self, node_id: Option<&str>) -> Result<Arc<S>, Status> {
+        Ok(self.identity(node_id)?.service.clone())
+    }
+
+    fn identity(&self, node_id: Option<&str>) -> Result<&Identity<S>, Status> {
+        Ok(match node_id {
+            Some(node_id) => {
+                let node_id = hex::decode(node_id)
+                    .ok()
+                    .and_then(|id| <[u8; 32]>::try_from(id).ok())
+                    .ok_or_else(|| {
+                        Status::invalid_argument(format!("invalid node ID: {node_id}"))
+                    })?;
+                self.find(&node_id).ok_or_else(|| {
+                    Status::not_found(format!("unknown identity {}", hex::encode(node_id)))
+                })?
+            }
+            None if self.identities.len() == 1 => &self.identities[0],
+            None => {
+                return Err(Status::invalid_argument(format!(
+                    "the identity must be named in `{NODE_ID_HEADER}`"
+                )))
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn discovering_identities() {
+        let root = tempfile::tempdir().unwrap();
+        for dir in ["a", "b/c", "b/c/d", "e"] {
+            std::fs::create_dir_all(root.path().join(dir)).unwrap();
+        }
+        for dir in ["a", "b/c", "b/c/d"] {
+            std::fs::write(
+                root.path()
+                    .join(dir)
+                    .join(post::metadata::METADATA_FILE_NAME),
+                "{}",
+            )
+            .unwrap();
+        }
+        let dirs = discover(root.path()).unwrap();
+        assert_eq!(vec![root.path().join("a"), root.path().join("b/c")], dirs);
+    }
+
+    #[test]
+    fn connecting_to_identities() {
+        let mut registry = Registry::new(1);
+        registry
+            .register(Some([1; 32]), "a".into(), Arc::new("a"))
+            .unwrap();
+        let (service, permit) = registry.connect(None).unwrap();
+        assert_eq!("a", *service);
+        // The identity is connected already
+        let err = registry.connect(Some(&hex::encode([1; 32]))).unwrap_err();
+        assert_eq!(tonic::Code::ResourceExhausted, err.code());
+        drop(permit);
+
+        assert!(registry
+            .register(Some([1; 32]), "b".into(), Arc::new("b"))
+            .is_err());
+        registry
+            .register(Some([2; 32]), "b".into(), Arc::new("b"))
+            .unwrap();
+        let err = registry.connect(None).unwrap_err();
+        assert_eq!(tonic::Code::InvalidArgument, err.code());
+        let (service, _permit) = registry.connect(Some(&hex::encode([2; 32]))).unwrap();
+        assert_eq!("b", *service);
+        let (service, _permit) = registry.connect(Some(&hex::encode([1; 32]))).unwrap();
+        assert_eq!("a", *service);
+
+        let err = registry.connect(Some(&hex::encode([3; 32]))).unwrap_err();
+        assert_eq!(tonic::Code::NotFound, err.code());
+        let err = registry.connect(Some("zz")).unwrap_err();
+        assert_eq!(tonic::Code::InvalidArgument, err.code());
+    }
+}
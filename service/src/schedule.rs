@@ -0,0 +1,134 @@
+//! Time windows the proofs may be generated in
+//!
+//! Operators sharing the machine with other workloads (or paying for power by the hour)
+//! can restrict the proof generation to windows of the day, i.e. `22:00-06:00` (in UTC).
+//! Outside of them, new proof generations are deferred and a running one is paused:
+//! it's stopped (saving a checkpoint if enabled) and resumed once a window opens
+//! and the node asks for the proof again.
+
+use std::{str::FromStr, time::SystemTime};
+
+const MINUTES_PER_DAY: u32 = 24 * 60;
+
+/// A window of the day from `start` until `end` (in minutes since midnight UTC).
+/// It spans midnight if `end` is before `start`, and the whole day if they are equal.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TimeWindow {
+    start: u32,
+    end: u32,
+}
+
+impl TimeWindow {
+    fn contains(&self, minute: u32) -> bool {
+        if self.start <= self.end {
+            self.start == self.end || (self.start..self.end).contains(&minute)
+        } else {
+            minute >= self.start || minute < self.end
+        }
+    }
+}
+
+/// Parse the window from `HH:MM-HH:MM`.
+impl FromStr for TimeWindow {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let parse_time = |time: &str| -> Result<u32, String> {
+            let (hours, minutes) = time
+                .trim()
+                .split_once(':')
+                .ok_or_else(|| format!("invalid time `{time}`, expected HH:MM"))?;
+            let hours: u32 = hours
+                .parse()
+                .map_err(|_| format!("invalid hours `{hours}`"))?;
+            let minutes: u32 = minutes
+                .parse()
+                .map_err(|_| format!("invalid minutes `{minutes}`"))?;
+            if hours >= 24 || minutes >= 60 {
+                return Err(format!("invalid time `{time}`"));
+            }
+            Ok(hours * 60 + minutes)
+        };
+        let (start, end) = s
+            .split_once('-')
+            .ok_or_else(|| format!("invalid window `{s}`, expected HH:MM-HH:MM"))?;
+        Ok(Self {
+            start: parse_time(start)?,
+            end: parse_time(end)?,
+        })
+    }
+}
+
+/// Windows of the day the proofs may be generated in. Any time without windows.
+#[derive(Debug, Clone, Default)]
+pub struct ProvingSchedule {
+    windows: Vec<TimeWindow>,
+}
+
+impl ProvingSchedule {
+    pub fn new(windows: Vec<TimeWindow>) -> Self {
+        Self { windows }
+    }
+
+    /// Whether the proofs may be generated at the time `at`.
+    pub fn allows(&self, at: SystemTime) -> bool {
+        let secs = at
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        let minute = (secs / 60 % MINUTES_PER_DAY as u64) as u32;
+        self.windows.is_empty() || self.windows.iter().any(|w| w.contains(minute))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+
+    fn at(hours: u64, minutes: u64) -> SystemTime {
+        // Some day at midnight UTC
+        let day = SystemTime::UNIX_EPOCH + Duration::from_secs(19_000 * 86_400);
+        day + Duration::from_secs(hours * 3600 + minutes * 60)
+    }
+
+    #[test]
+    fn parsing_windows() {
+        assert_eq!(
+            TimeWindow {
+                start: 22 * 60,
+                end: 6 * 60 + 30
+            },
+            "22:00-06:30".parse().unwrap()
+        );
+        assert!("22:00".parse::<TimeWindow>().is_err());
+        assert!("24:00-06:00".parse::<TimeWindow>().is_err());
+        assert!("22:60-06:00".parse::<TimeWindow>().is_err());
+        assert!("ab:00-06:00".parse::<TimeWindow>().is_err());
+    }
+
+    #[test]
+    fn allowing_proving_in_windows() {
+        assert!(ProvingSchedule::default().allows(at(12, 0)));
+
+        let night = ProvingSchedule::new(vec!["22:00-06:00".parse().unwrap()]);
+        assert!(night.allows(at(23, 0)));
+        assert!(night.allows(at(0, 0)));
+        assert!(night.allows(at(5, 59)));
+        assert!(!night.allows(at(6, 0)));
+        assert!(!night.allows(at(12, 0)));
+
+        let split = ProvingSchedule::new(vec![
+            "01:00-02:00".parse().unwrap(),
+            "13:00-14:00".parse().unwrap(),
+        ]);
+        assert!(split.allows(at(1, 30)));
+        assert!(split.allows(at(13, 0)));
+        assert!(!split.allows(at(2, 0)));
+        assert!(!split.allows(at(12, 59)));
+
+        let all_day = ProvingSchedule::new(vec!["08:00-08:00".parse().unwrap()]);
+        assert!(all_day.allows(at(7, 0)));
+    }
+}
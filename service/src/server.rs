@@ -0,0 +1,138 @@
+//! Serving a node connecting to the service (`--listen`)
+//!
+//! By default, the service connects to the node (see [crate::client::ServiceClient]).
+//! Where the service sits behind a stable address and the nodes come and go,
+//! the [NodeServer] listens instead: the node connects to it and streams its requests,
+//! which are handled the same way. Proving for several identities, the node names
+//! the identity of its stream (see [crate::pool]).
+//!
+//! The node can also query the metadata of the POS data on its own, without a stream,
+//! i.e. to check its compatibility before connecting.
+
+use std::sync::Arc;
+
+use tokio::sync::mpsc;
+use tokio_stream::wrappers::ReceiverStream;
+use tonic::{Request, Response, Status, Streaming};
+
+use crate::{
+    client::{
+        convert_metadata, handle_request,
+        spacemesh_v1::{MetadataRequest, MetadataResponse, NodeRequest, ServiceResponse},
+        PostService,
+    },
+    operator::Readiness,
+    pool::{Registry, NODE_ID_HEADER},
+};
+
+pub mod post_service_v1 {
+    tonic::include_proto!("post_service.v1");
+}
+
+pub use post_service_v1::post_service_server::PostServiceServer;
+
+/// Serves the requests of the nodes connecting to it with the services of the identities.
+pub struct NodeServer<S> {
+    identities: Registry<S>,
+    readiness: Option<Arc<Readiness>>,
+}
+
+impl<S: PostService + Send + Sync + 'static> NodeServer<S> {
+    pub fn new(identities: Registry<S>) -> Self {
+        Self {
+            identities,
+            readiness: None,
+        }
+    }
+
+    /// Report whether a node is connected to the `readiness`.
+    pub fn with_readiness(mut self, readiness: Arc<Readiness>) -> Self {
+        self.readiness = Some(readiness);
+        self
+    }
+
+    pub fn into_service(self) -> PostServiceServer<Self> {
+        PostServiceServer::new(self)
+    }
+}
+
+#[tonic::async_trait]
+impl<S: PostService + Send + Sync + 'static> post_service_v1::post_service_server::PostService
+    for NodeServer<S>
+{
+    type ConnectStream = ReceiverStream<Result<ServiceResponse, Status>>;
+
+    async fn connect(
+        &self,
+        request: Request<Streaming<NodeRequest>>,
+    ) -> Result<Response<Self::ConnectStream>, Status> {
+        let node_id = requested_node_id(&request)?;
+        let (service, permit) = self.identities.connect(node_id)?;
+        log::info!(
+            "node connected from {:?} (identity: {})",
+            request.remote_addr(),
+            node_id.unwrap_or("the only one")
+        );
+        let mut inbound = request.into_inner();
+        let (tx, rx) = mpsc::channel(1);
+        let readiness = self.readiness.clone();
+        if let Some(readiness) = &readiness {
+            readiness.set_node_connected(true);
+        }
+        tokio::spawn(async move {
+            loop {
+                match inbound.message().await {
+                    Ok(Some(request)) => {
+                        // Generating and verifying proofs blocks, off the async runtime.
+                        let service = service.clone();
+                        let response =
+                            tokio::task::spawn_blocking(move || handle_request(&*service, request))
+                                .await;
+                        let Ok(response) = response else {
+                            log::error!("handling the request of the node panicked");
+                            break;
+                        };
+                        if tx.send(Ok(response)).await.is_err() {
+                            break;
+                        }
+                    }
+                    Ok(None) => break,
+                    Err(status) => {
+                        log::info!("node stream failed: {status}");
+                        break;
+                    }
+                }
+            }
+            log::info!("node disconnected");
+            drop(permit);
+            if let Some(readiness) = &readiness {
+                readiness.set_node_connected(false);
+            }
+        });
+        Ok(Response::new(ReceiverStream::new(rx)))
+    }
+
+    async fn metadata(
+        &self,
+        request: Request<MetadataRequest>,
+    ) -> Result<Response<MetadataResponse>, Status> {
+        let service = self.identities.service(requested_node_id(&request)?)?;
+        let metadata = service
+            .get_metadata()
+            .map_err(|e| Status::failed_precondition(format!("loading POS metadata: {e:#}")))?;
+        Ok(Response::new(MetadataResponse {
+            meta: Some(convert_metadata(metadata)),
+        }))
+    }
+}
+
+/// The identity named in the metadata of the `request` (see [crate::pool]).
+fn requested_node_id<T>(request: &Request<T>) -> Result<Option<&str>, Status> {
+    match request.metadata().get(NODE_ID_HEADER) {
+        Some(node_id) => node_id
+            .to_str()
+            .map(Some)
+            .map_err(|_| Status::invalid_argument("invalid node ID")),
+        None => Ok(None),
+    }
+}
@@ -1,17 +1,45 @@
 //! Post Service
 
 use std::{
-    path::PathBuf,
-    sync::{atomic::AtomicBool, Arc, Mutex},
+    collections::VecDeque,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex, MutexGuard, TryLockError},
+    time::{Duration, Instant, SystemTime},
 };
 
 use eyre::Context;
 use post::{
+    cancel::{CancellationToken, Cancelled},
+    encryption::DataKey,
     metadata::{PostMetadata, ProofMetadata},
-    pow::randomx::{PoW, RandomXFlag},
-    prove::Proof,
+    pow::{
+        self,
+        cache::PowCache,
+        randomx::{BackgroundPoW, PoW, RandomXFlag},
+    },
+    prove::{
+        CoreSelection, DeadlineExceeded, JoiningChallenges, LabelScanner, NumaPlacement, Proof,
+        ProofCache, ProvingCheckpoint, ProvingStats, ProvingThrottle, UnitRange,
+    },
+    reader::{ParallelReads, ReaderBackend},
     verification::Verifier,
 };
+use serde::Serialize;
+
+use crate::{
+    data_init::{DataInit, InitParams, InitStatus},
+    logging::ProvingContext,
+    schedule::ProvingSchedule,
+};
+
+/// Number of most recent proofs kept in the [ProofCache].
+const CACHED_PROOFS: usize = 16;
+
+/// Most results of finished proof generations kept until the node requests them.
+const MAX_FINISHED: usize = 64;
+
+/// Challenge of the proof generated by [PostService::benchmark].
+const BENCHMARK_CHALLENGE: [u8; 32] = [0xBE; 32];
 
 #[derive(Debug)]
 pub enum ProofGenState {
@@ -21,8 +49,260 @@ pub enum ProofGenState {
 
 #[derive(Debug)]
 struct ProofGenProcess {
-    handle: std::thread::JoinHandle<eyre::Result<Proof<'static>>>,
-    challenge: Vec<u8>,
+    handle: std::thread::JoinHandle<eyre::Result<Vec<Proof<'static>>>>,
+    /// The challenges it started with, followed by those pushed to `joining`.
+    challenges: Vec<[u8; 32]>,
+    /// Challenges requested while it's running join it.
+    joining: Arc<JoiningChallenges>,
+    stats: Arc<Mutex<ProvingStats>>,
+    /// Cancels this proof generation only (a child of the service's token).
+    cancel: CancellationToken,
+    /// It was cancelled outside of the proving schedule, to be resumed later.
+    paused: bool,
+    watchdog: Option<Watchdog>,
+    /// Times the challenges were retried after stalling.
+    retries: usize,
+}
+
+/// Detects a proof generation making no progress (i.e. on a dead disk or hung IO).
+#[derive(Debug)]
+struct Watchdog {
+    timeout: Duration,
+    /// Retries of a stalled proof generation before failing it.
+    max_retries: usize,
+    /// Passes and labels scanned at the last progress.
+    progress: (u32, u64),
+    since: Instant,
+}
+
+impl Watchdog {
+    /// Whether the proof generation with the `stats` made no progress for the timeout.
+    /// It's only watched while passing over the POS data, not before the first pass
+    /// (i.e. waiting for the turn to prove or for the k2pow).
+    fn stalled(&mut self, stats: &ProvingStats) -> bool {
+        let progress = (stats.passes, stats.labels_scanned);
+        if progress != self.progress {
+            self.progress = progress;
+            self.since = Instant::now();
+        }
+        stats.passes > 0 && self.since.elapsed() >= self.timeout
+    }
+}
+
+#[derive(Debug, Default)]
+struct ProofGeneration {
+    running: Option<ProofGenProcess>,
+    /// Challenges requested while a proof generation is running which couldn't join it
+    /// (i.e. it's finishing or paused). They are all proven together by the next one.
+    queued: Vec<[u8; 32]>,
+    /// Results of finished proof generations until they are requested,
+    /// the oldest first (see [ProofGeneration::finish]).
+    finished: VecDeque<([u8; 32], eyre::Result<Proof<'static>>)>,
+    /// Error of the last failed proof generation.
+    last_error: Option<String>,
+    /// Times the queued challenges were retried after stalling.
+    retries: usize,
+}
+
+/// State of the proof generation reported to the operator (see [crate::operator]).
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct ProvingStatus {
+    /// Challenges (hex) of the running proof generation.
+    pub proving: Vec<String>,
+    /// Challenges (hex) waiting for the running proof generation to finish.
+    pub queued: Vec<String>,
+    /// Challenges (hex) with results the node didn't request yet.
+    pub finished: Vec<String>,
+    /// Progress of the running proof generation.
+    pub stats: Option<ProvingProgress>,
+    /// Error of the last failed proof generation.
+    pub last_error: Option<String>,
+    /// Challenges (hex) of the interrupted proof generation saved in the datadir,
+    /// resumed when the node requests them.
+    pub checkpoint: Vec<String>,
+}
+
+/// Progress of a running proof generation, see [ProvingStats].
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct ProvingProgress {
+    pub passes: u32,
+    pub labels_scanned: u64,
+    pub k2pow_time_s: f64,
+    pub reading_time_s: f64,
+    /// Bytes per second.
+    pub read_throughput: f64,
+}
+
+impl From<&ProvingStats> for ProvingProgress {
+    fn from(stats: &ProvingStats) -> Self {
+        Self {
+            passes: stats.passes,
+            labels_scanned: stats.labels_scanned,
+            k2pow_time_s: stats.k2pow_time.as_secs_f64(),
+            reading_time_s: stats.reading_time.as_secs_f64(),
+            read_throughput: stats.read_throughput(),
+        }
+    }
+}
+
+/// Result of [PostService::benchmark].
+#[derive(Debug, Clone, PartialEq)]
+pub struct ProvingBenchmark {
+    /// Number of labels of the POS data to prove over.
+    pub total_labels: u64,
+    pub labels_scanned: u64,
+    /// Time the first pass waited for its k2pow.
+    pub k2pow_time: Duration,
+    pub reading_time: Duration,
+}
+
+impl ProvingBenchmark {
+    /// Projected duration of a full pass over the POS data: the k2pow
+    /// and reading all labels as fast as the ones scanned.
+    pub fn projected_pass(&self) -> Duration {
+        let reading = match self.labels_scanned {
+            0 => Duration::ZERO,
+            scanned => self
+                .reading_time
+                .mul_f64(self.total_labels as f64 / scanned as f64),
+        };
+        self.k2pow_time + reading
+    }
+}
+
+impl ProofGeneration {
+    /// Collect the results of the running proof generation if it's finished
+    /// or give it up if it stalled.
+    fn collect_finished(&mut self) {
+        self.check_stalled();
+        if !matches!(&self.running, Some(process) if process.handle.is_finished()) {
+            return;
+        }
+        let mut process = self.running.take().unwrap();
+        log::info!(
+            "proof generation is finished, stats: {:?}",
+            process.stats.lock().unwrap()
+        );
+        // The challenges which didn't join it yet are proven by the next one.
+        let left = process.joining.close();
+        process.challenges.retain(|c| !left.contains(c));
+        self.queued.extend(left);
+        let result = match process.handle.join() {
+            Ok(result) => result,
+            Err(err) => {
+                std::panic::resume_unwind(err);
+            }
+        };
+        match result {
+            Err(e) if process.paused && e.is::<Cancelled>() => {
+                log::info!(
+                    "proof generation for challenges {:X?} is paused until the proving schedule allows it",
+                    process.challenges
+                );
+                self.queued.splice(0..0, process.challenges);
+            }
+            Ok(proofs) => {
+                for (challenge, proof) in process.challenges.into_iter().zip(proofs) {
+                    self.finish(challenge, Ok(proof));
+                }
+            }
+            Err(e) => {
+                self.last_error = Some(format!("{e:?}"));
+                for challenge in &process.challenges[1..] {
+                    self.finish(*challenge, Err(copy_error(&e)));
+                }
+                self.finish(process.challenges[0], Err(e));
+            }
+        }
+    }
+
+    /// Keep the result of the proof generation for the `challenge` until it's requested.
+    /// Over [MAX_FINISHED] results, the oldest are dropped (i.e. of challenges
+    /// the node didn't request again).
+    fn finish(&mut self, challenge: [u8; 32], result: eyre::Result<Proof<'static>>) {
+        self.finished.retain(|(c, _)| *c != challenge);
+        self.finished.push_back((challenge, result));
+        while self.finished.len() > MAX_FINISHED {
+            if let Some((challenge, _)) = self.finished.pop_front() {
+                log::warn!(
+                    "dropping unrequested result of proof generation for challenge {challenge:X?}"
+                );
+            }
+        }
+    }
+
+    /// Take the result of the proof generation for the `challenge` (if it's finished).
+    fn take_finished(&mut self, challenge: &[u8; 32]) -> Option<eyre::Result<Proof<'static>>> {
+        let idx = self.finished.iter().position(|(c, _)| c == challenge)?;
+        self.finished.remove(idx).map(|(_, result)| result)
+    }
+
+    /// Cancel the running proof generation if it stalled and leave its thread behind,
+    /// as it might never finish (i.e. blocked reading a dead disk). Its challenges
+    /// are retried by the next proof generation, or fail once out of retries.
+    fn check_stalled(&mut self) {
+        let Some(process) = &mut self.running else {
+            return;
+        };
+        let Some(watchdog) = &mut process.watchdog else {
+            return;
+        };
+        if process.handle.is_finished() || !watchdog.stalled(&process.stats.lock().unwrap()) {
+            return;
+        }
+        let (timeout, max_retries) = (watchdog.timeout, watchdog.max_retries);
+        let process = self.running.take().unwrap();
+        process
+            .cancel
+            .cancel(format!("no progress for {timeout:?}"));
+        if process.retries < max_retries {
+            log::warn!(
+                "proof generation for challenges {:X?} made no progress for {timeout:?}, \
+                retrying ({} of {max_retries})",
+                process.challenges,
+                process.retries + 1,
+            );
+            self.retries = process.retries + 1;
+            self.queued.splice(0..0, process.challenges);
+            return;
+        }
+        let error = format!("proof generation stalled: no progress for {timeout:?}");
+        log::error!("{error} (challenges {:X?})", process.challenges);
+        self.last_error = Some(error.clone());
+        for challenge in process.challenges {
+            self.finish(challenge, Err(eyre::eyre!("{error}")));
+        }
+    }
+}
+
+/// Copy the error of a proof generation for another challenge proven along.
+fn copy_error(e: &eyre::Report) -> eyre::Report {
+    if e.is::<DeadlineExceeded>() {
+        DeadlineExceeded.into()
+    } else if let Some(cancelled) = e.downcast_ref::<Cancelled>() {
+        cancelled.clone().into()
+    } else {
+        eyre::eyre!("{e:?}")
+    }
+}
+
+/// CPU cores to run the proving threads on (see [CoreSelection]).
+#[derive(Debug, Clone, Default)]
+enum Cores {
+    #[default]
+    Any,
+    Only(Vec<usize>),
+    Except(Vec<usize>),
+}
+
+impl Cores {
+    fn selection(&self) -> CoreSelection<'_> {
+        match self {
+            Cores::Any => CoreSelection::Any,
+            Cores::Only(cores) => CoreSelection::Only(cores),
+            Cores::Except(cores) => CoreSelection::Except(cores),
+        }
+    }
 }
 
 pub struct PostService {
@@ -32,10 +312,36 @@ pub struct PostService {
     nonces: usize,
     threads: usize,
     pow_flags: RandomXFlag,
-    proof_generation: Mutex<Option<ProofGenProcess>>,
+    data_key: Option<DataKey>,
+    checkpoint_interval: Option<Duration>,
+    reader: ReaderBackend,
+    read_chunk_size: usize,
+    read_ahead: usize,
+    parallel_reads: Option<Vec<PathBuf>>,
+    label_scanner: Option<Arc<Mutex<dyn LabelScanner>>>,
+    proving_timeout: Option<Duration>,
+    /// Timeout and retries of the watchdog of the proof generation.
+    stall_timeout: Option<(Duration, usize)>,
+    best_effort: bool,
+    cores: Cores,
+    numa: NumaPlacement,
+    memory_budget: Option<usize>,
+    wide_nonce_groups: bool,
+    units: Option<UnitRange>,
+    verify_checksums: bool,
+    pow_prover: Option<Arc<dyn pow::Prover + Send + Sync>>,
+    pow_cache: Option<Arc<PowCache>>,
+    pow_threads: Option<usize>,
+    low_priority_pow: bool,
+    proof_cache: Option<ProofCache>,
+    proving_lock: Option<Arc<Mutex<()>>>,
+    schedule: Option<ProvingSchedule>,
+    throttle: Arc<ProvingThrottle>,
+    proof_generation: Mutex<ProofGeneration>,
+    initialization: DataInit,
 
     verifier: Verifier,
-    stop: Arc<AtomicBool>,
+    stop: CancellationToken,
 }
 
 impl PostService {
@@ -47,67 +353,715 @@ impl PostService {
         threads: usize,
         pow_flags: RandomXFlag,
     ) -> eyre::Result<Self> {
+        let stop = CancellationToken::new();
         Ok(Self {
-            proof_generation: Mutex::new(None),
+            proof_generation: Mutex::default(),
+            initialization: DataInit::new(datadir.clone(), init_cfg, stop.child()),
             datadir,
             cfg,
             init_cfg,
             nonces,
             threads,
             pow_flags,
+            data_key: None,
+            checkpoint_interval: None,
+            reader: ReaderBackend::default(),
+            read_chunk_size: post::reader::DEFAULT_READ_CHUNK_SIZE,
+            read_ahead: post::reader::DEFAULT_READ_AHEAD,
+            parallel_reads: None,
+            label_scanner: None,
+            proving_timeout: None,
+            stall_timeout: None,
+            best_effort: false,
+            cores: Cores::Any,
+            numa: NumaPlacement::Off,
+            memory_budget: None,
+            wide_nonce_groups: false,
+            units: None,
+            verify_checksums: false,
+            pow_prover: None,
+            pow_cache: None,
+            pow_threads: None,
+            low_priority_pow: false,
+            proof_cache: None,
+            proving_lock: None,
+            schedule: None,
+            throttle: Arc::default(),
             verifier: Verifier::new(Box::new(PoW::new(RandomXFlag::get_recommended_flags())?)),
-            stop: Arc::new(AtomicBool::new(false)),
+            stop,
         })
     }
+
+    /// Set the key to decrypt the POS data encrypted at rest.
+    pub fn with_data_key(mut self, key: DataKey) -> Self {
+        self.data_key = Some(key);
+        self
+    }
+
+    /// Save the proving progress periodically to resume the proof generation
+    /// if it's interrupted (i.e. the service is restarted).
+    ///
+    /// A proof generation interrupted by a restart of the service is resumed
+    /// when the node requests its challenges again.
+    pub fn with_checkpoint_interval(mut self, interval: Duration) -> Self {
+        self.checkpoint_interval = Some(interval);
+        let challenges = self.checkpointed_challenges();
+        if !challenges.is_empty() {
+            log::info!(
+                "found proving checkpoint for challenges {challenges:X?}, resuming when they're requested"
+            );
+        }
+        self
+    }
+
+    /// Set the backend used to read the POS data.
+    pub fn with_reader(mut self, reader: ReaderBackend) -> Self {
+        self.reader = reader;
+        self
+    }
+
+    /// Set the size (in bytes) of chunks of POS data read at once
+    /// and how many of them are read ahead of processing them.
+    pub fn with_read_chunks(mut self, chunk_size: usize, read_ahead: usize) -> Self {
+        self.read_chunk_size = chunk_size;
+        self.read_ahead = read_ahead;
+        self
+    }
+
+    /// Read the POS data files residing on different disks in parallel.
+    /// The disks are told apart by the device IDs of the files,
+    /// unless the directories they are mounted at are given.
+    pub fn with_parallel_reads(mut self, disks: Vec<PathBuf>) -> Self {
+        self.parallel_reads = Some(disks);
+        self
+    }
+
+    /// Scan the labels for the proofs with the `scanner` (i.e. on a GPU)
+    /// instead of checking them on the CPU.
+    pub fn with_label_scanner<S: LabelScanner + 'static>(mut self, scanner: S) -> Self {
+        self.label_scanner = Some(Arc::new(Mutex::new(scanner)));
+        self
+    }
+
+    /// Give up the proof generation if it's estimated to not finish within the `timeout`
+    /// (since it was started), failing with [DeadlineExceeded](post::prove::DeadlineExceeded).
+    pub fn with_proving_timeout(mut self, timeout: Duration) -> Self {
+        self.proving_timeout = Some(timeout);
+        self
+    }
+
+    /// Give up a proof generation making no progress over the POS data for the `timeout`
+    /// (i.e. on a dead disk or hung IO), retrying it up to `retries` times before
+    /// reporting the failure to the node. The timeout must be longer than the k2pow
+    /// of a pass, as the passes might wait for it.
+    pub fn with_stall_watchdog(mut self, timeout: Duration, retries: usize) -> Self {
+        self.stall_timeout = Some((timeout, retries));
+        self
+    }
+
+    /// Skip missing or unreadable POS data files when proving, instead of failing.
+    pub fn with_best_effort_proving(mut self) -> Self {
+        self.best_effort = true;
+        self
+    }
+
+    /// Run the proving threads only on the given CPU `cores`.
+    pub fn with_cores(mut self, cores: Vec<usize>) -> Self {
+        self.cores = Cores::Only(cores);
+        self
+    }
+
+    /// Keep the proving threads off the given CPU `cores`.
+    pub fn with_excluded_cores(mut self, cores: Vec<usize>) -> Self {
+        self.cores = Cores::Except(cores);
+        self
+    }
+
+    /// Run the proving threads on the NUMA node of the `placement`.
+    pub fn with_numa_placement(mut self, placement: NumaPlacement) -> Self {
+        self.numa = placement;
+        self
+    }
+
+    /// Bound the memory (in bytes) used to read the POS data and collect
+    /// the indices of labels, spilling them to disk if needed.
+    pub fn with_memory_budget(mut self, budget: usize) -> Self {
+        self.memory_budget = Some(budget);
+        self
+    }
+
+    /// Allow more than 256 nonce groups, their proofs are V2 (see [post::prove::ProofVersion]).
+    pub fn with_wide_nonce_groups(mut self) -> Self {
+        self.wide_nonce_groups = true;
+        self
+    }
+
+    /// Prove with only the `units` of the POS data (i.e. the ones currently available).
+    pub fn with_units(mut self, units: UnitRange) -> Self {
+        self.units = Some(units);
+        self
+    }
+
+    /// Verify the POS data files against the checksums recorded in the metadata
+    /// while proving, failing the proof generation if some are corrupted.
+    pub fn with_checksum_verification(mut self) -> Self {
+        self.verify_checksums = true;
+        self
+    }
+
+    /// Calculate the k2 proofs of work with the `prover` (i.e. a remote one)
+    /// instead of RandomX with the PoW flags.
+    pub fn with_pow_prover(mut self, prover: Arc<dyn pow::Prover + Send + Sync>) -> Self {
+        self.pow_prover = Some(prover);
+        self
+    }
+
+    /// Initialize RandomX with the PoW flags in the background right away and reuse it,
+    /// instead of initializing it for every proof generation. The dataset of the fast mode
+    /// then takes minutes once, and is likely ready by the time a proof is requested.
+    ///
+    /// Replaced by a [with_pow_prover](Self::with_pow_prover) called later.
+    pub fn with_background_pow_init(mut self) -> eyre::Result<Self> {
+        let flags = self.pow_flags;
+        let pow = BackgroundPoW::spawn(move || post::prove::randomx_prover(flags))
+            .wrap_err("spawning RandomX initialization")?;
+        self.pow_prover = Some(Arc::new(pow));
+        Ok(self)
+    }
+
+    /// Calculate the k2 proofs of work on `threads` threads ('0' means one per core)
+    /// instead of the number of the proving threads.
+    pub fn with_pow_threads(mut self, threads: usize) -> Self {
+        self.pow_threads = Some(threads);
+        self
+    }
+
+    /// Run the k2pow threads at the lowest OS scheduling priority, so that they
+    /// don't starve the reading of the POS data and other processes on the machine.
+    pub fn with_low_priority_pow(mut self) -> Self {
+        self.low_priority_pow = true;
+        self
+    }
+
+    /// Cache the k2 proofs of work found in the POS data directory, so that
+    /// they are not calculated again if the proof generation is retried.
+    /// The cached ones are loaded once, here.
+    pub fn with_pow_cache(mut self) -> eyre::Result<Self> {
+        let cache = post::prove::load_pow_cache(&self.datadir)?;
+        self.pow_cache = Some(Arc::new(cache));
+        Ok(self)
+    }
+
+    /// Cache the generated proofs in the `cache`, so that they are not generated again
+    /// if the service restarts before the node got them.
+    pub fn with_proof_cache(mut self, cache: ProofCache) -> Self {
+        self.proof_cache = Some(cache);
+        self
+    }
+
+    /// Generate the proofs one at a time with the other services given the same `lock`
+    /// (i.e. of other identities in this process), so that they don't compete
+    /// for the disks and the CPU. The time waiting for the others counts in the proving timeout.
+    pub fn with_sequential_proving(mut self, lock: Arc<Mutex<()>>) -> Self {
+        self.proving_lock = Some(lock);
+        self
+    }
+
+    /// Generate the proofs only in the time windows of the `schedule`, deferring
+    /// the proof generations requested outside of them and pausing the running one
+    /// when a window closes. A paused proof generation is resumed (from its checkpoint,
+    /// if enabled) once the node requests its proof within a window.
+    pub fn with_proving_schedule(mut self, schedule: ProvingSchedule) -> Self {
+        self.schedule = Some(schedule);
+        self
+    }
+
+    /// Check the labels on only `threads` of the proving threads ('0' means all of them),
+    /// until changed with [PostService::set_active_threads].
+    pub fn with_active_threads(self, threads: usize) -> Self {
+        self.throttle.set_threads(threads);
+        self
+    }
+
+    /// Pick the nonces and threads minimizing the expected proving time on this machine
+    /// with the POS data and settings of the service (see [post::prove::tune]),
+    /// taking the k2pow into account if it's calculated `locally`.
+    pub fn with_auto_tuning(mut self, local_k2pow: bool) -> eyre::Result<Self> {
+        let parallel_reads = match self.parallel_reads.as_deref() {
+            None => ParallelReads::Off,
+            Some([]) => ParallelReads::PerDevice,
+            Some(disks) => ParallelReads::PerDirectory(disks),
+        };
+        let settings = post::prove::ProvingSettings {
+            key: self.data_key.as_ref(),
+            reader: self.reader,
+            read_chunk_size: self.read_chunk_size,
+            read_ahead: self.read_ahead,
+            parallel_reads,
+            units: self.units,
+            wide_nonce_groups: self.wide_nonce_groups,
+            pow_threads: self.pow_threads,
+            ..Default::default()
+        };
+        let pow_flags = local_k2pow.then_some(self.pow_flags);
+        let tuning = post::prove::tune(&self.datadir, &self.cfg, &settings, pow_flags)
+            .wrap_err("tuning nonces and threads")?;
+        self.nonces = tuning.nonces;
+        self.threads = tuning.threads;
+        Ok(self)
+    }
+
+    /// Benchmark the k2pow with the PoW flags on this machine for `duration`
+    /// and estimate how long the proofs of work of a pass over the POS data take.
+    pub fn estimate_k2pow_duration(&self, duration: Duration) -> eyre::Result<Duration> {
+        let metadata = post::metadata::load(&self.datadir).wrap_err("loading metadata")?;
+        let params = post::prove::ProvingParams::new(&metadata, &self.cfg)?;
+        let benchmark = PoW::new(self.pow_flags)?.benchmark(duration)?;
+        log::info!(
+            "k2pow hashrate with flags {:?}: {:.0} hashes/s",
+            benchmark.flags,
+            benchmark.hashrate()
+        );
+        let nonce_groups = self.nonces.div_ceil(16) as u32;
+        Ok(benchmark
+            .expected_duration(&params.pow_difficulty)
+            .saturating_mul(nonce_groups))
+    }
+
+    /// Load the proof of the `challenge` from the proof cache, if it's valid.
+    /// Invalid cached proofs are removed.
+    fn load_cached_proof(
+        &self,
+        cache: &ProofCache,
+        challenge: &[u8; 32],
+    ) -> Option<Proof<'static>> {
+        let metadata = match post::metadata::load(&self.datadir) {
+            Ok(metadata) => metadata,
+            Err(e) => {
+                log::warn!("not looking for a cached proof: {e:?}");
+                return None;
+            }
+        };
+        let result = cache.load(challenge, &metadata).and_then(|proof| {
+            let Some(proof) = proof else {
+                return Ok(None);
+            };
+            let proof_metadata = ProofMetadata::new(metadata, *challenge);
+            self.verifier
+                .verify(&proof, &proof_metadata, &self.cfg, &self.init_cfg)
+                .wrap_err("verifying cached proof")?;
+            Ok(Some(proof))
+        });
+        match result {
+            Ok(proof) => proof,
+            Err(e) => {
+                log::warn!("ignoring invalid cached proof: {e:?}");
+                if let Err(e) = cache.remove(challenge, &metadata) {
+                    log::warn!("failed to remove invalid cached proof: {e:?}");
+                }
+                None
+            }
+        }
+    }
 }
 
-impl crate::client::PostService for PostService {
-    fn gen_proof(&self, challenge: Vec<u8>) -> eyre::Result<ProofGenState> {
+/// Cache the `proofs` of the `challenges` in the `cache`, keeping only the most recent ones.
+/// Lock the `lock` shared by the services proving sequentially,
+/// unless the proof generation is cancelled while waiting.
+fn wait_for_turn<'a>(
+    lock: &'a Mutex<()>,
+    stop: &CancellationToken,
+) -> Result<MutexGuard<'a, ()>, Cancelled> {
+    let mut waiting = false;
+    loop {
+        match lock.try_lock() {
+            Ok(guard) => return Ok(guard),
+            // A panic of another proof generation doesn't matter, the lock guards nothing.
+            Err(TryLockError::Poisoned(e)) => return Ok(e.into_inner()),
+            Err(TryLockError::WouldBlock) => {
+                if !waiting {
+                    log::info!("waiting for the proof generation of another identity to finish");
+                    waiting = true;
+                }
+                stop.check()?;
+                std::thread::sleep(Duration::from_millis(100));
+            }
+        }
+    }
+}
+
+fn cache_proofs(cache: &ProofCache, datadir: &Path, challenges: &[[u8; 32]], proofs: &[Proof]) {
+    let metadata = match post::metadata::load(datadir) {
+        Ok(metadata) => metadata,
+        Err(e) => {
+            log::warn!("failed to cache proofs: {e:?}");
+            return;
+        }
+    };
+    for (challenge, proof) in challenges.iter().zip(proofs) {
+        if let Err(e) = cache.store(challenge, &metadata, proof) {
+            log::warn!("failed to cache proof for challenge {challenge:X?}: {e:?}");
+        }
+    }
+    if let Err(e) = cache.prune(CACHED_PROOFS) {
+        log::warn!("failed to prune proof cache: {e:?}");
+    }
+}
+
+impl PostService {
+    /// Report the state of the proof generation.
+    pub fn proving_status(&self) -> ProvingStatus {
         let mut proof_gen = self.proof_generation.lock().unwrap();
-        if let Some(process) = proof_gen.as_mut() {
-            eyre::ensure!(
-                process.challenge == challenge,
-                 "proof generation is in progress for a different challenge (current: {:X?}, requested: {:X?})", process.challenge, challenge,
-                );
+        proof_gen.collect_finished();
+        let (proving, stats) = match &proof_gen.running {
+            Some(process) => (
+                process.challenges.iter().map(hex::encode).collect(),
+                Some(ProvingProgress::from(&*process.stats.lock().unwrap())),
+            ),
+            None => (Vec::new(), None),
+        };
+        ProvingStatus {
+            proving,
+            queued: proof_gen.queued.iter().map(hex::encode).collect(),
+            finished: proof_gen
+                .finished
+                .iter()
+                .map(|(challenge, _)| hex::encode(challenge))
+                .collect(),
+            stats,
+            last_error: proof_gen.last_error.clone(),
+            checkpoint: self
+                .checkpointed_challenges()
+                .iter()
+                .map(hex::encode)
+                .collect(),
+        }
+    }
 
-            if process.handle.is_finished() {
-                log::info!("proof generation is finished");
-                let result = match proof_gen.take().unwrap().handle.join() {
-                    Ok(result) => result,
-                    Err(err) => {
-                        std::panic::resume_unwind(err);
-                    }
-                };
+    /// The challenges of the proving checkpoint in the datadir (if checkpointing is enabled).
+    fn checkpointed_challenges(&self) -> Vec<[u8; 32]> {
+        if self.checkpoint_interval.is_none() {
+            return Vec::new();
+        }
+        match ProvingCheckpoint::load(&self.datadir) {
+            Ok(checkpoint) => checkpoint
+                .map(|c| c.challenges.iter().map(|c| c.challenge).collect())
+                .unwrap_or_default(),
+            Err(e) => {
+                log::warn!("failed to load proving checkpoint: {e:?}");
+                Vec::new()
+            }
+        }
+    }
 
-                match result {
-                    Ok(proof) => {
-                        return Ok(ProofGenState::Finished { proof });
-                    }
-                    Err(e) => {
-                        return Err(e);
-                    }
-                }
+    /// Report the configuration of the service.
+    pub fn config(&self) -> serde_json::Value {
+        serde_json::json!({
+            "datadir": self.datadir,
+            "k1": self.cfg.k1,
+            "k2": self.cfg.k2,
+            "k3": self.cfg.k3,
+            "pow_difficulty": hex::encode(self.cfg.pow_difficulty),
+            "labels_per_unit": self.init_cfg.labels_per_unit,
+            "scrypt": {
+                "n": self.init_cfg.scrypt.n,
+                "r": self.init_cfg.scrypt.r,
+                "p": self.init_cfg.scrypt.p,
+            },
+            "nonces": self.nonces,
+            "threads": self.threads,
+            "active_threads": self.throttle.threads(),
+            "pow_flags": format!("{:?}", self.pow_flags),
+            "pow_threads": self.pow_threads,
+            "remote_or_shared_pow": self.pow_prover.is_some(),
+            "reader": format!("{:?}", self.reader),
+            "proving_timeout_s": self.proving_timeout.map(|t| t.as_secs()),
+            "stall_timeout_s": self.stall_timeout.map(|(t, _)| t.as_secs()),
+            "best_effort": self.best_effort,
+            "memory_budget": self.memory_budget,
+            "wide_nonce_groups": self.wide_nonce_groups,
+            "verify_checksums": self.verify_checksums,
+            "encrypted": self.data_key.is_some(),
+        })
+    }
+
+    /// Check the labels on only `threads` of the proving threads ('0' means all of them),
+    /// applied to the running proof generation right away, i.e. to leave the CPU
+    /// to other work on the machine for a while.
+    pub fn set_active_threads(&self, threads: usize) {
+        log::info!(
+            "proving on {} threads",
+            if threads == 0 {
+                "all".to_string()
             } else {
-                log::info!("proof generation in progress");
-                return Ok(ProofGenState::InProgress);
+                threads.to_string()
             }
+        );
+        self.throttle.set_threads(threads);
+    }
+
+    /// The number of the proving threads checking the labels ('0' means all of them).
+    pub fn active_threads(&self) -> usize {
+        self.throttle.threads()
+    }
+
+    /// Start initializing the POS data in the background (see [crate::data_init]),
+    /// unless a proof is being generated.
+    pub fn start_initialization(&self, params: InitParams) -> eyre::Result<()> {
+        let proof_gen = self.proof_generation.lock().unwrap();
+        eyre::ensure!(
+            proof_gen.running.is_none(),
+            "a proof is being generated, the POS data can't be initialized"
+        );
+        self.initialization.start(params)
+    }
+
+    pub fn initialization_status(&self) -> InitStatus {
+        self.initialization.status()
+    }
+
+    /// Cancel initializing the POS data, returns whether it was being initialized.
+    pub fn cancel_initialization(&self) -> bool {
+        self.initialization.cancel()
+    }
+
+    /// Abort the running proof generation, including the k2pow it waits for,
+    /// i.e. when the node doesn't need its proofs anymore.
+    /// Its challenges then fail with [Cancelled]. Returns `false` if none was running.
+    pub fn abort_proof_generation(&self, reason: &str) -> bool {
+        let proof_gen = self.proof_generation.lock().unwrap();
+        match &proof_gen.running {
+            Some(process) => {
+                log::info!(
+                    "aborting proof generation for challenges {:X?}: {reason}",
+                    process.challenges
+                );
+                process.cancel.cancel(reason);
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+impl PostService {
+    /// Generate a proof with the settings of the service until `percent` of the POS data
+    /// is read, to project how long a full pass takes (see [ProvingBenchmark::projected_pass]).
+    ///
+    /// The proving checkpoint, proof cache and schedule are not used.
+    pub fn benchmark(mut self, percent: f64) -> eyre::Result<ProvingBenchmark> {
+        self.checkpoint_interval = None;
+        self.proof_cache = None;
+        self.schedule = None;
+        let metadata = self.get_metadata()?;
+        let units = self.units.map_or(metadata.num_units, |units| units.count);
+        let total_labels = units as u64 * metadata.labels_per_unit;
+        let target = (total_labels as f64 * percent / 100.0).ceil() as u64;
+
+        crate::client::PostService::gen_proof(&self, BENCHMARK_CHALLENGE.to_vec())?;
+        let stats = loop {
+            std::thread::sleep(Duration::from_millis(100));
+            let proof_gen = self.proof_generation.lock().unwrap();
+            let process = proof_gen
+                .running
+                .as_ref()
+                .expect("benchmarked proof generation should be running");
+            let stats = process.stats.lock().unwrap().clone();
+            if stats.labels_scanned >= target || process.handle.is_finished() {
+                break stats;
+            }
+        };
+        self.abort_proof_generation("benchmark is finished");
+        let process = self
+            .proof_generation
+            .lock()
+            .unwrap()
+            .running
+            .take()
+            .unwrap();
+        match process.handle.join() {
+            Ok(Err(e)) if !e.is::<Cancelled>() => return Err(e.wrap_err("benchmarking proving")),
+            Ok(_) => {}
+            Err(err) => std::panic::resume_unwind(err),
         }
 
+        Ok(ProvingBenchmark {
+            total_labels,
+            labels_scanned: stats.labels_scanned,
+            k2pow_time: stats.k2pow_time,
+            reading_time: stats.reading_time,
+        })
+    }
+}
+
+impl crate::client::PostService for PostService {
+    fn gen_proof(&self, challenge: Vec<u8>) -> eyre::Result<ProofGenState> {
         let ch: [u8; 32] = challenge
             .as_slice()
             .try_into()
             .map_err(|_| eyre::eyre!("invalid challenge format"))?;
-        log::info!("starting proof generation for challenge {ch:X?}");
+
+        let mut guard = self.proof_generation.lock().unwrap();
+        let proof_gen = &mut *guard;
+        proof_gen.collect_finished();
+        if let Some(result) = proof_gen.take_finished(&ch) {
+            return result.map(|proof| ProofGenState::Finished { proof });
+        }
+        eyre::ensure!(
+            !self.initialization.is_running(),
+            "the POS data is being initialized"
+        );
+        if let Some(cache) = &self.proof_cache {
+            if let Some(proof) = self.load_cached_proof(cache, &ch) {
+                log::info!("found cached proof for challenge {ch:X?}");
+                return Ok(ProofGenState::Finished { proof });
+            }
+        }
+
+        let allowed = self
+            .schedule
+            .as_ref()
+            .map_or(true, |schedule| schedule.allows(SystemTime::now()));
+        if !allowed {
+            match proof_gen.running.as_mut() {
+                Some(process) if !process.paused => {
+                    log::info!(
+                        "pausing proof generation for challenges {:X?} outside of the proving schedule",
+                        process.challenges
+                    );
+                    process
+                        .cancel
+                        .cancel("paused outside of the proving schedule");
+                    process.paused = true;
+                }
+                Some(_) => {}
+                None => {
+                    if !proof_gen.queued.contains(&ch) {
+                        log::info!(
+                            "deferring proof generation for challenge {ch:X?} until the proving schedule allows it"
+                        );
+                        proof_gen.queued.push(ch);
+                    }
+                    return Ok(ProofGenState::InProgress);
+                }
+            }
+        }
+
+        if let Some(process) = proof_gen.running.as_mut() {
+            if !process.challenges.contains(&ch) && !proof_gen.queued.contains(&ch) {
+                if process.joining.push(ch) {
+                    log::info!("adding challenge {ch:X?} to the running proof generation");
+                    process.challenges.push(ch);
+                } else {
+                    log::info!("queueing proof generation for challenge {ch:X?}");
+                    proof_gen.queued.push(ch);
+                }
+            }
+            log::info!("proof generation in progress");
+            log::debug!("proving stats: {:?}", process.stats.lock().unwrap());
+            return Ok(ProofGenState::InProgress);
+        }
+
+        let mut challenges = std::mem::take(&mut proof_gen.queued);
+        if !challenges.contains(&ch) {
+            challenges.push(ch);
+        }
+        log::info!("starting proof generation for challenges {challenges:X?}");
         let pow_flags = self.pow_flags;
         let cfg = self.cfg;
         let datadir = self.datadir.clone();
         let nonces = self.nonces;
         let threads = self.threads;
-        let stop = self.stop.clone();
-        *proof_gen = Some(ProofGenProcess {
-            challenge,
+        let stop = self.stop.child();
+        let data_key = self.data_key.clone();
+        let checkpoint_interval = self.checkpoint_interval;
+        let reader = self.reader;
+        let read_chunk_size = self.read_chunk_size;
+        let read_ahead = self.read_ahead;
+        let parallel_reads = self.parallel_reads.clone();
+        let label_scanner = self.label_scanner.clone();
+        let deadline = self.proving_timeout.map(|timeout| Instant::now() + timeout);
+        let best_effort = self.best_effort;
+        let cores = self.cores.clone();
+        let numa = self.numa;
+        let memory_budget = self.memory_budget;
+        let wide_nonce_groups = self.wide_nonce_groups;
+        let units = self.units;
+        let verify_checksums = self.verify_checksums;
+        let pow_prover = self.pow_prover.clone();
+        let pow_cache = self.pow_cache.clone();
+        let pow_threads = self.pow_threads;
+        let low_priority_pow = self.low_priority_pow;
+        let proof_cache = self.proof_cache.clone();
+        let proving_lock = self.proving_lock.clone();
+        let throttle = self.throttle.clone();
+        let stats = Arc::new(Mutex::new(ProvingStats::default()));
+        let joining = Arc::new(JoiningChallenges::default());
+        proof_gen.running = Some(ProofGenProcess {
+            challenges: challenges.clone(),
+            joining: joining.clone(),
+            stats: stats.clone(),
+            cancel: stop.clone(),
+            paused: false,
+            watchdog: self.stall_timeout.map(|(timeout, max_retries)| Watchdog {
+                timeout,
+                max_retries,
+                progress: (0, 0),
+                since: Instant::now(),
+            }),
+            retries: std::mem::take(&mut proof_gen.retries),
             handle: std::thread::spawn(move || {
-                post::prove::generate_proof(&datadir, &ch, cfg, nonces, threads, pow_flags, stop)
+                let _turn = match &proving_lock {
+                    Some(lock) => Some(wait_for_turn(lock, &stop)?),
+                    None => None,
+                };
+                let _logging = ProvingContext::enter(&challenges, stats.clone());
+                let parallel_reads = match parallel_reads.as_deref() {
+                    None => ParallelReads::Off,
+                    Some([]) => ParallelReads::PerDevice,
+                    Some(disks) => ParallelReads::PerDirectory(disks),
+                };
+                let settings = post::prove::ProvingSettings {
+                    key: data_key.as_ref(),
+                    checkpoint_interval,
+                    reader,
+                    read_chunk_size,
+                    read_ahead,
+                    parallel_reads,
+                    scanner: label_scanner.as_deref(),
+                    deadline,
+                    best_effort,
+                    stats: Some(&*stats),
+                    source: None,
+                    cores: cores.selection(),
+                    numa,
+                    memory_budget,
+                    wide_nonce_groups,
+                    index_hook: None,
+                    units,
+                    verify_checksums,
+                    pow_prover: pow_prover.as_ref(),
+                    pow_cache: pow_cache.as_ref(),
+                    pow_threads,
+                    low_priority_pow,
+                    throttle: Some(&throttle),
+                    joining: Some(&joining),
+                };
+                let proofs = post::prove::generate_proofs_with_settings(
+                    &datadir,
+                    &challenges,
+                    cfg,
+                    nonces,
+                    threads,
+                    pow_flags,
+                    stop,
+                    &settings,
+                )?;
+                if let Some(cache) = proof_cache {
+                    let challenges = [challenges, joining.joined()].concat();
+                    cache_proofs(&cache, &datadir, &challenges, &proofs);
+                }
+                Ok(proofs)
             }),
         });
 
@@ -128,9 +1082,9 @@ impl crate::client::PostService for PostService {
 impl Drop for PostService {
     fn drop(&mut self) {
         log::info!("shutting down post service");
-        if let Some(process) = self.proof_generation.lock().unwrap().take() {
+        if let Some(process) = self.proof_generation.lock().unwrap().running.take() {
             log::debug!("killing proof generation process");
-            self.stop.store(true, std::sync::atomic::Ordering::Relaxed);
+            self.stop.cancel("post service is shutting down");
             let _ = process.handle.join().unwrap();
             log::debug!("proof generation process exited");
         }
@@ -0,0 +1,177 @@
+//! Remote verification of proofs
+//!
+//! Verifying proofs takes RandomX (2 GiB of memory in the fast mode) and a lot of CPU
+//! when many proofs arrive at once. The [VerificationServer] runs on a dedicated machine
+//! and verifies the proofs for nodes, on a [VerifierPool] limiting the number of proofs
+//! verified at the same time.
+
+use std::{borrow::Cow, sync::Arc};
+
+use post::{
+    config::{InitConfig, ProofConfig},
+    metadata::ProofMetadata,
+    prove::{Proof, ProofVersion},
+    verification::{Error, QueueFull, VerifierPool},
+};
+use tonic::{Request, Response, Status};
+
+use verification_v1::{VerifyRequest, VerifyResponse};
+
+pub mod verification_v1 {
+    tonic::include_proto!("verification.v1");
+}
+
+pub use verification_v1::verification_service_server::VerificationServiceServer;
+
+/// Serves verification requests with a [VerifierPool],
+/// for the POST configuration of the network it was created with.
+///
+/// Requests are rejected with `RESOURCE_EXHAUSTED` if the queue of the pool is full.
+pub struct VerificationServer {
+    pool: Arc<VerifierPool>,
+    cfg: ProofConfig,
+    init_cfg: InitConfig,
+}
+
+impl VerificationServer {
+    pub fn new(pool: VerifierPool, cfg: ProofConfig, init_cfg: InitConfig) -> Self {
+        Self {
+            pool: Arc::new(pool),
+            cfg,
+            init_cfg,
+        }
+    }
+
+    pub fn into_service(self) -> VerificationServiceServer<Self> {
+        VerificationServiceServer::new(self)
+    }
+}
+
+fn parse_request(request: VerifyRequest) -> Result<(Proof<'static>, ProofMetadata), Status> {
+    let proof = request
+        .proof
+        .ok_or_else(|| Status::invalid_argument("missing proof"))?;
+    let metadata = request
+        .metadata
+        .ok_or_else(|| Status::invalid_argument("missing metadata"))?;
+    let bytes32 = |bytes: Vec<u8>, what: &str| -> Result<[u8; 32], Status> {
+        bytes
+            .try_into()
+            .map_err(|_| Status::invalid_argument(format!("{what} must be 32 bytes")))
+    };
+    Ok((
+        Proof {
+            // Not in the request, implied by the nonce group.
+            version: ProofVersion::of_nonce_group(proof.nonce / 16),
+            nonce: proof.nonce,
+            indices: Cow::Owned(proof.indices),
+            pow: proof.pow,
+        },
+        ProofMetadata {
+            node_id: bytes32(metadata.node_id, "node ID")?,
+            commitment_atx_id: bytes32(metadata.commitment_atx_id, "commitment ATX ID")?,
+            challenge: bytes32(metadata.challenge, "challenge")?,
+            num_units: metadata.num_units,
+        },
+    ))
+}
+
+fn response(result: Result<(), Error>) -> VerifyResponse {
+    let err = match result {
+        Ok(()) => {
+            return VerifyResponse {
+                valid: true,
+                ..Default::default()
+            }
+        }
+        Err(err) => err,
+    };
+    let (invalid_index, invalid_index_position) = match &err {
+        Error::InvalidMsb {
+            index, position, ..
+        }
+        | Error::InvalidLsb {
+            index, position, ..
+        } => (Some(*index), Some(*position as u32)),
+        _ => (None, None),
+    };
+    VerifyResponse {
+        valid: false,
+        error: err.to_string(),
+        invalid_index,
+        invalid_index_position,
+    }
+}
+
+#[tonic::async_trait]
+impl verification_v1::verification_service_server::VerificationService for VerificationServer {
+    async fn verify(
+        &self,
+        request: Request<VerifyRequest>,
+    ) -> Result<Response<VerifyResponse>, Status> {
+        let (proof, metadata) = parse_request(request.into_inner())?;
+        let node_id = hex::encode(metadata.node_id);
+        let pending = self
+            .pool
+            .try_submit(proof, metadata, self.cfg, self.init_cfg)
+            .map_err(|QueueFull| Status::resource_exhausted("too many proofs to verify"))?;
+        let result = tokio::task::spawn_blocking(move || pending.wait())
+            .await
+            .map_err(|e| Status::internal(e.to_string()))?;
+        match &result {
+            Ok(()) => log::debug!("proof of {node_id} is valid"),
+            Err(err) => log::info!("proof of {node_id} is invalid: {err}"),
+        }
+        Ok(Response::new(response(result)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use verification_v1::{Proof, ProofMetadata};
+
+    #[test]
+    fn parsing_request() {
+        let request = VerifyRequest {
+            proof: Some(Proof {
+                nonce: 1,
+                indices: vec![1, 2, 3],
+                pow: 7,
+            }),
+            metadata: Some(ProofMetadata {
+                node_id: vec![1; 32],
+                commitment_atx_id: vec![2; 32],
+                challenge: vec![3; 32],
+                num_units: 4,
+            }),
+        };
+        let (proof, metadata) = parse_request(request.clone()).unwrap();
+        assert_eq!((1, 7), (proof.nonce, proof.pow));
+        assert_eq!([1, 2, 3], proof.indices.as_ref());
+        assert_eq!([3; 32], metadata.challenge);
+
+        for invalid in [
+            VerifyRequest {
+                proof: None,
+                ..request.clone()
+            },
+            VerifyRequest {
+                metadata: None,
+                ..request.clone()
+            },
+            VerifyRequest {
+                metadata: Some(ProofMetadata {
+                    node_id: vec![1; 31],
+                    ..request.metadata.clone().unwrap()
+                }),
+                ..request.clone()
+            },
+        ] {
+            assert_eq!(
+                tonic::Code::InvalidArgument,
+                parse_request(invalid).err().unwrap().code()
+            );
+        }
+    }
+}
@@ -118,7 +118,12 @@ impl TestServer {
     where
         S: PostService,
     {
-        ServiceClient::new(format!("http://{}", self.addr), None, service).unwrap()
+        ServiceClient::new(self.address(), None, service).unwrap()
+    }
+
+    /// Address of the server to connect the service clients to.
+    pub fn address(&self) -> String {
+        format!("http://{}", self.addr)
     }
 
     pub async fn generate_proof(
@@ -9,7 +9,7 @@ use tokio::sync::oneshot;
 use post::{
     initialize::{CpuInitializer, Initialize},
     metadata::PostMetadata,
-    prove::Proof,
+    prove::{Proof, ProofVersion},
 };
 use post_service::{
     client::{
@@ -106,6 +106,7 @@ async fn test_gen_proof_finished() {
         assert_eq!(c.as_slice(), challenge);
         Ok(ProofGenState::Finished {
             proof: Proof {
+                version: ProofVersion::V1,
                 nonce: 1,
                 indices: Cow::Owned(indices.to_vec()),
                 pow: 7,
@@ -282,3 +283,22 @@ async fn test_get_metadata(#[case] vrf_difficulty: Option<[u8; 32]>) {
     client_handle.abort();
     let _ = client_handle.await;
 }
+
+#[tokio::test]
+async fn failing_over_to_next_node() {
+    let mut test_server = TestServer::new().await;
+    // Nothing listens on the first node
+    let unreachable = std::net::TcpListener::bind("[::1]:0").unwrap();
+    let address = format!("http://{}", unreachable.local_addr().unwrap());
+    drop(unreachable);
+
+    let client = post_service::client::ServiceClient::new(address, None, MockPostService::new())
+        .unwrap()
+        .with_failover([test_server.address()], None, 2)
+        .unwrap();
+    let client_handle = tokio::spawn(client.run(None, std::time::Duration::from_millis(10)));
+
+    test_server.connected.recv().await.unwrap();
+    client_handle.abort();
+    let _ = client_handle.await;
+}
@@ -0,0 +1,94 @@
+use post::{
+    config::ScryptParams,
+    initialize::{CpuInitializer, Initialize},
+    metadata::PostMetadata,
+};
+use post_service::initialization::{InitializationServer, RemoteInitializer};
+use tokio::net::TcpListener;
+use tokio_stream::wrappers::TcpListenerStream;
+use tonic::transport::Server;
+
+/// Start the server on the runtime the remote initializer blocks on.
+fn start_server(runtime: &tokio::runtime::Runtime, scrypt: ScryptParams) -> String {
+    let server = InitializationServer::new(Box::new(CpuInitializer::new(scrypt)), scrypt)
+        .with_chunk_size(16 * 100);
+    let listener = runtime.block_on(TcpListener::bind("[::1]:0")).unwrap();
+    let addr = listener.local_addr().unwrap();
+    runtime.spawn(
+        Server::builder()
+            .add_service(server.into_service())
+            .serve_with_incoming(TcpListenerStream::new(listener)),
+    );
+    format!("http://{addr}")
+}
+
+#[test]
+fn remote_initialization() {
+    let runtime = tokio::runtime::Runtime::new().unwrap();
+    let scrypt = ScryptParams::new(2, 1, 1);
+    let address = start_server(&runtime, scrypt);
+
+    let mut remote = RemoteInitializer::new(address, scrypt, runtime.handle().clone()).unwrap();
+    let mut local = CpuInitializer::new(scrypt);
+    let commitment = [7u8; 32];
+    let difficulty = Some([0x0F; 32]);
+
+    let mut remote_labels = Vec::new();
+    let remote_nonce = remote
+        .initialize_to(&mut remote_labels, &commitment, 50..1050, difficulty)
+        .unwrap();
+    let mut local_labels = Vec::new();
+    let local_nonce = local
+        .initialize_to(&mut local_labels, &commitment, 50..1050, difficulty)
+        .unwrap();
+
+    assert_eq!(1000 * 16, remote_labels.len());
+    assert_eq!(local_labels, remote_labels);
+    assert!(remote_nonce.is_some());
+    assert_eq!(local_nonce, remote_nonce);
+}
+
+#[test]
+fn remote_initialization_into_files() {
+    let runtime = tokio::runtime::Runtime::new().unwrap();
+    let scrypt = ScryptParams::new(2, 1, 1);
+    let address = start_server(&runtime, scrypt);
+
+    let remote_dir = tempfile::tempdir().unwrap();
+    let local_dir = tempfile::tempdir().unwrap();
+    let init = |initializer: &mut dyn Initialize, dir: &std::path::Path| -> PostMetadata {
+        initializer
+            .initialize(dir, &[1; 32], &[2; 32], 200, 3, 250, Some([0x0F; 32]))
+            .unwrap()
+    };
+    let remote_metadata = init(
+        &mut RemoteInitializer::new(address, scrypt, runtime.handle().clone()).unwrap(),
+        remote_dir.path(),
+    );
+    let local_metadata = init(&mut CpuInitializer::new(scrypt), local_dir.path());
+
+    assert_eq!(local_metadata.nonce, remote_metadata.nonce);
+    for idx in 0..remote_metadata.num_files() {
+        let name = remote_metadata.file_name(idx);
+        assert_eq!(
+            std::fs::read(local_dir.path().join(&name)).unwrap(),
+            std::fs::read(remote_dir.path().join(&name)).unwrap(),
+        );
+    }
+}
+
+#[test]
+fn rejects_mismatched_scrypt_params() {
+    let runtime = tokio::runtime::Runtime::new().unwrap();
+    let address = start_server(&runtime, ScryptParams::new(2, 1, 1));
+
+    let mut remote = RemoteInitializer::new(
+        address,
+        ScryptParams::new(4, 1, 1),
+        runtime.handle().clone(),
+    )
+    .unwrap();
+    remote
+        .initialize_to(&mut Vec::new(), &[0; 32], 0..10, None)
+        .unwrap_err();
+}
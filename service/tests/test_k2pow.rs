@@ -0,0 +1,129 @@
+use std::{
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use post::{
+    cancel::CancellationToken,
+    pow::{self, Prover},
+};
+use post_service::k2pow::{K2PowServer, RemotePowProver};
+use tokio::net::TcpListener;
+use tokio_stream::wrappers::TcpListenerStream;
+use tonic::transport::Server;
+
+/// Fake prover counting the nonce groups it was asked for.
+#[derive(Default, Clone)]
+struct FakeProver {
+    calls: Arc<AtomicUsize>,
+    cancelled: Arc<AtomicBool>,
+}
+
+impl pow::Prover for FakeProver {
+    fn prove(
+        &self,
+        nonce_group: u8,
+        challenge: &[u8; 8],
+        _: &[u8; 32],
+        _: &[u8; 32],
+        cancel: &CancellationToken,
+    ) -> Result<u64, pow::Error> {
+        self.calls.fetch_add(1, Ordering::SeqCst);
+        match nonce_group {
+            // Takes forever, until cancelled
+            100 => {
+                while !cancel.is_cancelled() {
+                    std::thread::sleep(Duration::from_millis(10));
+                }
+                self.cancelled.store(true, Ordering::SeqCst);
+                Err(pow::Error::Cancelled)
+            }
+            200 => Err(pow::Error::PoWNotFound),
+            _ => Ok(nonce_group as u64 * 1000 + challenge[0] as u64),
+        }
+    }
+}
+
+/// Start the server on a separate runtime, as the remote prover blocks on its own.
+fn start_server(runtime: &tokio::runtime::Runtime, prover: FakeProver) -> String {
+    let server = K2PowServer::new(Box::new(prover));
+    let listener = runtime.block_on(TcpListener::bind("[::1]:0")).unwrap();
+    let addr = listener.local_addr().unwrap();
+    runtime.spawn(
+        Server::builder()
+            .add_service(server.into_service())
+            .serve_with_incoming(TcpListenerStream::new(listener)),
+    );
+    format!("http://{addr}")
+}
+
+#[test]
+fn remote_pow_in_batches() {
+    let runtime = tokio::runtime::Runtime::new().unwrap();
+    let fake = FakeProver::default();
+    let address = start_server(&runtime, fake.clone());
+
+    let remote = RemotePowProver::new(address, None)
+        .unwrap()
+        .with_batch_size(4);
+    let cancel = CancellationToken::new();
+    for group in 0..6 {
+        let pow = remote
+            .prove(group, &[7; 8], &[0xFF; 32], &[1; 32], &cancel)
+            .unwrap();
+        assert_eq!(group as u64 * 1000 + 7, pow);
+    }
+    // Two batches: 0..4 and 4..8
+    assert_eq!(8, fake.calls.load(Ordering::SeqCst));
+
+    // Another challenge is not answered from the batch
+    let pow = remote
+        .prove(5, &[8; 8], &[0xFF; 32], &[1; 32], &cancel)
+        .unwrap();
+    assert_eq!(5008, pow);
+    assert_eq!(12, fake.calls.load(Ordering::SeqCst));
+}
+
+#[test]
+fn remote_pow_not_found() {
+    let runtime = tokio::runtime::Runtime::new().unwrap();
+    let address = start_server(&runtime, FakeProver::default());
+
+    let remote = RemotePowProver::new(address, None)
+        .unwrap()
+        .with_batch_size(1);
+    let result = remote.prove(200, &[0; 8], &[0; 32], &[0; 32], &CancellationToken::new());
+    assert!(matches!(result, Err(pow::Error::PoWNotFound)));
+}
+
+#[test]
+fn cancelling_remote_pow() {
+    let runtime = tokio::runtime::Runtime::new().unwrap();
+    let fake = FakeProver::default();
+    let address = start_server(&runtime, fake.clone());
+
+    let remote = RemotePowProver::new(address, None).unwrap();
+    let cancel = CancellationToken::new();
+    cancel.cancel_after(Duration::from_millis(200));
+    let result = remote.prove(100, &[0; 8], &[0; 32], &[0; 32], &cancel);
+    assert!(matches!(result, Err(pow::Error::Cancelled)));
+
+    // The server stops calculating once the request is dropped
+    for _ in 0..100 {
+        if fake.cancelled.load(Ordering::SeqCst) {
+            return;
+        }
+        std::thread::sleep(Duration::from_millis(50));
+    }
+    panic!("the server didn't cancel the proof of work");
+}
+
+#[test]
+fn remote_pow_server_unavailable() {
+    let remote = RemotePowProver::new("http://[::1]:1".into(), None).unwrap();
+    let result = remote.prove(0, &[0; 8], &[0; 32], &[0; 32], &CancellationToken::new());
+    assert!(matches!(result, Err(pow::Error::Internal(_))));
+}
@@ -0,0 +1,165 @@
+use std::sync::Arc;
+
+use post::metadata::PostMetadata;
+use post_service::{
+    data_init::{InitParams, InitStatus},
+    operator::{self, OperatorState},
+    service::ProvingStatus,
+};
+use rcgen::{BasicConstraints, Certificate, CertificateParams, IsCa};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio_rustls::{
+    rustls::{self, ServerName},
+    TlsConnector,
+};
+
+struct IdleState;
+
+impl OperatorState for IdleState {
+    fn proving_status(&self) -> ProvingStatus {
+        ProvingStatus::default()
+    }
+
+    fn config(&self) -> serde_json::Value {
+        serde_json::json!({})
+    }
+
+    fn metadata(&self) -> eyre::Result<PostMetadata> {
+        Err(eyre::eyre!("no POS data"))
+    }
+
+    fn active_threads(&self) -> usize {
+        0
+    }
+
+    fn set_active_threads(&self, _: usize) {}
+
+    fn start_initialization(&self, _: InitParams) -> eyre::Result<()> {
+        Err(eyre::eyre!("not supported"))
+    }
+
+    fn initialization_status(&self) -> InitStatus {
+        InitStatus::Idle
+    }
+
+    fn cancel_initialization(&self) -> bool {
+        false
+    }
+}
+
+fn ca() -> Certificate {
+    let mut params = CertificateParams::new(vec![]);
+    params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+    Certificate::from_params(params).unwrap()
+}
+
+fn cert(name: &str) -> Certificate {
+    Certificate::from_params(CertificateParams::new(vec![name.into()])).unwrap()
+}
+
+/// GET /status over TLS, authenticating with the `client` certificate (if given).
+async fn get_status(
+    address: std::net::SocketAddr,
+    server_ca: &Certificate,
+    client: Option<(&Certificate, &Certificate)>,
+) -> std::io::Result<String> {
+    let mut roots = rustls::RootCertStore::empty();
+    roots
+        .add(&rustls::Certificate(server_ca.serialize_der().unwrap()))
+        .unwrap();
+    let builder = rustls::ClientConfig::builder()
+        .with_safe_defaults()
+        .with_root_certificates(roots);
+    let config = match client {
+        Some((cert, ca)) => builder
+            .with_client_auth_cert(
+                vec![rustls::Certificate(
+                    cert.serialize_der_with_signer(ca).unwrap(),
+                )],
+                rustls::PrivateKey(cert.serialize_private_key_der()),
+            )
+            .unwrap(),
+        None => builder.with_no_client_auth(),
+    };
+
+    let stream = tokio::net::TcpStream::connect(address).await?;
+    let mut stream = TlsConnector::from(Arc::new(config))
+        .connect(ServerName::try_from("localhost").unwrap(), stream)
+        .await?;
+    stream
+        .write_all(b"GET /status HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n")
+        .await?;
+    let mut response = String::new();
+    stream.read_to_string(&mut response).await?;
+    Ok(response)
+}
+
+#[tokio::test]
+async fn requiring_client_certificates() {
+    let server_ca = ca();
+    let server_cert = cert("localhost");
+    // The clients are authenticated against another CA than the server's
+    let client_ca = ca();
+
+    let acceptor = operator::tls_acceptor(
+        &server_cert.serialize_pem_with_signer(&server_ca).unwrap(),
+        &server_cert.serialize_private_key_pem(),
+        &client_ca.serialize_pem().unwrap(),
+    )
+    .unwrap();
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let address = listener.local_addr().unwrap();
+    tokio::spawn(operator::serve(
+        listener,
+        Arc::new(IdleState),
+        Arc::new(operator::Readiness::new(false)),
+        Some(acceptor),
+    ));
+
+    let client_cert = cert("operator");
+    let response = get_status(address, &server_ca, Some((&client_cert, &client_ca)))
+        .await
+        .unwrap();
+    assert!(response.starts_with("HTTP/1.1 200 OK"), "{response}");
+    assert!(response.contains(r#""state":"idle""#));
+
+    // Without a certificate or with one signed by another CA
+    let response = get_status(address, &server_ca, None).await;
+    assert!(!response.unwrap_or_default().contains("200 OK"));
+    let response = get_status(address, &server_ca, Some((&client_cert, &server_ca))).await;
+    assert!(!response.unwrap_or_default().contains("200 OK"));
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn serving_on_unix_socket() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("operator.sock");
+    // A stale socket of a previous run is replaced
+    drop(std::os::unix::net::UnixListener::bind(&path).unwrap());
+
+    let server_path = path.clone();
+    tokio::spawn(async move {
+        operator::serve_unix(
+            &server_path,
+            Arc::new(IdleState),
+            Arc::new(operator::Readiness::new(false)),
+        )
+        .await
+    });
+
+    let mut stream = loop {
+        match tokio::net::UnixStream::connect(&path).await {
+            Ok(stream) => break stream,
+            Err(_) => tokio::time::sleep(std::time::Duration::from_millis(10)).await,
+        }
+    };
+    stream
+        .write_all(b"GET /status HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n")
+        .await
+        .unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).await.unwrap();
+    assert!(response.starts_with("HTTP/1.1 200 OK"), "{response}");
+    assert!(response.contains(r#""state":"idle""#));
+}
@@ -0,0 +1,193 @@
+use std::sync::Arc;
+
+use post::metadata::PostMetadata;
+use tokio::net::TcpListener;
+use tokio_stream::wrappers::TcpListenerStream;
+use tonic::transport::Server;
+
+use post_service::{
+    client::{
+        spacemesh_v1::{
+            node_request, service_response, GenProofRequest, GenProofResponse, GenProofStatus,
+            MetadataRequest, NodeRequest,
+        },
+        MockPostService,
+    },
+    operator::Readiness,
+    pool::{Registry, NODE_ID_HEADER},
+    server::{post_service_v1::post_service_client::PostServiceClient, NodeServer},
+    service::ProofGenState,
+};
+
+#[tokio::test]
+async fn serving_connected_node() {
+    let mut service = MockPostService::new();
+    service.expect_get_metadata().returning(|| {
+        Ok(PostMetadata {
+            num_units: 4,
+            ..Default::default()
+        })
+    });
+    service
+        .expect_gen_proof()
+        .returning(|_| Ok(ProofGenState::InProgress));
+    let mut identities = Registry::new(1);
+    identities
+        .register(Some([1; 32]), "post".into(), Arc::new(service))
+        .unwrap();
+    let node_server = NodeServer::new(identities).with_readiness(Arc::new(Readiness::new(false)));
+
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let address = listener.local_addr().unwrap();
+    let server = tokio::spawn(
+        Server::builder()
+            .add_service(node_server.into_service())
+            .serve_with_incoming(TcpListenerStream::new(listener)),
+    );
+
+    let mut node = PostServiceClient::connect(format!("http://{address}"))
+        .await
+        .unwrap();
+    let requests = vec![
+        NodeRequest {
+            kind: Some(node_request::Kind::Metadata(MetadataRequest {})),
+        },
+        NodeRequest {
+            kind: Some(node_request::Kind::GenProof(GenProofRequest {
+                challenge: vec![0xCA; 32],
+            })),
+        },
+    ];
+    let mut responses = node
+        .connect(tokio_stream::iter(requests))
+        .await
+        .unwrap()
+        .into_inner();
+
+    let response = responses.message().await.unwrap().unwrap();
+    match response.kind {
+        Some(service_response::Kind::Metadata(metadata)) => {
+            assert_eq!(4, metadata.meta.unwrap().num_units)
+        }
+        kind => panic!("unexpected response: {kind:?}"),
+    }
+    let response = responses.message().await.unwrap().unwrap();
+    assert_eq!(
+        Some(service_response::Kind::GenProof(GenProofResponse {
+            status: GenProofStatus::Ok as i32,
+            proof: None,
+            metadata: None,
+        })),
+        response.kind
+    );
+    // The node closed its stream
+    assert!(responses.message().await.unwrap().is_none());
+
+    server.abort();
+}
+
+#[tokio::test]
+async fn serving_identities_of_pool() {
+    let mut identities = Registry::new(1);
+    for (node_id, num_units) in [([1; 32], 1), ([2; 32], 2)] {
+        let mut service = MockPostService::new();
+        service.expect_get_metadata().returning(move || {
+            Ok(PostMetadata {
+                node_id,
+                num_units,
+                ..Default::default()
+            })
+        });
+        identities
+            .register(
+                node_id,
+                format!("post-{num_units}").into(),
+                Arc::new(service),
+            )
+            .unwrap();
+    }
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let address = listener.local_addr().unwrap();
+    let server = tokio::spawn(
+        Server::builder()
+            .add_service(NodeServer::new(identities).into_service())
+            .serve_with_incoming(TcpListenerStream::new(listener)),
+    );
+    let mut node = PostServiceClient::connect(format!("http://{address}"))
+        .await
+        .unwrap();
+    let metadata_request = || {
+        tokio_stream::iter([NodeRequest {
+            kind: Some(node_request::Kind::Metadata(MetadataRequest {})),
+        }])
+    };
+
+    // The identity must be named with several of them
+    let status = node.connect(metadata_request()).await.unwrap_err();
+    assert_eq!(tonic::Code::InvalidArgument, status.code());
+
+    let mut request = tonic::Request::new(metadata_request());
+    request
+        .metadata_mut()
+        .insert(NODE_ID_HEADER, hex::encode([2; 32]).parse().unwrap());
+    let mut responses = node.connect(request).await.unwrap().into_inner();
+    match responses.message().await.unwrap().unwrap().kind {
+        Some(service_response::Kind::Metadata(metadata)) => {
+            assert_eq!(2, metadata.meta.unwrap().num_units)
+        }
+        kind => panic!("unexpected response: {kind:?}"),
+    }
+
+    server.abort();
+}
+
+#[tokio::test]
+async fn querying_metadata() {
+    let mut service = MockPostService::new();
+    service.expect_get_metadata().returning(|| {
+        Ok(PostMetadata {
+            node_id: [1; 32],
+            commitment_atx_id: [2; 32],
+            num_units: 4,
+            labels_per_unit: 256,
+            nonce: Some(7),
+            ..Default::default()
+        })
+    });
+    let mut identities = Registry::new(1);
+    identities
+        .register(Some([1; 32]), "post".into(), Arc::new(service))
+        .unwrap();
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let address = listener.local_addr().unwrap();
+    let server = tokio::spawn(
+        Server::builder()
+            .add_service(NodeServer::new(identities).into_service())
+            .serve_with_incoming(TcpListenerStream::new(listener)),
+    );
+
+    let mut node = PostServiceClient::connect(format!("http://{address}"))
+        .await
+        .unwrap();
+    let metadata = node
+        .metadata(MetadataRequest {})
+        .await
+        .unwrap()
+        .into_inner()
+        .meta
+        .unwrap();
+    assert_eq!(vec![1; 32], metadata.node_id);
+    assert_eq!(vec![2; 32], metadata.commitment_atx_id);
+    assert_eq!(4, metadata.num_units);
+    assert_eq!(256, metadata.labels_per_unit);
+    assert_eq!(Some(7), metadata.nonce);
+
+    // The identity is queried without connecting to it
+    let _responses = node
+        .connect(tokio_stream::iter(Vec::<NodeRequest>::new()))
+        .await
+        .unwrap();
+    assert!(node.metadata(MetadataRequest {}).await.is_ok());
+
+    server.abort();
+}
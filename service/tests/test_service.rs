@@ -1,12 +1,25 @@
-use std::{thread::sleep, time::Duration};
+use std::{
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    thread::sleep,
+    time::{Duration, Instant, SystemTime},
+};
 
 use post::{
+    cancel::Cancelled,
     config::{InitConfig, ProofConfig, ScryptParams},
     initialize::{CpuInitializer, Initialize},
     metadata::ProofMetadata,
     pow::randomx::RandomXFlag,
+    prove::{Candidate, LabelScanner, Proof, ProofCache},
+};
+use post_service::{
+    client::PostService,
+    schedule::ProvingSchedule,
+    service::{ProofGenState, ProvingBenchmark},
 };
-use post_service::{client::PostService, service::ProofGenState};
 
 #[test]
 fn test_generate_and_verify() {
@@ -108,7 +121,7 @@ fn reject_invalid_challenge() {
 }
 
 #[test]
-fn cannot_run_parallel_proof_gens() {
+fn proving_multiple_challenges() {
     // Initialize some data
     let datadir = tempfile::tempdir().unwrap();
 
@@ -125,7 +138,7 @@ fn cannot_run_parallel_proof_gens() {
         scrypt: ScryptParams::new(2, 1, 1),
     };
 
-    CpuInitializer::new(init_cfg.scrypt)
+    let metadata = CpuInitializer::new(init_cfg.scrypt)
         .initialize(
             datadir.path(),
             &[0xBE; 32],
@@ -137,7 +150,7 @@ fn cannot_run_parallel_proof_gens() {
         )
         .unwrap();
 
-    // Generate a proof
+    // Generate proofs
     let service = post_service::service::PostService::new(
         datadir.into_path(),
         cfg,
@@ -150,8 +163,475 @@ fn cannot_run_parallel_proof_gens() {
 
     let result = service.gen_proof(vec![0xAA; 32]);
     assert!(matches!(result, Ok(ProofGenState::InProgress)));
-    // Try to generate another proof with a different challenge
+    // An invalid challenge is rejected
     assert!(service.gen_proof(vec![0xBB; 5]).is_err());
-    // Try again with the same challenge
-    assert!(matches!(result, Ok(ProofGenState::InProgress)));
+
+    // Another challenge requested meanwhile joins the running proof generation
+    let mut proofs = [None, None];
+    while proofs.iter().any(Option::is_none) {
+        for (proof, challenge) in proofs.iter_mut().zip([0xAA, 0xBB]) {
+            if proof.is_none() {
+                if let ProofGenState::Finished { proof: p } =
+                    service.gen_proof(vec![challenge; 32]).unwrap()
+                {
+                    *proof = Some(p);
+                }
+            }
+        }
+        sleep(Duration::from_millis(10));
+    }
+
+    for (proof, challenge) in proofs.into_iter().zip([0xAA, 0xBB]) {
+        service
+            .verify_proof(
+                &proof.unwrap(),
+                &ProofMetadata::new(metadata, [challenge; 32]),
+            )
+            .expect("proof should be valid");
+    }
+}
+
+#[test]
+fn reusing_cached_proof() {
+    // Initialize some data
+    let datadir = tempfile::tempdir().unwrap();
+    let cache_dir = tempfile::tempdir().unwrap();
+
+    let cfg = ProofConfig {
+        k1: 8,
+        k2: 4,
+        k3: 4,
+        pow_difficulty: [0xFF; 32],
+    };
+    let init_cfg = InitConfig {
+        min_num_units: 1,
+        max_num_units: 1000,
+        labels_per_unit: 256,
+        scrypt: ScryptParams::new(2, 1, 1),
+    };
+
+    CpuInitializer::new(init_cfg.scrypt)
+        .initialize(
+            datadir.path(),
+            &[0xBE; 32],
+            &[0xCE; 32],
+            init_cfg.labels_per_unit,
+            4,
+            init_cfg.labels_per_unit,
+            None,
+        )
+        .unwrap();
+
+    let new_service = || {
+        post_service::service::PostService::new(
+            datadir.path().to_path_buf(),
+            cfg,
+            init_cfg,
+            16,
+            1,
+            RandomXFlag::get_recommended_flags(),
+        )
+        .unwrap()
+        .with_proof_cache(ProofCache::new(cache_dir.path()).unwrap())
+    };
+
+    // Generate a proof
+    let service = new_service();
+    let proof = loop {
+        if let ProofGenState::Finished { proof } = service.gen_proof(vec![0xCA; 32]).unwrap() {
+            break proof;
+        }
+        sleep(Duration::from_millis(10));
+    };
+    drop(service);
+
+    // The restarted service finds it in the cache
+    let service = new_service();
+    match service.gen_proof(vec![0xCA; 32]).unwrap() {
+        ProofGenState::Finished { proof: cached } => assert_eq!(proof, cached),
+        ProofGenState::InProgress => panic!("proof should be cached"),
+    }
+
+    // An invalid cached proof is generated again
+    let metadata = post::metadata::load(datadir.path()).unwrap();
+    let invalid = Proof {
+        nonce: proof.nonce + 1,
+        ..proof.clone()
+    };
+    ProofCache::new(cache_dir.path())
+        .unwrap()
+        .store(&[0xCA; 32], &metadata, &invalid)
+        .unwrap();
+    assert!(matches!(
+        service.gen_proof(vec![0xCA; 32]).unwrap(),
+        ProofGenState::InProgress
+    ));
+}
+
+#[test]
+fn aborting_proof_generation() {
+    let datadir = tempfile::tempdir().unwrap();
+
+    let cfg = ProofConfig {
+        k1: 8,
+        k2: 4,
+        k3: 4,
+        // The k2pow is never found
+        pow_difficulty: [0; 32],
+    };
+    let init_cfg = InitConfig {
+        min_num_units: 1,
+        max_num_units: 1000,
+        labels_per_unit: 256,
+        scrypt: ScryptParams::new(2, 1, 1),
+    };
+    CpuInitializer::new(init_cfg.scrypt)
+        .initialize(
+            datadir.path(),
+            &[0xBE; 32],
+            &[0xCE; 32],
+            init_cfg.labels_per_unit,
+            1,
+            init_cfg.labels_per_unit,
+            None,
+        )
+        .unwrap();
+
+    let service = post_service::service::PostService::new(
+        datadir.into_path(),
+        cfg,
+        init_cfg,
+        16,
+        1,
+        RandomXFlag::get_recommended_flags(),
+    )
+    .unwrap();
+    assert!(!service.abort_proof_generation("nothing to abort"));
+    assert!(matches!(
+        service.gen_proof(vec![0xCA; 32]).unwrap(),
+        ProofGenState::InProgress
+    ));
+    assert!(service.abort_proof_generation("challenge withdrawn"));
+
+    let err = loop {
+        match service.gen_proof(vec![0xCA; 32]) {
+            Ok(ProofGenState::InProgress) => sleep(Duration::from_millis(10)),
+            Ok(ProofGenState::Finished { .. }) => panic!("proof should not be found"),
+            Err(err) => break err,
+        }
+    };
+    let cancelled = err.downcast_ref::<Cancelled>().unwrap();
+    assert_eq!("challenge withdrawn", cancelled.reason);
+}
+
+/// Scans no labels until released, like reading a dead disk.
+struct StuckScanner(Arc<AtomicBool>);
+
+impl LabelScanner for StuckScanner {
+    fn set_ciphers(
+        &mut self,
+        _keys: &[[u8; 16]],
+        _difficulty_msb: u8,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        Ok(())
+    }
+
+    fn scan(&mut self, _labels: &[u8]) -> Result<Vec<Candidate>, Box<dyn std::error::Error>> {
+        while !self.0.load(Ordering::Relaxed) {
+            sleep(Duration::from_millis(10));
+        }
+        Err("released".into())
+    }
+}
+
+#[test]
+fn giving_up_stalled_proof_generation() {
+    let datadir = tempfile::tempdir().unwrap();
+
+    let cfg = ProofConfig {
+        k1: 8,
+        k2: 4,
+        k3: 4,
+        pow_difficulty: [0xFF; 32],
+    };
+    let init_cfg = InitConfig {
+        min_num_units: 1,
+        max_num_units: 1000,
+        labels_per_unit: 256,
+        scrypt: ScryptParams::new(2, 1, 1),
+    };
+    CpuInitializer::new(init_cfg.scrypt)
+        .initialize(
+            datadir.path(),
+            &[0xBE; 32],
+            &[0xCE; 32],
+            init_cfg.labels_per_unit,
+            1,
+            init_cfg.labels_per_unit,
+            None,
+        )
+        .unwrap();
+
+    let release = Arc::new(AtomicBool::new(false));
+    let service = post_service::service::PostService::new(
+        datadir.into_path(),
+        cfg,
+        init_cfg,
+        16,
+        1,
+        RandomXFlag::get_recommended_flags(),
+    )
+    .unwrap()
+    .with_label_scanner(StuckScanner(release.clone()))
+    .with_stall_watchdog(Duration::from_millis(200), 1);
+
+    let start = Instant::now();
+    let err = loop {
+        match service.gen_proof(vec![0xCA; 32]) {
+            Ok(ProofGenState::InProgress) => sleep(Duration::from_millis(10)),
+            Ok(ProofGenState::Finished { .. }) => panic!("proof should not be found"),
+            Err(err) => break err,
+        }
+    };
+    release.store(true, Ordering::Relaxed);
+    assert!(err.to_string().contains("stalled"), "{err}");
+    // Retried once before giving up
+    assert!(start.elapsed() >= Duration::from_millis(400));
+}
+
+#[test]
+fn reporting_proving_checkpoint_after_restart() {
+    let datadir = tempfile::tempdir().unwrap();
+
+    let cfg = ProofConfig {
+        k1: 8,
+        // Never found, the proof generation goes on until it's stopped
+        k2: 200,
+        k3: 4,
+        pow_difficulty: [0xFF; 32],
+    };
+    let init_cfg = InitConfig {
+        min_num_units: 1,
+        max_num_units: 1000,
+        labels_per_unit: 256,
+        scrypt: ScryptParams::new(2, 1, 1),
+    };
+    CpuInitializer::new(init_cfg.scrypt)
+        .initialize(
+            datadir.path(),
+            &[0xBE; 32],
+            &[0xCE; 32],
+            init_cfg.labels_per_unit,
+            1,
+            init_cfg.labels_per_unit,
+            None,
+        )
+        .unwrap();
+
+    let new_service = || {
+        post_service::service::PostService::new(
+            datadir.path().to_path_buf(),
+            cfg,
+            init_cfg,
+            16,
+            1,
+            RandomXFlag::get_recommended_flags(),
+        )
+        .unwrap()
+        .with_checkpoint_interval(Duration::from_secs(60))
+    };
+
+    let service = new_service();
+    assert!(service.proving_status().checkpoint.is_empty());
+    assert!(matches!(
+        service.gen_proof(vec![0xCA; 32]).unwrap(),
+        ProofGenState::InProgress
+    ));
+    while service.proving_status().checkpoint.is_empty() {
+        sleep(Duration::from_millis(10));
+    }
+    drop(service);
+
+    // The restarted service resumes it when requested
+    let service = new_service();
+    assert_eq!(
+        vec![hex::encode([0xCA; 32])],
+        service.proving_status().checkpoint
+    );
+}
+
+#[test]
+fn proving_sequentially_with_other_services() {
+    let datadir = tempfile::tempdir().unwrap();
+
+    let cfg = ProofConfig {
+        k1: 8,
+        k2: 4,
+        k3: 4,
+        pow_difficulty: [0xFF; 32],
+    };
+    let init_cfg = InitConfig {
+        min_num_units: 1,
+        max_num_units: 1000,
+        labels_per_unit: 256,
+        scrypt: ScryptParams::new(2, 1, 1),
+    };
+    CpuInitializer::new(init_cfg.scrypt)
+        .initialize(
+            datadir.path(),
+            &[0xBE; 32],
+            &[0xCE; 32],
+            init_cfg.labels_per_unit,
+            4,
+            init_cfg.labels_per_unit,
+            None,
+        )
+        .unwrap();
+
+    let lock = Arc::new(Mutex::new(()));
+    let service = post_service::service::PostService::new(
+        datadir.into_path(),
+        cfg,
+        init_cfg,
+        16,
+        1,
+        RandomXFlag::get_recommended_flags(),
+    )
+    .unwrap()
+    .with_sequential_proving(lock.clone());
+
+    // Another service is proving, it waits for its turn (and can be aborted meanwhile)
+    let other = lock.lock().unwrap();
+    assert!(matches!(
+        service.gen_proof(vec![0xCA; 32]).unwrap(),
+        ProofGenState::InProgress
+    ));
+    assert!(service.abort_proof_generation("challenge withdrawn"));
+    let err = loop {
+        match service.gen_proof(vec![0xCA; 32]) {
+            Ok(ProofGenState::InProgress) => sleep(Duration::from_millis(10)),
+            Ok(ProofGenState::Finished { .. }) => panic!("proof should not be generated"),
+            Err(err) => break err,
+        }
+    };
+    assert!(err.downcast_ref::<Cancelled>().is_some());
+
+    // It proves once the other one is done
+    drop(other);
+    loop {
+        if let ProofGenState::Finished { .. } = service.gen_proof(vec![0xCA; 32]).unwrap() {
+            break;
+        }
+        sleep(Duration::from_millis(10));
+    }
+}
+
+#[test]
+fn deferring_proving_outside_of_schedule() {
+    let datadir = tempfile::tempdir().unwrap();
+
+    let cfg = ProofConfig {
+        k1: 8,
+        k2: 4,
+        k3: 4,
+        pow_difficulty: [0xFF; 32],
+    };
+    let init_cfg = InitConfig {
+        min_num_units: 1,
+        max_num_units: 1000,
+        labels_per_unit: 256,
+        scrypt: ScryptParams::new(2, 1, 1),
+    };
+    CpuInitializer::new(init_cfg.scrypt)
+        .initialize(
+            datadir.path(),
+            &[0xBE; 32],
+            &[0xCE; 32],
+            init_cfg.labels_per_unit,
+            4,
+            init_cfg.labels_per_unit,
+            None,
+        )
+        .unwrap();
+
+    // A window starting in two hours
+    let minute = SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap()
+        .as_secs()
+        / 60;
+    let time = |minute: u64| format!("{:02}:{:02}", minute / 60 % 24, minute % 60);
+    let window = format!("{}-{}", time(minute + 120), time(minute + 180));
+    let schedule = ProvingSchedule::new(vec![window.parse().unwrap()]);
+
+    let service = post_service::service::PostService::new(
+        datadir.into_path(),
+        cfg,
+        init_cfg,
+        16,
+        1,
+        RandomXFlag::get_recommended_flags(),
+    )
+    .unwrap()
+    .with_proving_schedule(schedule);
+
+    assert!(matches!(
+        service.gen_proof(vec![0xCA; 32]).unwrap(),
+        ProofGenState::InProgress
+    ));
+    let status = service.proving_status();
+    assert!(status.proving.is_empty());
+    assert_eq!(vec![hex::encode([0xCA; 32])], status.queued);
+}
+
+#[test]
+fn benchmarking_proving() {
+    let datadir = tempfile::tempdir().unwrap();
+
+    let cfg = ProofConfig {
+        k1: 8,
+        k2: 4,
+        k3: 4,
+        pow_difficulty: [0xFF; 32],
+    };
+    let init_cfg = InitConfig {
+        min_num_units: 1,
+        max_num_units: 1000,
+        labels_per_unit: 256,
+        scrypt: ScryptParams::new(2, 1, 1),
+    };
+
+    CpuInitializer::new(init_cfg.scrypt)
+        .initialize(
+            datadir.path(),
+            &[0xBE; 32],
+            &[0xCE; 32],
+            init_cfg.labels_per_unit,
+            4,
+            init_cfg.labels_per_unit,
+            None,
+        )
+        .unwrap();
+
+    let service = post_service::service::PostService::new(
+        datadir.path().into(),
+        cfg,
+        init_cfg,
+        16,
+        1,
+        RandomXFlag::get_recommended_flags(),
+    )
+    .unwrap();
+    let result = service.benchmark(50.0).unwrap();
+    assert_eq!(4 * 256, result.total_labels);
+    assert!(result.labels_scanned > 0);
+    assert!(result.projected_pass() >= result.reading_time);
+
+    let result = ProvingBenchmark {
+        total_labels: 1000,
+        labels_scanned: 100,
+        k2pow_time: Duration::from_secs(1),
+        reading_time: Duration::from_secs(2),
+    };
+    assert_eq!(Duration::from_secs(21), result.projected_pass());
 }
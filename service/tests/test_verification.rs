@@ -0,0 +1,87 @@
+use post::{
+    config::{InitConfig, ProofConfig, ScryptParams},
+    pow::NoopPowVerifier,
+    verification::{Verifier, VerifierPool},
+};
+use post_service::verification::{
+    verification_v1::{
+        verification_service_client::VerificationServiceClient, Proof, ProofMetadata, VerifyRequest,
+    },
+    VerificationServer,
+};
+use tokio::net::TcpListener;
+use tokio_stream::wrappers::TcpListenerStream;
+use tonic::transport::Server;
+
+async fn start_server() -> String {
+    let cfg = ProofConfig {
+        k1: 10,
+        k2: 10,
+        k3: 10,
+        pow_difficulty: [0xFF; 32],
+    };
+    let init_cfg = InitConfig {
+        min_num_units: 1,
+        max_num_units: 10,
+        labels_per_unit: 2048,
+        scrypt: ScryptParams::new(4, 1, 1),
+    };
+    let pool = VerifierPool::new(Verifier::new(Box::new(NoopPowVerifier)), 2, 4).unwrap();
+    let server = VerificationServer::new(pool, cfg, init_cfg);
+
+    let listener = TcpListener::bind("[::1]:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(
+        Server::builder()
+            .add_service(server.into_service())
+            .serve_with_incoming(TcpListenerStream::new(listener)),
+    );
+    format!("http://{addr}")
+}
+
+fn request(indices: Vec<u8>) -> VerifyRequest {
+    VerifyRequest {
+        proof: Some(Proof {
+            nonce: 0,
+            indices,
+            pow: 0,
+        }),
+        metadata: Some(ProofMetadata {
+            node_id: vec![0; 32],
+            commitment_atx_id: vec![0; 32],
+            challenge: vec![0; 32],
+            num_units: 10,
+        }),
+    }
+}
+
+#[tokio::test]
+async fn verifying_remotely() {
+    let address = start_server().await;
+    let mut client = VerificationServiceClient::connect(address).await.unwrap();
+
+    // 10 indices of 15 bits are expected
+    let response = client
+        .verify(request(vec![1, 2, 3]))
+        .await
+        .unwrap()
+        .into_inner();
+    assert!(!response.valid);
+    assert!(response.error.contains("invalid number of indices"));
+    assert_eq!(None, response.invalid_index);
+
+    // The indices point to label 0, which doesn't satisfy the difficulty.
+    let response = client
+        .verify(request(vec![0; 19]))
+        .await
+        .unwrap()
+        .into_inner();
+    assert!(!response.valid);
+    assert_eq!(Some(0), response.invalid_index);
+    assert_eq!(Some(0), response.invalid_index_position);
+
+    let mut invalid = request(vec![]);
+    invalid.metadata.as_mut().unwrap().challenge = vec![0; 8];
+    let status = client.verify(invalid).await.unwrap_err();
+    assert_eq!(tonic::Code::InvalidArgument, status.code());
+}
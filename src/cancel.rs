@@ -0,0 +1,154 @@
+//! Cancelling long running operations, like generating a proof.
+
+use std::{
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+/// The operation was cancelled with a [CancellationToken].
+#[derive(Debug, Clone, thiserror::Error)]
+#[error("cancelled: {reason}")]
+pub struct Cancelled {
+    pub reason: String,
+}
+
+/// Token to cancel an operation, with a reason or after a timeout.
+///
+/// Clones share the cancellation. A [child](CancellationToken::child) is cancelled
+/// along with its parent, but can be cancelled on its own.
+#[derive(Debug, Clone, Default)]
+pub struct CancellationToken(Arc<Inner>);
+
+#[derive(Debug)]
+struct Inner {
+    cancelled: AtomicBool,
+    reason: Mutex<Option<String>>,
+    created: Instant,
+    /// Nanoseconds since `created` at which the token is cancelled, `u64::MAX` if never.
+    timeout: AtomicU64,
+    parent: Option<CancellationToken>,
+}
+
+impl Default for Inner {
+    fn default() -> Self {
+        Self {
+            cancelled: AtomicBool::new(false),
+            reason: Mutex::new(None),
+            created: Instant::now(),
+            timeout: AtomicU64::new(u64::MAX),
+            parent: None,
+        }
+    }
+}
+
+impl CancellationToken {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// A token cancelled when this one is.
+    pub fn child(&self) -> Self {
+        Self(Arc::new(Inner {
+            parent: Some(self.clone()),
+            ..Default::default()
+        }))
+    }
+
+    /// Cancel with the `reason`. The reason of the first cancellation is kept.
+    pub fn cancel(&self, reason: impl Into<String>) {
+        self.0
+            .reason
+            .lock()
+            .unwrap()
+            .get_or_insert_with(|| reason.into());
+        self.0.cancelled.store(true, Ordering::Relaxed);
+    }
+
+    /// Cancel once the `timeout` elapses (from now on), unless cancelled earlier.
+    pub fn cancel_after(&self, timeout: Duration) {
+        let at = self.0.created.elapsed() + timeout;
+        let at = u64::try_from(at.as_nanos()).unwrap_or(u64::MAX);
+        self.0.timeout.fetch_min(at, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        if self.0.cancelled.load(Ordering::Relaxed) {
+            return true;
+        }
+        let timeout = self.0.timeout.load(Ordering::Relaxed);
+        if timeout != u64::MAX && self.0.created.elapsed().as_nanos() >= timeout as u128 {
+            self.cancel("timed out");
+            return true;
+        }
+        match &self.0.parent {
+            Some(parent) if parent.is_cancelled() => {
+                self.cancel(parent.reason().unwrap_or_default());
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// The reason of the cancellation, if cancelled.
+    pub fn reason(&self) -> Option<String> {
+        if !self.is_cancelled() {
+            return None;
+        }
+        self.0.reason.lock().unwrap().clone()
+    }
+
+    /// Fail with [Cancelled] if cancelled.
+    pub fn check(&self) -> Result<(), Cancelled> {
+        match self.reason() {
+            Some(reason) => Err(Cancelled { reason }),
+            None => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cancelling() {
+        let token = CancellationToken::new();
+        let clone = token.clone();
+        assert!(!clone.is_cancelled());
+        assert!(clone.check().is_ok());
+
+        token.cancel("stopped");
+        token.cancel("stopped again");
+        assert!(clone.is_cancelled());
+        assert_eq!(Some("stopped".to_string()), clone.reason());
+        assert_eq!("cancelled: stopped", clone.check().unwrap_err().to_string());
+    }
+
+    #[test]
+    fn cancelling_after_timeout() {
+        let token = CancellationToken::new();
+        token.cancel_after(Duration::from_secs(3600));
+        assert!(!token.is_cancelled());
+
+        token.cancel_after(Duration::from_millis(10));
+        std::thread::sleep(Duration::from_millis(20));
+        assert!(token.is_cancelled());
+        assert_eq!(Some("timed out".to_string()), token.reason());
+    }
+
+    #[test]
+    fn cancelling_children() {
+        let parent = CancellationToken::new();
+        let child = parent.child();
+        child.cancel("child only");
+        assert!(child.is_cancelled());
+        assert!(!parent.is_cancelled());
+
+        let child = parent.child();
+        parent.cancel("everything");
+        assert_eq!(Some("everything".to_string()), child.reason());
+    }
+}
@@ -1,4 +1,6 @@
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
+
+use crate::initialize::LABEL_SIZE;
 
 /// POST configuration (network parameter)
 #[repr(C)]
@@ -15,6 +17,57 @@ pub struct InitConfig {
     pub scrypt: ScryptParams,
 }
 
+/// Layout of POS data files in the data directory.
+///
+/// Many small files suit object storage, while few huge files suit local RAID.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(default)]
+pub struct FileLayout {
+    /// The maximal size of a single POS data file in bytes.
+    /// Must be a multiple of the label size (16B).
+    pub max_file_size: u64,
+    /// The minimal number of digits of the file index in file names (zero-padded).
+    /// `0` gives the default `postdata_N.bin` naming.
+    /// Padding keeps the files sorted in object storage listings.
+    pub file_index_width: u8,
+}
+
+impl Default for FileLayout {
+    fn default() -> Self {
+        Self {
+            max_file_size: 4 * 1024 * 1024 * 1024,
+            file_index_width: 0,
+        }
+    }
+}
+
+/// A [FileLayout] whose files can't hold whole labels.
+#[derive(Debug, thiserror::Error)]
+#[error("max file size ({0}) must be a positive multiple of label size")]
+pub struct InvalidFileSize(pub u64);
+
+impl FileLayout {
+    /// Check that every file holds a positive number of whole labels.
+    pub fn validate(&self) -> Result<(), InvalidFileSize> {
+        if self.max_file_size == 0 || self.max_file_size % LABEL_SIZE as u64 != 0 {
+            return Err(InvalidFileSize(self.max_file_size));
+        }
+        Ok(())
+    }
+
+    pub fn labels_per_file(&self) -> u64 {
+        self.max_file_size / LABEL_SIZE as u64
+    }
+
+    /// Name of the POS data file with the given index.
+    pub fn file_name(&self, idx: u64) -> String {
+        format!(
+            "postdata_{idx:0width$}.bin",
+            width = self.file_index_width as usize
+        )
+    }
+}
+
 #[repr(C)]
 #[serde_with::serde_as]
 #[derive(Debug, Clone, Copy, Deserialize)]
@@ -32,7 +85,7 @@ pub struct ProofConfig {
 }
 
 #[repr(C)]
-#[derive(Debug, Clone, Copy, Deserialize)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
 pub struct ScryptParams {
     pub n: usize,
     pub r: usize,
@@ -58,3 +111,37 @@ impl From<ScryptParams> for scrypt_jane::scrypt::ScryptParams {
         )
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::FileLayout;
+
+    #[test]
+    fn file_names() {
+        let layout = FileLayout::default();
+        assert_eq!("postdata_0.bin", layout.file_name(0));
+        assert_eq!("postdata_123.bin", layout.file_name(123));
+
+        let layout = FileLayout {
+            file_index_width: 4,
+            ..layout
+        };
+        assert_eq!("postdata_0000.bin", layout.file_name(0));
+        assert_eq!("postdata_0123.bin", layout.file_name(123));
+        assert_eq!("postdata_12345.bin", layout.file_name(12345));
+    }
+
+    #[test]
+    fn validating_file_size() {
+        let layout = FileLayout::default();
+        assert!(layout.validate().is_ok());
+        for max_file_size in [0, 15, 17] {
+            assert!(FileLayout {
+                max_file_size,
+                ..layout
+            }
+            .validate()
+            .is_err());
+        }
+    }
+}
@@ -0,0 +1,263 @@
+//! At-rest encryption of POS data.
+//!
+//! The POS data is encrypted with AES-128 in CTR mode. The counter of a block
+//! is its index in the whole POS data (across all files), so any part of the data
+//! can be decrypted independently while reading it for proving.
+//!
+//! The key is derived from an operator passphrase with scrypt, salted with the
+//! commitment, so the same passphrase gives different keys for different identities.
+
+use std::{error::Error, io::Write, ops::Range, path::Path};
+
+use aes::{
+    cipher::{generic_array::GenericArray, BlockEncrypt, KeyInit},
+    Aes128,
+};
+use scrypt_jane::scrypt::scrypt;
+
+use crate::{
+    config::{FileLayout, ScryptParams},
+    initialize::{self, Initialize, ProgressObserver, VrfNonce},
+    metadata::PostMetadata,
+};
+
+const BLOCK_SIZE: usize = 16;
+/// Number of keystream blocks generated at once.
+const KEYSTREAM_BLOCKS: usize = 64;
+
+/// Key for encrypting and decrypting POS data.
+#[derive(Clone)]
+pub struct DataKey {
+    aes: Aes128,
+    check: [u8; 16],
+}
+
+impl std::fmt::Debug for DataKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("DataKey")
+            .field("check", &self.check)
+            .finish_non_exhaustive()
+    }
+}
+
+impl DataKey {
+    /// Derive the key from the passphrase for the POS data of the given commitment.
+    pub fn derive(passphrase: &[u8], commitment: &[u8; 32]) -> Self {
+        let mut key = [0u8; 16];
+        let params = ScryptParams::new(32768, 8, 1);
+        scrypt(passphrase, commitment, params.into(), &mut key);
+        Self::from_bytes(key)
+    }
+
+    pub fn from_bytes(key: [u8; 16]) -> Self {
+        let mut hasher = blake3::Hasher::new();
+        hasher.update(b"post-data-key-check");
+        hasher.update(&key);
+        let mut check = [0u8; 16];
+        check.copy_from_slice(&hasher.finalize().as_bytes()[..16]);
+        Self {
+            aes: Aes128::new(GenericArray::from_slice(&key)),
+            check,
+        }
+    }
+
+    /// Value stored in the metadata to recognize the key without revealing it.
+    pub fn check(&self) -> [u8; 16] {
+        self.check
+    }
+
+    /// Ensure that this key was used to encrypt the POS data described by `metadata`.
+    pub fn validate(&self, metadata: &PostMetadata) -> eyre::Result<()> {
+        match metadata.encryption_key_check {
+            Some(check) if check == self.check => Ok(()),
+            Some(_) => Err(eyre::eyre!("invalid POS data key")),
+            None => Err(eyre::eyre!("POS data is not encrypted")),
+        }
+    }
+
+    /// Encrypt (or decrypt) `data` located at byte offset `pos` in the POS data.
+    pub fn apply_keystream(&self, mut data: &mut [u8], pos: u64) {
+        let mut block_idx = pos / BLOCK_SIZE as u64;
+        let mut offset = (pos % BLOCK_SIZE as u64) as usize;
+        let mut blocks = [GenericArray::default(); KEYSTREAM_BLOCKS];
+
+        while !data.is_empty() {
+            let count = (offset + data.len())
+                .div_ceil(BLOCK_SIZE)
+                .min(KEYSTREAM_BLOCKS);
+            for (i, block) in blocks[..count].iter_mut().enumerate() {
+                *block = GenericArray::from((block_idx as u128 + i as u128).to_le_bytes());
+            }
+            self.aes.encrypt_blocks(&mut blocks[..count]);
+
+            let len = (count * BLOCK_SIZE - offset).min(data.len());
+            let keystream = blocks[..count].iter().flatten().skip(offset);
+            for (byte, key) in data[..len].iter_mut().zip(keystream) {
+                *byte ^= key;
+            }
+            data = &mut std::mem::take(&mut data)[len..];
+            block_idx += count as u64;
+            offset = 0;
+        }
+    }
+}
+
+/// Writer encrypting the data written to the inner writer.
+pub struct EncryptingWriter<'a> {
+    inner: &'a mut dyn Write,
+    key: &'a DataKey,
+    pos: u64,
+    buf: Vec<u8>,
+}
+
+impl<'a> EncryptingWriter<'a> {
+    /// Create a writer of the POS data starting at byte offset `pos`.
+    pub fn new(inner: &'a mut dyn Write, key: &'a DataKey, pos: u64) -> Self {
+        Self {
+            inner,
+            key,
+            pos,
+            buf: Vec::new(),
+        }
+    }
+}
+
+impl Write for EncryptingWriter<'_> {
+    fn write(&mut self, data: &[u8]) -> std::io::Result<usize> {
+        self.buf.clear();
+        self.buf.extend_from_slice(data);
+        self.key.apply_keystream(&mut self.buf, self.pos);
+        self.inner.write_all(&self.buf)?;
+        self.pos += data.len() as u64;
+        Ok(data.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Initializer encrypting the labels initialized by the inner initializer.
+///
+/// The key is recorded in the metadata (as [DataKey::check]) of the initialized POS data.
+pub struct EncryptingInitializer<I: Initialize> {
+    inner: I,
+    key: DataKey,
+}
+
+impl<I: Initialize> EncryptingInitializer<I> {
+    pub fn new(inner: I, key: DataKey) -> Self {
+        Self { inner, key }
+    }
+}
+
+impl<I: Initialize> Initialize for EncryptingInitializer<I> {
+    fn initialize_with_progress(
+        &mut self,
+        datadir: &Path,
+        node_id: &[u8; 32],
+        commitment_atx_id: &[u8; 32],
+        labels_per_unit: u64,
+        num_units: u32,
+        layout: FileLayout,
+        vrf_difficulty: Option<[u8; 32]>,
+        progress: &mut dyn ProgressObserver,
+    ) -> Result<PostMetadata, Box<dyn Error>> {
+        let check = self.key.check();
+        initialize::initialize_files(
+            self,
+            datadir,
+            node_id,
+            commitment_atx_id,
+            labels_per_unit,
+            num_units,
+            layout,
+            vrf_difficulty,
+            progress,
+            Some(check),
+        )
+    }
+
+    fn initialize_to(
+        &mut self,
+        writer: &mut dyn Write,
+        commitment: &[u8; 32],
+        labels: Range<u64>,
+        vrf_difficulty: Option<[u8; 32]>,
+    ) -> Result<Option<VrfNonce>, Box<dyn Error>> {
+        let pos = labels.start * initialize::LABEL_SIZE as u64;
+        let mut writer = EncryptingWriter::new(writer, &self.key, pos);
+        self.inner
+            .initialize_to(&mut writer, commitment, labels, vrf_difficulty)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use proptest::prelude::*;
+
+    use crate::initialize::{CpuInitializer, LABEL_SIZE};
+
+    use super::*;
+
+    proptest! {
+        #[test]
+        fn decrypting_any_part(data in prop::collection::vec(any::<u8>(), 0..2000), start in 0..2000usize, len in 0..2000usize, pos in 0..1_000_000u64) {
+            let key = DataKey::from_bytes([7; 16]);
+            let mut encrypted = data.clone();
+            key.apply_keystream(&mut encrypted, pos);
+
+            let start = start.min(data.len());
+            let end = (start + len).min(data.len());
+            let mut part = encrypted[start..end].to_vec();
+            key.apply_keystream(&mut part, pos + start as u64);
+            prop_assert_eq!(&data[start..end], part.as_slice());
+        }
+    }
+
+    #[test]
+    fn keystream_depends_on_position() {
+        let key = DataKey::from_bytes([1; 16]);
+        let mut a = [0u8; 32];
+        let mut b = [0u8; 32];
+        key.apply_keystream(&mut a, 0);
+        key.apply_keystream(&mut b, 16);
+        assert_ne!(a, [0u8; 32]);
+        assert_eq!(a[16..], b[..16]);
+    }
+
+    #[test]
+    fn derived_key_depends_on_passphrase_and_commitment() {
+        let key = DataKey::derive(b"secret", &[0; 32]);
+        assert_eq!(key.check(), DataKey::derive(b"secret", &[0; 32]).check());
+        assert_ne!(key.check(), DataKey::derive(b"secret", &[1; 32]).check());
+        assert_ne!(key.check(), DataKey::derive(b"other", &[0; 32]).check());
+    }
+
+    #[test]
+    fn initializing_encrypted_data() {
+        let params = ScryptParams::new(2, 1, 1);
+        let datadir = tempfile::tempdir().unwrap();
+        let key = DataKey::from_bytes([3; 16]);
+        let metadata = EncryptingInitializer::new(CpuInitializer::new(params), key.clone())
+            .initialize(datadir.path(), &[0; 32], &[0; 32], 100, 2, 64, None)
+            .unwrap();
+        key.validate(&metadata).unwrap();
+        assert!(DataKey::from_bytes([4; 16]).validate(&metadata).is_err());
+
+        let commitment = initialize::calc_commitment(&[0; 32], &[0; 32]);
+        let mut expected = Vec::new();
+        CpuInitializer::new(params)
+            .initialize_to(&mut expected, &commitment, 0..200, None)
+            .unwrap();
+
+        let mut data = Vec::new();
+        for id in 0..4 {
+            data.extend(std::fs::read(datadir.path().join(format!("postdata_{id}.bin"))).unwrap());
+        }
+        assert_ne!(expected, data);
+        key.apply_keystream(&mut data, 0);
+        assert_eq!(expected, data);
+        assert_eq!(200 * LABEL_SIZE, data.len());
+    }
+}
@@ -1,16 +1,34 @@
 use std::{
     error::Error,
     fs::{create_dir_all, File},
-    io::Write,
+    io::{BufWriter, Read, Seek, SeekFrom, Write},
     ops::Range,
     path::Path,
 };
 
+use eyre::Context;
 use mockall::automock;
-use rayon::prelude::{IntoParallelIterator, ParallelIterator};
+use rayon::prelude::{
+    IndexedParallelIterator, IntoParallelIterator, IntoParallelRefIterator, ParallelIterator,
+};
 use scrypt_jane::scrypt::scrypt;
 
-use crate::{config::ScryptParams, metadata::PostMetadata};
+use crate::{
+    config::{FileLayout, ScryptParams},
+    metadata::{self, PostMetadata},
+    rate_limit::TokenBucket,
+};
+
+mod estimate;
+mod hybrid;
+mod preallocate;
+mod relocate;
+mod stream;
+
+pub use estimate::{estimate, Estimate};
+pub use hybrid::{HybridInitializer, WorkerStats};
+pub use relocate::{relocate, RelocateOptions};
+pub use stream::LabelStream;
 
 pub const LABEL_SIZE: usize = 16;
 pub const ENTIRE_LABEL_SIZE: usize = 32;
@@ -28,6 +46,92 @@ pub struct VrfNonce {
     pub label: [u8; 32],
 }
 
+/// Progress of initialization reported to a [ProgressObserver].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Progress {
+    /// ID of the file being initialized.
+    pub file_id: u64,
+    /// Number of labels written so far (including files completed before resuming).
+    pub labels_written: u64,
+    /// Total number of labels to initialize.
+    pub total_labels: u64,
+}
+
+/// Observes progress of initialization.
+pub trait ProgressObserver {
+    fn on_progress(&mut self, progress: Progress);
+}
+
+impl<F: FnMut(Progress)> ProgressObserver for F {
+    fn on_progress(&mut self, progress: Progress) {
+        self(progress)
+    }
+}
+
+/// Writer reporting the number of labels written to a [ProgressObserver].
+struct ProgressWriter<'a> {
+    writer: &'a mut dyn Write,
+    observer: &'a mut dyn ProgressObserver,
+    progress: Progress,
+    bytes_written: u64,
+}
+
+impl Write for ProgressWriter<'_> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let written = self.writer.write(buf)?;
+        let labels_before = self.bytes_written / LABEL_SIZE as u64;
+        self.bytes_written += written as u64;
+        let labels = self.bytes_written / LABEL_SIZE as u64;
+        if labels > labels_before {
+            self.progress.labels_written += labels - labels_before;
+            self.observer.on_progress(self.progress);
+        }
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+/// Writer calculating BLAKE3 checksum of the data written.
+struct HashingWriter<W: Write> {
+    writer: W,
+    hasher: blake3::Hasher,
+}
+
+impl<W: Write> HashingWriter<W> {
+    fn new(writer: W) -> Self {
+        Self {
+            writer,
+            hasher: blake3::Hasher::new(),
+        }
+    }
+
+    fn finish(self) -> (W, [u8; 32]) {
+        (self.writer, self.hasher.finalize().into())
+    }
+}
+
+impl<W: Write> Write for HashingWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let written = self.writer.write(buf)?;
+        self.hasher.update(&buf[..written]);
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+/// Calculate BLAKE3 checksum of the file.
+pub fn file_checksum(path: &Path) -> std::io::Result<[u8; 32]> {
+    let mut hasher = blake3::Hasher::new();
+    std::io::copy(&mut File::open(path)?, &mut hasher)?;
+    Ok(hasher.finalize().into())
+}
+
 #[automock]
 pub trait Initialize {
     #[allow(clippy::too_many_arguments)]
@@ -39,45 +143,82 @@ pub trait Initialize {
         labels_per_unit: u64,
         num_units: u32,
         labels_per_file: u64,
-        mut vrf_difficulty: Option<[u8; 32]>,
+        vrf_difficulty: Option<[u8; 32]>,
     ) -> Result<PostMetadata, Box<dyn Error>> {
-        // Ensure that datadir exists
-        create_dir_all(datadir)?;
-
-        let commitment = calc_commitment(node_id, commitment_atx_id);
+        self.initialize_with_progress(
+            datadir,
+            node_id,
+            commitment_atx_id,
+            labels_per_unit,
+            num_units,
+            FileLayout {
+                max_file_size: labels_per_file * LABEL_SIZE as u64,
+                ..Default::default()
+            },
+            vrf_difficulty,
+            &mut |_: Progress| {},
+        )
+    }
 
-        let total_labels = labels_per_unit * num_units as u64;
+    /// Initialize POS data in `datadir` with the given files `layout`,
+    /// reporting progress to the `progress` observer.
+    #[allow(clippy::too_many_arguments)]
+    fn initialize_with_progress(
+        &mut self,
+        datadir: &Path,
+        node_id: &[u8; 32],
+        commitment_atx_id: &[u8; 32],
+        labels_per_unit: u64,
+        num_units: u32,
+        layout: FileLayout,
+        vrf_difficulty: Option<[u8; 32]>,
+        progress: &mut dyn ProgressObserver,
+    ) -> Result<PostMetadata, Box<dyn Error>> {
+        initialize_files(
+            self,
+            datadir,
+            node_id,
+            commitment_atx_id,
+            labels_per_unit,
+            num_units,
+            layout,
+            vrf_difficulty,
+            progress,
+            None,
+        )
+    }
 
-        let mut files_number = total_labels / labels_per_file;
-        if total_labels % labels_per_file != 0 {
-            files_number += 1;
-        }
-        let mut nonce = None;
-        for file_id in 0..files_number {
-            let mut post_data = File::create(datadir.join(format!("postdata_{}.bin", file_id)))?;
-            let index = file_id * labels_per_file;
-            let labels = index..total_labels.min(index + labels_per_file);
-            let new_nonce =
-                self.initialize_to(&mut post_data, &commitment, labels, vrf_difficulty)?;
-            if let Some(n) = new_nonce {
-                vrf_difficulty = Some(n.label);
-                nonce = Some(n);
-            }
-        }
+    fn initialize_to(
+        &mut self,
+        writer: &mut dyn Write,
+        commitment: &[u8; 32],
+        labels: Range<u64>,
+        vrf_difficulty: Option<[u8; 32]>,
+    ) -> Result<Option<VrfNonce>, Box<dyn Error>>;
+}
 
-        let metadata = PostMetadata {
-            node_id: *node_id,
-            commitment_atx_id: *commitment_atx_id,
+impl<T: Initialize + ?Sized> Initialize for Box<T> {
+    fn initialize_with_progress(
+        &mut self,
+        datadir: &Path,
+        node_id: &[u8; 32],
+        commitment_atx_id: &[u8; 32],
+        labels_per_unit: u64,
+        num_units: u32,
+        layout: FileLayout,
+        vrf_difficulty: Option<[u8; 32]>,
+        progress: &mut dyn ProgressObserver,
+    ) -> Result<PostMetadata, Box<dyn Error>> {
+        (**self).initialize_with_progress(
+            datadir,
+            node_id,
+            commitment_atx_id,
             labels_per_unit,
             num_units,
-            max_file_size: labels_per_file * 16,
-            nonce: nonce.map(|n| n.index),
-            last_position: None,
-        };
-        let metadata_file = File::create(datadir.join("postdata_metadata.json"))?;
-        serde_json::to_writer_pretty(metadata_file, &metadata)?;
-
-        Ok(metadata)
+            layout,
+            vrf_difficulty,
+            progress,
+        )
     }
 
     fn initialize_to(
@@ -86,19 +227,542 @@ pub trait Initialize {
         commitment: &[u8; 32],
         labels: Range<u64>,
         vrf_difficulty: Option<[u8; 32]>,
-    ) -> Result<Option<VrfNonce>, Box<dyn Error>>;
+    ) -> Result<Option<VrfNonce>, Box<dyn Error>> {
+        (**self).initialize_to(writer, commitment, labels, vrf_difficulty)
+    }
+}
+
+/// Initialize POS data files in `datadir` with the `initializer`.
+///
+/// It's the implementation of [Initialize::initialize_with_progress]
+/// and records `encryption_key_check` in the metadata.
+#[allow(clippy::too_many_arguments)]
+pub(crate) fn initialize_files<I: Initialize + ?Sized>(
+    initializer: &mut I,
+    datadir: &Path,
+    node_id: &[u8; 32],
+    commitment_atx_id: &[u8; 32],
+    labels_per_unit: u64,
+    num_units: u32,
+    layout: FileLayout,
+    mut vrf_difficulty: Option<[u8; 32]>,
+    progress: &mut dyn ProgressObserver,
+    encryption_key_check: Option<[u8; 16]>,
+) -> Result<PostMetadata, Box<dyn Error>> {
+    layout.validate()?;
+    let labels_per_file = layout.labels_per_file();
+
+    // Ensure that datadir exists
+    create_dir_all(datadir)?;
+
+    let commitment = calc_commitment(node_id, commitment_atx_id);
+
+    let total_labels = labels_per_unit * num_units as u64;
+
+    let mut files_number = total_labels / labels_per_file;
+    if total_labels % labels_per_file != 0 {
+        files_number += 1;
+    }
+
+    let mut metadata = PostMetadata {
+        node_id: *node_id,
+        commitment_atx_id: *commitment_atx_id,
+        labels_per_unit,
+        num_units,
+        max_file_size: layout.max_file_size,
+        file_index_width: layout.file_index_width,
+        nonce: None,
+        last_position: None,
+        encryption_key_check,
+    };
+
+    let (first_label, resumed_nonce) = resume_point(datadir, &metadata)?;
+    let first_file = first_label / labels_per_file;
+    let mut nonce = None;
+    if let (Some(index), Some(_)) = (resumed_nonce, vrf_difficulty) {
+        // Recalculate the full label of the nonce to continue the search from it.
+        nonce = initializer.initialize_to(
+            &mut std::io::sink(),
+            &commitment,
+            index..index + 1,
+            Some([0xFFu8; 32]),
+        )?;
+        if let Some(n) = nonce {
+            vrf_difficulty = vrf_difficulty.map(|d| d.min(n.label));
+        }
+    }
+    if first_label > 0 {
+        log::info!(
+            "resuming initialization from label {first_label} in file {first_file} (nonce: {nonce:?})"
+        );
+    }
+
+    let mut checksums = match first_file {
+        0 => Vec::new(),
+        _ => metadata::load_checksums(datadir)?,
+    };
+    checksums.truncate(first_file as usize);
+    // Completed files might lack checksums if initialized by an older version.
+    for file_id in checksums.len() as u64..first_file {
+        checksums.push(file_checksum(&datadir.join(layout.file_name(file_id)))?);
+    }
+
+    ensure_disk_space(datadir, layout, first_label, total_labels)?;
+
+    for file_id in first_file..files_number {
+        let path = datadir.join(layout.file_name(file_id));
+        let index = file_id * labels_per_file;
+        let labels = first_label.max(index)..total_labels.min(index + labels_per_file);
+        let file = if labels.start > index {
+            // Append to the file filled partially before growing (see [grow]).
+            File::options().read(true).append(true).open(&path)?
+        } else {
+            File::create(&path)?
+        };
+        preallocate::preallocate(&file, (labels.end - index) * LABEL_SIZE as u64)
+            .map_err(|e| format!("preallocating {}: {e}", path.display()))?;
+        let mut post_data = if labels.start > index {
+            let mut hasher = blake3::Hasher::new();
+            std::io::copy(&mut (&file), &mut hasher)?;
+            HashingWriter {
+                writer: file,
+                hasher,
+            }
+        } else {
+            HashingWriter::new(file)
+        };
+        let labels_end = labels.end;
+        let mut writer = ProgressWriter {
+            writer: &mut post_data,
+            observer: progress,
+            progress: Progress {
+                file_id,
+                labels_written: labels.start,
+                total_labels,
+            },
+            bytes_written: 0,
+        };
+        let new_nonce =
+            initializer.initialize_to(&mut writer, &commitment, labels, vrf_difficulty)?;
+        if let Some(n) = new_nonce {
+            vrf_difficulty = Some(n.label);
+            nonce = Some(n);
+        }
+        let (post_data, checksum) = post_data.finish();
+        post_data.sync_all()?;
+        checksums.push(checksum);
+
+        // Checkpoint the progress to be able to resume if interrupted.
+        metadata.nonce = nonce.map(|n| n.index);
+        metadata.last_position = Some(labels_end);
+        metadata::save_with_checksums(datadir, &metadata, &checksums)?;
+    }
+
+    metadata.nonce = nonce.map(|n| n.index);
+    metadata.last_position = None;
+    metadata::save_with_checksums(datadir, &metadata, &checksums)?;
+
+    Ok(metadata)
+}
+
+/// Check if there is enough disk space to write `labels` to the POS data files in `datadir`.
+///
+/// Space taken by the files to be overwritten or appended to is considered available.
+fn ensure_disk_space(
+    datadir: &Path,
+    layout: FileLayout,
+    first_label: u64,
+    total_labels: u64,
+) -> Result<(), Box<dyn Error>> {
+    let Some(available) = preallocate::available_space(datadir)? else {
+        return Ok(());
+    };
+    let labels_per_file = layout.labels_per_file();
+    let mut needed = 0;
+    for file_id in first_label / labels_per_file..total_labels.div_ceil(labels_per_file) {
+        let index = file_id * labels_per_file;
+        let size = (total_labels.min(index + labels_per_file) - index) * LABEL_SIZE as u64;
+        // Space preallocated by an interrupted initialization is already taken.
+        let existing = std::fs::metadata(datadir.join(layout.file_name(file_id)))
+            .map(|m| preallocate::allocated_space(&m))
+            .unwrap_or(0);
+        needed += size.saturating_sub(existing);
+    }
+    if needed > available {
+        return Err(format!(
+            "not enough disk space in {}: {needed} bytes needed, {available} bytes available",
+            datadir.display()
+        )
+        .into());
+    }
+    Ok(())
+}
+
+/// Grow the POS data in `datadir` to `num_units`.
+///
+/// The labels of the new units are appended to the existing data (filling up
+/// the last file first) and the search for the VRF nonce is continued over them.
+/// The metadata is updated first to mark the new labels as not initialized yet,
+/// so an interrupted growing is resumed as an interrupted initialization.
+///
+/// The `initializer` must match the one used to initialize the data
+/// (i.e. encrypt the data with the same key).
+pub fn grow<I: Initialize + ?Sized>(
+    initializer: &mut I,
+    datadir: &Path,
+    num_units: u32,
+    vrf_difficulty: Option<[u8; 32]>,
+    progress: &mut dyn ProgressObserver,
+) -> Result<PostMetadata, Box<dyn Error>> {
+    let existing = metadata::load(datadir)?;
+    if existing.last_position.is_some() {
+        return Err("initialization is in progress, it must be completed first".into());
+    }
+    if num_units <= existing.num_units {
+        return Err(format!(
+            "number of units ({num_units}) must be greater than the current one ({})",
+            existing.num_units
+        )
+        .into());
+    }
+    log::info!(
+        "growing POS data in {} from {} to {num_units} units",
+        datadir.display(),
+        existing.num_units
+    );
+
+    let grown = PostMetadata {
+        num_units,
+        last_position: Some(existing.total_labels()),
+        ..existing
+    };
+    metadata::save(datadir, &grown)?;
+
+    initializer.initialize_with_progress(
+        datadir,
+        &existing.node_id,
+        &existing.commitment_atx_id,
+        existing.labels_per_unit,
+        num_units,
+        existing.layout(),
+        vrf_difficulty,
+        progress,
+    )
+}
+
+/// Shrink the POS data in `datadir` to `num_units`.
+///
+/// The trailing labels are removed by truncating the last kept file and
+/// removing the files after it. If the VRF nonce is among the removed labels,
+/// a new one is searched for with the `initializer` in the kept labels (only if
+/// `vrf_difficulty` is given, otherwise it fails without modifying anything).
+///
+/// The metadata is updated before removing the labels, so shrinking can be
+/// safely repeated if interrupted.
+pub fn shrink<I: Initialize + ?Sized>(
+    initializer: &mut I,
+    datadir: &Path,
+    num_units: u32,
+    vrf_difficulty: Option<[u8; 32]>,
+) -> Result<PostMetadata, Box<dyn Error>> {
+    let existing = metadata::load(datadir)?;
+    if existing.last_position.is_some() {
+        return Err("initialization is in progress, it must be completed first".into());
+    }
+    if num_units == 0 || num_units > existing.num_units {
+        return Err(format!(
+            "number of units ({num_units}) must be positive and not greater than the current one ({})",
+            existing.num_units
+        )
+        .into());
+    }
+    let shrunk = PostMetadata {
+        num_units,
+        ..existing
+    };
+    let total_labels = shrunk.total_labels();
+
+    let mut metadata = shrunk;
+    if let Some(nonce) = existing.nonce.filter(|&n| n >= total_labels) {
+        let Some(difficulty) = vrf_difficulty else {
+            return Err(format!(
+                "VRF nonce {nonce} is in the labels to remove and VRF difficulty is not given"
+            )
+            .into());
+        };
+        log::info!("VRF nonce {nonce} is in the labels to remove, searching for a new one");
+        let commitment = calc_commitment(&existing.node_id, &existing.commitment_atx_id);
+        let new_nonce = initializer.initialize_to(
+            &mut std::io::sink(),
+            &commitment,
+            0..total_labels,
+            Some(difficulty),
+        )?;
+        match new_nonce {
+            Some(n) => metadata.nonce = Some(n.index),
+            None => return Err("no VRF nonce found in the labels to keep".into()),
+        }
+    }
+
+    // The checksum of the last kept file after truncating it.
+    let mut checksums = metadata::load_checksums(datadir)?;
+    if checksums.len() >= metadata.num_files() {
+        let last = metadata.num_files() - 1;
+        let size = metadata.labels_in_file(last) as u64 * LABEL_SIZE as u64;
+        let file = File::open(datadir.join(metadata.file_name(last)))?;
+        let mut hasher = blake3::Hasher::new();
+        std::io::copy(&mut file.take(size), &mut hasher)?;
+        checksums.truncate(last);
+        checksums.push(hasher.finalize().into());
+    } else {
+        checksums.clear();
+    }
+    metadata::save_with_checksums(datadir, &metadata, &checksums)?;
+
+    let last = metadata.num_files() - 1;
+    File::options()
+        .write(true)
+        .open(datadir.join(metadata.file_name(last)))?
+        .set_len(metadata.labels_in_file(last) as u64 * LABEL_SIZE as u64)?;
+    for entry in crate::reader::pos_files(datadir)?.skip(last + 1) {
+        log::info!("removing POS file {}", entry.path().display());
+        std::fs::remove_file(entry.path())?;
+    }
+
+    Ok(metadata)
+}
+
+/// Find where to resume an interrupted initialization from.
+///
+/// Files already completed (according to the checkpoint in the metadata) are
+/// validated by their sizes. Returns the index of the first label to initialize
+/// and the index of the VRF nonce found in the labels before it.
+fn resume_point(datadir: &Path, expected: &PostMetadata) -> eyre::Result<(u64, Option<u64>)> {
+    let Some(existing) = metadata::load_if_exists(datadir)? else {
+        return Ok((0, None));
+    };
+    eyre::ensure!(
+        existing.node_id == expected.node_id
+            && existing.commitment_atx_id == expected.commitment_atx_id
+            && existing.labels_per_unit == expected.labels_per_unit
+            && existing.num_units == expected.num_units
+            && existing.layout() == expected.layout()
+            && existing.encryption_key_check == expected.encryption_key_check,
+        "existing POS data in {} was initialized with different parameters: {existing:?}",
+        datadir.display()
+    );
+
+    let layout = expected.layout();
+    let labels_per_file = layout.labels_per_file();
+    let initialized = existing
+        .last_position
+        .unwrap_or(existing.total_labels())
+        .min(expected.total_labels());
+
+    let mut first_file = 0;
+    while first_file * labels_per_file < initialized {
+        let start = first_file * labels_per_file;
+        let end = expected.total_labels().min(start + labels_per_file);
+        if end > initialized {
+            break;
+        }
+        let path = datadir.join(layout.file_name(first_file));
+        let size = path.metadata().map(|m| m.len()).unwrap_or_default();
+        let expected_size = (end - start) * LABEL_SIZE as u64;
+        if size != expected_size {
+            log::warn!(
+                "POS file {} is incomplete (size: {size}, expected: {expected_size})",
+                path.display(),
+            );
+            break;
+        }
+        first_file += 1;
+    }
+
+    let mut first_label = first_file * labels_per_file;
+    if first_label < initialized {
+        // The checkpoint is in the middle of the file only after growing (see [grow]),
+        // when the file was completely filled with the labels before it.
+        let path = datadir.join(layout.file_name(first_file));
+        let size = path.metadata().map(|m| m.len()).unwrap_or_default();
+        if size == (initialized - first_label) * LABEL_SIZE as u64 {
+            first_label = initialized;
+        }
+    }
+
+    match existing.nonce {
+        // The nonce was found in labels that must be initialized again.
+        // It's not known which label is the best in the labels before it.
+        Some(nonce) if nonce >= first_label => {
+            log::warn!("VRF nonce {nonce} is in a file to be reinitialized, starting over");
+            Ok((0, None))
+        }
+        nonce => Ok((first_label, nonce)),
+    }
 }
 
 pub struct CpuInitializer {
     scrypt_params: ScryptParams,
+    pool: Option<rayon::ThreadPool>,
+    write_limiter: Option<TokenBucket>,
 }
 
 impl CpuInitializer {
+    /// Number of labels computed in parallel before writing them out in order.
+    const BATCH_SIZE: usize = 64 * 1024;
+
+    /// Create an initializer using the global rayon thread pool.
     pub fn new(scrypt_params: ScryptParams) -> Self {
-        Self { scrypt_params }
+        Self {
+            scrypt_params,
+            pool: None,
+            write_limiter: None,
+        }
+    }
+
+    /// Create an initializer using a dedicated pool of `threads` threads.
+    /// '0' means use all available threads.
+    pub fn with_threads(scrypt_params: ScryptParams, threads: usize) -> eyre::Result<Self> {
+        let pool = rayon::ThreadPoolBuilder::new()
+            .num_threads(threads)
+            .thread_name(|id| format!("cpu-initializer-{id}"))
+            .build()
+            .wrap_err("building thread pool")?;
+        Ok(Self {
+            scrypt_params,
+            pool: Some(pool),
+            write_limiter: None,
+        })
+    }
+
+    /// Limit the rate of writing labels to `bytes_per_sec`.
+    ///
+    /// Useful on shared machines to not starve other disk users.
+    pub fn with_write_rate_limit(mut self, bytes_per_sec: u64) -> Self {
+        self.write_limiter = Some(TokenBucket::new(bytes_per_sec));
+        self
+    }
+
+    fn scrypt_labels(&self, commitment: &[u8; 32], labels: Range<u64>) -> Vec<[u8; 32]> {
+        let params = self.scrypt_params;
+        let compute = || {
+            labels
+                .into_par_iter()
+                .map(|index| scrypt_label(commitment, params, index))
+                .collect::<Vec<_>>()
+        };
+        match &self.pool {
+            Some(pool) => pool.install(compute),
+            None => compute(),
+        }
+    }
+
+    /// Variant of [Initialize::initialize_to] computing labels in chunks of
+    /// `chunk_size` labels, with computing overlapped with writing.
+    ///
+    /// The chunks are computed by the thread pool, in windows of one chunk per thread,
+    /// on a separate thread. A window is written out in order while the next one is computed.
+    pub fn initialize_to_chunked(
+        &mut self,
+        writer: &mut dyn Write,
+        commitment: &[u8; 32],
+        labels: Range<u64>,
+        mut vrf_difficulty: Option<[u8; 32]>,
+        chunk_size: usize,
+    ) -> Result<Option<VrfNonce>, Box<dyn Error>> {
+        let chunk_size = chunk_size.max(1);
+        let threads = match &self.pool {
+            Some(pool) => pool.current_num_threads(),
+            None => rayon::current_num_threads(),
+        };
+        let window = chunk_size * threads;
+        let params = self.scrypt_params;
+        let pool = self.pool.as_ref();
+        let write_limiter = &mut self.write_limiter;
+        let mut best_nonce = None;
+
+        std::thread::scope(|s| {
+            // Holds a single window computed ahead of the one being written.
+            let (tx, rx) = std::sync::mpsc::sync_channel::<(u64, Vec<Vec<[u8; 32]>>)>(1);
+            let labels_end = labels.end;
+            s.spawn(move || {
+                for window_start in labels.step_by(window) {
+                    let window_end = labels_end.min(window_start + window as u64);
+                    let compute = || {
+                        (window_start..window_end)
+                            .step_by(chunk_size)
+                            .collect::<Vec<_>>()
+                            .into_par_iter()
+                            .map(|start| {
+                                (start..window_end.min(start + chunk_size as u64))
+                                    .map(|index| scrypt_label(commitment, params, index))
+                                    .collect::<Vec<_>>()
+                            })
+                            .collect::<Vec<_>>()
+                    };
+                    let chunks = match pool {
+                        Some(pool) => pool.install(compute),
+                        None => compute(),
+                    };
+                    if tx.send((window_start, chunks)).is_err() {
+                        // Writing failed
+                        return;
+                    }
+                }
+            });
+
+            for (window_start, chunks) in rx {
+                let labels = chunks.into_iter().flatten().collect::<Vec<_>>();
+                if let Some(limiter) = write_limiter.as_mut() {
+                    limiter.take(labels.len() as u64 * LABEL_SIZE as u64);
+                }
+                write_labels(
+                    writer,
+                    labels,
+                    window_start,
+                    &mut vrf_difficulty,
+                    &mut best_nonce,
+                )?;
+            }
+            Ok::<_, Box<dyn Error>>(best_nonce)
+        })
     }
 }
 
+fn scrypt_label(commitment: &[u8; 32], params: ScryptParams, index: u64) -> [u8; 32] {
+    let mut label = [0u8; 32];
+    let mut scrypt_data = [0u8; 72];
+    scrypt_data[0..32].copy_from_slice(commitment);
+    scrypt_data[32..40].copy_from_slice(&index.to_le_bytes());
+    scrypt(&scrypt_data, &[], params.into(), &mut label);
+    label
+}
+
+/// Write out `labels` starting at index `first_index`,
+/// searching for the smallest label below `vrf_difficulty`.
+fn write_labels(
+    writer: &mut dyn Write,
+    labels: Vec<[u8; 32]>,
+    first_index: u64,
+    vrf_difficulty: &mut Option<[u8; 32]>,
+    best_nonce: &mut Option<VrfNonce>,
+) -> std::io::Result<()> {
+    for (id, label) in labels.into_iter().enumerate() {
+        if let Some(difficulty) = *vrf_difficulty {
+            if label < difficulty {
+                *best_nonce = Some(VrfNonce {
+                    index: first_index + id as u64,
+                    label,
+                });
+                *vrf_difficulty = Some(label);
+                log::trace!("Found new smallest nonce: {best_nonce:?}");
+            }
+        }
+        writer.write_all(&label[..16])?;
+    }
+    Ok(())
+}
+
 impl Initialize for CpuInitializer {
     fn initialize_to(
         &mut self,
@@ -107,32 +771,23 @@ impl Initialize for CpuInitializer {
         labels: Range<u64>,
         mut vrf_difficulty: Option<[u8; 32]>,
     ) -> Result<Option<VrfNonce>, Box<dyn Error>> {
-        let data = labels
-            .clone()
-            .into_par_iter()
-            .map(|index| {
-                let mut label = [0u8; 32];
-                let mut scrypt_data = [0u8; 72];
-                scrypt_data[0..32].copy_from_slice(commitment);
-                scrypt_data[32..40].copy_from_slice(&index.to_le_bytes());
-                scrypt(&scrypt_data, &[], self.scrypt_params.into(), &mut label);
-                label
-            })
-            .collect::<Vec<_>>();
-
         let mut best_nonce = None;
-        for (id, label) in data.into_iter().enumerate() {
-            if let Some(difficulty) = vrf_difficulty {
-                if label < difficulty {
-                    best_nonce = Some(VrfNonce {
-                        index: labels.start + id as u64,
-                        label,
-                    });
-                    vrf_difficulty = Some(label);
-                    log::trace!("Found new smallest nonce: {best_nonce:?}");
-                }
+        let labels_end = labels.end;
+
+        for batch_start in labels.step_by(Self::BATCH_SIZE) {
+            let batch_end = labels_end.min(batch_start + Self::BATCH_SIZE as u64);
+            let data = self.scrypt_labels(commitment, batch_start..batch_end);
+
+            if let Some(limiter) = &mut self.write_limiter {
+                limiter.take(data.len() as u64 * LABEL_SIZE as u64);
             }
-            writer.write_all(&label[..16])?;
+            write_labels(
+                writer,
+                data,
+                batch_start,
+                &mut vrf_difficulty,
+                &mut best_nonce,
+            )?;
         }
 
         Ok(best_nonce)
@@ -153,11 +808,266 @@ pub(crate) fn generate_label(commitment: &[u8; 32], params: ScryptParams, index:
     label
 }
 
+/// Integrity of a single POS data file.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct FileIntegrity {
+    pub file_id: usize,
+    /// The file doesn't exist.
+    pub missing: bool,
+    /// Number of sampled labels.
+    pub labels_checked: u64,
+    /// Indices (relative to the beginning of the file) of sampled labels
+    /// that are invalid or couldn't be read (i.e. the file is truncated).
+    pub corrupted_labels: Vec<u64>,
+}
+
+impl FileIntegrity {
+    pub fn is_ok(&self) -> bool {
+        !self.missing && self.corrupted_labels.is_empty()
+    }
+
+    /// Fraction of the sampled labels that are corrupted.
+    pub fn corruption_rate(&self) -> f64 {
+        match (self.missing, self.labels_checked) {
+            (true, _) => 1.0,
+            (false, 0) => 0.0,
+            (false, checked) => self.corrupted_labels.len() as f64 / checked as f64,
+        }
+    }
+}
+
+/// Result of [verify_data].
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct IntegrityReport {
+    pub files: Vec<FileIntegrity>,
+}
+
+impl IntegrityReport {
+    pub fn is_ok(&self) -> bool {
+        self.files.iter().all(FileIntegrity::is_ok)
+    }
+
+    /// Files that are missing or contain corrupted labels.
+    pub fn damaged_files(&self) -> impl Iterator<Item = &FileIntegrity> {
+        self.files.iter().filter(|f| !f.is_ok())
+    }
+}
+
+/// Verify integrity of POS data in `datadir`.
+///
+/// Samples `fraction` (in %, up to 100) of the labels in every file and recomputes
+/// them from the commitment. At least one label is checked in every file.
+/// Unlike [crate::pos_verification::verify_files], it doesn't stop at the first
+/// invalid label but reports the corruption of every file.
+pub fn verify_data(
+    datadir: &Path,
+    fraction: f64,
+    scrypt_params: ScryptParams,
+) -> eyre::Result<IntegrityReport> {
+    eyre::ensure!(
+        (0.0..=100.0).contains(&fraction),
+        "fraction of labels to verify ({fraction}%) must be between 0% and 100%"
+    );
+    let metadata = metadata::load(datadir).wrap_err("loading metadata")?;
+    eyre::ensure!(
+        metadata.encryption_key_check.is_none(),
+        "verifying encrypted POS data is not supported"
+    );
+    let commitment = calc_commitment(&metadata.node_id, &metadata.commitment_atx_id);
+    let labels_per_file = metadata.max_file_size / LABEL_SIZE as u64;
+
+    let mut rng = rand::thread_rng();
+    let mut files = Vec::with_capacity(metadata.num_files());
+    for file_id in 0..metadata.num_files() {
+        let path = datadir.join(metadata.file_name(file_id));
+        let mut file = match File::open(&path) {
+            Ok(file) => file,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                log::warn!("POS file {} is missing", path.display());
+                files.push(FileIntegrity {
+                    file_id,
+                    missing: true,
+                    ..Default::default()
+                });
+                continue;
+            }
+            Err(e) => return Err(e).wrap_err_with(|| format!("opening {}", path.display())),
+        };
+
+        let labels_in_file = metadata.labels_in_file(file_id) as u64;
+        let to_check = ((labels_in_file as f64 * fraction / 100.0) as usize)
+            .max(1)
+            .min(labels_in_file as usize);
+        let mut indices = rand::seq::index::sample(&mut rng, labels_in_file as usize, to_check)
+            .into_iter()
+            .map(|i| i as u64)
+            .collect::<Vec<_>>();
+        indices.sort_unstable();
+
+        let mut labels = Vec::with_capacity(indices.len());
+        for index in indices {
+            let mut label = [0u8; LABEL_SIZE];
+            file.seek(SeekFrom::Start(index * LABEL_SIZE as u64))?;
+            match file.read_exact(&mut label) {
+                Ok(()) => labels.push((index, Some(label))),
+                Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => {
+                    labels.push((index, None))
+                }
+                Err(e) => return Err(e).wrap_err_with(|| format!("reading {}", path.display())),
+            }
+        }
+
+        let labels_offset = file_id as u64 * labels_per_file;
+        let mut corrupted_labels = labels
+            .par_iter()
+            .filter(|(index, label)| {
+                label
+                    .map(|l| l != generate_label(&commitment, scrypt_params, labels_offset + index))
+                    .unwrap_or(true)
+            })
+            .map(|(index, _)| *index)
+            .collect::<Vec<_>>();
+        corrupted_labels.sort_unstable();
+
+        if !corrupted_labels.is_empty() {
+            log::warn!(
+                "found {} corrupted labels out of {} checked in {}",
+                corrupted_labels.len(),
+                labels.len(),
+                path.display()
+            );
+        }
+        files.push(FileIntegrity {
+            file_id,
+            missing: false,
+            labels_checked: labels.len() as u64,
+            corrupted_labels,
+        });
+    }
+
+    Ok(IntegrityReport { files })
+}
+
+/// Regenerate labels in range `labels` (relative to the beginning of the file)
+/// of POS file `file_id` in place.
+pub fn repair_labels(
+    initializer: &mut dyn Initialize,
+    datadir: &Path,
+    file_id: usize,
+    labels: Range<u64>,
+) -> eyre::Result<()> {
+    let metadata = metadata::load(datadir).wrap_err("loading metadata")?;
+    let labels_in_file = metadata.labels_in_file(file_id) as u64;
+    eyre::ensure!(
+        labels.end <= labels_in_file,
+        "labels {labels:?} out of file {file_id} with {labels_in_file} labels"
+    );
+
+    let path = datadir.join(metadata.file_name(file_id));
+    log::info!("repairing labels {labels:?} in {}", path.display());
+    let commitment = calc_commitment(&metadata.node_id, &metadata.commitment_atx_id);
+    let labels_offset = file_id as u64 * (metadata.max_file_size / LABEL_SIZE as u64);
+
+    let mut file = File::options()
+        .write(true)
+        .create(true)
+        .truncate(false)
+        .open(&path)
+        .wrap_err_with(|| format!("opening {}", path.display()))?;
+    file.seek(SeekFrom::Start(labels.start * LABEL_SIZE as u64))?;
+    let mut writer = BufWriter::new(file);
+    initializer
+        .initialize_to(
+            &mut writer,
+            &commitment,
+            labels_offset + labels.start..labels_offset + labels.end,
+            None,
+        )
+        .map_err(|e| eyre::eyre!("initializing labels: {e}"))?;
+    writer
+        .into_inner()
+        .map_err(|e| e.into_error())?
+        .sync_all()?;
+
+    let mut checksums = metadata::load_checksums(datadir)?;
+    if let Some(checksum) = checksums.get_mut(file_id) {
+        *checksum = file_checksum(&path)?;
+        metadata::save_with_checksums(datadir, &metadata, &checksums)?;
+    }
+    Ok(())
+}
+
+/// Validate the POS data files against the checksums recorded in the metadata.
+///
+/// It's much faster than [verify_data] as it doesn't recompute any labels,
+/// but it can't tell which labels are corrupted.
+/// Returns IDs of the files that are missing or don't match their checksums.
+pub fn verify_checksums(datadir: &Path) -> eyre::Result<Vec<usize>> {
+    let metadata = metadata::load(datadir).wrap_err("loading metadata")?;
+    let checksums = metadata::load_checksums(datadir).wrap_err("loading checksums")?;
+    eyre::ensure!(
+        checksums.len() == metadata.num_files(),
+        "checksums of POS files are not recorded in the metadata"
+    );
+
+    let mut damaged = checksums
+        .into_par_iter()
+        .enumerate()
+        .filter_map(|(file_id, expected)| {
+            let path = datadir.join(metadata.file_name(file_id));
+            match file_checksum(&path) {
+                Ok(checksum) if checksum == expected => None,
+                Ok(_) => {
+                    log::warn!("POS file {} doesn't match its checksum", path.display());
+                    Some(file_id)
+                }
+                Err(e) => {
+                    log::warn!("failed to read POS file {}: {e}", path.display());
+                    Some(file_id)
+                }
+            }
+        })
+        .collect::<Vec<_>>();
+    damaged.sort_unstable();
+    Ok(damaged)
+}
+
+/// Regenerate POS files found damaged by [verify_data].
+///
+/// As only a sample of labels is verified, a damaged file is regenerated as a whole.
+/// The VRF nonce is not searched for again as it's stored in the metadata.
+/// Returns IDs of the repaired files.
+pub fn repair_data(
+    initializer: &mut dyn Initialize,
+    datadir: &Path,
+    report: &IntegrityReport,
+) -> eyre::Result<Vec<usize>> {
+    let metadata = metadata::load(datadir).wrap_err("loading metadata")?;
+    let mut repaired = Vec::new();
+    for file in report.damaged_files() {
+        let path = datadir.join(metadata.file_name(file.file_id));
+        if !file.missing {
+            // Drop any excess data (the file could also be longer than expected).
+            File::options()
+                .write(true)
+                .open(&path)?
+                .set_len(0)
+                .wrap_err_with(|| format!("truncating {}", path.display()))?;
+        }
+        let labels = metadata.labels_in_file(file.file_id) as u64;
+        repair_labels(initializer, datadir, file.file_id, 0..labels)?;
+        repaired.push(file.file_id);
+    }
+    Ok(repaired)
+}
+
 #[cfg(test)]
 mod tests {
     use std::io::Read;
 
-    use crate::{metadata, reader};
+    use itertools::Itertools;
+
+    use crate::reader;
 
     use super::*;
 
@@ -176,6 +1086,107 @@ mod tests {
         assert_eq!(expected_size, pos_file.metadata().unwrap().len());
     }
 
+    #[test]
+    fn reporting_progress() {
+        let scrypt_params = ScryptParams::new(2, 1, 1);
+        let data_dir = tempfile::tempdir().unwrap();
+
+        let mut reports = Vec::new();
+        CpuInitializer::new(scrypt_params)
+            .initialize_with_progress(
+                data_dir.path(),
+                &[0u8; 32],
+                &[0u8; 32],
+                10,
+                5,
+                FileLayout {
+                    max_file_size: 20 * 16,
+                    file_index_width: 0,
+                },
+                None,
+                &mut |progress: Progress| reports.push(progress),
+            )
+            .unwrap();
+
+        // Labels are written one by one by the CPU initializer
+        assert_eq!(50, reports.len());
+        for (id, progress) in reports.into_iter().enumerate() {
+            assert_eq!(
+                Progress {
+                    file_id: id as u64 / 20,
+                    labels_written: id as u64 + 1,
+                    total_labels: 50,
+                },
+                progress
+            );
+        }
+    }
+
+    #[test]
+    fn progress_writer_counts_whole_labels() {
+        let mut reports = Vec::new();
+        let mut observer = |progress: Progress| reports.push(progress.labels_written);
+        let mut sink = std::io::sink();
+        let mut writer = ProgressWriter {
+            writer: &mut sink,
+            observer: &mut observer,
+            progress: Progress {
+                file_id: 0,
+                labels_written: 100,
+                total_labels: 200,
+            },
+            bytes_written: 0,
+        };
+        writer.write_all(&[0u8; 8]).unwrap();
+        writer.write_all(&[0u8; 8]).unwrap();
+        writer.write_all(&[0u8; 40]).unwrap();
+        writer.write_all(&[0u8; 8]).unwrap();
+
+        assert_eq!(vec![101, 103, 104], reports);
+    }
+
+    #[test]
+    fn initialize_with_dedicated_thread_pool() {
+        let labels = 0..CpuInitializer::BATCH_SIZE as u64 + 100;
+        let scrypt_params = ScryptParams::new(2, 1, 1);
+
+        let mut expected = Vec::new();
+        let expected_nonce = CpuInitializer::new(scrypt_params)
+            .initialize_to(&mut expected, &[0u8; 32], labels.clone(), Some([0xFF; 32]))
+            .unwrap();
+
+        let mut data = Vec::new();
+        let nonce = CpuInitializer::with_threads(scrypt_params, 3)
+            .unwrap()
+            .initialize_to(&mut data, &[0u8; 32], labels, Some([0xFF; 32]))
+            .unwrap();
+
+        assert_eq!(expected, data);
+        assert_eq!(expected_nonce, nonce);
+    }
+
+    #[test]
+    fn limiting_write_rate() {
+        let labels = 0..CpuInitializer::BATCH_SIZE as u64 * 6;
+        let scrypt_params = ScryptParams::new(2, 1, 1);
+        let bytes_per_sec = CpuInitializer::BATCH_SIZE as u64 * LABEL_SIZE as u64 * 4;
+
+        let mut expected = Vec::new();
+        CpuInitializer::new(scrypt_params)
+            .initialize_to(&mut expected, &[0u8; 32], labels.clone(), None)
+            .unwrap();
+
+        let mut data = Vec::new();
+        let start = std::time::Instant::now();
+        CpuInitializer::new(scrypt_params)
+            .with_write_rate_limit(bytes_per_sec)
+            .initialize_to(&mut data, &[0u8; 32], labels, None)
+            .unwrap();
+        // 4 batches fit in the initial burst, the remaining 2 are throttled
+        assert!(start.elapsed() >= std::time::Duration::from_millis(500));
+        assert_eq!(expected, data);
+    }
+
     #[test]
     fn test_initialize_fits_in_single_file() {
         let scrypt_params = ScryptParams::new(4, 1, 1);
@@ -226,6 +1237,41 @@ mod tests {
         assert_eq!(None, metadata.last_position);
     }
 
+    #[test]
+    fn initialize_with_zero_padded_file_names() {
+        let scrypt_params = ScryptParams::new(2, 1, 1);
+        let data_dir = tempfile::tempdir().unwrap();
+        let layout = FileLayout {
+            max_file_size: 16 * 10,
+            file_index_width: 3,
+        };
+        let metadata = CpuInitializer::new(scrypt_params)
+            .initialize_with_progress(
+                data_dir.path(),
+                &[0u8; 32],
+                &[0u8; 32],
+                100,
+                2,
+                layout,
+                None,
+                &mut |_: Progress| {},
+            )
+            .unwrap();
+
+        assert_eq!(layout, metadata.layout());
+        for id in 0..20 {
+            assert!(data_dir
+                .path()
+                .join(format!("postdata_{id:03}.bin"))
+                .exists());
+        }
+        assert_eq!(
+            20,
+            reader::pos_files(data_dir.path()).unwrap().count(),
+            "padded files must be recognized as POS files"
+        );
+    }
+
     #[test]
     fn test_initialize_split_many_files() {
         let scrypt_params = ScryptParams::new(4, 1, 1);
@@ -260,6 +1306,128 @@ mod tests {
         assert_eq!(16000, total_size);
     }
 
+    /// Wraps CpuInitializer and records the ranges of initialized labels.
+    struct RecordingInitializer {
+        inner: CpuInitializer,
+        ranges: Vec<Range<u64>>,
+    }
+
+    impl Initialize for RecordingInitializer {
+        fn initialize_to(
+            &mut self,
+            writer: &mut dyn Write,
+            commitment: &[u8; 32],
+            labels: Range<u64>,
+            vrf_difficulty: Option<[u8; 32]>,
+        ) -> Result<Option<VrfNonce>, Box<dyn Error>> {
+            self.ranges.push(labels.clone());
+            self.inner
+                .initialize_to(writer, commitment, labels, vrf_difficulty)
+        }
+    }
+
+    #[test]
+    fn resuming_interrupted_initialization() {
+        let scrypt_params = ScryptParams::new(4, 1, 1);
+        let data_dir = tempfile::tempdir().unwrap();
+        let data_path = data_dir.path();
+        let difficulty = Some([0xFFu8; 32]);
+
+        let expected = CpuInitializer::new(scrypt_params)
+            .initialize(
+                &data_path.join("full"),
+                &[1; 32],
+                &[2; 32],
+                100,
+                10,
+                150,
+                difficulty,
+            )
+            .unwrap();
+
+        // Simulate interruption after the file with the nonce was completed:
+        // the next file is incomplete and the rest is missing.
+        let nonce = expected.nonce.unwrap();
+        let completed = nonce / 150 + 1;
+        let resumed_path = data_path.join("resumed");
+        CpuInitializer::new(scrypt_params)
+            .initialize(&resumed_path, &[1; 32], &[2; 32], 100, 10, 150, difficulty)
+            .unwrap();
+        for id in completed + 1..7 {
+            std::fs::remove_file(resumed_path.join(format!("postdata_{id}.bin"))).unwrap();
+        }
+        if completed < 7 {
+            std::fs::File::create(resumed_path.join(format!("postdata_{completed}.bin")))
+                .unwrap()
+                .set_len(100)
+                .unwrap();
+        }
+        metadata::save(
+            &resumed_path,
+            &PostMetadata {
+                last_position: Some(1000.min((completed + 1) * 150)),
+                ..metadata::load(&resumed_path).unwrap()
+            },
+        )
+        .unwrap();
+
+        let mut initializer = RecordingInitializer {
+            inner: CpuInitializer::new(scrypt_params),
+            ranges: Vec::new(),
+        };
+        let metadata = initializer
+            .initialize(&resumed_path, &[1; 32], &[2; 32], 100, 10, 150, difficulty)
+            .unwrap();
+        // Recalculate the nonce label and initialize the remaining files
+        let expected_ranges: Vec<_> = std::iter::once(nonce..nonce + 1)
+            .chain((completed..7).map(|id| id * 150..1000.min((id + 1) * 150)))
+            .collect();
+        assert_eq!(expected_ranges, initializer.ranges);
+
+        assert_eq!(expected.nonce, metadata.nonce);
+        assert_eq!(None, metadata.last_position);
+        for id in 0..7 {
+            let file = format!("postdata_{id}.bin");
+            assert_eq!(
+                std::fs::read(data_path.join("full").join(&file)).unwrap(),
+                std::fs::read(resumed_path.join(&file)).unwrap()
+            );
+        }
+    }
+
+    #[test]
+    fn resuming_complete_initialization_does_nothing() {
+        let scrypt_params = ScryptParams::new(4, 1, 1);
+        let data_dir = tempfile::tempdir().unwrap();
+
+        let expected = CpuInitializer::new(scrypt_params)
+            .initialize(data_dir.path(), &[1; 32], &[2; 32], 100, 2, 150, None)
+            .unwrap();
+
+        let mut initializer = RecordingInitializer {
+            inner: CpuInitializer::new(scrypt_params),
+            ranges: Vec::new(),
+        };
+        let metadata = initializer
+            .initialize(data_dir.path(), &[1; 32], &[2; 32], 100, 2, 150, None)
+            .unwrap();
+        assert!(initializer.ranges.is_empty());
+        assert_eq!(expected.nonce, metadata.nonce);
+    }
+
+    #[test]
+    fn refuse_to_resume_with_different_parameters() {
+        let scrypt_params = ScryptParams::new(4, 1, 1);
+        let data_dir = tempfile::tempdir().unwrap();
+
+        CpuInitializer::new(scrypt_params)
+            .initialize(data_dir.path(), &[1; 32], &[2; 32], 100, 2, 150, None)
+            .unwrap();
+        CpuInitializer::new(scrypt_params)
+            .initialize(data_dir.path(), &[3; 32], &[2; 32], 100, 2, 150, None)
+            .unwrap_err();
+    }
+
     #[test]
     fn initialization_to_many_files_gives_same_result_as_single_file() {
         let scrypt_params = ScryptParams::new(4, 1, 1);
@@ -309,4 +1477,410 @@ mod tests {
         let metadata_single = metadata::load(&data_path.join("single")).unwrap();
         assert_eq!(metadata_many.nonce, metadata_single.nonce);
     }
+
+    #[test]
+    fn verifying_intact_data() {
+        let scrypt_params = ScryptParams::new(2, 1, 1);
+        let data_dir = tempfile::tempdir().unwrap();
+        CpuInitializer::new(scrypt_params)
+            .initialize(data_dir.path(), &[0u8; 32], &[0u8; 32], 100, 3, 64, None)
+            .unwrap();
+
+        let report = verify_data(data_dir.path(), 100.0, scrypt_params).unwrap();
+        assert!(report.is_ok());
+        assert_eq!(5, report.files.len());
+        assert_eq!(
+            300,
+            report.files.iter().map(|f| f.labels_checked).sum::<u64>()
+        );
+    }
+
+    #[test]
+    fn verifying_corrupted_data() {
+        let scrypt_params = ScryptParams::new(2, 1, 1);
+        let data_dir = tempfile::tempdir().unwrap();
+        let data_path = data_dir.path();
+        CpuInitializer::new(scrypt_params)
+            .initialize(data_path, &[0u8; 32], &[0u8; 32], 100, 3, 64, None)
+            .unwrap();
+
+        // flip a bit in label 5 of file 1
+        let path = data_path.join("postdata_1.bin");
+        let mut data = std::fs::read(&path).unwrap();
+        data[5 * LABEL_SIZE] ^= 1;
+        std::fs::write(&path, data).unwrap();
+        // truncate file 2 to 60 labels
+        File::options()
+            .write(true)
+            .open(data_path.join("postdata_2.bin"))
+            .unwrap()
+            .set_len(60 * LABEL_SIZE as u64)
+            .unwrap();
+        // remove file 3
+        std::fs::remove_file(data_path.join("postdata_3.bin")).unwrap();
+
+        let report = verify_data(data_path, 100.0, scrypt_params).unwrap();
+        assert!(!report.is_ok());
+        assert_eq!(
+            vec![1, 2, 3],
+            report.damaged_files().map(|f| f.file_id).collect_vec()
+        );
+        assert_eq!(vec![5], report.files[1].corrupted_labels);
+        assert_eq!((60..64).collect_vec(), report.files[2].corrupted_labels);
+        assert!(report.files[3].missing);
+        assert_eq!(1.0, report.files[3].corruption_rate());
+        assert!(report.files[4].is_ok());
+    }
+
+    #[test]
+    fn verifying_checks_at_least_one_label_per_file() {
+        let scrypt_params = ScryptParams::new(2, 1, 1);
+        let data_dir = tempfile::tempdir().unwrap();
+        CpuInitializer::new(scrypt_params)
+            .initialize(data_dir.path(), &[0u8; 32], &[0u8; 32], 100, 1, 10, None)
+            .unwrap();
+
+        let report = verify_data(data_dir.path(), 0.0, scrypt_params).unwrap();
+        assert!(report.files.iter().all(|f| f.labels_checked == 1));
+    }
+
+    #[test]
+    fn verifying_rejects_fraction_out_of_range() {
+        let scrypt_params = ScryptParams::new(2, 1, 1);
+        let data_dir = tempfile::tempdir().unwrap();
+        CpuInitializer::new(scrypt_params)
+            .initialize(data_dir.path(), &[0u8; 32], &[0u8; 32], 100, 1, 10, None)
+            .unwrap();
+
+        for fraction in [-1.0, 100.1, f64::NAN] {
+            assert!(verify_data(data_dir.path(), fraction, scrypt_params).is_err());
+        }
+        let report = verify_data(data_dir.path(), 100.0, scrypt_params).unwrap();
+        assert_eq!(
+            100,
+            report.files.iter().map(|f| f.labels_checked).sum::<u64>()
+        );
+    }
+
+    #[test]
+    fn repairing_damaged_files() {
+        let scrypt_params = ScryptParams::new(2, 1, 1);
+        let data_dir = tempfile::tempdir().unwrap();
+        let data_path = data_dir.path();
+        CpuInitializer::new(scrypt_params)
+            .initialize(data_path, &[0u8; 32], &[0u8; 32], 100, 3, 64, None)
+            .unwrap();
+        let original = (0..5)
+            .map(|id| std::fs::read(data_path.join(format!("postdata_{id}.bin"))).unwrap())
+            .collect_vec();
+
+        let path = data_path.join("postdata_1.bin");
+        let mut data = std::fs::read(&path).unwrap();
+        data[5 * LABEL_SIZE] ^= 1;
+        data.extend_from_slice(&[0u8; 7]);
+        std::fs::write(&path, data).unwrap();
+        std::fs::remove_file(data_path.join("postdata_4.bin")).unwrap();
+
+        let report = verify_data(data_path, 100.0, scrypt_params).unwrap();
+        let mut initializer = RecordingInitializer {
+            inner: CpuInitializer::new(scrypt_params),
+            ranges: vec![],
+        };
+        let repaired = repair_data(&mut initializer, data_path, &report).unwrap();
+        assert_eq!(vec![1, 4], repaired);
+        assert_eq!(vec![64..128, 256..300], initializer.ranges);
+
+        for (id, expected) in original.iter().enumerate() {
+            let data = std::fs::read(data_path.join(format!("postdata_{id}.bin"))).unwrap();
+            assert_eq!(expected, &data);
+        }
+        assert!(verify_data(data_path, 100.0, scrypt_params)
+            .unwrap()
+            .is_ok());
+    }
+
+    #[test]
+    fn repairing_range_of_labels() {
+        let scrypt_params = ScryptParams::new(2, 1, 1);
+        let data_dir = tempfile::tempdir().unwrap();
+        let data_path = data_dir.path();
+        CpuInitializer::new(scrypt_params)
+            .initialize(data_path, &[0u8; 32], &[0u8; 32], 100, 3, 64, None)
+            .unwrap();
+        let path = data_path.join("postdata_2.bin");
+        let original = std::fs::read(&path).unwrap();
+
+        let mut data = original.clone();
+        data[10 * LABEL_SIZE..20 * LABEL_SIZE].fill(0);
+        std::fs::write(&path, data).unwrap();
+
+        let mut initializer = CpuInitializer::new(scrypt_params);
+        repair_labels(&mut initializer, data_path, 2, 10..20).unwrap();
+        assert_eq!(original, std::fs::read(&path).unwrap());
+
+        assert!(repair_labels(&mut initializer, data_path, 2, 60..65).is_err());
+    }
+
+    #[test]
+    fn recording_file_checksums() {
+        let scrypt_params = ScryptParams::new(2, 1, 1);
+        let data_dir = tempfile::tempdir().unwrap();
+        let data_path = data_dir.path();
+        CpuInitializer::new(scrypt_params)
+            .initialize(data_path, &[0u8; 32], &[0u8; 32], 100, 3, 64, None)
+            .unwrap();
+
+        let checksums = metadata::load_checksums(data_path).unwrap();
+        assert_eq!(5, checksums.len());
+        for (id, checksum) in checksums.iter().enumerate() {
+            let data = std::fs::read(data_path.join(format!("postdata_{id}.bin"))).unwrap();
+            assert_eq!(blake3::hash(&data).as_bytes(), checksum);
+        }
+        assert!(verify_checksums(data_path).unwrap().is_empty());
+
+        // flip a bit in file 1 and remove file 3
+        let path = data_path.join("postdata_1.bin");
+        let mut data = std::fs::read(&path).unwrap();
+        data[100] ^= 1;
+        std::fs::write(&path, data).unwrap();
+        std::fs::remove_file(data_path.join("postdata_3.bin")).unwrap();
+        assert_eq!(vec![1, 3], verify_checksums(data_path).unwrap());
+
+        // repairing updates the checksums
+        let report = verify_data(data_path, 100.0, scrypt_params).unwrap();
+        repair_data(&mut CpuInitializer::new(scrypt_params), data_path, &report).unwrap();
+        assert!(verify_checksums(data_path).unwrap().is_empty());
+    }
+
+    #[test]
+    fn resuming_keeps_checksums_of_completed_files() {
+        let scrypt_params = ScryptParams::new(2, 1, 1);
+        let data_dir = tempfile::tempdir().unwrap();
+        let data_path = data_dir.path();
+        let mut initializer = CpuInitializer::new(scrypt_params);
+        let metadata = initializer
+            .initialize(data_path, &[0u8; 32], &[0u8; 32], 100, 3, 64, None)
+            .unwrap();
+        let checksums = metadata::load_checksums(data_path).unwrap();
+
+        // interrupted after 2 files, with checksums not recorded
+        std::fs::remove_file(data_path.join("postdata_2.bin")).unwrap();
+        metadata::save_with_checksums(
+            data_path,
+            &PostMetadata {
+                last_position: Some(128),
+                ..metadata
+            },
+            &[],
+        )
+        .unwrap();
+
+        initializer
+            .initialize(data_path, &[0u8; 32], &[0u8; 32], 100, 3, 64, None)
+            .unwrap();
+        assert_eq!(checksums, metadata::load_checksums(data_path).unwrap());
+    }
+
+    #[test]
+    fn growing_gives_same_result_as_initializing() {
+        let scrypt_params = ScryptParams::new(2, 1, 1);
+        let difficulty = Some([0x0F; 32]);
+        let expected_dir = tempfile::tempdir().unwrap();
+        let expected = CpuInitializer::new(scrypt_params)
+            .initialize(
+                expected_dir.path(),
+                &[1; 32],
+                &[2; 32],
+                100,
+                5,
+                64,
+                difficulty,
+            )
+            .unwrap();
+
+        let data_dir = tempfile::tempdir().unwrap();
+        let data_path = data_dir.path();
+        let mut initializer = RecordingInitializer {
+            inner: CpuInitializer::new(scrypt_params),
+            ranges: vec![],
+        };
+        initializer
+            .initialize(data_path, &[1; 32], &[2; 32], 100, 2, 64, difficulty)
+            .unwrap();
+        initializer.ranges.clear();
+
+        let metadata = grow(
+            &mut initializer,
+            data_path,
+            5,
+            difficulty,
+            &mut |_: Progress| {},
+        )
+        .unwrap();
+        assert_eq!(5, metadata.num_units);
+        assert_eq!(expected.nonce, metadata.nonce);
+        assert_eq!(None, metadata.last_position);
+
+        // only the new labels were initialized (except recalculating the nonce)
+        let new_ranges = initializer
+            .ranges
+            .iter()
+            .filter(|r| r.end - r.start > 1)
+            .cloned()
+            .collect_vec();
+        assert_eq!(
+            vec![200..256, 256..320, 320..384, 384..448, 448..500],
+            new_ranges
+        );
+
+        for id in 0..8 {
+            let name = format!("postdata_{id}.bin");
+            assert_eq!(
+                std::fs::read(expected_dir.path().join(&name)).unwrap(),
+                std::fs::read(data_path.join(&name)).unwrap(),
+            );
+        }
+        assert_eq!(
+            metadata::load_checksums(expected_dir.path()).unwrap(),
+            metadata::load_checksums(data_path).unwrap()
+        );
+    }
+
+    #[test]
+    fn growing_requires_more_units() {
+        let scrypt_params = ScryptParams::new(2, 1, 1);
+        let data_dir = tempfile::tempdir().unwrap();
+        let mut initializer = CpuInitializer::new(scrypt_params);
+        initializer
+            .initialize(data_dir.path(), &[0; 32], &[0; 32], 100, 2, 64, None)
+            .unwrap();
+        grow(
+            &mut initializer,
+            data_dir.path(),
+            2,
+            None,
+            &mut |_: Progress| {},
+        )
+        .unwrap_err();
+    }
+
+    #[rstest::rstest]
+    #[case(None)]
+    #[case(Some([0x0F; 32]))]
+    fn shrinking_gives_same_result_as_initializing(#[case] difficulty: Option<[u8; 32]>) {
+        let scrypt_params = ScryptParams::new(2, 1, 1);
+        let expected_dir = tempfile::tempdir().unwrap();
+        let expected = CpuInitializer::new(scrypt_params)
+            .initialize(
+                expected_dir.path(),
+                &[1; 32],
+                &[2; 32],
+                100,
+                2,
+                64,
+                difficulty,
+            )
+            .unwrap();
+
+        let data_dir = tempfile::tempdir().unwrap();
+        let data_path = data_dir.path();
+        let mut initializer = CpuInitializer::new(scrypt_params);
+        let original = initializer
+            .initialize(data_path, &[1; 32], &[2; 32], 100, 5, 64, difficulty)
+            .unwrap();
+        // Pretend the nonce is in the labels to remove.
+        metadata::save(
+            data_path,
+            &PostMetadata {
+                nonce: original.nonce.map(|_| 450),
+                ..original
+            },
+        )
+        .unwrap();
+
+        let metadata = shrink(&mut initializer, data_path, 2, difficulty).unwrap();
+        assert_eq!(2, metadata.num_units);
+        assert_eq!(expected.nonce, metadata.nonce);
+
+        let files = reader::pos_files(data_path).unwrap().collect_vec();
+        assert_eq!(4, files.len());
+        for entry in files {
+            assert_eq!(
+                std::fs::read(expected_dir.path().join(entry.file_name())).unwrap(),
+                std::fs::read(entry.path()).unwrap(),
+            );
+        }
+        assert_eq!(
+            metadata::load_checksums(expected_dir.path()).unwrap(),
+            metadata::load_checksums(data_path).unwrap()
+        );
+        assert!(verify_data(data_path, 100.0, scrypt_params)
+            .unwrap()
+            .is_ok());
+    }
+
+    #[test]
+    fn shrinking_without_difficulty_keeps_nonce_in_range() {
+        let scrypt_params = ScryptParams::new(2, 1, 1);
+        let data_dir = tempfile::tempdir().unwrap();
+        let data_path = data_dir.path();
+        let mut initializer = CpuInitializer::new(scrypt_params);
+        let original = initializer
+            .initialize(data_path, &[0; 32], &[0; 32], 100, 5, 64, None)
+            .unwrap();
+        metadata::save(
+            data_path,
+            &PostMetadata {
+                nonce: Some(450),
+                ..original
+            },
+        )
+        .unwrap();
+
+        shrink(&mut initializer, data_path, 2, None).unwrap_err();
+        // nothing was modified
+        assert_eq!(5, metadata::load(data_path).unwrap().num_units);
+        assert_eq!(8, reader::pos_files(data_path).unwrap().count());
+
+        let metadata = shrink(&mut initializer, data_path, 5, None).unwrap();
+        assert_eq!(Some(450), metadata.nonce);
+    }
+
+    #[rstest::rstest]
+    #[case(1, None)]
+    #[case(7, Some(2))]
+    #[case(100, Some(3))]
+    #[case(10_000, None)]
+    fn chunked_initialization_gives_same_result(
+        #[case] chunk_size: usize,
+        #[case] threads: Option<usize>,
+    ) {
+        let scrypt_params = ScryptParams::new(2, 1, 1);
+        let labels = 13..2000;
+        let difficulty = Some([0x0F; 32]);
+        let mut expected = Vec::new();
+        let expected_nonce = CpuInitializer::new(scrypt_params)
+            .initialize_to(&mut expected, &[5; 32], labels.clone(), difficulty)
+            .unwrap();
+
+        let mut initializer = match threads {
+            Some(threads) => CpuInitializer::with_threads(scrypt_params, threads).unwrap(),
+            None => CpuInitializer::new(scrypt_params),
+        };
+        let mut data = Vec::new();
+        let nonce = initializer
+            .initialize_to_chunked(&mut data, &[5; 32], labels, difficulty, chunk_size)
+            .unwrap();
+        assert_eq!(expected, data);
+        assert_eq!(expected_nonce, nonce);
+    }
+
+    #[test]
+    fn chunked_initialization_stops_on_write_error() {
+        let mut initializer = CpuInitializer::new(ScryptParams::new(2, 1, 1));
+        let mut buf = [0u8; 100];
+        initializer
+            .initialize_to_chunked(&mut buf.as_mut_slice(), &[0; 32], 0..1000, None, 10)
+            .unwrap_err();
+    }
 }
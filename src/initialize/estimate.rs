@@ -0,0 +1,113 @@
+//! Estimating the time and space needed for initialization.
+
+use std::{error::Error, time::Duration, time::Instant};
+
+use crate::config::FileLayout;
+
+use super::{Initialize, LABEL_SIZE};
+
+/// Expected cost of initialization.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Estimate {
+    pub total_labels: u64,
+    /// Disk space taken by the POS data in bytes.
+    pub disk_space: u64,
+    pub num_files: u64,
+    /// Measured throughput of the initializer.
+    pub labels_per_sec: f64,
+    /// Expected duration of the initialization.
+    pub duration: Duration,
+    /// Average rate of writing the POS data in bytes/sec.
+    pub write_rate: f64,
+}
+
+/// Estimate the initialization of `num_units` without writing anything.
+///
+/// The throughput is measured by initializing `sample_labels` labels
+/// (including the search for the VRF nonce) with the `initializer`.
+pub fn estimate<I: Initialize + ?Sized>(
+    initializer: &mut I,
+    labels_per_unit: u64,
+    num_units: u32,
+    layout: FileLayout,
+    sample_labels: u64,
+) -> Result<Estimate, Box<dyn Error>> {
+    if sample_labels == 0 {
+        return Err("number of sample labels must be positive".into());
+    }
+    layout.validate()?;
+    let total_labels = labels_per_unit * num_units as u64;
+
+    let start = Instant::now();
+    initializer.initialize_to(
+        &mut std::io::sink(),
+        &[0u8; 32],
+        0..sample_labels,
+        Some([0xFF; 32]),
+    )?;
+    let labels_per_sec = sample_labels as f64 / start.elapsed().as_secs_f64();
+
+    Ok(Estimate {
+        total_labels,
+        disk_space: total_labels * LABEL_SIZE as u64,
+        num_files: total_labels.div_ceil(layout.labels_per_file()),
+        labels_per_sec,
+        duration: Duration::from_secs_f64(total_labels as f64 / labels_per_sec),
+        write_rate: labels_per_sec * LABEL_SIZE as f64,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{io::Write, ops::Range};
+
+    use crate::initialize::VrfNonce;
+
+    use super::*;
+
+    /// Initializes 1000 labels per second.
+    struct FixedSpeedInitializer;
+
+    impl Initialize for FixedSpeedInitializer {
+        fn initialize_to(
+            &mut self,
+            _: &mut dyn Write,
+            _: &[u8; 32],
+            labels: Range<u64>,
+            _: Option<[u8; 32]>,
+        ) -> Result<Option<VrfNonce>, Box<dyn Error>> {
+            std::thread::sleep(Duration::from_millis(labels.end - labels.start));
+            Ok(None)
+        }
+    }
+
+    #[test]
+    fn estimating() {
+        let layout = FileLayout {
+            max_file_size: 1000 * LABEL_SIZE as u64,
+            ..Default::default()
+        };
+        let estimate = estimate(&mut FixedSpeedInitializer, 2500, 4, layout, 100).unwrap();
+        assert_eq!(10_000, estimate.total_labels);
+        assert_eq!(160_000, estimate.disk_space);
+        assert_eq!(10, estimate.num_files);
+        // sleeping takes at least the given time
+        assert!(estimate.labels_per_sec <= 1000.0);
+        assert!(estimate.duration >= Duration::from_secs(10));
+        assert_eq!(estimate.labels_per_sec * 16.0, estimate.write_rate);
+    }
+
+    #[test]
+    fn sample_must_not_be_empty() {
+        estimate(&mut FixedSpeedInitializer, 100, 1, FileLayout::default(), 0).unwrap_err();
+    }
+
+    #[test]
+    fn files_must_hold_labels() {
+        let layout = FileLayout {
+            max_file_size: 8,
+            ..Default::default()
+        };
+        estimate(&mut FixedSpeedInitializer, 100, 1, layout, 10).unwrap_err();
+    }
+}
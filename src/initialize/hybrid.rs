@@ -0,0 +1,354 @@
+//! Initialization with many initializers (i.e. the CPU and GPUs) working concurrently.
+//!
+//! The labels are split into chunks claimed by the workers as they become idle.
+//! The size of a chunk is proportional to the measured throughput of the worker
+//! that claims it, so that faster workers take more work. Chunks are written out
+//! in order, so the result is the same as if a single initializer was used.
+
+use std::{
+    collections::BTreeMap,
+    error::Error,
+    io::Write,
+    ops::Range,
+    sync::{mpsc, Condvar, Mutex},
+    time::{Duration, Instant},
+};
+
+use super::{Initialize, VrfNonce, LABEL_SIZE};
+
+/// Statistics of a worker of [HybridInitializer].
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct WorkerStats {
+    pub name: String,
+    /// Total number of labels initialized by the worker.
+    pub labels: u64,
+    /// Measured throughput. '0' if not measured yet.
+    pub labels_per_sec: f64,
+}
+
+struct Worker {
+    initializer: Box<dyn Initialize + Send>,
+    stats: WorkerStats,
+}
+
+impl Worker {
+    fn record(&mut self, labels: u64, elapsed: Duration) {
+        let speed = labels as f64 / elapsed.as_secs_f64().max(f64::EPSILON);
+        self.stats.labels += labels;
+        self.stats.labels_per_sec = if self.stats.labels_per_sec == 0.0 {
+            speed
+        } else {
+            // smooth out the fluctuations
+            (self.stats.labels_per_sec + speed) / 2.0
+        };
+    }
+
+    fn chunk_size(&self, target_duration: Duration, max: u64) -> u64 {
+        let size = (self.stats.labels_per_sec * target_duration.as_secs_f64()) as u64;
+        size.clamp(HybridInitializer::MIN_CHUNK_LABELS, max)
+    }
+}
+
+struct Chunk {
+    start: u64,
+    data: Vec<u8>,
+    nonce: Option<VrfNonce>,
+}
+
+/// Progress of the work shared between the workers.
+struct Schedule {
+    /// Index of the first label not claimed by any worker.
+    next: u64,
+    /// Index of the first label not written out yet.
+    written: u64,
+    aborted: bool,
+}
+
+/// Initializer distributing the work between many initializers.
+///
+/// ```no_run
+/// # use post::{config::ScryptParams, initialize::{CpuInitializer, HybridInitializer}};
+/// let params = ScryptParams::new(8192, 1, 1);
+/// let initializer = HybridInitializer::new()
+///     .with_worker("cpu", Box::new(CpuInitializer::new(params)));
+/// ```
+pub struct HybridInitializer {
+    workers: Vec<Worker>,
+    target_chunk_duration: Duration,
+    max_buffered_labels: u64,
+}
+
+impl Default for HybridInitializer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl HybridInitializer {
+    const MIN_CHUNK_LABELS: u64 = 1024;
+
+    pub fn new() -> Self {
+        Self {
+            workers: Vec::new(),
+            target_chunk_duration: Duration::from_secs(1),
+            max_buffered_labels: 16 * 1024 * 1024,
+        }
+    }
+
+    pub fn with_worker(
+        mut self,
+        name: impl Into<String>,
+        initializer: Box<dyn Initialize + Send>,
+    ) -> Self {
+        self.workers.push(Worker {
+            initializer,
+            stats: WorkerStats {
+                name: name.into(),
+                ..Default::default()
+            },
+        });
+        self
+    }
+
+    /// How long should initializing a single chunk take.
+    /// Shorter chunks balance the work better but increase the overhead.
+    pub fn with_target_chunk_duration(mut self, duration: Duration) -> Self {
+        self.target_chunk_duration = duration;
+        self
+    }
+
+    /// Maximum number of labels initialized ahead of the labels written out.
+    /// Bounds the memory used when a slow worker holds back the faster ones.
+    pub fn with_max_buffered_labels(mut self, labels: u64) -> Self {
+        self.max_buffered_labels = labels.max(Self::MIN_CHUNK_LABELS);
+        self
+    }
+
+    pub fn stats(&self) -> Vec<WorkerStats> {
+        self.workers.iter().map(|w| w.stats.clone()).collect()
+    }
+
+    fn run(
+        &mut self,
+        writer: &mut dyn Write,
+        commitment: &[u8; 32],
+        labels: Range<u64>,
+        vrf_difficulty: Option<[u8; 32]>,
+    ) -> Result<Option<VrfNonce>, String> {
+        let end = labels.end;
+        let max_buffered = self.max_buffered_labels;
+        let max_chunk = (max_buffered / self.workers.len() as u64).max(Self::MIN_CHUNK_LABELS);
+        let target_duration = self.target_chunk_duration;
+        let schedule = Mutex::new(Schedule {
+            next: labels.start,
+            written: labels.start,
+            aborted: false,
+        });
+        let progressed = Condvar::new();
+
+        std::thread::scope(|s| {
+            let (tx, rx) = mpsc::channel::<Result<Chunk, String>>();
+            for worker in self.workers.iter_mut() {
+                let tx = tx.clone();
+                let (schedule, progressed) = (&schedule, &progressed);
+                s.spawn(move || loop {
+                    let size = worker.chunk_size(target_duration, max_chunk);
+                    let range = {
+                        let mut schedule = schedule.lock().unwrap();
+                        loop {
+                            if schedule.aborted || schedule.next >= end {
+                                return;
+                            }
+                            let buffered = schedule.next + size - schedule.written;
+                            if schedule.next == schedule.written || buffered <= max_buffered {
+                                break;
+                            }
+                            schedule = progressed.wait(schedule).unwrap();
+                        }
+                        let start = schedule.next;
+                        schedule.next = end.min(start + size);
+                        start..schedule.next
+                    };
+
+                    let started = Instant::now();
+                    let mut data =
+                        Vec::with_capacity((range.end - range.start) as usize * LABEL_SIZE);
+                    let result = worker.initializer.initialize_to(
+                        &mut data,
+                        commitment,
+                        range.clone(),
+                        vrf_difficulty,
+                    );
+                    let chunk = match result {
+                        Ok(nonce) => {
+                            worker.record(range.end - range.start, started.elapsed());
+                            Ok(Chunk {
+                                start: range.start,
+                                data,
+                                nonce,
+                            })
+                        }
+                        Err(e) => Err(format!("worker {}: {e}", worker.stats.name)),
+                    };
+                    let failed = chunk.is_err();
+                    if tx.send(chunk).is_err() || failed {
+                        return;
+                    }
+                });
+            }
+            drop(tx);
+
+            let result = write_in_order(writer, &rx, labels, &schedule, &progressed);
+            // Stop the workers (it's a no-op if all work is done).
+            schedule.lock().unwrap().aborted = true;
+            progressed.notify_all();
+            result
+        })
+    }
+}
+
+fn write_in_order(
+    writer: &mut dyn Write,
+    chunks: &mpsc::Receiver<Result<Chunk, String>>,
+    labels: Range<u64>,
+    schedule: &Mutex<Schedule>,
+    progressed: &Condvar,
+) -> Result<Option<VrfNonce>, String> {
+    let mut best_nonce: Option<VrfNonce> = None;
+    let mut pending = BTreeMap::new();
+    let mut position = labels.start;
+
+    while position < labels.end {
+        let chunk = chunks
+            .recv()
+            .map_err(|_| "all workers stopped".to_string())??;
+        if let Some(nonce) = chunk.nonce {
+            // Prefer the smaller index on tie, like a single initializer does.
+            if best_nonce.map_or(true, |best| {
+                (nonce.label, nonce.index) < (best.label, best.index)
+            }) {
+                best_nonce = Some(nonce);
+            }
+        }
+        pending.insert(chunk.start, chunk.data);
+
+        while let Some(data) = pending.remove(&position) {
+            writer.write_all(&data).map_err(|e| e.to_string())?;
+            position += (data.len() / LABEL_SIZE) as u64;
+            schedule.lock().unwrap().written = position;
+            progressed.notify_all();
+        }
+    }
+    Ok(best_nonce)
+}
+
+impl Initialize for HybridInitializer {
+    fn initialize_to(
+        &mut self,
+        writer: &mut dyn Write,
+        commitment: &[u8; 32],
+        labels: Range<u64>,
+        vrf_difficulty: Option<[u8; 32]>,
+    ) -> Result<Option<VrfNonce>, Box<dyn Error>> {
+        if self.workers.is_empty() {
+            return Err("no workers in hybrid initializer".into());
+        }
+        if labels.is_empty() {
+            return Ok(None);
+        }
+        Ok(self.run(writer, commitment, labels, vrf_difficulty)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{config::ScryptParams, initialize::CpuInitializer};
+
+    use super::*;
+
+    struct FailingInitializer;
+
+    impl Initialize for FailingInitializer {
+        fn initialize_to(
+            &mut self,
+            _: &mut dyn Write,
+            _: &[u8; 32],
+            _: Range<u64>,
+            _: Option<[u8; 32]>,
+        ) -> Result<Option<VrfNonce>, Box<dyn Error>> {
+            Err("broken".into())
+        }
+    }
+
+    #[test]
+    fn same_result_as_single_initializer() {
+        let params = ScryptParams::new(2, 1, 1);
+        let commitment = [7u8; 32];
+        let labels = 100..20_000;
+        let difficulty = Some([0x0F; 32]);
+
+        let mut expected = Vec::new();
+        let expected_nonce = CpuInitializer::new(params)
+            .initialize_to(&mut expected, &commitment, labels.clone(), difficulty)
+            .unwrap();
+
+        let mut initializer = HybridInitializer::new()
+            .with_worker("cpu1", Box::new(CpuInitializer::new(params)))
+            .with_worker(
+                "cpu2",
+                Box::new(CpuInitializer::with_threads(params, 1).unwrap()),
+            )
+            .with_target_chunk_duration(Duration::from_millis(10))
+            .with_max_buffered_labels(4096);
+        let mut data = Vec::new();
+        let nonce = initializer
+            .initialize_to(&mut data, &commitment, labels.clone(), difficulty)
+            .unwrap();
+
+        assert_eq!(expected, data);
+        assert_eq!(expected_nonce, nonce);
+        let stats = initializer.stats();
+        assert_eq!(
+            labels.end - labels.start,
+            stats.iter().map(|s| s.labels).sum::<u64>()
+        );
+    }
+
+    #[test]
+    fn faster_worker_takes_bigger_chunks() {
+        let mut worker = Worker {
+            initializer: Box::new(FailingInitializer),
+            stats: WorkerStats::default(),
+        };
+        let target = Duration::from_secs(1);
+        assert_eq!(
+            HybridInitializer::MIN_CHUNK_LABELS,
+            worker.chunk_size(target, 1 << 20)
+        );
+        worker.record(100_000, Duration::from_secs(1));
+        assert_eq!(100_000, worker.chunk_size(target, 1 << 20));
+        assert_eq!(50_000, worker.chunk_size(target, 50_000));
+        worker.record(300_000, Duration::from_secs(1));
+        assert_eq!(200_000, worker.chunk_size(target, 1 << 20));
+        assert_eq!(400_000, worker.stats.labels);
+    }
+
+    #[test]
+    fn fails_if_worker_fails() {
+        let mut initializer = HybridInitializer::new()
+            .with_worker(
+                "cpu",
+                Box::new(CpuInitializer::new(ScryptParams::new(2, 1, 1))),
+            )
+            .with_worker("broken", Box::new(FailingInitializer));
+        let result = initializer.initialize_to(&mut std::io::sink(), &[0; 32], 0..100_000, None);
+        assert!(result.unwrap_err().to_string().contains("broken"));
+    }
+
+    #[test]
+    fn fails_without_workers() {
+        HybridInitializer::new()
+            .initialize_to(&mut std::io::sink(), &[0; 32], 0..100, None)
+            .unwrap_err();
+    }
+}
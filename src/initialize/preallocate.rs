@@ -0,0 +1,150 @@
+//! Preallocating disk space for POS data files.
+//!
+//! Allocating the space of a file before writing it avoids fragmenting it
+//! and makes the initialization fail early if the disk is too small.
+//! The space is allocated without changing the file size, so the size
+//! of a file still tells how much of it was written.
+
+use std::{
+    fs::{File, Metadata},
+    io,
+    path::Path,
+};
+
+/// Allocate disk space for the first `len` bytes of the `file` without changing its size.
+///
+/// It's a no-op on platforms and filesystems that don't support it.
+#[cfg(target_os = "linux")]
+pub(crate) fn preallocate(file: &File, len: u64) -> io::Result<()> {
+    use std::os::fd::AsRawFd;
+
+    let len =
+        libc::off_t::try_from(len).map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+    // SAFETY: the file descriptor is valid for the lifetime of `file`.
+    let ret = unsafe { libc::fallocate(file.as_raw_fd(), libc::FALLOC_FL_KEEP_SIZE, 0, len) };
+    if ret == 0 {
+        return Ok(());
+    }
+    let err = io::Error::last_os_error();
+    match err.raw_os_error() {
+        Some(libc::EOPNOTSUPP) | Some(libc::ENOSYS) => {
+            log::debug!("preallocation not supported: {err}");
+            Ok(())
+        }
+        _ => Err(err),
+    }
+}
+
+#[cfg(target_os = "macos")]
+pub(crate) fn preallocate(file: &File, len: u64) -> io::Result<()> {
+    use std::os::fd::AsRawFd;
+
+    let size = file.metadata()?.len();
+    if len <= size {
+        return Ok(());
+    }
+    let mut store = libc::fstore_t {
+        fst_flags: libc::F_ALLOCATEALL,
+        fst_posmode: libc::F_PEOFPOSMODE,
+        fst_offset: 0,
+        fst_length: libc::off_t::try_from(len - size)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?,
+        fst_bytesalloc: 0,
+    };
+    // SAFETY: the file descriptor is valid for the lifetime of `file`
+    // and `store` outlives the call.
+    let ret = unsafe { libc::fcntl(file.as_raw_fd(), libc::F_PREALLOCATE, &mut store) };
+    if ret == -1 {
+        let err = io::Error::last_os_error();
+        if err.raw_os_error() == Some(libc::ENOTSUP) {
+            log::debug!("preallocation not supported: {err}");
+            return Ok(());
+        }
+        return Err(err);
+    }
+    Ok(())
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "macos")))]
+pub(crate) fn preallocate(_file: &File, _len: u64) -> io::Result<()> {
+    Ok(())
+}
+
+/// Disk space (in bytes) taken by a file, including the space preallocated past its size.
+#[cfg(unix)]
+pub(crate) fn allocated_space(metadata: &Metadata) -> u64 {
+    use std::os::unix::fs::MetadataExt;
+
+    // `st_blocks` is counted in 512-byte units regardless of the filesystem block size.
+    (metadata.blocks() * 512).max(metadata.len())
+}
+
+#[cfg(not(unix))]
+pub(crate) fn allocated_space(metadata: &Metadata) -> u64 {
+    metadata.len()
+}
+
+/// Disk space (in bytes) available to the user on the filesystem containing `path`.
+///
+/// Returns `None` if it's unknown on the platform.
+#[cfg(unix)]
+pub(crate) fn available_space(path: &Path) -> io::Result<Option<u64>> {
+    use std::{ffi::CString, os::unix::ffi::OsStrExt};
+
+    let path = CString::new(path.as_os_str().as_bytes())
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+    let mut stat = std::mem::MaybeUninit::<libc::statvfs>::uninit();
+    // SAFETY: `path` is a valid C string and `stat` is initialized by a successful call.
+    let stat = unsafe {
+        if libc::statvfs(path.as_ptr(), stat.as_mut_ptr()) != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        stat.assume_init()
+    };
+    #[allow(clippy::unnecessary_cast)]
+    Ok(Some(stat.f_bavail as u64 * stat.f_frsize as u64))
+}
+
+#[cfg(not(unix))]
+pub(crate) fn available_space(_path: &Path) -> io::Result<Option<u64>> {
+    Ok(None)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn preallocating_keeps_file_size() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("file");
+        std::fs::write(&path, [1u8; 100]).unwrap();
+        let file = File::options().append(true).open(&path).unwrap();
+
+        preallocate(&file, 4096).unwrap();
+        assert_eq!(100, file.metadata().unwrap().len());
+    }
+
+    #[test]
+    fn allocated_space_counts_preallocation() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("file");
+        std::fs::write(&path, [1u8; 100]).unwrap();
+        let file = File::options().append(true).open(&path).unwrap();
+        assert!(allocated_space(&file.metadata().unwrap()) >= 100);
+
+        preallocate(&file, 1 << 20).unwrap();
+        let allocated = allocated_space(&file.metadata().unwrap());
+        #[cfg(target_os = "linux")]
+        assert!(allocated >= 1 << 20, "{allocated}");
+        assert!(allocated >= 100);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn available_space_is_known() {
+        let dir = tempfile::tempdir().unwrap();
+        assert!(available_space(dir.path()).unwrap().is_some());
+        available_space(&dir.path().join("missing")).unwrap_err();
+    }
+}
@@ -0,0 +1,282 @@
+//! Moving POS data to another directory.
+
+use std::{
+    fs::File,
+    io::{Read, Seek, SeekFrom, Write},
+    path::Path,
+};
+
+use eyre::Context;
+
+use crate::metadata::{self, PostMetadata};
+
+use super::{file_checksum, HashingWriter, LABEL_SIZE};
+
+/// Options for [relocate].
+#[derive(Debug, Clone, Copy)]
+pub struct RelocateOptions {
+    /// Copy the POS data, keeping the source directory intact.
+    pub keep_source: bool,
+    /// Validate the copied files against their checksums.
+    /// The checksums recorded in the metadata are used if available,
+    /// otherwise they are calculated from the source files.
+    pub verify: bool,
+}
+
+impl Default for RelocateOptions {
+    fn default() -> Self {
+        Self {
+            keep_source: false,
+            verify: true,
+        }
+    }
+}
+
+/// Move (or copy) the POS data from `datadir` to `new_dir`.
+///
+/// Files are renamed if both directories are on the same filesystem,
+/// otherwise they are copied and the source files are removed after
+/// the copies are validated and synced.
+/// The metadata is written to `new_dir` after all files are relocated
+/// and removed from `datadir` last.
+///
+/// Relocation can be resumed by calling it again with the same arguments.
+/// Partially copied files are continued from where the copying stopped.
+pub fn relocate(datadir: &Path, new_dir: &Path, options: RelocateOptions) -> eyre::Result<()> {
+    let metadata = metadata::load(datadir).wrap_err("loading metadata")?;
+    eyre::ensure!(
+        metadata.last_position.is_none(),
+        "initialization is in progress, it must be completed first"
+    );
+    std::fs::create_dir_all(new_dir).wrap_err_with(|| format!("creating {}", new_dir.display()))?;
+    eyre::ensure!(
+        datadir.canonicalize()? != new_dir.canonicalize()?,
+        "source and destination directories are the same"
+    );
+    log::info!(
+        "relocating POS data from {} to {}",
+        datadir.display(),
+        new_dir.display()
+    );
+
+    let recorded = metadata::load_checksums(datadir).wrap_err("loading checksums")?;
+    let mut checksums = Vec::with_capacity(metadata.num_files());
+    for file_id in 0..metadata.num_files() {
+        let expected = recorded
+            .get(file_id)
+            .copied()
+            .filter(|_| recorded.len() == metadata.num_files());
+        let checksum = relocate_file(&metadata, file_id, datadir, new_dir, expected, options)
+            .wrap_err_with(|| format!("relocating POS file {}", metadata.file_name(file_id)))?;
+        checksums.push(checksum);
+    }
+
+    // Record the checksums only if all of them are known.
+    let checksums = checksums.into_iter().collect::<Option<Vec<_>>>();
+    metadata::save_with_checksums(new_dir, &metadata, checksums.as_deref().unwrap_or_default())?;
+
+    if !options.keep_source {
+        metadata::remove(datadir).wrap_err("removing source metadata")?;
+    }
+    log::info!("relocated POS data to {}", new_dir.display());
+    Ok(())
+}
+
+/// Relocate a single file, returning its checksum if it's known.
+fn relocate_file(
+    metadata: &PostMetadata,
+    file_id: usize,
+    datadir: &Path,
+    new_dir: &Path,
+    expected: Option<[u8; 32]>,
+    options: RelocateOptions,
+) -> eyre::Result<Option<[u8; 32]>> {
+    let name = metadata.file_name(file_id);
+    let src = datadir.join(&name);
+    let dst = new_dir.join(&name);
+    let size = metadata.labels_in_file(file_id) as u64 * LABEL_SIZE as u64;
+
+    if !src.exists() {
+        // Already moved by an interrupted relocation.
+        eyre::ensure!(!options.keep_source, "source file is missing");
+        let dst_size = std::fs::metadata(&dst)
+            .wrap_err("source file is missing and it wasn't moved")?
+            .len();
+        eyre::ensure!(
+            dst_size == size,
+            "source file is missing and the moved file has invalid size {dst_size} (expected {size})"
+        );
+        return Ok(expected);
+    }
+
+    let src_size = std::fs::metadata(&src)?.len();
+    eyre::ensure!(
+        src_size == size,
+        "invalid size of the source file {src_size} (expected {size})"
+    );
+
+    if !options.keep_source && std::fs::rename(&src, &dst).is_ok() {
+        log::debug!("moved {} to {}", src.display(), dst.display());
+        return Ok(expected);
+    }
+
+    let expected = match expected {
+        Some(checksum) => Some(checksum),
+        None if options.verify => Some(file_checksum(&src)?),
+        None => None,
+    };
+    let checksum = copy_file(&src, &dst)?;
+    if matches!(expected, Some(expected) if expected != checksum) {
+        // Start over next time.
+        std::fs::remove_file(&dst)?;
+        eyre::bail!("checksum of the copied file doesn't match, removed the copy");
+    }
+
+    if !options.keep_source {
+        std::fs::remove_file(&src)?;
+    }
+    log::debug!("copied {} to {}", src.display(), dst.display());
+    Ok(expected.or(Some(checksum)))
+}
+
+/// Copy `src` to `dst`, continuing after the data already in `dst`.
+/// Returns the checksum of the copy.
+fn copy_file(src: &Path, dst: &Path) -> eyre::Result<[u8; 32]> {
+    let mut src = File::open(src)?;
+    let src_size = src.metadata()?.len();
+    let mut dst = File::options()
+        .create(true)
+        .truncate(false)
+        .read(true)
+        .write(true)
+        .open(dst)?;
+    if dst.metadata()?.len() > src_size {
+        dst.set_len(0)?;
+    }
+
+    let mut hasher = blake3::Hasher::new();
+    let copied = std::io::copy(&mut (&dst).take(src_size), &mut hasher)?;
+    if copied > 0 {
+        log::info!("resuming copying at byte {copied}");
+    }
+    src.seek(SeekFrom::Start(copied))?;
+    dst.seek(SeekFrom::Start(copied))?;
+
+    let mut writer = HashingWriter {
+        writer: dst,
+        hasher,
+    };
+    std::io::copy(&mut src, &mut writer)?;
+    writer.flush()?;
+    let (dst, checksum) = writer.finish();
+    dst.sync_all()?;
+    Ok(checksum)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::initialize::{verify_checksums, CpuInitializer, Initialize};
+
+    use super::*;
+
+    fn initialize(datadir: &Path) -> PostMetadata {
+        CpuInitializer::new(crate::config::ScryptParams::new(2, 1, 1))
+            .initialize(datadir, &[0; 32], &[0; 32], 100, 3, 120, None)
+            .unwrap()
+    }
+
+    fn read_files(datadir: &Path, metadata: &PostMetadata) -> Vec<Vec<u8>> {
+        (0..metadata.num_files())
+            .map(|id| std::fs::read(datadir.join(metadata.file_name(id))).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn moving_data() {
+        let src = tempfile::tempdir().unwrap();
+        let dst = tempfile::tempdir().unwrap();
+        let metadata = initialize(src.path());
+        let files = read_files(src.path(), &metadata);
+
+        relocate(src.path(), dst.path(), RelocateOptions::default()).unwrap();
+
+        assert_eq!(files, read_files(dst.path(), &metadata));
+        assert!(metadata::load_if_exists(src.path()).unwrap().is_none());
+        assert_eq!(0, src.path().read_dir().unwrap().count());
+        assert!(verify_checksums(dst.path()).unwrap().is_empty());
+    }
+
+    #[test]
+    fn copying_data() {
+        let src = tempfile::tempdir().unwrap();
+        let dst = tempfile::tempdir().unwrap();
+        let metadata = initialize(src.path());
+        let files = read_files(src.path(), &metadata);
+
+        let options = RelocateOptions {
+            keep_source: true,
+            ..Default::default()
+        };
+        relocate(src.path(), dst.path(), options).unwrap();
+
+        assert_eq!(files, read_files(src.path(), &metadata));
+        assert_eq!(files, read_files(dst.path(), &metadata));
+        assert_eq!(
+            metadata::load_checksums(src.path()).unwrap(),
+            metadata::load_checksums(dst.path()).unwrap()
+        );
+    }
+
+    #[test]
+    fn resuming_partial_copy() {
+        let src = tempfile::tempdir().unwrap();
+        let dst = tempfile::tempdir().unwrap();
+        let metadata = initialize(src.path());
+        let files = read_files(src.path(), &metadata);
+
+        // The first file was moved, the second one copied partially.
+        std::fs::rename(
+            src.path().join(metadata.file_name(0)),
+            dst.path().join(metadata.file_name(0)),
+        )
+        .unwrap();
+        std::fs::write(dst.path().join(metadata.file_name(1)), &files[1][..100]).unwrap();
+
+        let checksum = copy_file(
+            &src.path().join(metadata.file_name(1)),
+            &dst.path().join(metadata.file_name(1)),
+        )
+        .unwrap();
+        assert_eq!(*blake3::hash(&files[1]).as_bytes(), checksum);
+
+        relocate(src.path(), dst.path(), RelocateOptions::default()).unwrap();
+        assert_eq!(files, read_files(dst.path(), &metadata));
+    }
+
+    #[test]
+    fn detects_corrupted_copy() {
+        let src = tempfile::tempdir().unwrap();
+        let dst = tempfile::tempdir().unwrap();
+        let metadata = initialize(src.path());
+        let files = read_files(src.path(), &metadata);
+
+        // A garbage prefix left by a previous copy.
+        std::fs::write(dst.path().join(metadata.file_name(0)), vec![0xFF; 32]).unwrap();
+        let options = RelocateOptions {
+            keep_source: true,
+            ..Default::default()
+        };
+        assert!(relocate(src.path(), dst.path(), options).is_err());
+        // The source is intact and relocating again succeeds.
+        assert_eq!(files, read_files(src.path(), &metadata));
+        relocate(src.path(), dst.path(), options).unwrap();
+        assert_eq!(files, read_files(dst.path(), &metadata));
+    }
+
+    #[test]
+    fn rejects_same_directory() {
+        let src = tempfile::tempdir().unwrap();
+        initialize(src.path());
+        assert!(relocate(src.path(), src.path(), RelocateOptions::default()).is_err());
+    }
+}
@@ -0,0 +1,247 @@
+//! Streaming initialization.
+//!
+//! Labels are initialized on a background thread and read through [std::io::Read],
+//! so they can be piped to any consumer (i.e. a compressor, a network socket
+//! or a custom storage engine). The buffering between the initializer and
+//! the consumer is bounded - a slow consumer pauses the initialization.
+
+use std::{
+    error::Error,
+    io::{Read, Write},
+    ops::Range,
+    sync::mpsc::{sync_channel, Receiver, SyncSender},
+    thread::JoinHandle,
+};
+
+use super::{Initialize, VrfNonce, LABEL_SIZE};
+
+/// Number of chunks the buffer is split into.
+const CHUNKS: usize = 16;
+
+/// Sends the written data in chunks of `chunk_size` bytes.
+struct ChannelWriter {
+    tx: SyncSender<Vec<u8>>,
+    chunk: Vec<u8>,
+    chunk_size: usize,
+}
+
+impl ChannelWriter {
+    fn send_chunk(&mut self) -> std::io::Result<()> {
+        let chunk = std::mem::replace(&mut self.chunk, Vec::with_capacity(self.chunk_size));
+        self.tx
+            .send(chunk)
+            .map_err(|_| std::io::Error::new(std::io::ErrorKind::BrokenPipe, "stream dropped"))
+    }
+}
+
+impl Write for ChannelWriter {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let len = buf.len().min(self.chunk_size - self.chunk.len());
+        self.chunk.extend_from_slice(&buf[..len]);
+        if self.chunk.len() == self.chunk_size {
+            self.send_chunk()?;
+        }
+        Ok(len)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        if !self.chunk.is_empty() {
+            self.send_chunk()?;
+        }
+        Ok(())
+    }
+}
+
+type InitResult = Result<Option<VrfNonce>, String>;
+
+/// Stream of labels initialized in the background.
+///
+/// ```no_run
+/// # use post::{config::ScryptParams, initialize::{CpuInitializer, LabelStream}};
+/// let initializer = CpuInitializer::new(ScryptParams::new(8192, 1, 1));
+/// let mut stream = LabelStream::new(Box::new(initializer), [0; 32], 0..1000, None, 1024 * 1024);
+/// std::io::copy(&mut stream, &mut std::io::sink()).unwrap();
+/// let vrf_nonce = stream.finish().unwrap();
+/// ```
+pub struct LabelStream {
+    rx: Receiver<Vec<u8>>,
+    chunk: Vec<u8>,
+    pos: usize,
+    handle: Option<JoinHandle<InitResult>>,
+    result: Option<InitResult>,
+}
+
+impl LabelStream {
+    /// Start initializing `labels` on a background thread.
+    ///
+    /// At most about `buffer_size` bytes of labels are initialized ahead of the reader.
+    pub fn new(
+        mut initializer: Box<dyn Initialize + Send>,
+        commitment: [u8; 32],
+        labels: Range<u64>,
+        vrf_difficulty: Option<[u8; 32]>,
+        buffer_size: usize,
+    ) -> Self {
+        let chunk_size = (buffer_size / CHUNKS / LABEL_SIZE).max(1) * LABEL_SIZE;
+        let (tx, rx) = sync_channel(CHUNKS - 1);
+        let handle = std::thread::spawn(move || {
+            let mut writer = ChannelWriter {
+                tx,
+                chunk: Vec::with_capacity(chunk_size),
+                chunk_size,
+            };
+            let nonce = initializer
+                .initialize_to(&mut writer, &commitment, labels, vrf_difficulty)
+                .map_err(|e| e.to_string())?;
+            writer.flush().map_err(|e| e.to_string())?;
+            Ok(nonce)
+        });
+
+        Self {
+            rx,
+            chunk: Vec::new(),
+            pos: 0,
+            handle: Some(handle),
+            result: None,
+        }
+    }
+
+    fn join(&mut self) -> &InitResult {
+        let handle = self.handle.take();
+        self.result.get_or_insert_with(|| match handle {
+            Some(handle) => handle
+                .join()
+                .unwrap_or_else(|_| Err("initialization thread panicked".to_string())),
+            None => Err("initialization thread missing".to_string()),
+        })
+    }
+
+    /// Wait for the initialization to finish and return the found VRF nonce.
+    ///
+    /// Should be called after reading the whole stream, otherwise
+    /// the initialization is interrupted.
+    pub fn finish(mut self) -> Result<Option<VrfNonce>, Box<dyn Error>> {
+        // Unblock the initializer if the stream wasn't read to the end.
+        let (_, rx) = sync_channel(0);
+        drop(std::mem::replace(&mut self.rx, rx));
+        Ok(self.join().clone()?)
+    }
+}
+
+impl Read for LabelStream {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        if self.pos == self.chunk.len() {
+            match self.rx.recv() {
+                Ok(chunk) => {
+                    self.chunk = chunk;
+                    self.pos = 0;
+                }
+                // The initializer finished (or failed).
+                Err(_) => {
+                    return match self.join() {
+                        Ok(_) => Ok(0),
+                        Err(e) => Err(std::io::Error::new(std::io::ErrorKind::Other, e.clone())),
+                    }
+                }
+            }
+        }
+        let len = buf.len().min(self.chunk.len() - self.pos);
+        buf[..len].copy_from_slice(&self.chunk[self.pos..self.pos + len]);
+        self.pos += len;
+        Ok(len)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        sync::{
+            atomic::{AtomicU64, Ordering},
+            Arc,
+        },
+        time::Duration,
+    };
+
+    use crate::{
+        config::ScryptParams,
+        initialize::{CpuInitializer, MockInitialize},
+    };
+
+    use super::*;
+
+    #[test]
+    fn streams_same_labels_as_initialize_to() {
+        let params = ScryptParams::new(2, 1, 1);
+        let labels = 17..5000;
+        let difficulty = Some([0x1F; 32]);
+        let mut expected = Vec::new();
+        let expected_nonce = CpuInitializer::new(params)
+            .initialize_to(&mut expected, &[3; 32], labels.clone(), difficulty)
+            .unwrap();
+
+        let initializer = Box::new(CpuInitializer::new(params));
+        let mut stream = LabelStream::new(initializer, [3; 32], labels, difficulty, 1000);
+        let mut data = Vec::new();
+        stream.read_to_end(&mut data).unwrap();
+
+        assert_eq!(expected, data);
+        assert_eq!(expected_nonce, stream.finish().unwrap());
+    }
+
+    /// Writes labels one by one counting them.
+    struct CountingInitializer(Arc<AtomicU64>);
+
+    impl Initialize for CountingInitializer {
+        fn initialize_to(
+            &mut self,
+            writer: &mut dyn Write,
+            _: &[u8; 32],
+            labels: Range<u64>,
+            _: Option<[u8; 32]>,
+        ) -> Result<Option<VrfNonce>, Box<dyn Error>> {
+            for _ in labels {
+                writer.write_all(&[0u8; LABEL_SIZE])?;
+                self.0.fetch_add(1, Ordering::SeqCst);
+            }
+            Ok(None)
+        }
+    }
+
+    #[test]
+    fn slow_reader_pauses_initialization() {
+        let written = Arc::new(AtomicU64::new(0));
+        let initializer = Box::new(CountingInitializer(written.clone()));
+        // 16 chunks of a single label
+        let mut stream = LabelStream::new(initializer, [0; 32], 0..1000, None, 16 * LABEL_SIZE);
+
+        std::thread::sleep(Duration::from_millis(100));
+        // buffered chunks + the chunk being sent
+        assert!(written.load(Ordering::SeqCst) <= CHUNKS as u64 + 1);
+
+        let mut data = Vec::new();
+        stream.read_to_end(&mut data).unwrap();
+        assert_eq!(1000 * LABEL_SIZE, data.len());
+        assert_eq!(1000, written.load(Ordering::SeqCst));
+    }
+
+    #[test]
+    fn finishing_early_interrupts_initialization() {
+        let written = Arc::new(AtomicU64::new(0));
+        let initializer = Box::new(CountingInitializer(written.clone()));
+        let stream = LabelStream::new(initializer, [0; 32], 0..1000, None, 16 * LABEL_SIZE);
+        assert!(stream.finish().is_err());
+        assert!(written.load(Ordering::SeqCst) < 1000);
+    }
+
+    #[test]
+    fn reports_initialization_failure() {
+        let mut initializer = MockInitialize::new();
+        initializer
+            .expect_initialize_to()
+            .returning(|_, _, _, _| Err("failed".into()));
+        let mut stream = LabelStream::new(Box::new(initializer), [0; 32], 0..100, None, 1024);
+        let err = stream.read_to_end(&mut Vec::new()).unwrap_err();
+        assert_eq!("failed", err.to_string());
+        assert_eq!("failed", stream.finish().unwrap_err().to_string());
+    }
+}
@@ -1,12 +1,15 @@
+pub mod cancel;
 mod cipher;
 mod compression;
 pub mod config;
 mod difficulty;
+pub mod encryption;
 pub mod initialize;
 pub mod metadata;
 pub mod pos_verification;
 pub mod pow;
 pub mod prove;
 mod random_values_gen;
+mod rate_limit;
 pub mod reader;
 pub mod verification;
@@ -2,9 +2,13 @@ use std::{fs::File, io::BufReader, path::Path};
 
 use serde::{Deserialize, Serialize};
 use serde_with::base64::Base64;
+use serde_with::hex::Hex;
 use serde_with::serde_as;
 
-const METADATA_FILE_NAME: &str = "postdata_metadata.json";
+use crate::config::FileLayout;
+
+pub const METADATA_FILE_NAME: &str = "postdata_metadata.json";
+const METADATA_TMP_FILE_NAME: &str = "postdata_metadata.json.tmp";
 
 #[serde_as]
 #[derive(Debug, Deserialize, Serialize, Clone, Copy, Default)]
@@ -17,11 +21,32 @@ pub struct PostMetadata {
     pub labels_per_unit: u64,
     pub num_units: u32,
     pub max_file_size: u64,
+    /// See [FileLayout::file_index_width].
+    #[serde(default)]
+    pub file_index_width: u8,
     pub nonce: Option<u64>,
+    /// Number of labels (counting from 0) already initialized and searched for the VRF nonce.
+    /// It's set only while the initialization is in progress.
     pub last_position: Option<u64>,
+    /// Set if the POS data is encrypted, see [crate::encryption::DataKey::check].
+    #[serde_as(as = "Option<Base64>")]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub encryption_key_check: Option<[u8; 16]>,
 }
 
 impl PostMetadata {
+    pub fn layout(&self) -> FileLayout {
+        FileLayout {
+            max_file_size: self.max_file_size,
+            file_index_width: self.file_index_width,
+        }
+    }
+
+    /// Name of the POS data file with the given index.
+    pub fn file_name(&self, idx: usize) -> String {
+        self.layout().file_name(idx as u64)
+    }
+
     pub fn total_labels(&self) -> u64 {
         self.num_units as u64 * self.labels_per_unit
     }
@@ -52,12 +77,132 @@ impl PostMetadata {
     }
 }
 
+/// Content of the metadata file.
+#[serde_as]
+#[derive(Deserialize, Serialize)]
+#[serde(rename_all = "PascalCase")]
+struct MetadataFile {
+    #[serde(flatten)]
+    metadata: PostMetadata,
+    /// BLAKE3 checksums of the POS data files, indexed by file ID.
+    #[serde_as(as = "Vec<Hex>")]
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    file_checksums: Vec<[u8; 32]>,
+}
+
+fn read_file(path: &Path) -> eyre::Result<MetadataFile> {
+    let reader = BufReader::new(File::open(path)?);
+    Ok(serde_json::from_reader(reader)?)
+}
+
+/// Read the metadata file.
+///
+/// If it's missing or corrupted (i.e. it was written partially in place by an older version),
+/// it's recovered from the temporary file, if that one is complete.
+/// The temporary file is complete if the crash happened right before renaming it.
+fn read(datadir: &Path) -> eyre::Result<MetadataFile> {
+    let path = datadir.join(METADATA_FILE_NAME);
+    let err = match read_file(&path) {
+        Ok(file) => return Ok(file),
+        Err(err) => err,
+    };
+    let tmp_path = datadir.join(METADATA_TMP_FILE_NAME);
+    match read_file(&tmp_path) {
+        Ok(file) => {
+            log::warn!(
+                "recovering metadata {} from {} ({err})",
+                path.display(),
+                tmp_path.display()
+            );
+            std::fs::rename(&tmp_path, &path)?;
+            Ok(file)
+        }
+        Err(_) => Err(err.wrap_err(format!("reading {}", path.display()))),
+    }
+}
+
 pub fn load(datadir: &Path) -> eyre::Result<PostMetadata> {
-    let metatada_path = datadir.join(METADATA_FILE_NAME);
-    let metadata_file = File::open(metatada_path)?;
-    let reader = BufReader::new(metadata_file);
-    let m = serde_json::from_reader(reader)?;
-    Ok(m)
+    Ok(read(datadir)?.metadata)
+}
+
+/// Load metadata if the metadata file exists in the `datadir`.
+pub fn load_if_exists(datadir: &Path) -> eyre::Result<Option<PostMetadata>> {
+    match exists(datadir) {
+        true => load(datadir).map(Some),
+        false => Ok(None),
+    }
+}
+
+/// Whether the metadata file (or the temporary one to recover it from) exists.
+fn exists(datadir: &Path) -> bool {
+    datadir.join(METADATA_FILE_NAME).exists() || datadir.join(METADATA_TMP_FILE_NAME).exists()
+}
+
+/// Load BLAKE3 checksums of the POS data files (indexed by file ID) recorded in the metadata.
+/// It's empty if checksums were not recorded.
+pub fn load_checksums(datadir: &Path) -> eyre::Result<Vec<[u8; 32]>> {
+    Ok(read(datadir)?.file_checksums)
+}
+
+/// Save metadata, keeping the file checksums already recorded.
+/// Fails if the existing metadata file can't be read, not to lose its checksums.
+pub fn save(datadir: &Path, metadata: &PostMetadata) -> eyre::Result<()> {
+    let checksums = match exists(datadir) {
+        true => load_checksums(datadir)?,
+        false => Vec::new(),
+    };
+    save_with_checksums(datadir, metadata, &checksums)
+}
+
+/// Save metadata together with the checksums of the POS data files.
+///
+/// The metadata file is replaced atomically - the new content is written
+/// to a temporary file, synced and then renamed over the metadata file.
+pub fn save_with_checksums(
+    datadir: &Path,
+    metadata: &PostMetadata,
+    checksums: &[[u8; 32]],
+) -> eyre::Result<()> {
+    let file = MetadataFile {
+        metadata: *metadata,
+        file_checksums: checksums.to_vec(),
+    };
+    replace_file(
+        datadir,
+        METADATA_FILE_NAME,
+        METADATA_TMP_FILE_NAME,
+        |tmp_file| Ok(serde_json::to_writer_pretty(tmp_file, &file)?),
+    )
+}
+
+/// Replace the file `name` in the `dir` atomically with the content written by `write`
+/// to the temporary file `tmp_name`, which is synced and then renamed over the file.
+pub(crate) fn replace_file(
+    dir: &Path,
+    name: &str,
+    tmp_name: &str,
+    write: impl FnOnce(&mut File) -> eyre::Result<()>,
+) -> eyre::Result<()> {
+    let tmp_path = dir.join(tmp_name);
+    let mut tmp_file = File::create(&tmp_path)?;
+    write(&mut tmp_file)?;
+    tmp_file.sync_all()?;
+    std::fs::rename(&tmp_path, dir.join(name))?;
+    // Persist the rename.
+    #[cfg(unix)]
+    File::open(dir)?.sync_all()?;
+    Ok(())
+}
+
+/// Remove the metadata file (and the temporary one) from the `datadir`.
+pub fn remove(datadir: &Path) -> std::io::Result<()> {
+    for name in [METADATA_FILE_NAME, METADATA_TMP_FILE_NAME] {
+        match std::fs::remove_file(datadir.join(name)) {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => return Err(e),
+            _ => {}
+        }
+    }
+    Ok(())
 }
 
 #[repr(C)]
@@ -86,7 +231,7 @@ impl ProofMetadata {
 
 #[cfg(test)]
 mod tests {
-    use super::PostMetadata;
+    use super::*;
 
     #[test]
     fn test_num_files() {
@@ -118,4 +263,127 @@ mod tests {
         assert_eq!(1, m.labels_in_file(0));
         assert_eq!(0, m.labels_in_file(1));
     }
+
+    #[test]
+    fn saving_keeps_checksums() {
+        let datadir = tempfile::tempdir().unwrap();
+        let metadata = PostMetadata {
+            labels_per_unit: 100,
+            num_units: 2,
+            max_file_size: 1600,
+            ..Default::default()
+        };
+        save_with_checksums(datadir.path(), &metadata, &[[1; 32], [2; 32]]).unwrap();
+        assert_eq!(
+            vec![[1; 32], [2; 32]],
+            load_checksums(datadir.path()).unwrap()
+        );
+
+        save(
+            datadir.path(),
+            &PostMetadata {
+                nonce: Some(7),
+                ..metadata
+            },
+        )
+        .unwrap();
+        assert_eq!(Some(7), load(datadir.path()).unwrap().nonce);
+        assert_eq!(
+            vec![[1; 32], [2; 32]],
+            load_checksums(datadir.path()).unwrap()
+        );
+    }
+
+    #[test]
+    fn saving_fails_on_unreadable_metadata() {
+        let datadir = tempfile::tempdir().unwrap();
+        save(datadir.path(), &PostMetadata::default()).unwrap();
+
+        std::fs::write(datadir.path().join(METADATA_FILE_NAME), "{").unwrap();
+        assert!(save(datadir.path(), &PostMetadata::default()).is_err());
+        assert_eq!(
+            b"{".as_slice(),
+            std::fs::read(datadir.path().join(METADATA_FILE_NAME)).unwrap()
+        );
+    }
+
+    #[test]
+    fn loading_metadata_without_checksums() {
+        let datadir = tempfile::tempdir().unwrap();
+        let metadata = PostMetadata {
+            num_units: 2,
+            ..Default::default()
+        };
+        std::fs::write(
+            datadir.path().join(METADATA_FILE_NAME),
+            serde_json::to_vec(&metadata).unwrap(),
+        )
+        .unwrap();
+        assert!(load_checksums(datadir.path()).unwrap().is_empty());
+        assert_eq!(2, load(datadir.path()).unwrap().num_units);
+    }
+
+    #[test]
+    fn saving_replaces_metadata_file() {
+        let datadir = tempfile::tempdir().unwrap();
+        let metadata = PostMetadata {
+            num_units: 2,
+            ..Default::default()
+        };
+        save(datadir.path(), &metadata).unwrap();
+        save(
+            datadir.path(),
+            &PostMetadata {
+                num_units: 3,
+                ..metadata
+            },
+        )
+        .unwrap();
+        assert_eq!(3, load(datadir.path()).unwrap().num_units);
+        assert!(!datadir.path().join(METADATA_TMP_FILE_NAME).exists());
+    }
+
+    #[test]
+    fn recovering_partially_written_metadata() {
+        let datadir = tempfile::tempdir().unwrap();
+        let metadata = PostMetadata {
+            num_units: 2,
+            ..Default::default()
+        };
+        save_with_checksums(datadir.path(), &metadata, &[[1; 32]]).unwrap();
+        let content = std::fs::read(datadir.path().join(METADATA_FILE_NAME)).unwrap();
+
+        // Partial metadata file without a temporary file can't be recovered
+        let path = datadir.path().join(METADATA_FILE_NAME);
+        std::fs::write(&path, &content[..content.len() / 2]).unwrap();
+        assert!(load(datadir.path()).is_err());
+
+        // Complete temporary file is used to recover
+        std::fs::write(datadir.path().join(METADATA_TMP_FILE_NAME), &content).unwrap();
+        assert_eq!(2, load(datadir.path()).unwrap().num_units);
+        assert_eq!(content, std::fs::read(&path).unwrap());
+        assert!(!datadir.path().join(METADATA_TMP_FILE_NAME).exists());
+        assert_eq!(vec![[1; 32]], load_checksums(datadir.path()).unwrap());
+    }
+
+    #[test]
+    fn recovering_missing_metadata() {
+        let datadir = tempfile::tempdir().unwrap();
+        assert!(load_if_exists(datadir.path()).unwrap().is_none());
+
+        let metadata = PostMetadata {
+            num_units: 2,
+            ..Default::default()
+        };
+        save(datadir.path(), &metadata).unwrap();
+        std::fs::rename(
+            datadir.path().join(METADATA_FILE_NAME),
+            datadir.path().join(METADATA_TMP_FILE_NAME),
+        )
+        .unwrap();
+        assert_eq!(
+            2,
+            load_if_exists(datadir.path()).unwrap().unwrap().num_units
+        );
+    }
 }
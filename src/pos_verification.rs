@@ -39,7 +39,7 @@ pub fn verify_files(
     log::info!("verifying POS files {from_file} -> {to_file}");
 
     for idx in from_file..=to_file {
-        let file_path = datadir.join(format!("postdata_{}.bin", idx));
+        let file_path = datadir.join(metadata.file_name(idx));
         log::info!("verifying file {}", file_path.display());
 
         let file = std::fs::File::open(file_path)?;
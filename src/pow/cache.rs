@@ -0,0 +1,365 @@
+//! Caching found proofs of work.
+//!
+//! The k2pow of a nonce group takes minutes to hours on slow CPUs. When the proof
+//! generation is retried for the same challenge (i.e. after a restart),
+//! it would calculate them all over again. The [PowCache] remembers the proofs
+//! of work found, in memory and optionally in a file, so that they are calculated once.
+//! It's shared by the proof generations (of a POS data directory) with a [CachingProver].
+
+use std::{
+    collections::VecDeque,
+    fs::File,
+    io::{self, BufReader},
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+};
+
+use serde::{Deserialize, Serialize};
+use serde_with::{base64::Base64, serde_as};
+
+use super::{Error, PowVerifier, Prover};
+use crate::{cancel::CancellationToken, prove::ProofVersion};
+
+/// Maximal number of proofs of work cached, the oldest ones are evicted first.
+const MAX_CACHED_POWS: usize = 4096;
+
+/// A proof of work with the input it was found for.
+#[serde_as]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+struct CachedPow {
+    nonce_group: u32,
+    #[serde_as(as = "Base64")]
+    challenge: [u8; 8],
+    #[serde_as(as = "Base64")]
+    difficulty: [u8; 32],
+    #[serde_as(as = "Base64")]
+    miner_id: [u8; 32],
+    pow: u64,
+}
+
+impl CachedPow {
+    fn matches(
+        &self,
+        nonce_group: u32,
+        challenge: &[u8; 8],
+        difficulty: &[u8; 32],
+        miner_id: &[u8; 32],
+    ) -> bool {
+        self.nonce_group == nonce_group
+            && self.challenge == *challenge
+            && self.difficulty == *difficulty
+            && self.miner_id == *miner_id
+    }
+}
+
+/// Proofs of work found before, keyed by the nonce group, the challenge,
+/// the difficulty and the miner ID.
+///
+/// If backed by a file, the cached proofs of work are loaded from it (dropping
+/// those that don't verify) and every newly found one is saved to it.
+#[derive(Debug, Default)]
+pub struct PowCache {
+    path: Option<PathBuf>,
+    pows: Mutex<VecDeque<CachedPow>>,
+}
+
+impl PowCache {
+    /// Cache the proofs of work in memory.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Cache the proofs of work in the file at `path` too, loading those cached in it.
+    ///
+    /// The loaded proofs of work are verified with the `verifier`, the invalid ones
+    /// (i.e. from a corrupted file) are dropped. A file that can't be parsed
+    /// is replaced with the proofs of work found from now on.
+    pub fn with_file(self, path: &Path, verifier: &dyn PowVerifier) -> eyre::Result<Self> {
+        let loaded: Vec<CachedPow> = match File::open(path) {
+            Ok(file) => serde_json::from_reader(BufReader::new(file)).unwrap_or_else(|e| {
+                log::warn!(
+                    "ignoring corrupted cached proofs of work in {}: {e}",
+                    path.display()
+                );
+                Vec::new()
+            }),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Vec::new(),
+            Err(e) => return Err(e.into()),
+        };
+        let mut pows = VecDeque::<CachedPow>::new();
+        for cached in loaded {
+            let valid = super::verify(
+                verifier,
+                ProofVersion::of_nonce_group(cached.nonce_group),
+                cached.pow,
+                cached.nonce_group,
+                &cached.challenge,
+                &cached.difficulty,
+                &cached.miner_id,
+            )
+            .is_ok();
+            if valid {
+                pows.push_back(cached);
+            } else {
+                log::warn!(
+                    "dropping invalid cached proof of work for nonce group {}",
+                    cached.nonce_group
+                );
+            }
+        }
+        let evicted = pows.len().saturating_sub(MAX_CACHED_POWS);
+        pows.drain(..evicted);
+        log::info!(
+            "loaded {} cached proofs of work from {}",
+            pows.len(),
+            path.display()
+        );
+        Ok(Self {
+            path: Some(path.to_path_buf()),
+            pows: Mutex::new(pows),
+        })
+    }
+
+    fn get(
+        &self,
+        nonce_group: u32,
+        challenge: &[u8; 8],
+        difficulty: &[u8; 32],
+        miner_id: &[u8; 32],
+    ) -> Option<u64> {
+        self.pows
+            .lock()
+            .unwrap()
+            .iter()
+            .find(|c| c.matches(nonce_group, challenge, difficulty, miner_id))
+            .map(|c| c.pow)
+    }
+
+    /// The cached proof of work of the input or the one found with `prove`.
+    fn get_or_prove(
+        &self,
+        nonce_group: u32,
+        challenge: &[u8; 8],
+        difficulty: &[u8; 32],
+        miner_id: &[u8; 32],
+        prove: impl FnOnce() -> Result<u64, Error>,
+    ) -> Result<u64, Error> {
+        if let Some(pow) = self.get(nonce_group, challenge, difficulty, miner_id) {
+            log::debug!("using cached proof of work for nonce group {nonce_group}");
+            return Ok(pow);
+        }
+        let pow = prove()?;
+        self.insert(CachedPow {
+            nonce_group,
+            challenge: *challenge,
+            difficulty: *difficulty,
+            miner_id: *miner_id,
+            pow,
+        });
+        Ok(pow)
+    }
+
+    fn insert(&self, cached: CachedPow) {
+        let mut pows = self.pows.lock().unwrap();
+        if pows.len() >= MAX_CACHED_POWS {
+            pows.pop_front();
+        }
+        pows.push_back(cached);
+        if let Some(path) = &self.path {
+            if let Err(e) = save(path, pows.make_contiguous()) {
+                log::warn!("failed to save cached proofs of work: {e:?}");
+            }
+        }
+    }
+}
+
+/// Save the `pows` in the file at `path`, replacing it atomically.
+fn save(path: &Path, pows: &[CachedPow]) -> eyre::Result<()> {
+    let tmp_path = path.with_extension("tmp");
+    let mut tmp_file = File::create(&tmp_path)?;
+    serde_json::to_writer(&mut tmp_file, pows)?;
+    tmp_file.sync_all()?;
+    std::fs::rename(&tmp_path, path)?;
+    Ok(())
+}
+
+/// A [Prover] answering from the [PowCache], calculating the proofs of work
+/// missing in it with another prover.
+pub struct CachingProver {
+    cache: Arc<PowCache>,
+    prover: Arc<dyn Prover + Send + Sync>,
+}
+
+impl CachingProver {
+    /// Cache the proofs of work found by the `prover` in the `cache`.
+    pub fn new(cache: Arc<PowCache>, prover: Arc<dyn Prover + Send + Sync>) -> Self {
+        Self { cache, prover }
+    }
+}
+
+impl Prover for CachingProver {
+    fn prove(
+        &self,
+        nonce_group: u8,
+        challenge: &[u8; 8],
+        difficulty: &[u8; 32],
+        miner_id: &[u8; 32],
+        cancel: &CancellationToken,
+    ) -> Result<u64, Error> {
+        self.cache
+            .get_or_prove(nonce_group as u32, challenge, difficulty, miner_id, || {
+                self.prover
+                    .prove(nonce_group, challenge, difficulty, miner_id, cancel)
+            })
+    }
+
+    fn prove_wide(
+        &self,
+        nonce_group: u32,
+        challenge: &[u8; 8],
+        difficulty: &[u8; 32],
+        miner_id: &[u8; 32],
+        cancel: &CancellationToken,
+    ) -> Result<u64, Error> {
+        self.cache
+            .get_or_prove(nonce_group, challenge, difficulty, miner_id, || {
+                self.prover
+                    .prove_wide(nonce_group, challenge, difficulty, miner_id, cancel)
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use mockall::predicate::eq;
+
+    use super::*;
+    use crate::pow::{MockPowVerifier, MockProver};
+
+    #[test]
+    fn caching_pows_in_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("k2pow_cache.json");
+        let cancel = CancellationToken::new();
+
+        let mut prover = MockProver::new();
+        prover
+            .expect_prove()
+            .times(2)
+            .returning(|nonce_group, challenge, _, _, _| {
+                Ok(nonce_group as u64 + challenge[0] as u64)
+            });
+        let cache = PowCache::new()
+            .with_file(&path, &MockPowVerifier::new())
+            .unwrap();
+        let cache = CachingProver::new(Arc::new(cache), Arc::new(prover));
+        assert_eq!(
+            1,
+            cache
+                .prove(0, &[1; 8], &[2; 32], &[3; 32], &cancel)
+                .unwrap()
+        );
+        assert_eq!(
+            1,
+            cache
+                .prove(0, &[1; 8], &[2; 32], &[3; 32], &cancel)
+                .unwrap()
+        );
+        // Another challenge is calculated.
+        assert_eq!(
+            2,
+            cache
+                .prove(0, &[2; 8], &[2; 32], &[3; 32], &cancel)
+                .unwrap()
+        );
+
+        // Reloaded from the file, dropping the proof of work that doesn't verify.
+        let mut verifier = MockPowVerifier::new();
+        verifier
+            .expect_verify()
+            .with(eq(1), eq(0), eq([1; 8]), eq([2; 32]), eq([3; 32]))
+            .returning(|_, _, _, _, _| Ok(()));
+        verifier
+            .expect_verify()
+            .returning(|_, _, _, _, _| Err(Error::InvalidPoW));
+        let mut prover = MockProver::new();
+        prover
+            .expect_prove()
+            .once()
+            .returning(|_, _, _, _, _| Ok(7));
+        let cache = PowCache::new().with_file(&path, &verifier).unwrap();
+        let cache = CachingProver::new(Arc::new(cache), Arc::new(prover));
+        assert_eq!(
+            1,
+            cache
+                .prove(0, &[1; 8], &[2; 32], &[3; 32], &cancel)
+                .unwrap()
+        );
+        assert_eq!(
+            7,
+            cache
+                .prove(0, &[2; 8], &[2; 32], &[3; 32], &cancel)
+                .unwrap()
+        );
+    }
+
+    #[test]
+    fn ignoring_corrupted_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("k2pow_cache.json");
+        std::fs::write(&path, "[{").unwrap();
+        let cancel = CancellationToken::new();
+
+        let mut prover = MockProver::new();
+        prover
+            .expect_prove()
+            .once()
+            .returning(|_, _, _, _, _| Ok(7));
+        let cache = PowCache::new()
+            .with_file(&path, &MockPowVerifier::new())
+            .unwrap();
+        let cache = CachingProver::new(Arc::new(cache), Arc::new(prover));
+        assert_eq!(
+            7,
+            cache
+                .prove(0, &[1; 8], &[2; 32], &[3; 32], &cancel)
+                .unwrap()
+        );
+
+        // The corrupted file was replaced.
+        let mut verifier = MockPowVerifier::new();
+        verifier.expect_verify().returning(|_, _, _, _, _| Ok(()));
+        let cache = PowCache::new().with_file(&path, &verifier).unwrap();
+        assert_eq!(Some(7), cache.get(0, &[1; 8], &[2; 32], &[3; 32]));
+    }
+
+    #[test]
+    fn evicting_oldest_pows() {
+        let mut prover = MockProver::new();
+        prover
+            .expect_prove()
+            .returning(|nonce_group, _, _, _, _| Ok(nonce_group as u64));
+        prover
+            .expect_prove_wide()
+            .returning(|nonce_group, _, _, _, _| Ok(nonce_group as u64));
+        let cache = Arc::new(PowCache::new());
+        let prover = CachingProver::new(cache.clone(), Arc::new(prover));
+        let cancel = CancellationToken::new();
+        for nonce_group in 0..=MAX_CACHED_POWS as u32 {
+            let version = ProofVersion::of_nonce_group(nonce_group);
+            crate::pow::prove(
+                &prover,
+                version,
+                nonce_group,
+                &[0; 8],
+                &[0; 32],
+                &[0; 32],
+                &cancel,
+            )
+            .unwrap();
+        }
+        let pows = cache.pows.lock().unwrap();
+        assert_eq!(MAX_CACHED_POWS, pows.len());
+        assert_eq!(1, pows.front().unwrap().nonce_group);
+    }
+}
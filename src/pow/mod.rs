@@ -5,34 +5,121 @@
 //! with a powerful enough computer could try many nonces
 //! at the same time. In effect a proof could be found
 //! without actually holding the whole POST data.
+//!
+//! The PoW is [RandomX](randomx), designed to run best on CPUs: its random programs
+//! and the 2 GiB dataset they read make GPUs slower than the CPUs they'd relieve.
+//! There is thus no GPU backend. Machines with slow CPUs can delegate the PoW
+//! to another machine instead (see the remote k2pow prover of the POST service).
 
+pub mod cache;
 pub mod randomx;
+use std::time::Duration;
+
 use mockall::*;
 use thiserror::Error;
 
+use crate::{cancel::CancellationToken, prove::ProofVersion};
+
+/// Nonce groups taking a single byte in the PoW input (of [ProofVersion::V1] proofs).
+/// Wider ones take 4 bytes and are proven with [ProofVersion::V2] proofs.
+pub const NARROW_NONCE_GROUPS: u32 = 256;
+
 #[derive(Error, Debug)]
 pub enum Error {
     #[error("proof of work not found")]
     PoWNotFound,
     #[error("proof of work is invalid")]
     InvalidPoW,
+    #[error("proof of work was cancelled")]
+    Cancelled,
     #[error(transparent)]
     Internal(Box<dyn std::error::Error + Send + Sync>),
 }
 
+/// Progress of a proof of work search reported to a [ProgressObserver].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Progress {
+    pub nonce_group: u32,
+    /// Number of hashes tried so far.
+    pub hashes: u64,
+    /// Time since the search started.
+    pub elapsed: Duration,
+}
+
+impl Progress {
+    /// Hashes tried per second.
+    pub fn hashrate(&self) -> f64 {
+        match self.elapsed.as_secs_f64() {
+            secs if secs > 0.0 => self.hashes as f64 / secs,
+            _ => 0.0,
+        }
+    }
+}
+
+/// Expected number of hashes to try to find a proof of work, i.e. a hash below the `difficulty`.
+pub fn expected_hashes(difficulty: &[u8; 32]) -> f64 {
+    // The probability of a hash (big endian) being below the difficulty.
+    let probability = difficulty
+        .iter()
+        .rev()
+        .fold(0.0, |p, &byte| (p + byte as f64) / 256.0);
+    1.0 / probability
+}
+
+/// Observes progress of proof of work searches.
+///
+/// It's called from the threads searching, and must be fast not to slow them down.
+pub trait ProgressObserver: Send + Sync {
+    fn on_progress(&self, progress: Progress);
+}
+
+impl<F: Fn(Progress) + Send + Sync> ProgressObserver for F {
+    fn on_progress(&self, progress: Progress) {
+        self(progress)
+    }
+}
+
 #[automock]
 pub trait Prover {
+    /// Find a proof of work, failing with [Error::Cancelled] once `cancel` is cancelled.
     fn prove(
         &self,
         nonce_group: u8,
         challenge: &[u8; 8],
         difficulty: &[u8; 32],
         miner_id: &[u8; 32],
+        cancel: &CancellationToken,
     ) -> Result<u64, Error>;
+
+    /// Find a proof of work of a wide nonce group (above 255) of a [ProofVersion::V2] proof.
+    ///
+    /// Unsupported unless implemented.
+    fn prove_wide(
+        &self,
+        nonce_group: u32,
+        _challenge: &[u8; 8],
+        _difficulty: &[u8; 32],
+        _miner_id: &[u8; 32],
+        _cancel: &CancellationToken,
+    ) -> Result<u64, Error> {
+        Err(wide_unsupported(nonce_group))
+    }
 }
 
+impl std::fmt::Debug for dyn Prover + Send + Sync + '_ {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("Prover")
+    }
+}
+
+/// Verifies proofs of work, i.e. of the [crate::verification::Verifier].
+///
+/// [randomx::PoW] is the real one. Verifying with it needs to initialize RandomX,
+/// which is slow even in the light mode, so tests of the code verifying proofs
+/// can use [NoopPowVerifier] or [MockPowVerifier] instead.
 #[automock]
 pub trait PowVerifier {
+    /// Verify the `pow` found for the input, failing with [Error::InvalidPoW] if invalid.
     fn verify(
         &self,
         pow: u64,
@@ -41,4 +128,97 @@ pub trait PowVerifier {
         difficulty: &[u8; 32],
         miner_id: &[u8; 32],
     ) -> Result<(), Error>;
+
+    /// Verify the `pow` found for a wide nonce group (above 255) of a [ProofVersion::V2] proof.
+    ///
+    /// Unsupported unless implemented.
+    fn verify_wide(
+        &self,
+        _pow: u64,
+        nonce_group: u32,
+        _challenge: &[u8; 8],
+        _difficulty: &[u8; 32],
+        _miner_id: &[u8; 32],
+    ) -> Result<(), Error> {
+        Err(wide_unsupported(nonce_group))
+    }
+}
+
+fn wide_unsupported(nonce_group: u32) -> Error {
+    Error::Internal(format!("wide nonce group {nonce_group} is not supported").into())
+}
+
+/// Find a proof of work of the `nonce_group` with the `prover`,
+/// in the PoW input format of the proofs of the `version`
+/// (see [ProofVersion::of_nonce_group]).
+pub fn prove<P: Prover + ?Sized>(
+    prover: &P,
+    version: ProofVersion,
+    nonce_group: u32,
+    challenge: &[u8; 8],
+    difficulty: &[u8; 32],
+    miner_id: &[u8; 32],
+    cancel: &CancellationToken,
+) -> Result<u64, Error> {
+    if ProofVersion::of_nonce_group(nonce_group) != version {
+        let msg = format!("nonce group {nonce_group} doesn't fit {version:?} proofs");
+        return Err(Error::Internal(msg.into()));
+    }
+    match version {
+        ProofVersion::V1 => {
+            prover.prove(nonce_group as u8, challenge, difficulty, miner_id, cancel)
+        }
+        ProofVersion::V2 => prover.prove_wide(nonce_group, challenge, difficulty, miner_id, cancel),
+    }
+}
+
+/// Verify the `pow` of the `nonce_group` with the `verifier`,
+/// in the PoW input format of the proofs of the `version`.
+/// Nonce groups not fitting the `version` are invalid.
+pub fn verify<V: PowVerifier + ?Sized>(
+    verifier: &V,
+    version: ProofVersion,
+    pow: u64,
+    nonce_group: u32,
+    challenge: &[u8; 8],
+    difficulty: &[u8; 32],
+    miner_id: &[u8; 32],
+) -> Result<(), Error> {
+    if ProofVersion::of_nonce_group(nonce_group) != version {
+        return Err(Error::InvalidPoW);
+    }
+    match version {
+        ProofVersion::V1 => {
+            verifier.verify(pow, nonce_group as u8, challenge, difficulty, miner_id)
+        }
+        ProofVersion::V2 => verifier.verify_wide(pow, nonce_group, challenge, difficulty, miner_id),
+    }
+}
+
+/// A [PowVerifier] accepting every proof of work, for tests only.
+///
+/// It makes verifying proofs fast and deterministic, checking everything but the PoW.
+///
+/// ```
+/// # use post::{pow::NoopPowVerifier, verification::Verifier};
+/// let verifier = Verifier::new(Box::new(NoopPowVerifier));
+/// ```
+#[derive(Debug, Clone, Copy, Default)]
+pub struct NoopPowVerifier;
+
+impl PowVerifier for NoopPowVerifier {
+    fn verify(&self, _: u64, _: u8, _: &[u8; 8], _: &[u8; 32], _: &[u8; 32]) -> Result<(), Error> {
+        Ok(())
+    }
+
+    fn verify_wide(
+        &self,
+        _: u64,
+        _: u32,
+        _: &[u8; 8],
+        _: &[u8; 32],
+        _: &[u8; 32],
+    ) -> Result<(), Error> {
+        Ok(())
+    }
 }
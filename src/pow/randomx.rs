@@ -1,12 +1,29 @@
 pub use randomx_rs::RandomXFlag;
-use randomx_rs::{RandomXCache, RandomXDataset, RandomXError, RandomXVM};
+use randomx_rs::{RandomXCache, RandomXVM};
 use rayon::prelude::{IntoParallelIterator, ParallelIterator};
-use std::sync::atomic::{AtomicUsize, Ordering};
+use std::{
+    sync::{
+        atomic::{AtomicU64, AtomicUsize, Ordering},
+        Arc, Mutex, OnceLock,
+    },
+    thread::JoinHandle,
+    time::{Duration, Instant},
+};
 use thread_local::ThreadLocal;
 
-use super::{Error, PowVerifier, Prover};
+use super::{Error, PowVerifier, Progress, ProgressObserver, Prover};
+use crate::{cancel::CancellationToken, prove::ProofVersion};
+use dataset::{Dataset, FastVm};
+
+mod dataset;
+
+pub use dataset::DatasetProgress;
 
 const RANDOMX_CACHE_KEY: &[u8] = b"spacemesh-randomx-cache-key";
+/// The time to report the progress is checked every so many hashes (by each thread).
+const PROGRESS_CHECK_HASHES: u64 = 256;
+/// Marks a nonce group without a proof of work found yet (PoW nonces take 7 bytes).
+const NOT_FOUND: u64 = u64::MAX;
 
 impl From<randomx_rs::RandomXError> for Error {
     fn from(e: randomx_rs::RandomXError) -> Self {
@@ -14,21 +31,60 @@ impl From<randomx_rs::RandomXError> for Error {
     }
 }
 
+/// RandomX proof of work, in the fast mode (with the 2 GiB dataset) if the flags
+/// contain [RandomXFlag::FLAG_FULL_MEM], in the light mode otherwise.
+///
+/// The dataset is allocated by RandomX in the memory of the process, so it can't be
+/// shared with other processes. Services co-located on a host can share one dataset
+/// by calculating the k2pow on a single k2pow server (see the POST service).
 pub struct PoW {
     cache: Option<RandomXCache>,
-    dataset: Option<RandomXDataset>,
+    dataset: Option<Arc<Dataset>>,
     flags: RandomXFlag,
-    vms: ThreadLocal<RandomXVM>,
+    vms: ThreadLocal<Vm>,
+    /// Interval of reporting the progress of searching and the observer reported to.
+    progress: Option<(Duration, Box<dyn ProgressObserver>)>,
 }
 
 impl PoW {
+    /// Initialize RandomX with the `flags`.
+    ///
+    /// Large pages ([RandomXFlag::FLAG_LARGE_PAGES]) speed the hashing up, but need
+    /// huge pages reserved in the OS. If they can't be allocated, RandomX falls back
+    /// to regular pages with a warning.
+    ///
+    /// The progress of initializing the dataset of the fast mode is logged.
     pub fn new(flags: RandomXFlag) -> Result<PoW, Error> {
+        Self::new_with_progress(flags, log_dataset_progress)
+    }
+
+    /// Initialize RandomX with the `flags` (see [PoW::new]), reporting the progress
+    /// of initializing the dataset of the fast mode to `progress`.
+    ///
+    /// The dataset is initialized on the threads of the current rayon pool.
+    pub fn new_with_progress(
+        flags: RandomXFlag,
+        progress: impl Fn(DatasetProgress) + Sync,
+    ) -> Result<PoW, Error> {
+        match Self::init(flags, &progress) {
+            Err(e) if flags.contains(RandomXFlag::FLAG_LARGE_PAGES) => {
+                log::warn!("failed to initialize RandomX with large pages ({e}), not using them");
+                Self::init(without_large_pages(flags), &progress)
+            }
+            result => result,
+        }
+    }
+
+    fn init(flags: RandomXFlag, progress: &(dyn Fn(DatasetProgress) + Sync)) -> Result<PoW, Error> {
         log::debug!("initializing RandomX");
-        let cache = RandomXCache::new(flags, RANDOMX_CACHE_KEY)?;
         let (cache, dataset) = if flags.contains(RandomXFlag::FLAG_FULL_MEM) {
-            (None, Some(RandomXDataset::new(flags, cache, 0)?))
+            log::info!("initializing RandomX dataset, it takes a while");
+            let start = Instant::now();
+            let dataset = Dataset::new(flags, RANDOMX_CACHE_KEY, progress)?;
+            log::info!("RandomX dataset initialized in {:.1?}", start.elapsed());
+            (None, Some(Arc::new(dataset)))
         } else {
-            (Some(cache), None)
+            (Some(RandomXCache::new(flags, RANDOMX_CACHE_KEY)?), None)
         };
         log::debug!("RandomX initialized");
 
@@ -37,74 +93,410 @@ impl PoW {
             dataset,
             flags,
             vms: ThreadLocal::new(),
+            progress: None,
         })
     }
 
-    fn get_vm(&self) -> Result<&RandomXVM, RandomXError> {
-        self.vms
-            .get_or_try(|| RandomXVM::new(self.flags, self.cache.clone(), self.dataset.clone()))
+    /// Report the progress of every proof of work search to the `observer`
+    /// every `interval`, and once more when it finishes.
+    pub fn with_progress(
+        self,
+        interval: Duration,
+        observer: impl ProgressObserver + 'static,
+    ) -> Self {
+        Self {
+            progress: Some((interval, Box::new(observer))),
+            ..self
+        }
+    }
+
+    fn get_vm(&self) -> Result<&Vm, Error> {
+        self.vms.get_or_try(|| {
+            let new_vm = |flags| match &self.dataset {
+                Some(dataset) => FastVm::new(flags, dataset).map(Vm::Fast),
+                None => Ok(Vm::Light(RandomXVM::new(flags, self.cache.clone(), None)?)),
+            };
+            match new_vm(self.flags) {
+                // The huge pages might run out for the scratchpads of the VMs.
+                Err(e) if self.flags.contains(RandomXFlag::FLAG_LARGE_PAGES) => {
+                    log::warn!(
+                        "failed to create RandomX VM with large pages ({e}), not using them"
+                    );
+                    new_vm(without_large_pages(self.flags))
+                }
+                result => result,
+            }
+        })
     }
 }
 
-impl Prover for PoW {
+/// A VM of the light mode (by randomx-rs) or the fast mode (with the [Dataset]).
+enum Vm {
+    Light(RandomXVM),
+    Fast(FastVm),
+}
+
+impl Vm {
+    fn calculate_hash(&self, input: &[u8]) -> Result<Vec<u8>, Error> {
+        match self {
+            Vm::Light(vm) => Ok(vm.calculate_hash(input)?),
+            Vm::Fast(vm) => Ok(vm.calculate_hash(input)),
+        }
+    }
+}
+
+/// Log the progress of initializing the dataset of the fast mode.
+fn log_dataset_progress(progress: DatasetProgress) {
+    log::info!(
+        "RandomX dataset {:.0}% initialized in {:.0?}",
+        progress.percent(),
+        progress.elapsed
+    );
+}
+
+/// A [PoW] initialized on a background thread, i.e. at startup,
+/// so that the dataset of the fast mode is ready by the time it's needed.
+///
+/// Proving and verifying wait for the initialization to finish.
+pub struct BackgroundPoW {
+    handle: Mutex<Option<JoinHandle<Result<PoW, Error>>>>,
+    /// The initialized PoW, or the error initializing it.
+    pow: OnceLock<Result<PoW, String>>,
+}
+
+impl BackgroundPoW {
+    /// Start initializing the PoW with `init` on a new thread.
+    pub fn spawn(
+        init: impl FnOnce() -> Result<PoW, Error> + Send + 'static,
+    ) -> std::io::Result<Self> {
+        let handle = std::thread::Builder::new()
+            .name("randomx-init".into())
+            .spawn(init)?;
+        Ok(Self {
+            handle: Mutex::new(Some(handle)),
+            pow: OnceLock::new(),
+        })
+    }
+
+    /// Whether the initialization has finished (successfully or not).
+    pub fn is_ready(&self) -> bool {
+        self.pow.get().is_some()
+            || self
+                .handle
+                .lock()
+                .unwrap()
+                .as_ref()
+                .map_or(true, JoinHandle::is_finished)
+    }
+
+    /// Wait for the initialization to finish.
+    pub fn get(&self) -> Result<&PoW, Error> {
+        let pow = self.pow.get_or_init(|| {
+            let handle = self.handle.lock().unwrap().take();
+            match handle.map(JoinHandle::join) {
+                Some(Ok(result)) => result.map_err(|e| e.to_string()),
+                Some(Err(_)) => Err("RandomX initialization panicked".to_string()),
+                None => Err("RandomX initialization was abandoned".to_string()),
+            }
+        });
+        pow.as_ref()
+            .map_err(|e| Error::Internal(format!("initializing RandomX: {e}").into()))
+    }
+}
+
+impl Prover for BackgroundPoW {
     fn prove(
         &self,
         nonce_group: u8,
         challenge: &[u8; 8],
         difficulty: &[u8; 32],
         miner_id: &[u8; 32],
+        cancel: &CancellationToken,
     ) -> Result<u64, Error> {
-        let pow_input = [
-            [0u8; 7].as_slice(),
-            [nonce_group].as_slice(),
-            challenge,
-            miner_id,
-        ]
-        .concat();
+        self.get()?
+            .prove(nonce_group, challenge, difficulty, miner_id, cancel)
+    }
 
-        let iterations = AtomicUsize::new(0);
-        let (pow_nonce, _) = (0..2u64.pow(56))
+    fn prove_wide(
+        &self,
+        nonce_group: u32,
+        challenge: &[u8; 8],
+        difficulty: &[u8; 32],
+        miner_id: &[u8; 32],
+        cancel: &CancellationToken,
+    ) -> Result<u64, Error> {
+        self.get()?
+            .prove_wide(nonce_group, challenge, difficulty, miner_id, cancel)
+    }
+}
+
+impl PowVerifier for BackgroundPoW {
+    fn verify(
+        &self,
+        pow: u64,
+        nonce_group: u8,
+        challenge: &[u8; 8],
+        difficulty: &[u8; 32],
+        miner_id: &[u8; 32],
+    ) -> Result<(), Error> {
+        self.get()?
+            .verify(pow, nonce_group, challenge, difficulty, miner_id)
+    }
+
+    fn verify_wide(
+        &self,
+        pow: u64,
+        nonce_group: u32,
+        challenge: &[u8; 8],
+        difficulty: &[u8; 32],
+        miner_id: &[u8; 32],
+    ) -> Result<(), Error> {
+        self.get()?
+            .verify_wide(pow, nonce_group, challenge, difficulty, miner_id)
+    }
+}
+
+fn without_large_pages(mut flags: RandomXFlag) -> RandomXFlag {
+    flags.remove(RandomXFlag::FLAG_LARGE_PAGES);
+    flags
+}
+
+/// Input of the RandomX hash: the 7 lowest bytes of the `pow` nonce, the `nonce_group`,
+/// the `challenge` and the `miner_id`.
+///
+/// The nonce group is encoded as in the proofs of its version (see [ProofVersion::of_nonce_group]):
+/// a single byte in V1, 4 bytes (little endian) in V2.
+/// The inputs of both never collide, having different lengths.
+fn pow_input(pow: u64, nonce_group: u32, challenge: &[u8; 8], miner_id: &[u8; 32]) -> Vec<u8> {
+    let nonce_group = match ProofVersion::of_nonce_group(nonce_group) {
+        ProofVersion::V1 => vec![nonce_group as u8],
+        ProofVersion::V2 => nonce_group.to_le_bytes().to_vec(),
+    };
+    [
+        &pow.to_le_bytes()[0..7],
+        nonce_group.as_slice(),
+        challenge,
+        miner_id,
+    ]
+    .concat()
+}
+
+/// Throughput of the PoW on this machine, measured by [PoW::benchmark].
+#[derive(Debug, Clone, Copy)]
+pub struct Benchmark {
+    /// Flags of the benchmarked PoW, telling the fast and the light mode apart.
+    pub flags: RandomXFlag,
+    pub hashes: u64,
+    pub elapsed: Duration,
+}
+
+impl Benchmark {
+    /// Hashes calculated per second.
+    pub fn hashrate(&self) -> f64 {
+        match self.elapsed.as_secs_f64() {
+            secs if secs > 0.0 => self.hashes as f64 / secs,
+            _ => 0.0,
+        }
+    }
+
+    /// Expected time to find a proof of work for a nonce group with the `difficulty`.
+    pub fn expected_duration(&self, difficulty: &[u8; 32]) -> Duration {
+        Duration::try_from_secs_f64(super::expected_hashes(difficulty) / self.hashrate())
+            .unwrap_or(Duration::MAX)
+    }
+}
+
+impl PoW {
+    /// Measure the throughput of calculating hashes on all the threads for `duration`.
+    pub fn benchmark(&self, duration: Duration) -> Result<Benchmark, Error> {
+        // Fail early if VMs can't be created.
+        self.get_vm()?;
+        let pow_input = pow_input(0, 0, b"benchmrk", &[0; 32]);
+        let start = Instant::now();
+        let hashes = AtomicU64::new(0);
+        let result = (0..2u64.pow(56))
             .into_par_iter()
             .map_init(
                 || -> Result<_, Error> { Ok((self.get_vm()?, pow_input.clone())) },
                 |state, pow_nonce| {
-                    if let Ok((vm, pow_input)) = state {
+                    if start.elapsed() >= duration {
+                        return Some(Ok(()));
+                    }
+                    let Ok((vm, pow_input)) = state else {
+                        return Some(Err(Error::Internal("failed to create RandomX VM".into())));
+                    };
+                    pow_input[0..7].copy_from_slice(&pow_nonce.to_le_bytes()[0..7]);
+                    if let Err(e) = vm.calculate_hash(pow_input.as_slice()) {
+                        return Some(Err(e));
+                    }
+                    hashes.fetch_add(1, Ordering::Relaxed);
+                    None
+                },
+            )
+            .find_map_any(|res| res);
+        result.unwrap_or(Ok(()))?;
+        Ok(Benchmark {
+            flags: self.flags,
+            hashes: hashes.into_inner(),
+            elapsed: start.elapsed(),
+        })
+    }
+
+    /// Find proofs of work for all the `nonce_groups` in a single parallel sweep
+    /// over the PoW nonces, returning them in the same order.
+    ///
+    /// Every thread tries each PoW nonce for all the nonce groups not found yet,
+    /// reusing its VM and the dataset entries it touched, instead of
+    /// a separate sweep (and its ramp-up and tail) per nonce group.
+    /// The progress is reported with the first of the `nonce_groups`.
+    ///
+    /// The nonce groups are encoded as in the proofs of their versions
+    /// (see [ProofVersion::of_nonce_group]), the wide ones in V2.
+    pub fn prove_many(
+        &self,
+        nonce_groups: &[u32],
+        challenge: &[u8; 8],
+        difficulty: &[u8; 32],
+        miner_id: &[u8; 32],
+        cancel: &CancellationToken,
+    ) -> Result<Vec<u64>, Error> {
+        let Some(&first_group) = nonce_groups.first() else {
+            return Ok(Vec::new());
+        };
+        let inputs: Vec<_> = nonce_groups
+            .iter()
+            .map(|&nonce_group| pow_input(0, nonce_group, challenge, miner_id))
+            .collect();
+        let found: Vec<_> = nonce_groups
+            .iter()
+            .map(|_| AtomicU64::new(NOT_FOUND))
+            .collect();
+        let remaining = AtomicUsize::new(nonce_groups.len());
+
+        let start = Instant::now();
+        let iterations = AtomicU64::new(0);
+        // Nanoseconds since the start at which the progress is reported next.
+        let next_report = AtomicU64::new(0);
+        let report = |hashes: u64| {
+            if let Some((interval, observer)) = &self.progress {
+                let elapsed = start.elapsed();
+                let nanos = elapsed.as_nanos() as u64;
+                let at = next_report.load(Ordering::Relaxed);
+                let next = nanos + interval.as_nanos() as u64;
+                if nanos >= at
+                    && next_report
+                        .compare_exchange(at, next, Ordering::Relaxed, Ordering::Relaxed)
+                        .is_ok()
+                {
+                    observer.on_progress(Progress {
+                        nonce_group: first_group,
+                        hashes,
+                        elapsed,
+                    });
+                }
+            }
+        };
+        let result = (0..2u64.pow(56))
+            .into_par_iter()
+            .map_init(
+                || -> Result<_, Error> { Ok((self.get_vm()?, inputs.clone())) },
+                |state, pow_nonce| {
+                    if cancel.is_cancelled() {
+                        return Some(Err(Error::Cancelled));
+                    }
+                    let Ok((vm, inputs)) = state else {
+                        return None;
+                    };
+                    for (pow_input, found) in inputs.iter_mut().zip(&found) {
+                        if found.load(Ordering::Relaxed) != NOT_FOUND {
+                            continue;
+                        }
                         pow_input[0..7].copy_from_slice(&pow_nonce.to_le_bytes()[0..7]);
                         let hash = vm.calculate_hash(pow_input.as_slice()).ok()?;
-                        iterations.fetch_add(1, Ordering::Relaxed); // Increment the iteration counter atomically
-                        Some((pow_nonce, hash))
-                    } else {
-                        None
+                        let hashes = iterations.fetch_add(1, Ordering::Relaxed) + 1;
+                        if hashes % PROGRESS_CHECK_HASHES == 0 {
+                            report(hashes);
+                        }
+                        if hash.as_slice() < difficulty
+                            && found
+                                .compare_exchange(
+                                    NOT_FOUND,
+                                    pow_nonce,
+                                    Ordering::Relaxed,
+                                    Ordering::Relaxed,
+                                )
+                                .is_ok()
+                            && remaining.fetch_sub(1, Ordering::Relaxed) == 1
+                        {
+                            return Some(Ok(()));
+                        }
                     }
+                    None
                 },
             )
-            .filter_map(|res| res)
-            .find_any(|(_, hash)| hash.as_slice() < difficulty)
-            .ok_or(Error::PoWNotFound)?;
+            .find_map_any(|res| res);
 
         let total_iterations = iterations.load(Ordering::Relaxed);
-        log::debug!("Took {total_iterations:?} PoW iterations to find a valid nonce");
+        // The final report is due regardless of the interval.
+        next_report.store(0, Ordering::Relaxed);
+        report(total_iterations);
 
-        Ok(pow_nonce)
+        result.ok_or(Error::PoWNotFound)??;
+        log::debug!(
+            "Took {total_iterations:?} PoW iterations to find valid nonces for {} nonce groups",
+            nonce_groups.len()
+        );
+        Ok(found.into_iter().map(AtomicU64::into_inner).collect())
     }
 }
 
-impl PowVerifier for PoW {
-    fn verify(
+impl Prover for PoW {
+    fn prove(
         &self,
-        pow: u64,
         nonce_group: u8,
         challenge: &[u8; 8],
         difficulty: &[u8; 32],
         miner_id: &[u8; 32],
-    ) -> Result<(), Error> {
-        let pow_input = [
-            &pow.to_le_bytes()[0..7],
-            [nonce_group].as_slice(),
+        cancel: &CancellationToken,
+    ) -> Result<u64, Error> {
+        let pows = self.prove_many(
+            &[nonce_group as u32],
             challenge,
+            difficulty,
             miner_id,
-        ]
-        .concat();
+            cancel,
+        )?;
+        Ok(pows[0])
+    }
+
+    fn prove_wide(
+        &self,
+        nonce_group: u32,
+        challenge: &[u8; 8],
+        difficulty: &[u8; 32],
+        miner_id: &[u8; 32],
+        cancel: &CancellationToken,
+    ) -> Result<u64, Error> {
+        if ProofVersion::of_nonce_group(nonce_group) != ProofVersion::V2 {
+            return Err(Error::Internal(
+                format!("nonce group {nonce_group} is not wide").into(),
+            ));
+        }
+        let pows = self.prove_many(&[nonce_group], challenge, difficulty, miner_id, cancel)?;
+        Ok(pows[0])
+    }
+}
+
+impl PoW {
+    fn verify_pow(
+        &self,
+        pow: u64,
+        nonce_group: u32,
+        challenge: &[u8; 8],
+        difficulty: &[u8; 32],
+        miner_id: &[u8; 32],
+    ) -> Result<(), Error> {
+        let pow_input = pow_input(pow, nonce_group, challenge, miner_id);
 
         let vm = self.get_vm()?;
         let hash = vm.calculate_hash(pow_input.as_slice())?;
@@ -116,6 +508,33 @@ impl PowVerifier for PoW {
     }
 }
 
+impl PowVerifier for PoW {
+    fn verify(
+        &self,
+        pow: u64,
+        nonce_group: u8,
+        challenge: &[u8; 8],
+        difficulty: &[u8; 32],
+        miner_id: &[u8; 32],
+    ) -> Result<(), Error> {
+        self.verify_pow(pow, nonce_group as u32, challenge, difficulty, miner_id)
+    }
+
+    fn verify_wide(
+        &self,
+        pow: u64,
+        nonce_group: u32,
+        challenge: &[u8; 8],
+        difficulty: &[u8; 32],
+        miner_id: &[u8; 32],
+    ) -> Result<(), Error> {
+        if ProofVersion::of_nonce_group(nonce_group) != ProofVersion::V2 {
+            return Err(Error::InvalidPoW);
+        }
+        self.verify_pow(pow, nonce_group, challenge, difficulty, miner_id)
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use crate::pow::PowVerifier;
@@ -133,7 +552,13 @@ mod tests {
         ];
         let prover = PoW::new(RandomXFlag::get_recommended_flags()).unwrap();
         let pow = prover
-            .prove(nonce, challenge, difficulty, &[6; 32])
+            .prove(
+                nonce,
+                challenge,
+                difficulty,
+                &[6; 32],
+                &CancellationToken::new(),
+            )
             .unwrap();
         prover
             .verify(pow, nonce, challenge, difficulty, &[6; 32])
@@ -152,13 +577,196 @@ mod tests {
         let prover = PoW::new(RandomXFlag::get_recommended_flags()).unwrap();
 
         let pow = prover
-            .prove(nonce, challenge, difficulty, &[1; 32])
+            .prove(
+                nonce,
+                challenge,
+                difficulty,
+                &[1; 32],
+                &CancellationToken::new(),
+            )
             .unwrap();
         prover
             .verify(pow, nonce, challenge, difficulty, &[2; 32])
             .unwrap_err();
     }
 
+    #[test]
+    fn test_pow_wide_nonce_group() {
+        let challenge = b"hello!!!";
+        let mut difficulty = [0xff; 32];
+        difficulty[0] = 0x0f;
+        let prover = PoW::new(RandomXFlag::get_recommended_flags()).unwrap();
+        let cancel = CancellationToken::new();
+        let pow = prover
+            .prove_wide(300, challenge, &difficulty, &[6; 32], &cancel)
+            .unwrap();
+        prover
+            .verify_wide(pow, 300, challenge, &difficulty, &[6; 32])
+            .unwrap();
+
+        // Narrow nonce groups are never wide.
+        assert!(prover
+            .prove_wide(7, challenge, &difficulty, &[6; 32], &cancel)
+            .is_err());
+        assert!(matches!(
+            prover.verify_wide(pow, 7, challenge, &difficulty, &[6; 32]),
+            Err(Error::InvalidPoW)
+        ));
+    }
+
+    #[test]
+    fn encoding_nonce_groups() {
+        let narrow = pow_input(7, 255, b"challeng", &[6; 32]);
+        assert_eq!(48, narrow.len());
+        assert_eq!([7, 0, 0, 0, 0, 0, 0, 255], narrow[..8]);
+
+        let wide = pow_input(7, 256, b"challeng", &[6; 32]);
+        assert_eq!(51, wide.len());
+        assert_eq!(256u32.to_le_bytes(), wide[7..11]);
+        assert_eq!(b"challeng", &wide[11..19]);
+    }
+
+    #[test]
+    fn cancelling_pow() {
+        let prover = PoW::new(RandomXFlag::get_recommended_flags()).unwrap();
+        let cancel = CancellationToken::new();
+        cancel.cancel("test");
+        // difficulty 0 is impossible to be met
+        let result = prover.prove(0, b"challeng", &[0; 32], &[6; 32], &cancel);
+        assert!(matches!(result, Err(Error::Cancelled)));
+    }
+
+    #[test]
+    fn reporting_progress() {
+        let reports = std::sync::Arc::new(std::sync::Mutex::new(Vec::new()));
+        let prover = PoW::new(RandomXFlag::get_recommended_flags())
+            .unwrap()
+            .with_progress(Duration::ZERO, {
+                let reports = reports.clone();
+                move |progress: Progress| reports.lock().unwrap().push(progress)
+            });
+        let mut difficulty = [0xff; 32];
+        difficulty[0] = 0x01;
+        prover
+            .prove(
+                7,
+                b"challeng",
+                &difficulty,
+                &[6; 32],
+                &CancellationToken::new(),
+            )
+            .unwrap();
+
+        let reports = reports.lock().unwrap();
+        // At least once at the end
+        let last = reports.last().unwrap();
+        assert!(last.hashes > 0);
+        assert!(reports.iter().all(|p| p.nonce_group == 7));
+        assert!(reports.iter().all(|p| p.hashes <= last.hashes));
+        assert!(last.hashrate() > 0.0);
+    }
+
+    #[test]
+    fn proving_many_nonce_groups() {
+        let challenge = b"hello!!!";
+        let mut difficulty = [0xff; 32];
+        difficulty[0] = 0x0f;
+        let prover = PoW::new(RandomXFlag::get_recommended_flags()).unwrap();
+        let nonce_groups = [3, 7, 300, 7];
+        let pows = prover
+            .prove_many(
+                &nonce_groups,
+                challenge,
+                &difficulty,
+                &[6; 32],
+                &CancellationToken::new(),
+            )
+            .unwrap();
+        assert_eq!(nonce_groups.len(), pows.len());
+        for (nonce_group, pow) in nonce_groups.into_iter().zip(pows) {
+            let version = ProofVersion::of_nonce_group(nonce_group);
+            crate::pow::verify(
+                &prover,
+                version,
+                pow,
+                nonce_group,
+                challenge,
+                &difficulty,
+                &[6; 32],
+            )
+            .unwrap();
+        }
+
+        let pows = prover
+            .prove_many(
+                &[],
+                challenge,
+                &difficulty,
+                &[6; 32],
+                &CancellationToken::new(),
+            )
+            .unwrap();
+        assert!(pows.is_empty());
+    }
+
+    #[test]
+    fn benchmarking() {
+        let prover = PoW::new(RandomXFlag::get_recommended_flags()).unwrap();
+        let benchmark = prover.benchmark(Duration::from_millis(200)).unwrap();
+        assert!(benchmark.hashes > 0);
+        assert!(benchmark.elapsed >= Duration::from_millis(200));
+        assert!(benchmark.hashrate() > 0.0);
+
+        let mut difficulty = [0xff; 32];
+        difficulty[0] = 0x0f;
+        let easy = benchmark.expected_duration(&difficulty);
+        difficulty[0] = 0x00;
+        assert!(benchmark.expected_duration(&difficulty) > easy * 15);
+        assert_eq!(Duration::MAX, benchmark.expected_duration(&[0; 32]));
+    }
+
+    #[test]
+    fn initializing_in_background() {
+        let pow = BackgroundPoW::spawn(|| PoW::new(RandomXFlag::get_recommended_flags())).unwrap();
+        let mut difficulty = [0xff; 32];
+        difficulty[0] = 0x0f;
+        let cancel = CancellationToken::new();
+        let proof = pow
+            .prove(7, b"challeng", &difficulty, &[6; 32], &cancel)
+            .unwrap();
+        assert!(pow.is_ready());
+        pow.verify(proof, 7, b"challeng", &difficulty, &[6; 32])
+            .unwrap();
+
+        let failed = BackgroundPoW::spawn(|| Err(Error::PoWNotFound)).unwrap();
+        assert!(matches!(
+            failed.prove(7, b"challeng", &difficulty, &[6; 32], &cancel),
+            Err(Error::Internal(_))
+        ));
+        assert!(failed.is_ready());
+    }
+
+    #[test]
+    fn large_pages_fall_back_to_regular_pages() {
+        // Huge pages are likely not reserved where the tests run.
+        let flags = RandomXFlag::get_recommended_flags() | RandomXFlag::FLAG_LARGE_PAGES;
+        let prover = PoW::new(flags).unwrap();
+        let mut difficulty = [0xff; 32];
+        difficulty[0] = 0x0f;
+        let pow = prover
+            .prove(
+                7,
+                b"challeng",
+                &difficulty,
+                &[6; 32],
+                &CancellationToken::new(),
+            )
+            .unwrap();
+        prover
+            .verify(pow, 7, b"challeng", &difficulty, &[6; 32])
+            .unwrap();
+    }
+
     #[test]
     fn reject_invalid_pow() {
         let prover = PoW::new(RandomXFlag::get_recommended_flags()).unwrap();
@@ -0,0 +1,226 @@
+//! The dataset of the RandomX fast mode, initialized on all cores.
+//!
+//! randomx-rs initializes the dataset whole on the calling thread, which takes minutes.
+//! RandomX can initialize disjoint ranges of the dataset items concurrently though,
+//! so the dataset (and the VMs using it) are created with the raw RandomX API here.
+
+use std::{
+    os::raw::{c_int, c_ulong, c_void},
+    ptr::{self, NonNull},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
+
+use randomx_rs::RandomXFlag;
+use rayon::prelude::{IntoParallelIterator, ParallelIterator};
+
+use crate::pow::Error;
+
+/// Number of ranges of the dataset items, initialized (and reported) one at a time by each thread.
+const RANGES: u64 = 32;
+/// Size (in bytes) of a RandomX hash.
+const HASH_SIZE: usize = 32;
+
+/// The raw RandomX API, linked by randomx-rs.
+#[allow(non_camel_case_types)]
+mod ffi {
+    use std::os::raw::{c_int, c_ulong, c_void};
+
+    #[repr(C)]
+    pub struct randomx_cache {
+        _private: [u8; 0],
+    }
+
+    #[repr(C)]
+    pub struct randomx_dataset {
+        _private: [u8; 0],
+    }
+
+    #[repr(C)]
+    pub struct randomx_vm {
+        _private: [u8; 0],
+    }
+
+    extern "C" {
+        pub fn randomx_alloc_cache(flags: c_int) -> *mut randomx_cache;
+        pub fn randomx_init_cache(cache: *mut randomx_cache, key: *const c_void, key_size: usize);
+        pub fn randomx_release_cache(cache: *mut randomx_cache);
+        pub fn randomx_alloc_dataset(flags: c_int) -> *mut randomx_dataset;
+        pub fn randomx_dataset_item_count() -> c_ulong;
+        pub fn randomx_init_dataset(
+            dataset: *mut randomx_dataset,
+            cache: *mut randomx_cache,
+            start_item: c_ulong,
+            item_count: c_ulong,
+        );
+        pub fn randomx_release_dataset(dataset: *mut randomx_dataset);
+        pub fn randomx_create_vm(
+            flags: c_int,
+            cache: *mut randomx_cache,
+            dataset: *mut randomx_dataset,
+        ) -> *mut randomx_vm;
+        pub fn randomx_destroy_vm(vm: *mut randomx_vm);
+        pub fn randomx_calculate_hash(
+            vm: *mut randomx_vm,
+            input: *const c_void,
+            input_size: usize,
+            output: *mut c_void,
+        );
+    }
+}
+
+/// Progress of initializing the dataset of the fast mode.
+#[derive(Debug, Clone, Copy)]
+pub struct DatasetProgress {
+    /// Dataset items initialized so far.
+    pub items: u64,
+    pub total_items: u64,
+    pub elapsed: Duration,
+}
+
+impl DatasetProgress {
+    /// Percentage of the dataset initialized.
+    pub fn percent(&self) -> f64 {
+        self.items as f64 * 100.0 / self.total_items.max(1) as f64
+    }
+}
+
+fn allocation_error(what: &str, flags: RandomXFlag) -> Error {
+    Error::Internal(format!("failed to allocate RandomX {what} with flags {flags:?}").into())
+}
+
+/// The cache the dataset is initialized from.
+struct Cache(NonNull<ffi::randomx_cache>);
+
+// Initializing the dataset only reads the cache.
+unsafe impl Sync for Cache {}
+
+impl Cache {
+    fn new(flags: RandomXFlag, key: &[u8]) -> Result<Self, Error> {
+        // SAFETY: allocating has no preconditions.
+        let cache = unsafe { ffi::randomx_alloc_cache(flags.bits() as c_int) };
+        let cache = NonNull::new(cache).ok_or_else(|| allocation_error("cache", flags))?;
+        // SAFETY: the cache is allocated and the key outlives the call.
+        unsafe { ffi::randomx_init_cache(cache.as_ptr(), key.as_ptr().cast(), key.len()) };
+        Ok(Self(cache))
+    }
+
+    fn as_ptr(&self) -> *mut ffi::randomx_cache {
+        self.0.as_ptr()
+    }
+}
+
+impl Drop for Cache {
+    fn drop(&mut self) {
+        // SAFETY: the cache is allocated and not used anymore.
+        unsafe { ffi::randomx_release_cache(self.0.as_ptr()) }
+    }
+}
+
+/// The dataset of the fast mode, shared by the [FastVm]s.
+pub(super) struct Dataset(NonNull<ffi::randomx_dataset>);
+
+// The VMs only read the dataset, it's written while initializing disjoint ranges of it.
+unsafe impl Send for Dataset {}
+unsafe impl Sync for Dataset {}
+
+impl Dataset {
+    /// Initialize the dataset of the cache with the `key` on the threads of the current
+    /// rayon pool, reporting the `progress` once every range of items is initialized.
+    pub(super) fn new(
+        flags: RandomXFlag,
+        key: &[u8],
+        progress: &(dyn Fn(DatasetProgress) + Sync),
+    ) -> Result<Self, Error> {
+        let cache = Cache::new(flags, key)?;
+        // SAFETY: allocating has no preconditions.
+        let dataset = unsafe { ffi::randomx_alloc_dataset(flags.bits() as c_int) };
+        let dataset =
+            Self(NonNull::new(dataset).ok_or_else(|| allocation_error("dataset", flags))?);
+
+        // SAFETY: querying a constant.
+        let total_items = unsafe { ffi::randomx_dataset_item_count() } as u64;
+        let range_size = total_items.div_ceil(RANGES);
+        let items = AtomicU64::new(0);
+        let start = Instant::now();
+        (0..RANGES).into_par_iter().for_each(|range| {
+            let first = range * range_size;
+            let count = range_size.min(total_items.saturating_sub(first));
+            if count == 0 {
+                return;
+            }
+            // SAFETY: the ranges initialized by the threads are disjoint and within the dataset.
+            unsafe {
+                ffi::randomx_init_dataset(
+                    dataset.as_ptr(),
+                    cache.as_ptr(),
+                    first as c_ulong,
+                    count as c_ulong,
+                )
+            };
+            progress(DatasetProgress {
+                items: items.fetch_add(count, Ordering::Relaxed) + count,
+                total_items,
+                elapsed: start.elapsed(),
+            });
+        });
+        Ok(dataset)
+    }
+
+    fn as_ptr(&self) -> *mut ffi::randomx_dataset {
+        self.0.as_ptr()
+    }
+}
+
+impl Drop for Dataset {
+    fn drop(&mut self) {
+        // SAFETY: the VMs using the dataset keep it alive, so none is left.
+        unsafe { ffi::randomx_release_dataset(self.0.as_ptr()) }
+    }
+}
+
+/// A VM of the fast mode, hashing with the [Dataset].
+pub(super) struct FastVm {
+    vm: NonNull<ffi::randomx_vm>,
+    _dataset: Arc<Dataset>,
+}
+
+// A VM is used by a single thread at a time.
+unsafe impl Send for FastVm {}
+
+impl FastVm {
+    pub(super) fn new(flags: RandomXFlag, dataset: &Arc<Dataset>) -> Result<Self, Error> {
+        // SAFETY: the dataset is initialized, the VM keeps it alive.
+        let vm = unsafe {
+            ffi::randomx_create_vm(flags.bits() as c_int, ptr::null_mut(), dataset.as_ptr())
+        };
+        Ok(Self {
+            vm: NonNull::new(vm).ok_or_else(|| allocation_error("VM", flags))?,
+            _dataset: dataset.clone(),
+        })
+    }
+
+    pub(super) fn calculate_hash(&self, input: &[u8]) -> Vec<u8> {
+        let mut hash = vec![0u8; HASH_SIZE];
+        // SAFETY: the VM is used by this thread only, the input and the output outlive the call.
+        unsafe {
+            ffi::randomx_calculate_hash(
+                self.vm.as_ptr(),
+                input.as_ptr().cast::<c_void>(),
+                input.len(),
+                hash.as_mut_ptr().cast::<c_void>(),
+            )
+        };
+        hash
+    }
+}
+
+impl Drop for FastVm {
+    fn drop(&mut self) {
+        // SAFETY: the VM is not used anymore.
+        unsafe { ffi::randomx_destroy_vm(self.vm.as_ptr()) }
+    }
+}
@@ -7,41 +7,149 @@
 //! TODO: describe the algorithm
 //! ## k2 proof of work
 //! TODO: explain
+//!
+//! ## passes
+//! A pass checks every label of the POS data for a range of nonces. If none of them
+//! gets `k2` labels passing the difficulty, the next pass tries the next range of nonces.
+//! The labels found for a nonce in a pass over the whole data are all it will ever have,
+//! so a nonce which fell short of `k2` can't be extended by another pass: it would
+//! find the same labels again. Only an interrupted pass is continued
+//! (see [ProvingCheckpoint]), with the indices found so far.
 
 use std::borrow::{Borrow, Cow};
-use std::sync::{
-    atomic::{AtomicBool, Ordering},
-    Mutex,
+use std::sync::{Arc, Mutex};
+use std::{
+    ops::Range,
+    path::Path,
+    time::{Duration, Instant},
 };
-use std::{collections::HashMap, ops::Range, path::Path, time::Instant};
 
 use aes::cipher::block_padding::NoPadding;
 use aes::cipher::BlockEncrypt;
 use eyre::Context;
 use primitive_types::U256;
 use randomx_rs::RandomXFlag;
-use rayon::prelude::{ParallelBridge, ParallelIterator};
 use serde::{Deserialize, Serialize};
 use serde_with::{base64::Base64, serde_as};
 
 use crate::{
+    cancel::CancellationToken,
     cipher::AesCipher,
     compression::{compress_indices, required_bits},
     config::ProofConfig,
     difficulty::proving_difficulty,
+    encryption::DataKey,
     metadata::{self, PostMetadata},
     pow,
-    reader::read_data,
+    reader::{
+        read_data, read_source, readable_size, Batch, LabelSource, ParallelReads, ReaderBackend,
+        DEFAULT_READ_AHEAD, DEFAULT_READ_CHUNK_SIZE,
+    },
 };
 
+mod affinity;
+mod benchmark;
+mod cache;
+mod checkpoint;
+mod checksums;
+mod deadline;
+mod joining;
+mod k2pow;
+mod memory;
+mod multiple;
+mod nonces;
+mod numa;
+mod pipeline;
+mod scanning;
+mod simd;
+mod stats;
+mod streaming;
+mod throttle;
+mod tuning;
+
+pub use affinity::CoreSelection;
+pub use benchmark::{benchmark, BenchmarkResult};
+pub use cache::ProofCache;
+use checkpoint::ReadProgress;
+pub use checkpoint::{ChallengeCheckpoint, ProvingCheckpoint};
+use checksums::ChecksumVerifier;
+pub use deadline::DeadlineExceeded;
+use deadline::PassEstimator;
+pub use joining::JoiningChallenges;
+use k2pow::BackgroundPows;
+use memory::IndexStore;
+pub use multiple::generate_proofs_with_settings;
+pub use numa::NumaPlacement;
+pub use scanning::{Candidate, LabelScanner};
+pub use stats::{IndexHook, ProvingStats};
+pub use streaming::StreamingProver;
+pub use throttle::ProvingThrottle;
+pub use tuning::{tune, Tuning};
+
 const LABEL_SIZE: usize = 16;
 const BLOCK_SIZE: usize = 16; // size of the aes block
 const AES_BATCH: usize = 8; // will use encrypt8 asm method
 const CHUNK_SIZE: usize = BLOCK_SIZE * AES_BATCH;
+/// Interval of logging the progress of the k2pow of a nonce group.
+const K2POW_PROGRESS_INTERVAL: Duration = Duration::from_secs(60);
+/// Name of the file in the POS data directory caching the k2 proofs of work.
+const POW_CACHE_FILE_NAME: &str = "k2pow_cache.json";
+
+/// Format of a [Proof], determining how it's verified (see [crate::verification::Verifier]).
+///
+/// Changes of the proof (i.e. wider nonces or another compression of the indices)
+/// come with a new version, so that the proofs of the older versions stay verifiable.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Deserialize, Serialize)]
+#[non_exhaustive]
+pub enum ProofVersion {
+    /// 16 nonces per AES block with a k2pow per nonce group, the indices compressed
+    /// to the bits required to address all the labels.
+    #[default]
+    V1,
+    /// Like [ProofVersion::V1], but of a wide nonce group (above 255) taking 4 bytes
+    /// (little endian) in the PoW input, see [ProvingSettings::wide_nonce_groups].
+    V2,
+}
+
+impl ProofVersion {
+    /// Version of the proofs of the `nonce_group`: the narrow ones fit V1 proofs,
+    /// which every verifier accepts, only the wide ones need V2.
+    pub fn of_nonce_group(nonce_group: u32) -> Self {
+        match nonce_group < pow::NARROW_NONCE_GROUPS {
+            true => ProofVersion::V1,
+            false => ProofVersion::V2,
+        }
+    }
+}
+
+/// Number of the version, i.e. to pass it through the FFI.
+impl From<ProofVersion> for u32 {
+    fn from(version: ProofVersion) -> Self {
+        match version {
+            ProofVersion::V1 => 1,
+            ProofVersion::V2 => 2,
+        }
+    }
+}
+
+impl TryFrom<u32> for ProofVersion {
+    type Error = String;
+
+    fn try_from(number: u32) -> Result<Self, Self::Error> {
+        match number {
+            1 => Ok(ProofVersion::V1),
+            2 => Ok(ProofVersion::V2),
+            n => Err(format!("unknown proof version {n}")),
+        }
+    }
+}
 
 #[serde_as]
 #[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
 pub struct Proof<'a> {
+    /// Missing in the proofs serialized before it was introduced, which are all V1.
+    #[serde(default)]
+    pub version: ProofVersion,
     pub nonce: u32,
     #[serde_as(as = "Base64")]
     pub indices: Cow<'a, [u8]>,
@@ -51,6 +159,7 @@ pub struct Proof<'a> {
 impl Proof<'static> {
     pub fn new(nonce: u32, indices: &[u64], num_labels: u64, pow: u64) -> Self {
         Self {
+            version: ProofVersion::of_nonce_group(nonce / Prover8_56::NONCES_PER_AES),
             nonce,
             indices: Cow::Owned(compress_indices(indices, required_bits(num_labels))),
             pow,
@@ -121,6 +230,28 @@ impl Prover8_56 {
         pow_prover: &P,
         miner_id: &[u8; 32],
     ) -> eyre::Result<Self> {
+        let cancel = CancellationToken::new();
+        let pows = Self::calculate_pows(
+            challenge,
+            nonces.clone(),
+            params,
+            pow_prover,
+            miner_id,
+            &cancel,
+        )?;
+        Self::with_pows(challenge, nonces, params, &pows)
+    }
+
+    /// Calculate the proofs of work for the nonce groups of `nonces`, one after another.
+    /// Fails with [Cancelled](crate::cancel::Cancelled) if `cancel` is cancelled before all of them are calculated.
+    pub(crate) fn calculate_pows<P: pow::Prover + ?Sized>(
+        challenge: &[u8; 32],
+        nonces: Range<u32>,
+        params: ProvingParams,
+        pow_prover: &P,
+        miner_id: &[u8; 32],
+        cancel: &CancellationToken,
+    ) -> eyre::Result<Vec<u64>> {
         // TODO consider to relax it to allow any range of nonces
         eyre::ensure!(
             nonces.start % Self::NONCES_PER_AES == 0,
@@ -131,20 +262,46 @@ impl Prover8_56 {
             "nonces must be a multiple of 16"
         );
         log::info!("calculating proof of work for nonces {nonces:?}",);
-        let ciphers: Vec<AesCipher> = nonce_group_range(nonces.clone(), Self::NONCES_PER_AES)
+        nonce_group_range(nonces, Self::NONCES_PER_AES)
             .map(|nonce_group| {
+                cancel.check()?;
                 log::debug!("calculating proof of work for nonce group {nonce_group}");
-                let pow = pow_prover.prove(
-                    nonce_group.try_into()?,
+                let pow = pow::prove(
+                    pow_prover,
+                    ProofVersion::of_nonce_group(nonce_group),
+                    nonce_group,
                     challenge[..8].try_into().unwrap(),
                     &params.pow_difficulty,
                     miner_id,
-                )?;
+                    cancel,
+                );
+                cancel.check()?;
+                let pow = pow?;
                 log::debug!("proof of work: {pow}");
-
-                Ok(AesCipher::new(challenge, nonce_group, pow))
+                Ok(pow)
             })
-            .collect::<eyre::Result<_>>()?;
+            .collect()
+    }
+
+    /// Create a prover with already calculated proofs of work
+    /// for the nonce groups of `nonces` (see [Prover8_56::pows]).
+    pub fn with_pows(
+        challenge: &[u8; 32],
+        nonces: Range<u32>,
+        params: ProvingParams,
+        pows: &[u64],
+    ) -> eyre::Result<Self> {
+        let nonce_groups = nonce_group_range(nonces.clone(), Self::NONCES_PER_AES);
+        eyre::ensure!(
+            nonce_groups.len() == pows.len(),
+            "expected {} proofs of work, got {}",
+            nonce_groups.len(),
+            pows.len()
+        );
+        let ciphers: Vec<AesCipher> = nonce_groups
+            .zip(pows)
+            .map(|(nonce_group, &pow)| AesCipher::new(challenge, nonce_group, pow))
+            .collect();
 
         let lazy_ciphers = nonces
             .map(|nonce| {
@@ -167,6 +324,71 @@ impl Prover8_56 {
         })
     }
 
+    /// Proofs of work of the nonce groups, in order.
+    pub fn pows(&self) -> Vec<u64> {
+        self.ciphers.iter().map(|c| c.pow).collect()
+    }
+
+    /// AES-128 keys of the nonce group ciphers, in order.
+    pub fn nonce_group_keys(&self) -> Vec<[u8; 16]> {
+        self.ciphers.iter().map(|c| c.key).collect()
+    }
+
+    /// MSB part of the difficulty, checked with the nonce group ciphers.
+    pub fn difficulty_msb(&self) -> u8 {
+        self.difficulty_msb
+    }
+
+    /// Same as [Prover::prove], but checks only the `candidates` found in the batch
+    /// by a [LabelScanner]. The candidates are checked in the order [Prover::prove]
+    /// would find them, so both give the same results.
+    pub fn prove_candidates<F>(
+        &self,
+        batch: &[u8],
+        index: u64,
+        mut candidates: Vec<Candidate>,
+        mut consume: F,
+    ) -> Option<(u32, Vec<u64>)>
+    where
+        F: FnMut(u32, u64) -> Option<Vec<u64>>,
+    {
+        let labels_in_chunks = (batch.len() / CHUNK_SIZE * AES_BATCH) as u32;
+        candidates.sort_unstable_by_key(|c| {
+            let chunk = c.label / AES_BATCH as u32;
+            (chunk, c.cipher, c.label % AES_BATCH as u32, c.offset)
+        });
+
+        let mut out = [0u8; BLOCK_SIZE];
+        for c in candidates {
+            let valid = c.label < labels_in_chunks && c.offset < Self::NONCES_PER_AES;
+            let Some(cipher) = self.ciphers.get(c.cipher as usize).filter(|_| valid) else {
+                log::warn!("ignoring invalid candidate: {c:?}");
+                continue;
+            };
+            let label_offset = c.label as usize * LABEL_SIZE;
+            let label = &batch[label_offset..label_offset + LABEL_SIZE];
+            cipher
+                .aes
+                .encrypt_block_b2b(label.into(), out.as_mut_slice().into());
+
+            let msb = out[c.offset as usize];
+            let nonce = calc_nonce(cipher.nonce_group, Self::NONCES_PER_AES, c.offset as usize);
+            let label_index = index + c.label as u64;
+            if msb == self.difficulty_msb {
+                if let Some(p) =
+                    self.check_lsb(label, nonce, c.offset as usize, label_index, &mut consume)
+                {
+                    return Some(p);
+                }
+            } else if msb < self.difficulty_msb {
+                if let Some(indexes) = consume(nonce, label_index) {
+                    return Some((nonce, indexes));
+                }
+            }
+        }
+        None
+    }
+
     pub(crate) fn split_difficulty(difficulty: u64) -> (u8, u64) {
         ((difficulty >> 56) as u8, difficulty & 0x00ff_ffff_ffff_ffff)
     }
@@ -228,30 +450,30 @@ impl Prover for Prover8_56 {
             for cipher in &self.ciphers {
                 _ = cipher.aes.encrypt_padded_b2b::<NoPadding>(chunk, &mut u8s);
 
-                for (offset, &msb) in u8s.iter().enumerate() {
-                    if msb <= self.difficulty_msb {
-                        if msb == self.difficulty_msb {
-                            // Check LSB
-                            let nonce =
-                                calc_nonce(cipher.nonce_group, Self::NONCES_PER_AES, offset);
-                            let label_offset = offset / Self::NONCES_PER_AES as usize * LABEL_SIZE;
-                            if let Some(p) = self.check_lsb(
-                                &chunk[label_offset..label_offset + LABEL_SIZE],
-                                nonce,
-                                offset,
-                                index,
-                                &mut consume,
-                            ) {
-                                return Some(p);
-                            }
-                        } else {
-                            // valid label
-                            let index = index + (offset as u32 / Self::NONCES_PER_AES) as u64;
-                            let nonce =
-                                calc_nonce(cipher.nonce_group, Self::NONCES_PER_AES, offset);
-                            if let Some(indexes) = consume(nonce, index) {
-                                return Some((nonce, indexes));
-                            }
+                let mut passing = simd::not_above(&u8s, self.difficulty_msb);
+                while passing != 0 {
+                    let offset = passing.trailing_zeros() as usize;
+                    passing &= passing - 1;
+                    let msb = u8s[offset];
+                    if msb == self.difficulty_msb {
+                        // Check LSB
+                        let nonce = calc_nonce(cipher.nonce_group, Self::NONCES_PER_AES, offset);
+                        let label_offset = offset / Self::NONCES_PER_AES as usize * LABEL_SIZE;
+                        if let Some(p) = self.check_lsb(
+                            &chunk[label_offset..label_offset + LABEL_SIZE],
+                            nonce,
+                            offset,
+                            index,
+                            &mut consume,
+                        ) {
+                            return Some(p);
+                        }
+                    } else {
+                        // valid label
+                        let index = index + (offset as u32 / Self::NONCES_PER_AES) as u64;
+                        let nonce = calc_nonce(cipher.nonce_group, Self::NONCES_PER_AES, offset);
+                        if let Some(indexes) = consume(nonce, index) {
+                            return Some((nonce, indexes));
                         }
                     }
                 }
@@ -263,98 +485,370 @@ impl Prover for Prover8_56 {
     }
 }
 
+/// Load the metadata of the POS data, checking the `settings` are valid for it.
+fn load_metadata(datadir: &Path, settings: &ProvingSettings) -> eyre::Result<PostMetadata> {
+    eyre::ensure!(
+        settings.read_chunk_size > 0 && settings.read_chunk_size % CHUNK_SIZE == 0,
+        "read chunk size must be a positive multiple of {CHUNK_SIZE} bytes"
+    );
+    let metadata = metadata::load(datadir).wrap_err("loading metadata")?;
+    if let Some(units) = settings.units {
+        eyre::ensure!(
+            units.count > 0 && units.first as u64 + units.count as u64 <= metadata.num_units as u64,
+            "units {units:?} are out of the {} units of POS data",
+            metadata.num_units
+        );
+    }
+    match (settings.key, metadata.encryption_key_check) {
+        (Some(key), _) => key.validate(&metadata)?,
+        (None, Some(_)) => eyre::bail!("POS data is encrypted, the key is required"),
+        (None, None) => {}
+    }
+    if settings.best_effort && settings.source.is_none() {
+        log_coverage(datadir, &metadata)?;
+    }
+    Ok(metadata)
+}
+
+/// Log which portion of the POS data can be read when proving in the best-effort mode.
+fn log_coverage(datadir: &Path, metadata: &PostMetadata) -> eyre::Result<()> {
+    let total = metadata.num_units as u64 * metadata.labels_per_unit * LABEL_SIZE as u64;
+    let readable = readable_size(datadir, metadata.layout())?.min(total);
+    eyre::ensure!(readable > 0, "none of the POS data can be read");
+    if readable < total {
+        log::warn!(
+            "proving with {:.2}% of the POS data ({readable} of {total} bytes), the proof might not be found",
+            readable as f64 * 100.0 / total as f64
+        );
+    } else {
+        log::info!("all POS data can be read");
+    }
+    Ok(())
+}
+
+/// Range (in bytes) of the POS data to scan,
+/// all of it unless restricted to some [units](ProvingSettings::units).
+fn scanned_range(metadata: &PostMetadata, settings: &ProvingSettings) -> Range<u64> {
+    let unit_size = metadata.labels_per_unit * LABEL_SIZE as u64;
+    let units = settings.units.unwrap_or(UnitRange {
+        first: 0,
+        count: metadata.num_units,
+    });
+    units.first as u64 * unit_size..(units.first as u64 + units.count as u64) * unit_size
+}
+
+/// Read the [scanned](scanned_range) POS data from the `position` (in bytes) on,
+/// from the [LabelSource] if set, or the POS data files.
+fn read_pos_data<'a>(
+    datadir: &Path,
+    metadata: &PostMetadata,
+    position: u64,
+    settings: &ProvingSettings<'a>,
+) -> eyre::Result<Box<dyn Iterator<Item = Batch> + Send + 'a>> {
+    let range = scanned_range(metadata, settings);
+    let position = position.max(range.start);
+    match settings.source {
+        Some(source) => Ok(Box::new(read_source(
+            source,
+            settings.read_chunk_size,
+            position,
+            range.end,
+        ))),
+        None => read_data(
+            datadir,
+            settings.read_chunk_size,
+            metadata.layout(),
+            position..range.end,
+            settings.reader,
+            settings.read_ahead,
+            settings.parallel_reads,
+            settings.best_effort,
+        ),
+    }
+}
+
+/// Hash the `batch` read with the [ChecksumVerifier] (if any).
+fn verify_checksums(verifier: Option<&Mutex<ChecksumVerifier>>, batch: &Batch) {
+    if let Some(verifier) = verifier {
+        verifier.lock().unwrap().update(batch);
+    }
+}
+
+/// Names of the POS data files found corrupted in the pass by the [ChecksumVerifier] (if any).
+fn corrupted_files(verifier: Option<&Mutex<ChecksumVerifier>>) -> Vec<String> {
+    verifier.map_or_else(Vec::new, |v| v.lock().unwrap().finish_pass())
+}
+
+/// The [pow::Prover] of the `settings`, or RandomX with the `pow_flags`,
+/// caching the proofs of work in the [pow::cache::PowCache] of the `settings` (if any).
+fn pow_prover(
+    settings: &ProvingSettings,
+    pow_flags: RandomXFlag,
+) -> eyre::Result<Arc<dyn pow::Prover + Send + Sync>> {
+    let prover: Arc<dyn pow::Prover + Send + Sync> = match settings.pow_prover {
+        Some(prover) => prover.clone(),
+        None => Arc::new(randomx_prover(pow_flags)?),
+    };
+    Ok(match settings.pow_cache {
+        Some(cache) => Arc::new(pow::cache::CachingProver::new(cache.clone(), prover)),
+        None => prover,
+    })
+}
+
+/// Load the k2 proofs of work cached in the `datadir`, to share them
+/// between the proof generations with [ProvingSettings::pow_cache].
+pub fn load_pow_cache(datadir: &Path) -> eyre::Result<pow::cache::PowCache> {
+    // Verifying the few cached proofs of work doesn't need the full dataset.
+    let verifier = pow::randomx::PoW::new(RandomXFlag::get_recommended_flags())?;
+    pow::cache::PowCache::new()
+        .with_file(&datadir.join(POW_CACHE_FILE_NAME), &verifier)
+        .wrap_err("loading cached proofs of work")
+}
+
+/// RandomX with the `pow_flags`, as used for proving unless another [pow::Prover]
+/// is set in the [ProvingSettings]: logging the progress of the k2pow.
+pub fn randomx_prover(pow_flags: RandomXFlag) -> Result<pow::randomx::PoW, pow::Error> {
+    Ok(pow::randomx::PoW::new(pow_flags)?
+        .with_progress(K2POW_PROGRESS_INTERVAL, log_k2pow_progress))
+}
+
+/// Log the progress of the k2pow, so that it's clear it's not stuck.
+fn log_k2pow_progress(progress: pow::Progress) {
+    log::info!(
+        "k2pow for nonce group {}: {} hashes in {:.0?} ({:.0} hashes/s)",
+        progress.nonce_group,
+        progress.hashes,
+        progress.elapsed,
+        progress.hashrate()
+    );
+}
+
+/// Check the nonce groups of the `nonces` fit in the PoW input,
+/// unless `wide` nonce groups are allowed.
+fn check_nonce_groups(nonces: &Range<u32>, wide: bool) -> eyre::Result<()> {
+    let groups = nonce_group_range(nonces.clone(), Prover8_56::NONCES_PER_AES);
+    eyre::ensure!(
+        wide || groups.end <= pow::NARROW_NONCE_GROUPS,
+        "nonces {nonces:?} exceed {} nonce groups, wide nonce groups are not allowed",
+        pow::NARROW_NONCE_GROUPS
+    );
+    Ok(())
+}
+
+/// Scan the labels with the `scanner` (if any).
+/// Returns `None` if they must be checked on the CPU.
+fn scan_labels(scanner: Option<&Mutex<dyn LabelScanner>>, labels: &[u8]) -> Option<Vec<Candidate>> {
+    match scanner?.lock().unwrap().scan(labels) {
+        Ok(candidates) => Some(candidates),
+        Err(e) => {
+            log::warn!("failed to scan labels, checking them on CPU: {e}");
+            None
+        }
+    }
+}
+
 /// Generate a proof that data is still held, given the challenge.
+///
+/// With `nonces` of 0, the number of nonces of a pass is picked automatically
+/// from the measured speeds of reading the POS data and checking the labels.
 #[allow(clippy::too_many_arguments)]
-pub fn generate_proof<Stopper>(
+pub fn generate_proof<Cancel>(
     datadir: &Path,
     challenge: &[u8; 32],
     cfg: ProofConfig,
     nonces: usize,
     threads: usize,
     pow_flags: RandomXFlag,
-    stop: Stopper,
+    cancel: Cancel,
 ) -> eyre::Result<Proof<'static>>
 where
-    Stopper: Borrow<AtomicBool>,
+    Cancel: Borrow<CancellationToken>,
 {
-    let stop = stop.borrow();
-    let metadata = metadata::load(datadir).wrap_err("loading metadata")?;
-    let params = ProvingParams::new(&metadata, &cfg)?;
-    log::info!("generating proof with PoW flags: {pow_flags:?} and params: {params:?}");
-    let pow_prover = pow::randomx::PoW::new(pow_flags)?;
-
-    let mut start_nonce = 0;
-    let mut end_nonce = start_nonce + nonces as u32;
-
-    let pool = rayon::ThreadPoolBuilder::new()
-        .num_threads(threads)
-        .build()
-        .wrap_err("building thread pool")?;
-
-    let total_time = Instant::now();
-    loop {
-        if stop.load(Ordering::Relaxed) {
-            eyre::bail!("proof generation was stopped");
-        }
-
-        let indexes = Mutex::new(HashMap::<u32, Vec<u64>>::new());
-
-        let pow_time = Instant::now();
-        let prover = pool.install(|| {
-            Prover8_56::new(
-                challenge,
-                start_nonce..end_nonce,
-                params,
-                &pow_prover,
-                &metadata.node_id,
-            )
-            .wrap_err("creating prover")
-        })?;
-
-        let pow_mins = pow_time.elapsed().as_secs() / 60;
-        log::info!("Finished k2pow in {} minutes", pow_mins);
-
-        let read_time = Instant::now();
-        let data_reader = read_data(datadir, 1024 * 1024, metadata.max_file_size)?;
-        log::info!("Started reading POST data");
-        let result = pool.install(|| {
-            data_reader
-                .par_bridge()
-                .take_any_while(|_| !stop.load(Ordering::Relaxed))
-                .find_map_any(|batch| {
-                    prover.prove(
-                        &batch.data,
-                        batch.pos / BLOCK_SIZE as u64,
-                        |nonce, index| {
-                            let mut indexes = indexes.lock().unwrap();
-                            let vec = indexes.entry(nonce).or_default();
-                            vec.push(index);
-                            if vec.len() >= cfg.k2 as usize {
-                                return Some(std::mem::take(vec));
-                            }
-                            None
-                        },
-                    )
-                })
-        });
+    generate_proof_with_key(
+        datadir, challenge, cfg, nonces, threads, pow_flags, cancel, None,
+    )
+}
 
-        let read_mins = read_time.elapsed().as_secs() / 60;
-        log::info!("Finished reading POST data in {} minutes", read_mins);
+/// Generate a proof that data is still held, given the challenge.
+///
+/// The POS data encrypted at rest is decrypted with the `key` while reading.
+#[allow(clippy::too_many_arguments)]
+pub fn generate_proof_with_key<Cancel>(
+    datadir: &Path,
+    challenge: &[u8; 32],
+    cfg: ProofConfig,
+    nonces: usize,
+    threads: usize,
+    pow_flags: RandomXFlag,
+    cancel: Cancel,
+    key: Option<&DataKey>,
+) -> eyre::Result<Proof<'static>>
+where
+    Cancel: Borrow<CancellationToken>,
+{
+    let settings = ProvingSettings {
+        key,
+        ..Default::default()
+    };
+    generate_proof_with_settings(
+        datadir, challenge, cfg, nonces, threads, pow_flags, cancel, &settings,
+    )
+}
 
-        if let Some((nonce, indices)) = result {
-            let num_labels = metadata.num_units as u64 * metadata.labels_per_unit;
-            let pow = prover.get_pow(nonce).unwrap();
+/// Contiguous units of the POS data: `count` of them from the `first` one.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct UnitRange {
+    pub first: u32,
+    pub count: u32,
+}
 
-            let total_minutes = total_time.elapsed().as_secs() / 60;
+/// Optional settings of the proof generation.
+#[derive(Debug, Clone, Copy)]
+pub struct ProvingSettings<'a> {
+    /// Key to decrypt the POS data encrypted at rest while reading.
+    pub key: Option<&'a DataKey>,
+    /// If set, the progress is saved as a [ProvingCheckpoint] in the POS data
+    /// directory periodically and when stopped. The proof generation for the same
+    /// challenges is then resumed from the checkpoint, instead of starting from scratch.
+    /// The checkpoint is removed once the proofs of all the challenges are found.
+    pub checkpoint_interval: Option<Duration>,
+    /// Backend used to read the POS data.
+    pub reader: ReaderBackend,
+    /// Size (in bytes) of a chunk of POS data read at once.
+    /// Must be a multiple of 128 bytes (8 labels).
+    pub read_chunk_size: usize,
+    /// Number of chunks read ahead of processing them (in flight at once with io_uring).
+    /// It's the size of the queue between the reader of the POS data and
+    /// the workers checking the labels, which wait for each other when it's full or empty.
+    pub read_ahead: usize,
+    /// Whether to read the POS data files residing on different disks in parallel.
+    pub parallel_reads: ParallelReads<'a>,
+    /// If set, the labels are scanned with the [LabelScanner] (i.e. on a GPU)
+    /// instead of evaluating the difficulty check on the CPU.
+    pub scanner: Option<&'a Mutex<dyn LabelScanner>>,
+    /// If set, the proof generation fails with [DeadlineExceeded] as soon as
+    /// it's estimated (from the duration of the previous pass or the throughput
+    /// of reading the POS data) to not finish before the deadline.
+    pub deadline: Option<Instant>,
+    /// If set, missing and unreadable POS data files are skipped instead of failing
+    /// the proof generation. The proof is then looked for in the rest of the POS data,
+    /// which is less likely to succeed the more of it is missing.
+    pub best_effort: bool,
+    /// If set, the [ProvingStats] are updated as the proof generation goes.
+    pub stats: Option<&'a Mutex<ProvingStats>>,
+    /// If set, the labels are read from the [LabelSource] instead of the POS data files.
+    /// The metadata is still loaded from the POS data directory. The [ReaderBackend]
+    /// and [ParallelReads] don't apply to it.
+    pub source: Option<&'a dyn LabelSource>,
+    /// CPU cores to run the proving threads on.
+    pub cores: CoreSelection<'a>,
+    /// NUMA node to run the proving threads on, so that the POS data read
+    /// and the buffers checking it stay in memory local to them.
+    /// Combined with the [CoreSelection], only the selected cores of the node are used.
+    pub numa: NumaPlacement,
+    /// If set, the memory (in bytes) for the POS data read and the indices of labels
+    /// passing the difficulty is bounded by it. The `read_chunk_size` and `read_ahead`
+    /// are reduced to fit half of it and the indices exceeding the rest
+    /// are spilled to a temporary file.
+    pub memory_budget: Option<usize>,
+    /// Allow more than 256 nonce groups (4096 nonces), so that machines with many cores
+    /// can try thousands of nonces in a single pass. The k2 proofs of work of nonce groups
+    /// above 255 have a wider input, so their proofs are [ProofVersion::V2],
+    /// which only the verifiers supporting it accept.
+    pub wide_nonce_groups: bool,
+    /// If set, the [IndexHook] is called with every index found for a nonce.
+    pub index_hook: Option<&'a dyn IndexHook>,
+    /// If set, only these units of the POS data are scanned, i.e. when the others
+    /// are not available. The indices of the labels are still those in the whole POS data.
+    pub units: Option<UnitRange>,
+    /// Verify the POS data files read whole in a pass against the checksums recorded
+    /// in the metadata (if any), hashing the data on the thread reading it.
+    /// If corrupted files are found and the pass didn't find the proof,
+    /// the proof generation fails instead of trying more passes.
+    pub verify_checksums: bool,
+    /// If set, the k2 proofs of work are calculated with the [pow::Prover]
+    /// (i.e. a remote or an instrumented one) instead of RandomX with the PoW flags.
+    pub pow_prover: Option<&'a Arc<dyn pow::Prover + Send + Sync>>,
+    /// If set, the k2 proofs of work found are cached (i.e. in the POS data directory,
+    /// see [load_pow_cache]), so that they are not calculated again when the proof
+    /// generation is retried for the same challenge.
+    pub pow_cache: Option<&'a Arc<pow::cache::PowCache>>,
+    /// Number of threads calculating the k2 proofs of work ('0' means one per core).
+    /// If not set, it's the number of the proving threads. The k2pow is CPU bound and
+    /// benefits from all the cores, while the proving threads might be bound by the disks.
+    pub pow_threads: Option<usize>,
+    /// Run the k2pow threads at the lowest OS scheduling priority (`SCHED_IDLE` on Linux,
+    /// the background mode on Windows), so that they don't starve the threads reading
+    /// the POS data and other processes (i.e. the node) on a shared machine.
+    pub low_priority_pow: bool,
+    /// If set, the number of threads checking the labels is limited by the [ProvingThrottle],
+    /// which can be changed while proving.
+    pub throttle: Option<&'a ProvingThrottle>,
+    /// If set, the challenges pushed to the [JoiningChallenges] while proving
+    /// are proven along, joining the next pass over the POS data.
+    pub joining: Option<&'a JoiningChallenges>,
+}
 
-            log::info!("Found proof for nonce: {nonce}, pow: {pow} with {indices:?} indices. Proof took {total_minutes} minutes");
-            return Ok(Proof::new(nonce, &indices, num_labels, pow));
+impl Default for ProvingSettings<'_> {
+    fn default() -> Self {
+        Self {
+            key: None,
+            checkpoint_interval: None,
+            reader: ReaderBackend::default(),
+            read_chunk_size: DEFAULT_READ_CHUNK_SIZE,
+            read_ahead: DEFAULT_READ_AHEAD,
+            parallel_reads: ParallelReads::default(),
+            scanner: None,
+            deadline: None,
+            best_effort: false,
+            stats: None,
+            source: None,
+            cores: CoreSelection::Any,
+            numa: NumaPlacement::Off,
+            memory_budget: None,
+            wide_nonce_groups: false,
+            index_hook: None,
+            units: None,
+            verify_checksums: false,
+            pow_prover: None,
+            pow_cache: None,
+            pow_threads: None,
+            low_priority_pow: false,
+            throttle: None,
+            joining: None,
         }
-
-        (start_nonce, end_nonce) = (end_nonce, end_nonce + nonces as u32);
     }
 }
 
+/// Generate a proof that data is still held, given the challenge,
+/// with the optional [ProvingSettings].
+#[allow(clippy::too_many_arguments)]
+pub fn generate_proof_with_settings<Cancel>(
+    datadir: &Path,
+    challenge: &[u8; 32],
+    cfg: ProofConfig,
+    nonces: usize,
+    threads: usize,
+    pow_flags: RandomXFlag,
+    cancel: Cancel,
+    settings: &ProvingSettings,
+) -> eyre::Result<Proof<'static>>
+where
+    Cancel: Borrow<CancellationToken>,
+{
+    let mut proofs = generate_proofs_with_settings(
+        datadir,
+        std::slice::from_ref(challenge),
+        cfg,
+        nonces,
+        threads,
+        pow_flags,
+        cancel,
+        settings,
+    )?;
+    Ok(proofs.remove(0))
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -376,6 +870,31 @@ mod tests {
                 .take(indices.len())
                 .collect::<Vec<_>>()
         );
+        assert_eq!(ProofVersion::V1, proof.version);
+
+        // The proofs of wide nonce groups are V2.
+        let proof = Proof::new(255 * 16 + 15, &indices, 9, 77);
+        assert_eq!(ProofVersion::V1, proof.version);
+        let proof = Proof::new(256 * 16, &indices, 9, 77);
+        assert_eq!(ProofVersion::V2, proof.version);
+    }
+
+    #[test]
+    fn numbering_proof_versions() {
+        for version in [ProofVersion::V1, ProofVersion::V2] {
+            assert_eq!(Ok(version), ProofVersion::try_from(u32::from(version)));
+        }
+        assert_eq!(1, u32::from(ProofVersion::V1));
+        assert!(ProofVersion::try_from(0).is_err());
+        assert!(ProofVersion::try_from(3).is_err());
+    }
+
+    #[test]
+    fn deserializing_unversioned_proof() {
+        let proof: Proof =
+            serde_json::from_str(r#"{"nonce":7,"indices":"AQID","pow":77}"#).unwrap();
+        assert_eq!(ProofVersion::V1, proof.version);
+        assert_eq!([1, 2, 3], proof.indices.as_ref());
     }
 
     #[test]
@@ -397,16 +916,28 @@ mod tests {
 
         pow_prover
             .expect_prove()
-            .with(eq(0), eq([0; 8]), eq(cfg.pow_difficulty), always())
+            .with(
+                eq(0),
+                eq([0; 8]),
+                eq(cfg.pow_difficulty),
+                always(),
+                always(),
+            )
             .once()
-            .returning(|_, _, _, _| Ok(0));
+            .returning(|_, _, _, _, _| Ok(0));
         assert!(Prover8_56::new(&[0; 32], 0..16, params, &pow_prover, &meta.node_id).is_ok());
 
         pow_prover
             .expect_prove()
-            .with(eq(1), eq([0; 8]), eq(cfg.pow_difficulty), always())
+            .with(
+                eq(1),
+                eq([0; 8]),
+                eq(cfg.pow_difficulty),
+                always(),
+                always(),
+            )
             .once()
-            .returning(|_, _, _, _| Ok(0));
+            .returning(|_, _, _, _, _| Ok(0));
         assert!(Prover8_56::new(&[0; 32], 16..32, params, &pow_prover, &meta.node_id).is_ok());
 
         assert!(Prover8_56::new(&[0; 32], 0..0, params, &pow_prover, &meta.node_id).is_err());
@@ -431,7 +962,7 @@ mod tests {
         pow_prover
             .expect_prove()
             .once()
-            .returning(|_, _, _, _| Err(pow::Error::PoWNotFound));
+            .returning(|_, _, _, _, _| Err(pow::Error::PoWNotFound));
         let params = ProvingParams::new(&meta, &cfg).unwrap();
         assert!(Prover8_56::new(&[0; 32], 0..16, params, &pow_prover, &meta.node_id).is_err());
     }
@@ -449,10 +980,12 @@ mod tests {
             num_units: 1,
             labels_per_unit: 100,
             max_file_size: 1,
+            file_index_width: 0,
             node_id: [0u8; 32],
             commitment_atx_id: [0u8; 32],
             nonce: None,
             last_position: None,
+            encryption_key_check: None,
         };
         {
             let params = ProvingParams::new(&metadata, &cfg).unwrap();
@@ -480,7 +1013,7 @@ mod tests {
             pow_difficulty: [0xFF; 32],
         };
         let mut pow_prover = pow::MockProver::new();
-        pow_prover.expect_prove().returning(|_, _, _, _| Ok(0));
+        pow_prover.expect_prove().returning(|_, _, _, _, _| Ok(0));
 
         let prover = Prover8_56::new(
             challenge,
@@ -524,7 +1057,7 @@ mod tests {
             pow_difficulty: [0xFF; 32],
         };
         let mut pow_prover = pow::MockProver::new();
-        pow_prover.expect_prove().returning(|_, _, _, _| Ok(0));
+        pow_prover.expect_prove().returning(|_, _, _, _, _| Ok(0));
 
         let indexes = loop {
             let mut indicies = HashMap::<u32, Vec<u64>>::new();
@@ -591,7 +1124,7 @@ mod tests {
         pow_prover
             .expect_prove()
             .once()
-            .returning(|_, _, _, _| Ok(0));
+            .returning(|_, _, _, _, _| Ok(0));
         let data = repeat(0..=11) // it's important for range len to not be a multiple of AES block
             .flatten()
             .take(num_labels * LABEL_SIZE)
@@ -629,6 +1162,92 @@ mod tests {
         );
     }
 
+    /// Scans labels on the CPU, the way an accelerator would.
+    struct TestScanner {
+        ciphers: Vec<aes::Aes128>,
+        difficulty_msb: u8,
+    }
+
+    impl LabelScanner for TestScanner {
+        fn set_ciphers(
+            &mut self,
+            keys: &[[u8; 16]],
+            difficulty_msb: u8,
+        ) -> Result<(), Box<dyn std::error::Error>> {
+            use aes::cipher::{generic_array::GenericArray, KeyInit};
+            self.ciphers = keys
+                .iter()
+                .map(|k| aes::Aes128::new(GenericArray::from_slice(k)))
+                .collect();
+            self.difficulty_msb = difficulty_msb;
+            Ok(())
+        }
+
+        fn scan(&mut self, labels: &[u8]) -> Result<Vec<Candidate>, Box<dyn std::error::Error>> {
+            let mut candidates = Vec::new();
+            // Reversed to check that the order doesn't matter
+            for (label, data) in labels.chunks_exact(LABEL_SIZE).enumerate().rev() {
+                for (cipher, aes) in self.ciphers.iter().enumerate() {
+                    let mut out = [0u8; BLOCK_SIZE];
+                    aes.encrypt_block_b2b(data.into(), out.as_mut_slice().into());
+                    for (offset, &byte) in out.iter().enumerate() {
+                        if byte <= self.difficulty_msb {
+                            candidates.push(Candidate {
+                                label: label as u32,
+                                cipher: cipher as u32,
+                                offset: offset as u32,
+                            });
+                        }
+                    }
+                }
+            }
+            Ok(candidates)
+        }
+    }
+
+    #[test]
+    fn proving_with_scanner_gives_same_results() {
+        let challenge = b"hello world, challenge me!!!!!!!";
+        let num_labels = 4096 + 3;
+        let mut data = vec![0u8; num_labels * LABEL_SIZE];
+        thread_rng().fill_bytes(&mut data);
+        let params = ProvingParams {
+            difficulty: proving_difficulty(200, num_labels as u64).unwrap(),
+            pow_difficulty: [0xFF; 32],
+        };
+        let prover = Prover8_56::with_pows(challenge, 0..48, params, &[1, 2, 3]).unwrap();
+
+        let mut expected = Vec::new();
+        let res = prover.prove(&data, 16, |nonce, index| {
+            expected.push((nonce, index));
+            None
+        });
+        assert!(res.is_none());
+        assert!(!expected.is_empty());
+
+        let mut scanner = TestScanner {
+            ciphers: Vec::new(),
+            difficulty_msb: 0,
+        };
+        scanner
+            .set_ciphers(&prover.nonce_group_keys(), prover.difficulty_msb())
+            .unwrap();
+        let mut found = Vec::new();
+        let res = prover.prove_candidates(&data, 16, scanner.scan(&data).unwrap(), |n, i| {
+            found.push((n, i));
+            None
+        });
+        assert!(res.is_none());
+        assert_eq!(expected, found);
+    }
+
+    #[test]
+    fn checking_nonce_groups() {
+        assert!(check_nonce_groups(&(0..4096), false).is_ok());
+        assert!(check_nonce_groups(&(4080..4112), false).is_err());
+        assert!(check_nonce_groups(&(4080..4112), true).is_ok());
+    }
+
     #[test]
     fn calculating_nonce_group_range() {
         assert_eq!(0..1, nonce_group_range(0..1, 16));
@@ -0,0 +1,282 @@
+//! Running the proving threads on selected CPU cores.
+//!
+//! Smeshers co-located with a node or other latency sensitive services
+//! can reserve CPU cores for them by keeping the proving threads off them,
+//! or run the k2pow threads at a low priority.
+
+use std::{io, path::Path};
+
+use eyre::Context;
+use rayon::ThreadPool;
+
+use super::NumaPlacement;
+
+/// CPU cores to run the proving threads on.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum CoreSelection<'a> {
+    /// Any core.
+    #[default]
+    Any,
+    /// Only the given cores.
+    Only(&'a [usize]),
+    /// All cores available to the process except the given ones.
+    Except(&'a [usize]),
+}
+
+impl CoreSelection<'_> {
+    /// The selected cores, or `None` for any core.
+    fn cores(&self) -> eyre::Result<Option<Vec<usize>>> {
+        let cores = match *self {
+            CoreSelection::Any => return Ok(None),
+            CoreSelection::Only(cores) => {
+                let available = available_cores().wrap_err("getting available CPU cores")?;
+                let unavailable = cores
+                    .iter()
+                    .filter(|core| !available.contains(core))
+                    .collect::<Vec<_>>();
+                eyre::ensure!(
+                    unavailable.is_empty(),
+                    "CPU cores {unavailable:?} are not available"
+                );
+                cores.to_vec()
+            }
+            CoreSelection::Except(excluded) => available_cores()
+                .wrap_err("getting available CPU cores")?
+                .into_iter()
+                .filter(|core| !excluded.contains(core))
+                .collect(),
+        };
+        eyre::ensure!(!cores.is_empty(), "no CPU cores selected for proving");
+        Ok(Some(cores))
+    }
+}
+
+/// The cores to run the proving threads on, selected by `cores` and
+/// on the NUMA node of the `numa` placement, or `None` for any core.
+pub(crate) fn select_cores(
+    cores: CoreSelection,
+    numa: NumaPlacement,
+    datadir: &Path,
+) -> eyre::Result<Option<Vec<usize>>> {
+    let selected = match (cores.cores()?, numa.cores(datadir)?) {
+        (None, None) => return Ok(None),
+        (Some(cores), None) | (None, Some(cores)) => cores,
+        (Some(cores), Some(node)) => cores.into_iter().filter(|c| node.contains(c)).collect(),
+    };
+    eyre::ensure!(
+        !selected.is_empty(),
+        "no CPU cores selected for proving on the NUMA node"
+    );
+    log::info!("running proving threads on CPU cores {selected:?}");
+    Ok(Some(selected))
+}
+
+/// Build a thread pool of `threads` threads running on the `cores` (if given).
+/// With '0' threads, there's one thread per core.
+pub(crate) fn thread_pool(
+    threads: usize,
+    cores: Option<&[usize]>,
+    name: &'static str,
+) -> eyre::Result<ThreadPool> {
+    build_thread_pool(threads, cores, name, false)
+}
+
+/// Build a thread pool for the k2pow like [thread_pool], with its threads
+/// at the lowest OS scheduling priority if `low_priority` is set, so that they
+/// don't starve the threads reading the POS data and other processes on the machine.
+pub(crate) fn pow_thread_pool(
+    threads: usize,
+    cores: Option<&[usize]>,
+    low_priority: bool,
+) -> eyre::Result<ThreadPool> {
+    build_thread_pool(threads, cores, "post-k2pow", low_priority)
+}
+
+fn build_thread_pool(
+    threads: usize,
+    cores: Option<&[usize]>,
+    name: &'static str,
+    low_priority: bool,
+) -> eyre::Result<ThreadPool> {
+    let threads = match (threads, cores) {
+        (0, Some(cores)) => cores.len(),
+        _ => threads,
+    };
+    let cores = cores.map(<[usize]>::to_vec);
+    let builder = rayon::ThreadPoolBuilder::new()
+        .num_threads(threads)
+        .thread_name(move |idx| format!("{name}-{idx}"));
+    if cores.is_none() && !low_priority {
+        return builder.build().wrap_err("building thread pool");
+    }
+    builder
+        .start_handler(move |_| {
+            if let Some(cores) = &cores {
+                pin_thread(cores, name);
+            }
+            if low_priority {
+                lower_priority(name);
+            }
+        })
+        .build()
+        .wrap_err("building thread pool")
+}
+
+/// Lower the OS scheduling priority of the current thread, warning if it fails.
+fn lower_priority(name: &str) {
+    if let Err(e) = set_low_priority() {
+        log::warn!("failed to lower the priority of a {name} thread: {e}");
+    }
+}
+
+/// Run the current thread only when the CPU is otherwise idle (`SCHED_IDLE`).
+#[cfg(target_os = "linux")]
+fn set_low_priority() -> io::Result<()> {
+    let param = libc::sched_param { sched_priority: 0 };
+    // SAFETY: `param` outlives the call, '0' is the current thread.
+    let ret = unsafe { libc::sched_setscheduler(0, libc::SCHED_IDLE, &param) };
+    if ret != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+/// Put the current thread in the background mode, lowering its CPU and I/O priority.
+#[cfg(windows)]
+fn set_low_priority() -> io::Result<()> {
+    use windows_sys::Win32::System::Threading::{
+        GetCurrentThread, SetThreadPriority, THREAD_MODE_BACKGROUND_BEGIN,
+    };
+    // SAFETY: the pseudo handle of the current thread is always valid.
+    let ok = unsafe { SetThreadPriority(GetCurrentThread(), THREAD_MODE_BACKGROUND_BEGIN) };
+    if ok == 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+#[cfg(not(any(target_os = "linux", windows)))]
+fn set_low_priority() -> io::Result<()> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "lowering thread priority is only supported on Linux and Windows",
+    ))
+}
+
+/// Run the current thread on the `cores`, warning if it fails.
+pub(crate) fn pin_thread(cores: &[usize], name: &str) {
+    if let Err(e) = set_affinity(cores) {
+        log::warn!("failed to set CPU affinity of a {name} thread: {e}");
+    }
+}
+
+/// Restrict the current thread to run on the `cores`.
+#[cfg(target_os = "linux")]
+fn set_affinity(cores: &[usize]) -> io::Result<()> {
+    // SAFETY: `cpu_set_t` is a plain bitmask, valid when zeroed.
+    let mut set: libc::cpu_set_t = unsafe { std::mem::zeroed() };
+    for &core in cores {
+        if core >= libc::CPU_SETSIZE as usize {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("invalid CPU core {core}"),
+            ));
+        }
+        // SAFETY: the core is within the set.
+        unsafe { libc::CPU_SET(core, &mut set) };
+    }
+    // SAFETY: `set` outlives the call and its size is given.
+    let ret = unsafe { libc::sched_setaffinity(0, std::mem::size_of_val(&set), &set) };
+    if ret != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+/// Cores the current thread is allowed to run on.
+#[cfg(target_os = "linux")]
+fn available_cores() -> io::Result<Vec<usize>> {
+    // SAFETY: `cpu_set_t` is a plain bitmask, valid when zeroed.
+    let mut set: libc::cpu_set_t = unsafe { std::mem::zeroed() };
+    // SAFETY: `set` outlives the call and its size is given.
+    let ret = unsafe { libc::sched_getaffinity(0, std::mem::size_of_val(&set), &mut set) };
+    if ret != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok((0..libc::CPU_SETSIZE as usize)
+        // SAFETY: the core is within the set.
+        .filter(|&core| unsafe { libc::CPU_ISSET(core, &set) })
+        .collect())
+}
+
+#[cfg(not(target_os = "linux"))]
+fn set_affinity(_cores: &[usize]) -> io::Result<()> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "selecting CPU cores is only supported on Linux",
+    ))
+}
+
+#[cfg(not(target_os = "linux"))]
+fn available_cores() -> io::Result<Vec<usize>> {
+    set_affinity(&[]).map(|_| Vec::new())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn any_core() {
+        let dir = Path::new(".");
+        assert_eq!(
+            None,
+            select_cores(CoreSelection::Any, NumaPlacement::Off, dir).unwrap()
+        );
+        let pool = thread_pool(2, None, "test").unwrap();
+        assert_eq!(2, pool.current_num_threads());
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn selecting_cores() {
+        let available = available_cores().unwrap();
+        let core = available[0];
+
+        let dir = Path::new(".");
+
+        let cores = select_cores(CoreSelection::Only(&[core]), NumaPlacement::Off, dir).unwrap();
+        let pool = thread_pool(0, cores.as_deref(), "test").unwrap();
+        assert_eq!(1, pool.current_num_threads());
+        assert_eq!(vec![core], pool.install(|| available_cores().unwrap()));
+
+        let cores = select_cores(CoreSelection::Except(&[core]), NumaPlacement::Off, dir);
+        if available.len() > 1 {
+            let cores = cores.unwrap();
+            let pool = thread_pool(3, cores.as_deref(), "test").unwrap();
+            let cores = pool.install(|| available_cores().unwrap());
+            assert_eq!(&available[1..], cores);
+        } else {
+            assert!(cores.is_err());
+        }
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn low_priority_pow_threads() {
+        let pool = pow_thread_pool(2, None, true).unwrap();
+        // SAFETY: querying the policy of the current thread.
+        let policy = pool.install(|| unsafe { libc::sched_getscheduler(0) });
+        assert_eq!(libc::SCHED_IDLE, policy);
+
+        let pool = pow_thread_pool(2, None, false).unwrap();
+        let policy = pool.install(|| unsafe { libc::sched_getscheduler(0) });
+        assert_ne!(libc::SCHED_IDLE, policy);
+    }
+
+    #[test]
+    fn no_cores_selected() {
+        let dir = Path::new(".");
+        assert!(select_cores(CoreSelection::Only(&[]), NumaPlacement::Off, dir).is_err());
+    }
+}
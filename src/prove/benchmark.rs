@@ -0,0 +1,143 @@
+//! Benchmarking the proof generation without POS data.
+//!
+//! Synthetic labels are streamed through the same pipeline as the POS data
+//! when generating a proof, so the results tell how fast the CPU checks the labels
+//! on the given number of threads. Reading the POS data from the disks is not included.
+
+use std::{
+    sync::atomic::{AtomicU64, Ordering},
+    time::{Duration, Instant},
+};
+
+use rand::{rngs::SmallRng, RngCore, SeedableRng};
+use rayon::ThreadPool;
+
+use super::{affinity, pipeline, Prover, Prover8_56, ProvingParams, CHUNK_SIZE, LABEL_SIZE};
+use crate::{
+    config::ProofConfig,
+    metadata::PostMetadata,
+    reader::{Batch, DEFAULT_READ_AHEAD, DEFAULT_READ_CHUNK_SIZE},
+};
+
+const TIB: u64 = 1024 * 1024 * 1024 * 1024;
+
+/// Result of a [benchmark].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BenchmarkResult {
+    /// Number of labels checked.
+    pub labels: u64,
+    /// How long checking them took.
+    pub duration: Duration,
+}
+
+impl BenchmarkResult {
+    pub fn labels_per_sec(&self) -> f64 {
+        self.labels as f64 / self.duration.as_secs_f64()
+    }
+
+    /// Estimated time of a pass over 1 TiB of POS data,
+    /// assuming the storage reads it at least as fast.
+    pub fn time_per_tib(&self) -> Duration {
+        let labels = (TIB / LABEL_SIZE as u64) as f64;
+        Duration::from_secs_f64(labels / self.labels_per_sec())
+    }
+}
+
+/// Measure how fast `size` bytes of synthetic labels are checked for `nonces`
+/// on `threads` threads ('0' means all available).
+///
+/// The k2 proof of work is not computed, dummy ones are used instead.
+pub fn benchmark(
+    cfg: ProofConfig,
+    size: u64,
+    threads: usize,
+    nonces: usize,
+) -> eyre::Result<BenchmarkResult> {
+    let size = size / CHUNK_SIZE as u64 * CHUNK_SIZE as u64;
+    eyre::ensure!(size > 0, "size must be at least {CHUNK_SIZE} bytes");
+    eyre::ensure!(
+        nonces > 0 && nonces % (Prover8_56::NONCES_PER_AES as usize) == 0,
+        "nonces must be a positive multiple of {}",
+        Prover8_56::NONCES_PER_AES
+    );
+
+    let metadata = PostMetadata {
+        num_units: 1,
+        labels_per_unit: size / LABEL_SIZE as u64,
+        ..Default::default()
+    };
+    let params = ProvingParams::new(&metadata, &cfg)?;
+    let pool = affinity::thread_pool(threads, None, "post-bench")?;
+    let result = measure(&pool, params, size, nonces)?;
+    log::info!(
+        "checked {} labels in {:.2?} ({:.0} labels/s, {:.2?} per TiB)",
+        result.labels,
+        result.duration,
+        result.labels_per_sec(),
+        result.time_per_tib()
+    );
+    Ok(result)
+}
+
+/// Measure how fast `size` bytes (a multiple of [CHUNK_SIZE]) of synthetic labels
+/// are checked for `nonces` with the `params` on the `pool`.
+pub(crate) fn measure(
+    pool: &ThreadPool,
+    params: ProvingParams,
+    size: u64,
+    nonces: usize,
+) -> eyre::Result<BenchmarkResult> {
+    let pows = vec![0; nonces / Prover8_56::NONCES_PER_AES as usize];
+    let prover = Prover8_56::with_pows(&[0; 32], 0..nonces as u32, params, &pows)?;
+
+    let mut rng = SmallRng::from_entropy();
+    let batches = (0..size).step_by(DEFAULT_READ_CHUNK_SIZE).map(move |pos| {
+        let mut data = vec![0u8; (size - pos).min(DEFAULT_READ_CHUNK_SIZE as u64) as usize];
+        rng.fill_bytes(&mut data);
+        Batch { data, pos }
+    });
+
+    let labels = AtomicU64::new(0);
+    let start = Instant::now();
+    pipeline::process_batches(
+        pool,
+        batches,
+        DEFAULT_READ_AHEAD,
+        None,
+        None,
+        || true,
+        |batch| {
+            let index = batch.pos / LABEL_SIZE as u64;
+            prover.prove(&batch.data, index, |_, _| None);
+            labels.fetch_add((batch.data.len() / LABEL_SIZE) as u64, Ordering::Relaxed);
+            None::<()>
+        },
+    )?;
+
+    Ok(BenchmarkResult {
+        labels: labels.into_inner(),
+        duration: start.elapsed(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn benchmarking() {
+        let cfg = ProofConfig {
+            k1: 10,
+            k2: 10,
+            k3: 10,
+            pow_difficulty: [0xFF; 32],
+        };
+        let result = benchmark(cfg, 4 * 1024 * 1024 + 100, 2, 32).unwrap();
+        assert_eq!(4 * 1024 * 1024 / LABEL_SIZE as u64, result.labels);
+        assert!(result.labels_per_sec() > 0.0);
+        assert!(result.time_per_tib() > Duration::ZERO);
+
+        assert!(benchmark(cfg, 16, 1, 16).is_err());
+        assert!(benchmark(cfg, 1024, 1, 10).is_err());
+    }
+}
@@ -0,0 +1,239 @@
+//! Caching generated proofs on disk.
+//!
+//! A proof generated for a challenge is lost if the proving process restarts
+//! before the proof was used, and generating it again takes another pass (or more)
+//! over the POS data. Proofs are thus cached in a directory until pruned.
+
+use std::{
+    fs::File,
+    io::{self, BufReader},
+    path::{Path, PathBuf},
+};
+
+use serde::{Deserialize, Serialize};
+use serde_with::{base64::Base64, serde_as};
+
+use super::Proof;
+use crate::metadata::PostMetadata;
+
+const FILE_PREFIX: &str = "proof_";
+const FILE_EXTENSION: &str = "json";
+
+/// A cached proof with the key it was cached under.
+#[serde_as]
+#[derive(Debug, Deserialize, Serialize)]
+struct CachedProof {
+    #[serde_as(as = "Base64")]
+    challenge: [u8; 32],
+    #[serde_as(as = "Base64")]
+    node_id: [u8; 32],
+    #[serde_as(as = "Base64")]
+    metadata_hash: [u8; 32],
+    proof: Proof<'static>,
+}
+
+/// Proofs cached in a directory, keyed by the challenge, the node ID
+/// and the hash of the metadata of the POS data they were generated for.
+#[derive(Debug, Clone)]
+pub struct ProofCache {
+    dir: PathBuf,
+}
+
+impl ProofCache {
+    /// Cache the proofs in the `dir`, creating it if needed.
+    pub fn new(dir: &Path) -> io::Result<Self> {
+        std::fs::create_dir_all(dir)?;
+        Ok(Self {
+            dir: dir.to_path_buf(),
+        })
+    }
+
+    /// Load the proof of the `challenge` for the POS data of the `metadata`, if cached.
+    ///
+    /// Fails if the cached proof is corrupted or was cached for another key.
+    pub fn load(
+        &self,
+        challenge: &[u8; 32],
+        metadata: &PostMetadata,
+    ) -> eyre::Result<Option<Proof<'static>>> {
+        let metadata_hash = metadata_hash(metadata)?;
+        let path = self.path(challenge, &metadata.node_id, &metadata_hash);
+        let file = match File::open(path) {
+            Ok(file) => file,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e.into()),
+        };
+        let cached: CachedProof = serde_json::from_reader(BufReader::new(file))?;
+        eyre::ensure!(
+            cached.challenge == *challenge
+                && cached.node_id == metadata.node_id
+                && cached.metadata_hash == metadata_hash,
+            "cached proof was generated for another challenge or POS data"
+        );
+        Ok(Some(cached.proof))
+    }
+
+    /// Cache the `proof` of the `challenge` for the POS data of the `metadata`,
+    /// replacing the previous one atomically.
+    pub fn store(
+        &self,
+        challenge: &[u8; 32],
+        metadata: &PostMetadata,
+        proof: &Proof,
+    ) -> eyre::Result<()> {
+        let metadata_hash = metadata_hash(metadata)?;
+        let path = self.path(challenge, &metadata.node_id, &metadata_hash);
+        let cached = CachedProof {
+            challenge: *challenge,
+            node_id: metadata.node_id,
+            metadata_hash,
+            proof: Proof {
+                version: proof.version,
+                nonce: proof.nonce,
+                indices: proof.indices.clone().into_owned().into(),
+                pow: proof.pow,
+            },
+        };
+        let tmp_path = path.with_extension("tmp");
+        let mut tmp_file = File::create(&tmp_path)?;
+        serde_json::to_writer(&mut tmp_file, &cached)?;
+        tmp_file.sync_all()?;
+        std::fs::rename(&tmp_path, path)?;
+        Ok(())
+    }
+
+    /// Remove the proof of the `challenge` for the POS data of the `metadata` (if cached).
+    pub fn remove(&self, challenge: &[u8; 32], metadata: &PostMetadata) -> eyre::Result<()> {
+        let path = self.path(challenge, &metadata.node_id, &metadata_hash(metadata)?);
+        match std::fs::remove_file(path) {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e.into()),
+            _ => Ok(()),
+        }
+    }
+
+    /// Remove all but the `keep` most recently cached proofs.
+    pub fn prune(&self, keep: usize) -> io::Result<()> {
+        let mut entries = Vec::new();
+        for entry in std::fs::read_dir(&self.dir)? {
+            let entry = entry?;
+            let path = entry.path();
+            let cached = path.extension().is_some_and(|ext| ext == FILE_EXTENSION)
+                && entry.file_name().to_string_lossy().starts_with(FILE_PREFIX);
+            if cached {
+                entries.push((entry.metadata()?.modified()?, path));
+            }
+        }
+        entries.sort_by(|a, b| b.0.cmp(&a.0));
+        for (_, path) in entries.into_iter().skip(keep) {
+            std::fs::remove_file(path)?;
+        }
+        Ok(())
+    }
+
+    fn path(&self, challenge: &[u8; 32], node_id: &[u8; 32], metadata_hash: &[u8; 32]) -> PathBuf {
+        let mut hasher = blake3::Hasher::new();
+        hasher.update(challenge);
+        hasher.update(node_id);
+        hasher.update(metadata_hash);
+        let key = hasher.finalize().to_hex();
+        self.dir
+            .join(format!("{FILE_PREFIX}{key}.{FILE_EXTENSION}"))
+    }
+}
+
+/// Hash of the `metadata`, telling apart proofs of POS data initialized differently.
+fn metadata_hash(metadata: &PostMetadata) -> eyre::Result<[u8; 32]> {
+    Ok(blake3::hash(&serde_json::to_vec(metadata)?).into())
+}
+
+#[cfg(test)]
+mod tests {
+    use tempfile::tempdir;
+
+    use super::*;
+    use crate::prove::ProofVersion;
+
+    fn proof(nonce: u32) -> Proof<'static> {
+        Proof {
+            version: ProofVersion::V1,
+            nonce,
+            indices: vec![1, 2, 3].into(),
+            pow: 7,
+        }
+    }
+
+    #[test]
+    fn caching_proofs() {
+        let dir = tempdir().unwrap();
+        let cache = ProofCache::new(&dir.path().join("proofs")).unwrap();
+        let metadata = PostMetadata {
+            node_id: [1; 32],
+            num_units: 4,
+            ..Default::default()
+        };
+
+        assert!(cache.load(&[0; 32], &metadata).unwrap().is_none());
+        cache.store(&[0; 32], &metadata, &proof(1)).unwrap();
+        cache.store(&[1; 32], &metadata, &proof(2)).unwrap();
+        assert_eq!(Some(proof(1)), cache.load(&[0; 32], &metadata).unwrap());
+        assert_eq!(Some(proof(2)), cache.load(&[1; 32], &metadata).unwrap());
+
+        // Other POS data
+        let other = PostMetadata {
+            num_units: 5,
+            ..metadata
+        };
+        assert!(cache.load(&[0; 32], &other).unwrap().is_none());
+
+        cache.remove(&[0; 32], &metadata).unwrap();
+        assert!(cache.load(&[0; 32], &metadata).unwrap().is_none());
+        assert!(cache.load(&[1; 32], &metadata).unwrap().is_some());
+    }
+
+    #[test]
+    fn rejecting_invalid_proofs() {
+        let dir = tempdir().unwrap();
+        let cache = ProofCache::new(dir.path()).unwrap();
+        let metadata = PostMetadata::default();
+        cache.store(&[0; 32], &metadata, &proof(1)).unwrap();
+        let path = std::fs::read_dir(dir.path())
+            .unwrap()
+            .next()
+            .unwrap()
+            .unwrap()
+            .path();
+
+        // Cached under another key
+        let content = std::fs::read_to_string(&path).unwrap();
+        let cached: CachedProof = serde_json::from_str(&content).unwrap();
+        let moved = CachedProof {
+            challenge: [9; 32],
+            ..cached
+        };
+        std::fs::write(&path, serde_json::to_vec(&moved).unwrap()).unwrap();
+        assert!(cache.load(&[0; 32], &metadata).is_err());
+
+        // Corrupted
+        std::fs::write(&path, "{").unwrap();
+        assert!(cache.load(&[0; 32], &metadata).is_err());
+    }
+
+    #[test]
+    fn pruning_proofs() {
+        let dir = tempdir().unwrap();
+        let cache = ProofCache::new(dir.path()).unwrap();
+        let metadata = PostMetadata::default();
+        for challenge in 0..3 {
+            cache.store(&[challenge; 32], &metadata, &proof(1)).unwrap();
+            // Tell the files apart by their modification times
+            std::thread::sleep(std::time::Duration::from_millis(10));
+        }
+        std::fs::write(dir.path().join("other.json"), "").unwrap();
+
+        cache.prune(2).unwrap();
+        assert!(cache.load(&[0; 32], &metadata).unwrap().is_none());
+        assert!(cache.load(&[1; 32], &metadata).unwrap().is_some());
+        assert!(cache.load(&[2; 32], &metadata).unwrap().is_some());
+        assert!(dir.path().join("other.json").exists());
+    }
+}
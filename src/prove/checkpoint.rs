@@ -0,0 +1,192 @@
+//! Checkpointing proof generation to resume it after an interruption.
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    fs::File,
+    io::BufReader,
+    ops::Range,
+    path::Path,
+};
+
+use serde::{Deserialize, Serialize};
+use serde_with::{base64::Base64, serde_as};
+
+use crate::metadata;
+
+const CHECKPOINT_FILE_NAME: &str = "proving_checkpoint.json";
+const CHECKPOINT_TMP_FILE_NAME: &str = "proving_checkpoint.json.tmp";
+
+/// State of an interrupted proof generation.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct ProvingCheckpoint {
+    /// Position (in bytes) up to which the POS data was read entirely.
+    pub position: u64,
+    /// Progress of every challenge proven in the interrupted pass over the POS data.
+    pub challenges: Vec<ChallengeCheckpoint>,
+}
+
+/// Progress of a challenge in an interrupted pass over the POS data.
+#[serde_as]
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct ChallengeCheckpoint {
+    #[serde_as(as = "Base64")]
+    pub challenge: [u8; 32],
+    /// Nonces tried in the interrupted pass over the POS data.
+    pub nonces: Range<u32>,
+    /// K2 proofs of work of the nonce groups of `nonces`.
+    pub pows: Vec<u64>,
+    /// Indices of labels (below the position of the checkpoint) passing the difficulty,
+    /// for every nonce.
+    pub indices: BTreeMap<u32, Vec<u64>>,
+}
+
+impl ProvingCheckpoint {
+    /// Load the checkpoint from the `datadir` if it exists.
+    pub fn load(datadir: &Path) -> eyre::Result<Option<Self>> {
+        let path = datadir.join(CHECKPOINT_FILE_NAME);
+        if !path.exists() {
+            return Ok(None);
+        }
+        let reader = BufReader::new(File::open(path)?);
+        Ok(Some(serde_json::from_reader(reader)?))
+    }
+
+    /// Save the checkpoint in the `datadir`, replacing the previous one atomically.
+    pub fn save(&self, datadir: &Path) -> eyre::Result<()> {
+        metadata::replace_file(
+            datadir,
+            CHECKPOINT_FILE_NAME,
+            CHECKPOINT_TMP_FILE_NAME,
+            |tmp_file| Ok(serde_json::to_writer(tmp_file, self)?),
+        )
+    }
+
+    /// Remove the checkpoint from the `datadir` (if it exists).
+    pub fn remove(datadir: &Path) -> std::io::Result<()> {
+        match std::fs::remove_file(datadir.join(CHECKPOINT_FILE_NAME)) {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e),
+            _ => Ok(()),
+        }
+    }
+
+    /// The progress of the `challenge` (if it's checkpointed).
+    pub fn challenge(&self, challenge: &[u8; 32]) -> Option<&ChallengeCheckpoint> {
+        self.challenges.iter().find(|c| c.challenge == *challenge)
+    }
+}
+
+impl ChallengeCheckpoint {
+    /// Create a checkpoint of the challenge at `position`, keeping only indices below it.
+    pub(crate) fn new(
+        challenge: [u8; 32],
+        nonces: Range<u32>,
+        pows: Vec<u64>,
+        position: u64,
+        indices: &HashMap<u32, Vec<u64>>,
+    ) -> Self {
+        let below = position / super::LABEL_SIZE as u64;
+        let indices = indices
+            .iter()
+            .map(|(&nonce, indices)| {
+                let kept: Vec<u64> = indices.iter().copied().filter(|&i| i < below).collect();
+                (nonce, kept)
+            })
+            .filter(|(_, indices)| !indices.is_empty())
+            .collect();
+        Self {
+            challenge,
+            nonces,
+            pows,
+            indices,
+        }
+    }
+}
+
+/// Tracks the position up to which the data was read entirely,
+/// while batches of it are processed out of order.
+#[derive(Debug, Default)]
+pub(crate) struct ReadProgress {
+    position: u64,
+    /// Ranges processed after a gap, by their starts.
+    done: BTreeMap<u64, u64>,
+}
+
+impl ReadProgress {
+    pub(crate) fn new(position: u64) -> Self {
+        Self {
+            position,
+            done: BTreeMap::new(),
+        }
+    }
+
+    pub(crate) fn complete(&mut self, range: Range<u64>) {
+        self.done.insert(range.start, range.end);
+        while let Some(end) = self.done.remove(&self.position) {
+            self.position = end;
+        }
+    }
+
+    pub(crate) fn position(&self) -> u64 {
+        self.position
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tracking_read_progress() {
+        let mut progress = ReadProgress::new(16);
+        progress.complete(32..48);
+        assert_eq!(16, progress.position());
+        progress.complete(16..32);
+        assert_eq!(48, progress.position());
+        progress.complete(64..80);
+        progress.complete(48..64);
+        assert_eq!(80, progress.position());
+    }
+
+    #[test]
+    fn checkpoint_keeps_indices_below_position() {
+        let indices = HashMap::from([(1, vec![0, 5, 10]), (2, vec![12]), (3, vec![1])]);
+        let checkpoint = ChallengeCheckpoint::new([0; 32], 0..16, vec![7], 16 * 10, &indices);
+        assert_eq!(
+            BTreeMap::from([(1, vec![0, 5]), (3, vec![1])]),
+            checkpoint.indices
+        );
+    }
+
+    #[test]
+    fn saving_and_loading() {
+        let datadir = tempfile::tempdir().unwrap();
+        assert!(ProvingCheckpoint::load(datadir.path()).unwrap().is_none());
+
+        let checkpoint = ProvingCheckpoint {
+            position: 1024,
+            challenges: vec![
+                ChallengeCheckpoint {
+                    challenge: [1; 32],
+                    nonces: 16..32,
+                    pows: vec![1],
+                    indices: BTreeMap::from([(17, vec![1, 2, 3])]),
+                },
+                ChallengeCheckpoint {
+                    challenge: [2; 32],
+                    nonces: 0..16,
+                    pows: vec![2],
+                    indices: BTreeMap::new(),
+                },
+            ],
+        };
+        checkpoint.save(datadir.path()).unwrap();
+        let loaded = ProvingCheckpoint::load(datadir.path()).unwrap().unwrap();
+        assert_eq!(checkpoint, loaded);
+        assert_eq!(Some(&checkpoint.challenges[1]), loaded.challenge(&[2; 32]));
+        assert!(loaded.challenge(&[3; 32]).is_none());
+
+        ProvingCheckpoint::remove(datadir.path()).unwrap();
+        ProvingCheckpoint::remove(datadir.path()).unwrap();
+        assert!(ProvingCheckpoint::load(datadir.path()).unwrap().is_none());
+    }
+}
@@ -0,0 +1,163 @@
+//! Verifying the POS data files against their checksums while proving.
+//!
+//! The initializer records a BLAKE3 checksum of every POS data file in the metadata.
+//! A pass reads the files anyway, so the data read is hashed on the way
+//! and every file read whole is compared against its checksum. Corrupted files
+//! are then found while proving rather than when the proof fails verification.
+//!
+//! The files are hashed in the order they are read, on the thread reading them.
+
+use std::{collections::HashMap, path::Path};
+
+use super::ProvingSettings;
+use crate::{
+    metadata::{self, PostMetadata},
+    reader::Batch,
+};
+
+/// Hashes the POS data read in a pass and compares the files read whole
+/// against their checksums.
+pub(crate) struct ChecksumVerifier {
+    metadata: PostMetadata,
+    checksums: Vec<[u8; 32]>,
+    /// Files being hashed, by file ID.
+    files: HashMap<u64, FileHasher>,
+    /// IDs of the files found corrupted in the pass.
+    corrupted: Vec<u64>,
+}
+
+struct FileHasher {
+    hasher: blake3::Hasher,
+    /// Offset in the file of the next byte to hash.
+    offset: u64,
+}
+
+impl ChecksumVerifier {
+    /// Create a verifier of the POS data in the `datadir`.
+    /// Returns `None` if there is nothing to verify.
+    pub(crate) fn load(
+        datadir: &Path,
+        metadata: &PostMetadata,
+        settings: &ProvingSettings,
+    ) -> eyre::Result<Option<Self>> {
+        if settings.source.is_some() {
+            log::warn!("not verifying checksums of labels read from a label source");
+            return Ok(None);
+        }
+        let checksums = metadata::load_checksums(datadir)?;
+        if checksums.is_empty() {
+            log::warn!("not verifying checksums, none are recorded in the metadata");
+            return Ok(None);
+        }
+        Ok(Some(Self::new(*metadata, checksums)))
+    }
+
+    fn new(metadata: PostMetadata, checksums: Vec<[u8; 32]>) -> Self {
+        Self {
+            metadata,
+            checksums,
+            files: HashMap::new(),
+            corrupted: Vec::new(),
+        }
+    }
+
+    /// Hash the `batch` of POS data, as read (before decrypting it).
+    pub(crate) fn update(&mut self, batch: &Batch) {
+        let file_size = self.metadata.max_file_size;
+        let id = batch.pos / file_size;
+        let offset = batch.pos % file_size;
+        if offset == 0 && (id as usize) < self.checksums.len() {
+            let hasher = blake3::Hasher::new();
+            self.files.insert(id, FileHasher { hasher, offset });
+        }
+        let Some(file) = self.files.get_mut(&id) else {
+            return;
+        };
+        if file.offset != offset {
+            // Part of the file was skipped, it can't be verified.
+            self.files.remove(&id);
+            return;
+        }
+        file.hasher.update(&batch.data);
+        file.offset += batch.data.len() as u64;
+
+        let size = file_size.min(self.metadata.total_size() - id * file_size);
+        if file.offset >= size {
+            let file = self.files.remove(&id).unwrap();
+            let name = self.metadata.file_name(id as usize);
+            if *file.hasher.finalize().as_bytes() == self.checksums[id as usize] {
+                log::debug!("verified checksum of {name}");
+            } else {
+                log::error!("POS data file {name} is corrupted, its checksum doesn't match");
+                self.corrupted.push(id);
+            }
+        }
+    }
+
+    /// Finish the pass, returning the names of the files found corrupted in it.
+    pub(crate) fn finish_pass(&mut self) -> Vec<String> {
+        self.files.clear();
+        self.corrupted.sort_unstable();
+        self.corrupted
+            .drain(..)
+            .map(|id| self.metadata.file_name(id as usize))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::{thread_rng, RngCore};
+
+    use super::*;
+
+    fn verifier(data: &[u8], file_size: u64) -> ChecksumVerifier {
+        let metadata = PostMetadata {
+            labels_per_unit: data.len() as u64 / 16,
+            num_units: 1,
+            max_file_size: file_size,
+            ..Default::default()
+        };
+        let checksums = data
+            .chunks(file_size as usize)
+            .map(|file| blake3::hash(file).into())
+            .collect();
+        ChecksumVerifier::new(metadata, checksums)
+    }
+
+    fn read(verifier: &mut ChecksumVerifier, data: &[u8], range: std::ops::Range<usize>) {
+        let file_size = verifier.metadata.max_file_size as usize;
+        for pos in range.step_by(32) {
+            let end = (pos + 32)
+                .min(data.len())
+                .min((pos / file_size + 1) * file_size);
+            verifier.update(&Batch {
+                data: data[pos..end].to_vec(),
+                pos: pos as u64,
+            });
+        }
+    }
+
+    #[test]
+    fn verifying_checksums() {
+        let mut data = vec![0u8; 5 * 64];
+        thread_rng().fill_bytes(&mut data);
+        let mut verifier = verifier(&data, 128);
+        read(&mut verifier, &data, 0..data.len());
+        assert!(verifier.finish_pass().is_empty());
+
+        let checksums = verifier.checksums.clone();
+        data[130] ^= 1;
+        data[300] ^= 1;
+        let mut verifier = ChecksumVerifier::new(verifier.metadata, checksums);
+        read(&mut verifier, &data, 0..data.len());
+        assert_eq!(
+            vec!["postdata_1.bin", "postdata_2.bin"],
+            verifier.finish_pass()
+        );
+
+        // Files not read whole are not verified.
+        read(&mut verifier, &data, 160..data.len());
+        assert_eq!(vec!["postdata_2.bin"], verifier.finish_pass());
+    }
+}
@@ -0,0 +1,77 @@
+//! Giving up proof generation which can't finish before a deadline.
+
+use std::{
+    sync::atomic::{AtomicU64, Ordering},
+    time::{Duration, Instant},
+};
+
+/// Time to measure the throughput for before estimating the end of a pass.
+const MIN_ESTIMATION_TIME: Duration = Duration::from_secs(10);
+
+/// The proof generation is estimated to not finish before the deadline.
+#[derive(Debug, thiserror::Error)]
+#[error("proof generation can't finish before the deadline")]
+pub struct DeadlineExceeded;
+
+/// Estimates when a pass over the POS data finishes from the throughput so far.
+#[derive(Debug)]
+pub(crate) struct PassEstimator {
+    start: Instant,
+    /// Bytes to read in the pass.
+    total: u64,
+    read: AtomicU64,
+}
+
+impl PassEstimator {
+    pub(crate) fn new(total: u64) -> Self {
+        Self {
+            start: Instant::now(),
+            total,
+            read: AtomicU64::new(0),
+        }
+    }
+
+    pub(crate) fn record(&self, bytes: u64) {
+        self.read.fetch_add(bytes, Ordering::Relaxed);
+    }
+
+    /// Estimated end of the pass, after the throughput was measured long enough.
+    pub(crate) fn estimated_end(&self) -> Option<Instant> {
+        self.estimated_end_at(Instant::now())
+    }
+
+    fn estimated_end_at(&self, now: Instant) -> Option<Instant> {
+        let elapsed = now.duration_since(self.start);
+        let read = self.read.load(Ordering::Relaxed);
+        if elapsed < MIN_ESTIMATION_TIME || read == 0 {
+            return None;
+        }
+        let remaining = self.total.saturating_sub(read);
+        Some(now + elapsed.mul_f64(remaining as f64 / read as f64))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn estimating_end_of_pass() {
+        let estimator = PassEstimator::new(1000);
+        let start = estimator.start;
+        estimator.record(100);
+        assert_eq!(
+            None,
+            estimator.estimated_end_at(start + Duration::from_secs(1))
+        );
+
+        let now = start + Duration::from_secs(20);
+        assert_eq!(
+            Some(now + Duration::from_secs(180)),
+            estimator.estimated_end_at(now)
+        );
+
+        estimator.record(900);
+        assert_eq!(Some(now), estimator.estimated_end_at(now));
+    }
+}
@@ -0,0 +1,114 @@
+//! Challenges joining a running proof generation.
+
+use std::sync::Mutex;
+
+/// Challenges requested while a proof generation is running, proven along with
+/// its challenges instead of waiting for it to finish.
+///
+/// They join the next pass over the POS data (a pass resumed from a checkpoint
+/// doesn't read all the data, so they wait for the one after it). Their proofs are
+/// returned after the proofs of the challenges the proof generation started with,
+/// in the order of [JoiningChallenges::joined].
+#[derive(Debug, Default)]
+pub struct JoiningChallenges(Mutex<JoinState>);
+
+#[derive(Debug, Default)]
+struct JoinState {
+    /// Challenges waiting for the next pass.
+    pending: Vec<[u8; 32]>,
+    joined: Vec<[u8; 32]>,
+    /// The proof generation is finished, no more challenges can join.
+    closed: bool,
+}
+
+impl JoiningChallenges {
+    /// Add the `challenge` to the running proof generation.
+    /// Returns `false` if it's finished, the challenge must be proven by another one then.
+    pub fn push(&self, challenge: [u8; 32]) -> bool {
+        let mut state = self.0.lock().unwrap();
+        if state.closed {
+            return false;
+        }
+        if !state.pending.contains(&challenge) && !state.joined.contains(&challenge) {
+            state.pending.push(challenge);
+        }
+        true
+    }
+
+    /// The challenges which joined the proof generation.
+    pub fn joined(&self) -> Vec<[u8; 32]> {
+        self.0.lock().unwrap().joined.clone()
+    }
+
+    /// Stop accepting challenges, i.e. once the proof generation failed.
+    /// Returns the challenges which didn't join it yet.
+    pub fn close(&self) -> Vec<[u8; 32]> {
+        let mut state = self.0.lock().unwrap();
+        state.closed = true;
+        std::mem::take(&mut state.pending)
+    }
+
+    /// Take the challenges waiting to join, except those among `proving`.
+    pub(crate) fn take(&self, proving: &[[u8; 32]]) -> Vec<[u8; 32]> {
+        let mut state = self.0.lock().unwrap();
+        let pending = std::mem::take(&mut state.pending);
+        let taken: Vec<_> = pending
+            .into_iter()
+            .filter(|c| !proving.contains(c))
+            .collect();
+        state.joined.extend(&taken);
+        taken
+    }
+
+    /// Close it unless challenges other than those among `proving` are waiting to join
+    /// (those are dropped, as [JoiningChallenges::take] would do).
+    /// Returns `true` if closed, i.e. the proof generation can finish.
+    pub(crate) fn close_if_empty(&self, proving: &[[u8; 32]]) -> bool {
+        let mut state = self.0.lock().unwrap();
+        state.pending.retain(|c| !proving.contains(c));
+        state.closed = state.pending.is_empty();
+        state.closed
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn joining_until_closed() {
+        let joining = JoiningChallenges::default();
+        assert!(joining.push([1; 32]));
+        assert!(joining.push([2; 32]));
+        assert!(joining.push([1; 32]));
+        // Already proven challenges don't join twice.
+        assert_eq!(vec![[2; 32]], joining.take(&[[1; 32]]));
+        assert_eq!(vec![[2; 32]], joining.joined());
+        assert!(joining.close_if_empty(&[[1; 32], [2; 32]]));
+
+        assert!(!joining.push([3; 32]));
+        assert!(joining.close().is_empty());
+    }
+
+    #[test]
+    fn not_closed_with_pending_challenges() {
+        let joining = JoiningChallenges::default();
+        assert!(joining.push([1; 32]));
+        assert!(!joining.close_if_empty(&[]));
+        assert!(joining.push([2; 32]));
+        assert_eq!(vec![[1; 32], [2; 32]], joining.close());
+        assert!(joining.joined().is_empty());
+    }
+
+    #[test]
+    fn closed_with_only_proving_challenges_pending() {
+        let joining = JoiningChallenges::default();
+        assert!(joining.push([2; 32]));
+        assert_eq!(vec![[2; 32]], joining.take(&[[1; 32]]));
+        // Pushed again after joining the last pass.
+        assert!(joining.push([1; 32]));
+        assert!(joining.close_if_empty(&[[1; 32], [2; 32]]));
+        assert!(joining.close().is_empty());
+        assert_eq!(vec![[2; 32]], joining.joined());
+    }
+}
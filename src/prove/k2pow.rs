@@ -0,0 +1,133 @@
+//! Calculating the k2pow of the next pass in the background.
+//!
+//! The k2pow is CPU bound, while the passes over the POS data are usually
+//! bound by the disks. The proofs of work for the nonces of the next pass
+//! are thus calculated while the current pass reads the POS data, so that
+//! the next pass (if needed) doesn't wait for them.
+
+use std::{ops::Range, sync::Arc, thread::JoinHandle};
+
+use eyre::Context;
+use rayon::ThreadPool;
+
+use super::{Prover8_56, ProvingParams};
+use crate::{cancel::CancellationToken, pow};
+
+/// Proofs of work for the nonce groups of `nonces`, calculated on a separate thread.
+///
+/// Dropping it cancels the calculation.
+pub(crate) struct BackgroundPows {
+    nonces: Range<u32>,
+    handle: Option<JoinHandle<eyre::Result<Vec<u64>>>>,
+    cancel: CancellationToken,
+}
+
+impl BackgroundPows {
+    /// Start calculating the proofs of work for the `nonces` on the `pool`,
+    /// until done or `cancel` is cancelled.
+    pub(crate) fn spawn(
+        pool: Arc<ThreadPool>,
+        pow_prover: Arc<dyn pow::Prover + Send + Sync>,
+        challenge: [u8; 32],
+        nonces: Range<u32>,
+        params: ProvingParams,
+        miner_id: [u8; 32],
+        cancel: CancellationToken,
+    ) -> eyre::Result<Self> {
+        let handle = std::thread::Builder::new()
+            .name("post-k2pow".into())
+            .spawn({
+                let nonces = nonces.clone();
+                let cancel = cancel.clone();
+                move || {
+                    pool.install(|| {
+                        Prover8_56::calculate_pows(
+                            &challenge,
+                            nonces,
+                            params,
+                            pow_prover.as_ref(),
+                            &miner_id,
+                            &cancel,
+                        )
+                    })
+                }
+            })
+            .wrap_err("spawning k2pow thread")?;
+        Ok(Self {
+            nonces,
+            handle: Some(handle),
+            cancel,
+        })
+    }
+
+    pub(crate) fn nonces(&self) -> &Range<u32> {
+        &self.nonces
+    }
+
+    /// Wait for the proofs of work.
+    pub(crate) fn join(mut self) -> eyre::Result<Vec<u64>> {
+        self.handle
+            .take()
+            .unwrap()
+            .join()
+            .map_err(|_| eyre::eyre!("k2pow thread panicked"))?
+    }
+}
+
+impl Drop for BackgroundPows {
+    fn drop(&mut self) {
+        if self.handle.is_some() {
+            self.cancel.cancel("k2pow is not needed anymore");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use randomx_rs::RandomXFlag;
+
+    use super::*;
+    use crate::{
+        config::ProofConfig,
+        metadata::PostMetadata,
+        pow::{randomx::PoW, PowVerifier},
+    };
+
+    #[test]
+    fn calculating_pows_in_background() {
+        let metadata = PostMetadata {
+            num_units: 1,
+            labels_per_unit: 1000,
+            ..Default::default()
+        };
+        let cfg = ProofConfig {
+            k1: 10,
+            k2: 10,
+            k3: 10,
+            pow_difficulty: [0xFF; 32],
+        };
+        let params = ProvingParams::new(&metadata, &cfg).unwrap();
+        let pow_prover = Arc::new(PoW::new(RandomXFlag::get_recommended_flags()).unwrap());
+        let pool = Arc::new(rayon::ThreadPoolBuilder::new().build().unwrap());
+
+        let pows = BackgroundPows::spawn(
+            pool,
+            pow_prover.clone(),
+            [7; 32],
+            16..48,
+            params,
+            [1; 32],
+            CancellationToken::new(),
+        )
+        .unwrap();
+        assert_eq!(&(16..48), pows.nonces());
+
+        let pows = pows.join().unwrap();
+        assert_eq!(2, pows.len());
+        for (nonce_group, pow) in (1..3).zip(pows) {
+            pow_prover
+                .verify(pow, nonce_group, &[7; 8], &params.pow_difficulty, &[1; 32])
+                .unwrap();
+        }
+    }
+}
@@ -0,0 +1,292 @@
+//! Bounding the memory used by the proof generation.
+//!
+//! The memory goes to the batches of POS data in flight (queued or being checked)
+//! and to the indices of labels passing the difficulty, collected for every nonce
+//! until one of them has `k2` of them. With a memory budget, the batches are made
+//! smaller to fit half of it and the indices exceeding the rest are spilled
+//! to a temporary file.
+
+use std::{
+    collections::HashMap,
+    fs::File,
+    io::{self, BufReader, BufWriter, Read, Seek, SeekFrom, Write},
+};
+
+use super::{ProvingSettings, CHUNK_SIZE};
+
+/// Memory (in bytes) taken by an index kept in memory, with the overhead of its `Vec`.
+const INDEX_MEMORY: usize = 16;
+/// Size (in bytes) of a (nonce, index) record in the spill file.
+const RECORD_SIZE: usize = 4 + 8;
+
+/// Fit the batches of POS data in flight on `threads` workers into half of the `budget`
+/// (in bytes) by reducing the read chunk size and then the read ahead.
+///
+/// Returns the adapted settings and the number of indices to keep in memory
+/// with the rest of the budget.
+pub(crate) fn fit_budget<'a>(
+    settings: &ProvingSettings<'a>,
+    budget: usize,
+    threads: usize,
+) -> eyre::Result<(ProvingSettings<'a>, usize)> {
+    let batches_budget = budget / 2;
+    // Queued batches, batches being checked and the one being read.
+    let in_flight = |read_ahead: usize| read_ahead + threads + 1;
+
+    let mut read_chunk_size = settings.read_chunk_size;
+    let mut read_ahead = settings.read_ahead;
+    while in_flight(read_ahead) * read_chunk_size > batches_budget && read_chunk_size > CHUNK_SIZE {
+        read_chunk_size = (read_chunk_size / 2 / CHUNK_SIZE).max(1) * CHUNK_SIZE;
+    }
+    if in_flight(read_ahead) * read_chunk_size > batches_budget {
+        read_ahead = (batches_budget / read_chunk_size).saturating_sub(threads + 1);
+    }
+    let batches_memory = in_flight(read_ahead) * read_chunk_size;
+    eyre::ensure!(
+        batches_memory <= batches_budget,
+        "memory budget of {budget} bytes is too small for {threads} threads"
+    );
+    if (read_chunk_size, read_ahead) != (settings.read_chunk_size, settings.read_ahead) {
+        log::info!(
+            "reading POS data in chunks of {read_chunk_size} bytes, {read_ahead} ahead, to fit the memory budget"
+        );
+    }
+    let settings = ProvingSettings {
+        read_chunk_size,
+        read_ahead,
+        ..*settings
+    };
+    Ok((settings, (budget - batches_memory) / INDEX_MEMORY))
+}
+
+/// Indices of labels passing the difficulty, for every nonce of a pass.
+///
+/// Once more than the limit of them are kept in memory, they are all moved
+/// to a temporary file and read back for the nonce reaching `k2` indices.
+#[derive(Debug)]
+pub(crate) struct IndexStore {
+    k2: usize,
+    memory_limit: Option<usize>,
+    indices: HashMap<u32, Vec<u64>>,
+    in_memory: usize,
+    /// Spill file and the number of indices in it for every nonce.
+    spilled: Option<(BufWriter<File>, HashMap<u32, usize>)>,
+    /// Size of the records in the spill file, without those written by a failed spill.
+    spilled_size: u64,
+}
+
+impl IndexStore {
+    /// Create a store keeping at most `memory_limit` indices in memory (if set).
+    pub(crate) fn new(
+        k2: usize,
+        memory_limit: Option<usize>,
+        indices: HashMap<u32, Vec<u64>>,
+    ) -> Self {
+        let in_memory = indices.values().map(Vec::len).sum();
+        Self {
+            k2,
+            memory_limit,
+            indices,
+            in_memory,
+            spilled: None,
+            spilled_size: 0,
+        }
+    }
+
+    /// Add an `index` of the `nonce`.
+    /// Returns all indices of the nonce once it has `k2` of them.
+    pub(crate) fn push(&mut self, nonce: u32, index: u64) -> Option<Vec<u64>> {
+        let vec = self.indices.entry(nonce).or_default();
+        vec.push(index);
+        self.in_memory += 1;
+        let in_memory = vec.len();
+        let spilled = self
+            .spilled
+            .as_ref()
+            .and_then(|(_, counts)| counts.get(&nonce).copied())
+            .unwrap_or_default();
+        if in_memory + spilled >= self.k2 {
+            return match self.take(nonce) {
+                Ok(indices) => Some(indices),
+                Err(e) => {
+                    log::error!("failed to read spilled indices of nonce {nonce}: {e}");
+                    None
+                }
+            };
+        }
+        if matches!(self.memory_limit, Some(limit) if self.in_memory > limit) {
+            if let Err(e) = self.spill() {
+                log::warn!("failed to spill indices to disk, keeping them in memory: {e}");
+                self.memory_limit = None;
+            }
+        }
+        None
+    }
+
+    /// All indices of every nonce.
+    pub(crate) fn all(&mut self) -> io::Result<HashMap<u32, Vec<u64>>> {
+        let mut all = HashMap::<u32, Vec<u64>>::new();
+        self.read_spilled(|nonce, index| all.entry(nonce).or_default().push(index))?;
+        for (nonce, indices) in &self.indices {
+            all.entry(*nonce).or_default().extend(indices);
+        }
+        Ok(all)
+    }
+
+    /// Remove and return the indices of the `nonce`.
+    fn take(&mut self, nonce: u32) -> io::Result<Vec<u64>> {
+        let mut indices = Vec::with_capacity(self.k2);
+        self.read_spilled(|n, index| {
+            if n == nonce {
+                indices.push(index);
+            }
+        })?;
+        if let Some((_, counts)) = &mut self.spilled {
+            counts.remove(&nonce);
+        }
+        let in_memory = self.indices.remove(&nonce).unwrap_or_default();
+        self.in_memory -= in_memory.len();
+        indices.extend(in_memory);
+        Ok(indices)
+    }
+
+    /// Move the indices in memory to the spill file.
+    /// If that fails, they are all kept in memory.
+    fn spill(&mut self) -> io::Result<()> {
+        if self.spilled.is_none() {
+            self.spilled = Some((BufWriter::new(tempfile::tempfile()?), HashMap::new()));
+        }
+        let (file, counts) = self.spilled.as_mut().unwrap();
+        // Overwrite the records of a failed spill.
+        file.seek(SeekFrom::Start(self.spilled_size))?;
+        for (nonce, indices) in &self.indices {
+            for index in indices {
+                file.write_all(&nonce.to_le_bytes())?;
+                file.write_all(&index.to_le_bytes())?;
+            }
+        }
+        file.flush()?;
+        for (nonce, indices) in self.indices.drain() {
+            *counts.entry(nonce).or_default() += indices.len();
+        }
+        log::debug!("spilled {} indices to disk", self.in_memory);
+        self.spilled_size += (self.in_memory * RECORD_SIZE) as u64;
+        self.in_memory = 0;
+        Ok(())
+    }
+
+    /// Call `f` for every spilled (nonce, index) of the nonces not taken yet,
+    /// in the order they were spilled.
+    fn read_spilled(&mut self, mut f: impl FnMut(u32, u64)) -> io::Result<()> {
+        let Some((file, counts)) = &mut self.spilled else {
+            return Ok(());
+        };
+        // Every spill is flushed, the records are all in the file.
+        let file = file.get_mut();
+        file.seek(SeekFrom::Start(0))?;
+        let mut record = [0u8; RECORD_SIZE];
+        let mut reader = BufReader::new(&*file).take(self.spilled_size);
+        let result = loop {
+            match reader.read_exact(&mut record) {
+                Ok(()) => {
+                    let (nonce, index) = record.split_at(4);
+                    let nonce = u32::from_le_bytes(nonce.try_into().unwrap());
+                    if counts.contains_key(&nonce) {
+                        f(nonce, u64::from_le_bytes(index.try_into().unwrap()));
+                    }
+                }
+                Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => break Ok(()),
+                Err(e) => break Err(e),
+            }
+        };
+        drop(reader);
+        file.seek(SeekFrom::Start(self.spilled_size))?;
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fitting_budget() {
+        let settings = ProvingSettings {
+            read_chunk_size: 1024 * 1024,
+            read_ahead: 8,
+            ..Default::default()
+        };
+        // Fits already
+        let (fitted, indices) = fit_budget(&settings, 100 * 1024 * 1024, 3).unwrap();
+        assert_eq!(1024 * 1024, fitted.read_chunk_size);
+        assert_eq!(8, fitted.read_ahead);
+        assert_eq!((100 - 12) * 1024 * 1024 / INDEX_MEMORY, indices);
+
+        // Smaller chunks
+        let (fitted, _) = fit_budget(&settings, 12 * 1024 * 1024, 3).unwrap();
+        assert_eq!(512 * 1024, fitted.read_chunk_size);
+        assert_eq!(8, fitted.read_ahead);
+
+        // Smallest chunks and less read ahead
+        let (fitted, _) = fit_budget(&settings, 12 * CHUNK_SIZE, 3).unwrap();
+        assert_eq!(CHUNK_SIZE, fitted.read_chunk_size);
+        assert_eq!(2, fitted.read_ahead);
+
+        assert!(fit_budget(&settings, 4 * CHUNK_SIZE, 3).is_err());
+    }
+
+    #[test]
+    fn collecting_indices_in_memory() {
+        let mut store = IndexStore::new(3, None, HashMap::from([(1, vec![10])]));
+        assert_eq!(None, store.push(1, 11));
+        assert_eq!(None, store.push(2, 20));
+        assert_eq!(Some(vec![10, 11, 12]), store.push(1, 12));
+        assert_eq!(HashMap::from([(2, vec![20])]), store.all().unwrap());
+    }
+
+    #[test]
+    fn spilling_indices() {
+        let mut store = IndexStore::new(4, Some(2), HashMap::new());
+        for index in 0..3 {
+            assert_eq!(None, store.push(1, index));
+            assert_eq!(None, store.push(2, 10 + index));
+        }
+        assert!(store.spilled.is_some());
+        assert!(store.in_memory <= 2);
+        assert_eq!(
+            HashMap::from([(1, vec![0, 1, 2]), (2, vec![10, 11, 12])]),
+            store.all().unwrap()
+        );
+
+        assert_eq!(Some(vec![10, 11, 12, 13]), store.push(2, 13));
+        assert_eq!(None, store.push(3, 30));
+        assert_eq!(
+            HashMap::from([(1, vec![0, 1, 2]), (3, vec![30])]),
+            store.all().unwrap()
+        );
+        assert_eq!(Some(vec![0, 1, 2, 3]), store.push(1, 3));
+    }
+
+    #[test]
+    fn ignoring_records_of_failed_spill() {
+        let mut store = IndexStore::new(4, Some(2), HashMap::new());
+        for index in 0..3 {
+            assert_eq!(None, store.push(1, index));
+        }
+        assert!(store.indices.is_empty());
+        // A spill of the index 7 of the nonce 1 failed after writing it partially.
+        let (file, _) = store.spilled.as_mut().unwrap();
+        file.write_all(&1u32.to_le_bytes()).unwrap();
+        file.write_all(&7u64.to_le_bytes()[..4]).unwrap();
+        file.flush().unwrap();
+        assert_eq!(HashMap::from([(1, vec![0, 1, 2])]), store.all().unwrap());
+
+        assert_eq!(None, store.push(2, 20));
+        assert_eq!(None, store.push(2, 21));
+        assert_eq!(None, store.push(2, 22));
+        assert_eq!(
+            HashMap::from([(1, vec![0, 1, 2]), (2, vec![20, 21, 22])]),
+            store.all().unwrap()
+        );
+    }
+}
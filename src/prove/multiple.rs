@@ -0,0 +1,500 @@
+//! Generating proofs for several challenges with a single pass over the POS data.
+//!
+//! Reading the POS data takes most of the proving time, so instead of proving
+//! the challenges one after another, every chunk read is checked against
+//! the provers of all challenges which don't have a proof yet.
+//! A single challenge is proven the same way, as the only one.
+
+use std::{
+    borrow::Borrow,
+    collections::HashMap,
+    ops::Range,
+    path::Path,
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+    time::Instant,
+};
+
+use eyre::Context;
+use randomx_rs::RandomXFlag;
+
+use super::{
+    affinity, check_nonce_groups, corrupted_files, load_metadata, memory, nonces, pipeline,
+    pow_prover, read_pos_data, scan_labels, scanned_range, verify_checksums, BackgroundPows,
+    Candidate, ChallengeCheckpoint, ChecksumVerifier, DeadlineExceeded, IndexStore, PassEstimator,
+    Proof, Prover, Prover8_56, ProvingCheckpoint, ProvingParams, ProvingSettings, ReadProgress,
+    BLOCK_SIZE,
+};
+use crate::{cancel::CancellationToken, config::ProofConfig};
+
+/// Proving state of a challenge across the passes over the POS data.
+struct ChallengeState {
+    challenge: [u8; 32],
+    /// Nonces to try in the next pass.
+    nonces: Range<u32>,
+    /// Proofs of work of the next pass, calculated while the current one reads the POS data.
+    next_pows: Option<BackgroundPows>,
+    /// Progress of the interrupted pass to resume.
+    resumed: Option<ChallengeCheckpoint>,
+    proof: Option<Proof<'static>>,
+}
+
+/// Proving state of a challenge during a pass over the POS data.
+struct ChallengePass {
+    /// Index of the [ChallengeState] of the challenge.
+    state: usize,
+    challenge: [u8; 32],
+    nonces: Range<u32>,
+    prover: Prover8_56,
+    /// Indexes of the prover's nonce group keys among the keys of all provers.
+    ciphers: Range<u32>,
+    indexes: Mutex<IndexStore>,
+    result: Mutex<Option<(u32, Vec<u64>)>>,
+}
+
+impl ChallengePass {
+    /// Check a batch of labels, unless the proof was already found.
+    /// Every index found is passed to `on_index` too.
+    /// Returns `true` if the proof was found in this batch.
+    fn prove(
+        &self,
+        batch: &[u8],
+        index: u64,
+        candidates: Option<&[Candidate]>,
+        on_index: &(dyn Fn(&[u8; 32], u32, u64) + Sync),
+    ) -> bool {
+        if self.result.lock().unwrap().is_some() {
+            return false;
+        }
+        let consume = |nonce, index| {
+            on_index(&self.challenge, nonce, index);
+            self.indexes.lock().unwrap().push(nonce, index)
+        };
+        let result = match candidates {
+            Some(candidates) => {
+                let own = candidates
+                    .iter()
+                    .filter(|c| self.ciphers.contains(&c.cipher))
+                    .map(|c| Candidate {
+                        cipher: c.cipher - self.ciphers.start,
+                        ..*c
+                    })
+                    .collect();
+                self.prover.prove_candidates(batch, index, own, consume)
+            }
+            None => self.prover.prove(batch, index, consume),
+        };
+        let Some(result) = result else {
+            return false;
+        };
+        let mut found = self.result.lock().unwrap();
+        if found.is_some() {
+            return false;
+        }
+        *found = Some(result);
+        true
+    }
+}
+
+/// Load the proving checkpoint from the `datadir`, keeping the progress of the `challenges`
+/// which tried `nonces` of them (with `0`, as many as the first one of them).
+fn load_checkpoint(
+    datadir: &Path,
+    challenges: &[[u8; 32]],
+    nonces: usize,
+) -> Option<ProvingCheckpoint> {
+    let mut checkpoint = match ProvingCheckpoint::load(datadir) {
+        Ok(checkpoint) => checkpoint?,
+        Err(e) => {
+            log::warn!("ignoring invalid proving checkpoint: {e:?}");
+            return None;
+        }
+    };
+    checkpoint
+        .challenges
+        .retain(|c| challenges.contains(&c.challenge));
+    // With automatic nonces, resume with the nonces of the checkpoint.
+    let nonces = match (nonces, checkpoint.challenges.first()) {
+        (0, Some(c)) => c.nonces.len(),
+        _ => nonces,
+    };
+    checkpoint.challenges.retain(|c| c.nonces.len() == nonces);
+    if checkpoint.challenges.is_empty() {
+        log::info!("ignoring proving checkpoint of other challenges");
+        return None;
+    }
+    for c in &checkpoint.challenges {
+        log::info!(
+            "resuming proof generation for challenge {:X?} with nonces {:?} at position {}",
+            c.challenge,
+            c.nonces,
+            checkpoint.position
+        );
+    }
+    Some(checkpoint)
+}
+
+/// Generate proofs that data is still held for all the `challenges`, sharing
+/// the reads of the POS data between them. The proofs are returned in the order
+/// of the challenges.
+///
+/// The challenges joining while proving (see [ProvingSettings::joining]) are proven too,
+/// their proofs follow those of the `challenges`.
+///
+/// With [ProvingSettings::checkpoint_interval], the progress of all the challenges
+/// is saved in one checkpoint. Only the checkpointed challenges take part in the resumed
+/// pass, the others join the passes after it.
+#[allow(clippy::too_many_arguments)]
+pub fn generate_proofs_with_settings<Cancel>(
+    datadir: &Path,
+    challenges: &[[u8; 32]],
+    cfg: ProofConfig,
+    nonces: usize,
+    threads: usize,
+    pow_flags: RandomXFlag,
+    cancel: Cancel,
+    settings: &ProvingSettings,
+) -> eyre::Result<Vec<Proof<'static>>>
+where
+    Cancel: Borrow<CancellationToken>,
+{
+    if challenges.is_empty() {
+        return Ok(Vec::new());
+    }
+    let ProvingSettings {
+        key,
+        checkpoint_interval,
+        scanner,
+        deadline,
+        stats,
+        index_hook,
+        joining,
+        ..
+    } = *settings;
+    let cancel = cancel.borrow();
+    let metadata = load_metadata(datadir, settings)?;
+    let params = ProvingParams::new(&metadata, &cfg)?;
+    log::info!(
+        "generating proofs for {} challenges with PoW flags: {pow_flags:?} and params: {params:?}",
+        challenges.len()
+    );
+    let pow_prover = pow_prover(settings, pow_flags)?;
+    let checkpoint = match checkpoint_interval {
+        Some(_) => load_checkpoint(datadir, challenges, nonces),
+        None => None,
+    };
+
+    let cores = affinity::select_cores(settings.cores, settings.numa, datadir)?;
+    let pool = affinity::thread_pool(threads, cores.as_deref(), "post-prove")?;
+    let pow_pool = Arc::new(affinity::pow_thread_pool(
+        settings.pow_threads.unwrap_or(threads),
+        cores.as_deref(),
+        settings.low_priority_pow,
+    )?);
+    let (settings, index_budget) = match settings.memory_budget {
+        Some(budget) => {
+            let (fitted, limit) = memory::fit_budget(settings, budget, pool.current_num_threads())?;
+            (fitted, Some(limit))
+        }
+        None => (*settings, None),
+    };
+    let nonces = match &checkpoint {
+        Some(checkpoint) => checkpoint.challenges[0].nonces.len(),
+        None if nonces == 0 => {
+            nonces::select(datadir, &metadata, &cfg, &settings, &pool, challenges.len())?
+        }
+        None => nonces,
+    };
+
+    let num_labels = metadata.num_units as u64 * metadata.labels_per_unit;
+    let range = scanned_range(&metadata, &settings);
+    let checksums = match settings.verify_checksums {
+        true => ChecksumVerifier::load(datadir, &metadata, &settings)?.map(Mutex::new),
+        false => None,
+    };
+    let mut states = challenges
+        .iter()
+        .map(|&challenge| {
+            let resumed = checkpoint
+                .as_ref()
+                .and_then(|c| c.challenge(&challenge))
+                .cloned();
+            ChallengeState {
+                challenge,
+                nonces: resumed
+                    .as_ref()
+                    .map_or(0..nonces as u32, |c| c.nonces.clone()),
+                next_pows: None,
+                resumed,
+                proof: None,
+            }
+        })
+        .collect::<Vec<_>>();
+    let mut position = checkpoint.map_or(range.start, |c| c.position);
+    let total_time = Instant::now();
+    let mut last_pass_time = None;
+    loop {
+        cancel.check()?;
+        if let Some(deadline) = deadline {
+            let pass_end = Instant::now() + last_pass_time.unwrap_or_default();
+            if pass_end >= deadline {
+                return Err(DeadlineExceeded.into());
+            }
+        }
+        let pass_time = Instant::now();
+
+        if let (Some(joining), true) = (joining, position == range.start) {
+            let proving: Vec<_> = states.iter().map(|state| state.challenge).collect();
+            for challenge in joining.take(&proving) {
+                log::info!("challenge {challenge:X?} joins the proof generation");
+                states.push(ChallengeState {
+                    challenge,
+                    nonces: 0..nonces as u32,
+                    next_pows: None,
+                    resumed: None,
+                    proof: None,
+                });
+            }
+        }
+
+        // The indices of all challenges share the budget.
+        let unproven = states.iter().filter(|state| state.proof.is_none()).count();
+        let index_limit = index_budget.map(|limit| limit / unproven);
+
+        let mut passes = Vec::new();
+        let mut keys = Vec::new();
+        for (idx, state) in states.iter_mut().enumerate() {
+            // A pass must read all the POS data, the challenges which are not resumed
+            // wait for the resumed pass to finish.
+            if state.proof.is_some() || (position != range.start && state.resumed.is_none()) {
+                continue;
+            }
+            let (prover, indexes) = match state.resumed.take() {
+                Some(checkpoint) => {
+                    let prover = Prover8_56::with_pows(
+                        &state.challenge,
+                        checkpoint.nonces,
+                        params,
+                        &checkpoint.pows,
+                    )
+                    .wrap_err("creating prover from checkpoint")?;
+                    (prover, checkpoint.indices.into_iter().collect())
+                }
+                None => {
+                    check_nonce_groups(&state.nonces, settings.wide_nonce_groups)?;
+                    let pows = match state.next_pows.take() {
+                        Some(pows) if *pows.nonces() == state.nonces => pows.join()?,
+                        _ => pow_pool.install(|| {
+                            Prover8_56::calculate_pows(
+                                &state.challenge,
+                                state.nonces.clone(),
+                                params,
+                                pow_prover.as_ref(),
+                                &metadata.node_id,
+                                cancel,
+                            )
+                        })?,
+                    };
+                    let prover = Prover8_56::with_pows(
+                        &state.challenge,
+                        state.nonces.clone(),
+                        params,
+                        &pows,
+                    )
+                    .wrap_err("creating prover")?;
+                    (prover, HashMap::new())
+                }
+            };
+            let first_cipher = keys.len() as u32;
+            keys.extend(prover.nonce_group_keys());
+            passes.push(ChallengePass {
+                state: idx,
+                challenge: state.challenge,
+                nonces: state.nonces.clone(),
+                prover,
+                ciphers: first_cipher..keys.len() as u32,
+                indexes: Mutex::new(IndexStore::new(cfg.k2 as usize, index_limit, indexes)),
+                result: Mutex::default(),
+            });
+        }
+        log::info!(
+            "Finished k2pow in {} minutes",
+            pass_time.elapsed().as_secs() / 60
+        );
+        if let Some(stats) = stats {
+            stats.lock().unwrap().start_pass(pass_time.elapsed());
+        }
+        // The k2pow of the next pass is calculated while this one reads the POS data.
+        for pass in &passes {
+            let next_nonces = pass.nonces.end..pass.nonces.end + nonces as u32;
+            states[pass.state].next_pows =
+                match check_nonce_groups(&next_nonces, settings.wide_nonce_groups) {
+                    Ok(()) => Some(BackgroundPows::spawn(
+                        pow_pool.clone(),
+                        pow_prover.clone(),
+                        pass.challenge,
+                        next_nonces,
+                        params,
+                        metadata.node_id,
+                        cancel.child(),
+                    )?),
+                    Err(_) => None,
+                };
+        }
+        // Without passes (every challenge is proven), reading stops right away.
+        if let (Some(scanner), Some(first)) = (scanner, passes.first()) {
+            scanner
+                .lock()
+                .unwrap()
+                .set_ciphers(&keys, first.prover.difficulty_msb())
+                .map_err(|e| eyre::eyre!("setting up label scanner: {e}"))?;
+        }
+        let progress = Mutex::new(ReadProgress::new(position));
+        let save_checkpoint = || {
+            let position = progress.lock().unwrap().position();
+            let mut challenges = Vec::with_capacity(passes.len());
+            for pass in &passes {
+                let indices = match pass.indexes.lock().unwrap().all() {
+                    Ok(indices) => indices,
+                    Err(e) => {
+                        log::warn!("failed to save proving checkpoint: {e:?}");
+                        return;
+                    }
+                };
+                challenges.push(ChallengeCheckpoint::new(
+                    pass.challenge,
+                    pass.nonces.clone(),
+                    pass.prover.pows(),
+                    position,
+                    &indices,
+                ));
+            }
+            let checkpoint = ProvingCheckpoint {
+                position,
+                challenges,
+            };
+            if let Err(e) = checkpoint.save(datadir) {
+                log::warn!("failed to save proving checkpoint: {e:?}");
+            }
+        };
+        if checkpoint_interval.is_some() {
+            save_checkpoint();
+        }
+        let last_checkpoint = Mutex::new(Instant::now());
+        // The indices per nonce are only recorded for a single challenge.
+        let single = passes.len() == 1;
+        let on_index = |challenge: &[u8; 32], nonce, index| {
+            if let (Some(stats), true) = (stats, single) {
+                stats.lock().unwrap().record_index(nonce);
+            }
+            if let Some(hook) = index_hook {
+                hook.on_challenge_index(challenge, nonce, index);
+            }
+        };
+        let pending = AtomicUsize::new(passes.len());
+        let estimator = PassEstimator::new(range.end.saturating_sub(position));
+        let deadline_exceeded = AtomicBool::new(false);
+
+        let read_time = Instant::now();
+        let data_reader = read_pos_data(datadir, &metadata, position, &settings)?
+            .inspect(|batch| verify_checksums(checksums.as_ref(), batch));
+        log::info!("Started reading POST data");
+        pipeline::process_batches(
+            &pool,
+            data_reader,
+            settings.read_ahead,
+            cores.as_deref(),
+            settings.throttle,
+            || {
+                !cancel.is_cancelled()
+                    && !deadline_exceeded.load(Ordering::Relaxed)
+                    && pending.load(Ordering::Relaxed) > 0
+            },
+            |mut batch| {
+                if let Some(key) = key {
+                    key.apply_keystream(&mut batch.data, batch.pos);
+                }
+                let index = batch.pos / BLOCK_SIZE as u64;
+                let candidates = scan_labels(scanner, &batch.data);
+                for pass in &passes {
+                    if pass.prove(&batch.data, index, candidates.as_deref(), &on_index) {
+                        pending.fetch_sub(1, Ordering::Relaxed);
+                    }
+                }
+                if let Some(stats) = stats {
+                    stats.lock().unwrap().record_batch(batch.data.len());
+                }
+                if pending.load(Ordering::Relaxed) == 0 {
+                    return None::<()>;
+                }
+                if let Some(deadline) = deadline {
+                    estimator.record(batch.data.len() as u64);
+                    if matches!(estimator.estimated_end(), Some(end) if end > deadline) {
+                        deadline_exceeded.store(true, Ordering::Relaxed);
+                    }
+                }
+                if let Some(interval) = checkpoint_interval {
+                    let end = batch.pos + batch.data.len() as u64;
+                    progress.lock().unwrap().complete(batch.pos..end);
+                    if let Ok(mut last) = last_checkpoint.try_lock() {
+                        if last.elapsed() >= interval {
+                            save_checkpoint();
+                            *last = Instant::now();
+                        }
+                    }
+                }
+                None
+            },
+        )?;
+
+        let read_mins = read_time.elapsed().as_secs() / 60;
+        log::info!("Finished reading POST data in {} minutes", read_mins);
+        let corrupted = corrupted_files(checksums.as_ref());
+        if checkpoint_interval.is_some() && cancel.is_cancelled() {
+            save_checkpoint();
+        }
+
+        for pass in passes {
+            let state = &mut states[pass.state];
+            match pass.result.into_inner().unwrap() {
+                Some((nonce, indices)) => {
+                    let pow = pass.prover.get_pow(nonce).unwrap();
+                    let total_minutes = total_time.elapsed().as_secs() / 60;
+                    log::info!("Found proof for challenge #{}: nonce: {nonce}, pow: {pow} with {indices:?} indices. Proof took {total_minutes} minutes", pass.state);
+                    state.proof = Some(Proof::new(nonce, &indices, num_labels, pow));
+                    state.next_pows = None;
+                }
+                None => state.nonces = pass.nonces.end..pass.nonces.end + nonces as u32,
+            }
+        }
+        let proving: Vec<_> = states.iter().map(|state| state.challenge).collect();
+        if states.iter().all(|state| state.proof.is_some())
+            && joining.map_or(true, |joining| joining.close_if_empty(&proving))
+        {
+            if checkpoint_interval.is_some() {
+                if let Err(e) = ProvingCheckpoint::remove(datadir) {
+                    log::warn!("failed to remove proving checkpoint: {e:?}");
+                }
+            }
+            if !corrupted.is_empty() {
+                log::warn!("the proofs might fail verification, some POS data files are corrupted");
+            }
+            return Ok(states.into_iter().flat_map(|state| state.proof).collect());
+        }
+        eyre::ensure!(
+            corrupted.is_empty(),
+            "corrupted POS data files: {}",
+            corrupted.join(", ")
+        );
+
+        cancel.check()?;
+        if deadline_exceeded.load(Ordering::Relaxed) {
+            log::info!("reading POS data is estimated to not finish before the deadline");
+            return Err(DeadlineExceeded.into());
+        }
+        last_pass_time = Some(pass_time.elapsed());
+        position = range.start;
+    }
+}
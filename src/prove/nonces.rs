@@ -0,0 +1,155 @@
+//! Picking the number of nonces of a pass automatically.
+//!
+//! More nonces make finding a proof in a pass over the POS data more likely,
+//! so fewer passes are needed, but every group of 16 of them adds an AES encryption
+//! per label. Once checking the labels is slower than reading them, the passes get longer.
+//! The number of nonces minimizing the expected proving time is picked
+//! from the measured speeds of reading the POS data and checking the labels.
+//! The time of the k2 proofs of work is not taken into account.
+
+use std::{
+    path::Path,
+    time::{Duration, Instant},
+};
+
+use rayon::ThreadPool;
+
+use super::{
+    benchmark, read_pos_data, scanned_range, Prover8_56, ProvingParams, ProvingSettings, LABEL_SIZE,
+};
+use crate::{config::ProofConfig, metadata::PostMetadata, pow};
+
+/// The maximum number of nonce groups in a pass with wide nonce groups.
+pub(super) const MAX_WIDE_NONCE_GROUPS: usize = 4096;
+/// How long to read the POS data to measure the read speed (at most).
+const READ_TIME: Duration = Duration::from_secs(1);
+/// How much POS data to read to measure the read speed (at most).
+const READ_SIZE: u64 = 1024 * 1024 * 1024;
+/// Size of the synthetic labels checked to measure the speed of checking.
+pub(super) const BENCHMARK_SIZE: u64 = 64 * 1024 * 1024;
+/// Nonces to check the synthetic labels for.
+/// Enough groups for the encryption to outweigh generating the labels.
+pub(super) const BENCHMARK_NONCES: usize = 128;
+
+/// Pick the number of nonces minimizing the expected time to find the proofs
+/// of `challenges` challenges in the POS data, checking the labels on the `pool`.
+pub(crate) fn select(
+    datadir: &Path,
+    metadata: &PostMetadata,
+    cfg: &ProofConfig,
+    settings: &ProvingSettings,
+    pool: &ThreadPool,
+    challenges: usize,
+) -> eyre::Result<usize> {
+    let read_speed = measure_read_speed(datadir, metadata, settings)?;
+    let params = ProvingParams::new(metadata, cfg)?;
+    let checks = benchmark::measure(pool, params, BENCHMARK_SIZE, BENCHMARK_NONCES)?;
+    let groups = BENCHMARK_NONCES / Prover8_56::NONCES_PER_AES as usize;
+    let group_checks_per_sec = checks.labels_per_sec() * groups as f64;
+
+    let range = scanned_range(metadata, settings);
+    let num_labels = (range.end - range.start) / LABEL_SIZE as u64;
+    let read_time = (range.end - range.start) as f64 / read_speed;
+    let group_time = num_labels as f64 * challenges as f64 / group_checks_per_sec;
+    let max_groups = match settings.wide_nonce_groups {
+        true => MAX_WIDE_NONCE_GROUPS,
+        false => pow::NARROW_NONCE_GROUPS as usize,
+    };
+    let nonces = optimal_nonces(cfg.k1, cfg.k2, read_time, group_time, max_groups);
+    log::info!(
+        "picked {nonces} nonces (reading POS data at {:.0} MiB/s, checking {:.0} labels/s per nonce group)",
+        read_speed / (1024.0 * 1024.0),
+        group_checks_per_sec
+    );
+    Ok(nonces)
+}
+
+/// Read speed of the POS data (in bytes per second), measured by reading
+/// its beginning for a while.
+pub(super) fn measure_read_speed(
+    datadir: &Path,
+    metadata: &PostMetadata,
+    settings: &ProvingSettings,
+) -> eyre::Result<f64> {
+    let start = Instant::now();
+    let mut read = 0;
+    for batch in read_pos_data(datadir, metadata, 0, settings)? {
+        read += batch.data.len() as u64;
+        if read >= READ_SIZE || start.elapsed() >= READ_TIME {
+            break;
+        }
+    }
+    eyre::ensure!(read > 0, "no POS data could be read");
+    Ok(read as f64 / start.elapsed().as_secs_f64())
+}
+
+/// The number of nonces minimizing the expected proving time,
+/// given the time to read all labels and to check all of them for a nonce group (in seconds).
+fn optimal_nonces(k1: u32, k2: u32, read_time: f64, group_time: f64, max_groups: usize) -> usize {
+    let nonce_success = nonce_success(k1, k2);
+    (1..=max_groups)
+        .map(|groups| {
+            let nonces = groups * Prover8_56::NONCES_PER_AES as usize;
+            let pass_success = 1.0 - (1.0 - nonce_success).powi(nonces as i32);
+            let pass_time = read_time.max(group_time * groups as f64);
+            (nonces, pass_time / pass_success)
+        })
+        .min_by(|a, b| a.1.total_cmp(&b.1))
+        .map(|(nonces, _)| nonces)
+        .unwrap()
+}
+
+/// Probability of a nonce to have at least `k2` labels passing the difficulty in a pass.
+///
+/// Every label passes with probability `k1 / num_labels`, so the number of passing labels
+/// follows (almost exactly) the Poisson distribution with mean `k1`.
+pub(super) fn nonce_success(k1: u32, k2: u32) -> f64 {
+    if k1 == 0 {
+        return if k2 == 0 { 1.0 } else { 0.0 };
+    }
+    let mean = k1 as f64;
+    let mut ln_factorial = 0.0;
+    let mut success = 0.0;
+    for i in 0.. {
+        if i > 0 {
+            ln_factorial += (i as f64).ln();
+        }
+        if i < k2 {
+            continue;
+        }
+        let p = (i as f64 * mean.ln() - mean - ln_factorial).exp();
+        success += p;
+        // Past the mean, the terms only get smaller.
+        if i as f64 > mean && p <= success * f64::EPSILON {
+            break;
+        }
+    }
+    success.min(1.0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn nonce_success_probability() {
+        assert!((nonce_success(26, 0) - 1.0).abs() < 1e-9);
+        assert_eq!(0.0, nonce_success(0, 1));
+        // Mainnet parameters
+        assert!((nonce_success(26, 37) - 0.02438).abs() < 1e-4);
+        assert!((nonce_success(26, 26) - 0.5261).abs() < 1e-4);
+        assert!(nonce_success(100, 80) > 0.98);
+    }
+
+    #[test]
+    fn picking_nonces() {
+        // Checking is free, the more nonces the better (until a pass surely succeeds).
+        assert!(optimal_nonces(26, 37, 10.0, 0.0, 256) >= 1024);
+        // Checking is slow, every pass should be as short as possible.
+        assert_eq!(16, optimal_nonces(26, 37, 1.0, 10.0, 256));
+        // Checking 10 nonce groups takes as long as reading.
+        assert_eq!(160, optimal_nonces(26, 37, 10.0, 1.0, 256));
+        // Wide nonce groups
+        assert_eq!(4096 * 16, optimal_nonces(26, 60, 10.0, 0.0, 4096));
+    }
+}
@@ -0,0 +1,153 @@
+//! Placing the proving threads on a NUMA node.
+//!
+//! On multi-socket servers, the memory and the PCIe devices (disks, HBAs, NICs)
+//! are attached to one of the sockets (NUMA nodes). Accessing them from another node
+//! goes through the interconnect between the nodes, which is slower.
+//! The kernel allocates memory on the node of the thread first touching it,
+//! so running the reader and the workers on one node keeps the batches of POS data
+//! and the buffers of the workers in the memory local to them.
+
+use std::{io, path::Path};
+
+/// NUMA node to run the proving threads on.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum NumaPlacement {
+    /// Don't place the threads, let the OS schedule them.
+    #[default]
+    Off,
+    /// The node the storage of the POS data is attached to.
+    /// Falls back to not placing the threads if it can't be determined.
+    Storage,
+    /// The given node.
+    Node(usize),
+}
+
+impl NumaPlacement {
+    /// The cores of the selected node, or `None` if the threads are not placed.
+    pub(crate) fn cores(&self, datadir: &Path) -> eyre::Result<Option<Vec<usize>>> {
+        let node = match *self {
+            NumaPlacement::Off => return Ok(None),
+            NumaPlacement::Node(node) => node,
+            NumaPlacement::Storage => match storage_node(datadir) {
+                Ok(Some(node)) => {
+                    log::info!("POS data in {} is on NUMA node {node}", datadir.display());
+                    node
+                }
+                Ok(None) => {
+                    log::warn!(
+                        "unknown NUMA node of POS data in {}, not placing threads",
+                        datadir.display()
+                    );
+                    return Ok(None);
+                }
+                Err(e) => {
+                    log::warn!("failed to find NUMA node of POS data, not placing threads: {e}");
+                    return Ok(None);
+                }
+            },
+        };
+        let cores = node_cores(node).map_err(|e| eyre::eyre!("getting NUMA node {node}: {e}"))?;
+        Ok(Some(cores))
+    }
+}
+
+/// Cores of the NUMA `node`.
+#[cfg(target_os = "linux")]
+fn node_cores(node: usize) -> io::Result<Vec<usize>> {
+    let list = std::fs::read_to_string(format!("/sys/devices/system/node/node{node}/cpulist"))?;
+    parse_cpu_list(list.trim())
+}
+
+/// NUMA node of the device storing the file or directory at `path`.
+///
+/// The block device (or one of its parents, like the PCIe controller)
+/// in sysfs tells its node, unless the system has a single one.
+#[cfg(target_os = "linux")]
+fn storage_node(path: &Path) -> io::Result<Option<usize>> {
+    use std::os::unix::fs::MetadataExt;
+
+    let dev = std::fs::metadata(path)?.dev();
+    // SAFETY: `major` and `minor` only extract the bits of the device number.
+    let (major, minor) = unsafe { (libc::major(dev), libc::minor(dev)) };
+    let sysfs = format!("/sys/dev/block/{major}:{minor}");
+    let device = match std::fs::canonicalize(sysfs) {
+        Ok(device) => device,
+        // Not a block device, i.e. a network or virtual file system.
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e),
+    };
+    for dir in device.ancestors() {
+        if let Ok(node) = std::fs::read_to_string(dir.join("numa_node")) {
+            // -1 means the device is not attached to a particular node.
+            return Ok(node.trim().parse().ok());
+        }
+    }
+    Ok(None)
+}
+
+/// Parse a list of CPUs like `0-3,8,10-11`.
+#[cfg(target_os = "linux")]
+fn parse_cpu_list(list: &str) -> io::Result<Vec<usize>> {
+    let invalid = || {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("invalid CPU list: {list}"),
+        )
+    };
+    let mut cores = Vec::new();
+    for range in list.split(',').filter(|r| !r.is_empty()) {
+        let (start, end) = range.split_once('-').unwrap_or((range, range));
+        let start = start.parse::<usize>().map_err(|_| invalid())?;
+        let end = end.parse::<usize>().map_err(|_| invalid())?;
+        cores.extend(start..=end);
+    }
+    Ok(cores)
+}
+
+#[cfg(not(target_os = "linux"))]
+fn node_cores(_node: usize) -> io::Result<Vec<usize>> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "NUMA placement is only supported on Linux",
+    ))
+}
+
+#[cfg(not(target_os = "linux"))]
+fn storage_node(_path: &Path) -> io::Result<Option<usize>> {
+    node_cores(0).map(|_| None)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn no_placement() {
+        assert_eq!(None, NumaPlacement::Off.cores(Path::new(".")).unwrap());
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn parsing_cpu_list() {
+        assert_eq!(vec![0], parse_cpu_list("0").unwrap());
+        assert_eq!(
+            vec![0, 1, 2, 3, 8, 10, 11],
+            parse_cpu_list("0-3,8,10-11").unwrap()
+        );
+        assert!(parse_cpu_list("").unwrap().is_empty());
+        assert!(parse_cpu_list("0-x").is_err());
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn placing_on_node() {
+        let cores = NumaPlacement::Node(0).cores(Path::new("."));
+        if Path::new("/sys/devices/system/node/node0").exists() {
+            assert!(!cores.unwrap().unwrap().is_empty());
+        } else {
+            assert!(cores.is_err());
+        }
+        // The storage node is optional, it never fails.
+        NumaPlacement::Storage.cores(Path::new(".")).unwrap();
+    }
+}
@@ -0,0 +1,203 @@
+//! Pipeline of the proof generation: a reader of the POS data feeding
+//! a pool of workers checking the labels.
+//!
+//! The reader runs on its own thread and sends the batches it read to the workers
+//! through a bounded queue. Reading thus goes on while the workers are busy,
+//! until the queue is full, and the workers take the batches as soon as they are
+//! read. Neither waits for the other unless it's the bottleneck.
+
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    mpsc, Mutex,
+};
+
+use eyre::Context;
+use rayon::ThreadPool;
+
+use super::{affinity, ProvingThrottle};
+
+/// Process the `batches` with `process` on all threads of the `pool`.
+///
+/// The batches are read on a separate thread (running on the `reader_cores` if given),
+/// up to `queue_size` of them ahead of the workers. Processing stops as soon as `process` returns
+/// a result (which is then returned), `keep_going` returns `false`
+/// or all batches are processed. The workers above the limit of the `throttle` (if given)
+/// wait for it to be raised.
+#[allow(clippy::too_many_arguments)]
+pub(crate) fn process_batches<B, R, I, K, P>(
+    pool: &ThreadPool,
+    batches: I,
+    queue_size: usize,
+    reader_cores: Option<&[usize]>,
+    throttle: Option<&ProvingThrottle>,
+    keep_going: K,
+    process: P,
+) -> eyre::Result<Option<R>>
+where
+    B: Send,
+    R: Send,
+    I: Iterator<Item = B> + Send,
+    K: Fn() -> bool + Sync,
+    P: Fn(B) -> Option<R> + Sync,
+{
+    std::thread::scope(|s| {
+        let (tx, rx) = mpsc::sync_channel(queue_size);
+        std::thread::Builder::new()
+            .name("post-reader".into())
+            .spawn_scoped(s, move || {
+                if let Some(cores) = reader_cores {
+                    affinity::pin_thread(cores, "post-reader");
+                }
+                for batch in batches {
+                    if tx.send(batch).is_err() {
+                        log::debug!("stopped reading POS data");
+                        return;
+                    }
+                }
+            })
+            .wrap_err("spawning reader thread")?;
+
+        let queue = Mutex::new(rx);
+        let result = Mutex::new(None);
+        let next_batch = || {
+            if !keep_going() || result.lock().unwrap().is_some() {
+                return None;
+            }
+            queue.lock().unwrap().recv().ok()
+        };
+        // Set once a worker runs out of batches, so that the throttled ones stop waiting.
+        let done = AtomicBool::new(false);
+        let waiting = || keep_going() && !done.load(Ordering::Relaxed);
+        let (next_batch, process, waiting, done, result_ref) =
+            (&next_batch, &process, &waiting, &done, &result);
+        pool.scope(|scope| {
+            for id in 0..pool.current_num_threads() {
+                scope.spawn(move |_| loop {
+                    if let Some(throttle) = throttle {
+                        throttle.wait(id, waiting);
+                    }
+                    let Some(batch) = next_batch() else {
+                        done.store(true, Ordering::Relaxed);
+                        return;
+                    };
+                    if let Some(r) = process(batch) {
+                        result_ref.lock().unwrap().get_or_insert(r);
+                        return;
+                    }
+                });
+            }
+        });
+        // Dropping the queue stops the reader.
+        drop(queue);
+        Ok(result.into_inner().unwrap())
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::AtomicUsize;
+
+    use super::*;
+
+    fn pool(threads: usize) -> ThreadPool {
+        rayon::ThreadPoolBuilder::new()
+            .num_threads(threads)
+            .build()
+            .unwrap()
+    }
+
+    #[rstest::rstest]
+    fn processing_all_batches(#[values(1, 4)] threads: usize, #[values(0, 2)] queue_size: usize) {
+        let processed = Mutex::new(Vec::new());
+        let result = process_batches(
+            &pool(threads),
+            0..100,
+            queue_size,
+            None,
+            None,
+            || true,
+            |batch| {
+                processed.lock().unwrap().push(batch);
+                None::<()>
+            },
+        )
+        .unwrap();
+        assert!(result.is_none());
+
+        let mut processed = processed.into_inner().unwrap();
+        processed.sort();
+        assert_eq!((0..100).collect::<Vec<_>>(), processed);
+    }
+
+    #[test]
+    fn stopping_at_result() {
+        let read = AtomicUsize::new(0);
+        let batches = (0..).inspect(|_| {
+            read.fetch_add(1, Ordering::Relaxed);
+        });
+        let result = process_batches(
+            &pool(1),
+            batches,
+            4,
+            None,
+            None,
+            || true,
+            |batch| (batch == 10).then_some(batch),
+        )
+        .unwrap();
+        assert_eq!(Some(10), result);
+        // The reader stopped with at most the queue full and a batch in hand.
+        assert!(read.load(Ordering::Relaxed) <= 11 + 4 + 1);
+    }
+
+    #[test]
+    fn stopping_when_told() {
+        let processed = AtomicUsize::new(0);
+        let result = process_batches(
+            &pool(1),
+            0..100,
+            0,
+            None,
+            None,
+            || processed.load(Ordering::Relaxed) < 5,
+            |_| {
+                processed.fetch_add(1, Ordering::Relaxed);
+                None::<()>
+            },
+        )
+        .unwrap();
+        assert!(result.is_none());
+        assert_eq!(5, processed.load(Ordering::Relaxed));
+    }
+
+    #[test]
+    fn throttling_workers() {
+        let throttle = ProvingThrottle::new(1);
+        let running = AtomicUsize::new(0);
+        let max_running = AtomicUsize::new(0);
+        let processed = AtomicUsize::new(0);
+        let result = process_batches(
+            &pool(4),
+            0..200,
+            4,
+            None,
+            Some(&throttle),
+            || true,
+            |_| {
+                let now = running.fetch_add(1, Ordering::SeqCst) + 1;
+                max_running.fetch_max(now, Ordering::SeqCst);
+                std::thread::sleep(std::time::Duration::from_micros(100));
+                running.fetch_sub(1, Ordering::SeqCst);
+                // Lift the limit halfway through
+                if processed.fetch_add(1, Ordering::SeqCst) == 100 {
+                    assert_eq!(1, max_running.load(Ordering::SeqCst));
+                    throttle.set_threads(0);
+                }
+                None::<()>
+            },
+        )
+        .unwrap();
+        assert!(result.is_none());
+        assert_eq!(200, processed.load(Ordering::SeqCst));
+    }
+}
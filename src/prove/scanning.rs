@@ -0,0 +1,41 @@
+//! Offloading the scanning of labels to an accelerator (i.e. a GPU).
+//!
+//! Checking every label against the difficulty takes an AES encryption per label
+//! and nonce group, which might be slower than reading the POS data on weak CPUs.
+//! A [LabelScanner] evaluates the MSB part of the difficulty check in bulk and
+//! returns only the candidates, which the [Prover8_56](super::Prover8_56) then
+//! checks entirely, just as it does without a scanner.
+
+/// A label of a batch whose encryption with the AES cipher of a nonce group
+/// has a byte not above the MSB part of the difficulty.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Candidate {
+    /// Index of the label in the batch.
+    pub label: u32,
+    /// Index of the nonce group cipher (see [Prover8_56::nonce_group_keys](super::Prover8_56::nonce_group_keys)).
+    pub cipher: u32,
+    /// Offset of the byte in the encrypted label, the nonce within the group.
+    pub offset: u32,
+}
+
+/// Finds candidate labels passing the difficulty check.
+pub trait LabelScanner: Send {
+    /// Set the AES-128 keys of the nonce group ciphers and the MSB part of the difficulty
+    /// for the following scans.
+    fn set_ciphers(
+        &mut self,
+        keys: &[[u8; 16]],
+        difficulty_msb: u8,
+    ) -> Result<(), Box<dyn std::error::Error>>;
+
+    /// Find all [Candidate]s among the `labels`.
+    ///
+    /// The order of the candidates doesn't matter.
+    fn scan(&mut self, labels: &[u8]) -> Result<Vec<Candidate>, Box<dyn std::error::Error>>;
+}
+
+impl std::fmt::Debug for dyn LabelScanner + '_ {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("LabelScanner")
+    }
+}
@@ -0,0 +1,145 @@
+//! Vectorized MSB part of the difficulty check.
+//!
+//! Every chunk of labels encrypted with a nonce group cipher gives [CHUNK_SIZE] bytes,
+//! one per label and nonce, to compare against the MSB part of the difficulty.
+//! Only a tiny fraction of them pass, so the bytes are compared all at once
+//! (with the widest instructions the CPU supports, detected at runtime)
+//! into a bit mask, and only the passing ones are looked at one by one.
+
+use super::CHUNK_SIZE;
+
+/// Bit mask of the bytes of a chunk, bit `i` set if byte `i` is selected.
+pub(crate) type Mask = u128;
+
+const _: () = assert!(CHUNK_SIZE == Mask::BITS as usize);
+
+/// Mask of the `bytes` not above the `threshold`.
+#[inline]
+pub(crate) fn not_above(bytes: &[u8; CHUNK_SIZE], threshold: u8) -> Mask {
+    #[cfg(target_arch = "x86_64")]
+    {
+        if is_x86_feature_detected!("avx512bw") {
+            // SAFETY: the CPU supports the instructions.
+            return unsafe { x86::not_above_avx512(bytes, threshold) };
+        }
+        if is_x86_feature_detected!("avx2") {
+            // SAFETY: the CPU supports the instructions.
+            return unsafe { x86::not_above_avx2(bytes, threshold) };
+        }
+    }
+    #[cfg(target_arch = "aarch64")]
+    {
+        if std::arch::is_aarch64_feature_detected!("neon") {
+            // SAFETY: the CPU supports the instructions.
+            return unsafe { aarch64::not_above_neon(bytes, threshold) };
+        }
+    }
+    not_above_scalar(bytes, threshold)
+}
+
+fn not_above_scalar(bytes: &[u8; CHUNK_SIZE], threshold: u8) -> Mask {
+    bytes
+        .iter()
+        .enumerate()
+        .filter(|(_, &b)| b <= threshold)
+        .fold(0, |mask, (i, _)| mask | 1 << i)
+}
+
+#[cfg(target_arch = "x86_64")]
+mod x86 {
+    use std::arch::x86_64::*;
+
+    use super::{Mask, CHUNK_SIZE};
+
+    #[target_feature(enable = "avx512bw")]
+    pub(super) unsafe fn not_above_avx512(bytes: &[u8; CHUNK_SIZE], threshold: u8) -> Mask {
+        let threshold = _mm512_set1_epi8(threshold as i8);
+        let ptr = bytes.as_ptr().cast::<__m512i>();
+        let lo = _mm512_cmple_epu8_mask(_mm512_loadu_si512(ptr), threshold);
+        let hi = _mm512_cmple_epu8_mask(_mm512_loadu_si512(ptr.add(1)), threshold);
+        lo as Mask | (hi as Mask) << 64
+    }
+
+    #[target_feature(enable = "avx2")]
+    pub(super) unsafe fn not_above_avx2(bytes: &[u8; CHUNK_SIZE], threshold: u8) -> Mask {
+        let threshold = _mm256_set1_epi8(threshold as i8);
+        let ptr = bytes.as_ptr().cast::<__m256i>();
+        let mut mask = 0;
+        for i in 0..4 {
+            let v = _mm256_loadu_si256(ptr.add(i));
+            // There is no unsigned comparison, but `min(v, t) == v` iff `v <= t`.
+            let le = _mm256_cmpeq_epi8(_mm256_min_epu8(v, threshold), v);
+            mask |= (_mm256_movemask_epi8(le) as u32 as Mask) << (32 * i);
+        }
+        mask
+    }
+}
+
+#[cfg(target_arch = "aarch64")]
+mod aarch64 {
+    use std::arch::aarch64::*;
+
+    use super::{Mask, CHUNK_SIZE};
+
+    #[target_feature(enable = "neon")]
+    pub(super) unsafe fn not_above_neon(bytes: &[u8; CHUNK_SIZE], threshold: u8) -> Mask {
+        // There is no movemask, so the bytes of the comparison result are reduced
+        // to bits by keeping a different bit of every byte and adding them up.
+        const BITS: [u8; 16] = [1, 2, 4, 8, 16, 32, 64, 128, 1, 2, 4, 8, 16, 32, 64, 128];
+        let bits = vld1q_u8(BITS.as_ptr());
+        let threshold = vdupq_n_u8(threshold);
+        let mut mask = 0;
+        for i in 0..8 {
+            let le = vcleq_u8(vld1q_u8(bytes.as_ptr().add(16 * i)), threshold);
+            let le = vandq_u8(le, bits);
+            let lo = vaddv_u8(vget_low_u8(le)) as Mask;
+            let hi = vaddv_u8(vget_high_u8(le)) as Mask;
+            mask |= (lo | hi << 8) << (16 * i);
+        }
+        mask
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::{Rng, RngCore};
+
+    use super::*;
+
+    #[test]
+    fn selecting_bytes_not_above() {
+        let mut rng = rand::thread_rng();
+        let mut bytes = [0u8; CHUNK_SIZE];
+        for _ in 0..1000 {
+            rng.fill_bytes(&mut bytes);
+            let threshold = rng.gen();
+            let expected = not_above_scalar(&bytes, threshold);
+            assert_eq!(expected, not_above(&bytes, threshold));
+            for (i, &b) in bytes.iter().enumerate() {
+                assert_eq!(b <= threshold, expected & 1 << i != 0);
+            }
+        }
+        assert_eq!(Mask::MAX, not_above(&[0xFF; CHUNK_SIZE], 0xFF));
+        assert_eq!(0, not_above(&[1; CHUNK_SIZE], 0));
+    }
+
+    #[cfg(target_arch = "x86_64")]
+    #[test]
+    fn x86_kernels() {
+        let mut rng = rand::thread_rng();
+        let mut bytes = [0u8; CHUNK_SIZE];
+        for _ in 0..1000 {
+            rng.fill_bytes(&mut bytes);
+            let threshold = rng.gen();
+            let expected = not_above_scalar(&bytes, threshold);
+            if is_x86_feature_detected!("avx512bw") {
+                assert_eq!(expected, unsafe {
+                    x86::not_above_avx512(&bytes, threshold)
+                });
+            }
+            if is_x86_feature_detected!("avx2") {
+                assert_eq!(expected, unsafe { x86::not_above_avx2(&bytes, threshold) });
+            }
+        }
+    }
+}
@@ -0,0 +1,120 @@
+//! Statistics of the proof generation to diagnose why it takes long.
+
+use std::{
+    collections::BTreeMap,
+    time::{Duration, Instant},
+};
+
+use super::LABEL_SIZE;
+
+/// Hook called with every (nonce, index) of a label passing the difficulty,
+/// as soon as it's found, to monitor how the indices of every nonce accumulate
+/// or to collect statistics.
+///
+/// Set it in [ProvingSettings::index_hook](super::ProvingSettings::index_hook).
+/// Any `Fn(u32, u64) + Sync` closure is one. It's called from the proving threads,
+/// concurrently, and must be fast not to slow down the proof generation.
+pub trait IndexHook: Sync {
+    fn on_index(&self, nonce: u32, index: u64);
+
+    /// Called with the challenge the index was found for. Implement it to tell apart
+    /// the indices of several challenges proven at once, it calls [IndexHook::on_index]
+    /// by default.
+    fn on_challenge_index(&self, challenge: &[u8; 32], nonce: u32, index: u64) {
+        let _ = challenge;
+        self.on_index(nonce, index)
+    }
+}
+
+impl<F: Fn(u32, u64) + Sync> IndexHook for F {
+    fn on_index(&self, nonce: u32, index: u64) {
+        self(nonce, index)
+    }
+}
+
+impl std::fmt::Debug for dyn IndexHook + '_ {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("IndexHook")
+    }
+}
+
+/// Statistics of a proof generation, updated as it goes.
+///
+/// Pass a `Mutex<ProvingStats>` in [ProvingSettings::stats](super::ProvingSettings::stats)
+/// and lock it to look at them during or after the proof generation.
+#[derive(Debug, Clone, Default)]
+pub struct ProvingStats {
+    /// Number of passes over the POS data started.
+    pub passes: u32,
+    /// Number of labels scanned in all passes.
+    pub labels_scanned: u64,
+    /// Number of indices found for every nonce in the current pass.
+    /// A proof is found once a nonce has `k2` of them.
+    /// Only recorded when proving a single challenge.
+    pub indices_per_nonce: BTreeMap<u32, usize>,
+    /// Time the passes waited for the k2pow of their nonce groups.
+    /// The k2pow of a pass is calculated while the previous pass reads the POS data.
+    pub k2pow_time: Duration,
+    /// Time spent reading (and scanning) the POS data.
+    pub reading_time: Duration,
+    /// Start of the current reading and the reading time before it.
+    reading_since: Option<(Instant, Duration)>,
+}
+
+impl ProvingStats {
+    /// Throughput of reading (and scanning) the POS data in bytes per second.
+    pub fn read_throughput(&self) -> f64 {
+        match self.reading_time.as_secs_f64() {
+            secs if secs > 0.0 => (self.labels_scanned * LABEL_SIZE as u64) as f64 / secs,
+            _ => 0.0,
+        }
+    }
+
+    pub(crate) fn start_pass(&mut self, k2pow_time: Duration) {
+        self.passes += 1;
+        self.k2pow_time += k2pow_time;
+        self.indices_per_nonce.clear();
+        self.reading_since = Some((Instant::now(), self.reading_time));
+    }
+
+    pub(crate) fn record_batch(&mut self, bytes: usize) {
+        self.labels_scanned += (bytes / LABEL_SIZE) as u64;
+        if let Some((since, before)) = self.reading_since {
+            self.reading_time = before + since.elapsed();
+        }
+    }
+
+    pub(crate) fn record_index(&mut self, nonce: u32) {
+        *self.indices_per_nonce.entry(nonce).or_default() += 1;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn recording_stats() {
+        let mut stats = ProvingStats::default();
+        assert_eq!(0.0, stats.read_throughput());
+
+        stats.start_pass(Duration::from_secs(2));
+        stats.record_batch(160);
+        stats.record_index(3);
+        stats.record_index(3);
+        stats.record_index(5);
+        assert_eq!(1, stats.passes);
+        assert_eq!(10, stats.labels_scanned);
+        assert_eq!(BTreeMap::from([(3, 2), (5, 1)]), stats.indices_per_nonce);
+
+        stats.start_pass(Duration::from_secs(3));
+        stats.record_batch(16);
+        assert_eq!(2, stats.passes);
+        assert_eq!(11, stats.labels_scanned);
+        assert_eq!(Duration::from_secs(5), stats.k2pow_time);
+        assert!(stats.indices_per_nonce.is_empty());
+
+        stats.reading_time = Duration::from_secs(2);
+        assert_eq!(88.0, stats.read_throughput());
+    }
+}
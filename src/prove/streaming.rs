@@ -0,0 +1,190 @@
+//! Proving with labels pushed by the caller.
+//!
+//! [generate_proof](super::generate_proof) reads the POS data itself.
+//! Integrators with their own IO layer push the labels to a [StreamingProver]
+//! in any order (and from any number of threads) instead, and poll it for the proofs found.
+
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    ops::Range,
+    sync::Mutex,
+};
+
+use super::{memory::IndexStore, Proof, Prover, Prover8_56, ProvingParams, CHUNK_SIZE, LABEL_SIZE};
+use crate::{config::ProofConfig, metadata::PostMetadata, pow};
+
+/// Checks the labels pushed with [consume](StreamingProver::consume)
+/// for the `nonces` of a challenge.
+#[derive(Debug)]
+pub struct StreamingProver {
+    prover: Prover8_56,
+    num_labels: u64,
+    state: Mutex<State>,
+}
+
+#[derive(Debug)]
+struct State {
+    indices: IndexStore,
+    /// Nonces a proof was found for.
+    proved: HashSet<u32>,
+    found: VecDeque<Proof<'static>>,
+}
+
+impl StreamingProver {
+    /// Create a prover for the POS data of the `metadata`,
+    /// calculating the k2 proofs of work of the `nonces` first.
+    pub fn new<P: pow::Prover>(
+        challenge: &[u8; 32],
+        nonces: Range<u32>,
+        metadata: &PostMetadata,
+        cfg: &ProofConfig,
+        pow_prover: &P,
+    ) -> eyre::Result<Self> {
+        let params = ProvingParams::new(metadata, cfg)?;
+        let prover = Prover8_56::new(challenge, nonces, params, pow_prover, &metadata.node_id)?;
+        Ok(Self::with_prover(prover, metadata, cfg))
+    }
+
+    /// Create a prover for the POS data of the `metadata`
+    /// with the already calculated k2 proofs of work of the `nonces`.
+    pub fn with_pows(
+        challenge: &[u8; 32],
+        nonces: Range<u32>,
+        metadata: &PostMetadata,
+        cfg: &ProofConfig,
+        pows: &[u64],
+    ) -> eyre::Result<Self> {
+        let params = ProvingParams::new(metadata, cfg)?;
+        let prover = Prover8_56::with_pows(challenge, nonces, params, pows)?;
+        Ok(Self::with_prover(prover, metadata, cfg))
+    }
+
+    fn with_prover(prover: Prover8_56, metadata: &PostMetadata, cfg: &ProofConfig) -> Self {
+        Self {
+            prover,
+            num_labels: metadata.num_units as u64 * metadata.labels_per_unit,
+            state: Mutex::new(State {
+                indices: IndexStore::new(cfg.k2 as usize, None, HashMap::new()),
+                proved: HashSet::new(),
+                found: VecDeque::new(),
+            }),
+        }
+    }
+
+    /// Proofs of work of the nonce groups, in order.
+    pub fn pows(&self) -> Vec<u64> {
+        self.prover.pows()
+    }
+
+    /// Check the `labels` starting at the label `index_offset`.
+    ///
+    /// The labels must be whole chunks of 8 labels (128 bytes).
+    /// Every label must be consumed once at most, or the same index might be used twice.
+    pub fn consume(&self, index_offset: u64, labels: &[u8]) -> eyre::Result<()> {
+        eyre::ensure!(
+            labels.len() % CHUNK_SIZE == 0,
+            "labels must be a multiple of {CHUNK_SIZE} bytes, got {}",
+            labels.len()
+        );
+        let end = index_offset + (labels.len() / LABEL_SIZE) as u64;
+        eyre::ensure!(
+            end <= self.num_labels,
+            "labels {index_offset}..{end} are out of the POS data of {} labels",
+            self.num_labels
+        );
+        self.prover.prove(labels, index_offset, |nonce, index| {
+            let mut state = self.state.lock().unwrap();
+            if state.proved.contains(&nonce) {
+                return None;
+            }
+            if let Some(indices) = state.indices.push(nonce, index) {
+                let pow = self.prover.get_pow(nonce).unwrap();
+                log::info!("found proof for nonce {nonce}");
+                state.proved.insert(nonce);
+                state
+                    .found
+                    .push_back(Proof::new(nonce, &indices, self.num_labels, pow));
+            }
+            // Keep going to find the proofs of the other nonces.
+            None
+        });
+        Ok(())
+    }
+
+    /// Take the next found proof (in the order they were found), if any.
+    pub fn poll(&self) -> Option<Proof<'static>> {
+        self.state.lock().unwrap().found.pop_front()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::{thread_rng, RngCore};
+
+    use super::*;
+
+    #[test]
+    fn streaming_labels() {
+        let metadata = PostMetadata {
+            labels_per_unit: 256,
+            num_units: 1,
+            ..Default::default()
+        };
+        let cfg = ProofConfig {
+            k1: 64,
+            k2: 8,
+            k3: 8,
+            pow_difficulty: [0xFF; 32],
+        };
+        let mut data = vec![0u8; 256 * LABEL_SIZE];
+        thread_rng().fill_bytes(&mut data);
+
+        let params = ProvingParams::new(&metadata, &cfg).unwrap();
+        let prover = Prover8_56::with_pows(&[0; 32], 0..32, params, &[1, 2]).unwrap();
+        let mut indices = HashMap::<u32, Vec<u64>>::new();
+        let (nonce, expected) = prover
+            .prove(&data, 0, |nonce, index| {
+                let vec = indices.entry(nonce).or_default();
+                vec.push(index);
+                (vec.len() >= cfg.k2 as usize).then(|| std::mem::take(vec))
+            })
+            .unwrap();
+
+        let streaming =
+            StreamingProver::with_pows(&[0; 32], 0..32, &metadata, &cfg, &[1, 2]).unwrap();
+        assert_eq!(None, streaming.poll());
+        for (i, chunk) in data.chunks(4 * CHUNK_SIZE).enumerate() {
+            streaming.consume(i as u64 * 32, chunk).unwrap();
+        }
+        let proof = streaming.poll().unwrap();
+        assert_eq!(
+            Proof::new(nonce, &expected, 256, prover.get_pow(nonce).unwrap()),
+            proof
+        );
+
+        // One proof per nonce at most
+        let mut nonces = HashSet::from([proof.nonce]);
+        while let Some(proof) = streaming.poll() {
+            assert!(nonces.insert(proof.nonce));
+        }
+    }
+
+    #[test]
+    fn rejecting_invalid_labels() {
+        let metadata = PostMetadata {
+            labels_per_unit: 16,
+            num_units: 1,
+            ..Default::default()
+        };
+        let cfg = ProofConfig {
+            k1: 8,
+            k2: 8,
+            k3: 8,
+            pow_difficulty: [0xFF; 32],
+        };
+        let prover = StreamingProver::with_pows(&[0; 32], 0..16, &metadata, &cfg, &[0]).unwrap();
+        assert!(prover.consume(0, &[0; CHUNK_SIZE]).is_ok());
+        assert!(prover.consume(0, &[0; LABEL_SIZE]).is_err());
+        assert!(prover.consume(8, &[0; 2 * CHUNK_SIZE]).is_err());
+    }
+}
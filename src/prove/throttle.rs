@@ -0,0 +1,54 @@
+//! Throttling the proof generation at runtime.
+
+use std::{
+    sync::{Condvar, Mutex},
+    time::Duration,
+};
+
+/// Limit of the number of threads checking the labels, adjustable while proving,
+/// i.e. lowered when the machine is needed for other work and raised later.
+///
+/// The threads of the pool above the limit wait for it to be raised
+/// (between batches of labels), the limit can't exceed the size of the pool.
+#[derive(Debug, Default)]
+pub struct ProvingThrottle {
+    /// '0' means no limit.
+    limit: Mutex<usize>,
+    changed: Condvar,
+}
+
+impl ProvingThrottle {
+    /// Limit the proving to `threads` threads ('0' means all of them).
+    pub fn new(threads: usize) -> Self {
+        Self {
+            limit: Mutex::new(threads),
+            changed: Condvar::new(),
+        }
+    }
+
+    /// Change the limit to `threads` threads ('0' means all of them),
+    /// applied to the running proof generation right away.
+    pub fn set_threads(&self, threads: usize) {
+        *self.limit.lock().unwrap() = threads;
+        self.changed.notify_all();
+    }
+
+    /// The limit of threads ('0' means all of them).
+    pub fn threads(&self) -> usize {
+        *self.limit.lock().unwrap()
+    }
+
+    /// Wait until the worker `id` (counted from 0) is within the limit,
+    /// unless `keep_going` returns `false` meanwhile.
+    pub(crate) fn wait<K: Fn() -> bool>(&self, id: usize, keep_going: K) {
+        let mut limit = self.limit.lock().unwrap();
+        while *limit != 0 && id >= *limit && keep_going() {
+            // Wake up periodically to check if the proving should stop.
+            limit = self
+                .changed
+                .wait_timeout(limit, Duration::from_millis(100))
+                .unwrap()
+                .0;
+        }
+    }
+}
@@ -0,0 +1,218 @@
+//! Picking the number of nonces and threads of the proof generation automatically.
+//!
+//! Unlike picking only the nonces (see [super::nonces]), the speeds are measured per thread
+//! and the k2 proofs of work of the first pass are taken into account: more nonces
+//! need more of them before the pass can start. The proving time is modelled as
+//! the k2pow of the first pass and the expected number of passes, every pass taking
+//! as long as reading the POS data or checking its labels on the threads, whichever is slower.
+//! Of the numbers of threads proving (almost) as fast as all of them, the fewest are picked,
+//! leaving the others to the rest of the machine.
+
+use std::{path::Path, time::Duration};
+
+use super::{
+    affinity, benchmark,
+    nonces::{self, BENCHMARK_NONCES, BENCHMARK_SIZE, MAX_WIDE_NONCE_GROUPS},
+    scanned_range, Prover8_56, ProvingParams, ProvingSettings, LABEL_SIZE,
+};
+use crate::{config::ProofConfig, metadata, pow};
+
+/// How long to measure the RandomX hashrate.
+const POW_BENCHMARK_TIME: Duration = Duration::from_secs(2);
+/// Fewer threads are picked if the expected proving time is at most this much longer.
+const TOLERANCE: f64 = 1.05;
+
+/// The nonces and threads picked by [tune], with the measurements they are based on.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Tuning {
+    pub nonces: usize,
+    pub threads: usize,
+    /// Expected time to find a proof with them.
+    pub expected_duration: Duration,
+    /// Bytes per second.
+    pub read_speed: f64,
+    /// Labels checked per second for a group of 16 nonces on a thread.
+    pub group_checks_per_thread: f64,
+    /// RandomX hashes per second on a thread, if the k2pow is calculated locally.
+    pub hashrate_per_thread: Option<f64>,
+}
+
+/// Measure the speed of reading the POS data in `datadir`, checking the labels on a thread
+/// and, with `pow_flags`, calculating the k2pow on a thread (without them, the k2pow
+/// is calculated elsewhere, i.e. remotely). Then pick the nonces and threads (out of
+/// the available cores) minimizing the expected time to find a proof with the `settings`.
+pub fn tune(
+    datadir: &Path,
+    cfg: &ProofConfig,
+    settings: &ProvingSettings,
+    pow_flags: Option<pow::randomx::RandomXFlag>,
+) -> eyre::Result<Tuning> {
+    let metadata = metadata::load(datadir)?;
+    let params = ProvingParams::new(&metadata, cfg)?;
+    let read_speed = nonces::measure_read_speed(datadir, &metadata, settings)?;
+
+    let pool = affinity::thread_pool(1, None, "post-tune")?;
+    let checks = benchmark::measure(&pool, params, BENCHMARK_SIZE, BENCHMARK_NONCES)?;
+    let groups = BENCHMARK_NONCES / Prover8_56::NONCES_PER_AES as usize;
+    let group_checks_per_thread = checks.labels_per_sec() * groups as f64;
+
+    let hashrate_per_thread = match pow_flags {
+        Some(flags) => {
+            // The benchmark calculates hashes on all threads of the global pool.
+            let benchmark = pow::randomx::PoW::new(flags)?.benchmark(POW_BENCHMARK_TIME)?;
+            Some(benchmark.hashrate() / rayon::current_num_threads() as f64)
+        }
+        None => None,
+    };
+
+    let range = scanned_range(&metadata, settings);
+    let num_labels = (range.end - range.start) / LABEL_SIZE as u64;
+    let model = Model {
+        k1: cfg.k1,
+        k2: cfg.k2,
+        read_time: (range.end - range.start) as f64 / read_speed,
+        group_time: num_labels as f64 / group_checks_per_thread,
+        pow_group_time: hashrate_per_thread
+            .map(|rate| pow::expected_hashes(&params.pow_difficulty) / rate),
+        max_groups: match settings.wide_nonce_groups {
+            true => MAX_WIDE_NONCE_GROUPS,
+            false => pow::NARROW_NONCE_GROUPS as usize,
+        },
+        pow_threads: settings.pow_threads,
+    };
+    let cores = std::thread::available_parallelism().map_or(1, |n| n.get());
+    let (nonces, threads, expected) = model.optimize(cores);
+    let tuning = Tuning {
+        nonces,
+        threads,
+        expected_duration: Duration::try_from_secs_f64(expected).unwrap_or(Duration::MAX),
+        read_speed,
+        group_checks_per_thread,
+        hashrate_per_thread,
+    };
+    log::info!(
+        "picked {nonces} nonces on {threads} of {cores} threads, expecting a proof in {:.0?}: \
+        reading POS data at {:.0} MiB/s, checking {:.0} labels/s per nonce group and thread, \
+        k2pow {}; passes are {}",
+        tuning.expected_duration,
+        read_speed / (1024.0 * 1024.0),
+        group_checks_per_thread,
+        match hashrate_per_thread {
+            Some(rate) => format!("at {rate:.0} hashes/s per thread"),
+            None => "calculated elsewhere".to_string(),
+        },
+        match model.pass_time(nonces / Prover8_56::NONCES_PER_AES as usize, threads) {
+            time if time > model.read_time => "bound by checking the labels",
+            _ => "bound by reading the POS data",
+        }
+    );
+    Ok(tuning)
+}
+
+/// Times (in seconds) the expected proving time is modelled from.
+struct Model {
+    k1: u32,
+    k2: u32,
+    /// Reading all labels.
+    read_time: f64,
+    /// Checking all labels for a nonce group on a thread.
+    group_time: f64,
+    /// The k2pow of a nonce group on a thread, if calculated locally.
+    pow_group_time: Option<f64>,
+    max_groups: usize,
+    /// Threads calculating the k2pow ('0' means all cores), by default the proving threads.
+    pow_threads: Option<usize>,
+}
+
+impl Model {
+    fn pass_time(&self, groups: usize, threads: usize) -> f64 {
+        self.read_time
+            .max(self.group_time * groups as f64 / threads as f64)
+    }
+
+    /// Expected time to find a proof with the nonce `groups` on the `threads` out of `cores`.
+    fn expected_time(&self, groups: usize, threads: usize, cores: usize, success: f64) -> f64 {
+        let nonces = groups * Prover8_56::NONCES_PER_AES as usize;
+        let pass_success = 1.0 - (1.0 - success).powi(nonces as i32);
+        let pow_threads = match self.pow_threads {
+            Some(0) => cores,
+            Some(threads) => threads,
+            None => threads,
+        };
+        let pow_time = self
+            .pow_group_time
+            .map_or(0.0, |time| time * groups as f64 / pow_threads as f64);
+        pow_time + self.pass_time(groups, threads) / pass_success
+    }
+
+    /// The nonces and threads (out of `cores`) to prove with and the expected proving time.
+    fn optimize(&self, cores: usize) -> (usize, usize, f64) {
+        let success = nonces::nonce_success(self.k1, self.k2);
+        let best_per_threads = (1..=cores.max(1))
+            .map(|threads| {
+                let (groups, time) = (1..=self.max_groups)
+                    .map(|groups| (groups, self.expected_time(groups, threads, cores, success)))
+                    .min_by(|a, b| a.1.total_cmp(&b.1))
+                    .unwrap();
+                (groups * Prover8_56::NONCES_PER_AES as usize, threads, time)
+            })
+            .collect::<Vec<_>>();
+        let best = best_per_threads
+            .iter()
+            .map(|(_, _, time)| *time)
+            .min_by(f64::total_cmp)
+            .unwrap();
+        *best_per_threads
+            .iter()
+            .find(|(_, _, time)| *time <= best * TOLERANCE)
+            .unwrap()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn model(read_time: f64, group_time: f64, pow_group_time: Option<f64>) -> Model {
+        Model {
+            k1: 26,
+            k2: 37,
+            read_time,
+            group_time,
+            pow_group_time,
+            max_groups: 256,
+            pow_threads: None,
+        }
+    }
+
+    #[test]
+    fn picking_nonces_and_threads() {
+        // Checking is free, the fewest threads and the most nonces.
+        let (nonces, threads, _) = model(10.0, 0.0, None).optimize(8);
+        assert_eq!(1, threads);
+        assert!(nonces >= 1024);
+
+        // Checking a nonce group on a thread takes as long as reading,
+        // more threads check more nonces in a pass.
+        let (nonces, threads, _) = model(10.0, 10.0, None).optimize(1);
+        assert_eq!((16, 1), (nonces, threads));
+        let (nonces, threads, _) = model(10.0, 10.0, None).optimize(8);
+        assert!(nonces > 16);
+        assert!(threads > 1);
+
+        // The k2pow makes more nonces costly
+        let (nonces_with_pow, _, time) = model(10.0, 10.0, Some(100.0)).optimize(8);
+        assert!(nonces_with_pow < nonces);
+        assert!(time > 10.0);
+    }
+
+    #[test]
+    fn modelling_pow_threads() {
+        let mut model = model(10.0, 1.0, Some(8.0));
+        let success = nonces::nonce_success(26, 37);
+        let on_proving_threads = model.expected_time(4, 2, 8, success);
+        model.pow_threads = Some(0);
+        let on_all_cores = model.expected_time(4, 2, 8, success);
+        assert!((on_proving_threads - on_all_cores - (16.0 - 4.0)).abs() < 1e-9);
+    }
+}
@@ -0,0 +1,94 @@
+//! Token bucket rate limiter used to throttle IO.
+
+use std::time::{Duration, Instant};
+
+#[derive(Debug)]
+pub(crate) struct TokenBucket {
+    /// Tokens added per second.
+    rate: f64,
+    /// Maximum number of tokens that can be accumulated.
+    capacity: f64,
+    tokens: f64,
+    last_refill: Instant,
+}
+
+impl TokenBucket {
+    /// Create a bucket allowing `rate` tokens per second with bursts up to `rate` tokens.
+    pub(crate) fn new(rate: u64) -> Self {
+        assert!(rate > 0, "rate must be > 0");
+        Self {
+            rate: rate as f64,
+            capacity: rate as f64,
+            tokens: rate as f64,
+            last_refill: Instant::now(),
+        }
+    }
+
+    /// Take `amount` tokens from the bucket and return how long to wait
+    /// for the bucket to get them back.
+    ///
+    /// Taking more tokens than available is allowed - the debt is paid back by waiting.
+    fn take_at(&mut self, amount: u64, now: Instant) -> Duration {
+        let elapsed = now.saturating_duration_since(self.last_refill);
+        self.tokens = (self.tokens + elapsed.as_secs_f64() * self.rate).min(self.capacity);
+        self.last_refill = now;
+
+        self.tokens -= amount as f64;
+        if self.tokens >= 0.0 {
+            Duration::ZERO
+        } else {
+            Duration::from_secs_f64(-self.tokens / self.rate)
+        }
+    }
+
+    /// Take `amount` tokens, blocking until they are available.
+    pub(crate) fn take(&mut self, amount: u64) {
+        let wait = self.take_at(amount, Instant::now());
+        if !wait.is_zero() {
+            log::trace!("throttling for {wait:?}");
+            std::thread::sleep(wait);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use super::TokenBucket;
+
+    #[test]
+    fn allows_burst_up_to_capacity() {
+        let mut bucket = TokenBucket::new(100);
+        let now = Instant::now();
+        assert_eq!(Duration::ZERO, bucket.take_at(60, now));
+        assert_eq!(Duration::ZERO, bucket.take_at(40, now));
+        assert_eq!(Duration::from_millis(500), bucket.take_at(50, now));
+    }
+
+    #[test]
+    fn refills_over_time() {
+        let mut bucket = TokenBucket::new(100);
+        let now = Instant::now();
+        assert_eq!(Duration::ZERO, bucket.take_at(100, now));
+        assert_eq!(
+            Duration::ZERO,
+            bucket.take_at(50, now + Duration::from_millis(500))
+        );
+        // Doesn't accumulate more than the capacity
+        let later = now + Duration::from_secs(10);
+        assert_eq!(Duration::from_secs(1), bucket.take_at(200, later));
+    }
+
+    #[test]
+    fn debt_is_paid_by_waiting() {
+        let mut bucket = TokenBucket::new(100);
+        let now = Instant::now();
+        assert_eq!(Duration::from_secs(2), bucket.take_at(300, now));
+        // After waiting for 2s the bucket is empty
+        assert_eq!(
+            Duration::from_millis(100),
+            bucket.take_at(10, now + Duration::from_secs(2))
+        );
+    }
+}
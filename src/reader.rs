@@ -1,13 +1,25 @@
 use std::{
     fs::{DirEntry, File},
-    io::Read,
-    path::Path,
+    io::{Read, Seek, SeekFrom},
+    ops::Range,
+    path::{Path, PathBuf},
 };
 
 use eyre::Context;
 use itertools::Itertools;
 use regex::Regex;
 
+use crate::config::FileLayout;
+
+mod direct;
+mod mmap;
+mod source;
+#[cfg(all(feature = "io-uring", target_os = "linux"))]
+mod uring;
+
+pub(crate) use source::read_source;
+pub use source::{LabelSource, PosFiles};
+
 #[derive(Debug, PartialEq, Eq)]
 pub struct Batch {
     pub data: Vec<u8>,
@@ -84,6 +96,11 @@ impl<T: Read> Iterator for BatchingReader<T> {
 }
 
 pub(crate) fn pos_files(datadir: &Path) -> eyre::Result<impl Iterator<Item = DirEntry>> {
+    Ok(numbered_pos_files(datadir)?.map(|(_, entry)| entry))
+}
+
+/// POS data files with their numbers (`N` in `postdata_N.bin`), sorted by the numbers.
+fn numbered_pos_files(datadir: &Path) -> eyre::Result<impl Iterator<Item = (u64, DirEntry)>> {
     let file_re = Regex::new(r"^postdata_(\d+)\.bin$").unwrap();
     let files = datadir
         .read_dir()
@@ -98,25 +115,150 @@ pub(crate) fn pos_files(datadir: &Path) -> eyre::Result<impl Iterator<Item = Dir
                 None
             }
         })
-        .sorted_by_key(|(id, _)| *id)
-        .map(|(_, entry)| entry);
+        .sorted_by_key(|(id, _)| *id);
 
     Ok(files)
 }
 
-pub(crate) fn read_data(
+/// Backend used to read the POS data.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ReaderBackend {
+    /// Buffered sequential reads.
+    #[default]
+    Buffered,
+    /// Sequential reads bypassing the OS page cache (O_DIRECT/F_NOCACHE),
+    /// so that reading the POS data doesn't evict the cache of other processes.
+    Direct,
+    /// Memory mapped files. It might outperform reads if the POS data
+    /// (partially) fits in RAM and stays cached between proving passes.
+    Mmap,
+    /// Reads submitted via io_uring, keeping the chunks read ahead in flight at once.
+    #[cfg(all(feature = "io-uring", target_os = "linux"))]
+    IoUring,
+}
+
+/// Default size (in bytes) of a chunk of POS data read at once.
+pub const DEFAULT_READ_CHUNK_SIZE: usize = 1024 * 1024;
+/// Default number of chunks read ahead of processing them.
+pub const DEFAULT_READ_AHEAD: usize = 4;
+
+/// How to read POS data files residing on different disks.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ParallelReads<'a> {
+    /// Read the files one by one.
+    #[default]
+    Off,
+    /// Read files on different devices (as told by their device IDs) in parallel.
+    PerDevice,
+    /// Read files in different directories (i.e. mount points of disks) in parallel.
+    /// The files outside of them are read as if they were in another directory.
+    PerDirectory(&'a [PathBuf]),
+}
+
+/// A POS data file to read.
+pub(crate) struct DataFile {
+    pub(crate) file: File,
+    /// Position of the first byte to read in the POS data.
+    pub(crate) pos: u64,
+    /// Offset of the first byte to read in the file.
+    pub(crate) offset: u64,
+    /// Number of bytes to read.
+    pub(crate) size: u64,
+    pub(crate) name: String,
+    /// Identifies the disk the file is read from in parallel with the others.
+    pub(crate) disk: u64,
+}
+
+#[cfg(unix)]
+fn device_id(file: &File) -> std::io::Result<u64> {
+    use std::os::unix::fs::MetadataExt;
+    Ok(file.metadata()?.dev())
+}
+
+#[cfg(not(unix))]
+fn device_id(_file: &File) -> std::io::Result<u64> {
+    Ok(0)
+}
+
+/// Fail if the POS data files with the `ids` are missing,
+/// unless `skip_missing` is set, in which case they are only logged.
+fn missing_files(layout: &FileLayout, ids: Range<u64>, skip_missing: bool) -> eyre::Result<()> {
+    if ids.is_empty() {
+        return Ok(());
+    }
+    let missing = match ids.end - ids.start {
+        1 => layout.file_name(ids.start),
+        _ => format!(
+            "{} to {}",
+            layout.file_name(ids.start),
+            layout.file_name(ids.end - 1)
+        ),
+    };
+    eyre::ensure!(skip_missing, "missing POS data: {missing}");
+    log::warn!("skipping missing POS data: {missing}");
+    Ok(())
+}
+
+/// Open the POS data files to read the `range` (in bytes) of the POS data.
+/// A range ending at `u64::MAX` covers the files there are.
+///
+/// Missing files (within the range) and files which can't be opened fail
+/// unless `skip_missing` is set, in which case they are skipped.
+fn data_files(
     datadir: &Path,
-    batch_size: usize,
-    file_size: u64,
-) -> eyre::Result<impl Iterator<Item = Batch>> {
-    let mut readers = Vec::<BatchingReader<File>>::new();
-    let mut files = pos_files(datadir)?.enumerate().peekable();
+    layout: FileLayout,
+    range: Range<u64>,
+    direct: bool,
+    parallel: ParallelReads,
+    skip_missing: bool,
+) -> eyre::Result<Vec<DataFile>> {
+    let dirs = match parallel {
+        ParallelReads::PerDirectory(dirs) => dirs
+            .iter()
+            .map(|dir| {
+                dir.canonicalize()
+                    .wrap_err_with(|| format!("resolving {}", dir.display()))
+            })
+            .collect::<eyre::Result<Vec<_>>>()?,
+        _ => Vec::new(),
+    };
+    let file_size = layout.max_file_size;
+    let mut data_files = Vec::new();
+    let mut files = numbered_pos_files(datadir)?.peekable();
+    let mut expected_id = 0;
+    // Only the files missing within the range matter.
+    let first_id = range.start / file_size;
+    let end_id = range.end.div_ceil(file_size);
 
     while let Some((id, entry)) = files.next() {
-        let pos = id as u64 * file_size;
+        missing_files(
+            &layout,
+            expected_id.max(first_id)..id.min(end_id),
+            skip_missing,
+        )?;
+        expected_id = id + 1;
+        let pos = id * file_size;
+        if pos >= range.end {
+            break;
+        }
+        if pos + file_size <= range.start {
+            continue;
+        }
         let path = entry.path();
-        let file = File::open(&path)?;
-        let pos_file_size = file.metadata().unwrap().len();
+        let file = if direct {
+            direct::open(&path)
+        } else {
+            File::open(&path)
+        };
+        let file = file.and_then(|file| Ok((file.metadata()?.len(), file)));
+        let (pos_file_size, file) = match file {
+            Ok(file) => file,
+            Err(e) if skip_missing => {
+                log::warn!("skipping unreadable POS data file {}: {e}", path.display());
+                continue;
+            }
+            Err(e) => return Err(e).wrap_err_with(|| format!("opening {}", path.display())),
+        };
 
         // If there are more files, check if the size of the file is correct
         if files.peek().is_some() && pos_file_size != file_size {
@@ -126,13 +268,195 @@ pub(crate) fn read_data(
             );
         }
 
-        let identifier = Some(entry.file_name().to_string_lossy().into_owned());
-        readers.push(BatchingReader::new(
-            file, pos, batch_size, file_size, identifier,
-        ));
+        let disk = match parallel {
+            ParallelReads::Off => 0,
+            ParallelReads::PerDevice => device_id(&file)?,
+            ParallelReads::PerDirectory(_) => {
+                let path = path.canonicalize()?;
+                dirs.iter()
+                    .position(|dir| path.starts_with(dir))
+                    .unwrap_or(dirs.len()) as u64
+            }
+        };
+
+        let skip = range.start.saturating_sub(pos);
+        let end = pos_file_size.min(file_size).min(range.end - pos);
+        data_files.push(DataFile {
+            file,
+            pos: pos + skip,
+            offset: skip,
+            size: end.saturating_sub(skip),
+            name: entry.file_name().to_string_lossy().into_owned(),
+            disk,
+        });
+    }
+    if range.end != u64::MAX {
+        missing_files(&layout, expected_id.max(first_id)..end_id, skip_missing)?;
+    }
+    Ok(data_files)
+}
+
+/// Size (in bytes) of the POS data which can be read,
+/// skipping missing and unreadable files.
+pub(crate) fn readable_size(datadir: &Path, layout: FileLayout) -> eyre::Result<u64> {
+    let files = data_files(
+        datadir,
+        layout,
+        0..u64::MAX,
+        false,
+        ParallelReads::Off,
+        true,
+    )?;
+    Ok(files.iter().map(|f| f.size).sum())
+}
+
+/// Read the `range` (in bytes) of the POS data with the given `backend`.
+/// With `skip_missing`, missing and unreadable files are skipped (see [data_files]).
+///
+/// The batches are read as the returned iterator is consumed, except with io_uring,
+/// which keeps up to `read_ahead` reads in flight. The consumer is expected to read
+/// ahead of processing the batches itself (see the proving pipeline).
+///
+/// With [ParallelReads] the files on every disk are read on a separate thread,
+/// in order, up to `read_ahead` batches ahead. The batches of the disks are
+/// merged by their positions, so the POS data is still returned in order.
+/// The disks are read in parallel as far as the consumer reaches the files
+/// of every disk within `read_ahead` batches (i.e. consecutive files reside
+/// on different disks).
+#[allow(clippy::too_many_arguments)]
+pub(crate) fn read_data(
+    datadir: &Path,
+    batch_size: usize,
+    layout: FileLayout,
+    range: Range<u64>,
+    backend: ReaderBackend,
+    read_ahead: usize,
+    parallel: ParallelReads,
+    skip_missing: bool,
+) -> eyre::Result<Box<dyn Iterator<Item = Batch> + Send>> {
+    let direct = backend == ReaderBackend::Direct;
+    let files = data_files(datadir, layout, range, direct, parallel, skip_missing)?;
+
+    let mut disks: Vec<Vec<DataFile>> = Vec::new();
+    for file in files {
+        match disks.iter_mut().find(|files| files[0].disk == file.disk) {
+            Some(files) => files.push(file),
+            None => disks.push(vec![file]),
+        }
+    }
+
+    if disks.len() <= 1 {
+        let files = disks.into_iter().flatten().collect();
+        return read_files(files, batch_size, backend, read_ahead);
     }
 
-    Ok(readers.into_iter().flatten())
+    log::info!("reading POS data from {} disks in parallel", disks.len());
+    let readers = disks
+        .into_iter()
+        .map(|files| {
+            let batches = read_files(files, batch_size, backend, read_ahead)?;
+            read_ahead_of(batches, read_ahead)
+        })
+        .collect::<eyre::Result<Vec<_>>>()?;
+    Ok(Box::new(MergeByPosition {
+        readers: readers.into_iter().map(Iterator::peekable).collect(),
+    }))
+}
+
+/// Read the batches of the `files` one after another.
+fn read_files(
+    files: Vec<DataFile>,
+    batch_size: usize,
+    backend: ReaderBackend,
+    #[cfg_attr(
+        not(all(feature = "io-uring", target_os = "linux")),
+        allow(unused_variables)
+    )]
+    read_ahead: usize,
+) -> eyre::Result<Box<dyn Iterator<Item = Batch> + Send>> {
+    Ok(match backend {
+        ReaderBackend::Buffered => {
+            let readers = files
+                .into_iter()
+                .map(|mut f| {
+                    if f.offset > 0 {
+                        f.file.seek(SeekFrom::Start(f.offset))?;
+                    }
+                    Ok(BatchingReader::new(
+                        f.file,
+                        f.pos,
+                        batch_size,
+                        f.size,
+                        Some(f.name),
+                    ))
+                })
+                .collect::<eyre::Result<Vec<_>>>()?;
+            Box::new(readers.into_iter().flatten())
+        }
+        ReaderBackend::Direct => Box::new(
+            files
+                .into_iter()
+                .flat_map(move |f| direct::DirectReader::new(f, batch_size)),
+        ),
+        ReaderBackend::Mmap => {
+            let readers = files
+                .into_iter()
+                .map(|f| mmap::MmapReader::new(f, batch_size))
+                .collect::<std::io::Result<Vec<_>>>()?;
+            Box::new(readers.into_iter().flatten())
+        }
+        // Reading ahead is keeping the reads in flight in the ring.
+        #[cfg(all(feature = "io-uring", target_os = "linux"))]
+        ReaderBackend::IoUring => {
+            let reader = uring::UringReader::new(files, batch_size, read_ahead)?;
+            Box::new(reader.map_while(|batch| {
+                batch
+                    .map_err(|err| log::error!("failed to read POS data: {err}"))
+                    .ok()
+            }))
+        }
+    })
+}
+
+/// Merges the batches of the readers (each returning them in order) by their positions.
+struct MergeByPosition {
+    readers: Vec<std::iter::Peekable<Box<dyn Iterator<Item = Batch> + Send>>>,
+}
+
+impl Iterator for MergeByPosition {
+    type Item = Batch;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.readers.retain_mut(|reader| reader.peek().is_some());
+        let (idx, _) = self
+            .readers
+            .iter_mut()
+            .filter_map(|reader| reader.peek().map(|batch| batch.pos))
+            .enumerate()
+            .min_by_key(|(_, pos)| *pos)?;
+        self.readers[idx].next()
+    }
+}
+
+/// Read the `batches` on a separate thread, up to `read_ahead` (at least 1) of them
+/// ahead of the consumer. The thread stops once the returned iterator is dropped.
+fn read_ahead_of(
+    batches: Box<dyn Iterator<Item = Batch> + Send>,
+    read_ahead: usize,
+) -> eyre::Result<Box<dyn Iterator<Item = Batch> + Send>> {
+    let (tx, rx) = std::sync::mpsc::sync_channel(read_ahead.saturating_sub(1));
+    std::thread::Builder::new()
+        .name("post-disk-reader".into())
+        .spawn(move || {
+            for batch in batches {
+                if tx.send(batch).is_err() {
+                    log::debug!("stopped reading POS data");
+                    return;
+                }
+            }
+        })
+        .wrap_err("spawning reader thread")?;
+    Ok(Box::new(rx.into_iter()))
 }
 
 pub fn read_from<R: Read>(
@@ -151,7 +475,29 @@ mod tests {
 
     use tempfile::tempdir;
 
-    use super::{pos_files, read_data, Batch, BatchingReader};
+    use super::{
+        pos_files, read_data, readable_size, Batch, BatchingReader, ParallelReads, ReaderBackend,
+    };
+    use crate::config::FileLayout;
+
+    fn layout(max_file_size: u64) -> FileLayout {
+        FileLayout {
+            max_file_size,
+            ..Default::default()
+        }
+    }
+
+    fn backends() -> Vec<ReaderBackend> {
+        #[allow(unused_mut)]
+        let mut backends = vec![
+            ReaderBackend::Buffered,
+            ReaderBackend::Direct,
+            ReaderBackend::Mmap,
+        ];
+        #[cfg(all(feature = "io-uring", target_os = "linux"))]
+        backends.push(ReaderBackend::IoUring);
+        backends
+    }
 
     #[test]
     fn batching_reader() {
@@ -192,16 +538,297 @@ mod tests {
             write!(tmp_file, "{part}").unwrap();
         }
 
-        let mut result = Vec::new();
-        let mut next_expected_index = 0;
-        let file_size = 4u64;
-        for batch in read_data(tmp_dir.path(), file_size as usize, file_size).unwrap() {
-            assert_eq!(next_expected_index, batch.pos);
-            result.extend(batch.data);
-            next_expected_index += file_size;
+        for backend in backends() {
+            let mut result = Vec::new();
+            let mut next_expected_index = 0;
+            let file_size = 4u64;
+            for batch in read_data(
+                tmp_dir.path(),
+                file_size as usize,
+                layout(file_size),
+                0..u64::MAX,
+                backend,
+                2,
+                ParallelReads::Off,
+                false,
+            )
+            .unwrap()
+            {
+                assert_eq!(next_expected_index, batch.pos);
+                result.extend(batch.data);
+                next_expected_index += file_size;
+            }
+
+            assert_eq!(b"2Hell1Welc", result.as_slice(), "{backend:?}");
+        }
+    }
+
+    #[test]
+    fn reading_pos_data_from_offset() {
+        let tmp_dir = tempdir().unwrap();
+        for (i, part) in ["abcd", "efgh", "ijkl"].iter().enumerate() {
+            let file_path = tmp_dir.path().join(format!("postdata_{i}.bin"));
+            std::fs::write(file_path, part).unwrap();
+        }
+
+        for backend in backends() {
+            let batches = read_data(
+                tmp_dir.path(),
+                4,
+                layout(4),
+                6..u64::MAX,
+                backend,
+                2,
+                ParallelReads::Off,
+                false,
+            )
+            .unwrap()
+            .collect::<Vec<_>>();
+            assert_eq!(
+                vec![
+                    Batch {
+                        data: b"gh".to_vec(),
+                        pos: 6
+                    },
+                    Batch {
+                        data: b"ijkl".to_vec(),
+                        pos: 8
+                    },
+                ],
+                batches,
+                "{backend:?}"
+            );
+            assert!(read_data(
+                tmp_dir.path(),
+                4,
+                layout(4),
+                12..u64::MAX,
+                backend,
+                2,
+                ParallelReads::Off,
+                false
+            )
+            .unwrap()
+            .next()
+            .is_none());
+        }
+    }
+
+    #[rstest::rstest]
+    fn reading_ahead(#[values(0, 1, 3)] read_ahead: usize) {
+        let tmp_dir = tempdir().unwrap();
+        let data = (0..=255u8).cycle().take(1000).collect::<Vec<_>>();
+        for (i, part) in data.chunks(100).enumerate() {
+            std::fs::write(tmp_dir.path().join(format!("postdata_{i}.bin")), part).unwrap();
+        }
+
+        for backend in backends() {
+            let mut read = Vec::new();
+            for batch in read_data(
+                tmp_dir.path(),
+                32,
+                layout(100),
+                0..u64::MAX,
+                backend,
+                read_ahead,
+                ParallelReads::Off,
+                false,
+            )
+            .unwrap()
+            {
+                assert_eq!(read.len() as u64, batch.pos);
+                read.extend(batch.data);
+            }
+            assert_eq!(data, read, "{backend:?}");
+
+            // Dropping the reader with batches read ahead
+            let mut reader = read_data(
+                tmp_dir.path(),
+                32,
+                layout(100),
+                0..u64::MAX,
+                backend,
+                read_ahead,
+                ParallelReads::Off,
+                false,
+            )
+            .unwrap();
+            assert_eq!(&data[..32], reader.next().unwrap().data);
+            drop(reader);
+        }
+    }
+
+    #[test]
+    fn reading_files_of_one_device() {
+        let tmp_dir = tempdir().unwrap();
+        for (i, part) in ["abcd", "efgh"].iter().enumerate() {
+            std::fs::write(tmp_dir.path().join(format!("postdata_{i}.bin")), part).unwrap();
+        }
+
+        let parallel = ParallelReads::PerDevice;
+        let read = read_data(
+            tmp_dir.path(),
+            2,
+            layout(4),
+            0..u64::MAX,
+            ReaderBackend::Buffered,
+            1,
+            parallel,
+            false,
+        )
+        .unwrap()
+        .map(|batch| batch.pos)
+        .collect::<Vec<_>>();
+        assert_eq!(vec![0, 2, 4, 6], read);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn reading_disks_in_parallel() {
+        let datadir = tempdir().unwrap();
+        let disks = [tempdir().unwrap(), tempdir().unwrap()];
+        let disk_paths = disks
+            .iter()
+            .map(|d| d.path().to_owned())
+            .collect::<Vec<_>>();
+        for (i, part) in ["abcd", "efgh", "ijkl", "mnop", "qrst"].iter().enumerate() {
+            let name = format!("postdata_{i}.bin");
+            // The last file is not on any of the disks
+            let path = match disk_paths.get(i / 2) {
+                Some(disk) => disk.join(&name),
+                None => datadir.path().join("other").join(&name),
+            };
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(&path, part).unwrap();
+            std::os::unix::fs::symlink(&path, datadir.path().join(name)).unwrap();
+        }
+
+        for backend in backends() {
+            let parallel = ParallelReads::PerDirectory(&disk_paths);
+            let batches = read_data(
+                datadir.path(),
+                4,
+                layout(4),
+                2..u64::MAX,
+                backend,
+                0,
+                parallel,
+                false,
+            )
+            .unwrap()
+            .collect::<Vec<_>>();
+            let positions = batches.iter().map(|b| b.pos).collect::<Vec<_>>();
+            assert_eq!(vec![2, 4, 8, 12, 16], positions, "{backend:?}");
+            let data = batches.into_iter().flat_map(|b| b.data).collect::<Vec<_>>();
+            assert_eq!(b"cdefghijklmnopqrst".as_slice(), data, "{backend:?}");
+
+            // The disks are read up to the end of the range only
+            let batches = read_data(
+                datadir.path(),
+                4,
+                layout(4),
+                2..10,
+                backend,
+                0,
+                parallel,
+                false,
+            )
+            .unwrap()
+            .collect::<Vec<_>>();
+            let positions = batches.iter().map(|b| b.pos).collect::<Vec<_>>();
+            assert_eq!(vec![2, 4, 8], positions, "{backend:?}");
+            let data = batches.into_iter().flat_map(|b| b.data).collect::<Vec<_>>();
+            assert_eq!(b"cdefghij".as_slice(), data, "{backend:?}");
+        }
+    }
+
+    #[test]
+    fn skipping_missing_files() {
+        let tmp_dir = tempdir().unwrap();
+        for i in [0, 2] {
+            std::fs::write(tmp_dir.path().join(format!("postdata_{i}.bin")), "abcd").unwrap();
+        }
+
+        for backend in backends() {
+            let parallel = ParallelReads::Off;
+            assert!(read_data(
+                tmp_dir.path(),
+                2,
+                layout(4),
+                0..u64::MAX,
+                backend,
+                0,
+                parallel,
+                false
+            )
+            .is_err());
+
+            let positions = read_data(
+                tmp_dir.path(),
+                2,
+                layout(4),
+                0..u64::MAX,
+                backend,
+                0,
+                parallel,
+                true,
+            )
+            .unwrap()
+            .map(|batch| batch.pos)
+            .collect::<Vec<_>>();
+            assert_eq!(vec![0, 2, 8, 10], positions, "{backend:?}");
+
+            // Files missing outside of the range don't matter
+            let positions = read_data(
+                tmp_dir.path(),
+                2,
+                layout(4),
+                8..12,
+                backend,
+                0,
+                parallel,
+                false,
+            )
+            .unwrap()
+            .map(|batch| batch.pos)
+            .collect::<Vec<_>>();
+            assert_eq!(vec![8, 10], positions, "{backend:?}");
+        }
+        assert_eq!(8, readable_size(tmp_dir.path(), layout(4)).unwrap());
+    }
+
+    #[test]
+    fn missing_trailing_files() {
+        let tmp_dir = tempdir().unwrap();
+        for i in 0..2 {
+            std::fs::write(tmp_dir.path().join(format!("postdata_{i:04}.bin")), "abcd").unwrap();
         }
+        let layout = FileLayout {
+            max_file_size: 4,
+            file_index_width: 4,
+        };
+        let read = |skip_missing| {
+            read_data(
+                tmp_dir.path(),
+                2,
+                layout,
+                0..16,
+                ReaderBackend::Buffered,
+                0,
+                ParallelReads::Off,
+                skip_missing,
+            )
+        };
 
-        assert_eq!(b"2Hell1Welc", result.as_slice());
+        let err = read(false).err().unwrap();
+        assert!(err
+            .to_string()
+            .contains("postdata_0002.bin to postdata_0003.bin"));
+        let positions = read(true)
+            .unwrap()
+            .map(|batch| batch.pos)
+            .collect::<Vec<_>>();
+        assert_eq!(vec![0, 2, 4, 6], positions);
     }
 
     #[rstest::rstest]
@@ -215,7 +842,19 @@ mod tests {
         let mut tmp_file = File::create(file_path).unwrap();
         write!(tmp_file, "some data").unwrap();
 
-        assert!(read_data(tmp_dir.path(), 4, 4).unwrap().next().is_none());
+        assert!(read_data(
+            tmp_dir.path(),
+            4,
+            layout(4),
+            0..u64::MAX,
+            Default::default(),
+            0,
+            ParallelReads::Off,
+            false
+        )
+        .unwrap()
+        .next()
+        .is_none());
     }
 
     #[test]
@@ -0,0 +1,213 @@
+//! Reading POS data bypassing the OS page cache.
+//!
+//! Reading terabytes of POS data through the page cache evicts everything else
+//! cached on the machine, while the data itself is read only once per proof.
+//! Direct IO requires buffers, offsets and sizes of reads aligned to the block size
+//! of the device, so the reads are extended to [ALIGNMENT] and the data is sliced out.
+
+use std::{fs::File, io, path::Path};
+
+use super::{Batch, DataFile};
+
+/// Alignment of direct reads, a multiple of the block size of common devices.
+const ALIGNMENT: usize = 4096;
+
+/// Open the file for direct reading.
+///
+/// Falls back to a regular file if the filesystem doesn't support direct IO (i.e. tmpfs).
+pub(crate) fn open(path: &Path) -> io::Result<File> {
+    match open_direct(path) {
+        Ok(file) => Ok(file),
+        Err(err) if err.kind() == io::ErrorKind::InvalidInput => {
+            log::warn!(
+                "direct IO not supported for {}, reading through page cache: {err}",
+                path.display()
+            );
+            File::open(path)
+        }
+        Err(err) => Err(err),
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn open_direct(path: &Path) -> io::Result<File> {
+    use std::os::unix::fs::OpenOptionsExt;
+
+    File::options()
+        .read(true)
+        .custom_flags(libc::O_DIRECT)
+        .open(path)
+}
+
+#[cfg(target_os = "macos")]
+fn open_direct(path: &Path) -> io::Result<File> {
+    use std::os::fd::AsRawFd;
+
+    let file = File::open(path)?;
+    // SAFETY: the file descriptor is valid for the lifetime of `file`.
+    if unsafe { libc::fcntl(file.as_raw_fd(), libc::F_NOCACHE, 1) } == -1 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(file)
+}
+
+#[cfg(windows)]
+fn open_direct(path: &Path) -> io::Result<File> {
+    use std::os::windows::fs::OpenOptionsExt;
+
+    const FILE_FLAG_NO_BUFFERING: u32 = 0x20000000;
+    File::options()
+        .read(true)
+        .custom_flags(FILE_FLAG_NO_BUFFERING)
+        .open(path)
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "macos", windows)))]
+fn open_direct(path: &Path) -> io::Result<File> {
+    log::warn!("direct IO is not supported on this platform");
+    File::open(path)
+}
+
+#[cfg(unix)]
+fn read_at(file: &File, buf: &mut [u8], offset: u64) -> io::Result<usize> {
+    std::os::unix::fs::FileExt::read_at(file, buf, offset)
+}
+
+#[cfg(windows)]
+fn read_at(file: &File, buf: &mut [u8], offset: u64) -> io::Result<usize> {
+    std::os::windows::fs::FileExt::seek_read(file, buf, offset)
+}
+
+/// Buffer with an aligned slice of the requested size.
+struct AlignedBuf {
+    buf: Vec<u8>,
+    start: usize,
+    len: usize,
+}
+
+impl AlignedBuf {
+    fn new(len: usize) -> Self {
+        let buf = vec![0u8; len + ALIGNMENT];
+        let start = buf.as_ptr().align_offset(ALIGNMENT);
+        Self { buf, start, len }
+    }
+
+    fn as_mut_slice(&mut self) -> &mut [u8] {
+        &mut self.buf[self.start..self.start + self.len]
+    }
+}
+
+/// Reads batches of a POS data file with direct IO.
+pub(crate) struct DirectReader {
+    file: DataFile,
+    /// Bytes of the file read so far (counting from [DataFile::offset]).
+    done: u64,
+    batch_size: usize,
+    buf: AlignedBuf,
+}
+
+impl DirectReader {
+    pub(crate) fn new(file: DataFile, batch_size: usize) -> Self {
+        // Enough for an unaligned batch extended to the alignment on both ends.
+        let buf_size = (batch_size + ALIGNMENT).div_ceil(ALIGNMENT) * ALIGNMENT;
+        Self {
+            file,
+            done: 0,
+            batch_size,
+            buf: AlignedBuf::new(buf_size),
+        }
+    }
+
+    /// Read `len` bytes at the file `offset` into the aligned buffer.
+    /// Returns the bytes read (it's less than `len` at the end of the file).
+    fn read(&mut self, offset: u64, len: usize) -> io::Result<&[u8]> {
+        let aligned_offset = offset / ALIGNMENT as u64 * ALIGNMENT as u64;
+        let skip = (offset - aligned_offset) as usize;
+        let aligned_len = (skip + len).div_ceil(ALIGNMENT) * ALIGNMENT;
+        let buf = &mut self.buf.as_mut_slice()[..aligned_len];
+
+        let mut filled = 0;
+        while filled < aligned_len {
+            match read_at(
+                &self.file.file,
+                &mut buf[filled..],
+                aligned_offset + filled as u64,
+            ) {
+                Ok(0) => break,
+                Ok(n) => filled += n,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) => return Err(e),
+            }
+        }
+        let end = filled.min(skip + len).max(skip);
+        Ok(&buf[skip..end])
+    }
+}
+
+impl Iterator for DirectReader {
+    type Item = Batch;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done >= self.file.size {
+            return None;
+        }
+        if self.done == 0 {
+            log::info!("Reading file: {}", self.file.name);
+        }
+        let len = (self.file.size - self.done).min(self.batch_size as u64) as usize;
+        let pos = self.file.pos + self.done;
+        match self.read(self.file.offset + self.done, len) {
+            Ok([]) => None,
+            Ok(data) => {
+                let batch = Batch {
+                    data: data.to_vec(),
+                    pos,
+                };
+                self.done += batch.data.len() as u64;
+                Some(batch)
+            }
+            Err(err) => {
+                log::error!("failed to read {}: {err}", self.file.name);
+                None
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::config::FileLayout;
+    use crate::reader::{read_data, ParallelReads, ReaderBackend};
+
+    #[test]
+    fn reading_unaligned_batches() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let data = (0..=255u8).cycle().take(30_000).collect::<Vec<_>>();
+        for (i, part) in data.chunks(10_000).enumerate() {
+            std::fs::write(tmp_dir.path().join(format!("postdata_{i}.bin")), part).unwrap();
+        }
+
+        for offset in [0, 100, 4096, 12_345] {
+            let mut read = Vec::new();
+            for batch in read_data(
+                tmp_dir.path(),
+                3000,
+                FileLayout {
+                    max_file_size: 10_000,
+                    ..Default::default()
+                },
+                offset..u64::MAX,
+                ReaderBackend::Direct,
+                0,
+                ParallelReads::Off,
+                false,
+            )
+            .unwrap()
+            {
+                assert_eq!(offset + read.len() as u64, batch.pos);
+                read.extend(batch.data);
+            }
+            assert_eq!(&data[offset as usize..], read.as_slice());
+        }
+    }
+}
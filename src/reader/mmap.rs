@@ -0,0 +1,59 @@
+//! Reading POS data via memory mapping.
+//!
+//! If (a part of) the POS data fits in RAM, it stays in the page cache
+//! between proving passes and mapping it avoids the syscalls of reading it.
+
+use memmap2::Mmap;
+
+use super::{Batch, DataFile};
+
+/// Reads batches of a memory mapped POS data file.
+pub(crate) struct MmapReader {
+    mmap: Mmap,
+    file: DataFile,
+    /// Bytes of the file read so far (counting from [DataFile::offset]).
+    done: u64,
+    batch_size: usize,
+}
+
+impl MmapReader {
+    pub(crate) fn new(file: DataFile, batch_size: usize) -> std::io::Result<Self> {
+        // SAFETY: the POS data files are not supposed to be modified while proving.
+        // If they are (i.e. truncated), reading them might crash the process.
+        let mmap = unsafe { Mmap::map(&file.file)? };
+        #[cfg(unix)]
+        mmap.advise(memmap2::Advice::Sequential)?;
+        Ok(Self {
+            mmap,
+            file,
+            done: 0,
+            batch_size,
+        })
+    }
+}
+
+impl Iterator for MmapReader {
+    type Item = Batch;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done >= self.file.size {
+            return None;
+        }
+        if self.done == 0 {
+            log::info!("Reading file: {}", self.file.name);
+        }
+        let start = (self.file.offset + self.done) as usize;
+        let end = (start + self.batch_size)
+            .min((self.file.offset + self.file.size) as usize)
+            .min(self.mmap.len());
+        if start >= end {
+            return None;
+        }
+        let batch = Batch {
+            data: self.mmap[start..end].to_vec(),
+            pos: self.file.pos + self.done,
+        };
+        self.done += batch.data.len() as u64;
+        Some(batch)
+    }
+}
@@ -0,0 +1,149 @@
+//! Proving from POS data stored elsewhere than in files in the POS data directory.
+
+use std::{fs::File, io, path::Path};
+
+use super::{pos_files, Batch};
+
+const LABEL_SIZE: u64 = 16;
+
+/// Source of the labels of POS data, e.g. a network block storage,
+/// an object storage or a custom archival format.
+pub trait LabelSource: Send + Sync {
+    /// Read the labels starting at the label `index` into `buf`.
+    ///
+    /// Returns the number of bytes read, which is smaller than the size of `buf`
+    /// only at the end of the POS data.
+    fn read_labels(&self, index: u64, buf: &mut [u8]) -> io::Result<usize>;
+}
+
+impl std::fmt::Debug for dyn LabelSource + '_ {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("LabelSource")
+    }
+}
+
+/// The POS data files (`postdata_N.bin`) in a directory as a [LabelSource].
+#[derive(Debug)]
+pub struct PosFiles {
+    files: Vec<File>,
+    /// Size (in bytes) of every file but the last.
+    file_size: u64,
+}
+
+impl PosFiles {
+    pub fn open(datadir: &Path, file_size: u64) -> eyre::Result<Self> {
+        eyre::ensure!(
+            file_size > 0 && file_size % LABEL_SIZE == 0,
+            "file size must be a positive multiple of {LABEL_SIZE} bytes"
+        );
+        let files = pos_files(datadir)?
+            .map(|entry| File::open(entry.path()))
+            .collect::<io::Result<_>>()?;
+        Ok(Self { files, file_size })
+    }
+}
+
+#[cfg(unix)]
+fn read_at(file: &File, buf: &mut [u8], offset: u64) -> io::Result<usize> {
+    std::os::unix::fs::FileExt::read_at(file, buf, offset)
+}
+
+#[cfg(windows)]
+fn read_at(file: &File, buf: &mut [u8], offset: u64) -> io::Result<usize> {
+    std::os::windows::fs::FileExt::seek_read(file, buf, offset)
+}
+
+impl LabelSource for PosFiles {
+    fn read_labels(&self, index: u64, buf: &mut [u8]) -> io::Result<usize> {
+        let mut pos = index * LABEL_SIZE;
+        let mut read = 0;
+        while read < buf.len() {
+            let Some(file) = self.files.get((pos / self.file_size) as usize) else {
+                break;
+            };
+            let offset = pos % self.file_size;
+            let len = (buf.len() - read).min((self.file_size - offset) as usize);
+            let n = match read_at(file, &mut buf[read..read + len], offset) {
+                Ok(0) => break,
+                Ok(n) => n,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e),
+            };
+            read += n;
+            pos += n as u64;
+        }
+        Ok(read)
+    }
+}
+
+/// Read the labels of the `source` from the position `offset` (in bytes) on,
+/// in batches of `batch_size` bytes. Reading stops at the `total_size` (in bytes),
+/// the end of the POS data or on the first error.
+pub(crate) fn read_source(
+    source: &dyn LabelSource,
+    batch_size: usize,
+    offset: u64,
+    total_size: u64,
+) -> impl Iterator<Item = Batch> + Send + '_ {
+    let mut pos = offset;
+    std::iter::from_fn(move || {
+        let size = total_size.saturating_sub(pos).min(batch_size as u64) as usize;
+        if size == 0 {
+            return None;
+        }
+        let mut data = vec![0u8; size];
+        match source.read_labels(pos / LABEL_SIZE, &mut data) {
+            Ok(0) => None,
+            Ok(n) => {
+                data.truncate(n);
+                let batch = Batch { data, pos };
+                pos += n as u64;
+                Some(batch)
+            }
+            Err(e) => {
+                log::error!("failed to read labels at position {pos}: {e}");
+                None
+            }
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use tempfile::tempdir;
+
+    use super::*;
+
+    #[test]
+    fn reading_labels_across_files() {
+        let datadir = tempdir().unwrap();
+        let data = (0..=255u8).cycle().take(16 * 10).collect::<Vec<_>>();
+        for (i, part) in data.chunks(16 * 4).enumerate() {
+            std::fs::write(datadir.path().join(format!("postdata_{i}.bin")), part).unwrap();
+        }
+        let source = PosFiles::open(datadir.path(), 16 * 4).unwrap();
+
+        let mut buf = [0u8; 16 * 3];
+        assert_eq!(buf.len(), source.read_labels(3, &mut buf).unwrap());
+        assert_eq!(&data[16 * 3..16 * 6], buf);
+
+        // Reading past the end
+        assert_eq!(16, source.read_labels(9, &mut buf).unwrap());
+        assert_eq!(&data[16 * 9..], &buf[..16]);
+        assert_eq!(0, source.read_labels(10, &mut buf).unwrap());
+    }
+
+    #[test]
+    fn reading_source_in_batches() {
+        let datadir = tempdir().unwrap();
+        let data = (0..=255u8).cycle().take(16 * 10).collect::<Vec<_>>();
+        std::fs::write(datadir.path().join("postdata_0.bin"), &data).unwrap();
+        let source = PosFiles::open(datadir.path(), 16 * 10).unwrap();
+
+        let batches = read_source(&source, 64, 32, 16 * 9).collect::<Vec<_>>();
+        let positions = batches.iter().map(|b| b.pos).collect::<Vec<_>>();
+        assert_eq!(vec![32, 96], positions);
+        let read = batches.into_iter().flat_map(|b| b.data).collect::<Vec<_>>();
+        assert_eq!(&data[32..16 * 9], read);
+    }
+}
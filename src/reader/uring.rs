@@ -0,0 +1,303 @@
+//! Reading POS data with io_uring.
+//!
+//! Keeping many reads in flight lets NVMe drives serve them in parallel,
+//! which plain sequential reads can't do.
+
+use std::{
+    collections::VecDeque,
+    io,
+    os::fd::{AsRawFd, RawFd},
+};
+
+use io_uring::{opcode, types, IoUring};
+
+use super::{Batch, DataFile};
+
+struct PendingRead {
+    buf: Vec<u8>,
+    pos: u64,
+    fd: RawFd,
+    /// Offset of the buffer in the file.
+    offset: u64,
+    /// Bytes of the buffer read so far, the rest is read again after a short read.
+    filled: usize,
+    /// Result of the completed read.
+    result: Option<i32>,
+}
+
+impl PendingRead {
+    /// Submission queue entry reading the rest of the buffer.
+    fn entry(&mut self, token: u64) -> io_uring::squeue::Entry {
+        let rest = &mut self.buf[self.filled..];
+        opcode::Read::new(types::Fd(self.fd), rest.as_mut_ptr(), rest.len() as u32)
+            .offset(self.offset + self.filled as u64)
+            .build()
+            .user_data(token)
+    }
+}
+
+/// Reads batches of POS data in order, keeping up to `queue_depth` reads in flight.
+pub(crate) struct UringReader {
+    ring: IoUring,
+    files: Vec<DataFile>,
+    /// Index of the file to submit reads from.
+    current: usize,
+    /// Offset (relative to [DataFile::offset]) of the next read to submit in the current file.
+    submitted: u64,
+    /// Reads in flight, in the order of submission.
+    pending: VecDeque<PendingRead>,
+    /// Token of the first pending read (tokens are assigned sequentially).
+    first_token: u64,
+    batch_size: usize,
+    queue_depth: usize,
+    /// Set after an error, no more batches are read.
+    failed: bool,
+}
+
+impl UringReader {
+    pub(crate) fn new(
+        files: Vec<DataFile>,
+        batch_size: usize,
+        queue_depth: usize,
+    ) -> io::Result<Self> {
+        let queue_depth = queue_depth.max(1);
+        let entries = u32::try_from(queue_depth.next_power_of_two())
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+        Ok(Self {
+            ring: IoUring::new(entries)?,
+            files,
+            current: 0,
+            submitted: 0,
+            pending: VecDeque::with_capacity(queue_depth),
+            first_token: 0,
+            batch_size,
+            queue_depth,
+            failed: false,
+        })
+    }
+
+    /// Push the read of the rest of the pending read with the `token` to the submission queue.
+    fn submit(&mut self, token: u64) {
+        let idx = (token - self.first_token) as usize;
+        let entry = self.pending[idx].entry(token);
+        // SAFETY: the buffer is kept in `pending` (its heap allocation doesn't move)
+        // and the file is kept open until the read completes.
+        unsafe {
+            self.ring
+                .submission()
+                .push(&entry)
+                .expect("submission queue is full");
+        }
+    }
+
+    /// Submit the next read to the submission queue.
+    /// Returns false if there is nothing more to read.
+    fn push_next(&mut self) -> bool {
+        while let Some(file) = self.files.get(self.current) {
+            if self.submitted >= file.size {
+                log::debug!("submitted all reads of {}", file.name);
+                self.current += 1;
+                self.submitted = 0;
+                continue;
+            }
+            if self.submitted == 0 {
+                log::info!("Reading file: {}", file.name);
+            }
+            let len = (file.size - self.submitted).min(self.batch_size as u64) as usize;
+            let token = self.first_token + self.pending.len() as u64;
+            self.pending.push_back(PendingRead {
+                buf: vec![0u8; len],
+                pos: file.pos + self.submitted,
+                fd: file.file.as_raw_fd(),
+                offset: file.offset + self.submitted,
+                filled: 0,
+                result: None,
+            });
+            self.submit(token);
+            self.submitted += len as u64;
+            return true;
+        }
+        false
+    }
+
+    /// Wait for at least one read to complete and record the results.
+    fn wait(&mut self) -> io::Result<()> {
+        self.ring.submit_and_wait(1)?;
+        for cqe in self.ring.completion() {
+            let idx = (cqe.user_data() - self.first_token) as usize;
+            self.pending[idx].result = Some(cqe.result());
+        }
+        Ok(())
+    }
+
+    /// Take the batch of the first pending read once it completes.
+    /// Short reads are resubmitted until the batch is read whole or the end of the file is reached.
+    fn next_batch(&mut self) -> Option<io::Result<Batch>> {
+        loop {
+            while self.pending.len() < self.queue_depth && self.push_next() {}
+
+            let read = self.pending.front_mut()?;
+            match read.result.take() {
+                None => {
+                    if let Err(err) = self.wait() {
+                        return Some(Err(err));
+                    }
+                }
+                Some(n) if n < 0 => {
+                    let err = io::Error::from_raw_os_error(-n);
+                    if err.kind() == io::ErrorKind::Interrupted {
+                        self.submit(self.first_token);
+                        continue;
+                    }
+                    let pos = read.pos + read.filled as u64;
+                    self.pending.pop_front();
+                    self.first_token += 1;
+                    return Some(Err(io::Error::new(
+                        err.kind(),
+                        format!("reading POS data at {pos}: {err}"),
+                    )));
+                }
+                Some(n) => {
+                    read.filled += n as usize;
+                    if n > 0 && read.filled < read.buf.len() {
+                        self.submit(self.first_token);
+                        continue;
+                    }
+                    let mut read = self.pending.pop_front().unwrap();
+                    self.first_token += 1;
+                    // The file was truncated in the meantime.
+                    if read.filled == 0 {
+                        continue;
+                    }
+                    read.buf.truncate(read.filled);
+                    return Some(Ok(Batch {
+                        data: read.buf,
+                        pos: read.pos,
+                    }));
+                }
+            }
+        }
+    }
+}
+
+impl Iterator for UringReader {
+    type Item = io::Result<Batch>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.failed {
+            return None;
+        }
+        let batch = self.next_batch();
+        self.failed = matches!(batch, Some(Err(_)));
+        batch
+    }
+}
+
+impl Drop for UringReader {
+    fn drop(&mut self) {
+        // The kernel might still write to the buffers of the reads in flight.
+        while self.pending.iter().any(|read| read.result.is_none()) {
+            if let Err(err) = self.wait() {
+                log::error!("failed to wait for io_uring completions: {err}");
+                // Leak the buffers rather than risk them being written after free.
+                std::mem::forget(std::mem::take(&mut self.pending));
+                return;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::File;
+
+    use super::UringReader;
+    use crate::config::FileLayout;
+    use crate::reader::{read_data, DataFile, ParallelReads, ReaderBackend};
+
+    fn data_file(file: File, size: u64) -> DataFile {
+        DataFile {
+            file,
+            pos: 0,
+            offset: 0,
+            size,
+            name: "postdata_0.bin".into(),
+            disk: 0,
+        }
+    }
+
+    #[test]
+    fn reading_with_deep_queue() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let data = (0..=255u8).cycle().take(10_000).collect::<Vec<_>>();
+        for (i, part) in data.chunks(4096).enumerate() {
+            std::fs::write(tmp_dir.path().join(format!("postdata_{i}.bin")), part).unwrap();
+        }
+
+        let mut read = Vec::new();
+        for batch in read_data(
+            tmp_dir.path(),
+            100,
+            FileLayout {
+                max_file_size: 4096,
+                ..Default::default()
+            },
+            0..u64::MAX,
+            ReaderBackend::IoUring,
+            8,
+            ParallelReads::Off,
+            false,
+        )
+        .unwrap()
+        {
+            assert_eq!(read.len() as u64, batch.pos);
+            read.extend(batch.data);
+        }
+        assert_eq!(data, read);
+    }
+
+    #[test]
+    fn dropping_reader_with_reads_in_flight() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        std::fs::write(tmp_dir.path().join("postdata_0.bin"), vec![1u8; 4096]).unwrap();
+
+        let mut reader = read_data(
+            tmp_dir.path(),
+            16,
+            FileLayout {
+                max_file_size: 4096,
+                ..Default::default()
+            },
+            0..u64::MAX,
+            ReaderBackend::IoUring,
+            4,
+            ParallelReads::Off,
+            false,
+        )
+        .unwrap();
+        assert_eq!(vec![1u8; 16], reader.next().unwrap().data);
+        drop(reader);
+    }
+
+    #[test]
+    fn reading_past_end_of_truncated_file() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let path = tmp_dir.path().join("postdata_0.bin");
+        std::fs::write(&path, [7u8; 10]).unwrap();
+
+        let file = data_file(File::open(&path).unwrap(), 16);
+        let mut reader = UringReader::new(vec![file], 16, 4).unwrap();
+        assert_eq!(vec![7u8; 10], reader.next().unwrap().unwrap().data);
+        assert!(reader.next().is_none());
+    }
+
+    #[test]
+    fn returning_read_errors() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        // Reading a directory fails with EISDIR.
+        let file = data_file(File::open(tmp_dir.path()).unwrap(), 16);
+        let mut reader = UringReader::new(vec![file], 16, 4).unwrap();
+        reader.next().unwrap().unwrap_err();
+        assert!(reader.next().is_none());
+    }
+}
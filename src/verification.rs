@@ -1,6 +1,11 @@
 //! # Proof verification
 //!
-//! ## Steps to verify a proof:
+//! The proofs are verified according to their [ProofVersion].
+//!
+//! ## Steps to verify a proof (V1):
+//!
+//! V2 proofs are verified the same way, only the nonce group in the PoW input
+//! is wider (see [ProofVersion::V2]).
 //!
 //! 1. verify PoW
 //! 2. verify number of indices == K2
@@ -25,6 +30,9 @@
 //! ```
 //! indices[0..K3] now contains randomly picked values
 //!
+//! [sampled_indices] returns the K3 indices selected for a proof, so that
+//! the selection can be cross-checked (see also [select_indices]).
+//!
 //! ## Verifying K3 indexes
 //!
 //! We must check if every index satisfies the difficulty condition.
@@ -35,47 +43,115 @@
 //!     - encrypt it with AES,
 //!     - convert AES output to u64,
 //!     - compare it with difficulty.
-use std::cmp::Ordering;
+//!
+//! ## Auditing proofs
+//!
+//! The owner of the POS data can check all K2 indices of a proof against the labels
+//! actually stored in it (see [Verifier::audit]), i.e. to make sure that the data
+//! and the prover are sound.
+use std::{
+    cmp::Ordering,
+    collections::VecDeque,
+    fs::File,
+    io::{Read, Seek, SeekFrom},
+    path::Path,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
 
 use cipher::BlockEncrypt;
 use itertools::Itertools;
 use log::debug;
+use rayon::prelude::{IndexedParallelIterator, IntoParallelIterator, ParallelIterator};
 
 use crate::{
     cipher::AesCipher,
     compression::{decompress_indexes, required_bits},
     config::{InitConfig, ProofConfig},
     difficulty::{proving_difficulty, scale_pow_difficulty},
+    encryption::DataKey,
     initialize::{calc_commitment, generate_label},
-    metadata::ProofMetadata,
-    pow::PowVerifier,
-    prove::{Proof, Prover8_56},
+    metadata::{PostMetadata, ProofMetadata},
+    pow::{self, PowVerifier},
+    prove::{Proof, ProofVersion, Prover8_56},
     random_values_gen::RandomValuesIterator,
 };
 
+mod distributed;
+mod invalidity;
+mod pool;
+
+pub use distributed::{LabelJob, LabelWorker, LocalLabelWorker};
+pub use invalidity::{InvalidityProof, InvalidityProofError};
+pub use pool::{PendingVerification, QueueFull, VerifierPool};
+
 const NONCES_PER_AES: u32 = Prover8_56::NONCES_PER_AES;
 
 pub struct Verifier {
     pow_verifier: Box<dyn PowVerifier + Send + Sync>,
+    /// Maximal number of proofs in the `valid` cache, '0' disables it.
+    cache_capacity: usize,
+    /// Keys (see [cache_key]) of the proofs recently verified fully as valid,
+    /// the least recently used first.
+    valid: Mutex<VecDeque<[u8; 32]>>,
+    timings_observer: Option<Box<dyn Fn(VerificationTimings) + Send + Sync>>,
+    /// Compute the labels of the verified indices if set, see [distributed].
+    label_workers: Vec<Box<dyn LabelWorker>>,
+}
+
+/// Time spent in the steps of verifying a proof, zero for the steps not reached.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct VerificationTimings {
+    /// Verifying the proof of work.
+    pub pow: Duration,
+    /// Decoding the indices and selecting the k3 of them to verify.
+    pub indices: Duration,
+    /// Recomputing and checking the labels of the selected indices.
+    pub labels: Duration,
+}
+
+/// How thoroughly a proof is verified.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum VerifyLevel {
+    /// Only the metadata, the proof of work and the number of indices,
+    /// without recomputing any labels.
+    PowOnly,
+    /// Like [VerifyLevel::Full], but only the first (up to k3) of the k3 indices
+    /// selected for verification.
+    Sampled(u32),
+    /// All the k3 indices selected for verification.
+    #[default]
+    Full,
 }
 
 #[derive(thiserror::Error, Debug)]
 pub enum Error {
-    #[error("nonce group (0) out of bounds (max 255)")]
+    #[error("nonce group {0} out of bounds of the proof version (max 255 in V1, min 256 in V2)")]
     NonceGroupOutOfBounds(u32),
-    #[error("invalid proof of work")]
-    InvalidPoW(#[from] crate::pow::Error),
+    #[error("invalid proof of work {pow} for nonce group {nonce_group}: {source}")]
+    InvalidPoW {
+        nonce_group: u32,
+        pow: u64,
+        #[source]
+        source: crate::pow::Error,
+    },
     #[error("invalid number of indices (expected: {expected}, got: {got})")]
     InvalidIndicesLen { expected: usize, got: usize },
-    #[error("MSB value for index: {index} doesn't satisfy difficulty: {msb} > {difficulty_msb} (label: {label:?})")]
+    #[error("MSB value for index: {index} (index #{position}) doesn't satisfy difficulty: {msb} > {difficulty_msb} (label: {label:?})")]
     InvalidMsb {
+        /// Position of the index among the verified indices, i.e. the k3 indices
+        /// selected for verification.
+        position: usize,
         index: u64,
         msb: u8,
         difficulty_msb: u8,
         label: [u8; 16],
     },
-    #[error("LSB value for index: {index} doesn't satisfy difficulty: {lsb} >= {difficulty_lsb} (label: {label:?})")]
+    #[error("LSB value for index: {index} (index #{position}) doesn't satisfy difficulty: {lsb} >= {difficulty_lsb} (label: {label:?})")]
     InvalidLsb {
+        /// Position of the index among the verified indices, i.e. the k3 indices
+        /// selected for verification.
+        position: usize,
         index: u64,
         lsb: u64,
         difficulty_lsb: u64,
@@ -83,8 +159,33 @@ pub enum Error {
     },
     #[error(transparent)]
     InvalidMetadata(#[from] MetadataValidationError),
-    #[error("invalid number of labels: (0)")]
+    #[error("invalid number of labels: {0}")]
     InvalidNumLabels(String),
+    #[error("failed to compute labels on a worker: {0}")]
+    LabelWorker(String),
+}
+
+/// Error of auditing a proof against the POS data (see [Verifier::audit]).
+#[derive(thiserror::Error, Debug)]
+pub enum AuditError {
+    #[error("invalid proof: {0}")]
+    Invalid(#[from] Error),
+    #[error("POS data doesn't match the proof: {0}")]
+    Data(#[from] eyre::Error),
+    #[error("failed to read the label at index {index}: {source}")]
+    Read {
+        index: u64,
+        #[source]
+        source: std::io::Error,
+    },
+    #[error("label stored at index: {index} (index #{position}) doesn't match the expected label: {stored:?} != {expected:?}")]
+    LabelMismatch {
+        /// Position of the index among the k2 indices of the proof.
+        position: usize,
+        index: u64,
+        stored: [u8; 16],
+        expected: [u8; 16],
+    },
 }
 
 #[derive(thiserror::Error, Debug)]
@@ -116,7 +217,40 @@ pub fn verify_metadata(
 
 impl Verifier {
     pub fn new(pow_verifier: Box<dyn PowVerifier + Send + Sync>) -> Self {
-        Self { pow_verifier }
+        Self {
+            pow_verifier,
+            cache_capacity: 0,
+            valid: Mutex::new(VecDeque::new()),
+            timings_observer: None,
+            label_workers: Vec::new(),
+        }
+    }
+
+    /// Recompute the labels of the verified indices on the `workers`
+    /// (i.e. other processes or machines) instead of in this process.
+    /// The indices are split evenly between the workers. No workers (the default)
+    /// recompute the labels in this process.
+    pub fn set_label_workers(&mut self, workers: Vec<Box<dyn LabelWorker>>) {
+        self.label_workers = workers;
+    }
+
+    /// Report the [VerificationTimings] of every verified proof (not found in the cache)
+    /// to the `observer`, i.e. to size the verification capacity.
+    pub fn set_timings_observer(
+        &mut self,
+        observer: impl Fn(VerificationTimings) + Send + Sync + 'static,
+    ) {
+        self.timings_observer = Some(Box::new(observer));
+    }
+
+    /// Remember up to `capacity` proofs verified as valid (the least recently used
+    /// are forgotten first), so that verifying them again (i.e. gossiped duplicates
+    /// or retries) is instant. Invalid proofs are not cached. '0' disables the cache.
+    pub fn set_cache_capacity(&mut self, capacity: usize) {
+        self.cache_capacity = capacity;
+        let valid = self.valid.get_mut().unwrap();
+        let evicted = valid.len().saturating_sub(capacity);
+        valid.drain(..evicted);
     }
 
     /// Verify if a proof is valid.
@@ -133,6 +267,140 @@ impl Verifier {
         metadata: &ProofMetadata,
         cfg: &ProofConfig,
         init_cfg: &InitConfig,
+    ) -> Result<(), Error> {
+        self.verify_at_level(proof, metadata, cfg, init_cfg, VerifyLevel::Full)
+    }
+
+    /// Verify a proof only as thoroughly as the `level`, i.e. to cheaply filter out
+    /// obviously invalid proofs before verifying the rest fully.
+    pub fn verify_at_level(
+        &self,
+        proof: &Proof,
+        metadata: &ProofMetadata,
+        cfg: &ProofConfig,
+        init_cfg: &InitConfig,
+        level: VerifyLevel,
+    ) -> Result<(), Error> {
+        if self.cache_capacity == 0 {
+            return self.verify_uncached(proof, metadata, cfg, init_cfg, level);
+        }
+        let key = cache_key(proof, metadata, cfg, init_cfg);
+        {
+            let mut valid = self.valid.lock().unwrap();
+            if let Some(position) = valid.iter().position(|k| *k == key) {
+                valid.remove(position);
+                valid.push_back(key);
+                debug!("proof found in the cache of valid proofs");
+                return Ok(());
+            }
+        }
+        self.verify_uncached(proof, metadata, cfg, init_cfg, level)?;
+        if level == VerifyLevel::Full {
+            let mut valid = self.valid.lock().unwrap();
+            if valid.len() >= self.cache_capacity {
+                valid.pop_front();
+            }
+            valid.push_back(key);
+        }
+        Ok(())
+    }
+
+    /// Audit a proof of the POS data in the `datadir` (decrypted with the `key`).
+    ///
+    /// Besides verifying the proof, it checks that every one of its k2 indices,
+    /// not only the k3 selected for verification, points to the label actually stored
+    /// in the POS data and that the label satisfies the difficulty.
+    /// It's meant for checking one's own proofs and POS data, not the proofs of others.
+    pub fn audit(
+        &self,
+        proof: &Proof,
+        metadata: &ProofMetadata,
+        cfg: &ProofConfig,
+        init_cfg: &InitConfig,
+        datadir: &Path,
+        key: Option<&DataKey>,
+    ) -> Result<(), AuditError> {
+        self.verify(proof, metadata, cfg, init_cfg)?;
+        let post_metadata = load_audited_metadata(datadir, metadata, key)?;
+
+        let num_labels = metadata.num_units as u64 * init_cfg.labels_per_unit;
+        let indices = match proof.version {
+            ProofVersion::V1 | ProofVersion::V2 => {
+                decompress_indexes(&proof.indices, required_bits(num_labels))
+                    .take(cfg.k2 as usize)
+                    .collect_vec()
+            }
+        };
+        let commitment = calc_commitment(&metadata.node_id, &metadata.commitment_atx_id);
+        let label_verifier = LabelVerifier::new(proof, metadata, cfg, num_labels)?;
+        // With label workers, all the labels are recomputed on them upfront.
+        let recomputed = match self.label_workers.is_empty() {
+            true => None,
+            false => Some(distributed::compute_labels(
+                &self.label_workers,
+                &commitment,
+                init_cfg.scrypt,
+                &indices,
+            )?),
+        };
+
+        let audit_index = |(position, index): (usize, u64)| -> Result<(), AuditError> {
+            let stored = read_label(datadir, &post_metadata, index, key)
+                .map_err(|source| AuditError::Read { index, source })?;
+            let expected = match &recomputed {
+                Some(labels) => labels[position],
+                None => generate_label(&commitment, init_cfg.scrypt, index),
+            };
+            if stored != expected {
+                return Err(AuditError::LabelMismatch {
+                    position,
+                    index,
+                    stored,
+                    expected,
+                });
+            }
+            Ok(label_verifier.verify(position, index, stored)?)
+        };
+        let invalid = indices
+            .into_par_iter()
+            .enumerate()
+            .find_map_first(|index| audit_index(index).err());
+        match invalid {
+            Some(err) => Err(err),
+            None => Ok(()),
+        }
+    }
+
+    fn verify_uncached(
+        &self,
+        proof: &Proof,
+        metadata: &ProofMetadata,
+        cfg: &ProofConfig,
+        init_cfg: &InitConfig,
+        level: VerifyLevel,
+    ) -> Result<(), Error> {
+        let mut timings = VerificationTimings::default();
+        let result = match proof.version {
+            ProofVersion::V1 | ProofVersion::V2 => {
+                self.verify_v1(proof, metadata, cfg, init_cfg, level, &mut timings)
+            }
+        };
+        if let Some(observer) = &self.timings_observer {
+            observer(timings);
+        }
+        result
+    }
+
+    /// Verify a V1 proof (or a V2 one, differing only in the nonce group of the PoW),
+    /// recording the time spent in the steps in the `timings`.
+    fn verify_v1(
+        &self,
+        proof: &Proof,
+        metadata: &ProofMetadata,
+        cfg: &ProofConfig,
+        init_cfg: &InitConfig,
+        level: VerifyLevel,
+        timings: &mut VerificationTimings,
     ) -> Result<(), Error> {
         verify_metadata(metadata, init_cfg)?;
 
@@ -145,15 +413,25 @@ impl Verifier {
             "verifying K2 pow for nonce group: {nonce_group} with difficulty: {:x?}",
             pow_difficulty
         );
-        self.pow_verifier.verify(
+        if ProofVersion::of_nonce_group(nonce_group) != proof.version {
+            return Err(Error::NonceGroupOutOfBounds(nonce_group));
+        }
+        let start = Instant::now();
+        let pow_result = pow::verify(
+            &*self.pow_verifier,
+            proof.version,
             proof.pow,
-            nonce_group
-                .try_into()
-                .map_err(|_| Error::NonceGroupOutOfBounds(nonce_group))?,
+            nonce_group,
             &challenge[..8].try_into().unwrap(),
             &pow_difficulty,
             &metadata.node_id,
-        )?;
+        );
+        timings.pow = start.elapsed();
+        pow_result.map_err(|source| Error::InvalidPoW {
+            nonce_group,
+            pow: proof.pow,
+            source,
+        })?;
 
         // Verify the number of indices against K2
         let num_labels = metadata.num_units as u64 * init_cfg.labels_per_unit;
@@ -165,70 +443,260 @@ impl Verifier {
                 got: proof.indices.len(),
             });
         }
+        let k3 = match level {
+            VerifyLevel::PowOnly => return Ok(()),
+            VerifyLevel::Sampled(k3) => k3.min(cfg.k3),
+            VerifyLevel::Full => cfg.k3,
+        };
 
+        let start = Instant::now();
         let indices_unpacked = decompress_indexes(&proof.indices, bits_per_index)
             .take(cfg.k2 as usize)
             .collect_vec();
         let commitment = calc_commitment(&metadata.node_id, &metadata.commitment_atx_id);
-        let cipher = AesCipher::new(&challenge, nonce_group, proof.pow);
-        let lazy_cipher = AesCipher::new_lazy(&challenge, proof.nonce, nonce_group, proof.pow);
+        let label_verifier = LabelVerifier::new(proof, metadata, cfg, num_labels)?;
 
-        let difficulty = proving_difficulty(cfg.k1, num_labels).map_err(Error::InvalidNumLabels)?;
-        let (difficulty_msb, difficulty_lsb) = Prover8_56::split_difficulty(difficulty);
+        // Select K3 indices
+        let k3_indices = select_indices(indices_unpacked, &k3_seed(proof, &challenge), k3 as usize);
+        timings.indices = start.elapsed();
+
+        // The indices are independent, recomputing their labels (scrypt) is done in parallel.
+        // The error of the first invalid index is reported, as if verified in order.
+        let verify_index = |(position, index): (usize, u64)| {
+            let label = generate_label(&commitment, init_cfg.scrypt, index);
+            label_verifier.verify(position, index, label)
+        };
+        let start = Instant::now();
+        let invalid =
+            if self.label_workers.is_empty() {
+                k3_indices
+                    .into_par_iter()
+                    .enumerate()
+                    .find_map_first(|index| verify_index(index).err())
+            } else {
+                let labels = distributed::compute_labels(
+                    &self.label_workers,
+                    &commitment,
+                    init_cfg.scrypt,
+                    &k3_indices,
+                )?;
+                k3_indices.into_iter().zip(labels).enumerate().find_map(
+                    |(position, (index, label))| {
+                        label_verifier.verify(position, index, label).err()
+                    },
+                )
+            };
+        timings.labels = start.elapsed();
+        match invalid {
+            Some(err) => Err(err),
+            None => Ok(()),
+        }
+    }
+}
 
-        let output_index = (proof.nonce % NONCES_PER_AES) as usize;
+/// Seed of selecting the k3 indices of the `proof` for verification
+/// (see [select_indices]).
+pub fn k3_seed(proof: &Proof, challenge: &[u8; 32]) -> Vec<u8> {
+    [
+        challenge.as_slice(),
+        &proof.nonce.to_le_bytes(),
+        proof.indices.as_ref(),
+        &proof.pow.to_le_bytes(),
+    ]
+    .concat()
+}
 
-        // Select K3 indices
-        let seed = &[
-            challenge.as_slice(),
-            &proof.nonce.to_le_bytes(),
-            proof.indices.as_ref(),
-            &proof.pow.to_le_bytes(),
-        ];
+/// Select `count` of the `indices` pseudo-randomly with the `seed`,
+/// the way the verifier selects the k3 indices to verify (see the module docs).
+///
+/// With an explicit seed, the selection can be reproduced by other implementations.
+pub fn select_indices(indices: Vec<u64>, seed: &[u8], count: usize) -> Vec<u64> {
+    RandomValuesIterator::new(indices, &[seed])
+        .take(count)
+        .collect_vec()
+}
+
+/// The k3 indices of the `proof` selected for verification, in the order verified
+/// (the `position` of [Error::InvalidMsb] and [Error::InvalidLsb]).
+///
+/// It lets auditors cross-check exactly which labels were verified.
+pub fn sampled_indices(
+    proof: &Proof,
+    metadata: &ProofMetadata,
+    cfg: &ProofConfig,
+    init_cfg: &InitConfig,
+) -> Result<Vec<u64>, Error> {
+    let num_labels = metadata.num_units as u64 * init_cfg.labels_per_unit;
+    let indices = match proof.version {
+        ProofVersion::V1 | ProofVersion::V2 => {
+            let bits_per_index = required_bits(num_labels);
+            let expected = expected_indices_bytes(bits_per_index, cfg.k2);
+            if proof.indices.len() != expected {
+                return Err(Error::InvalidIndicesLen {
+                    expected,
+                    got: proof.indices.len(),
+                });
+            }
+            decompress_indexes(&proof.indices, bits_per_index)
+                .take(cfg.k2 as usize)
+                .collect_vec()
+        }
+    };
+    let seed = k3_seed(proof, &metadata.challenge);
+    Ok(select_indices(indices, &seed, cfg.k3 as usize))
+}
 
-        let k3_indices = RandomValuesIterator::new(indices_unpacked, seed).take(cfg.k3 as usize);
+/// Load the metadata of the POS data in the `datadir`,
+/// ensuring that the proof with the `metadata` is of this POS data.
+fn load_audited_metadata(
+    datadir: &Path,
+    metadata: &ProofMetadata,
+    key: Option<&DataKey>,
+) -> eyre::Result<PostMetadata> {
+    let post_metadata = crate::metadata::load(datadir)?;
+    eyre::ensure!(
+        post_metadata.node_id == metadata.node_id
+            && post_metadata.commitment_atx_id == metadata.commitment_atx_id,
+        "POS data of another identity"
+    );
+    eyre::ensure!(
+        post_metadata.num_units == metadata.num_units,
+        "POS data has {} units, the proof {}",
+        post_metadata.num_units,
+        metadata.num_units
+    );
+    match (key, post_metadata.encryption_key_check) {
+        (Some(key), _) => key.validate(&post_metadata)?,
+        (None, Some(_)) => eyre::bail!("POS data is encrypted, the key is required"),
+        (None, None) => {}
+    }
+    Ok(post_metadata)
+}
 
-        k3_indices.into_iter().try_for_each(|index| {
-            let mut output = [0u8; 16];
-            let label = generate_label(&commitment, init_cfg.scrypt, index);
-            cipher
-                .aes
-                .encrypt_block_b2b(&label.into(), (&mut output).into());
-
-            let msb = output[output_index];
-            match msb.cmp(&difficulty_msb) {
-                Ordering::Less => {
-                    // valid
-                }
-                Ordering::Greater => {
-                    return Err(Error::InvalidMsb {
+/// Read the label at the `index` from the POS data.
+fn read_label(
+    datadir: &Path,
+    metadata: &PostMetadata,
+    index: u64,
+    key: Option<&DataKey>,
+) -> std::io::Result<[u8; 16]> {
+    let pos = index * 16;
+    let path = datadir.join(metadata.file_name((pos / metadata.max_file_size) as usize));
+    let mut file = File::open(path)?;
+    file.seek(SeekFrom::Start(pos % metadata.max_file_size))?;
+    let mut label = [0u8; 16];
+    file.read_exact(&mut label)?;
+    if let Some(key) = key {
+        key.apply_keystream(&mut label, pos);
+    }
+    Ok(label)
+}
+
+/// Checks if the labels of a proof satisfy the difficulty for its nonce.
+struct LabelVerifier {
+    cipher: AesCipher,
+    lazy_cipher: AesCipher,
+    output_index: usize,
+    difficulty_msb: u8,
+    difficulty_lsb: u64,
+}
+
+impl LabelVerifier {
+    fn new(
+        proof: &Proof,
+        metadata: &ProofMetadata,
+        cfg: &ProofConfig,
+        num_labels: u64,
+    ) -> Result<Self, Error> {
+        let nonce_group = proof.nonce / NONCES_PER_AES;
+        let difficulty = proving_difficulty(cfg.k1, num_labels).map_err(Error::InvalidNumLabels)?;
+        let (difficulty_msb, difficulty_lsb) = Prover8_56::split_difficulty(difficulty);
+        Ok(Self {
+            cipher: AesCipher::new(&metadata.challenge, nonce_group, proof.pow),
+            lazy_cipher: AesCipher::new_lazy(
+                &metadata.challenge,
+                proof.nonce,
+                nonce_group,
+                proof.pow,
+            ),
+            output_index: (proof.nonce % NONCES_PER_AES) as usize,
+            difficulty_msb,
+            difficulty_lsb,
+        })
+    }
+
+    /// Check the `label` of the `index` at the `position` among the verified indices.
+    fn verify(&self, position: usize, index: u64, label: [u8; 16]) -> Result<(), Error> {
+        let mut output = [0u8; 16];
+        self.cipher
+            .aes
+            .encrypt_block_b2b(&label.into(), (&mut output).into());
+
+        let msb = output[self.output_index];
+        match msb.cmp(&self.difficulty_msb) {
+            Ordering::Less => {
+                // valid
+            }
+            Ordering::Greater => {
+                return Err(Error::InvalidMsb {
+                    position,
+                    index,
+                    msb,
+                    difficulty_msb: self.difficulty_msb,
+                    label,
+                })
+            }
+            Ordering::Equal => {
+                // Need to check LSB
+                let mut output = [0u64; 2];
+                self.lazy_cipher
+                    .aes
+                    .encrypt_block_b2b(&label.into(), bytemuck::cast_slice_mut(&mut output).into());
+                let lsb = output[0].to_le() & 0x00ff_ffff_ffff_ffff;
+                if lsb >= self.difficulty_lsb {
+                    return Err(Error::InvalidLsb {
+                        position,
                         index,
-                        msb,
-                        difficulty_msb,
+                        lsb,
+                        difficulty_lsb: self.difficulty_lsb,
                         label,
-                    })
-                }
-                Ordering::Equal => {
-                    // Need to check LSB
-                    let mut output = [0u64; 2];
-                    lazy_cipher.aes.encrypt_block_b2b(
-                        &label.into(),
-                        bytemuck::cast_slice_mut(&mut output).into(),
-                    );
-                    let lsb = output[0].to_le() & 0x00ff_ffff_ffff_ffff;
-                    if lsb >= difficulty_lsb {
-                        return Err(Error::InvalidLsb {
-                            index,
-                            lsb,
-                            difficulty_lsb,
-                            label,
-                        });
-                    }
+                    });
                 }
             }
-            Ok(())
-        })
+        }
+        Ok(())
+    }
+}
+
+/// Hash of everything the validity of the `proof` depends on.
+fn cache_key(
+    proof: &Proof,
+    metadata: &ProofMetadata,
+    cfg: &ProofConfig,
+    init_cfg: &InitConfig,
+) -> [u8; 32] {
+    let mut hasher = blake3::Hasher::new();
+    hasher.update(&[proof.version as u8]);
+    hasher.update(&proof.nonce.to_le_bytes());
+    hasher.update(&(proof.indices.len() as u64).to_le_bytes());
+    hasher.update(&proof.indices);
+    hasher.update(&proof.pow.to_le_bytes());
+    hasher.update(&metadata.node_id);
+    hasher.update(&metadata.commitment_atx_id);
+    hasher.update(&metadata.challenge);
+    hasher.update(&metadata.num_units.to_le_bytes());
+    for n in [cfg.k1, cfg.k2, cfg.k3] {
+        hasher.update(&n.to_le_bytes());
+    }
+    hasher.update(&cfg.pow_difficulty);
+    for n in [init_cfg.min_num_units, init_cfg.max_num_units] {
+        hasher.update(&n.to_le_bytes());
+    }
+    hasher.update(&init_cfg.labels_per_unit.to_le_bytes());
+    for n in [init_cfg.scrypt.n, init_cfg.scrypt.r, init_cfg.scrypt.p] {
+        hasher.update(&(n as u64).to_le_bytes());
     }
+    hasher.finalize().into()
 }
 
 fn next_multiple_of(n: usize, mult: usize) -> usize {
@@ -248,17 +716,26 @@ fn expected_indices_bytes(required_bits: usize, k2: u32) -> usize {
 
 #[cfg(test)]
 mod tests {
-    use std::borrow::Cow;
+    use std::{
+        borrow::Cow,
+        sync::{Arc, Mutex},
+        time::Duration,
+    };
+
+    use mockall::predicate::{always, eq};
 
     use crate::{
         config::{InitConfig, ProofConfig, ScryptParams},
         metadata::ProofMetadata,
-        pow::MockPowVerifier,
-        prove::Proof,
+        pow::{MockPowVerifier, NoopPowVerifier},
+        prove::{Proof, ProofVersion},
         verification::Error,
     };
 
-    use super::{expected_indices_bytes, next_multiple_of, Verifier};
+    use super::{
+        expected_indices_bytes, k3_seed, next_multiple_of, sampled_indices, select_indices,
+        LocalLabelWorker, Verifier, VerifyLevel,
+    };
 
     #[test]
     fn test_next_mutliple_of() {
@@ -301,6 +778,7 @@ mod tests {
         let verifier = Verifier::new(pow_verifier);
         let result = verifier.verify(
             &Proof {
+                version: ProofVersion::V1,
                 nonce: 0,
                 indices: Cow::from(vec![1, 2, 3]),
                 pow: 0,
@@ -309,7 +787,14 @@ mod tests {
             &cfg,
             &init_cfg,
         );
-        assert!(matches!(result, Err(Error::InvalidPoW(_))));
+        assert!(matches!(
+            result,
+            Err(Error::InvalidPoW {
+                nonce_group: 0,
+                pow: 0,
+                ..
+            })
+        ));
     }
 
     #[test]
@@ -333,13 +818,10 @@ mod tests {
             challenge: [0u8; 32],
             num_units: 10,
         };
-        let mut pow_verifier = Box::new(MockPowVerifier::new());
-        pow_verifier
-            .expect_verify()
-            .returning(|_, _, _, _, _| Ok(()));
-        let verifier = Verifier::new(pow_verifier);
+        let verifier = Verifier::new(Box::new(NoopPowVerifier));
         {
             let empty_proof = Proof {
+                version: ProofVersion::V1,
                 nonce: 0,
                 indices: Cow::from(vec![]),
                 pow: 0,
@@ -355,15 +837,39 @@ mod tests {
         }
         {
             let nonce_out_of_bounds_proof = Proof {
+                version: ProofVersion::V1,
                 nonce: 256 * 16,
                 indices: Cow::from(vec![]),
                 pow: 0,
             };
             let res = verifier.verify(&nonce_out_of_bounds_proof, &fake_metadata, &pcfg, &icfg);
             assert!(matches!(res, Err(Error::NonceGroupOutOfBounds(256))));
+
+            let narrow_v2_proof = Proof {
+                version: ProofVersion::V2,
+                nonce: 255 * 16,
+                ..nonce_out_of_bounds_proof.clone()
+            };
+            let res = verifier.verify(&narrow_v2_proof, &fake_metadata, &pcfg, &icfg);
+            assert!(matches!(res, Err(Error::NonceGroupOutOfBounds(255))));
+
+            let mut pow_verifier = Box::new(MockPowVerifier::new());
+            pow_verifier
+                .expect_verify_wide()
+                .with(always(), eq(256), always(), always(), always())
+                .returning(|_, _, _, _, _| Ok(()));
+            let verifier = Verifier::new(pow_verifier);
+            let wide_proof = Proof {
+                version: ProofVersion::V2,
+                ..nonce_out_of_bounds_proof
+            };
+            let res = verifier.verify(&wide_proof, &fake_metadata, &pcfg, &icfg);
+            // Passes the nonce group check, but has no indices
+            assert!(matches!(res, Err(Error::InvalidIndicesLen { .. })));
         }
         {
             let proof_with_not_enough_indices = Proof {
+                version: ProofVersion::V1,
                 nonce: 0,
                 indices: Cow::from(vec![1, 2, 3]),
                 pow: 0,
@@ -378,6 +884,256 @@ mod tests {
                 })
             ));
         }
+        {
+            // All indices point to label 0, which doesn't satisfy the difficulty.
+            let proof_with_invalid_indices = Proof {
+                version: ProofVersion::V1,
+                nonce: 0,
+                indices: Cow::from(vec![0; expected_indices_bytes(15, 10)]),
+                pow: 0,
+            };
+            let result = verifier.verify(&proof_with_invalid_indices, &fake_metadata, &pcfg, &icfg);
+            assert!(matches!(
+                result,
+                Err(Error::InvalidMsb {
+                    position: 0,
+                    index: 0,
+                    ..
+                }) | Err(Error::InvalidLsb {
+                    position: 0,
+                    index: 0,
+                    ..
+                })
+            ));
+        }
+    }
+
+    #[test]
+    fn verifying_at_levels() {
+        let cfg = ProofConfig {
+            k1: 10,
+            k2: 10,
+            k3: 10,
+            pow_difficulty: [0xFF; 32],
+        };
+        let init_cfg = InitConfig {
+            min_num_units: 1,
+            max_num_units: 10,
+            labels_per_unit: 2048,
+            scrypt: ScryptParams::new(4, 1, 1),
+        };
+        let metadata = ProofMetadata {
+            node_id: [0u8; 32],
+            commitment_atx_id: [0u8; 32],
+            challenge: [0u8; 32],
+            num_units: 10,
+        };
+        let mut pow_verifier = Box::new(MockPowVerifier::new());
+        pow_verifier
+            .expect_verify()
+            .returning(|_, _, _, _, _| Ok(()));
+        let verifier = Verifier::new(pow_verifier);
+        // The indices point to label 0, which doesn't satisfy the difficulty.
+        let proof = Proof {
+            version: ProofVersion::V1,
+            nonce: 0,
+            indices: Cow::from(vec![0; expected_indices_bytes(15, 10)]),
+            pow: 0,
+        };
+        let verify = |level| verifier.verify_at_level(&proof, &metadata, &cfg, &init_cfg, level);
+        assert!(verify(VerifyLevel::PowOnly).is_ok());
+        assert!(verify(VerifyLevel::Sampled(0)).is_ok());
+        assert!(verify(VerifyLevel::Sampled(1)).is_err());
+        assert!(verify(VerifyLevel::Full).is_err());
+
+        let short_proof = Proof {
+            indices: Cow::from(vec![0; 3]),
+            ..proof.clone()
+        };
+        let result = verifier.verify_at_level(
+            &short_proof,
+            &metadata,
+            &cfg,
+            &init_cfg,
+            VerifyLevel::PowOnly,
+        );
+        assert!(matches!(result, Err(Error::InvalidIndicesLen { .. })));
+    }
+
+    #[test]
+    fn verifying_with_label_workers() {
+        let cfg = ProofConfig {
+            k1: 10,
+            k2: 10,
+            k3: 10,
+            pow_difficulty: [0xFF; 32],
+        };
+        let init_cfg = InitConfig {
+            min_num_units: 1,
+            max_num_units: 10,
+            labels_per_unit: 2048,
+            scrypt: ScryptParams::new(4, 1, 1),
+        };
+        let metadata = ProofMetadata {
+            node_id: [0u8; 32],
+            commitment_atx_id: [0u8; 32],
+            challenge: [0u8; 32],
+            num_units: 10,
+        };
+        let proof = Proof::new(
+            0,
+            &[100, 200, 0, 300, 400, 500, 600, 700, 800, 900],
+            10 * 2048,
+            0,
+        );
+
+        let local = Verifier::new(Box::new(NoopPowVerifier));
+        let mut distributed = Verifier::new(Box::new(NoopPowVerifier));
+        distributed.set_label_workers(vec![Box::new(LocalLabelWorker), Box::new(LocalLabelWorker)]);
+        let expected = format!("{:?}", local.verify(&proof, &metadata, &cfg, &init_cfg));
+        let result = format!(
+            "{:?}",
+            distributed.verify(&proof, &metadata, &cfg, &init_cfg)
+        );
+        assert_eq!(expected, result);
+    }
+
+    #[test]
+    fn sampling_indices() {
+        let cfg = ProofConfig {
+            k1: 10,
+            k2: 10,
+            k3: 4,
+            pow_difficulty: [0xFF; 32],
+        };
+        let init_cfg = InitConfig {
+            min_num_units: 1,
+            max_num_units: 10,
+            labels_per_unit: 2048,
+            scrypt: ScryptParams::new(4, 1, 1),
+        };
+        let metadata = ProofMetadata {
+            node_id: [0u8; 32],
+            commitment_atx_id: [0u8; 32],
+            challenge: [7u8; 32],
+            num_units: 10,
+        };
+        let indices = (1..=10).map(|i| i * 100).collect::<Vec<u64>>();
+        let proof = Proof::new(3, &indices, 10 * 2048, 5);
+
+        let sampled = sampled_indices(&proof, &metadata, &cfg, &init_cfg).unwrap();
+        assert_eq!(4, sampled.len());
+        assert!(sampled.iter().all(|i| indices.contains(i)));
+        let seed = k3_seed(&proof, &metadata.challenge);
+        assert_eq!(sampled, select_indices(indices.clone(), &seed, 4));
+        assert_ne!(sampled, select_indices(indices, b"another seed", 4));
+
+        // The verifier reports the position of the invalid index among the sampled ones.
+        let verifier = Verifier::new(Box::new(NoopPowVerifier));
+        match verifier.verify(&proof, &metadata, &cfg, &init_cfg) {
+            Err(Error::InvalidMsb {
+                position, index, ..
+            })
+            | Err(Error::InvalidLsb {
+                position, index, ..
+            }) => assert_eq!(sampled[position], index),
+            result => panic!("unexpected result: {result:?}"),
+        }
+    }
+
+    #[test]
+    fn caching_valid_proofs() {
+        // No indices are checked with k3 = 0, a proof with a valid PoW is valid.
+        let cfg = ProofConfig {
+            k1: 10,
+            k2: 10,
+            k3: 0,
+            pow_difficulty: [0xFF; 32],
+        };
+        let init_cfg = InitConfig {
+            min_num_units: 1,
+            max_num_units: 10,
+            labels_per_unit: 2048,
+            scrypt: ScryptParams::new(4, 1, 1),
+        };
+        let metadata = ProofMetadata {
+            node_id: [0u8; 32],
+            commitment_atx_id: [0u8; 32],
+            challenge: [0u8; 32],
+            num_units: 10,
+        };
+        let mut pow_verifier = Box::new(MockPowVerifier::new());
+        pow_verifier
+            .expect_verify()
+            .times(3)
+            .returning(|pow, _, _, _, _| match pow {
+                0 => Ok(()),
+                _ => Err(crate::pow::Error::InvalidPoW),
+            });
+        let mut verifier = Verifier::new(pow_verifier);
+        verifier.set_cache_capacity(1);
+        let proof = Proof {
+            version: ProofVersion::V1,
+            nonce: 0,
+            indices: Cow::from(vec![0; expected_indices_bytes(15, 10)]),
+            pow: 0,
+        };
+        // Verified once, then cached.
+        for _ in 0..3 {
+            verifier.verify(&proof, &metadata, &cfg, &init_cfg).unwrap();
+        }
+        // Invalid proofs are verified every time.
+        let invalid = Proof {
+            pow: 1,
+            ..proof.clone()
+        };
+        for _ in 0..2 {
+            assert!(verifier
+                .verify(&invalid, &metadata, &cfg, &init_cfg)
+                .is_err());
+        }
+    }
+
+    #[test]
+    fn observing_timings() {
+        let cfg = ProofConfig {
+            k1: 10,
+            k2: 10,
+            k3: 10,
+            pow_difficulty: [0xFF; 32],
+        };
+        let init_cfg = InitConfig {
+            min_num_units: 1,
+            max_num_units: 10,
+            labels_per_unit: 2048,
+            scrypt: ScryptParams::new(4, 1, 1),
+        };
+        let metadata = ProofMetadata {
+            node_id: [0u8; 32],
+            commitment_atx_id: [0u8; 32],
+            challenge: [0u8; 32],
+            num_units: 10,
+        };
+        let mut pow_verifier = Box::new(MockPowVerifier::new());
+        pow_verifier
+            .expect_verify()
+            .returning(|_, _, _, _, _| Ok(()));
+        let mut verifier = Verifier::new(pow_verifier);
+        let reported = Arc::new(Mutex::new(Vec::new()));
+        verifier.set_timings_observer({
+            let reported = reported.clone();
+            move |timings| reported.lock().unwrap().push(timings)
+        });
+        let proof = Proof {
+            version: ProofVersion::V1,
+            nonce: 0,
+            indices: Cow::from(vec![0; expected_indices_bytes(15, 10)]),
+            pow: 0,
+        };
+        assert!(verifier.verify(&proof, &metadata, &cfg, &init_cfg).is_err());
+        let reported = reported.lock().unwrap();
+        assert_eq!(1, reported.len());
+        assert!(reported[0].labels > Duration::ZERO);
     }
 
     #[test]
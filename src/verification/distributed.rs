@@ -0,0 +1,170 @@
+//! Recomputing the labels of verified indices on other processes or machines.
+//!
+//! Recomputing the labels (scrypt) is the main cost of verifying proofs with a large k3
+//! and of auditing them. The [Verifier](super::Verifier) with [LabelWorker]s set
+//! (see [Verifier::set_label_workers](super::Verifier::set_label_workers)) splits
+//! the indices into a [LabelJob] per worker and merges the computed labels back in order,
+//! checking them itself. The jobs are serializable, so that a worker can send them
+//! to another process or machine running [LabelJob::run].
+//!
+//! The workers are trusted, the labels they compute are not checked again.
+
+use itertools::Itertools;
+use rayon::prelude::{IntoParallelIterator, IntoParallelRefIterator, ParallelIterator};
+use serde::{Deserialize, Serialize};
+use serde_with::{hex::Hex, serde_as};
+
+use super::Error;
+use crate::{config::ScryptParams, initialize::generate_label};
+
+/// Labels to recompute.
+#[serde_as]
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct LabelJob {
+    #[serde_as(as = "Hex")]
+    pub commitment: [u8; 32],
+    pub scrypt: ScryptParams,
+    pub indices: Vec<u64>,
+}
+
+impl LabelJob {
+    /// Compute the labels of the indices, in their order.
+    pub fn run(&self) -> Vec<[u8; 16]> {
+        self.indices
+            .par_iter()
+            .map(|&index| generate_label(&self.commitment, self.scrypt, index))
+            .collect()
+    }
+}
+
+/// Computes the labels of [LabelJob]s, i.e. by sending them to another machine.
+pub trait LabelWorker: Send + Sync {
+    /// Compute the labels of the `job`, in the order of its indices.
+    fn compute(&self, job: &LabelJob) -> eyre::Result<Vec<[u8; 16]>>;
+}
+
+/// A [LabelWorker] running the jobs in this process.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct LocalLabelWorker;
+
+impl LabelWorker for LocalLabelWorker {
+    fn compute(&self, job: &LabelJob) -> eyre::Result<Vec<[u8; 16]>> {
+        Ok(job.run())
+    }
+}
+
+/// Compute the labels of the `indices` on the `workers` (at least one),
+/// splitting the indices evenly between them.
+pub(super) fn compute_labels(
+    workers: &[Box<dyn LabelWorker>],
+    commitment: &[u8; 32],
+    scrypt: ScryptParams,
+    indices: &[u64],
+) -> Result<Vec<[u8; 16]>, Error> {
+    let chunk_size = indices.len().div_ceil(workers.len()).max(1);
+    let labels = indices
+        .chunks(chunk_size)
+        .zip(workers)
+        .collect_vec()
+        .into_par_iter()
+        .map(|(indices, worker)| {
+            let job = LabelJob {
+                commitment: *commitment,
+                scrypt,
+                indices: indices.to_vec(),
+            };
+            let labels = worker
+                .compute(&job)
+                .map_err(|e| Error::LabelWorker(format!("{e:#}")))?;
+            if labels.len() != indices.len() {
+                return Err(Error::LabelWorker(format!(
+                    "expected {} labels, got {}",
+                    indices.len(),
+                    labels.len()
+                )));
+            }
+            Ok(labels)
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    Ok(labels.concat())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    };
+
+    use super::*;
+
+    /// Runs the jobs locally, counting the labels computed.
+    struct CountingWorker(Arc<AtomicUsize>);
+
+    impl LabelWorker for CountingWorker {
+        fn compute(&self, job: &LabelJob) -> eyre::Result<Vec<[u8; 16]>> {
+            self.0.fetch_add(job.indices.len(), Ordering::SeqCst);
+            Ok(job.run())
+        }
+    }
+
+    struct FailingWorker;
+
+    impl LabelWorker for FailingWorker {
+        fn compute(&self, _: &LabelJob) -> eyre::Result<Vec<[u8; 16]>> {
+            Err(eyre::eyre!("worker is gone"))
+        }
+    }
+
+    #[test]
+    fn encoding_job() {
+        let job = LabelJob {
+            commitment: [7; 32],
+            scrypt: ScryptParams::new(2, 1, 1),
+            indices: vec![1, 5, 3],
+        };
+        let encoded = serde_json::to_string(&job).unwrap();
+        let decoded: LabelJob = serde_json::from_str(&encoded).unwrap();
+        assert_eq!(job, decoded);
+        assert_eq!(
+            vec![
+                generate_label(&[7; 32], job.scrypt, 1),
+                generate_label(&[7; 32], job.scrypt, 5),
+                generate_label(&[7; 32], job.scrypt, 3),
+            ],
+            decoded.run()
+        );
+    }
+
+    #[test]
+    fn splitting_between_workers() {
+        let scrypt = ScryptParams::new(2, 1, 1);
+        let computed = [Arc::default(), Arc::default(), Arc::default()];
+        let workers = computed
+            .iter()
+            .map(|c| Box::new(CountingWorker(Arc::clone(c))) as Box<dyn LabelWorker>)
+            .collect_vec();
+        let indices = (0..8).collect_vec();
+
+        let labels = compute_labels(&workers, &[1; 32], scrypt, &indices).unwrap();
+        let expected = LabelJob {
+            commitment: [1; 32],
+            scrypt,
+            indices,
+        }
+        .run();
+        assert_eq!(expected, labels);
+        assert_eq!(
+            vec![3, 3, 2],
+            computed
+                .iter()
+                .map(|c| c.load(Ordering::SeqCst))
+                .collect_vec()
+        );
+
+        let workers: Vec<Box<dyn LabelWorker>> =
+            vec![Box::new(LocalLabelWorker), Box::new(FailingWorker)];
+        let result = compute_labels(&workers, &[1; 32], scrypt, &[1, 2]);
+        assert!(matches!(result, Err(Error::LabelWorker(_))));
+    }
+}
@@ -0,0 +1,201 @@
+//! Compact proofs of the invalidity of proofs.
+//!
+//! Verifying a proof recomputes k3 labels. A proof rejected because one of them doesn't
+//! satisfy the difficulty can be shown invalid to others with just that label:
+//! the [InvalidityProof] carries the proof, the index and the parameters needed
+//! to recompute the selection of the k3 indices and the single label.
+//! [InvalidityProof::check] convinces the receiver without verifying the whole proof.
+
+use serde::{Deserialize, Serialize};
+use serde_with::{hex::Hex, serde_as};
+
+use super::{sampled_indices, Error, LabelVerifier};
+use crate::{
+    config::{InitConfig, ProofConfig, ScryptParams},
+    initialize::{calc_commitment, generate_label},
+    metadata::ProofMetadata,
+    prove::Proof,
+};
+
+/// Evidence that a proof is invalid: the index at the `position` among the k3 indices
+/// selected for verification points to the `label`, which doesn't satisfy the difficulty.
+#[serde_as]
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct InvalidityProof {
+    pub proof: Proof<'static>,
+    pub metadata: ProofMetadata,
+    pub k1: u32,
+    pub k2: u32,
+    pub k3: u32,
+    pub labels_per_unit: u64,
+    pub scrypt: ScryptParams,
+    pub position: usize,
+    pub index: u64,
+    #[serde_as(as = "Hex")]
+    pub label: [u8; 16],
+}
+
+/// Why an [InvalidityProof] doesn't prove the proof invalid.
+#[derive(thiserror::Error, Debug)]
+pub enum InvalidityProofError {
+    #[error("invalid proof: {0}")]
+    InvalidProof(#[source] Error),
+    #[error("index {index} is not at position {position} of the verified indices")]
+    NotVerified { position: usize, index: u64 },
+    #[error("label of index {index} doesn't match the recomputed label")]
+    LabelMismatch { index: u64 },
+    #[error("label of index {index} satisfies the difficulty")]
+    ValidLabel { index: u64 },
+}
+
+impl InvalidityProof {
+    /// Extract the invalidity proof from the `err` of verifying the `proof`,
+    /// if it was rejected because of the label of an index.
+    pub fn extract(
+        err: &Error,
+        proof: &Proof,
+        metadata: &ProofMetadata,
+        cfg: &ProofConfig,
+        init_cfg: &InitConfig,
+    ) -> Option<Self> {
+        let (position, index, label) = match err {
+            Error::InvalidMsb {
+                position,
+                index,
+                label,
+                ..
+            }
+            | Error::InvalidLsb {
+                position,
+                index,
+                label,
+                ..
+            } => (*position, *index, *label),
+            _ => return None,
+        };
+        Some(Self {
+            proof: Proof {
+                version: proof.version,
+                nonce: proof.nonce,
+                indices: proof.indices.clone().into_owned().into(),
+                pow: proof.pow,
+            },
+            metadata: metadata.clone(),
+            k1: cfg.k1,
+            k2: cfg.k2,
+            k3: cfg.k3,
+            labels_per_unit: init_cfg.labels_per_unit,
+            scrypt: init_cfg.scrypt,
+            position,
+            index,
+            label,
+        })
+    }
+
+    /// Check that the proof is invalid, recomputing a single label.
+    ///
+    /// The parameters in the invalidity proof must be checked to match the network's
+    /// by the receiver. The proof of work is not checked, it doesn't make the proof valid.
+    pub fn check(&self) -> Result<(), InvalidityProofError> {
+        let cfg = ProofConfig {
+            k1: self.k1,
+            k2: self.k2,
+            k3: self.k3,
+            pow_difficulty: [0xFF; 32],
+        };
+        let init_cfg = InitConfig {
+            min_num_units: self.metadata.num_units,
+            max_num_units: self.metadata.num_units,
+            labels_per_unit: self.labels_per_unit,
+            scrypt: self.scrypt,
+        };
+        let sampled = sampled_indices(&self.proof, &self.metadata, &cfg, &init_cfg)
+            .map_err(InvalidityProofError::InvalidProof)?;
+        if sampled.get(self.position) != Some(&self.index) {
+            return Err(InvalidityProofError::NotVerified {
+                position: self.position,
+                index: self.index,
+            });
+        }
+
+        let commitment = calc_commitment(&self.metadata.node_id, &self.metadata.commitment_atx_id);
+        if generate_label(&commitment, self.scrypt, self.index) != self.label {
+            return Err(InvalidityProofError::LabelMismatch { index: self.index });
+        }
+        let num_labels = self.metadata.num_units as u64 * self.labels_per_unit;
+        let label_verifier = LabelVerifier::new(&self.proof, &self.metadata, &cfg, num_labels)
+            .map_err(InvalidityProofError::InvalidProof)?;
+        match label_verifier.verify(self.position, self.index, self.label) {
+            Ok(()) => Err(InvalidityProofError::ValidLabel { index: self.index }),
+            Err(_) => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{pow::NoopPowVerifier, verification::Verifier};
+
+    #[test]
+    fn extracting_and_checking() {
+        let cfg = ProofConfig {
+            k1: 10,
+            k2: 10,
+            k3: 10,
+            pow_difficulty: [0xFF; 32],
+        };
+        let init_cfg = InitConfig {
+            min_num_units: 1,
+            max_num_units: 10,
+            labels_per_unit: 2048,
+            scrypt: ScryptParams::new(4, 1, 1),
+        };
+        let metadata = ProofMetadata {
+            node_id: [1u8; 32],
+            commitment_atx_id: [2u8; 32],
+            challenge: [3u8; 32],
+            num_units: 10,
+        };
+        let indices = (1..=10).map(|i| i * 100).collect::<Vec<u64>>();
+        let proof = Proof::new(3, &indices, 10 * 2048, 5);
+
+        let verifier = Verifier::new(Box::new(NoopPowVerifier));
+        let err = verifier
+            .verify(&proof, &metadata, &cfg, &init_cfg)
+            .unwrap_err();
+        let invalidity = InvalidityProof::extract(&err, &proof, &metadata, &cfg, &init_cfg)
+            .expect("proof should be invalid because of a label");
+
+        // It's self-contained, i.e. it can be sent to another party.
+        let encoded = serde_json::to_string(&invalidity).unwrap();
+        let decoded: InvalidityProof = serde_json::from_str(&encoded).unwrap();
+        decoded.check().unwrap();
+
+        let forged = InvalidityProof {
+            label: [0; 16],
+            ..decoded.clone()
+        };
+        assert!(matches!(
+            forged.check(),
+            Err(InvalidityProofError::LabelMismatch { .. })
+        ));
+        let forged = InvalidityProof {
+            position: (decoded.position + 1) % 10,
+            ..decoded
+        };
+        assert!(matches!(
+            forged.check(),
+            Err(InvalidityProofError::NotVerified { .. })
+        ));
+
+        let not_about_label = Error::InvalidIndicesLen {
+            expected: 19,
+            got: 3,
+        };
+        assert!(
+            InvalidityProof::extract(&not_about_label, &proof, &metadata, &cfg, &init_cfg)
+                .is_none()
+        );
+    }
+}
@@ -0,0 +1,312 @@
+//! Verifying proofs on a pool of worker threads.
+//!
+//! Nodes verify the proofs they receive from many peers at once. The [VerifierPool]
+//! verifies them on a fixed number of worker threads sharing a single [Verifier]
+//! (and so a single RandomX dataset or cache), taking the proofs from a bounded queue.
+//! At most as many proofs as there are workers are verified at the same time,
+//! and submitting more proofs than fit in the queue blocks (or fails, see
+//! [VerifierPool::try_submit]), pushing back on the producers.
+
+use std::{
+    sync::{
+        mpsc::{self, Receiver, SyncSender, TrySendError},
+        Arc, Mutex,
+    },
+    thread::JoinHandle,
+};
+
+use super::{Error, Verifier};
+use crate::{
+    config::{InitConfig, ProofConfig},
+    metadata::ProofMetadata,
+    prove::Proof,
+};
+
+/// A proof to verify with what's needed to verify it.
+struct Job {
+    proof: Proof<'static>,
+    metadata: ProofMetadata,
+    cfg: ProofConfig,
+    init_cfg: InitConfig,
+    result: mpsc::Sender<Result<(), Error>>,
+}
+
+/// The queue of the [VerifierPool] is full.
+#[derive(thiserror::Error, Debug)]
+#[error("verification queue is full")]
+pub struct QueueFull;
+
+/// Result of a proof submitted to the [VerifierPool].
+pub struct PendingVerification(Receiver<Result<(), Error>>);
+
+impl PendingVerification {
+    /// Wait until the proof is verified.
+    pub fn wait(self) -> Result<(), Error> {
+        self.0
+            .recv()
+            .expect("verifier worker should send the result of every job")
+    }
+
+    /// Get the result if the proof is verified already.
+    pub fn try_result(&self) -> Option<Result<(), Error>> {
+        self.0.try_recv().ok()
+    }
+}
+
+/// Verifies proofs with a shared [Verifier] on `workers` threads.
+///
+/// Dropping the pool waits until the queued proofs are verified.
+pub struct VerifierPool {
+    jobs: Option<SyncSender<Job>>,
+    workers: Vec<JoinHandle<()>>,
+}
+
+impl VerifierPool {
+    /// Start `workers` threads verifying proofs with the `verifier`, queueing up to
+    /// `queue_size` proofs waiting for a free worker.
+    ///
+    /// Every proof is verified on a single worker, but its k3 indices are verified
+    /// in parallel on the global rayon thread pool.
+    pub fn new(verifier: Verifier, workers: usize, queue_size: usize) -> std::io::Result<Self> {
+        let verifier = Arc::new(verifier);
+        let (jobs, queue) = mpsc::sync_channel::<Job>(queue_size);
+        let queue = Arc::new(Mutex::new(queue));
+        let workers = (0..workers.max(1))
+            .map(|id| {
+                let verifier = verifier.clone();
+                let queue = queue.clone();
+                std::thread::Builder::new()
+                    .name(format!("post-verifier-{id}"))
+                    .spawn(move || work(&verifier, &queue))
+            })
+            .collect::<std::io::Result<_>>()?;
+        Ok(Self {
+            jobs: Some(jobs),
+            workers,
+        })
+    }
+
+    /// Submit a proof for verification, waiting for a place in the queue if it's full.
+    pub fn submit(
+        &self,
+        proof: Proof<'static>,
+        metadata: ProofMetadata,
+        cfg: ProofConfig,
+        init_cfg: InitConfig,
+    ) -> PendingVerification {
+        let (job, pending) = job(proof, metadata, cfg, init_cfg);
+        self.jobs
+            .as_ref()
+            .unwrap()
+            .send(job)
+            .expect("verifier workers should run until the pool is dropped");
+        pending
+    }
+
+    /// Submit a proof for verification, failing if the queue is full.
+    pub fn try_submit(
+        &self,
+        proof: Proof<'static>,
+        metadata: ProofMetadata,
+        cfg: ProofConfig,
+        init_cfg: InitConfig,
+    ) -> Result<PendingVerification, QueueFull> {
+        let (job, pending) = job(proof, metadata, cfg, init_cfg);
+        match self.jobs.as_ref().unwrap().try_send(job) {
+            Ok(()) => Ok(pending),
+            Err(TrySendError::Full(_)) => Err(QueueFull),
+            Err(TrySendError::Disconnected(_)) => {
+                panic!("verifier workers should run until the pool is dropped")
+            }
+        }
+    }
+
+    /// Verify a proof on the pool, blocking until verified.
+    pub fn verify(
+        &self,
+        proof: Proof<'static>,
+        metadata: ProofMetadata,
+        cfg: ProofConfig,
+        init_cfg: InitConfig,
+    ) -> Result<(), Error> {
+        self.submit(proof, metadata, cfg, init_cfg).wait()
+    }
+}
+
+impl Drop for VerifierPool {
+    fn drop(&mut self) {
+        // Closing the queue stops the workers once they verified the queued proofs.
+        self.jobs.take();
+        for worker in self.workers.drain(..) {
+            if worker.join().is_err() {
+                log::error!("verifier worker panicked");
+            }
+        }
+    }
+}
+
+fn job(
+    proof: Proof<'static>,
+    metadata: ProofMetadata,
+    cfg: ProofConfig,
+    init_cfg: InitConfig,
+) -> (Job, PendingVerification) {
+    let (result, pending) = mpsc::channel();
+    let job = Job {
+        proof,
+        metadata,
+        cfg,
+        init_cfg,
+        result,
+    };
+    (job, PendingVerification(pending))
+}
+
+/// Verify the proofs from the `queue` until it's closed.
+fn work(verifier: &Verifier, queue: &Mutex<Receiver<Job>>) {
+    loop {
+        // The lock is released before verifying, letting other workers take the next job.
+        let job = match queue.lock().unwrap().recv() {
+            Ok(job) => job,
+            Err(_) => return,
+        };
+        let result = verifier.verify(&job.proof, &job.metadata, &job.cfg, &job.init_cfg);
+        // The submitter might not wait for the result anymore.
+        let _ = job.result.send(result);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        borrow::Cow,
+        sync::atomic::{AtomicUsize, Ordering},
+        time::Duration,
+    };
+
+    use super::*;
+    use crate::{
+        config::ScryptParams,
+        pow::{self, MockPowVerifier},
+        prove::ProofVersion,
+    };
+
+    fn proof(pow: u64) -> Proof<'static> {
+        Proof {
+            version: ProofVersion::V1,
+            nonce: 0,
+            indices: Cow::from(vec![]),
+            pow,
+        }
+    }
+
+    const CFG: ProofConfig = ProofConfig {
+        k1: 3,
+        k2: 0,
+        k3: 0,
+        pow_difficulty: [0xFF; 32],
+    };
+
+    fn init_cfg() -> InitConfig {
+        InitConfig {
+            min_num_units: 1,
+            max_num_units: 10,
+            labels_per_unit: 2048,
+            scrypt: ScryptParams::new(2, 1, 1),
+        }
+    }
+
+    fn metadata() -> ProofMetadata {
+        ProofMetadata {
+            node_id: [0; 32],
+            commitment_atx_id: [0; 32],
+            challenge: [0; 32],
+            num_units: 1,
+        }
+    }
+
+    #[test]
+    fn verifying_on_workers() {
+        let mut pow_verifier = MockPowVerifier::new();
+        pow_verifier
+            .expect_verify()
+            .returning(|pow, _, _, _, _| match pow {
+                0 => Ok(()),
+                _ => Err(pow::Error::InvalidPoW),
+            });
+        let pool = VerifierPool::new(Verifier::new(Box::new(pow_verifier)), 2, 4).unwrap();
+
+        let pending = (0..8)
+            .map(|pow| pool.submit(proof(pow % 2), metadata(), CFG, init_cfg()))
+            .collect::<Vec<_>>();
+        for (pow, pending) in pending.into_iter().enumerate() {
+            match pow % 2 {
+                0 => pending.wait().unwrap(),
+                _ => assert!(matches!(pending.wait(), Err(Error::InvalidPoW { .. }))),
+            }
+        }
+        pool.verify(proof(0), metadata(), CFG, init_cfg()).unwrap();
+    }
+
+    #[test]
+    fn limiting_concurrency() {
+        let running = Arc::new(AtomicUsize::new(0));
+        let max_running = Arc::new(AtomicUsize::new(0));
+        let mut pow_verifier = MockPowVerifier::new();
+        {
+            let running = running.clone();
+            let max_running = max_running.clone();
+            pow_verifier
+                .expect_verify()
+                .returning(move |_, _, _, _, _| {
+                    let now = running.fetch_add(1, Ordering::SeqCst) + 1;
+                    max_running.fetch_max(now, Ordering::SeqCst);
+                    std::thread::sleep(Duration::from_millis(20));
+                    running.fetch_sub(1, Ordering::SeqCst);
+                    Ok(())
+                });
+        }
+        let pool = VerifierPool::new(Verifier::new(Box::new(pow_verifier)), 2, 8).unwrap();
+
+        let pending = (0..8)
+            .map(|pow| pool.submit(proof(pow), metadata(), CFG, init_cfg()))
+            .collect::<Vec<_>>();
+        for pending in pending {
+            pending.wait().unwrap();
+        }
+        assert!(max_running.load(Ordering::SeqCst) <= 2);
+    }
+
+    #[test]
+    fn rejecting_proofs_if_queue_is_full() {
+        let (started_tx, started) = mpsc::channel();
+        let (release, gate) = mpsc::channel::<()>();
+        let gate = Mutex::new(gate);
+        let mut pow_verifier = MockPowVerifier::new();
+        pow_verifier
+            .expect_verify()
+            .returning(move |_, _, _, _, _| {
+                started_tx.send(()).unwrap();
+                gate.lock().unwrap().recv().unwrap();
+                Ok(())
+            });
+        let pool = VerifierPool::new(Verifier::new(Box::new(pow_verifier)), 1, 1).unwrap();
+
+        // The worker is busy with the first proof, the second one waits in the queue
+        // and there is no place for the third one.
+        let first = pool.submit(proof(0), metadata(), CFG, init_cfg());
+        started.recv().unwrap();
+        let second = pool
+            .try_submit(proof(1), metadata(), CFG, init_cfg())
+            .unwrap();
+        assert!(second.try_result().is_none());
+        assert!(pool
+            .try_submit(proof(2), metadata(), CFG, init_cfg())
+            .is_err());
+
+        release.send(()).unwrap();
+        release.send(()).unwrap();
+        first.wait().unwrap();
+        second.wait().unwrap();
+    }
+}
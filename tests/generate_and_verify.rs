@@ -1,12 +1,30 @@
-use std::sync::atomic::AtomicBool;
+use std::{
+    collections::BTreeMap,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant},
+};
 
 use post::{
+    cancel::{CancellationToken, Cancelled},
     config::{InitConfig, ScryptParams},
+    encryption::{DataKey, EncryptingInitializer},
     initialize::{CpuInitializer, Initialize},
     metadata::ProofMetadata,
-    pow::randomx::{PoW, RandomXFlag},
-    prove::generate_proof,
-    verification::Verifier,
+    pow::{
+        self,
+        randomx::{PoW, RandomXFlag},
+    },
+    prove::{
+        generate_proof, generate_proof_with_key, generate_proof_with_settings,
+        generate_proofs_with_settings, load_pow_cache, ChallengeCheckpoint, DeadlineExceeded,
+        IndexHook, JoiningChallenges, Proof, Prover8_56, ProvingCheckpoint, ProvingParams,
+        ProvingSettings, ProvingStats, UnitRange,
+    },
+    reader::LabelSource,
+    verification::{AuditError, Verifier},
 };
 use tempfile::tempdir;
 
@@ -43,8 +61,8 @@ fn test_generate_and_verify() {
 
     let pow_flags = RandomXFlag::get_recommended_flags();
     // Generate a proof
-    let stop = AtomicBool::new(false);
-    let proof = generate_proof(datadir.path(), challenge, cfg, 32, 1, pow_flags, stop).unwrap();
+    let cancel = CancellationToken::new();
+    let proof = generate_proof(datadir.path(), challenge, cfg, 32, 1, pow_flags, cancel).unwrap();
 
     // Verify the proof
     let metadata = ProofMetadata::new(metadata, *challenge);
@@ -96,8 +114,8 @@ fn test_generate_and_verify_difficulty_msb_not_zero() {
 
     let pow_flags = RandomXFlag::get_recommended_flags();
     // Generate a proof
-    let stop = AtomicBool::new(false);
-    let proof = generate_proof(datadir.path(), challenge, cfg, 32, 1, pow_flags, stop).unwrap();
+    let cancel = CancellationToken::new();
+    let proof = generate_proof(datadir.path(), challenge, cfg, 32, 1, pow_flags, cancel).unwrap();
 
     // Verify the proof
     let metadata = ProofMetadata::new(metadata, *challenge);
@@ -113,3 +131,1166 @@ fn test_generate_and_verify_difficulty_msb_not_zero() {
         .verify(&invalid_proof, &metadata, &cfg, &init_cfg)
         .expect_err("proof should be invalid");
 }
+
+#[test]
+fn test_generate_and_verify_encrypted_data() {
+    let challenge = b"hello world, challenge me!!!!!!!";
+    let datadir = tempdir().unwrap();
+
+    let cfg = post::config::ProofConfig {
+        k1: 23,
+        k2: 32,
+        k3: 10,
+        pow_difficulty: [0xFF; 32],
+    };
+    let init_cfg = InitConfig {
+        min_num_units: 1,
+        max_num_units: 1000,
+        labels_per_unit: 256 * 16,
+        scrypt: ScryptParams::new(2, 1, 1),
+    };
+
+    let key = DataKey::from_bytes([0xAB; 16]);
+    let metadata = EncryptingInitializer::new(CpuInitializer::new(init_cfg.scrypt), key.clone())
+        .initialize(
+            datadir.path(),
+            &[77; 32],
+            &[0u8; 32],
+            init_cfg.labels_per_unit,
+            31,
+            1000,
+            None,
+        )
+        .unwrap();
+
+    let pow_flags = RandomXFlag::get_recommended_flags();
+    let cancel = CancellationToken::new();
+    // The key is required
+    assert!(generate_proof(datadir.path(), challenge, cfg, 32, 1, pow_flags, &cancel).is_err());
+    let wrong_key = DataKey::from_bytes([0xAC; 16]);
+    assert!(generate_proof_with_key(
+        datadir.path(),
+        challenge,
+        cfg,
+        32,
+        1,
+        pow_flags,
+        &cancel,
+        Some(&wrong_key),
+    )
+    .is_err());
+
+    let proof = generate_proof_with_key(
+        datadir.path(),
+        challenge,
+        cfg,
+        32,
+        1,
+        pow_flags,
+        &cancel,
+        Some(&key),
+    )
+    .unwrap();
+
+    let metadata = ProofMetadata::new(metadata, *challenge);
+    let verifier = Verifier::new(Box::new(PoW::new(pow_flags).unwrap()));
+    verifier
+        .verify(&proof, &metadata, &cfg, &init_cfg)
+        .expect("proof should be valid");
+}
+
+#[test]
+fn test_auditing_proof() {
+    let challenge = b"hello world, challenge me!!!!!!!";
+    let datadir = tempdir().unwrap();
+
+    let cfg = post::config::ProofConfig {
+        k1: 23,
+        k2: 32,
+        k3: 10,
+        pow_difficulty: [0xFF; 32],
+    };
+    let init_cfg = InitConfig {
+        min_num_units: 1,
+        max_num_units: 1000,
+        labels_per_unit: 256 * 16,
+        scrypt: ScryptParams::new(2, 1, 1),
+    };
+
+    let key = DataKey::from_bytes([0xAB; 16]);
+    let metadata = EncryptingInitializer::new(CpuInitializer::new(init_cfg.scrypt), key.clone())
+        .initialize(
+            datadir.path(),
+            &[77; 32],
+            &[0u8; 32],
+            init_cfg.labels_per_unit,
+            31,
+            1000,
+            None,
+        )
+        .unwrap();
+
+    let pow_flags = RandomXFlag::get_recommended_flags();
+    let cancel = CancellationToken::new();
+    let proof = generate_proof_with_key(
+        datadir.path(),
+        challenge,
+        cfg,
+        32,
+        1,
+        pow_flags,
+        &cancel,
+        Some(&key),
+    )
+    .unwrap();
+
+    let metadata = ProofMetadata::new(metadata, *challenge);
+    let verifier = Verifier::new(Box::new(PoW::new(pow_flags).unwrap()));
+    verifier
+        .audit(
+            &proof,
+            &metadata,
+            &cfg,
+            &init_cfg,
+            datadir.path(),
+            Some(&key),
+        )
+        .expect("proof should pass the audit");
+
+    // The key is required
+    let result = verifier.audit(&proof, &metadata, &cfg, &init_cfg, datadir.path(), None);
+    assert!(matches!(result, Err(AuditError::Data(_))));
+
+    // The proof is valid, but the POS data doesn't hold its labels anymore
+    for entry in std::fs::read_dir(datadir.path()).unwrap() {
+        let path = entry.unwrap().path();
+        if path.extension().is_some_and(|ext| ext == "bin") {
+            let len = std::fs::metadata(&path).unwrap().len();
+            std::fs::write(&path, vec![0u8; len as usize]).unwrap();
+        }
+    }
+    verifier
+        .verify(&proof, &metadata, &cfg, &init_cfg)
+        .expect("proof should be valid");
+    let result = verifier.audit(
+        &proof,
+        &metadata,
+        &cfg,
+        &init_cfg,
+        datadir.path(),
+        Some(&key),
+    );
+    assert!(matches!(
+        result,
+        Err(AuditError::LabelMismatch { position: 0, .. })
+    ));
+}
+
+#[test]
+fn test_generate_resuming_from_checkpoint() {
+    let challenge = b"hello world, challenge me!!!!!!!";
+    let datadir = tempdir().unwrap();
+
+    let cfg = post::config::ProofConfig {
+        k1: 23,
+        k2: 32,
+        k3: 10,
+        pow_difficulty: [0xFF; 32],
+    };
+    let init_cfg = InitConfig {
+        min_num_units: 1,
+        max_num_units: 1000,
+        labels_per_unit: 256 * 16,
+        scrypt: ScryptParams::new(2, 1, 1),
+    };
+
+    let metadata = CpuInitializer::new(init_cfg.scrypt)
+        .initialize(
+            datadir.path(),
+            &[77; 32],
+            &[0u8; 32],
+            init_cfg.labels_per_unit,
+            31,
+            1000,
+            None,
+        )
+        .unwrap();
+
+    let pow_flags = RandomXFlag::get_recommended_flags();
+    let cancel = CancellationToken::new();
+    let proof = generate_proof(datadir.path(), challenge, cfg, 32, 1, pow_flags, &cancel).unwrap();
+
+    // A checkpoint saved right after calculating the proofs of work.
+    let params = ProvingParams::new(&metadata, &cfg).unwrap();
+    let pow_prover = PoW::new(pow_flags).unwrap();
+    let prover = Prover8_56::new(challenge, 0..32, params, &pow_prover, &metadata.node_id).unwrap();
+    let checkpoint = ProvingCheckpoint {
+        position: 0,
+        challenges: vec![ChallengeCheckpoint {
+            challenge: *challenge,
+            nonces: 0..32,
+            pows: prover.pows(),
+            indices: BTreeMap::new(),
+        }],
+    };
+    checkpoint.save(datadir.path()).unwrap();
+
+    let settings = ProvingSettings {
+        checkpoint_interval: Some(Duration::from_secs(3600)),
+        ..Default::default()
+    };
+    let resumed_proof = generate_proof_with_settings(
+        datadir.path(),
+        challenge,
+        cfg,
+        32,
+        1,
+        pow_flags,
+        &cancel,
+        &settings,
+    )
+    .unwrap();
+    assert_eq!(proof, resumed_proof);
+    // The checkpoint is removed after the proof is found
+    assert!(ProvingCheckpoint::load(datadir.path()).unwrap().is_none());
+
+    let metadata = ProofMetadata::new(metadata, *challenge);
+    let verifier = Verifier::new(Box::new(PoW::new(pow_flags).unwrap()));
+    verifier
+        .verify(&resumed_proof, &metadata, &cfg, &init_cfg)
+        .expect("proof should be valid");
+}
+
+#[test]
+fn test_generate_past_deadline() {
+    let challenge = b"hello world, challenge me!!!!!!!";
+    let datadir = tempdir().unwrap();
+
+    let cfg = post::config::ProofConfig {
+        k1: 23,
+        k2: 32,
+        k3: 10,
+        pow_difficulty: [0xFF; 32],
+    };
+    CpuInitializer::new(ScryptParams::new(2, 1, 1))
+        .initialize(datadir.path(), &[77; 32], &[0u8; 32], 256, 4, 1000, None)
+        .unwrap();
+
+    let settings = ProvingSettings {
+        deadline: Some(Instant::now()),
+        ..Default::default()
+    };
+    let err = generate_proof_with_settings(
+        datadir.path(),
+        challenge,
+        cfg,
+        32,
+        1,
+        RandomXFlag::get_recommended_flags(),
+        CancellationToken::new(),
+        &settings,
+    )
+    .unwrap_err();
+    assert!(err.downcast_ref::<DeadlineExceeded>().is_some());
+}
+
+#[test]
+fn test_generate_cancelled() {
+    let challenge = b"hello world, challenge me!!!!!!!";
+    let datadir = tempdir().unwrap();
+
+    let cfg = post::config::ProofConfig {
+        k1: 23,
+        k2: 32,
+        k3: 10,
+        pow_difficulty: [0xFF; 32],
+    };
+    CpuInitializer::new(ScryptParams::new(2, 1, 1))
+        .initialize(datadir.path(), &[77; 32], &[0u8; 32], 256, 4, 1000, None)
+        .unwrap();
+    let pow_flags = RandomXFlag::get_recommended_flags();
+
+    let cancel = CancellationToken::new();
+    cancel.cancel("shutting down");
+    let err =
+        generate_proof(datadir.path(), challenge, cfg, 32, 1, pow_flags, &cancel).unwrap_err();
+    let cancelled = err.downcast_ref::<Cancelled>().unwrap();
+    assert_eq!("shutting down", cancelled.reason);
+
+    let cancel = CancellationToken::new();
+    cancel.cancel_after(Duration::ZERO);
+    let err =
+        generate_proof(datadir.path(), challenge, cfg, 32, 1, pow_flags, &cancel).unwrap_err();
+    let cancelled = err.downcast_ref::<Cancelled>().unwrap();
+    assert_eq!("timed out", cancelled.reason);
+}
+
+#[test]
+fn test_generate_for_multiple_challenges() {
+    let challenges = [[1; 32], [2; 32], [3; 32]];
+    let datadir = tempdir().unwrap();
+
+    let cfg = post::config::ProofConfig {
+        k1: 23,
+        k2: 32,
+        k3: 10,
+        pow_difficulty: [0xFF; 32],
+    };
+    let init_cfg = InitConfig {
+        min_num_units: 1,
+        max_num_units: 1000,
+        labels_per_unit: 256 * 16,
+        scrypt: ScryptParams::new(2, 1, 1),
+    };
+    let metadata = CpuInitializer::new(init_cfg.scrypt)
+        .initialize(
+            datadir.path(),
+            &[77; 32],
+            &[0u8; 32],
+            init_cfg.labels_per_unit,
+            4,
+            1000,
+            None,
+        )
+        .unwrap();
+
+    let pow_flags = RandomXFlag::get_recommended_flags();
+    let proofs = generate_proofs_with_settings(
+        datadir.path(),
+        &challenges,
+        cfg,
+        16,
+        1,
+        pow_flags,
+        CancellationToken::new(),
+        &ProvingSettings::default(),
+    )
+    .unwrap();
+    assert_eq!(challenges.len(), proofs.len());
+
+    let verifier = Verifier::new(Box::new(PoW::new(pow_flags).unwrap()));
+    for (challenge, proof) in challenges.iter().zip(&proofs) {
+        let single = generate_proof(
+            datadir.path(),
+            challenge,
+            cfg,
+            16,
+            1,
+            pow_flags,
+            CancellationToken::new(),
+        )
+        .unwrap();
+        assert_eq!(&single, proof);
+
+        let metadata = ProofMetadata::new(metadata, *challenge);
+        verifier
+            .verify(proof, &metadata, &cfg, &init_cfg)
+            .expect("proof should be valid");
+    }
+}
+
+#[test]
+fn test_generate_for_multiple_challenges_resuming_from_checkpoint() {
+    let challenges = [[1; 32], [2; 32]];
+    let datadir = tempdir().unwrap();
+
+    let cfg = post::config::ProofConfig {
+        k1: 23,
+        k2: 32,
+        k3: 10,
+        pow_difficulty: [0xFF; 32],
+    };
+    let metadata = CpuInitializer::new(ScryptParams::new(2, 1, 1))
+        .initialize(
+            datadir.path(),
+            &[77; 32],
+            &[0u8; 32],
+            256 * 16,
+            4,
+            1000,
+            None,
+        )
+        .unwrap();
+
+    let pow_flags = RandomXFlag::get_recommended_flags();
+    let cancel = CancellationToken::new();
+    let expected = generate_proofs_with_settings(
+        datadir.path(),
+        &challenges,
+        cfg,
+        16,
+        1,
+        pow_flags,
+        &cancel,
+        &ProvingSettings::default(),
+    )
+    .unwrap();
+
+    // Only the second challenge was being proven when interrupted.
+    let params = ProvingParams::new(&metadata, &cfg).unwrap();
+    let pow_prover = PoW::new(pow_flags).unwrap();
+    let prover = Prover8_56::new(
+        &challenges[1],
+        0..16,
+        params,
+        &pow_prover,
+        &metadata.node_id,
+    )
+    .unwrap();
+    let checkpoint = ProvingCheckpoint {
+        position: 0,
+        challenges: vec![ChallengeCheckpoint {
+            challenge: challenges[1],
+            nonces: 0..16,
+            pows: prover.pows(),
+            indices: BTreeMap::new(),
+        }],
+    };
+    checkpoint.save(datadir.path()).unwrap();
+
+    struct Hook(Mutex<BTreeMap<[u8; 32], usize>>);
+    impl IndexHook for Hook {
+        fn on_index(&self, _: u32, _: u64) {
+            unreachable!("the challenge of the index is given");
+        }
+        fn on_challenge_index(&self, challenge: &[u8; 32], _: u32, _: u64) {
+            *self.0.lock().unwrap().entry(*challenge).or_default() += 1;
+        }
+    }
+    let hook = Hook(Mutex::default());
+    let settings = ProvingSettings {
+        checkpoint_interval: Some(Duration::from_secs(3600)),
+        index_hook: Some(&hook),
+        ..Default::default()
+    };
+    let proofs = generate_proofs_with_settings(
+        datadir.path(),
+        &challenges,
+        cfg,
+        16,
+        1,
+        pow_flags,
+        &cancel,
+        &settings,
+    )
+    .unwrap();
+    assert_eq!(expected, proofs);
+    assert!(ProvingCheckpoint::load(datadir.path()).unwrap().is_none());
+    let found = hook.0.into_inner().unwrap();
+    assert_eq!(
+        challenges.to_vec(),
+        found.keys().copied().collect::<Vec<_>>()
+    );
+}
+
+#[test]
+fn test_generate_with_joining_challenges() {
+    let datadir = tempdir().unwrap();
+
+    let cfg = post::config::ProofConfig {
+        k1: 23,
+        k2: 32,
+        k3: 10,
+        pow_difficulty: [0xFF; 32],
+    };
+    CpuInitializer::new(ScryptParams::new(2, 1, 1))
+        .initialize(
+            datadir.path(),
+            &[77; 32],
+            &[0u8; 32],
+            256 * 16,
+            4,
+            1000,
+            None,
+        )
+        .unwrap();
+
+    let pow_flags = RandomXFlag::get_recommended_flags();
+    let joining = JoiningChallenges::default();
+    assert!(joining.push([2; 32]));
+    // Already proven challenges don't join.
+    assert!(joining.push([1; 32]));
+    let settings = ProvingSettings {
+        joining: Some(&joining),
+        ..Default::default()
+    };
+    let proofs = generate_proofs_with_settings(
+        datadir.path(),
+        &[[1; 32]],
+        cfg,
+        16,
+        1,
+        pow_flags,
+        CancellationToken::new(),
+        &settings,
+    )
+    .unwrap();
+    assert_eq!(vec![[2; 32]], joining.joined());
+    assert!(!joining.push([3; 32]));
+
+    for (challenge, proof) in [[1; 32], [2; 32]].iter().zip(&proofs) {
+        let single = generate_proof(
+            datadir.path(),
+            challenge,
+            cfg,
+            16,
+            1,
+            pow_flags,
+            CancellationToken::new(),
+        )
+        .unwrap();
+        assert_eq!(&single, proof);
+    }
+    assert_eq!(2, proofs.len());
+}
+
+#[test]
+fn test_generate_with_missing_data() {
+    let challenge = b"hello world, challenge me!!!!!!!";
+    let datadir = tempdir().unwrap();
+
+    let cfg = post::config::ProofConfig {
+        k1: 23,
+        k2: 32,
+        k3: 10,
+        pow_difficulty: [0xFF; 32],
+    };
+    let init_cfg = InitConfig {
+        min_num_units: 1,
+        max_num_units: 1000,
+        labels_per_unit: 256 * 16,
+        scrypt: ScryptParams::new(2, 1, 1),
+    };
+    let metadata = CpuInitializer::new(init_cfg.scrypt)
+        .initialize(
+            datadir.path(),
+            &[77; 32],
+            &[0u8; 32],
+            init_cfg.labels_per_unit,
+            4,
+            init_cfg.labels_per_unit,
+            None,
+        )
+        .unwrap();
+    std::fs::remove_file(datadir.path().join("postdata_1.bin")).unwrap();
+
+    let pow_flags = RandomXFlag::get_recommended_flags();
+    let generate = |best_effort| {
+        let settings = ProvingSettings {
+            best_effort,
+            ..Default::default()
+        };
+        generate_proof_with_settings(
+            datadir.path(),
+            challenge,
+            cfg,
+            32,
+            1,
+            pow_flags,
+            CancellationToken::new(),
+            &settings,
+        )
+    };
+    assert!(generate(false).is_err());
+
+    let proof = generate(true).unwrap();
+    let metadata = ProofMetadata::new(metadata, *challenge);
+    let verifier = Verifier::new(Box::new(PoW::new(pow_flags).unwrap()));
+    verifier
+        .verify(&proof, &metadata, &cfg, &init_cfg)
+        .expect("proof should be valid");
+}
+
+#[test]
+fn test_generate_with_stats() {
+    let challenge = b"hello world, challenge me!!!!!!!";
+    let datadir = tempdir().unwrap();
+
+    let cfg = post::config::ProofConfig {
+        k1: 23,
+        k2: 32,
+        k3: 10,
+        pow_difficulty: [0xFF; 32],
+    };
+    CpuInitializer::new(ScryptParams::new(2, 1, 1))
+        .initialize(datadir.path(), &[77; 32], &[0u8; 32], 256, 4, 1000, None)
+        .unwrap();
+
+    let stats = Mutex::new(ProvingStats::default());
+    let settings = ProvingSettings {
+        stats: Some(&stats),
+        ..Default::default()
+    };
+    let proof = generate_proof_with_settings(
+        datadir.path(),
+        challenge,
+        cfg,
+        32,
+        1,
+        RandomXFlag::get_recommended_flags(),
+        CancellationToken::new(),
+        &settings,
+    )
+    .unwrap();
+
+    let stats = stats.into_inner().unwrap();
+    assert!(stats.passes >= 1);
+    assert!(stats.labels_scanned > 0);
+    assert!(stats.read_throughput() > 0.0);
+    assert_eq!(
+        Some(&(cfg.k2 as usize)),
+        stats.indices_per_nonce.get(&proof.nonce)
+    );
+}
+
+#[test]
+fn test_generate_with_index_hook() {
+    let challenge = b"hello world, challenge me!!!!!!!";
+    let datadir = tempdir().unwrap();
+
+    let cfg = post::config::ProofConfig {
+        k1: 23,
+        k2: 32,
+        k3: 10,
+        pow_difficulty: [0xFF; 32],
+    };
+    CpuInitializer::new(ScryptParams::new(2, 1, 1))
+        .initialize(datadir.path(), &[77; 32], &[0u8; 32], 256, 4, 1000, None)
+        .unwrap();
+
+    let indices = Mutex::new(BTreeMap::<u32, Vec<u64>>::new());
+    let hook = |nonce: u32, index: u64| {
+        indices
+            .lock()
+            .unwrap()
+            .entry(nonce)
+            .or_default()
+            .push(index)
+    };
+    let settings = ProvingSettings {
+        index_hook: Some(&hook),
+        ..Default::default()
+    };
+    let proof = generate_proof_with_settings(
+        datadir.path(),
+        challenge,
+        cfg,
+        32,
+        1,
+        RandomXFlag::get_recommended_flags(),
+        CancellationToken::new(),
+        &settings,
+    )
+    .unwrap();
+
+    let indices = indices.into_inner().unwrap();
+    let num_labels = 256 * 4;
+    let expected = Proof::new(proof.nonce, &indices[&proof.nonce], num_labels, proof.pow);
+    assert_eq!(expected, proof);
+}
+
+/// POS data held in memory.
+struct MemorySource(Vec<u8>);
+
+impl LabelSource for MemorySource {
+    fn read_labels(&self, index: u64, buf: &mut [u8]) -> std::io::Result<usize> {
+        let start = (index as usize * 16).min(self.0.len());
+        let n = buf.len().min(self.0.len() - start);
+        buf[..n].copy_from_slice(&self.0[start..start + n]);
+        Ok(n)
+    }
+}
+
+#[test]
+fn test_generate_from_label_source() {
+    let challenge = b"hello world, challenge me!!!!!!!";
+    let datadir = tempdir().unwrap();
+
+    let cfg = post::config::ProofConfig {
+        k1: 23,
+        k2: 32,
+        k3: 10,
+        pow_difficulty: [0xFF; 32],
+    };
+    let init_cfg = InitConfig {
+        min_num_units: 1,
+        max_num_units: 1000,
+        labels_per_unit: 256 * 16,
+        scrypt: ScryptParams::new(2, 1, 1),
+    };
+    let metadata = CpuInitializer::new(init_cfg.scrypt)
+        .initialize(
+            datadir.path(),
+            &[77; 32],
+            &[0u8; 32],
+            init_cfg.labels_per_unit,
+            4,
+            init_cfg.labels_per_unit,
+            None,
+        )
+        .unwrap();
+
+    let pow_flags = RandomXFlag::get_recommended_flags();
+    let expected = generate_proof(
+        datadir.path(),
+        challenge,
+        cfg,
+        32,
+        1,
+        pow_flags,
+        CancellationToken::new(),
+    )
+    .unwrap();
+
+    // Move the POS data to memory
+    let mut data = Vec::new();
+    for i in 0..4 {
+        let path = datadir.path().join(format!("postdata_{i}.bin"));
+        data.extend(std::fs::read(&path).unwrap());
+        std::fs::remove_file(path).unwrap();
+    }
+    let source = MemorySource(data);
+    let settings = ProvingSettings {
+        source: Some(&source),
+        ..Default::default()
+    };
+    let proof = generate_proof_with_settings(
+        datadir.path(),
+        challenge,
+        cfg,
+        32,
+        1,
+        pow_flags,
+        CancellationToken::new(),
+        &settings,
+    )
+    .unwrap();
+    assert_eq!(expected, proof);
+
+    let metadata = ProofMetadata::new(metadata, *challenge);
+    let verifier = Verifier::new(Box::new(PoW::new(pow_flags).unwrap()));
+    verifier
+        .verify(&proof, &metadata, &cfg, &init_cfg)
+        .expect("proof should be valid");
+}
+
+#[test]
+fn test_generate_with_memory_budget() {
+    let challenge = b"hello world, challenge me!!!!!!!";
+    let datadir = tempdir().unwrap();
+
+    let cfg = post::config::ProofConfig {
+        k1: 23,
+        k2: 32,
+        k3: 10,
+        pow_difficulty: [0xFF; 32],
+    };
+    CpuInitializer::new(ScryptParams::new(2, 1, 1))
+        .initialize(datadir.path(), &[77; 32], &[0u8; 32], 256, 4, 1000, None)
+        .unwrap();
+
+    let pow_flags = RandomXFlag::get_recommended_flags();
+    let expected = generate_proof(
+        datadir.path(),
+        challenge,
+        cfg,
+        32,
+        1,
+        pow_flags,
+        CancellationToken::new(),
+    )
+    .unwrap();
+
+    // Small enough to read in small chunks and spill the indices to disk
+    let settings = ProvingSettings {
+        memory_budget: Some(8 * 1024),
+        ..Default::default()
+    };
+    let proof = generate_proof_with_settings(
+        datadir.path(),
+        challenge,
+        cfg,
+        32,
+        1,
+        pow_flags,
+        CancellationToken::new(),
+        &settings,
+    )
+    .unwrap();
+    assert_eq!(expected, proof);
+
+    let settings = ProvingSettings {
+        memory_budget: Some(256),
+        ..Default::default()
+    };
+    let result = generate_proof_with_settings(
+        datadir.path(),
+        challenge,
+        cfg,
+        32,
+        1,
+        pow_flags,
+        CancellationToken::new(),
+        &settings,
+    );
+    assert!(result.is_err());
+}
+
+#[test]
+fn test_generate_with_automatic_nonces() {
+    let challenge = b"hello world, challenge me!!!!!!!";
+    let datadir = tempdir().unwrap();
+
+    let cfg = post::config::ProofConfig {
+        k1: 23,
+        k2: 32,
+        k3: 10,
+        pow_difficulty: [0xFF; 32],
+    };
+    let init_cfg = InitConfig {
+        min_num_units: 1,
+        max_num_units: 1000,
+        labels_per_unit: 256 * 16,
+        scrypt: ScryptParams::new(2, 1, 1),
+    };
+    let metadata = CpuInitializer::new(init_cfg.scrypt)
+        .initialize(
+            datadir.path(),
+            &[77; 32],
+            &[0u8; 32],
+            init_cfg.labels_per_unit,
+            4,
+            1000,
+            None,
+        )
+        .unwrap();
+
+    let pow_flags = RandomXFlag::get_recommended_flags();
+    let cancel = CancellationToken::new();
+    let proof = generate_proof(datadir.path(), challenge, cfg, 0, 1, pow_flags, cancel).unwrap();
+
+    let metadata = ProofMetadata::new(metadata, *challenge);
+    let verifier = Verifier::new(Box::new(PoW::new(pow_flags).unwrap()));
+    verifier
+        .verify(&proof, &metadata, &cfg, &init_cfg)
+        .expect("proof should be valid");
+}
+
+#[test]
+fn test_generate_over_subset_of_units() {
+    let challenge = b"hello world, challenge me!!!!!!!";
+    let datadir = tempdir().unwrap();
+
+    let cfg = post::config::ProofConfig {
+        // The proof must be found within the labels of the proven units.
+        k1: 96,
+        k2: 32,
+        k3: 10,
+        pow_difficulty: [0xFF; 32],
+    };
+    let init_cfg = InitConfig {
+        min_num_units: 1,
+        max_num_units: 1000,
+        labels_per_unit: 256 * 16,
+        scrypt: ScryptParams::new(2, 1, 1),
+    };
+    let metadata = CpuInitializer::new(init_cfg.scrypt)
+        .initialize(
+            datadir.path(),
+            &[77; 32],
+            &[0u8; 32],
+            init_cfg.labels_per_unit,
+            4,
+            1000,
+            None,
+        )
+        .unwrap();
+
+    let indices = Mutex::new(Vec::new());
+    let hook = |_: u32, index: u64| indices.lock().unwrap().push(index);
+    let settings = ProvingSettings {
+        units: Some(UnitRange { first: 2, count: 2 }),
+        index_hook: Some(&hook),
+        ..Default::default()
+    };
+    let pow_flags = RandomXFlag::get_recommended_flags();
+    let proof = generate_proof_with_settings(
+        datadir.path(),
+        challenge,
+        cfg,
+        32,
+        1,
+        pow_flags,
+        CancellationToken::new(),
+        &settings,
+    )
+    .unwrap();
+
+    // Only the labels of the units 2 and 3 were scanned
+    let unit = init_cfg.labels_per_unit;
+    let indices = indices.into_inner().unwrap();
+    assert!(!indices.is_empty());
+    assert!(indices.iter().all(|i| (2 * unit..4 * unit).contains(i)));
+
+    let metadata = ProofMetadata::new(metadata, *challenge);
+    let verifier = Verifier::new(Box::new(PoW::new(pow_flags).unwrap()));
+    verifier
+        .verify(&proof, &metadata, &cfg, &init_cfg)
+        .expect("proof should be valid");
+
+    let settings = ProvingSettings {
+        units: Some(UnitRange { first: 3, count: 2 }),
+        ..Default::default()
+    };
+    let result = generate_proof_with_settings(
+        datadir.path(),
+        challenge,
+        cfg,
+        32,
+        1,
+        pow_flags,
+        CancellationToken::new(),
+        &settings,
+    );
+    assert!(result.is_err());
+}
+
+#[test]
+fn test_generate_verifying_checksums() {
+    let challenge = b"hello world, challenge me!!!!!!!";
+    let datadir = tempdir().unwrap();
+
+    let cfg = post::config::ProofConfig {
+        k1: 23,
+        k2: 32,
+        k3: 10,
+        pow_difficulty: [0xFF; 32],
+    };
+    let init_cfg = InitConfig {
+        min_num_units: 1,
+        max_num_units: 1000,
+        labels_per_unit: 256 * 16,
+        scrypt: ScryptParams::new(2, 1, 1),
+    };
+    let metadata = CpuInitializer::new(init_cfg.scrypt)
+        .initialize(
+            datadir.path(),
+            &[77; 32],
+            &[0u8; 32],
+            init_cfg.labels_per_unit,
+            2,
+            1000,
+            None,
+        )
+        .unwrap();
+
+    let settings = ProvingSettings {
+        verify_checksums: true,
+        ..Default::default()
+    };
+    let pow_flags = RandomXFlag::get_recommended_flags();
+    let proof = generate_proof_with_settings(
+        datadir.path(),
+        challenge,
+        cfg,
+        32,
+        1,
+        pow_flags,
+        CancellationToken::new(),
+        &settings,
+    )
+    .unwrap();
+    let verifier = Verifier::new(Box::new(PoW::new(pow_flags).unwrap()));
+    verifier
+        .verify(
+            &proof,
+            &ProofMetadata::new(metadata, *challenge),
+            &cfg,
+            &init_cfg,
+        )
+        .expect("proof should be valid");
+
+    // Corrupt a file, a pass not finding a proof then fails.
+    let path = datadir.path().join("postdata_1.bin");
+    let mut data = std::fs::read(&path).unwrap();
+    data[100] ^= 0xFF;
+    std::fs::write(&path, data).unwrap();
+
+    let cfg = post::config::ProofConfig { k2: 1000, ..cfg };
+    let err = generate_proof_with_settings(
+        datadir.path(),
+        challenge,
+        cfg,
+        16,
+        1,
+        pow_flags,
+        CancellationToken::new(),
+        &settings,
+    )
+    .unwrap_err();
+    assert_eq!("corrupted POS data files: postdata_1.bin", err.to_string());
+}
+
+/// PoW prover counting the proofs of work it calculates.
+struct CountingProver {
+    pow: PoW,
+    count: AtomicUsize,
+}
+
+impl pow::Prover for CountingProver {
+    fn prove(
+        &self,
+        nonce_group: u8,
+        challenge: &[u8; 8],
+        difficulty: &[u8; 32],
+        miner_id: &[u8; 32],
+        cancel: &CancellationToken,
+    ) -> Result<u64, pow::Error> {
+        self.count.fetch_add(1, Ordering::Relaxed);
+        pow::Prover::prove(
+            &self.pow,
+            nonce_group,
+            challenge,
+            difficulty,
+            miner_id,
+            cancel,
+        )
+    }
+}
+
+#[test]
+fn test_generate_with_custom_pow_prover() {
+    let challenge = b"hello world, challenge me!!!!!!!";
+    let datadir = tempdir().unwrap();
+
+    let cfg = post::config::ProofConfig {
+        k1: 23,
+        k2: 32,
+        k3: 10,
+        pow_difficulty: [0xFF; 32],
+    };
+    let init_cfg = InitConfig {
+        min_num_units: 1,
+        max_num_units: 1000,
+        labels_per_unit: 256 * 16,
+        scrypt: ScryptParams::new(2, 1, 1),
+    };
+    let metadata = CpuInitializer::new(init_cfg.scrypt)
+        .initialize(
+            datadir.path(),
+            &[77; 32],
+            &[0u8; 32],
+            init_cfg.labels_per_unit,
+            2,
+            1000,
+            None,
+        )
+        .unwrap();
+
+    let pow_flags = RandomXFlag::get_recommended_flags();
+    let counting = Arc::new(CountingProver {
+        pow: PoW::new(pow_flags).unwrap(),
+        count: AtomicUsize::new(0),
+    });
+    let pow_prover: Arc<dyn pow::Prover + Send + Sync> = counting.clone();
+    let settings = ProvingSettings {
+        pow_prover: Some(&pow_prover),
+        ..Default::default()
+    };
+    let proof = generate_proof_with_settings(
+        datadir.path(),
+        challenge,
+        cfg,
+        32,
+        1,
+        pow_flags,
+        CancellationToken::new(),
+        &settings,
+    )
+    .unwrap();
+    assert!(counting.count.load(Ordering::Relaxed) >= 2);
+
+    let verifier = Verifier::new(Box::new(PoW::new(pow_flags).unwrap()));
+    verifier
+        .verify(
+            &proof,
+            &ProofMetadata::new(metadata, *challenge),
+            &cfg,
+            &init_cfg,
+        )
+        .expect("proof should be valid");
+}
+
+#[test]
+fn test_generate_with_cached_pows() {
+    let challenge = b"hello world, challenge me!!!!!!!";
+    let datadir = tempdir().unwrap();
+
+    let cfg = post::config::ProofConfig {
+        k1: 23,
+        k2: 32,
+        k3: 10,
+        pow_difficulty: [0xFF; 32],
+    };
+    let init_cfg = InitConfig {
+        min_num_units: 1,
+        max_num_units: 1000,
+        labels_per_unit: 256 * 16,
+        scrypt: ScryptParams::new(2, 1, 1),
+    };
+    let metadata = CpuInitializer::new(init_cfg.scrypt)
+        .initialize(
+            datadir.path(),
+            &[77; 32],
+            &[0u8; 32],
+            init_cfg.labels_per_unit,
+            2,
+            1000,
+            None,
+        )
+        .unwrap();
+
+    let pow_flags = RandomXFlag::get_recommended_flags();
+    let counting = Arc::new(CountingProver {
+        pow: PoW::new(pow_flags).unwrap(),
+        count: AtomicUsize::new(0),
+    });
+    let pow_prover: Arc<dyn pow::Prover + Send + Sync> = counting.clone();
+    let generate = || {
+        // Loaded for every proof generation, so that the cache file is used.
+        let pow_cache = Arc::new(load_pow_cache(datadir.path()).unwrap());
+        let settings = ProvingSettings {
+            pow_prover: Some(&pow_prover),
+            pow_cache: Some(&pow_cache),
+            ..Default::default()
+        };
+        generate_proof_with_settings(
+            datadir.path(),
+            challenge,
+            cfg,
+            32,
+            1,
+            pow_flags,
+            CancellationToken::new(),
+            &settings,
+        )
+        .unwrap()
+    };
+    generate();
+    let calculated = counting.count.load(Ordering::Relaxed);
+    assert!(calculated > 0);
+
+    // Proving again for the same challenge uses the cached proofs of work.
+    let proof = generate();
+    assert_eq!(calculated, counting.count.load(Ordering::Relaxed));
+
+    let verifier = Verifier::new(Box::new(PoW::new(pow_flags).unwrap()));
+    verifier
+        .verify(
+            &proof,
+            &ProofMetadata::new(metadata, *challenge),
+            &cfg,
+            &init_cfg,
+        )
+        .expect("proof should be valid");
+}